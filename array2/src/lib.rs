@@ -1,4 +1,4 @@
-/// Cameron Castillo and Daniel Diaz Implementation for Array2.
+//! Cameron Castillo and Daniel Diaz Implementation for Array2.
 
 pub mod array2 {
 
@@ -20,12 +20,19 @@ pub mod array2 {
     /// ```
     ///
     #[derive(Debug, Clone, Eq, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct Array2<T: Clone> {
         pub data: Vec<T>,
         width: usize,
         height: usize,
     }
 
+    impl<T: Clone> Default for Array2<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
     impl<T: Clone> Array2<T> {
         /// ## Iterates over the Array2 in row-major order.
         ///
@@ -108,9 +115,9 @@ pub mod array2 {
         /// ```
         pub fn from_row_major(width: usize, height: usize, orig_vec: Vec<T>) -> Self {
             Self {
-                data: Vec::from(orig_vec),
-                width: width,
-                height: height,
+                data: orig_vec,
+                width,
+                height,
             }
         }
 
@@ -130,19 +137,19 @@ pub mod array2 {
         ///  ```
         pub fn from_col_major(width: usize, height: usize, orig_vec: Vec<T>) -> Self {
             let mut temp = Vec::new();
-            for c in 0..width.clone() {
+            for c in 0..width {
                 let x: Vec<T> = orig_vec
                     .clone()
                     .into_iter()
                     .skip(c)
-                    .step_by(width.clone())
+                    .step_by(width)
                     .collect();
                 for val in x.into_iter() {
                     temp.push(val)
                 }
             }
             Self {
-                data: Vec::from(temp),
+                data: temp,
                 width,
                 height,
             }
@@ -243,6 +250,125 @@ pub mod array2 {
             self.width
         }
 
+        /// ## Returns a copy of the rectangle of the Array2 starting at column `c` and row `r`
+        ///
+        /// The rectangle is `width` columns wide and `height` rows tall, and its data is stored
+        /// in row-major order. The rectangle is shrunk to fit when it extends past the Array2.
+        ///
+        /// # Example
+        /// ```
+        ///
+        /// use array2::array2::Array2;
+        /// let data = vec![1, 2, 3, 4, 5, 6, 7, 8, 9];
+        /// let array = Array2::from_row_major(3, 3, data);
+        /// let corner = array.crop(1, 1, 2, 2);
+        /// assert_eq!(corner.data, vec![5, 6, 8, 9]);
+        ///
+        /// ```
+        pub fn crop(&self, c: usize, r: usize, width: usize, height: usize) -> Self {
+            let width = width.min(self.width.saturating_sub(c));
+            let height = height.min(self.height.saturating_sub(r));
+            let mut temp = Vec::with_capacity(width * height);
+            for row in r..r + height {
+                let start = row * self.width + c;
+                temp.extend_from_slice(&self.data[start..start + width]);
+            }
+            Self {
+                data: temp,
+                width,
+                height,
+            }
+        }
+
+        /// ## Iterates over a rectangle of the Array2 in row-major order without copying it.
+        ///
+        /// The rectangle starts at column `c` and row `r`, and is shrunk to fit when it extends
+        /// past the Array2, just like `crop`.
+        ///
+        /// # Example
+        /// ```
+        ///
+        /// use array2::array2::Array2;
+        /// let data = vec![1, 2, 3, 4, 5, 6, 7, 8, 9];
+        /// let array = Array2::from_row_major(3, 3, data);
+        /// let column: Vec<&i32> = array.window(1, 0, 1, 3).collect();
+        /// assert_eq!(column, vec![&2, &5, &8]);
+        ///
+        /// ```
+        pub fn window(
+            &self,
+            c: usize,
+            r: usize,
+            width: usize,
+            height: usize,
+        ) -> impl Iterator<Item = &T> {
+            let width = width.min(self.width.saturating_sub(c));
+            let height = height.min(self.height.saturating_sub(r));
+            (r..r + height).flat_map(move |row| {
+                let start = row * self.width + c;
+                self.data[start..start + width].iter()
+            })
+        }
+
+        /// ## Combines two Array2s of the same dimensions element by element
+        ///
+        /// This function calls `f` with the values at the same column and row of both arrays,
+        /// and returns an Array2 of the results. It returns None if the dimensions differ.
+        ///
+        /// # Example
+        /// ```
+        ///
+        /// use array2::array2::Array2;
+        /// let a = Array2::from_row_major(2, 2, vec![1, 2, 3, 4]);
+        /// let b = Array2::from_row_major(2, 2, vec![4, 3, 2, 1]);
+        /// let difference = a.zip_with(&b, |x, y| x - y).unwrap();
+        /// assert_eq!(difference.data, vec![-3, -1, 1, 3]);
+        ///
+        /// ```
+        pub fn zip_with<U: Clone, V: Clone>(
+            &self,
+            other: &Array2<U>,
+            f: impl Fn(&T, &U) -> V,
+        ) -> Option<Array2<V>> {
+            if self.width != other.width || self.height != other.height {
+                return None;
+            }
+            let data = self
+                .data
+                .iter()
+                .zip(other.data.iter())
+                .map(|(a, b)| f(a, b))
+                .collect();
+            Some(Array2 {
+                data,
+                width: self.width,
+                height: self.height,
+            })
+        }
+
+        /// ## Applies a function to every element of the Array2
+        ///
+        /// This function calls `f` with every value in row-major order, and returns an Array2
+        /// of the results with the same dimensions. Maps compose: `a.map(f).map(g)` applies `f`
+        /// and then `g`.
+        ///
+        /// # Example
+        /// ```
+        ///
+        /// use array2::array2::Array2;
+        /// let array = Array2::from_row_major(2, 2, vec![1, 2, 3, 4]);
+        /// let doubled = array.map(|x| x * 2).map(|x| x + 1);
+        /// assert_eq!(doubled.data, vec![3, 5, 7, 9]);
+        ///
+        /// ```
+        pub fn map<U: Clone>(&self, f: impl Fn(&T) -> U) -> Array2<U> {
+            Array2 {
+                data: self.data.iter().map(f).collect(),
+                width: self.width,
+                height: self.height,
+            }
+        }
+
         /// # Sets a given width and height to the current Array2.
         pub fn set_dimensions(&mut self, width: usize, height: usize) {
             self.width = width;
@@ -294,7 +420,7 @@ pub mod array2 {
             }
 
             Self {
-                data: Vec::from(temp),
+                data: temp,
                 width: new_width,
                 height: new_height,
             }
@@ -330,4 +456,38 @@ mod test {
         let array3 = Array2::from_even_dimension(2, 2, even_data.clone());
         assert_eq!(array3.data, even_data);
     }
+
+    #[test]
+    fn test_crop() {
+        let data = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let array = Array2::from_row_major(4, 3, data);
+        let middle = array.crop(1, 0, 2, 2);
+        assert_eq!(middle.data, vec![2, 3, 6, 7]);
+        assert_eq!((middle.get_width(), middle.get_height()), (2, 2));
+        let clipped = array.crop(3, 1, 5, 5);
+        assert_eq!(clipped.data, vec![8, 12]);
+        assert_eq!(array.crop(4, 0, 1, 1).size(), 0);
+    }
+
+    #[test]
+    fn test_window() {
+        let data = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let array = Array2::from_row_major(4, 3, data);
+        let row: Vec<i32> = array.window(1, 2, 4, 1).cloned().collect();
+        assert_eq!(row, vec![10, 11, 12]);
+        let crop = array.crop(1, 1, 2, 2);
+        assert!(array.window(1, 1, 2, 2).eq(crop.data.iter()));
+        assert_eq!(array.window(0, 3, 1, 1).count(), 0);
+    }
+
+    #[test]
+    fn test_zip_with() {
+        let a = Array2::from_row_major(3, 2, vec![1, 2, 3, 4, 5, 6]);
+        let b = Array2::from_row_major(3, 2, vec![6, 5, 4, 3, 2, 1]);
+        let sums = a.zip_with(&b, |x, y| x + y).unwrap();
+        assert_eq!(sums.data, vec![7; 6]);
+        assert_eq!((sums.get_width(), sums.get_height()), (3, 2));
+        let c = Array2::from_row_major(2, 3, vec![0; 6]);
+        assert!(a.zip_with(&c, |x, y| x + y).is_none());
+    }
 }
@@ -1,12 +1,10 @@
-use std::convert::TryInto;
-
 /// Returns true iff the signed value `n` fits into `width` signed bits.
 ///
 /// # Arguments:
 /// * `n`: A signed integer value
 /// * `width`: the width of a bit field
 pub fn fitss(n: i64, width: u64) -> bool {
-    let max_val: i128 = 1_i128 << (width - 1);
+    let max_val: i128 = (1_i128 << (width - 1)) - 1;
     let min_val: i128 = -(1_i128 << (width - 1));
     (n as i128) >= min_val && (n as i128) <= max_val
 }
@@ -17,7 +15,7 @@ pub fn fitss(n: i64, width: u64) -> bool {
 /// * `n`: An usigned integer value
 /// * `width`: the width of a bit field
 pub fn fitsu(n: u64, width: u64) -> bool {
-    (n >> width) == 0
+    width >= 64 || (n >> width) == 0
 }
 
 /// Retrieve a signed value from `word`, represented by `width` bits
@@ -29,9 +27,7 @@ pub fn fitsu(n: u64, width: u64) -> bool {
 /// * `lsb`: the least-significant bit of the bit field
 pub fn gets(word: u64, width: u64, lsb: u64) -> i64 {
     let word_length = 64;
-    (((word << (word_length - width - lsb)) as i64) >> (word_length - width))
-        .try_into()
-        .unwrap()
+    ((word << (word_length - width - lsb)) as i64) >> (word_length - width)
 }
 
 /// Retrieve an unsigned value from `word`, represented by `width` bits
@@ -43,9 +39,7 @@ pub fn gets(word: u64, width: u64, lsb: u64) -> i64 {
 /// * `lsb`: the least-significant bit of the bit field
 pub fn getu(word: u64, width: u64, lsb: u64) -> u64 {
     let word_length = 64;
-    (word << (word_length - width - lsb) as u64 >> (word_length - width))
-        .try_into()
-        .unwrap()
+    word << (word_length - width - lsb) >> (word_length - width)
 }
 
 /// Return a modified version of the unsigned `word`,
@@ -80,12 +74,128 @@ pub fn newu(word: u64, width: u64, lsb: u64, value: u64) -> Option<u64> {
 pub fn news(word: u64, width: u64, lsb: u64, value: i64) -> Option<u64> {
     if fitss(value, width) {
         let mask: i128 = (1_i128 << width) - 1;
-        let val: u64 = (((value as i128) & (mask as i128)) << lsb) as u64;
+        let val: u64 = (((value as i128) & mask) << lsb) as u64;
         return Some(val | word);
     }
     None
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## A bit field of a word
+///
+/// The field holds `width` bits beginning at least-significant bit `lsb`, read with `gets` and
+/// written with `news` when `signed`, and with `getu` and `newu` otherwise.
+///
+/// # Usage Example
+///
+/// ```
+/// use bitpack::bitpack::{verify_layout, Field, Layout};
+///
+/// let a = Field { width: 9, lsb: 23, signed: false };
+/// let b = Field { width: 5, lsb: 18, signed: true };
+/// assert!(verify_layout(&Layout { fields: vec![a, b] }).is_ok());
+/// ```
+pub struct Field {
+    pub width: u64,
+    pub lsb: u64,
+    pub signed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## The bit fields a word is split into
+///
+/// # Usage Example
+///
+/// ```
+/// use bitpack::bitpack::{verify_layout, Field, Layout};
+///
+/// let low = Field { width: 8, lsb: 0, signed: false };
+/// let high = Field { width: 8, lsb: 4, signed: true };
+/// assert!(verify_layout(&Layout { fields: vec![low, high] }).is_err());
+/// ```
+pub struct Layout {
+    pub fields: Vec<Field>,
+}
+
+/// Widest field whose every value `verify_layout` round trips; wider fields are checked at the
+/// ends of their range and around zero.
+const EXHAUSTIVE_WIDTH: u64 = 12;
+
+/// Returns the values of `field` that `verify_layout` round trips, and the values just out of
+/// its range, which must be rejected.
+///
+/// # Arguments:
+/// * `field`: A bit field of a word
+fn test_values(field: &Field) -> (Vec<i128>, Vec<i128>) {
+    let (min, max): (i128, i128) = if field.signed {
+        (-(1_i128 << (field.width - 1)), (1_i128 << (field.width - 1)) - 1)
+    } else {
+        (0, (1_i128 << field.width) - 1)
+    };
+    let values = if field.width <= EXHAUSTIVE_WIDTH {
+        (min..=max).collect()
+    } else {
+        let mut values: Vec<i128> = [min, min + 1, -1, 0, 1, max - 1, max]
+            .into_iter()
+            .filter(|value| (min..=max).contains(value))
+            .collect();
+        values.dedup();
+        values
+    };
+    (values, vec![min - 1, max + 1])
+}
+
+/// Returns Ok iff every field of `layout` lies within a 64-bit word without overlapping another
+/// field, every value in its range survives a round trip through `news` and `gets` (or `newu`
+/// and `getu`) without touching the other fields, and the values just out of its range are
+/// rejected. Otherwise returns a message naming the first field that fails.
+///
+/// # Arguments:
+/// * `layout`: The bit fields of a word
+pub fn verify_layout(layout: &Layout) -> Result<(), String> {
+    let mut used: u64 = 0;
+    for (index, field) in layout.fields.iter().enumerate() {
+        let name = format!("Field {} ({} bits at bit {})", index, field.width, field.lsb);
+        if field.width == 0 || field.width + field.lsb > 64 {
+            return Err(format!("{} does not lie within a 64-bit word", name));
+        }
+        let mask = (((1_u128 << field.width) - 1) << field.lsb) as u64;
+        if used & mask != 0 {
+            return Err(format!("{} overlaps an earlier field", name));
+        }
+        used |= mask;
+    }
+    for (index, field) in layout.fields.iter().enumerate() {
+        let name = format!("Field {} ({} bits at bit {})", index, field.width, field.lsb);
+        let mask = (((1_u128 << field.width) - 1) << field.lsb) as u64;
+        let others = used & !mask;
+        let (values, out_of_range) = test_values(field);
+        for value in values {
+            let (word, read) = if field.signed {
+                let word = news(others, field.width, field.lsb, value as i64);
+                (word, word.map(|word| gets(word, field.width, field.lsb) as i128))
+            } else {
+                let word = newu(others, field.width, field.lsb, value as u64);
+                (word, word.map(|word| getu(word, field.width, field.lsb) as i128))
+            };
+            if read != Some(value) || word.map(|word| word & !mask) != Some(others) {
+                return Err(format!("{}: {} does not survive a round trip", name, value));
+            }
+        }
+        for value in out_of_range {
+            let fits = if field.signed {
+                i64::try_from(value).is_ok_and(|value| fitss(value, field.width))
+            } else {
+                u64::try_from(value).is_ok_and(|value| fitsu(value, field.width))
+            };
+            if fits {
+                return Err(format!("{}: {} is out of range but fits", name, value));
+            }
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::bitpack::*;
@@ -93,33 +203,61 @@ mod tests {
     #[test]
     fn test_fitsu() {
         // Test 9 bit
-        assert_eq!(true, fitsu(511, 9));
-        assert_eq!(false, fitsu(512, 9));
+        assert!(fitsu(511, 9));
+        assert!(!fitsu(512, 9));
         // Test 5 bit
-        assert_eq!(true, fitsu(31, 5));
-        assert_eq!(false, fitsu(32, 5));
+        assert!(fitsu(31, 5));
+        assert!(!fitsu(32, 5));
         // Test 4 bit
-        assert_eq!(true, fitsu(15, 4));
-        assert_eq!(false, fitsu(200, 4));
+        assert!(fitsu(15, 4));
+        assert!(!fitsu(200, 4));
     }
 
     #[test]
     fn test_fitss(){
         // Test 9 bit
-        assert_eq!(true, fitss(-256, 9));
-        assert_eq!(true, fitss(254, 9));
-        assert_eq!(false, fitss(-258, 9));
-        assert_eq!(false, fitss(300, 9));
+        assert!(fitss(-256, 9));
+        assert!(fitss(254, 9));
+        assert!(!fitss(-258, 9));
+        assert!(!fitss(300, 9));
         // Test 5 bit
-        assert_eq!(true, fitss(-16, 5));
-        assert_eq!(true, fitss(15, 5));
-        assert_eq!(false, fitss(-400, 5));
-        assert_eq!(false, fitss(521, 5));
+        assert!(fitss(-16, 5));
+        assert!(fitss(15, 5));
+        assert!(!fitss(-400, 5));
+        assert!(!fitss(521, 5));
         // Test 4 bit
-        assert_eq!(true, fitss(-8, 4));
-        assert_eq!(true, fitss(7, 4));
-        assert_eq!(false, fitss(-40, 4));
-        assert_eq!(false, fitss(40, 4));
+        assert!(fitss(-8, 4));
+        assert!(fitss(7, 4));
+        assert!(!fitss(-40, 4));
+        assert!(!fitss(40, 4));
+        // Test the ends of the range
+        assert!(fitss(255, 9));
+        assert!(!fitss(256, 9));
+        assert!(!fitss(-257, 9));
+    }
+
+    #[test]
+    fn test_verify_layout() {
+        let field = |width, lsb, signed| Field { width, lsb, signed };
+        let narrow = Layout {
+            fields: vec![
+                field(9, 23, false),
+                field(5, 18, true),
+                field(5, 13, true),
+                field(5, 8, true),
+                field(4, 4, false),
+                field(4, 0, false),
+            ],
+        };
+        assert_eq!(verify_layout(&narrow), Ok(()));
+        for signed in [false, true] {
+            let wide = Layout { fields: vec![field(64, 0, signed)] };
+            assert_eq!(verify_layout(&wide), Ok(()));
+        }
+        let overlapping = Layout { fields: vec![field(9, 23, false), field(5, 20, true)] };
+        assert!(verify_layout(&overlapping).is_err());
+        let outside = Layout { fields: vec![field(9, 60, false)] };
+        assert!(verify_layout(&outside).is_err());
     }
 
     #[test]
@@ -139,7 +277,7 @@ mod tests {
 
     #[test]
     fn test_gets(){
-        let mut word: u64 = 0_64;
+        let mut word: u64 = 0_u64;
         let b: i64 = -16;
         let c: i64 = -1;
         let d: i64 = -5;
@@ -0,0 +1,20 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rpeg::bench::scenarios;
+use rpeg::codec::compress_image;
+use std::hint::black_box;
+
+/// Measures the compression of every scenario of `rpeg::bench::scenarios`.
+fn encode(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("encode");
+    for scenario in scenarios() {
+        let image = scenario.image();
+        group.throughput(Throughput::Bytes(scenario.input_bytes()));
+        group.bench_function(&scenario.name, |bencher| {
+            bencher.iter(|| compress_image(black_box(&image), &scenario.encoder))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode);
+criterion_main!(benches);
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rpeg::codec::decode_unchecked_input;
+
+fuzz_target!(|bytes: &[u8]| {
+    let _ = decode_unchecked_input(bytes);
+});
@@ -0,0 +1,304 @@
+use crate::codec::{assemble, read_prelude, read_tile_words, write_words, DecodeOptions, Prelude};
+use crate::error::{CliError, ErrorKind};
+use crate::io::{read_input, Output};
+use crate::ppm::{Rgb, RgbImage};
+use crate::tiles::{tile_block_indices, tile_rects};
+use array2::array2::Array2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// ## Color adjustment applied to the decoded pixels
+///
+/// Every adjustment is a map over the pixels of an `Array2<Rgb>`, so a list of them composes
+/// into a pipeline applied in order by `adjust`. The values of the pixels are taken between 0
+/// and the denominator and clamped back into that range after every stage.
+///
+/// * `Gamma(g)` raises every channel to the power 1/g, so a gamma above 1 brightens the
+///   mid-tones.
+/// * `Brightness(b)` adds b to every channel, in units of the denominator.
+/// * `Contrast(c)` scales the distance of every channel from mid-gray by c.
+/// * `Saturation(s)` scales the distance of every channel from the luma of its pixel by s, so
+///   0 gives a gray image.
+///
+/// # Usage Example
+///
+/// ```
+/// use array2::array2::Array2;
+/// use rpeg::adjust::{adjust, Adjustment};
+/// use rpeg::ppm::Rgb;
+///
+/// let image = Array2::from_row_major(1, 1, vec![Rgb { red: 100, green: 150, blue: 200 }]);
+/// let stages = [Adjustment::Brightness(10.0), Adjustment::Saturation(0.0)];
+/// let adjusted = adjust(&image, &stages, 255);
+/// assert_eq!(adjusted.data[0], Rgb { red: 151, green: 151, blue: 151 });
+/// ```
+pub enum Adjustment {
+    Gamma(f64),
+    Brightness(f64),
+    Contrast(f64),
+    Saturation(f64),
+}
+
+impl Adjustment {
+    /// Returns an error message if the value of the adjustment is out of range: gamma must be
+    /// positive, contrast and saturation must not be negative, and every value must be finite.
+    pub fn check(&self) -> Result<(), String> {
+        match *self {
+            Adjustment::Gamma(gamma) if !(gamma.is_finite() && gamma > 0.0) => {
+                Err("The gamma must be a positive number".to_string())
+            }
+            Adjustment::Brightness(brightness) if !brightness.is_finite() => {
+                Err("The brightness must be a number".to_string())
+            }
+            Adjustment::Contrast(factor) | Adjustment::Saturation(factor)
+                if !(factor.is_finite() && factor >= 0.0) =>
+            {
+                Err("Contrast and saturation must not be negative".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns `image` with the adjustment applied to every pixel.
+    ///
+    /// # Arguments
+    /// * `image`: Decoded pixels
+    /// * `denominator`: Denominator of the Rgb values of the image
+    pub fn apply(&self, image: &Array2<Rgb>, denominator: u16) -> Array2<Rgb> {
+        let max = denominator as f64;
+        image.map(|pixel| {
+            let channels = [pixel.red, pixel.green, pixel.blue].map(|value| value as f64);
+            let [red, green, blue] = match *self {
+                Adjustment::Gamma(gamma) => {
+                    channels.map(|value| (value / max.max(1.0)).powf(1.0 / gamma) * max)
+                }
+                Adjustment::Brightness(brightness) => channels.map(|value| value + brightness),
+                Adjustment::Contrast(contrast) => {
+                    channels.map(|value| (value - max / 2.0) * contrast + max / 2.0)
+                }
+                Adjustment::Saturation(saturation) => {
+                    let luma = 0.299 * channels[0] + 0.587 * channels[1] + 0.114 * channels[2];
+                    channels.map(|value| luma + (value - luma) * saturation)
+                }
+            }
+            .map(|value| value.round().clamp(0.0, max) as u16);
+            Rgb { red, green, blue }
+        })
+    }
+}
+
+/// Returns `image` after every adjustment of `adjustments`, in order.
+///
+/// # Arguments
+/// * `image`: Decoded pixels
+/// * `adjustments`: Stages applied one after the other
+/// * `denominator`: Denominator of the Rgb values of the image
+pub fn adjust(image: &Array2<Rgb>, adjustments: &[Adjustment], denominator: u16) -> Array2<Rgb> {
+    match adjustments.split_first() {
+        None => image.clone(),
+        Some((first, rest)) => rest
+            .iter()
+            .fold(first.apply(image, denominator), |image, stage| {
+                stage.apply(&image, denominator)
+            }),
+    }
+}
+
+/// Brightens a compressed image by `brightness` without going back to pixels: the quantized
+/// average luma `a` of every block is offset by `brightness` in units of 255, the denominator
+/// of the decoded pixels, and clamped to its field, which adds `brightness` to every channel of
+/// the decoded pixels as `Adjustment::Brightness` does, up to one step of `a`. Only blocks
+/// whose average leaves the range clip, where the decoded pixels clip on their own. The b, c,
+/// d, and chroma values are kept, and so is the thumbnail, brightened along, but not the
+/// refinement of a near-lossless image, whose corrections no longer apply. Returns an error
+/// for palette-coded and raw tiles, and for images whose `a` is not linear in the decoded
+/// channels or sets the range of b, c, and d: sRGB, HDR, and perceptually quantized images.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+/// * `brightness`: Offset added to every channel, negative to darken the image
+pub fn brighten_image(bytes: &[u8], brightness: f64) -> Result<Vec<u8>, String> {
+    Adjustment::Brightness(brightness).check()?;
+    let Prelude {
+        mut header,
+        levels,
+        payloads,
+        ..
+    } = read_prelude(bytes, &DecodeOptions::default())?;
+    if header.srgb || header.hdr_exponent.is_some() || header.perceptual {
+        return Err(
+            "sRGB, HDR, and perceptually quantized images can only be brightened from their pixels"
+                .to_string(),
+        );
+    }
+    let layout = header.layout;
+    let offset = (brightness / 255.0 * layout.a_scale()).round();
+    let tiles = tile_rects(
+        header.coded_width(),
+        header.coded_height(),
+        header.tile_size,
+    );
+    let mut brightened = Vec::with_capacity(payloads.len());
+    for (index, (tile, payload)) in tiles.iter().zip(payloads).enumerate() {
+        if !header.tile_mode(index).holds_words() {
+            return Err("Palette-coded and raw tiles hold no code words to brighten".to_string());
+        }
+        let block_count = tile_block_indices(tile, header.coded_width()).len();
+        let words: Vec<u64> = read_tile_words(payload, block_count, &header, false)?
+            .into_iter()
+            .map(|word| {
+                let mut block = layout.unpack(word);
+                block.a = (block.a as f64 + offset).clamp(0.0, layout.a_scale()) as u16;
+                layout.pack(&block)
+            })
+            .collect();
+        brightened.push(write_words(&words, header.order, &layout));
+    }
+    header.thumbnail = header.thumbnail.map(|thumbnail| {
+        let pixels = Array2::from_row_major(
+            thumbnail.width as usize,
+            thumbnail.height as usize,
+            thumbnail.pixels,
+        );
+        let stage = Adjustment::Brightness(brightness);
+        RgbImage {
+            pixels: stage.apply(&pixels, thumbnail.denominator).data,
+            ..thumbnail
+        }
+    });
+    header.refinement = None;
+    Ok(assemble(&header, &levels, &brightened))
+}
+
+/// Brightens the compressed image `filename` with `brighten_image` by the sum of
+/// `adjustments`, which must all be brightness offsets, and writes the result to `output`.
+/// Failures are returned with the category the CLI exits with.
+///
+/// # Arguments
+/// * `filename`: Compressed image to brighten, or None to read from standard in
+/// * `output`: Destination of the brightened image
+/// * `adjustments`: Adjustments given on the command line
+pub fn adjust_compressed(
+    filename: Option<&str>,
+    output: &Output,
+    adjustments: &[Adjustment],
+) -> Result<(), CliError> {
+    let mut brightness = 0.0;
+    for adjustment in adjustments {
+        match adjustment {
+            Adjustment::Brightness(offset) => brightness += offset,
+            _ => {
+                return Err(CliError::new(
+                    ErrorKind::BadArguments,
+                    "Only --brightness can be adjusted in the compressed domain",
+                ))
+            }
+        }
+    }
+    if adjustments.is_empty() {
+        return Err(CliError::new(
+            ErrorKind::BadArguments,
+            "adjust expects --brightness",
+        ));
+    }
+    let bytes = read_input(filename)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let brightened = brighten_image(&bytes, brightness).map_err(|message| {
+        if read_prelude(&bytes, &DecodeOptions::default()).is_ok() {
+            CliError::new(ErrorKind::BadArguments, message)
+        } else {
+            CliError::stream(&bytes, message)
+        }
+    })?;
+    output
+        .write(&brightened)
+        .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{compress_image, decompress_image, EncoderOptions};
+    use crate::decoder::Decoder;
+
+    #[test]
+    fn stages_compose_in_order() {
+        let gray = |value| Rgb {
+            red: value,
+            green: value,
+            blue: value,
+        };
+        let image = Array2::from_row_major(3, 1, vec![gray(0), gray(100), gray(255)]);
+        let brighter = adjust(&image, &[Adjustment::Brightness(20.0)], 255);
+        assert_eq!(brighter.data, vec![gray(20), gray(120), gray(255)]);
+        let flat = adjust(&image, &[Adjustment::Contrast(0.0)], 255);
+        assert!(flat.data.iter().all(|pixel| *pixel == gray(128)));
+        let contrast_first = [Adjustment::Contrast(2.0), Adjustment::Brightness(20.0)];
+        let brightness_first = [Adjustment::Brightness(20.0), Adjustment::Contrast(2.0)];
+        assert_eq!(adjust(&image, &contrast_first, 255).data[1], gray(93));
+        assert_eq!(adjust(&image, &brightness_first, 255).data[1], gray(113));
+        assert_eq!(
+            adjust(&image, &[Adjustment::Gamma(2.0)], 255).data[1],
+            gray(160)
+        );
+        assert_eq!(adjust(&image, &[], 255).data, image.data);
+        assert!(Adjustment::Saturation(-1.0).check().is_err());
+    }
+
+    #[test]
+    fn brightening_offsets_the_averages_of_the_blocks() {
+        let image = RgbImage {
+            pixels: (0..16 * 8_u16)
+                .map(|i| Rgb {
+                    red: 40 + i % 16 * 8,
+                    green: 60 + i / 16 * 10,
+                    blue: 90,
+                })
+                .collect(),
+            width: 16,
+            height: 8,
+            denominator: 255,
+        };
+        let options = EncoderOptions {
+            tile_size: 8,
+            progressive: true,
+            ..EncoderOptions::default()
+        };
+        let compressed = compress_image(&image, &options);
+        let decoded = decompress_image(&compressed).unwrap();
+        let brightened = decompress_image(&brighten_image(&compressed, 10.0).unwrap()).unwrap();
+        for (brighter, pixel) in brightened.pixels.iter().zip(&decoded.pixels) {
+            for (brighter, value) in [
+                (brighter.red, pixel.red),
+                (brighter.green, pixel.green),
+                (brighter.blue, pixel.blue),
+            ] {
+                assert!(brighter.abs_diff(value + 10) <= 1);
+            }
+        }
+        // Darkening clamps the averages to black, keeping the gradients and the chroma.
+        let black = brighten_image(&compressed, -255.0).unwrap();
+        let blocks = Decoder::new().blocks(&black).unwrap();
+        let original = Decoder::new().blocks(&compressed).unwrap();
+        assert!(blocks.zip(original).all(|((_, _, dark), (_, _, block))| {
+            dark.a == 0 && (dark.b, dark.pr) == (block.b, block.pr)
+        }));
+        let palette = EncoderOptions {
+            palette: true,
+            ..EncoderOptions::default()
+        };
+        let flat = RgbImage {
+            pixels: vec![
+                Rgb {
+                    red: 9,
+                    green: 9,
+                    blue: 9
+                };
+                64
+            ],
+            width: 8,
+            height: 8,
+            denominator: 255,
+        };
+        assert!(brighten_image(&compress_image(&flat, &palette), 10.0).is_err());
+    }
+}
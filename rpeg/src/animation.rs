@@ -0,0 +1,987 @@
+use crate::codec::stats::Timings;
+use crate::codec::{decode_words, encode_words, DecodeOptions, PixelFormat};
+use crate::dct_coeff::DEFAULT_LUMA_RANGE;
+use crate::error::{CliError, ErrorKind};
+use crate::fixed::Arithmetic;
+use crate::io::{read_input, write_output, Output};
+use crate::layout::NARROW_LAYOUT;
+use crate::ppm::{Rgb, RgbImage};
+use array2::array2::Array2;
+use bitpack::bitpack::{gets, getu, news, verify_layout, Field, Layout};
+
+/// Magic bytes that open every multi-frame rpeg stream.
+pub const ANIMATION_MAGIC: &[u8; 4] = b"RPMF";
+
+/// Multi-frame stream version written by `compress_frames`, whose header is followed by the
+/// frame index.
+pub const ANIMATION_VERSION: u8 = 2;
+
+/// Multi-frame stream version without a frame index, which is still read.
+const UNINDEXED_VERSION: u8 = 1;
+
+/// Length of the header of a multi-frame stream, in front of the frame index.
+const STREAM_HEADER_LEN: usize = 17;
+
+/// Frame type of a frame storing the code word of every block.
+const KEY_FRAME: u8 = 0;
+
+/// Frame type of a frame storing only the code words of the blocks that changed.
+const DELTA_FRAME: u8 = 1;
+
+/// Frame type of a frame predicted from the previous one moved by a motion vector per
+/// macroblock, storing the residuals of the blocks the prediction misses.
+const MOTION_FRAME: u8 = 2;
+
+/// Side of a macroblock, the area sharing a motion vector, in 2x2 blocks.
+const MACROBLOCK_BLOCKS: usize = 4;
+
+/// Largest displacement searched by the motion search, in 2x2 blocks along each axis, which
+/// is 8 pixels.
+const MAX_MOTION_BLOCKS: i64 = 4;
+
+/// Horizontal displacement of a macroblock in pixels, in its 16-bit motion vector.
+const MOTION_DX: Field = Field {
+    width: 8,
+    lsb: 8,
+    signed: true,
+};
+
+/// Vertical displacement of a macroblock in pixels, in its 16-bit motion vector.
+const MOTION_DY: Field = Field {
+    width: 8,
+    lsb: 0,
+    signed: true,
+};
+
+/// Percentage of the blocks of a frame that must be stored for `SequenceOptions::default` to
+/// take it for a scene cut.
+pub const DEFAULT_SCENE_CUT_PERCENT: u8 = 90;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Settings used to compress a sequence of frames
+///
+/// `threshold` is the largest change of a quantized field that is not retransmitted. With
+/// `motion`, every frame after the first is predicted from the previous one, every 8x8
+/// macroblock moved by its own motion vector of up to 8 pixels along each axis, so that
+/// panning footage only stores the blocks the moved prediction misses.
+///
+/// A frame is stored as a key frame when more than `scene_cut_percent` of its blocks would be
+/// stored anyway, which marks a scene cut, and when `keyframe_interval` frames have passed since
+/// the last key frame. Key frames bound how far `seek_frame` reads back and how long the
+/// changes under `threshold` build up. A `scene_cut_percent` of 100 or more turns the detection
+/// off.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::animation::SequenceOptions;
+///
+/// let options = SequenceOptions {
+///     threshold: 1,
+///     motion: true,
+///     keyframe_interval: Some(30),
+///     ..SequenceOptions::default()
+/// };
+/// assert_ne!(options, SequenceOptions::default());
+/// ```
+pub struct SequenceOptions {
+    pub threshold: u64,
+    pub motion: bool,
+    pub keyframe_interval: Option<usize>,
+    pub scene_cut_percent: u8,
+}
+
+impl Default for SequenceOptions {
+    fn default() -> Self {
+        SequenceOptions {
+            threshold: 0,
+            motion: false,
+            keyframe_interval: None,
+            scene_cut_percent: DEFAULT_SCENE_CUT_PERCENT,
+        }
+    }
+}
+
+/// Returns the largest difference between any quantized field of two 32-bit code words, chroma
+/// indices included.
+///
+/// # Arguments
+/// * `previous`: Code word the decoder currently holds for the block
+/// * `current`: Code word of the block in the new frame
+pub fn word_difference(previous: u64, current: u64) -> u64 {
+    let layout = NARROW_LAYOUT;
+    let unsigned = [layout.a, layout.pb, layout.pr].map(|field| {
+        getu(previous, field.width, field.lsb).abs_diff(getu(current, field.width, field.lsb))
+    });
+    let signed = [layout.b, layout.c, layout.d].map(|field| {
+        gets(previous, field.width, field.lsb).abs_diff(gets(current, field.width, field.lsb))
+    });
+    unsigned.into_iter().chain(signed).max().unwrap()
+}
+
+/// Returns the residual of a 32-bit code word against its prediction: the difference of every
+/// field of the two words, modulo the size of the field, so that `add_residual` gives the
+/// word back exactly.
+///
+/// # Arguments
+/// * `predicted`: Code word the decoder predicts for the block
+/// * `current`: Code word of the block in the new frame
+pub fn word_residual(predicted: u64, current: u64) -> u64 {
+    let layout = NARROW_LAYOUT;
+    [layout.a, layout.b, layout.c, layout.d, layout.pb, layout.pr]
+        .into_iter()
+        .fold(0, |word, field| {
+            let value = |word| getu(word, field.width, field.lsb);
+            field.place(word, value(current).wrapping_sub(value(predicted)))
+        })
+}
+
+/// Returns the 32-bit code word a residual written by `word_residual` stands for.
+///
+/// # Arguments
+/// * `predicted`: Code word the decoder predicts for the block
+/// * `residual`: Residual of the block
+pub fn add_residual(predicted: u64, residual: u64) -> u64 {
+    let layout = NARROW_LAYOUT;
+    [layout.a, layout.b, layout.c, layout.d, layout.pb, layout.pr]
+        .into_iter()
+        .fold(0, |word, field| {
+            let value = |word| getu(word, field.width, field.lsb);
+            field.place(word, value(predicted).wrapping_add(value(residual)))
+        })
+}
+
+/// Returns the index of the block the block at column `col` and row `row` is predicted from
+/// when its macroblock moves by `vector`, clamped to the frame.
+///
+/// # Arguments
+/// * `col`, `row`: Position of the block, in 2x2 blocks
+/// * `vector`: Motion vector of its macroblock, in 2x2 blocks
+/// * `block_width`, `block_height`: Dimensions of the frame, in 2x2 blocks
+fn source_block(
+    (col, row): (usize, usize),
+    (dx, dy): (i64, i64),
+    block_width: usize,
+    block_height: usize,
+) -> usize {
+    let col = (col as i64 + dx).clamp(0, block_width as i64 - 1) as usize;
+    let row = (row as i64 + dy).clamp(0, block_height as i64 - 1) as usize;
+    row * block_width + col
+}
+
+/// Returns the code words of a frame predicted from `reference`, with every macroblock moved by
+/// its motion vector.
+///
+/// # Arguments
+/// * `reference`: Code words of the previous frame, as the decoder holds them
+/// * `vectors`: Motion vector of every macroblock in row-major order, in 2x2 blocks
+/// * `block_width`, `block_height`: Dimensions of the frame, in 2x2 blocks
+fn predict(
+    reference: &[u64],
+    vectors: &[(i64, i64)],
+    block_width: usize,
+    block_height: usize,
+) -> Vec<u64> {
+    let macroblock_width = block_width.div_ceil(MACROBLOCK_BLOCKS);
+    (0..block_width * block_height)
+        .map(|block| {
+            let (col, row) = (block % block_width, block / block_width);
+            let vector =
+                vectors[row / MACROBLOCK_BLOCKS * macroblock_width + col / MACROBLOCK_BLOCKS];
+            reference[source_block((col, row), vector, block_width, block_height)]
+        })
+        .collect()
+}
+
+/// Returns the motion vector of every macroblock in row-major order, in 2x2 blocks: the
+/// displacement of up to `MAX_MOTION_BLOCKS` along each axis whose prediction leaves the fewest
+/// blocks changed by more than `threshold`, the shortest one winning ties.
+///
+/// # Arguments
+/// * `reference`: Code words of the previous frame, as the decoder holds them
+/// * `words`: Code words of the new frame
+/// * `block_width`, `block_height`: Dimensions of the frame, in 2x2 blocks
+/// * `threshold`: Largest change of a quantized field that is not retransmitted
+fn search_motion(
+    reference: &[u64],
+    words: &[u64],
+    block_width: usize,
+    block_height: usize,
+    threshold: u64,
+) -> Vec<(i64, i64)> {
+    let range = -MAX_MOTION_BLOCKS..=MAX_MOTION_BLOCKS;
+    let mut candidates: Vec<(i64, i64)> = range
+        .clone()
+        .flat_map(|dy| range.clone().map(move |dx| (dx, dy)))
+        .collect();
+    candidates.sort_by_key(|(dx, dy)| dx.abs() + dy.abs());
+    let (macroblock_width, macroblock_height) = (
+        block_width.div_ceil(MACROBLOCK_BLOCKS),
+        block_height.div_ceil(MACROBLOCK_BLOCKS),
+    );
+    (0..macroblock_width * macroblock_height)
+        .map(|macroblock| {
+            let (left, top) = (
+                macroblock % macroblock_width * MACROBLOCK_BLOCKS,
+                macroblock / macroblock_width * MACROBLOCK_BLOCKS,
+            );
+            let blocks: Vec<(usize, usize)> = (top..(top + MACROBLOCK_BLOCKS).min(block_height))
+                .flat_map(|row| {
+                    (left..(left + MACROBLOCK_BLOCKS).min(block_width)).map(move |col| (col, row))
+                })
+                .collect();
+            let missed = |vector: (i64, i64)| {
+                blocks
+                    .iter()
+                    .filter(|(col, row)| {
+                        let source = source_block((*col, *row), vector, block_width, block_height);
+                        word_difference(reference[source], words[row * block_width + col])
+                            > threshold
+                    })
+                    .count()
+            };
+            *candidates
+                .iter()
+                .min_by_key(|vector| missed(**vector))
+                .unwrap()
+        })
+        .collect()
+}
+
+/// Returns the body of a delta frame storing the words of `words` that changed by more than
+/// `threshold` since `reference`, and the number of words it stores. The stored words are
+/// copied into `reference`.
+///
+/// # Arguments
+/// * `reference`: Code words the decoder holds before the frame
+/// * `words`: Code words of the frame
+/// * `threshold`: Largest change of a quantized field that is not retransmitted
+fn delta_body(reference: &mut [u64], words: &[u64], threshold: u64) -> (Vec<u8>, usize) {
+    let mut bitmap = vec![0_u8; words.len().div_ceil(8)];
+    let mut changed = Vec::new();
+    for (block, word) in words.iter().enumerate() {
+        if word_difference(reference[block], *word) > threshold {
+            bitmap[block / 8] |= 0x80 >> (block % 8);
+            reference[block] = *word;
+            changed.push(*word);
+        }
+    }
+    let mut body = vec![DELTA_FRAME];
+    body.extend_from_slice(&bitmap);
+    for word in changed.iter() {
+        NARROW_LAYOUT.write_word(*word, &mut body);
+    }
+    (body, changed.len())
+}
+
+/// Returns the body of a motion frame predicting `words` from `reference`, and the number of
+/// residuals it stores. `reference` becomes the code words the decoder holds after the frame.
+///
+/// # Arguments
+/// * `reference`: Code words the decoder holds before the frame
+/// * `words`: Code words of the frame
+/// * `block_width`: Width of the frame in blocks
+/// * `block_height`: Height of the frame in blocks
+/// * `threshold`: Largest change of a quantized field that is not retransmitted
+fn motion_body(
+    reference: &mut Vec<u64>,
+    words: &[u64],
+    block_width: usize,
+    block_height: usize,
+    threshold: u64,
+) -> (Vec<u8>, usize) {
+    let vectors = search_motion(reference, words, block_width, block_height, threshold);
+    *reference = predict(reference, &vectors, block_width, block_height);
+    let mut body = vec![MOTION_FRAME];
+    for (dx, dy) in vectors {
+        let vector = news(0, MOTION_DX.width, MOTION_DX.lsb, dx * 2)
+            .and_then(|vector| news(vector, MOTION_DY.width, MOTION_DY.lsb, dy * 2))
+            .unwrap();
+        body.extend_from_slice(&(vector as u16).to_be_bytes());
+    }
+    let mut bitmap = vec![0_u8; words.len().div_ceil(8)];
+    let mut residuals = Vec::new();
+    for (block, word) in words.iter().enumerate() {
+        if word_difference(reference[block], *word) > threshold {
+            bitmap[block / 8] |= 0x80 >> (block % 8);
+            residuals.push(word_residual(reference[block], *word));
+            reference[block] = *word;
+        }
+    }
+    body.extend_from_slice(&bitmap);
+    for residual in residuals.iter() {
+        NARROW_LAYOUT.write_word(*residual, &mut body);
+    }
+    (body, residuals.len())
+}
+
+/// Takes a sequence of frames of equal dimensions and compresses them into a multi-frame
+/// stream. The first frame stores every block; every following frame stores a bitmap of the
+/// blocks whose quantized coefficients moved by more than `options.threshold` since the
+/// decoder last received them, followed by the code words of those blocks only.
+///
+/// With `options.motion`, every following frame is a motion frame instead: the motion vector
+/// of every macroblock (16 bits each, the horizontal then the vertical displacement in pixels,
+/// signed 8 bits each), then the bitmap of the blocks the moved previous frame misses, followed
+/// by their residuals, as written by `word_residual`.
+///
+/// A frame at a scene cut, or `options.keyframe_interval` frames after the last key frame,
+/// stores every block again, as the first frame does. Returns an error for a key frame
+/// interval of 0 and for frames of different dimensions.
+///
+/// The stream starts with the magic bytes, the version, the (trimmed) width and height, and the
+/// number of frames, followed by the frame index: the position of every frame within the
+/// stream (64 bits each, in Bigendian format). Every frame then starts with its type: 0 for a
+/// key frame, 1 for a delta frame, 2 for a motion frame.
+///
+/// # Arguments
+/// * `frames`: Frames of the sequence, in order
+/// * `options`: Settings used to compress the sequence
+pub fn compress_frames(frames: &[RgbImage], options: &SequenceOptions) -> Result<Vec<u8>, String> {
+    let first = frames.first().ok_or("Expected at least one frame")?;
+    let (width, height) = (first.width as usize & !1, first.height as usize & !1);
+    let ranges = vec![DEFAULT_LUMA_RANGE; (width / 2) * (height / 2)];
+    let words = frames.iter().enumerate().map(|(index, frame)| {
+        if (frame.width, frame.height) != (first.width, first.height) {
+            return Err(format!(
+                "Frame {index} is {}x{}, expected {}x{}",
+                frame.width, frame.height, first.width, first.height
+            ));
+        }
+        let image: Array2<Rgb> = Array2::from_even_dimension(
+            frame.width as usize,
+            frame.height as usize,
+            frame.pixels.clone(),
+        );
+        let (words, _) = encode_words(
+            &image,
+            frame.denominator,
+            &ranges,
+            false,
+            false,
+            &NARROW_LAYOUT,
+            Arithmetic::default(),
+            &Timings::new(),
+        );
+        Ok(words)
+    });
+    compress_frame_words(width, height, words, options)
+}
+
+/// Writes a multi-frame stream, as described by `compress_frames`, from the code words of
+/// every frame, quantized with the default luma range in the narrow layout.
+///
+/// # Arguments
+/// * `width`: Width of the frames in pixels, which is even
+/// * `height`: Height of the frames in pixels, which is even
+/// * `frames`: Code words of every frame, in row-major block order, or the error that stopped
+///   reading the frames
+/// * `options`: Settings used to compress the sequence
+pub(crate) fn compress_frame_words(
+    width: usize,
+    height: usize,
+    frames: impl IntoIterator<Item = Result<Vec<u64>, String>>,
+    options: &SequenceOptions,
+) -> Result<Vec<u8>, String> {
+    let (block_width, block_height) = (width / 2, height / 2);
+    let threshold = options.threshold;
+    if options.keyframe_interval == Some(0) {
+        return Err("The key frame interval must be at least 1".to_string());
+    }
+    if options.motion {
+        verify_layout(&Layout {
+            fields: vec![MOTION_DX, MOTION_DY],
+        })?;
+    }
+
+    let mut bodies = Vec::new();
+    let mut reference: Vec<u64> = Vec::new();
+    let mut last_key_frame = 0;
+    for (index, words) in frames.into_iter().enumerate() {
+        let words = words?;
+        let interval_due = options
+            .keyframe_interval
+            .is_some_and(|interval| index - last_key_frame >= interval);
+        if index > 0 && !interval_due {
+            let mut predicted = reference.clone();
+            let (body, stored) = if options.motion {
+                motion_body(&mut predicted, &words, block_width, block_height, threshold)
+            } else {
+                delta_body(&mut predicted, &words, threshold)
+            };
+            if stored * 100 <= words.len() * usize::from(options.scene_cut_percent) {
+                reference = predicted;
+                bodies.push(body);
+                continue;
+            }
+        }
+        let mut body = vec![KEY_FRAME];
+        for word in words.iter() {
+            NARROW_LAYOUT.write_word(*word, &mut body);
+        }
+        reference = words;
+        last_key_frame = index;
+        bodies.push(body);
+    }
+
+    let mut output = Vec::new();
+    output.extend_from_slice(ANIMATION_MAGIC);
+    output.push(ANIMATION_VERSION);
+    output.extend_from_slice(&(width as u32).to_be_bytes());
+    output.extend_from_slice(&(height as u32).to_be_bytes());
+    output.extend_from_slice(&(bodies.len() as u32).to_be_bytes());
+    let mut offset = (STREAM_HEADER_LEN + bodies.len() * 8) as u64;
+    for body in bodies.iter() {
+        output.extend_from_slice(&offset.to_be_bytes());
+        offset += body.len() as u64;
+    }
+    for body in bodies.iter() {
+        output.extend_from_slice(body);
+    }
+    Ok(output)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Table of the frames of a multi-frame stream
+///
+/// Gives the position of every frame within the stream and whether it is a key frame, so that
+/// a frame can be decoded from the last key frame before it instead of from the first frame.
+/// Streams written before the frame index existed are indexed by walking their frames.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::animation::{compress_frames, FrameIndex, SequenceOptions};
+/// use rpeg::ppm::{Rgb, RgbImage};
+///
+/// let frame = RgbImage {
+///     pixels: vec![Rgb { red: 90, green: 60, blue: 30 }; 4 * 4],
+///     width: 4,
+///     height: 4,
+///     denominator: 255,
+/// };
+/// let frames = [frame.clone(), frame.clone(), frame];
+/// let stream = compress_frames(&frames, &SequenceOptions::default()).unwrap();
+/// let index = FrameIndex::read(&stream).unwrap();
+/// assert_eq!(index.key_frames, [true, false, false]);
+/// assert_eq!(index.key_frame_before(2), Some(0));
+/// ```
+pub struct FrameIndex {
+    pub width: usize,
+    pub height: usize,
+    /// Position of every frame within the stream, at its frame type.
+    pub offsets: Vec<usize>,
+    /// Whether every frame is a key frame, which stores the code word of every block.
+    pub key_frames: Vec<bool>,
+}
+
+impl FrameIndex {
+    /// Reads the frame index of a multi-frame stream written by `compress_frames`.
+    ///
+    /// # Arguments
+    /// * `bytes`: Raw bytes of the multi-frame stream
+    pub fn read(bytes: &[u8]) -> Result<FrameIndex, String> {
+        let truncated = || "Ran out of bytes while reading the frames".to_string();
+        if !bytes.starts_with(ANIMATION_MAGIC) {
+            return Err("Input is not a multi-frame rpeg stream".to_string());
+        }
+        let version = *bytes.get(4).ok_or_else(truncated)?;
+        if version != ANIMATION_VERSION && version != UNINDEXED_VERSION {
+            return Err(format!("Unsupported multi-frame version {version}"));
+        }
+        let field = |pos: usize| -> Result<u32, String> {
+            Ok(u32::from_be_bytes(
+                bytes
+                    .get(pos..pos + 4)
+                    .ok_or_else(truncated)?
+                    .try_into()
+                    .unwrap(),
+            ))
+        };
+        let (width, height, count) = (field(5)? as usize, field(9)? as usize, field(13)? as usize);
+        let block_count = (width / 2) * (height / 2);
+        let mut offsets = Vec::new();
+        if version == ANIMATION_VERSION {
+            let table = bytes
+                .get(STREAM_HEADER_LEN..STREAM_HEADER_LEN.saturating_add(count.saturating_mul(8)))
+                .ok_or_else(truncated)?;
+            offsets = table
+                .chunks_exact(8)
+                .map(|offset| u64::from_be_bytes(offset.try_into().unwrap()) as usize)
+                .collect();
+        } else {
+            // Without an index, the frames are walked: a delta frame is as long as its bitmap
+            // and the code words of the blocks it marks.
+            let mut pos = STREAM_HEADER_LEN;
+            for _ in 0..count {
+                offsets.push(pos);
+                pos += match *bytes.get(pos).ok_or_else(truncated)? {
+                    KEY_FRAME => 1 + block_count * 4,
+                    MOTION_FRAME => return Err("Motion frames need a frame index".to_string()),
+                    _ => {
+                        let bitmap = bytes
+                            .get(pos + 1..pos + 1 + block_count.div_ceil(8))
+                            .ok_or_else(truncated)?;
+                        let marked: u32 = bitmap.iter().map(|byte| byte.count_ones()).sum();
+                        1 + bitmap.len() + marked as usize * 4
+                    }
+                };
+            }
+        }
+        let key_frames = offsets
+            .iter()
+            .enumerate()
+            .map(|(index, offset)| match bytes.get(*offset) {
+                Some(&KEY_FRAME) => Ok(true),
+                Some(&DELTA_FRAME | &MOTION_FRAME) if index > 0 => Ok(false),
+                Some(_) => Err(format!("Frame {index} has an invalid frame type")),
+                None => Err(truncated()),
+            })
+            .collect::<Result<Vec<bool>, String>>()?;
+        Ok(FrameIndex {
+            width,
+            height,
+            offsets,
+            key_frames,
+        })
+    }
+
+    /// Returns the number of 2x2 blocks of every frame.
+    pub fn block_count(&self) -> usize {
+        (self.width / 2) * (self.height / 2)
+    }
+
+    /// Returns the number of macroblocks of every frame, each with its own motion vector in a
+    /// motion frame.
+    pub fn macroblock_count(&self) -> usize {
+        (self.width / 2).div_ceil(MACROBLOCK_BLOCKS) * (self.height / 2).div_ceil(MACROBLOCK_BLOCKS)
+    }
+
+    /// Returns the last key frame at or before `frame`, or None if `frame` is not in the
+    /// stream.
+    ///
+    /// # Arguments
+    /// * `frame`: Number of the frame, counted from 0
+    pub fn key_frame_before(&self, frame: usize) -> Option<usize> {
+        self.key_frames
+            .get(..=frame)?
+            .iter()
+            .rposition(|key_frame| *key_frame)
+    }
+}
+
+/// Updates `words`, the code words the decoder holds, with the frame starting at `offset`.
+///
+/// # Arguments
+/// * `bytes`: Raw bytes of the multi-frame stream
+/// * `offset`: Position of the frame, at its frame type
+/// * `index`: Frame index of the stream
+/// * `words`: Code word of every block, empty before the first key frame
+fn apply_frame(
+    bytes: &[u8],
+    offset: usize,
+    index: &FrameIndex,
+    words: &mut Vec<u64>,
+) -> Result<(), String> {
+    let block_count = index.block_count();
+    let truncated = || "Ran out of bytes while reading the frames".to_string();
+    let read_word = |pos: usize| {
+        bytes
+            .get(pos..pos + 4)
+            .map(|word| NARROW_LAYOUT.read_word(word))
+            .ok_or_else(truncated)
+    };
+    let mut pos = offset + 1;
+    match bytes.get(offset) {
+        Some(&KEY_FRAME) => {
+            *words = (0..block_count)
+                .map(|block| read_word(pos + block * 4))
+                .collect::<Result<_, String>>()?;
+        }
+        Some(&DELTA_FRAME) if !words.is_empty() => {
+            let bitmap = bytes
+                .get(pos..pos + block_count.div_ceil(8))
+                .ok_or_else(truncated)?;
+            pos += bitmap.len();
+            for (block, word) in words.iter_mut().enumerate() {
+                if bitmap[block / 8] & (0x80 >> (block % 8)) != 0 {
+                    *word = read_word(pos)?;
+                    pos += 4;
+                }
+            }
+        }
+        Some(&MOTION_FRAME) if !words.is_empty() => {
+            let table = bytes
+                .get(pos..pos + index.macroblock_count() * 2)
+                .ok_or_else(truncated)?;
+            pos += table.len();
+            let vectors = table
+                .chunks_exact(2)
+                .map(|vector| {
+                    let vector = u16::from_be_bytes([vector[0], vector[1]]) as u64;
+                    let (dx, dy) = (
+                        gets(vector, MOTION_DX.width, MOTION_DX.lsb),
+                        gets(vector, MOTION_DY.width, MOTION_DY.lsb),
+                    );
+                    if dx % 2 != 0 || dy % 2 != 0 {
+                        return Err(format!("Motion vector ({dx}, {dy}) is not block aligned"));
+                    }
+                    Ok((dx / 2, dy / 2))
+                })
+                .collect::<Result<Vec<(i64, i64)>, String>>()?;
+            *words = predict(words, &vectors, index.width / 2, index.height / 2);
+            let bitmap = bytes
+                .get(pos..pos + block_count.div_ceil(8))
+                .ok_or_else(truncated)?;
+            pos += bitmap.len();
+            for (block, word) in words.iter_mut().enumerate() {
+                if bitmap[block / 8] & (0x80 >> (block % 8)) != 0 {
+                    *word = add_residual(*word, read_word(pos)?);
+                    pos += 4;
+                }
+            }
+        }
+        _ => return Err("A delta frame does not follow a key frame".to_string()),
+    }
+    Ok(())
+}
+
+/// Returns the frame whose code words the decoder holds.
+///
+/// # Arguments
+/// * `words`: Code word of every block
+/// * `index`: Frame index of the stream
+/// * `options`: Settings used to decompress the frame, of which only `dither`, `tint`, and
+///   `arithmetic` apply
+fn decode_frame(
+    words: &[u64],
+    index: &FrameIndex,
+    options: &DecodeOptions,
+) -> Result<RgbImage, String> {
+    let pixels = decode_words(
+        words,
+        &vec![DEFAULT_LUMA_RANGE; index.block_count()],
+        index.width,
+        index.height,
+        options.dither.then_some((0, 0)),
+        &NARROW_LAYOUT,
+        false,
+        PixelFormat::Rgb,
+        options.tint.as_ref(),
+        options.arithmetic,
+        &Timings::new(),
+    )?;
+    Ok(RgbImage {
+        pixels: pixels.data,
+        width: index.width as u32,
+        height: index.height as u32,
+        denominator: 255,
+    })
+}
+
+/// Takes a multi-frame stream written by `compress_frames` and reconstructs every frame.
+///
+/// # Arguments
+/// * `bytes`: Raw bytes of the multi-frame stream
+pub fn decompress_frames(bytes: &[u8]) -> Result<Vec<RgbImage>, String> {
+    let index = FrameIndex::read(bytes)?;
+    let mut words = Vec::new();
+    index
+        .offsets
+        .iter()
+        .map(|offset| {
+            apply_frame(bytes, *offset, &index, &mut words)?;
+            decode_frame(&words, &index, &DecodeOptions::default())
+        })
+        .collect()
+}
+
+/// Decodes frame `frame` of a multi-frame stream without decoding the frames before it: the
+/// code words are read from the last key frame before it, and only the frame itself is
+/// converted back to pixels.
+///
+/// # Arguments
+/// * `bytes`: Raw bytes of the multi-frame stream
+/// * `frame`: Number of the frame, counted from 0
+/// * `options`: Settings used to decompress the frame, of which only `dither`, `tint`, and
+///   `arithmetic` apply
+pub fn seek_frame(bytes: &[u8], frame: usize, options: &DecodeOptions) -> Result<RgbImage, String> {
+    let index = FrameIndex::read(bytes)?;
+    let key_frame = index.key_frame_before(frame).ok_or(format!(
+        "Frame {frame} is past the last of the {} frames",
+        index.offsets.len()
+    ))?;
+    let mut words = Vec::new();
+    for offset in &index.offsets[key_frame..=frame] {
+        apply_frame(bytes, *offset, &index, &mut words)?;
+    }
+    decode_frame(&words, &index, options)
+}
+
+/// Expands a printf-style frame pattern such as `frame_%04d.ppm` for the frame `index`. Only a
+/// single `%d` conversion, with an optional zero-padded width, is understood.
+///
+/// # Arguments
+/// * `pattern`: Location of the frames with a `%d` conversion where the frame number goes
+/// * `index`: Number of the frame
+pub fn frame_path(pattern: &str, index: usize) -> String {
+    let Some(start) = pattern.find('%') else {
+        return pattern.to_string();
+    };
+    let Some(len) = pattern[start + 1..].find('d') else {
+        return pattern.to_string();
+    };
+    let spec = &pattern[start + 1..start + 1 + len];
+    if !spec.chars().all(|c| c.is_ascii_digit()) {
+        return pattern.to_string();
+    }
+    let width = spec.parse::<usize>().unwrap_or(0);
+    let number = if spec.starts_with('0') {
+        format!("{index:0width$}")
+    } else {
+        format!("{index:width$}")
+    };
+    format!(
+        "{}{}{}",
+        &pattern[..start],
+        number,
+        &pattern[start + 2 + len..]
+    )
+}
+
+/// Reads the frames matching `pattern`, numbered from 0 (or from 1 when there is no frame 0)
+/// until the first missing number, compresses them into a multi-frame stream, and writes it to
+/// standard out.
+///
+/// # Arguments
+/// * `pattern`: Location of the PPM frames, such as `frame_%04d.ppm`
+/// * `options`: Settings used to compress the sequence
+pub fn compress_sequence(pattern: &str, options: &SequenceOptions) -> Result<(), CliError> {
+    let first = if std::path::Path::new(&frame_path(pattern, 0)).exists() {
+        0
+    } else {
+        1
+    };
+    let frames = (first..)
+        .map(|index| frame_path(pattern, index))
+        .take_while(|path| std::path::Path::new(path).exists())
+        .map(|path| RgbImage::read(Some(&path)))
+        .collect::<Result<Vec<RgbImage>, String>>()
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let compressed = compress_frames(&frames, options)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    write_output(&compressed, None).map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+/// Decompresses a multi-frame stream and writes every frame as a PPM to the location given by
+/// `pattern`, numbering the frames from 0.
+///
+/// # Arguments
+/// * `filename`: Location of the multi-frame stream, or None to read from standard in
+/// * `pattern`: Location of the PPM frames to write, such as `out_%04d.ppm`
+pub fn decompress_sequence(filename: Option<&str>, pattern: &str) -> Result<(), CliError> {
+    let bytes = read_input(filename)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let frames = decompress_frames(&bytes).map_err(|message| CliError::stream(&bytes, message))?;
+    for (index, frame) in frames.iter().enumerate() {
+        frame
+            .write(Some(&frame_path(pattern, index)))
+            .map_err(|message| CliError::new(ErrorKind::Io, message))?;
+    }
+    Ok(())
+}
+
+/// Decodes one frame of a multi-frame stream with `seek_frame` and writes it as a PPM to
+/// `output`.
+///
+/// # Arguments
+/// * `filename`: Location of the multi-frame stream, or None to read from standard in
+/// * `frame`: Number of the frame, counted from 0
+/// * `output`: Destination of the PPM frame
+/// * `options`: Settings used to decompress the frame
+pub fn decompress_frame(
+    filename: Option<&str>,
+    frame: usize,
+    output: &Output,
+    options: &DecodeOptions,
+) -> Result<(), CliError> {
+    let bytes = read_input(filename)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let image =
+        seek_frame(&bytes, frame, options).map_err(|message| CliError::stream(&bytes, message))?;
+    output
+        .write_with(|writer| image.write_to(writer))
+        .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32, shift: u32) -> RgbImage {
+        let pixels = (0..width * height)
+            .map(|i| {
+                let x = i % width;
+                let value = if x >= shift && x < shift + 4 { 250 } else { 20 };
+                Rgb {
+                    red: value,
+                    green: value / 2,
+                    blue: 100,
+                }
+            })
+            .collect();
+        RgbImage {
+            pixels,
+            width,
+            height,
+            denominator: 255,
+        }
+    }
+
+    #[test]
+    fn motion_frames_follow_panning() {
+        // A textured scene panning 4 pixels to the left per frame.
+        let scene = |shift: u32| RgbImage {
+            pixels: (0..32 * 16_u32)
+                .map(|i| {
+                    let (x, y) = (i % 32 + shift, i / 32);
+                    let value = ((x / 2 * 37 + y / 2 * 91) % 200 + 20) as u16;
+                    Rgb {
+                        red: value,
+                        green: 255 - value,
+                        blue: value / 2,
+                    }
+                })
+                .collect(),
+            width: 32,
+            height: 16,
+            denominator: 255,
+        };
+        let frames: Vec<RgbImage> = (0..4).map(|index| scene(index * 4)).collect();
+        let delta = compress_frames(&frames, &SequenceOptions::default()).unwrap();
+        let options = SequenceOptions {
+            motion: true,
+            ..SequenceOptions::default()
+        };
+        let motion = compress_frames(&frames, &options).unwrap();
+        assert!(motion.len() < delta.len() / 2);
+        assert_eq!(decompress_frames(&motion), decompress_frames(&delta));
+        assert_eq!(
+            seek_frame(&motion, 3, &DecodeOptions::default()),
+            Ok(decompress_frames(&delta).unwrap().remove(3))
+        );
+        let (predicted, current) = (0x8123_4567, 0x0FED_CBA9);
+        assert_eq!(
+            add_residual(predicted, word_residual(predicted, current)),
+            current
+        );
+    }
+
+    #[test]
+    fn key_frames_follow_scene_cuts_and_intervals() {
+        // A cut to a scene sharing no block with the first one, held for three frames.
+        let cut = RgbImage {
+            pixels: vec![
+                Rgb {
+                    red: 60,
+                    green: 200,
+                    blue: 240,
+                };
+                16 * 8
+            ],
+            ..frame(16, 8, 0)
+        };
+        let frames = vec![
+            frame(16, 8, 0),
+            frame(16, 8, 0),
+            cut.clone(),
+            cut.clone(),
+            cut,
+        ];
+        let key_frames = |options: &SequenceOptions| {
+            let stream = compress_frames(&frames, options).unwrap();
+            FrameIndex::read(&stream).unwrap().key_frames
+        };
+        assert_eq!(
+            key_frames(&SequenceOptions::default()),
+            [true, false, true, false, false]
+        );
+        let interval = SequenceOptions {
+            keyframe_interval: Some(2),
+            scene_cut_percent: 100,
+            ..SequenceOptions::default()
+        };
+        assert_eq!(key_frames(&interval), [true, false, true, false, true]);
+        let stream = compress_frames(&frames, &interval).unwrap();
+        assert_eq!(
+            seek_frame(&stream, 4, &DecodeOptions::default()),
+            Ok(decompress_frames(&stream).unwrap().remove(4))
+        );
+        let never = SequenceOptions {
+            keyframe_interval: Some(0),
+            ..SequenceOptions::default()
+        };
+        assert!(compress_frames(&frames, &never).is_err());
+    }
+
+    #[test]
+    fn frame_patterns_expand() {
+        assert_eq!(frame_path("frame_%04d.ppm", 7), "frame_0007.ppm");
+        assert_eq!(frame_path("f%d.ppm", 12), "f12.ppm");
+        assert_eq!(frame_path("still.ppm", 3), "still.ppm");
+    }
+
+    #[test]
+    fn delta_frames_only_store_changed_blocks() {
+        let frames = vec![frame(16, 8, 0), frame(16, 8, 0), frame(16, 8, 8)];
+        let compressed = compress_frames(&frames, &SequenceOptions::default()).unwrap();
+        // Header, frame index, key frame, an empty delta frame, and a delta frame with a few
+        // blocks.
+        let prelude = 17 + 3 * 8;
+        let key_frame = 1 + 32 * 4;
+        let empty_delta = 1 + 4;
+        assert!(compressed.len() < prelude + key_frame + 2 * empty_delta + 32 * 4);
+        assert_eq!(compressed[prelude + key_frame], DELTA_FRAME);
+        assert!(
+            compressed[prelude + key_frame + 1..prelude + key_frame + empty_delta]
+                .iter()
+                .all(|byte| *byte == 0)
+        );
+
+        let decoded = decompress_frames(&compressed).unwrap();
+        assert_eq!(decoded.len(), 3);
+        let options = DecodeOptions::default();
+        assert_eq!(
+            seek_frame(&compressed, 2, &options).as_ref(),
+            Ok(&decoded[2])
+        );
+        assert!(seek_frame(&compressed, 3, &options).is_err());
+
+        // The same stream without its frame index, as version 1 wrote it.
+        let mut unindexed = compressed[..17].to_vec();
+        unindexed[4] = UNINDEXED_VERSION;
+        unindexed.extend_from_slice(&compressed[prelude..]);
+        assert_eq!(
+            FrameIndex::read(&unindexed).unwrap().offsets,
+            FrameIndex::read(&compressed)
+                .unwrap()
+                .offsets
+                .iter()
+                .map(|offset| offset - 3 * 8)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(decompress_frames(&unindexed).as_ref(), Ok(&decoded));
+        for (original, decoded) in frames.iter().zip(decoded.iter()) {
+            let expected = crate::codec::decompress_image(&crate::codec::compress_image(
+                original,
+                &Default::default(),
+            ))
+            .unwrap();
+            assert!(expected
+                .pixels
+                .iter()
+                .zip(decoded.pixels.iter())
+                .all(|(a, b)| (a.red, a.green, a.blue) == (b.red, b.green, b.blue)));
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use crate::codec::{check_encodable, compress_image, decompress_image, EncoderOptions};
+use crate::error::{CliError, ErrorKind};
+use crate::format::Header;
+use crate::io::{read_input, Output};
+use crate::png_image::read_png_or_ppm;
+use crate::ppm::RgbImage;
+use std::path::Path;
+
+/// Magic bytes that open every rpeg archive.
+pub const ARCHIVE_MAGIC: &[u8; 4] = b"RPAR";
+
+/// Archive version written by `write_archive`.
+pub const ARCHIVE_VERSION: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Represent one compressed image stored in an archive
+///
+/// `data` holds a complete compressed image, header included, so an entry can be decompressed
+/// on its own. `width` and `height` repeat the dimensions from that header so that the table of
+/// contents can be listed without parsing every image.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::archive::{read_archive, write_archive, ArchiveEntry};
+///
+/// let entry = ArchiveEntry { name: "sprite".to_string(), width: 0, height: 0, data: vec![] };
+/// let archive = write_archive(&[entry.clone()]);
+/// assert_eq!(read_archive(&archive), Ok(vec![entry]));
+/// ```
+pub struct ArchiveEntry {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl ArchiveEntry {
+    /// Builds an entry for an already compressed image, reading its dimensions from its header.
+    ///
+    /// # Arguments
+    /// * `name`: Name the image is stored under
+    /// * `data`: Compressed image, header included
+    pub fn from_compressed(name: &str, data: Vec<u8>) -> Result<ArchiveEntry, String> {
+        let (header, _) = Header::read(&data)?;
+        Ok(ArchiveEntry {
+            name: name.to_string(),
+            width: header.width,
+            height: header.height,
+            data,
+        })
+    }
+}
+
+/// Serializes `entries` into an archive: the magic bytes, the version, and the number of
+/// entries, followed by the table of contents and then the compressed images back to back.
+/// Every table of contents record holds the length of the name (16 bits), the name in UTF-8,
+/// the width and height (32 bits each), and the offset and length of the compressed image
+/// within the archive (64 bits each), all in Bigendian format.
+///
+/// # Arguments
+/// * `entries`: Compressed images to store, in order
+pub fn write_archive(entries: &[ArchiveEntry]) -> Vec<u8> {
+    let toc_len: usize = entries.iter().map(|entry| 26 + entry.name.len()).sum();
+    let mut offset = (9 + toc_len) as u64;
+    let mut output = Vec::new();
+    output.extend_from_slice(ARCHIVE_MAGIC);
+    output.push(ARCHIVE_VERSION);
+    output.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    for entry in entries.iter() {
+        output.extend_from_slice(&(entry.name.len() as u16).to_be_bytes());
+        output.extend_from_slice(entry.name.as_bytes());
+        output.extend_from_slice(&entry.width.to_be_bytes());
+        output.extend_from_slice(&entry.height.to_be_bytes());
+        output.extend_from_slice(&offset.to_be_bytes());
+        output.extend_from_slice(&(entry.data.len() as u64).to_be_bytes());
+        offset += entry.data.len() as u64;
+    }
+    for entry in entries.iter() {
+        output.extend_from_slice(&entry.data);
+    }
+    output
+}
+
+/// Parses an archive written by `write_archive` and returns its entries in order.
+///
+/// # Arguments
+/// * `bytes`: Raw bytes of the archive
+pub fn read_archive(bytes: &[u8]) -> Result<Vec<ArchiveEntry>, String> {
+    let truncated = || "Ran out of bytes while reading the archive".to_string();
+    if !bytes.starts_with(ARCHIVE_MAGIC) {
+        return Err("Input is not an rpeg archive".to_string());
+    }
+    match bytes.get(4) {
+        Some(&ARCHIVE_VERSION) => {}
+        Some(version) => return Err(format!("Unsupported archive version {version}")),
+        None => return Err(truncated()),
+    }
+    let count = u32::from_be_bytes(bytes.get(5..9).ok_or_else(truncated)?.try_into().unwrap());
+    let mut pos = 9;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let name_len = u16::from_be_bytes(
+            bytes
+                .get(pos..pos + 2)
+                .ok_or_else(truncated)?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        pos += 2;
+        let name = std::str::from_utf8(bytes.get(pos..pos + name_len).ok_or_else(truncated)?)
+            .map_err(|_| "Archive entry name is not valid UTF-8".to_string())?
+            .to_string();
+        pos += name_len;
+        let record = bytes.get(pos..pos + 24).ok_or_else(truncated)?;
+        let width = u32::from_be_bytes(record[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(record[4..8].try_into().unwrap());
+        let offset = u64::from_be_bytes(record[8..16].try_into().unwrap()) as usize;
+        let length = u64::from_be_bytes(record[16..24].try_into().unwrap()) as usize;
+        pos += 24;
+        let data = bytes
+            .get(offset..offset.saturating_add(length))
+            .ok_or_else(truncated)?
+            .to_vec();
+        entries.push(ArchiveEntry {
+            name,
+            width,
+            height,
+            data,
+        });
+    }
+    Ok(entries)
+}
+
+/// Compresses every PPM image in `filenames` and stores them in a single archive, named after
+/// the file stem of each image. The archive is written to `output`.
+///
+/// # Arguments
+/// * `filenames`: Locations of the PPM images within your disk
+/// * `output`: Destination of the archive
+/// * `options`: Settings used to compress every image
+pub fn pack(filenames: &[&str], output: &Output, options: &EncoderOptions) -> Result<(), CliError> {
+    check_encodable(options)?;
+    let entries = filenames
+        .iter()
+        .map(|filename| {
+            let image = RgbImage::read(Some(filename))
+                .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+            let name = Path::new(filename).file_stem().map_or_else(
+                || filename.to_string(),
+                |stem| stem.to_string_lossy().to_string(),
+            );
+            let data = compress_image(&image, options);
+            ArchiveEntry::from_compressed(&name, data)
+                .map_err(|message| CliError::new(ErrorKind::CorruptStream, message))
+        })
+        .collect::<Result<Vec<ArchiveEntry>, CliError>>()?;
+    let archive = write_archive(&entries);
+    output
+        .write(&archive)
+        .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+/// Adds `added` to `entries`. An entry replaces the entry of the same name in its position,
+/// and entries with new names are appended, so the other entries keep their order and bytes.
+///
+/// # Arguments
+/// * `entries`: Entries of an archive
+/// * `added`: Entries to add
+pub fn add_entries(entries: &mut Vec<ArchiveEntry>, added: Vec<ArchiveEntry>) {
+    for entry in added {
+        match entries
+            .iter_mut()
+            .find(|existing| existing.name == entry.name)
+        {
+            Some(existing) => *existing = entry,
+            None => entries.push(entry),
+        }
+    }
+}
+
+/// Removes the entries called `names` from `entries`. Returns an error, leaving `entries`
+/// unchanged, if one of the names is not in the archive.
+///
+/// # Arguments
+/// * `entries`: Entries of an archive
+/// * `names`: Names of the entries to remove
+pub fn remove_entries(entries: &mut Vec<ArchiveEntry>, names: &[&str]) -> Result<(), String> {
+    check_names(entries, names)?;
+    entries.retain(|entry| !names.contains(&entry.name.as_str()));
+    Ok(())
+}
+
+/// Returns an error naming the first of `names` that is not the name of one of `entries`.
+///
+/// # Arguments
+/// * `entries`: Entries of an archive
+/// * `names`: Names of entries
+fn check_names(entries: &[ArchiveEntry], names: &[&str]) -> Result<(), String> {
+    match names
+        .iter()
+        .find(|name| !entries.iter().any(|entry| entry.name == **name))
+    {
+        Some(missing) => Err(format!("The archive has no entry called {missing}")),
+        None => Ok(()),
+    }
+}
+
+/// Returns the table of contents of an archive, one line per entry with its name, its
+/// dimensions, and the size of its compressed image.
+///
+/// # Arguments
+/// * `entries`: Entries of an archive
+pub fn list_entries(entries: &[ArchiveEntry]) -> String {
+    let name_width = entries
+        .iter()
+        .map(|entry| entry.name.len())
+        .max()
+        .unwrap_or(0);
+    entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "{:<name_width$}  {}x{}  {} bytes\n",
+                entry.name,
+                entry.width,
+                entry.height,
+                entry.data.len()
+            )
+        })
+        .collect()
+}
+
+/// Reads the entries of the archive `filename`.
+///
+/// # Arguments
+/// * `filename`: Location of the archive within your disk, or None to read from standard in
+fn read_entries(filename: Option<&str>) -> Result<Vec<ArchiveEntry>, CliError> {
+    let bytes = read_input(filename)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    read_archive(&bytes).map_err(|message| CliError::stream(&bytes, message))
+}
+
+/// Replaces the archive `archive` with one holding `entries`.
+///
+/// # Arguments
+/// * `archive`: Location of the archive within your disk
+/// * `entries`: Entries of the updated archive
+fn write_entries(archive: &str, entries: &[ArchiveEntry]) -> Result<(), CliError> {
+    let output = Output {
+        filename: Some(archive.to_string()),
+        force: true,
+    };
+    output
+        .write(&write_archive(entries))
+        .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+/// Returns the entry of the image `filename`, named after its file stem. A compressed image is
+/// stored as it is, and a PPM or PNG image is compressed with `options`.
+///
+/// # Arguments
+/// * `filename`: Location of the image within your disk
+/// * `options`: Settings used to compress the image
+fn image_entry(filename: &str, options: &EncoderOptions) -> Result<ArchiveEntry, CliError> {
+    let bytes = read_input(Some(filename))
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let name = Path::new(filename).file_stem().map_or_else(
+        || filename.to_string(),
+        |stem| stem.to_string_lossy().to_string(),
+    );
+    let data = if Header::read(&bytes).is_ok() {
+        bytes
+    } else {
+        let image = read_png_or_ppm(&bytes)
+            .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+        compress_image(&image, options)
+    };
+    ArchiveEntry::from_compressed(&name, data)
+        .map_err(|message| CliError::new(ErrorKind::CorruptStream, message))
+}
+
+/// Adds the images `filenames` to the archive `archive`, creating it if it does not exist.
+/// Images already compressed are stored as they are and the other entries are copied without
+/// being recompressed; an image replaces the entry of the same name.
+///
+/// # Arguments
+/// * `archive`: Location of the archive within your disk
+/// * `filenames`: Locations of the PPM, PNG, or compressed images to add
+/// * `options`: Settings used to compress the PPM and PNG images
+pub fn archive_add(
+    archive: &str,
+    filenames: &[&str],
+    options: &EncoderOptions,
+) -> Result<(), CliError> {
+    check_encodable(options)?;
+    let mut entries = if Path::new(archive).exists() {
+        read_entries(Some(archive))?
+    } else {
+        Vec::new()
+    };
+    let added = filenames
+        .iter()
+        .map(|filename| image_entry(filename, options))
+        .collect::<Result<Vec<ArchiveEntry>, CliError>>()?;
+    add_entries(&mut entries, added);
+    write_entries(archive, &entries)
+}
+
+/// Removes the entries called `names` from the archive `archive`, copying the others without
+/// recompressing them.
+///
+/// # Arguments
+/// * `archive`: Location of the archive within your disk
+/// * `names`: Names of the entries to remove
+pub fn archive_remove(archive: &str, names: &[&str]) -> Result<(), CliError> {
+    let mut entries = read_entries(Some(archive))?;
+    remove_entries(&mut entries, names)
+        .map_err(|message| CliError::new(ErrorKind::BadArguments, message))?;
+    write_entries(archive, &entries)
+}
+
+/// Prints the table of contents of an archive to standard out.
+///
+/// # Arguments
+/// * `filename`: Location of the archive within your disk, or None to read from standard in
+pub fn archive_list(filename: Option<&str>) -> Result<(), CliError> {
+    print!("{}", list_entries(&read_entries(filename)?));
+    Ok(())
+}
+
+/// Decompresses every image of an archive into `out_dir`, as `<name>.ppm`. Any directory part
+/// of an entry name is dropped, so an archive cannot write outside of `out_dir`.
+///
+/// # Arguments
+/// * `filename`: Location of the archive within your disk, or None to read from standard in
+/// * `out_dir`: Directory receiving the decompressed images
+pub fn unpack(filename: Option<&str>, out_dir: &str) -> Result<(), CliError> {
+    archive_extract(filename, &[], out_dir)
+}
+
+/// Decompresses the entries called `names` of an archive into `out_dir`, or every entry if
+/// `names` is empty, as `unpack` does.
+///
+/// # Arguments
+/// * `filename`: Location of the archive within your disk, or None to read from standard in
+/// * `names`: Names of the entries to extract
+/// * `out_dir`: Directory receiving the decompressed images
+pub fn archive_extract(
+    filename: Option<&str>,
+    names: &[&str],
+    out_dir: &str,
+) -> Result<(), CliError> {
+    let mut entries = read_entries(filename)?;
+    check_names(&entries, names)
+        .map_err(|message| CliError::new(ErrorKind::BadArguments, message))?;
+    entries.retain(|entry| names.is_empty() || names.contains(&entry.name.as_str()));
+    for entry in entries {
+        let image = decompress_image(&entry.data)
+            .map_err(|message| CliError::stream(&entry.data, message))?;
+        let name = Path::new(&entry.name)
+            .file_name()
+            .map_or_else(|| "image".into(), |name| name.to_string_lossy());
+        let path = Path::new(out_dir).join(format!("{name}.ppm"));
+        image
+            .write(path.to_str())
+            .map_err(|message| CliError::new(ErrorKind::Io, message))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_added_and_removed_without_touching_the_others() {
+        let entry = |name: &str, data: &[u8]| ArchiveEntry {
+            name: name.to_string(),
+            width: data.len() as u32,
+            height: 1,
+            data: data.to_vec(),
+        };
+        let mut entries = vec![entry("a", b"first"), entry("b", b"second")];
+        add_entries(
+            &mut entries,
+            vec![entry("c", b"third"), entry("a", b"replaced")],
+        );
+        assert_eq!(
+            entries,
+            [
+                entry("a", b"replaced"),
+                entry("b", b"second"),
+                entry("c", b"third")
+            ]
+        );
+        assert!(remove_entries(&mut entries, &["b", "missing"]).is_err());
+        assert_eq!(entries.len(), 3);
+        remove_entries(&mut entries, &["b"]).unwrap();
+        let archive = write_archive(&entries);
+        assert_eq!(read_archive(&archive).as_ref(), Ok(&entries));
+        assert_eq!(list_entries(&entries), "a  8x1  8 bytes\nc  5x1  5 bytes\n");
+    }
+}
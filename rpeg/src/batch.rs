@@ -0,0 +1,461 @@
+use crate::codec::{compress, EncoderOptions};
+use crate::dct_coeff::luma_range_for_quality;
+use crate::error::{CliError, ErrorKind};
+use crate::io::Output;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Name given to the compressed images of a batch when `--name-template` is not given.
+pub const DEFAULT_NAME_TEMPLATE: &str = "{stem}.rpeg";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Piece of a name template: literal text or a placeholder.
+enum Piece {
+    Text(String),
+    Stem,
+    Extension,
+    Quality,
+    Index,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Template of the names of the compressed images of a batch
+///
+/// `{stem}` is replaced with the file name of the input without its extension, `{ext}` with
+/// its extension, `{quality}` with the quality it is compressed at, and `{index}` with the
+/// position of the input on the command line, from 1. `{{` and `}}` stand for braces.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::batch::NameTemplate;
+///
+/// let template = NameTemplate::parse("{stem}-q{quality}.rpeg").unwrap();
+/// assert_eq!(template.render("photos/cat.ppm", 1, Some(80)), "cat-q80.rpeg");
+/// assert!(NameTemplate::parse("{size}.rpeg").is_err());
+/// ```
+pub struct NameTemplate {
+    pieces: Vec<Piece>,
+}
+
+impl NameTemplate {
+    /// Parses a template, rejecting unknown placeholders and unmatched braces.
+    ///
+    /// # Arguments
+    /// * `template`: Template, such as `{stem}-q{quality}.rpeg`
+    pub fn parse(template: &str) -> Result<NameTemplate, String> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(char) = chars.next() {
+            match char {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(char) => name.push(char),
+                            None => return Err("Unmatched { in the name template".to_string()),
+                        }
+                    }
+                    let piece = match name.as_str() {
+                        "stem" => Piece::Stem,
+                        "ext" => Piece::Extension,
+                        "quality" => Piece::Quality,
+                        "index" => Piece::Index,
+                        _ => {
+                            return Err(format!(
+                                "Unknown placeholder {{{name}}} in the name template, expected \
+                                 {{stem}}, {{ext}}, {{quality}}, or {{index}}"
+                            ))
+                        }
+                    };
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    pieces.push(piece);
+                }
+                '}' => return Err("Unmatched } in the name template".to_string()),
+                _ => text.push(char),
+            }
+        }
+        pieces.push(Piece::Text(text));
+        if !pieces
+            .iter()
+            .any(|piece| *piece != Piece::Text(String::new()))
+        {
+            return Err("The name template is empty".to_string());
+        }
+        Ok(NameTemplate { pieces })
+    }
+
+    /// Returns true if the template has a `{quality}` placeholder.
+    pub fn uses_quality(&self) -> bool {
+        self.pieces.contains(&Piece::Quality)
+    }
+
+    /// Returns the name of the compressed version of `filename`. A `{quality}` placeholder
+    /// without a quality is left out.
+    ///
+    /// # Arguments
+    /// * `filename`: Location of the input image
+    /// * `index`: Position of the input in the batch, from 1
+    /// * `quality`: Quality the image is compressed at, if known
+    pub fn render(&self, filename: &str, index: usize, quality: Option<u8>) -> String {
+        let path = Path::new(filename);
+        let part = |part: Option<&std::ffi::OsStr>| {
+            part.map_or(String::new(), |part| part.to_string_lossy().into_owned())
+        };
+        self.pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Text(text) => text.clone(),
+                Piece::Stem => part(path.file_stem()),
+                Piece::Extension => part(path.extension()),
+                Piece::Quality => quality.map_or(String::new(), |quality| quality.to_string()),
+                Piece::Index => index.to_string(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// ## What a batch does with an input that cannot be read or parsed
+///
+/// Failures to write an output, or a cancellation, stop the batch whatever the policy.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::batch::OnError;
+///
+/// assert_eq!(OnError::parse("retry:3"), Ok(OnError::Retry(3)));
+/// assert_eq!(OnError::parse("skip"), Ok(OnError::Skip));
+/// assert!(OnError::parse("retry").is_err());
+/// ```
+pub enum OnError {
+    /// Stop the batch at the first unreadable input.
+    #[default]
+    Abort,
+    /// Leave the input out, and list it once the other inputs are compressed.
+    Skip,
+    /// Read the input again up to this many times, `RETRY_DELAY` apart, then skip it.
+    Retry(u32),
+}
+
+/// Time waited before reading an unreadable input again, for `OnError::Retry`. It gives
+/// network file systems and files still being copied a chance to settle.
+pub const RETRY_DELAY: Duration = Duration::from_millis(200);
+
+impl OnError {
+    /// Parses the value of `--on-error`: `skip`, `abort`, or `retry:N`.
+    ///
+    /// # Arguments
+    /// * `text`: Name of the policy
+    pub fn parse(text: &str) -> Result<OnError, String> {
+        match text {
+            "abort" => Ok(OnError::Abort),
+            "skip" => Ok(OnError::Skip),
+            _ => text
+                .strip_prefix("retry:")
+                .and_then(|count| count.parse::<u32>().ok())
+                .map(OnError::Retry)
+                .ok_or_else(|| format!("--on-error expects skip, abort, or retry:N, not {text}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Compression of one input of a batch, at one quality
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::batch::{plan_batch, NameTemplate};
+/// use std::path::Path;
+///
+/// let template = NameTemplate::parse("{stem}-q{quality}.rpeg").unwrap();
+/// let qualities = [Some(10), Some(90)];
+/// let jobs = plan_batch(&["cat.ppm"], &template, Path::new("out"), &qualities).unwrap();
+/// assert_eq!(jobs[1].output, Path::new("out/cat-q90.rpeg"));
+/// ```
+pub struct BatchJob {
+    /// Location of the input image.
+    pub input: String,
+    /// Quality the image is compressed at, or None for the luma range of the settings.
+    pub quality: Option<u8>,
+    /// Location of the compressed image.
+    pub output: PathBuf,
+}
+
+/// Returns the compression of every input at every quality, named by `template` below
+/// `out_dir`, or an error naming the first two jobs that would write the same file.
+///
+/// # Arguments
+/// * `filenames`: Locations of the input images
+/// * `template`: Template of the names of the compressed images
+/// * `out_dir`: Directory receiving the compressed images
+/// * `qualities`: Qualities every input is compressed at; None keeps the luma range of the
+///   settings
+pub fn plan_batch(
+    filenames: &[&str],
+    template: &NameTemplate,
+    out_dir: &Path,
+    qualities: &[Option<u8>],
+) -> Result<Vec<BatchJob>, String> {
+    if template.uses_quality() && qualities.contains(&None) {
+        return Err("{quality} in the name template needs --quality or --qualities".to_string());
+    }
+    let mut jobs = Vec::new();
+    let mut writers: HashMap<PathBuf, usize> = HashMap::new();
+    for (index, filename) in filenames.iter().enumerate() {
+        for quality in qualities {
+            let output = out_dir.join(template.render(filename, index + 1, *quality));
+            let describe = |job: &BatchJob| match job.quality {
+                Some(quality) => format!("{} at quality {quality}", job.input),
+                None => job.input.clone(),
+            };
+            let job = BatchJob {
+                input: filename.to_string(),
+                quality: *quality,
+                output: output.clone(),
+            };
+            if let Some(earlier) = writers.insert(output.clone(), jobs.len()) {
+                return Err(format!(
+                    "Both {} and {} would be written to {}; add {{quality}} or {{index}} to the \
+                     name template",
+                    describe(&jobs[earlier]),
+                    describe(&job),
+                    output.display()
+                ));
+            }
+            jobs.push(job);
+        }
+    }
+    Ok(jobs)
+}
+
+/// Compresses every PPM, PNG, or PFM image of `filenames` at every quality of `qualities` into
+/// `out_dir`, with the names of `template`. Every name is checked for collisions, and, without
+/// `force`, for existing files, before anything is compressed. Failures are returned with the
+/// category the CLI exits with. Once the cancellation token of `options` is cancelled, the
+/// image being compressed is left out and the error counts the images written before it.
+/// Inputs that cannot be read or parsed are handled as `on_error` says; those that were
+/// skipped are listed, with their errors, in the error returned at the end. So are the images
+/// whose compressed file appeared after the check, which are left untouched without `force`.
+///
+/// # Arguments
+/// * `filenames`: Locations of the input images
+/// * `template`: Template of the names of the compressed images
+/// * `out_dir`: Directory receiving the compressed images, created if it does not exist
+/// * `qualities`: Qualities every input is compressed at, or None for the settings as they are
+/// * `options`: Settings used to compress the images
+/// * `force`: Replace compressed images that already exist
+/// * `porcelain`: Print the `--porcelain` record of every compressed image to standard out
+/// * `on_error`: What to do with an input that cannot be read or parsed
+#[allow(clippy::too_many_arguments)]
+pub fn compress_batch(
+    filenames: &[&str],
+    template: &NameTemplate,
+    out_dir: &Path,
+    qualities: Option<&[u8]>,
+    options: &EncoderOptions,
+    force: bool,
+    porcelain: bool,
+    on_error: OnError,
+) -> Result<(), CliError> {
+    let qualities: Vec<Option<u8>> = qualities.map_or(vec![None], |qualities| {
+        qualities.iter().copied().map(Some).collect()
+    });
+    let jobs = plan_batch(filenames, template, out_dir, &qualities)
+        .map_err(|message| CliError::new(ErrorKind::BadArguments, message))?;
+    if let Some(job) = jobs.iter().find(|job| !force && job.output.exists()) {
+        return Err(CliError::new(
+            ErrorKind::Io,
+            format!(
+                "{} already exists, use --force to replace it",
+                job.output.display()
+            ),
+        ));
+    }
+    std::fs::create_dir_all(out_dir).map_err(|error| {
+        CliError::new(
+            ErrorKind::Io,
+            format!("Failed to create {}: {error}", out_dir.display()),
+        )
+    })?;
+    let mut failures = Vec::new();
+    let mut collided = false;
+    for (done, job) in jobs.iter().enumerate() {
+        let options = EncoderOptions {
+            luma_range: job
+                .quality
+                .map(luma_range_for_quality)
+                .or(options.luma_range),
+            ..options.clone()
+        };
+        let output = Output {
+            filename: Some(job.output.to_string_lossy().into_owned()),
+            force,
+        };
+        let retries = match on_error {
+            OnError::Retry(retries) => retries,
+            _ => 0,
+        };
+        let mut result = compress(Some(&job.input), &output, &options, false, None);
+        for _ in 0..retries {
+            if !matches!(&result, Err(error) if error.kind == ErrorKind::UnreadableInput) {
+                break;
+            }
+            std::thread::sleep(RETRY_DELAY);
+            result = compress(Some(&job.input), &output, &options, false, None);
+        }
+        match result {
+            Ok(result) if porcelain => print!("{}", result.to_porcelain()),
+            Ok(_) => {}
+            Err(error) if error.kind == ErrorKind::Cancelled => {
+                return Err(CliError::new(
+                    ErrorKind::Cancelled,
+                    format!(
+                        "Cancelled after compressing {} of {} images",
+                        done - failures.len(),
+                        jobs.len()
+                    ),
+                ))
+            }
+            Err(error)
+                if error.kind == ErrorKind::UnreadableInput && on_error != OnError::Abort =>
+            {
+                failures.push(format!("{}: {error}", job.input))
+            }
+            Err(error) if error.kind == ErrorKind::Io && !force && job.output.exists() => {
+                collided = true;
+                failures.push(format!("{}: {error}", job.input))
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    if failures.is_empty() {
+        return Ok(());
+    }
+    let kind = if collided {
+        ErrorKind::Io
+    } else {
+        ErrorKind::UnreadableInput
+    };
+    Err(CliError::new(
+        kind,
+        format!(
+            "Failed to compress {} of {} images:\n{}",
+            failures.len(),
+            jobs.len(),
+            failures.join("\n")
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_name_every_job_and_catch_collisions() {
+        let template = NameTemplate::parse("{{{index}}}_{stem}.{ext}-q{quality}.rpeg").unwrap();
+        assert_eq!(
+            template.render("a/b.c.ppm", 3, Some(7)),
+            "{3}_b.c.ppm-q7.rpeg"
+        );
+        assert!(NameTemplate::parse("{stem").is_err());
+        assert!(NameTemplate::parse("stem}").is_err());
+        assert!(NameTemplate::parse("").is_err());
+
+        let out = Path::new("out");
+        let qualities = [Some(10), Some(90)];
+        let template = NameTemplate::parse(DEFAULT_NAME_TEMPLATE).unwrap();
+        let error = plan_batch(&["cat.ppm"], &template, out, &qualities).unwrap_err();
+        assert!(error.contains("cat.ppm at quality 10 and cat.ppm at quality 90"));
+        let error = plan_batch(&["a/cat.ppm", "b/cat.png"], &template, out, &[None]).unwrap_err();
+        assert!(error.contains("out/cat.rpeg"));
+
+        let template = NameTemplate::parse("{stem}-q{quality}.rpeg").unwrap();
+        assert!(plan_batch(&["cat.ppm"], &template, out, &[None]).is_err());
+        let jobs = plan_batch(&["cat.ppm", "dog.ppm"], &template, out, &qualities).unwrap();
+        let outputs: Vec<&Path> = jobs.iter().map(|job| job.output.as_path()).collect();
+        assert_eq!(
+            outputs,
+            [
+                "out/cat-q10.rpeg",
+                "out/cat-q90.rpeg",
+                "out/dog-q10.rpeg",
+                "out/dog-q90.rpeg"
+            ]
+            .map(Path::new)
+        );
+    }
+
+    #[test]
+    fn unreadable_inputs_follow_the_error_policy() {
+        let root = std::env::temp_dir().join(format!("rpeg-batch-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let image = root.join("good.ppm");
+        crate::testkit::synthetic_image(crate::testkit::Pattern::Gradient, 8, 6)
+            .write(Some(image.to_str().unwrap()))
+            .unwrap();
+        let missing = root.join("missing.ppm");
+        let filenames = [missing.to_str().unwrap(), image.to_str().unwrap()];
+        let template = NameTemplate::parse(DEFAULT_NAME_TEMPLATE).unwrap();
+        let out = root.join("out");
+        let batch = |on_error| {
+            let options = EncoderOptions::default();
+            compress_batch(
+                &filenames, &template, &out, None, &options, true, false, on_error,
+            )
+        };
+
+        assert!(batch(OnError::Abort).is_err());
+        assert!(!out.join("good.rpeg").exists());
+        for on_error in [OnError::Skip, OnError::Retry(1)] {
+            let error = batch(on_error).unwrap_err();
+            assert_eq!(error.kind, ErrorKind::UnreadableInput);
+            assert!(error
+                .message
+                .starts_with("Failed to compress 1 of 2 images:\n"));
+            assert!(error.message.contains("missing.ppm: "));
+            assert!(out.join("good.rpeg").exists());
+        }
+
+        // A compressed file created while the missing input is retried is left as it is.
+        std::fs::remove_file(out.join("good.rpeg")).unwrap();
+        let late = out.join("good.rpeg");
+        let creator = std::thread::spawn(move || {
+            std::thread::sleep(RETRY_DELAY / 4);
+            std::fs::write(late, "kept").unwrap();
+        });
+        let error = compress_batch(
+            &filenames,
+            &template,
+            &out,
+            None,
+            &EncoderOptions::default(),
+            false,
+            false,
+            OnError::Retry(1),
+        )
+        .unwrap_err();
+        creator.join().unwrap();
+        assert_eq!(error.kind, ErrorKind::Io);
+        assert!(error
+            .message
+            .contains("Failed to compress 2 of 2 images:\n"));
+        assert!(error.message.contains("good.ppm: "), "{}", error.message);
+        assert_eq!(std::fs::read(out.join("good.rpeg")).unwrap(), b"kept");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
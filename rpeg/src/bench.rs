@@ -0,0 +1,247 @@
+use crate::codec::{compress_image, EncoderOptions};
+use crate::fixed::Arithmetic;
+use crate::ppm::RgbImage;
+use crate::testkit::{synthetic_image, Pattern};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+/// ## Encode scenario of the benchmarks
+///
+/// A synthetic image, drawn by `testkit::synthetic_image` so that every run compresses the
+/// same pixels on every platform, and the settings it is compressed with. `scenarios` lists
+/// those `cargo bench` measures; `run_encode_bench` times one of them, or any other, without
+/// Criterion, for a quick comparison before and after a change.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::bench::{run_encode_bench, BenchOptions};
+/// use rpeg::codec::EncoderOptions;
+/// use rpeg::testkit::Pattern;
+///
+/// let options = BenchOptions {
+///     name: "noise-64x48".to_string(),
+///     pattern: Pattern::Noise { seed: 1 },
+///     width: 64,
+///     height: 48,
+///     encoder: EncoderOptions::default(),
+///     iterations: 3,
+/// };
+/// let report = run_encode_bench(&options);
+/// assert_eq!(report.times.len(), 3);
+/// assert!(report.best() <= report.median());
+/// ```
+pub struct BenchOptions {
+    /// Name of the scenario, as Criterion reports it.
+    pub name: String,
+    /// Image that is compressed.
+    pub pattern: Pattern,
+    /// Width of the image, in pixels.
+    pub width: u32,
+    /// Height of the image, in pixels.
+    pub height: u32,
+    /// Settings the image is compressed with.
+    pub encoder: EncoderOptions,
+    /// Number of timed compressions of `run_encode_bench`, after one untimed one.
+    pub iterations: u32,
+}
+
+impl BenchOptions {
+    /// Returns the scenario of an image compressed with the default settings, timed 10 times.
+    ///
+    /// # Arguments
+    /// * `name`: Name of the scenario
+    /// * `pattern`: Image that is compressed
+    /// * `width`: Width of the image, in pixels
+    /// * `height`: Height of the image, in pixels
+    pub fn new(name: &str, pattern: Pattern, width: u32, height: u32) -> Self {
+        BenchOptions {
+            name: name.to_string(),
+            pattern,
+            width,
+            height,
+            encoder: EncoderOptions::default(),
+            iterations: 10,
+        }
+    }
+
+    /// Returns the scenario with the settings of `encoder`, named after `settings`.
+    ///
+    /// # Arguments
+    /// * `settings`: Name of the settings, appended to the name of the scenario
+    /// * `encoder`: Settings the image is compressed with
+    pub fn with(mut self, settings: &str, encoder: EncoderOptions) -> Self {
+        self.name = format!("{}/{settings}", self.name);
+        self.encoder = encoder;
+        self
+    }
+
+    /// Returns the image of the scenario.
+    pub fn image(&self) -> RgbImage {
+        synthetic_image(self.pattern, self.width, self.height)
+    }
+
+    /// Returns the size of the uncompressed pixels, 3 bytes each, for throughputs.
+    pub fn input_bytes(&self) -> u64 {
+        self.width as u64 * self.height as u64 * 3
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// ## Timings of a scenario measured by `run_encode_bench`
+///
+/// `times` holds the duration of every timed compression, in the order they ran, and
+/// `compressed_len` the size of the compressed image, which is the same every time.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::bench::BenchReport;
+/// use std::time::Duration;
+///
+/// let report = BenchReport {
+///     name: "gradient".to_string(),
+///     times: vec![Duration::from_millis(12), Duration::from_millis(10), Duration::from_millis(11)],
+///     input_bytes: 3_000_000,
+///     compressed_len: 750_000,
+/// };
+/// assert_eq!(report.best(), Duration::from_millis(10));
+/// assert_eq!(report.median(), Duration::from_millis(11));
+/// assert_eq!(report.megabytes_per_second(), 300.0);
+/// ```
+pub struct BenchReport {
+    /// Name of the scenario.
+    pub name: String,
+    /// Duration of every timed compression.
+    pub times: Vec<Duration>,
+    /// Size of the uncompressed pixels, 3 bytes each.
+    pub input_bytes: u64,
+    /// Size of the compressed image.
+    pub compressed_len: usize,
+}
+
+impl BenchReport {
+    /// Returns the shortest of the timed compressions.
+    pub fn best(&self) -> Duration {
+        self.times.iter().copied().min().unwrap_or_default()
+    }
+
+    /// Returns the median of the timed compressions, the lower one of the two middle ones for
+    /// an even count.
+    pub fn median(&self) -> Duration {
+        let mut times = self.times.clone();
+        times.sort();
+        times
+            .get(times.len().saturating_sub(1) / 2)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns the throughput of the shortest compression, in MB of uncompressed pixels per
+    /// second.
+    pub fn megabytes_per_second(&self) -> f64 {
+        self.input_bytes as f64 / 1e6 / self.best().as_secs_f64()
+    }
+}
+
+/// Returns the scenarios `cargo bench` measures: smooth, flat, and noisy content at the
+/// default settings, the arithmetics, the settings that cost more than one pass, tiles, and a
+/// wide image, whose rows are far apart in memory.
+pub fn scenarios() -> Vec<BenchOptions> {
+    let noise = || BenchOptions::new("noise-1024x768", Pattern::Noise { seed: 1 }, 1024, 768);
+    let arithmetic = |arithmetic| EncoderOptions {
+        arithmetic,
+        ..Default::default()
+    };
+    vec![
+        BenchOptions::new("gradient-1024x768", Pattern::Gradient, 1024, 768),
+        BenchOptions::new(
+            "checkerboard-1024x768",
+            Pattern::Checkerboard { cell: 8 },
+            1024,
+            768,
+        ),
+        noise(),
+        noise().with("fixed", arithmetic(Arithmetic::Fixed)),
+        noise().with("single", arithmetic(Arithmetic::Single)),
+        noise().with(
+            "optimize",
+            EncoderOptions {
+                optimize: true,
+                ..arithmetic(Arithmetic::Float)
+            },
+        ),
+        noise().with(
+            "two-pass",
+            EncoderOptions {
+                two_pass: true,
+                ..Default::default()
+            },
+        ),
+        noise().with(
+            "tiles-256",
+            EncoderOptions {
+                tile_size: 256,
+                ..Default::default()
+            },
+        ),
+        BenchOptions::new("noise-4096x512", Pattern::Noise { seed: 2 }, 4096, 512),
+    ]
+}
+
+/// Compresses the image of a scenario once to warm up, then `iterations` more times, and
+/// returns how long each of those took.
+///
+/// # Arguments
+/// * `options`: Scenario to measure
+pub fn run_encode_bench(options: &BenchOptions) -> BenchReport {
+    let image = options.image();
+    let compressed_len = compress_image(&image, &options.encoder).len();
+    let times = (0..options.iterations)
+        .map(|_| {
+            let start = Instant::now();
+            std::hint::black_box(compress_image(
+                std::hint::black_box(&image),
+                &options.encoder,
+            ));
+            start.elapsed()
+        })
+        .collect();
+    BenchReport {
+        name: options.name.clone(),
+        times,
+        input_bytes: options.input_bytes(),
+        compressed_len,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn every_scenario_names_its_settings() {
+        let scenarios = scenarios();
+        let names: HashSet<&str> = scenarios
+            .iter()
+            .map(|scenario| scenario.name.as_str())
+            .collect();
+        assert_eq!(names.len(), scenarios.len());
+        assert!(names.contains("noise-1024x768/single"));
+        let small = BenchOptions {
+            width: 32,
+            height: 24,
+            iterations: 2,
+            ..scenarios[5].clone()
+        };
+        let report = run_encode_bench(&small);
+        assert_eq!(report.name, "noise-1024x768/optimize");
+        assert_eq!(report.times.len(), 2);
+        assert_eq!(report.input_bytes, 32 * 24 * 3);
+        assert_eq!(
+            report.compressed_len,
+            compress_image(&small.image(), &small.encoder).len()
+        );
+    }
+}
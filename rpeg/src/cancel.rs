@@ -0,0 +1,124 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once, OnceLock};
+
+/// Message of the errors returned when a compression is cancelled.
+pub const CANCELLED: &str = "Compression was cancelled";
+
+/// Token cancelled by SIGINT and SIGTERM, once `on_signals` installed the handlers.
+static SIGNAL_TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+/// Installs the handlers of SIGINT and SIGTERM once `SIGNAL_TOKEN` holds the token they cancel.
+static SIGNAL_HANDLERS: Once = Once::new();
+
+#[derive(Clone, Debug, Default)]
+/// ## Flag asking a running compression to stop
+///
+/// Clones share the flag, so an embedder keeps one and hands the other to the
+/// `EncoderOptions`. The encoder checks it between chunks of block rows, and returns an error
+/// instead of the compressed image once it is cancelled.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::cancel::CancellationToken;
+/// use rpeg::encoder::Encoder;
+/// use rpeg::ppm::{Rgb, RgbImage};
+///
+/// let image = RgbImage {
+///     pixels: vec![Rgb { red: 10, green: 20, blue: 30 }; 16],
+///     width: 4,
+///     height: 4,
+///     denominator: 255,
+/// };
+/// let token = CancellationToken::new();
+/// let encoder = Encoder::new().cancellation(token.clone());
+/// assert!(encoder.compress(&image).is_ok());
+/// token.cancel();
+/// assert!(encoder.compress(&image).is_err());
+/// ```
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Returns a token that is not cancelled.
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// Asks every compression holding a clone of the token to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true once `cancel` was called on the token or one of its clones.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Returns a token cancelled when the process receives SIGINT or SIGTERM, installing the
+/// handlers on the first call, once the token exists, so that no signal is lost. A second
+/// signal ends the process at once, with the status of 128 plus the signal number, for work
+/// that does not check the token. Elsewhere than on Unix, the token is never cancelled and the
+/// signals keep their default behavior.
+pub fn on_signals() -> CancellationToken {
+    let token = SIGNAL_TOKEN.get_or_init(CancellationToken::new).clone();
+    SIGNAL_HANDLERS.call_once(|| {
+        #[cfg(unix)]
+        unsafe {
+            let handler = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::signal(libc::SIGINT, handler);
+            libc::signal(libc::SIGTERM, handler);
+        }
+    });
+    token
+}
+
+/// Returns true if SIGINT or SIGTERM was received since `on_signals` installed the handlers.
+pub fn interrupted() -> bool {
+    SIGNAL_TOKEN
+        .get()
+        .is_some_and(CancellationToken::is_cancelled)
+}
+
+/// Cancels the token of `on_signals`, or ends the process if it already was. Only touches an
+/// atomic flag and `_exit`, which are safe to use in a signal handler.
+#[cfg(unix)]
+extern "C" fn handle_signal(signal: libc::c_int) {
+    if let Some(token) = SIGNAL_TOKEN.get() {
+        if token.cancelled.swap(true, Ordering::SeqCst) {
+            unsafe { libc::_exit(128 + signal) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Encoder;
+    use crate::fixed::Arithmetic;
+    use crate::testkit::{synthetic_image, Pattern};
+
+    #[test]
+    fn chunked_encoding_matches_and_stops_when_cancelled() {
+        let image = synthetic_image(Pattern::Noise { seed: 7 }, 38, 150);
+        let token = CancellationToken::new();
+        for encoder in [
+            Encoder::new(),
+            Encoder::new().tile_size(48),
+            Encoder::new().arithmetic(Arithmetic::Fixed),
+        ] {
+            let expected = encoder.compress(&image).unwrap();
+            let chunked = encoder.clone().cancellation(token.clone());
+            assert_eq!(chunked.compress(&image).unwrap(), expected);
+        }
+        token.clone().cancel();
+        let error = Encoder::new()
+            .cancellation(token)
+            .compress(&image)
+            .unwrap_err();
+        assert_eq!(error, CANCELLED);
+        assert!(!interrupted());
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::OnceLock;
+
+/// Average chroma values a 4-bit index stands for, denser around zero where most blocks sit.
+const CHROMA_TABLE: [f32; 16] = [
+    -0.35, -0.20, -0.15, -0.10, -0.077, -0.055, -0.033, -0.011, 0.011, 0.033, 0.055, 0.077, 0.10,
+    0.15, 0.20, 0.35,
+];
+
+/// Average chroma values a 3-bit index stands for.
+const FAST_CHROMA_TABLE: [f32; 8] = [-0.30, -0.14, -0.06, -0.015, 0.015, 0.06, 0.14, 0.30];
+
+/// Average chroma values a 6-bit index stands for: 0.5 * ((k + 0.5) / 32)^1.7 on each side of
+/// zero, rounded to four decimals.
+const FINE_CHROMA_TABLE: [f32; 64] = [
+    -0.4868, -0.4608, -0.4354, -0.4106, -0.3864, -0.3629, -0.3399, -0.3175, -0.2958, -0.2747,
+    -0.2543, -0.2345, -0.2154, -0.197, -0.1792, -0.1622, -0.1458, -0.1302, -0.1153, -0.1011,
+    -0.0878, -0.0752, -0.0634, -0.0525, -0.0424, -0.0333, -0.0251, -0.0178, -0.0116, -0.0066,
+    -0.0028, -0.0004, 0.0004, 0.0028, 0.0066, 0.0116, 0.0178, 0.0251, 0.0333, 0.0424, 0.0525,
+    0.0634, 0.0752, 0.0878, 0.1011, 0.1153, 0.1302, 0.1458, 0.1622, 0.1792, 0.197, 0.2154, 0.2345,
+    0.2543, 0.2747, 0.2958, 0.3175, 0.3399, 0.3629, 0.3864, 0.4106, 0.4354, 0.4608, 0.4868,
+];
+
+/// ## Quantizes the average chroma of a block into a table index
+///
+/// A quantizer lists the chroma values its indices stand for, in increasing order, and maps
+/// every chroma to the index of the closest one. Implementors only need to provide `entries`;
+/// the search can be overridden when the table allows a faster one.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::chroma::{ChromaQuantizer, ChromaTable};
+///
+/// let table = ChromaTable::Fast;
+/// assert_eq!(table.entries().len(), 8);
+/// assert_eq!(table.chroma_of_index(table.index_of_chroma(0.13)), 0.14);
+/// ```
+pub trait ChromaQuantizer {
+    /// Returns the chroma values the indices stand for, in increasing order.
+    fn entries(&self) -> &[f32];
+
+    /// Returns the chroma value stored at `index`.
+    ///
+    /// # Arguments
+    /// * `index`: Index below the number of entries; larger indices panic
+    fn chroma_of_index(&self, index: usize) -> f32 {
+        self.entries()[index]
+    }
+
+    /// Returns the index of the entry closest to `chroma`. Ties go to the lower index, and
+    /// values one or more away from every entry map to index 0.
+    ///
+    /// # Arguments
+    /// * `chroma`: Average chroma of a block
+    fn index_of_chroma(&self, chroma: f32) -> usize {
+        closest_entry(self.entries(), 0, chroma)
+    }
+}
+
+/// Returns the index of the entry of `entries` closest to `chroma` as `index_of_chroma` does,
+/// counting from `first`, the index of the first of `entries` in its table.
+fn closest_entry(entries: &[f32], first: usize, chroma: f32) -> usize {
+    entries
+        .iter()
+        .map(|entry| (entry - chroma).abs())
+        .enumerate()
+        .fold((0, 1_f32), |(best, best_distance), (index, distance)| {
+            if distance < best_distance {
+                (first + index, distance)
+            } else {
+                (best, best_distance)
+            }
+        })
+        .0
+}
+
+/// Number of buckets `ChromaLut` splits the chroma values from -1 to 1 into.
+const LUT_BUCKETS: usize = 4096;
+
+/// Margin added on each side of a bucket, far wider than the rounding of a chroma in `f32`.
+const LUT_MARGIN: f64 = 1e-4;
+
+/// Entries every chroma of a range of values may be closest to, so that `index_of_chroma`
+/// compares a chroma with one or two entries instead of the whole table.
+struct ChromaLut {
+    /// First and last entry a chroma of every bucket may be closest to.
+    candidates: Vec<(u8, u8)>,
+}
+
+impl ChromaLut {
+    /// Returns the lookup table of the sorted `entries`: an entry is a candidate of a bucket
+    /// when the values closest to it, up to the midpoints with its neighbours, come within
+    /// `LUT_MARGIN` of the bucket.
+    fn new(entries: &[f32]) -> ChromaLut {
+        let midpoints: Vec<f64> = entries
+            .windows(2)
+            .map(|pair| (pair[0] as f64 + pair[1] as f64) / 2.0)
+            .collect();
+        let closest = |chroma: f64| midpoints.partition_point(|midpoint| *midpoint < chroma) as u8;
+        let width = 2.0 / LUT_BUCKETS as f64;
+        let candidates = (0..LUT_BUCKETS)
+            .map(|bucket| {
+                let start = bucket as f64 * width - 1.0;
+                (
+                    closest(start - LUT_MARGIN),
+                    closest(start + width + LUT_MARGIN),
+                )
+            })
+            .collect();
+        ChromaLut { candidates }
+    }
+
+    /// Returns the index of the entry of `entries` closest to `chroma`, exactly as the search
+    /// of every entry does.
+    fn index_of_chroma(&self, entries: &[f32], chroma: f32) -> usize {
+        let position = (chroma + 1.0) * (LUT_BUCKETS / 2) as f32;
+        // Values outside of the table, and NaN, go through every entry.
+        if !(0.0..LUT_BUCKETS as f32).contains(&position) {
+            return closest_entry(entries, 0, chroma);
+        }
+        let (first, last) = self.candidates[position as usize];
+        let (first, last) = (first as usize, last as usize);
+        closest_entry(&entries[first..=last], first, chroma)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// ## The chroma tables the decoder understands
+///
+/// `Standard` is the original 16-entry table, `Fast` an 8-entry table whose 3-bit indices
+/// leave room for a finer luma average, and `Fine` a 64-entry table for saturated images. The
+/// layout of the code words says which one the chroma indices refer to.
+pub enum ChromaTable {
+    #[default]
+    Standard,
+    Fast,
+    Fine,
+}
+
+impl ChromaTable {
+    /// Returns the lookup table of the entries, built on its first use.
+    fn lut(&self) -> &'static ChromaLut {
+        static LUTS: [OnceLock<ChromaLut>; 3] = [const { OnceLock::new() }; 3];
+        LUTS[*self as usize].get_or_init(|| ChromaLut::new(self.entries()))
+    }
+}
+
+impl ChromaQuantizer for ChromaTable {
+    fn entries(&self) -> &[f32] {
+        match self {
+            ChromaTable::Standard => &CHROMA_TABLE,
+            ChromaTable::Fast => &FAST_CHROMA_TABLE,
+            ChromaTable::Fine => &FINE_CHROMA_TABLE,
+        }
+    }
+
+    /// Looks the chroma up in a table of the entries it may be closest to, giving the index
+    /// the search of every entry gives.
+    fn index_of_chroma(&self, chroma: f32) -> usize {
+        self.lut().index_of_chroma(self.entries(), chroma)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_entry_maps_back_to_its_index() {
+        for table in [ChromaTable::Standard, ChromaTable::Fast, ChromaTable::Fine] {
+            let entries = table.entries();
+            assert!(entries.windows(2).all(|pair| pair[0] < pair[1]));
+            for index in 0..entries.len() {
+                assert_eq!(table.index_of_chroma(table.chroma_of_index(index)), index);
+                assert_eq!(entries[index], -entries[entries.len() - 1 - index]);
+            }
+        }
+    }
+
+    #[test]
+    fn chroma_maps_to_the_closest_entry() {
+        let table = ChromaTable::Standard;
+        assert_eq!(table.index_of_chroma(0.0), 7);
+        assert_eq!(table.index_of_chroma(0.3), 15);
+        assert_eq!(table.index_of_chroma(-0.09), 3);
+        assert_eq!(ChromaTable::Fast.index_of_chroma(0.0), 3);
+        assert_eq!(ChromaTable::Fine.index_of_chroma(0.005), 34);
+    }
+
+    #[test]
+    fn the_lookup_table_agrees_with_the_search_of_every_entry() {
+        for table in [ChromaTable::Standard, ChromaTable::Fast, ChromaTable::Fine] {
+            let entries = table.entries();
+            let steps = (0..=400_000).map(|step| step as f32 / 100_000.0 - 2.0);
+            // The values around every midpoint and bucket edge, a few steps of f32 apart.
+            let edges = entries
+                .windows(2)
+                .map(|pair| (pair[0] + pair[1]) / 2.0)
+                .chain(
+                    (0..=LUT_BUCKETS).map(|bucket| bucket as f32 * 2.0 / LUT_BUCKETS as f32 - 1.0),
+                )
+                .flat_map(|edge| {
+                    (-8..=8).map(move |ulps| f32::from_bits((edge.to_bits() as i32 + ulps) as u32))
+                });
+            let special = [
+                f32::NAN,
+                f32::INFINITY,
+                f32::NEG_INFINITY,
+                -0.0,
+                1.0,
+                -1.0,
+                1.7,
+            ];
+            for chroma in steps.chain(edges).chain(special) {
+                assert_eq!(
+                    table.index_of_chroma(chroma),
+                    closest_entry(entries, 0, chroma),
+                    "{table:?} {chroma}"
+                );
+            }
+        }
+    }
+}
@@ -1,71 +1,3807 @@
+pub mod cache;
+pub mod scratch;
+pub mod stats;
+
+use crate::adjust::{adjust, Adjustment};
+use crate::cancel::{CancellationToken, CANCELLED};
+use crate::color_tag::ColorTag;
+use crate::content::{classify, edge_density, TileMode, TEXT_EDGE_DENSITY};
 use crate::conversions;
 use crate::conversions::{
-    component_video_back_to_rbg_floats, fix_pixel_poss, from_blocks_to_component_format,
-    from_dct_to_component_video, rgb_floats_to_rgb, unpack_values,
+    component_video_to_pixels, fix_pixel_poss, from_blocks_to_component_format,
+    from_dct_to_component_video, masked_ranges, unpack_values,
+};
+use crate::dct_coeff::{
+    clipped_transform, quantize_block, transform_block, transform_chroma_error, MAX_LUMA_RANGE,
+};
+use crate::deblock::deblock;
+use crate::encoder::{encode_words_on_gpu, Backend, PadPolicy, Preset, RatioPolicy};
+use crate::error::{CliError, ErrorKind, RpegError};
+use crate::fixed::{decode_words_fixed, encode_words_fixed, Arithmetic};
+use crate::format::{
+    chunked_len, read_version, Compat, Header, WordOrder, CHUNKED_VERSION,
+    DEFAULT_LUMA_RANGE_MILLIS, MAX_METADATA_LEN,
 };
+use crate::hdr::{compress_hdr_image, HdrImage, InputImage, ToneMap};
+use crate::io::{notice, read_input, MappedFile, Output};
+use crate::layout::{
+    WordLayout, FINE_CHROMA_LAYOUT, MAX_CHROMA_WEIGHT, NARROW_LAYOUT, NEUTRAL_CHROMA_WEIGHT,
+};
+use crate::metrics::{input_size, psnr, write_report, FileMetrics};
+use crate::palette::{decode_palette, encode_palette};
+use crate::pipeline::{chunk_starts, ordered, CHUNK_ROWS};
+use crate::pixel::{Rgb16, Srgb};
+use crate::png_image::write_png;
+use crate::porcelain::FileResult;
+use crate::ppm::{Rgb, RgbImage};
+use crate::preprocess::Preprocess;
+use crate::progressive::{from_progressive, to_progressive};
+use crate::raw::{decode_raw, encode_raw};
+use crate::refine::{Refinement, MAX_ERROR};
+use crate::roi::{block_levels, luma_ranges, read_level_map, write_level_map, Region};
+use crate::signature::SIGNATURE_LEN;
+use crate::single::{decode_words_single, encode_words_single};
+use crate::structs::{Block, DCTCoefficient, QuantizedBlock, TransformedBlock};
+use crate::target::{compress_to_target, QualityTarget};
+use crate::thumbnail::{downscale, THUMBNAIL_WIDTH};
+use crate::tiles::{tile_block_indices, tile_rects, Rect};
+use crate::tint::{tint_component_video, tint_pixels, Tint};
+use crate::two_pass;
 use array2::array2::Array2;
-use conversions::blocks_to_dct;
+use cache::CoefficientCache;
+use conversions::chroma_error_sum;
 use conversions::component_video_to_blocks;
-use conversions::pack_values_into_word;
-use conversions::rbg_floats_to_component_video;
-use conversions::rgb_to_floats;
-use csc411_image::{Read, Rgb, RgbImage, Write};
-use csc411_rpegio::output_rpeg_data;
-use csc411_rpegio::read_in_rpeg_data;
+use conversions::pixels_to_component_video;
+use rayon::prelude::*;
+use scratch::ScratchBuffers;
+use stats::Timings;
+use std::borrow::Cow;
+use std::io::Write;
+use std::ops::{Deref, Range};
+use std::time::Instant;
+
+#[derive(Clone, Debug, Default)]
+/// ## Options controlling how an image is compressed
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::codec::EncoderOptions;
+/// use rpeg::encoder::{Backend, PadPolicy, RatioPolicy};
+/// use rpeg::fixed::Arithmetic;
+/// use rpeg::layout::WIDE_LAYOUT;
+/// use rpeg::preprocess::Preprocess;
+/// use rpeg::roi::Region;
+///
+/// let options = EncoderOptions {
+///     progressive: true,
+///     regions: vec![Region::parse("0,0,32,32:90").unwrap()],
+///     tile_size: 512,
+///     optimize: true,
+///     luma_range: Some(0.5),
+///     layout: WIDE_LAYOUT,
+///     arithmetic: Arithmetic::Float,
+///     backend: Backend::Cpu,
+///     deterministic: false,
+///     metadata: vec![("comment".to_string(), "test chart".to_string())],
+///     embed_thumbnail: true,
+///     two_pass: false,
+///     perceptual: false,
+///     palette: false,
+///     detect_content: false,
+///     pad: PadPolicy::Replicate,
+///     preprocess: Preprocess::default(),
+///     srgb: true,
+///     keep_orientation: false,
+///     compat: None,
+///     cancel: None,
+///     min_ratio: Some(1.5),
+///     ratio_policy: RatioPolicy::Store,
+///     raw_below_psnr: Some(20.0),
+///     chunked: false,
+///     chroma_priority: false,
+///     max_error: None,
+///     target: None,
+/// };
+/// ```
+pub struct EncoderOptions {
+    /// Store the DC terms of every block ahead of the b/c/d refinements, so that a prefix of
+    /// the compressed image can be decoded into a preview.
+    pub progressive: bool,
+    /// Regions of interest quantized with their own quality instead of the background one.
+    pub regions: Vec<Region>,
+    /// Side in pixels of the independently compressed tiles, which must be even, or 0 to
+    /// compress the image as a single tile.
+    pub tile_size: u32,
+    /// Search the neighbouring quantized values of a, b, c, and d of every block for the ones
+    /// that decode closest to the original pixels, instead of rounding each of them on its own.
+    pub optimize: bool,
+    /// Range b, c, and d of the blocks outside of every region are clamped to, between 0.001
+    /// and 0.5, or None for the default of 0.3. It is stored in the header, to the nearest
+    /// thousandth.
+    pub luma_range: Option<f64>,
+    /// Layout of the code word every block is packed into.
+    pub layout: WordLayout,
+    /// Arithmetic of the color transform and the 2x2 transform. `optimize` needs `Float`, and
+    /// `perceptual` a floating point one.
+    pub arithmetic: Arithmetic,
+    /// Hardware the blocks are encoded on.
+    pub backend: Backend,
+    /// Encode the tiles and chunks one after the other on the calling thread. The CPU output is
+    /// byte-identical with or without it, whatever the number of threads, since the pipeline
+    /// reassembles the pieces in order; this only rules out the `Gpu` backend, whose `f32`
+    /// results depend on the driver, and keeps the encoder off the rayon pool.
+    pub deterministic: bool,
+    /// Key/value pairs stored in the header, such as the source filename or a comment.
+    pub metadata: Vec<(String, String)>,
+    /// Store a copy of the image shrunk to `THUMBNAIL_WIDTH` pixels wide in the header, so that
+    /// previews can be shown without decoding the payload.
+    pub embed_thumbnail: bool,
+    /// Gather the b, c, and d coefficients of the background blocks in a first pass, and
+    /// encode with the luma range that quantizes them with the smallest error. Cannot be
+    /// combined with `luma_range`.
+    pub two_pass: bool,
+    /// Weight the range of every block by its brightness, giving finer steps to mid-gray
+    /// blocks and coarser ones to very dark and very bright blocks, where errors show the
+    /// least. Needs `Float` arithmetic and the `Cpu` backend.
+    pub perceptual: bool,
+    /// Store every tile as a palette followed by run-length coded indices, without loss, when
+    /// every tile holds at most `MAX_PALETTE_COLORS` colors and the result is no larger than
+    /// the code words. Screenshots and diagrams then avoid the ringing of the 2x2 transform.
+    /// Other images fall back to code words.
+    pub palette: bool,
+    /// Classify every tile as a photo, a graphic, or text, and code it in the matching mode:
+    /// code words, a palette, or code words whose background blocks never clip an edge. The
+    /// choice is recorded per tile in the header. `palette` takes precedence when every tile
+    /// can be palette coded.
+    pub detect_content: bool,
+    /// How an odd width or height is fitted to the 2x2 blocks.
+    pub pad: PadPolicy,
+    /// Resizing, rotation, and color adjustments applied to the image before it is compressed.
+    pub preprocess: Preprocess,
+    /// Treat the densities of the image as sRGB encoded, and convert them to linear light
+    /// before the color transform, so that the pixels of every block are averaged in linear
+    /// light. The decoder converts them back. Cannot be combined with `palette` or
+    /// `detect_content`, whose palettes hold the original densities.
+    pub srgb: bool,
+    /// Keep the EXIF orientation of a JPEG input in the metadata, under `ORIENTATION_KEY`,
+    /// instead of turning the pixels upright before the image is compressed.
+    pub keep_orientation: bool,
+    /// Write an earlier format instead of the rpeg container, with output bit-identical to its
+    /// reference implementation. It only holds the default settings, and its code words are
+    /// computed with floating point arithmetic whatever `arithmetic` says.
+    pub compat: Option<Compat>,
+    /// Token checked between chunks of block rows, which makes the compression fail with
+    /// `CANCELLED` once it is cancelled. The GPU backend only checks it between tiles.
+    pub cancel: Option<CancellationToken>,
+    /// Smallest compression ratio, as returned by `compression_ratio`, below which
+    /// `ratio_policy` applies, or None to accept any ratio.
+    pub min_ratio: Option<f64>,
+    /// What to do with an image that compresses less than `min_ratio`.
+    pub ratio_policy: RatioPolicy,
+    /// Store the tiles whose code words decode below this PSNR, in decibels, as raw pixels
+    /// instead, recording the choice per tile in the header. Noise-like content, which the
+    /// quantization cannot follow, then keeps every detail at three times the size.
+    pub raw_below_psnr: Option<f64>,
+    /// Store the image in the chunked container of `format::CHUNKED_VERSION`, whose decoders
+    /// skip the ancillary chunks they do not know, instead of the flag-based header.
+    pub chunked: bool,
+    /// Give the hue priority over the luma detail, for artwork where it matters more: the code
+    /// words take the fields of `FINE_CHROMA_LAYOUT` whatever `layout` says, with the largest
+    /// chroma weight under which the chroma of no block is clipped. Needs the `Cpu` backend.
+    pub chroma_priority: bool,
+    /// Store the corrections that bring every channel of the decoded image within this many
+    /// 8-bit steps of the original, 0 for a lossless image, at most `refine::MAX_ERROR`. The
+    /// bound holds for a decode without `deblock`, `dither`, a tint, or adjustments, with the
+    /// default arithmetic. Cannot be combined with HDR images.
+    pub max_error: Option<u8>,
+    /// Quality the round trip must reach, which the encoder meets by trying settings in order
+    /// of size, see `target::QualityTarget`, or None to compress with these settings alone.
+    /// Cannot be combined with `max_error`, which it picks itself, or with HDR images.
+    pub target: Option<QualityTarget>,
+}
+
+impl EncoderOptions {
+    /// Returns the default settings with the layout, rounding optimization, and tile size of
+    /// `preset`.
+    ///
+    /// # Arguments
+    /// * `preset`: Trade-off between encoding time and quality
+    pub fn preset(preset: Preset) -> EncoderOptions {
+        let mut options = EncoderOptions::default();
+        preset.apply(&mut options);
+        options
+    }
+
+    /// Returns true if some or all of the tiles may be stored raw: those below `raw_below_psnr`,
+    /// or every tile of an image below the minimum compression ratio.
+    pub fn stores_raw(&self) -> bool {
+        self.raw_below_psnr.is_some()
+            || (self.min_ratio.is_some() && self.ratio_policy == RatioPolicy::Store)
+    }
+
+    /// Returns true if the compression was given a cancellation token that was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Returns an error if `compat` is set along with a setting its format cannot hold or
+    /// that changes the code words of its reference implementation: anything but the pad
+    /// policy `Trim`, the default luma range, and the 32-bit layout, other than the
+    /// preprocessing, determinism, and orientation handling of the input.
+    pub fn check_compat(&self) -> Result<(), String> {
+        let Some(Compat::Csc411) = self.compat else {
+            return Ok(());
+        };
+        let default_range = self
+            .luma_range
+            .is_none_or(|range| (range * 1000.0).round() as u16 == DEFAULT_LUMA_RANGE_MILLIS);
+        if self.progressive
+            || !self.regions.is_empty()
+            || self.tile_size != 0
+            || self.optimize
+            || !default_range
+            || self.layout != NARROW_LAYOUT
+            || self.backend != Backend::Cpu
+            || !self.metadata.is_empty()
+            || self.embed_thumbnail
+            || self.two_pass
+            || self.perceptual
+            || self.palette
+            || self.detect_content
+            || self.pad != PadPolicy::Trim
+            || self.srgb
+            || self.stores_raw()
+            || self.chunked
+            || self.chroma_priority
+            || self.max_error.is_some()
+            || self.target.is_some()
+        {
+            return Err(
+                "--compat csc411 only holds the default settings, without tiles, regions, metadata, or a luma range"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Luma range of the `--high-contrast` preset. No coefficient is clipped with it, which keeps
+/// the edges of text and line art, at the cost of coarser steps in smooth areas.
+pub const HIGH_CONTRAST_LUMA_RANGE: f64 = MAX_LUMA_RANGE;
+
+/// Share of clipped b, c, and d coefficients above which `compress` warns that the quality is
+/// too low for the image.
+pub const CLIP_WARNING_SHARE: f64 = 0.01;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+/// ## Summary of how much the quantization of an image clipped and moved it
+///
+/// `chroma_error` is the mean absolute difference between the average Pb or Pr of a block and
+/// the chroma its index stands for, over the blocks stored as code words, on the scale of Pb
+/// and Pr, from -0.5 to 0.5. It is 0 when no block is stored as code words.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::codec::EncodeReport;
+///
+/// let report = EncodeReport { clipped: 3, coefficients: 300, chroma_error: 0.002 };
+/// assert_eq!(report.clipped_share(), 0.01);
+/// ```
+pub struct EncodeReport {
+    /// Number of b, c, and d coefficients that fell outside of their range and were clipped.
+    pub clipped: usize,
+    /// Number of b, c, and d coefficients in the image.
+    pub coefficients: usize,
+    /// Mean absolute error of the quantized Pb and Pr of the blocks.
+    pub chroma_error: f64,
+}
+
+impl EncodeReport {
+    /// Returns the share of the b, c, and d coefficients of the image that were clipped.
+    pub fn clipped_share(&self) -> f64 {
+        self.clipped as f64 / self.coefficients.max(1) as f64
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+/// Quantization statistics of some blocks, which add up across chunks and tiles.
+pub(crate) struct QuantizationStats {
+    /// Number of b, c, and d coefficients clipped.
+    pub clipped: usize,
+    /// Sum over the blocks of the absolute errors of their quantized Pb and Pr.
+    pub chroma_error: f64,
+    /// Number of blocks quantized.
+    pub blocks: usize,
+}
+
+impl QuantizationStats {
+    /// Returns the report of an image of `coefficients` b, c, and d coefficients.
+    pub(crate) fn report(self, coefficients: usize) -> EncodeReport {
+        EncodeReport {
+            clipped: self.clipped,
+            coefficients,
+            // Pb and Pr each count once per block.
+            chroma_error: self.chroma_error / (2 * self.blocks).max(1) as f64,
+        }
+    }
+}
+
+impl std::ops::Add for QuantizationStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        QuantizationStats {
+            clipped: self.clipped + other.clipped,
+            chroma_error: self.chroma_error + other.chroma_error,
+            blocks: self.blocks + other.blocks,
+        }
+    }
+}
+
+impl std::iter::Sum for QuantizationStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(QuantizationStats::default(), |total, stats| total + stats)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// ## Options controlling how a compressed image is decompressed
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::adjust::Adjustment;
+/// use rpeg::codec::DecodeOptions;
+/// use rpeg::fixed::Arithmetic;
+/// use rpeg::hdr::ToneMap;
+/// use rpeg::tiles::Rect;
+/// use rpeg::tint::Tint;
+///
+/// let options = DecodeOptions {
+///     preview: true,
+///     region: Some(Rect { x: 0, y: 0, width: 64, height: 64 }),
+///     deblock: true,
+///     dither: false,
+///     arithmetic: Arithmetic::Fixed,
+///     max_memory: Some(256 << 20),
+///     adjustments: vec![Adjustment::Contrast(1.2), Adjustment::Saturation(0.8)],
+///     tone_map: ToneMap::Reinhard,
+///     tint: Some(Tint::Monochrome),
+/// };
+/// ```
+pub struct DecodeOptions {
+    /// Accept a truncated compressed image and decode whatever part of it is present.
+    pub preview: bool,
+    /// Decode only this rectangle of the image.
+    pub region: Option<Rect>,
+    /// Smooth the boundaries between 2x2 blocks after decoding.
+    pub deblock: bool,
+    /// Round the decoded pixels with ordered dithering instead of truncating them, hiding the
+    /// banding left by the quantization of the code words.
+    pub dither: bool,
+    /// Arithmetic of the inverse 2x2 transform and the inverse color transform. Linear light
+    /// and HDR images need `Float`.
+    pub arithmetic: Arithmetic,
+    /// Fail before allocating anything for the pixels when decoding would need more than this
+    /// many bytes, as estimated by `decode_memory` from the header, or None for
+    /// `DEFAULT_MAX_MEMORY`.
+    pub max_memory: Option<u64>,
+    /// Color adjustments applied to the decoded pixels, in order, before they are returned.
+    pub adjustments: Vec<Adjustment>,
+    /// How the light of an HDR image is fitted to 8 bits, before the adjustments. Images that
+    /// are not HDR ignore it.
+    pub tone_map: ToneMap,
+    /// Render the luma alone, or through a two-color gradient, where the decoded luma and
+    /// chroma are converted to pixels. HDR images are tinted after their tone mapping.
+    pub tint: Option<Tint>,
+}
 
 /// Takes a PPM image `filename` as input or reads from standard in,
 /// and reduces the size of the image by three times compared to the original image.
 /// This is achieve through a lossy image compression process, which trades pixel information for
-/// portability while keeping some pixel quality.
+/// portability while keeping some pixel quality. The compressed image is written to `output`.
+/// A PFM image is compressed as an HDR image, see `rpeg::hdr`, and a JPEG image, with the
+/// `jpeg` feature, is turned upright by its EXIF orientation unless `keep_orientation` is set.
+/// Failures are returned with the category the CLI exits with.
 ///
 /// # Arguments
-/// * `filename`: Location of the PPM within your disk, or None to read from standard in
-pub fn compress(filename: Option<&str>) {
-    let original_image = RgbImage::read(filename.as_deref()).unwrap();
-    let image_denominator = original_image.denominator;
-    let image: Array2<Rgb> = Array2::from_even_dimension(
-        original_image.width as usize,
-        original_image.height as usize,
-        original_image.pixels,
+/// * `filename`: Location of the PPM or PFM within your disk, or None to read from standard in
+/// * `output`: Destination of the compressed image
+/// * `options`: Settings used to compress the image
+/// * `profile`: Print the time spent in every stage and the throughput to standard error
+/// * `report_file`: Location of a JSON or CSV report of the size, quality, and time of the
+///   compression, or None
+pub fn compress(
+    filename: Option<&str>,
+    output: &Output,
+    options: &EncoderOptions,
+    profile: bool,
+    report_file: Option<&str>,
+) -> Result<FileResult, CliError> {
+    let timings = Timings::new();
+    let start = Instant::now();
+    options
+        .check_compat()
+        .map_err(|message| CliError::new(ErrorKind::BadArguments, message))?;
+    let cancelled = || CliError::new(ErrorKind::Cancelled, CANCELLED);
+    if options.is_cancelled() {
+        return Err(cancelled());
+    }
+    let input = timings
+        .time("read", || InputImage::read(filename))
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let (original_image, options, (compressed_image, report)) = match input {
+        InputImage::Standard(image) => {
+            let compressed = compress_image_with_timings(&image, options, &timings)?;
+            (image, Cow::Borrowed(options), compressed)
+        }
+        #[cfg(feature = "jpeg")]
+        InputImage::Jpeg {
+            image,
+            orientation,
+            color_tag,
+        } => {
+            let (image, options) = crate::jpeg::prepare(image, orientation, color_tag, options);
+            let compressed = compress_image_with_timings(&image, &options, &timings)?;
+            (image, Cow::Owned(options), compressed)
+        }
+        InputImage::Hdr(image) => {
+            let compressed = compress_hdr_image(&image, options, &timings)
+                .map_err(|message| CliError::new(ErrorKind::BadArguments, message))?;
+            (
+                image.to_coded(image.exponent()),
+                Cow::Borrowed(options),
+                compressed,
+            )
+        }
+    };
+    if options.is_cancelled() {
+        return Err(cancelled());
+    }
+    if let Some(min_ratio) = options.min_ratio {
+        let ratio = compression_ratio(&original_image, &compressed_image);
+        let below = format!("The compression ratio of {ratio:.2} is below --min-ratio {min_ratio}");
+        match options.ratio_policy {
+            _ if ratio >= min_ratio => {}
+            RatioPolicy::Warn => notice(below),
+            RatioPolicy::Store => notice(format!(
+                "The compression ratio is below --min-ratio {min_ratio}; stored the pixels raw"
+            )),
+            RatioPolicy::Fail => return Err(CliError::new(ErrorKind::PoorRatio, below)),
+        }
+    }
+    if report.clipped_share() > CLIP_WARNING_SHARE {
+        notice(format!(
+            "Clipped {} of {} luma coefficients; a lower --quality widens their range, and \
+             --high-contrast keeps them all",
+            report.clipped, report.coefficients
+        ));
+    }
+    let elapsed = start.elapsed();
+    timings
+        .time("write", || output.write(&compressed_image))
+        .map_err(|message| {
+            if options.is_cancelled() {
+                cancelled()
+            } else {
+                CliError::new(ErrorKind::Io, message)
+            }
+        })?;
+    if profile {
+        timings.finish(start.elapsed(), original_image.pixels.len() * 3);
+        notice(&timings);
+    }
+    let input_bytes = input_size(filename, &original_image);
+    if let Some(report_file) = report_file {
+        let file = filename.unwrap_or("-");
+        let metrics = FileMetrics {
+            seconds: elapsed.as_secs_f64(),
+            ..FileMetrics::of_compressed(
+                file,
+                &original_image,
+                input_bytes,
+                &compressed_image,
+                &options,
+            )
+        };
+        write_report(report_file, &[metrics])
+            .map_err(|message| CliError::new(ErrorKind::Io, message))?;
+    }
+    Ok(FileResult {
+        input: filename.unwrap_or("-").to_string(),
+        output: output.filename.as_deref().unwrap_or("-").to_string(),
+        input_bytes,
+        output_bytes: compressed_image.len(),
+        seconds: start.elapsed().as_secs_f64(),
+    })
+}
+
+/// Takes an Rgb image and returns its compressed representation: a header followed by one
+/// 32-bit code word per 2x2 block of pixels, stored in the order selected by `options`. When
+/// `options.tile_size` is set, every tile is compressed on its own, in parallel, and the
+/// payload starts with a directory giving the length of every tile.
+///
+/// # Arguments
+/// * `original_image`: Image to compress
+/// * `options`: Settings used to compress the image
+///
+/// # Panics
+/// Panics if the settings of `options` cannot be combined, which `compress_image_with_timings`
+/// and `Encoder` return as an error instead.
+pub fn compress_image(original_image: &RgbImage, options: &EncoderOptions) -> Vec<u8> {
+    compress_image_with_report(original_image, options).0
+}
+
+/// Compresses an Rgb image exactly like `compress_image`, and also reports how many of its
+/// b, c, and d coefficients were clipped by the quantization, and how far it moved their
+/// chroma.
+///
+/// # Arguments
+/// * `original_image`: Image to compress
+/// * `options`: Settings used to compress the image
+///
+/// # Panics
+/// Panics like `compress_image`.
+pub fn compress_image_with_report(
+    original_image: &RgbImage,
+    options: &EncoderOptions,
+) -> (Vec<u8>, EncodeReport) {
+    compress_image_with_timings(original_image, options, &Timings::new())
+        .unwrap_or_else(|error| panic!("{error}"))
+}
+
+/// Compresses an Rgb image exactly like `compress_image_with_report`, and also adds the time
+/// spent in every stage of the pipeline to `timings`. Returns an error with
+/// `ErrorKind::BadArguments` if the settings of `options` cannot be combined.
+///
+/// # Arguments
+/// * `original_image`: Image to compress
+/// * `options`: Settings used to compress the image
+/// * `timings`: Timings the stages are added to
+pub fn compress_image_with_timings(
+    original_image: &RgbImage,
+    options: &EncoderOptions,
+    timings: &Timings,
+) -> Result<(Vec<u8>, EncodeReport), RpegError> {
+    check_encodable(options)?;
+    let cache =
+        CoefficientCache::with_timings(original_image, options, options.target.is_some(), timings);
+    compress_prepared(&cache, options, timings)
+}
+
+/// Compresses an Rgb image exactly like `compress_image_with_report`, reusing the blocks and
+/// transforms of `cache`, so that compressions of the same image that only differ in their
+/// quantization skip the conversion and the transform.
+///
+/// # Arguments
+/// * `cache`: Image to compress, along with its transforms, which must fit `options`
+/// * `options`: Settings used to compress the image
+///
+/// # Panics
+/// Panics if `cache` does not fit `options`, or like `compress_image`; `Encoder::compress_cached`
+/// returns these as an error instead.
+pub fn compress_cached(
+    cache: &CoefficientCache,
+    options: &EncoderOptions,
+) -> (Vec<u8>, EncodeReport) {
+    assert!(
+        cache.fits(options),
+        "The cache was built with other pre-processing, sRGB, or padding settings"
     );
-    let rgb_floats_image = rgb_to_floats(image, image_denominator);
-    let component_vide_form = rbg_floats_to_component_video(rgb_floats_image);
-    let blocks_of_pixels = component_video_to_blocks(component_vide_form);
-    let dct_coefficient = blocks_to_dct(blocks_of_pixels);
-    let compressed_imag = pack_values_into_word(dct_coefficient);
-    output_rpeg_data(
-        &compressed_imag.data,
-        compressed_imag.get_width() as u32,
-        compressed_imag.get_height() as u32,
+    compress_prepared(cache, options, &Timings::new()).unwrap_or_else(|error| panic!("{error}"))
+}
+
+/// Compresses an Rgb image exactly like `compress_image_with_report` into `output`, replacing
+/// what it held, and returns the report. When `ScratchBuffers::covers` accepts `options`, the
+/// temporaries are those of `scratch`, and a warm `scratch` and `output` allocate nothing.
+///
+/// # Arguments
+/// * `original_image`: Image to compress
+/// * `options`: Settings used to compress the image
+/// * `scratch`: Buffers of the temporaries, reused across calls
+/// * `output`: Buffer receiving the compressed image
+///
+/// # Panics
+/// Panics like `compress_image`; `Encoder::compress_into` returns the error instead.
+pub fn compress_into(
+    original_image: &RgbImage,
+    options: &EncoderOptions,
+    scratch: &mut ScratchBuffers,
+    output: &mut Vec<u8>,
+) -> EncodeReport {
+    if ScratchBuffers::covers(options) {
+        return scratch
+            .compress(original_image, options, output)
+            .unwrap_or_else(|error| panic!("{error}"));
+    }
+    let (compressed, report) = compress_image_with_report(original_image, options);
+    output.clear();
+    output.extend_from_slice(&compressed);
+    report
+}
+
+/// Compresses the image of `cache` with the settings of `options`, which it fits, adding the
+/// time spent in every stage to `timings`. Returns an error like `check_encodable` if the
+/// settings cannot be combined.
+pub(crate) fn compress_prepared(
+    cache: &CoefficientCache,
+    options: &EncoderOptions,
+    timings: &Timings,
+) -> Result<(Vec<u8>, EncodeReport), RpegError> {
+    let luma_range = check_encodable(options)?;
+    if let Some(target) = options.target {
+        return Ok(compress_to_target(cache, options, target, timings));
+    }
+    let arithmetic = match options.compat {
+        Some(Compat::Csc411) => Arithmetic::Float,
+        None => options.arithmetic,
+    };
+    let (input, original_image) = (cache.input(), cache.original());
+    let (image, image_denominator) = cache.pixels();
+    let (decoded_width, decoded_height) = cache.decoded_size();
+    let cached = || cache.transforms(options.deterministic, timings);
+    let layout = if options.chroma_priority {
+        let transforms = cached();
+        timings.time("chroma weight", || chroma_priority_layout(transforms))
+    } else {
+        options.layout
+    };
+    let width = image.get_width();
+    let height = image.get_height();
+    let levels = block_levels(&options.regions, width, height);
+    let region_qualities: Vec<u8> = options
+        .regions
+        .iter()
+        .map(|region| region.quality)
+        .collect();
+    let luma_range = if options.two_pass {
+        timings.time("first pass", || {
+            two_pass::gather_transforms(cached(), &levels).best_luma_range(&layout)
+        })
+    } else {
+        luma_range
+    };
+    let palette_payloads = if options.palette {
+        timings.time("palette", || {
+            encode_palette_tiles(image, image_denominator, options.tile_size).filter(|payloads| {
+                let length: usize = payloads.iter().map(Vec::len).sum();
+                length <= levels.len() * layout.word_bytes()
+            })
+        })
+    } else {
+        None
+    };
+    let palette = palette_payloads.is_some();
+    let tiles = tile_rects(width as u32, height as u32, options.tile_size);
+    let (tile_modes, palette_payloads): (Vec<TileMode>, Vec<Option<Vec<u8>>>) =
+        match palette_payloads {
+            Some(payloads) => (Vec::new(), payloads.into_iter().map(Some).collect()),
+            None if options.detect_content => timings.time("content detection", || {
+                ordered(&tiles, options.deterministic, |tile| {
+                    choose_tile_mode(image, image_denominator, tile, &layout)
+                })
+                .into_iter()
+                .unzip()
+            }),
+            None => (Vec::new(), vec![None; tiles.len()]),
+        };
+    let tile_modes = if tile_modes.iter().all(|mode| *mode == TileMode::Photo) {
+        Vec::new()
+    } else {
+        tile_modes
+    };
+
+    let mut header = Header {
+        width: decoded_width,
+        height: decoded_height,
+        order: if options.progressive && !palette {
+            WordOrder::Progressive
+        } else {
+            WordOrder::Sequential
+        },
+        region_qualities: if palette {
+            Vec::new()
+        } else {
+            region_qualities
+        },
+        tile_size: options.tile_size,
+        luma_range,
+        layout,
+        metadata: options.metadata.clone(),
+        thumbnail: options
+            .embed_thumbnail
+            .then(|| downscale(original_image, THUMBNAIL_WIDTH)),
+        perceptual: options.perceptual && !palette,
+        palette,
+        tile_modes,
+        srgb: options.srgb,
+        hdr_exponent: None,
+        signed: false,
+        chunked: options.chunked,
+        refinement: None,
+    };
+    let ranges = block_ranges(&header, &levels);
+    let encode_tile = |tile: &Rect| {
+        let indices = tile_block_indices(tile, header.coded_width());
+        let tile_ranges: Vec<f64> = indices.iter().map(|index| ranges[*index]).collect();
+        let tile_image = || {
+            image.crop(
+                tile.x as usize,
+                tile.y as usize,
+                tile.width as usize,
+                tile.height as usize,
+            )
+        };
+        let (tile_width, tile_height) = (tile.width as usize, tile.height as usize);
+        let (words, stats) = match options.backend {
+            _ if options.is_cancelled() => (Vec::new(), QuantizationStats::default()),
+            Backend::Cpu if arithmetic == Arithmetic::Fixed => {
+                let tile_image = tile_image();
+                encode_in_chunks(tile_width, tile_height, options, |row, blocks| {
+                    let chunk = tile_image.crop(0, row, tile_width, CHUNK_ROWS * 2);
+                    timings.time("fixed point", || {
+                        encode_words_fixed(
+                            &chunk,
+                            image_denominator,
+                            &tile_ranges[blocks],
+                            &header.layout,
+                        )
+                    })
+                })
+            }
+            Backend::Cpu if arithmetic == Arithmetic::Single => {
+                let tile_image = tile_image();
+                encode_in_chunks(tile_width, tile_height, options, |row, blocks| {
+                    let chunk = tile_image.crop(0, row, tile_width, CHUNK_ROWS * 2);
+                    timings.time("single precision", || {
+                        encode_words_single(
+                            &chunk,
+                            image_denominator,
+                            &tile_ranges[blocks],
+                            options.perceptual,
+                            &header.layout,
+                        )
+                    })
+                })
+            }
+            Backend::Cpu => encode_in_chunks(tile_width, tile_height, options, |row, range| {
+                let convert = || {
+                    let rows = (CHUNK_ROWS * 2).min(tile_height - row);
+                    let chunk =
+                        image.crop(tile.x as usize, tile.y as usize + row, tile_width, rows);
+                    timings.time("conversion", || {
+                        component_video_to_blocks(&pixels_to_component_video(
+                            &chunk,
+                            image_denominator,
+                        ))
+                    })
+                };
+                let Some(transforms) = cache.blocks(&indices[range.clone()], timings) else {
+                    return encode_blocks(
+                        &convert(),
+                        &tile_ranges[range],
+                        options.optimize,
+                        options.perceptual,
+                        &header.layout,
+                        timings,
+                    );
+                };
+                quantize_words(
+                    transforms.into_iter(),
+                    options.optimize.then(convert).as_ref(),
+                    &tile_ranges[range],
+                    options.perceptual,
+                    &header.layout,
+                    timings,
+                )
+            }),
+            Backend::Gpu => {
+                let tile_image = tile_image();
+                timings
+                    .time("gpu", || {
+                        encode_words_on_gpu(
+                            &tile_image,
+                            image_denominator,
+                            &tile_ranges,
+                            &header.layout,
+                        )
+                        .map(|(words, clipped)| {
+                            let blocks = component_video_to_blocks(&pixels_to_component_video(
+                                &tile_image,
+                                image_denominator,
+                            ));
+                            let indices = words.iter().map(|word| {
+                                let block = header.layout.unpack(*word);
+                                (block.pb as usize, block.pr as usize)
+                            });
+                            let stats = QuantizationStats {
+                                clipped,
+                                chroma_error: chroma_error_sum(&blocks, indices, &header.layout),
+                                blocks: words.len(),
+                            };
+                            (words, stats)
+                        })
+                    })
+                    .unwrap_or_else(|message| panic!("{message}"))
+            }
+        };
+        let payload = timings.time("packing", || {
+            write_words(&words, header.order, &header.layout)
+        });
+        (payload, stats)
+    };
+    let jobs: Vec<(&Rect, Option<Vec<u8>>)> = tiles.iter().zip(palette_payloads).collect();
+    let encoded =
+        ordered(
+            &jobs,
+            options.deterministic,
+            |(tile, palette_payload)| match palette_payload {
+                Some(payload) => (payload.clone(), QuantizationStats::default()),
+                None => encode_tile(tile),
+            },
+        );
+    let (mut payloads, mut stats): (Vec<Vec<u8>>, Vec<QuantizationStats>) =
+        encoded.into_iter().unzip();
+    if let Some(min_psnr) = options.raw_below_psnr {
+        let (pixels, _, _) = block_aligned(original_image, options.pad);
+        let denominator = PixelFormat::of(&header).denominator();
+        let as_image = |tile: &Array2<Rgb>, denominator: u16| RgbImage {
+            pixels: tile.data.clone(),
+            width: tile.get_width() as u32,
+            height: tile.get_height() as u32,
+            denominator,
+        };
+        let indices: Vec<usize> = (0..tiles.len()).collect();
+        let raw_payloads: Vec<Option<Vec<u8>>> = timings.time("raw tiles", || {
+            ordered(&indices, options.deterministic, |index| {
+                let (index, tile, payload) = (*index, &tiles[*index], &payloads[*index]);
+                let mode = header.tile_mode(index);
+                if !mode.holds_words() {
+                    return None;
+                }
+                let tile_pixels = pixels.crop(
+                    tile.x as usize,
+                    tile.y as usize,
+                    tile.width as usize,
+                    tile.height as usize,
+                );
+                let options = DecodeOptions::default();
+                let decoded = decode_tile(payload, tile, mode, &header, &ranges, &options, timings);
+                let original = as_image(&tile_pixels, original_image.denominator);
+                decoded
+                    .map_or(true, |decoded| {
+                        psnr(&original, &as_image(&decoded, denominator)) < min_psnr
+                    })
+                    .then(|| encode_raw(&tile_pixels, original_image.denominator, denominator))
+            })
+        });
+        if raw_payloads.iter().any(Option::is_some) {
+            let mut modes: Vec<TileMode> = (0..tiles.len())
+                .map(|index| header.tile_mode(index))
+                .collect();
+            for (index, raw) in raw_payloads.into_iter().enumerate() {
+                if let Some(raw) = raw {
+                    (payloads[index], stats[index], modes[index]) =
+                        (raw, QuantizationStats::default(), TileMode::Raw);
+                }
+            }
+            header.tile_modes = modes;
+        }
+    }
+    if let Some(max_error) = options.max_error {
+        header.refinement = Some(timings.time("refinement", || {
+            let decoded = decompress_image(&assemble(&header, &levels, &payloads))
+                .expect("the encoder writes images it decodes");
+            let (pixels, _, _) = block_aligned(original_image, options.pad);
+            let scale = |value: u16| {
+                let denominator = original_image.denominator.max(1) as u32;
+                ((value as u32 * decoded.denominator as u32 + denominator / 2) / denominator)
+                    .min(decoded.denominator as u32) as u16
+            };
+            let original = RgbImage {
+                pixels: pixels
+                    .crop(0, 0, decoded.width as usize, decoded.height as usize)
+                    .data
+                    .iter()
+                    .map(|pixel| Rgb {
+                        red: scale(pixel.red),
+                        green: scale(pixel.green),
+                        blue: scale(pixel.blue),
+                    })
+                    .collect(),
+                ..decoded
+            };
+            Refinement::compute(&original, &decoded, max_error)
+        }));
+    }
+    let report = stats
+        .into_iter()
+        .sum::<QuantizationStats>()
+        .report(header.block_count() * 3);
+
+    let output = timings.time("packing", || match options.compat {
+        Some(Compat::Csc411) => {
+            let mut output = Vec::new();
+            header.write_legacy(&mut output);
+            output.extend(payloads.concat());
+            output
+        }
+        None => assemble(&header, &levels, &payloads),
+    });
+    let below_ratio = options
+        .min_ratio
+        .is_some_and(|min_ratio| compression_ratio(input, &output) < min_ratio);
+    if options.stores_raw() && below_ratio {
+        let (pixels, _, _) = block_aligned(original_image, options.pad);
+        let header = Header {
+            order: WordOrder::Sequential,
+            region_qualities: Vec::new(),
+            perceptual: false,
+            palette: false,
+            tile_modes: vec![TileMode::Raw; tiles.len()],
+            // Raw tiles are exact.
+            refinement: None,
+            ..header
+        };
+        let denominator = PixelFormat::of(&header).denominator();
+        let payloads: Vec<Vec<u8>> = timings.time("raw", || {
+            tiles
+                .iter()
+                .map(|tile| {
+                    let tile_pixels = pixels.crop(
+                        tile.x as usize,
+                        tile.y as usize,
+                        tile.width as usize,
+                        tile.height as usize,
+                    );
+                    encode_raw(&tile_pixels, original_image.denominator, denominator)
+                })
+                .collect()
+        });
+        let stored = timings.time("packing", || assemble(&header, &levels, &payloads));
+        return Ok((
+            stored,
+            EncodeReport {
+                clipped: 0,
+                chroma_error: 0.0,
+                ..report
+            },
+        ));
+    }
+    Ok((output, report))
+}
+
+/// Returns the luma range of the background blocks in thousandths, or an error with
+/// `ErrorKind::BadArguments` describing the first setting of `options` the encoder rejects.
+///
+/// # Arguments
+/// * `options`: Settings used to compress the image
+pub(crate) fn check_encodable(options: &EncoderOptions) -> Result<u16, RpegError> {
+    options
+        .layout
+        .verify()
+        .map_err(|message| CliError::new(ErrorKind::BadArguments, message))?;
+    check_settings(options)
+}
+
+/// Returns the luma range like `check_encodable`, leaving out the layout of the code words,
+/// which the caller verified.
+///
+/// # Arguments
+/// * `options`: Settings used to compress the image
+pub(crate) fn check_settings(options: &EncoderOptions) -> Result<u16, RpegError> {
+    let reject = |message: &str| Err(CliError::new(ErrorKind::BadArguments, message));
+    options
+        .preprocess
+        .check()
+        .and(options.check_compat())
+        .map_err(|message| CliError::new(ErrorKind::BadArguments, message))?;
+    let luma_range = options
+        .luma_range
+        .map_or(DEFAULT_LUMA_RANGE_MILLIS, |range| {
+            (range * 1000.0).round() as u16
+        });
+    if !(1..=500).contains(&luma_range) {
+        return reject("The luma range must lie between 0.001 and 0.5");
+    }
+    if options.two_pass && options.luma_range.is_some() {
+        return reject("Two-pass encoding picks the luma range itself");
+    }
+    if options.regions.len() > 255 {
+        return reject("At most 255 regions of interest are supported");
+    }
+    if options.metadata.len() > MAX_METADATA_LEN
+        || options
+            .metadata
+            .iter()
+            .any(|(key, value)| key.len() > MAX_METADATA_LEN || value.len() > MAX_METADATA_LEN)
+    {
+        return reject(&format!(
+            "At most {MAX_METADATA_LEN} metadata entries of at most {MAX_METADATA_LEN} bytes are supported"
+        ));
+    }
+    if !options.tile_size.is_multiple_of(2) {
+        return reject("The tile size must be even");
+    }
+    if options.optimize && options.arithmetic != Arithmetic::Float {
+        return reject(
+            "Rounding optimization is only available with double precision floating point arithmetic",
+        );
+    }
+    if options.perceptual
+        && (options.arithmetic == Arithmetic::Fixed || options.backend == Backend::Gpu)
+    {
+        return reject(
+            "Perceptual quantization is only available with floating point arithmetic on the CPU",
+        );
+    }
+    if options.backend == Backend::Gpu {
+        if options.deterministic {
+            return reject("Deterministic output is only available with the CPU backend");
+        }
+        if options.optimize || options.arithmetic == Arithmetic::Fixed {
+            return reject(
+                "The GPU backend supports neither rounding optimization nor fixed point",
+            );
+        }
+        if !options.layout.supported_on_gpu() || options.chroma_priority {
+            return reject(
+                "The GPU backend only supports the standard chroma table without a chroma weight",
+            );
+        }
+    }
+    if options.srgb && (options.palette || options.detect_content) {
+        return reject(
+            "sRGB conversion cannot be combined with palette coding or content detection",
+        );
+    }
+    // The conversion to linear light runs in double precision, like its decode.
+    if options.srgb && options.arithmetic != Arithmetic::Float {
+        return reject(
+            "sRGB conversion is only available with double precision floating point arithmetic",
+        );
+    }
+    if options
+        .max_error
+        .is_some_and(|max_error| max_error > MAX_ERROR)
+    {
+        return reject(&format!("The max error must be at most {MAX_ERROR}"));
+    }
+    if let Some(target) = options.target {
+        if options.max_error.is_some() {
+            return reject("A quality target picks the max error itself");
+        }
+        target
+            .check()
+            .map_err(|message| CliError::new(ErrorKind::BadArguments, message))?;
+    }
+    Ok(luma_range)
+}
+
+/// Returns the layout of `EncoderOptions::chroma_priority`: the fields of `FINE_CHROMA_LAYOUT`,
+/// which give Pb and Pr the bits of the luma detail, with the largest chroma weight under which
+/// the average Pb and Pr of none of the blocks is clipped.
+///
+/// # Arguments
+/// * `transforms`: Transforms of the blocks of the image
+pub(crate) fn chroma_priority_layout<'b>(
+    transforms: impl IntoIterator<Item = &'b TransformedBlock>,
+) -> WordLayout {
+    let extent = transforms
+        .into_iter()
+        .map(|transform| transform.pb.abs().max(transform.pr.abs()))
+        .fold(0.0, f64::max);
+    let quarters = (0.5 / extent * NEUTRAL_CHROMA_WEIGHT as f64).floor();
+    WordLayout {
+        chroma_weight: quarters.clamp(NEUTRAL_CHROMA_WEIGHT as f64, MAX_CHROMA_WEIGHT as f64) as u8,
+        ..FINE_CHROMA_LAYOUT
+    }
+}
+
+/// Returns the compression ratio of an image: the size of its pixels at one byte per channel,
+/// as in a binary PPM without its header, over the size of the compressed image.
+///
+/// # Arguments
+/// * `image`: Image that was compressed
+/// * `compressed`: Compressed image, header included
+pub fn compression_ratio(image: &RgbImage, compressed: &[u8]) -> f64 {
+    (image.pixels.len() * 3) as f64 / compressed.len().max(1) as f64
+}
+
+/// Returns a compressed image from its parts: the header, the level map when the image has
+/// regions of interest, the tile directory when it is tiled, and the tile payloads.
+///
+/// # Arguments
+/// * `header`: Header of the compressed image
+/// * `levels`: Level of every block, as returned by `roi::block_levels`
+/// * `payloads`: Payload of every tile, in row-major tile order
+pub(crate) fn assemble(header: &Header, levels: &[u8], payloads: &[Vec<u8>]) -> Vec<u8> {
+    let mut output = Vec::new();
+    // A signature of the parts the image was made from would no longer match it.
+    let unsigned = Header {
+        signed: false,
+        ..header.clone()
+    };
+    let mut level_map = Vec::new();
+    if !header.region_qualities.is_empty() {
+        write_level_map(levels, &mut level_map);
+    }
+    let mut directory = Vec::new();
+    if header.tile_size != 0 {
+        for payload in payloads.iter() {
+            directory.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        }
+    }
+    let mut parts = vec![&level_map[..], &directory[..]];
+    parts.extend(payloads.iter().map(Vec::as_slice));
+    unsigned.write_image(&parts, &mut output);
+    output
+}
+
+/// Returns the pixels of an image on a grid of whole 2x2 blocks, along with the width and
+/// height of the image the decoder gives back. With `PadPolicy::Trim`, an odd dimension loses
+/// its last column or row, except a dimension of 1, which would leave no block at all. Every
+/// other odd dimension replicates its last column or row, and the decoder drops the copy.
+///
+/// # Arguments
+/// * `image`: Image to compress
+/// * `pad`: How an odd width or height is fitted to the blocks
+pub(crate) fn block_aligned(image: &RgbImage, pad: PadPolicy) -> (Array2<Rgb>, u32, u32) {
+    let (width, height) = (image.width as usize, image.height as usize);
+    let (coded_width, decoded_width) = aligned_length(width, pad);
+    let (coded_height, decoded_height) = aligned_length(height, pad);
+    let mut pixels = Vec::with_capacity(coded_width * coded_height);
+    for row in 0..coded_height {
+        for col in 0..coded_width {
+            pixels.push(image.pixels[row.min(height - 1) * width + col.min(width - 1)].clone());
+        }
+    }
+    (
+        Array2::from_row_major(coded_width, coded_height, pixels),
+        decoded_width,
+        decoded_height,
+    )
+}
+
+/// Returns the length of a dimension of an image on the grid of whole blocks of
+/// `block_aligned`, and the length the decoder gives back.
+///
+/// # Arguments
+/// * `length`: Width or height of the image in pixels
+/// * `pad`: How an odd length is fitted to the blocks
+pub(crate) fn aligned_length(length: usize, pad: PadPolicy) -> (usize, u32) {
+    if pad == PadPolicy::Replicate || length == 1 {
+        (length.next_multiple_of(2), length as u32)
+    } else {
+        (length & !1, (length & !1) as u32)
+    }
+}
+
+/// Classifies the content of one tile of an image with `classify`, and returns the mode the
+/// tile is coded with, along with its palette-coded payload for a graphic. A graphic whose
+/// palette stream would be larger than its code words is coded as text or as a photo instead,
+/// as its edge density says.
+///
+/// # Arguments
+/// * `image`: Image with even dimensions
+/// * `image_denominator`: Denominator of the Rgb values of the image
+/// * `tile`: Tile of the image
+/// * `layout`: Layout of the code words of the other tiles
+fn choose_tile_mode(
+    image: &Array2<Rgb>,
+    image_denominator: u16,
+    tile: &Rect,
+    layout: &WordLayout,
+) -> (TileMode, Option<Vec<u8>>) {
+    let tile_image = image.crop(
+        tile.x as usize,
+        tile.y as usize,
+        tile.width as usize,
+        tile.height as usize,
+    );
+    if classify(&tile_image, image_denominator) == TileMode::Graphic {
+        let budget = tile_image.data.len() / 4 * layout.word_bytes();
+        match encode_palette(&tile_image, image_denominator) {
+            Some(payload) if payload.len() <= budget => return (TileMode::Graphic, Some(payload)),
+            _ => {}
+        }
+    }
+    if edge_density(&tile_image, image_denominator) > TEXT_EDGE_DENSITY {
+        (TileMode::Text, None)
+    } else {
+        (TileMode::Photo, None)
+    }
+}
+
+/// Returns the range b, c, and d of every block are clamped to: the range of its region of
+/// interest, or of the background, widened to `MAX_LUMA_RANGE` for the background blocks of
+/// text tiles.
+///
+/// # Arguments
+/// * `header`: Header of the compressed image
+/// * `levels`: Level of every block in row-major order, as returned by `roi::block_levels`
+pub(crate) fn block_ranges(header: &Header, levels: &[u8]) -> Vec<f64> {
+    let mut ranges = luma_ranges(levels, header.background_range(), &header.region_qualities);
+    let tiles = tile_rects(
+        header.coded_width(),
+        header.coded_height(),
+        header.tile_size,
     );
+    for (index, tile) in tiles.iter().enumerate() {
+        if header.tile_mode(index) == TileMode::Text {
+            for block in tile_block_indices(tile, header.coded_width()) {
+                if levels[block] == 0 {
+                    ranges[block] = MAX_LUMA_RANGE;
+                }
+            }
+        }
+    }
+    ranges
+}
+
+/// Palette codes every tile of an image with `encode_palette`. Returns None as soon as one of
+/// the tiles cannot be palette coded.
+///
+/// # Arguments
+/// * `image`: Image with even dimensions
+/// * `image_denominator`: Denominator of the Rgb values of the image
+/// * `tile_size`: Side in pixels of the tiles, or 0 for a single tile
+fn encode_palette_tiles(
+    image: &Array2<Rgb>,
+    image_denominator: u16,
+    tile_size: u32,
+) -> Option<Vec<Vec<u8>>> {
+    let (width, height) = (image.get_width() as u32, image.get_height() as u32);
+    tile_rects(width, height, tile_size)
+        .par_iter()
+        .map(|tile| {
+            let tile_image = image.crop(
+                tile.x as usize,
+                tile.y as usize,
+                tile.width as usize,
+                tile.height as usize,
+            );
+            encode_palette(&tile_image, image_denominator)
+        })
+        .collect()
+}
+
+/// Returns the code words of a tile and their quantization statistics, as `encode` gives them,
+/// splitting the tile into chunks of `CHUNK_ROWS` rows of blocks that are encoded in the ordered
+/// pipeline. The blocks are encoded independently, so the words are the same. Chunks that
+/// start once the cancellation token of `options` is cancelled are left out.
+///
+/// # Arguments
+/// * `width`: Width of the tile in pixels, which is even
+/// * `height`: Height of the tile in pixels, which is even
+/// * `options`: Settings of the compression, for determinism and cancellation
+/// * `encode`: Encodes the rows of the tile starting at a pixel row, given the row-major
+///   indices of their blocks within the tile
+fn encode_in_chunks(
+    width: usize,
+    height: usize,
+    options: &EncoderOptions,
+    encode: impl Fn(usize, Range<usize>) -> (Vec<u64>, QuantizationStats) + Sync,
+) -> (Vec<u64>, QuantizationStats) {
+    let columns = width / 2;
+    let count = columns * (height / 2);
+    let chunks = ordered(&chunk_starts(height), options.deterministic, |row| {
+        if options.is_cancelled() {
+            return (Vec::new(), QuantizationStats::default());
+        }
+        let start = row / 2 * columns;
+        encode(*row, start..(start + CHUNK_ROWS * columns).min(count))
+    });
+    let stats = chunks.iter().map(|(_, stats)| *stats).sum();
+    let words = chunks.into_iter().flat_map(|(words, _)| words).collect();
+    (words, stats)
+}
+
+/// Runs the compression pipeline over an Rgb image and returns one code word per 2x2 block, in
+/// row-major block order, along with the number of b, c, and d coefficients clipped and the
+/// error of the quantized chroma.
+///
+/// # Arguments
+/// * `image`: Image, or tile of an image, with even dimensions
+/// * `image_denominator`: Denominator of the Rgb values of the image
+/// * `luma_ranges`: Range b, c, and d of every block are clamped to
+/// * `optimize`: Search the neighbouring coefficients of every block for the ones that decode
+///   closest to the block
+/// * `perceptual`: Weight the range of every block by its brightness, which needs `Float`
+///   arithmetic
+/// * `layout`: Layout of the code words
+/// * `arithmetic`: Arithmetic of the color transform and the 2x2 transform
+/// * `timings`: Timings the conversion, transform, and packing stages are added to
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn encode_words(
+    image: &Array2<Rgb>,
+    image_denominator: u16,
+    luma_ranges: &[f64],
+    optimize: bool,
+    perceptual: bool,
+    layout: &WordLayout,
+    arithmetic: Arithmetic,
+    timings: &Timings,
+) -> (Vec<u64>, QuantizationStats) {
+    if arithmetic == Arithmetic::Fixed {
+        return timings.time("fixed point", || {
+            encode_words_fixed(image, image_denominator, luma_ranges, layout)
+        });
+    }
+    if arithmetic == Arithmetic::Single {
+        return timings.time("single precision", || {
+            encode_words_single(image, image_denominator, luma_ranges, perceptual, layout)
+        });
+    }
+    let blocks_of_pixels = timings.time("conversion", || {
+        let component_vide_form = pixels_to_component_video(image, image_denominator);
+        component_video_to_blocks(&component_vide_form)
+    });
+    encode_blocks(
+        &blocks_of_pixels,
+        luma_ranges,
+        optimize,
+        perceptual,
+        layout,
+        timings,
+    )
+}
+
+/// Runs the compression pipeline from the color transform on, over the 2x2 blocks of an image
+/// already in component video, and returns one code word per block along with its quantization
+/// statistics, as `encode_words` does.
+///
+/// # Arguments
+/// * `blocks_of_pixels`: Blocks of the image, or tile of an image, in row-major block order
+/// * `luma_ranges`: Range b, c, and d of every block are clamped to
+/// * `optimize`: Search the neighbouring coefficients of every block for the ones that decode
+///   closest to the block
+/// * `perceptual`: Weight the range of every block by its brightness
+/// * `layout`: Layout of the code words
+/// * `timings`: Timings the transform and packing stages are added to
+pub(crate) fn encode_blocks(
+    blocks_of_pixels: &Array2<Block>,
+    luma_ranges: &[f64],
+    optimize: bool,
+    perceptual: bool,
+    layout: &WordLayout,
+    timings: &Timings,
+) -> (Vec<u64>, QuantizationStats) {
+    let transforms: Vec<TransformedBlock> = timings.time("transform", || {
+        blocks_of_pixels.data.iter().map(transform_block).collect()
+    });
+    quantize_words(
+        transforms.iter(),
+        optimize.then_some(blocks_of_pixels),
+        luma_ranges,
+        perceptual,
+        layout,
+        timings,
+    )
+}
+
+/// Quantizes blocks from their transforms and packs them into one code word each, along with
+/// their quantization statistics. Every compression on the CPU in floating point goes through
+/// it, whether its transforms were computed for it or taken from a `CoefficientCache`.
+///
+/// # Arguments
+/// * `transforms`: Transform of every block, in row-major order
+/// * `blocks`: The blocks in component video, to search the neighbouring coefficients of every
+///   block for the ones that decode closest to it, or None to round them
+/// * `luma_ranges`: Range b, c, and d of every block are clamped to
+/// * `perceptual`: Weight the range of every block by its brightness
+/// * `layout`: Layout of the code words
+/// * `timings`: Timings the quantization and packing stages are added to
+pub(crate) fn quantize_words<'b>(
+    transforms: impl Iterator<Item = &'b TransformedBlock>,
+    blocks: Option<&Array2<Block>>,
+    luma_ranges: &[f64],
+    perceptual: bool,
+    layout: &WordLayout,
+    timings: &Timings,
+) -> (Vec<u64>, QuantizationStats) {
+    let (coefficients, stats) = timings.time("quantization", || {
+        let mut stats = QuantizationStats::default();
+        let coefficients: Vec<DCTCoefficient> = transforms
+            .zip(luma_ranges)
+            .enumerate()
+            .map(|(index, (transform, luma_range))| {
+                let block = blocks.map(|blocks| &blocks.data[index]);
+                let (coefficient, range) =
+                    quantize_block(transform, *luma_range, perceptual, layout, block);
+                let indices = (coefficient.index_of_pb, coefficient.index_of_pr);
+                stats.clipped += clipped_transform(transform, range);
+                stats.chroma_error += transform_chroma_error(transform, indices, layout);
+                stats.blocks += 1;
+                coefficient
+            })
+            .collect();
+        (coefficients, stats)
+    });
+    let words = timings.time("packing", || {
+        coefficients
+            .iter()
+            .map(|coefficient| layout.pack(&QuantizedBlock::from(coefficient)))
+            .collect()
+    });
+    (words, stats)
+}
+
+/// Serializes code words in the given order.
+///
+/// # Arguments
+/// * `words`: One code word per 2x2 block, in row-major block order
+/// * `order`: Order in which the words are stored
+/// * `layout`: Layout of the code words
+pub(crate) fn write_words(words: &[u64], order: WordOrder, layout: &WordLayout) -> Vec<u8> {
+    match order {
+        WordOrder::Sequential => {
+            let mut bytes = Vec::with_capacity(words.len() * layout.word_bytes());
+            for word in words.iter() {
+                layout.write_word(*word, &mut bytes);
+            }
+            bytes
+        }
+        WordOrder::Progressive => to_progressive(words, layout),
+    }
 }
 
-/// Takes a compressed image in the form of a .txt file of raw-bytes of 32 bits words in Bigendian
+/// Takes a compressed image in the form of a file of raw-bytes of 32 bits words in Bigendian
 /// format or the bytes from standard-in, and decompresses the image back to an Rgb format. The image
 /// undergoes the process of decompression backwards in order to obtain a image similar to the original,
-/// but with less quality "usually not able to appreciate by the human eye."
+/// but with less quality "usually not able to appreciate by the human eye." The image is written
+/// to `output` as a PPM, as a PNG tagged with the color space of the header when `output`
+/// ends in `.png`, or as a PFM for an HDR image that is not tone mapped. Failures are returned with the category the CLI exits with.
+///
+/// # Arguments
+/// * `filename`: A file of raw 32 byte words in Bigendian format, or None to read from
+///   standard in
+/// * `output`: Destination of the PPM image
+/// * `options`: Settings used to decompress the image
+/// * `profile`: Print the time spent in every stage and the throughput to standard error
+/// * `mmap`: Map `filename` into memory instead of reading it, so that only the parts of it
+///   the decoder touches are paged in
+pub fn decompress(
+    filename: Option<&str>,
+    output: &Output,
+    options: &DecodeOptions,
+    profile: bool,
+    mmap: bool,
+) -> Result<FileResult, CliError> {
+    let timings = Timings::new();
+    let start = Instant::now();
+    let bytes: Box<dyn Deref<Target = [u8]>> = timings
+        .time("read", || match filename {
+            Some(filename) if mmap => {
+                MappedFile::open(filename).map(|mapped| Box::new(mapped) as _)
+            }
+            _ => read_input(filename).map(|bytes| Box::new(bytes) as _),
+        })
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let out_image =
+        decode(&bytes, options, &timings).map_err(|message| CliError::stream(&bytes, message))?;
+    let header = Header::read(&bytes)
+        .map_err(|message| CliError::stream(&bytes, message))?
+        .0;
+    let hdr_exponent = header
+        .hdr_exponent
+        .filter(|_| options.tone_map == ToneMap::None);
+    let png = output
+        .filename
+        .as_ref()
+        .is_some_and(|filename| filename.to_ascii_lowercase().ends_with(".png"));
+    if png && hdr_exponent.is_some() {
+        return Err(CliError::new(
+            ErrorKind::BadArguments,
+            "HDR images are written as PNG only with a --tone-map".to_string(),
+        ));
+    }
+    let color_tag = ColorTag::from_metadata(&header.metadata)
+        .map_err(|message| CliError::stream(&bytes, message))?;
+    let mut output_bytes = 0;
+    timings
+        .time("write", || {
+            output.write_with(|writer| {
+                let mut writer = CountingWriter {
+                    inner: writer,
+                    count: &mut output_bytes,
+                };
+                match hdr_exponent {
+                    Some(exponent) => {
+                        HdrImage::from_coded(&out_image, exponent).write_to(&mut writer)
+                    }
+                    None if png => writer
+                        .write_all(&write_png(&out_image, color_tag.as_ref())?)
+                        .map_err(|error| format!("Failed to write the image: {error}")),
+                    None => out_image.write_to(&mut writer),
+                }
+            })
+        })
+        .map_err(|message| CliError::new(ErrorKind::Io, message))?;
+    if profile {
+        timings.finish(start.elapsed(), out_image.pixels.len() * 3);
+        notice(&timings);
+    }
+    Ok(FileResult {
+        input: filename.unwrap_or("-").to_string(),
+        output: output.filename.as_deref().unwrap_or("-").to_string(),
+        input_bytes: bytes.len(),
+        output_bytes,
+        seconds: start.elapsed().as_secs_f64(),
+    })
+}
+
+/// Writer that counts the bytes written through it, for the results of `--porcelain`.
+struct CountingWriter<'a> {
+    inner: &'a mut dyn Write,
+    count: &'a mut usize,
+}
+
+impl Write for CountingWriter<'_> {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(bytes)?;
+        *self.count += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Takes the bytes of a compressed image and decompresses them back into an Rgb image.
+/// Returns an error if the header is malformed or the payload does not hold exactly one code
+/// word per block.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+pub fn decompress_image(bytes: &[u8]) -> Result<RgbImage, String> {
+    decode(bytes, &DecodeOptions::default(), &Timings::new())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Image decoded from untrusted bytes
+///
+/// Returned by `decode_unchecked_input`, with the dimensions and the denominator of the
+/// compressed image. Unlike a `RgbImage`, it can only be built by a decode that checked every
+/// byte of its input.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::codec::decode_unchecked_input;
+/// use rpeg::encoder::Encoder;
+/// use rpeg::ppm::{Rgb, RgbImage};
+///
+/// let pixel = Rgb { red: 90, green: 120, blue: 150 };
+/// let image = RgbImage { pixels: vec![pixel; 16], width: 4, height: 4, denominator: 255 };
+/// let decoded = decode_unchecked_input(&Encoder::new().compress(&image).unwrap()).unwrap();
+/// assert_eq!((decoded.width(), decoded.height(), decoded.denominator()), (4, 4, 255));
+/// let image: RgbImage = decoded.into_image();
+/// assert_eq!(image.pixels.len(), 16);
+/// ```
+pub struct DecodedImage(RgbImage);
+
+impl DecodedImage {
+    /// Returns the width of the image in pixels.
+    pub fn width(&self) -> u32 {
+        self.0.width
+    }
+
+    /// Returns the height of the image in pixels.
+    pub fn height(&self) -> u32 {
+        self.0.height
+    }
+
+    /// Returns the largest density of a channel.
+    pub fn denominator(&self) -> u16 {
+        self.0.denominator
+    }
+
+    /// Returns the pixels of the image in row-major order.
+    pub fn pixels(&self) -> &[Rgb] {
+        &self.0.pixels
+    }
+
+    /// Returns the decoded image.
+    pub fn image(&self) -> &RgbImage {
+        &self.0
+    }
+
+    /// Returns the decoded image, giving up the wrapper.
+    pub fn into_image(self) -> RgbImage {
+        self.0
+    }
+}
+
+impl From<DecodedImage> for RgbImage {
+    fn from(decoded: DecodedImage) -> Self {
+        decoded.0
+    }
+}
+
+/// Memory `decode_unchecked_input` lets a single decode take, so that a forged header fails
+/// instead of exhausting the memory of a fuzzer.
+pub const UNCHECKED_MAX_MEMORY: u64 = 1 << 30;
+
+/// Decodes arbitrary bytes, such as the inputs of a fuzzer, like `decompress_image`. Every
+/// malformed input is returned as an error rather than a panic: the path never unwraps a value
+/// or indexes past the end of a slice, and it fails before taking more than
+/// `UNCHECKED_MAX_MEMORY` bytes.
+///
+/// # Arguments
+/// * `bytes`: Any bytes
+pub fn decode_unchecked_input(bytes: &[u8]) -> Result<DecodedImage, RpegError> {
+    let options = DecodeOptions {
+        max_memory: Some(UNCHECKED_MAX_MEMORY),
+        ..Default::default()
+    };
+    decode(bytes, &options, &Timings::new())
+        .map(DecodedImage)
+        .map_err(|message| CliError::stream(bytes, message))
+}
+
+/// Takes the bytes of a compressed image, possibly only a prefix of them, and decodes as much
+/// of the image as is present. For a progressive image every block is present at low quality
+/// once the DC terms have arrived, and the refinements then sharpen it block by block. For a
+/// sequential image the blocks that have not arrived are left black.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, or a prefix of it that contains at least the header
+pub fn decompress_preview(bytes: &[u8]) -> Result<RgbImage, String> {
+    decode(
+        bytes,
+        &DecodeOptions {
+            preview: true,
+            ..Default::default()
+        },
+        &Timings::new(),
+    )
+}
+
+/// Takes the bytes of a compressed image and decompresses only the pixels inside `region`. For
+/// a tiled image only the tiles overlapping the region are decoded. The region is clipped to
+/// the image, and an error is returned when nothing of it is left.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+/// * `region`: Rectangle of the image to decode
+pub fn decompress_region(bytes: &[u8], region: &Rect) -> Result<RgbImage, String> {
+    decode(
+        bytes,
+        &DecodeOptions {
+            region: Some(*region),
+            ..Default::default()
+        },
+        &Timings::new(),
+    )
+}
+
+/// Takes the bytes of a compressed image and decompresses them as selected by `options`.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+/// * `options`: Settings used to decompress the image
+pub fn decompress_with_options(bytes: &[u8], options: &DecodeOptions) -> Result<RgbImage, String> {
+    decode(bytes, options, &Timings::new())
+}
+
+/// Decompresses a compressed image exactly like `decompress_with_options` into `image`,
+/// replacing what it held. When `ScratchBuffers::covers_decode` accepts the header and
+/// `options`, the temporaries are those of `scratch`, and a warm `scratch` and `image` of the
+/// same size allocate nothing.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+/// * `options`: Settings used to decompress the image
+/// * `scratch`: Buffers of the temporaries, reused across calls
+/// * `image`: Image receiving the decoded pixels
+pub fn decompress_into(
+    bytes: &[u8],
+    options: &DecodeOptions,
+    scratch: &mut ScratchBuffers,
+    image: &mut RgbImage,
+) -> Result<(), String> {
+    let (header, payload) = Header::locate(bytes)?;
+    if ScratchBuffers::covers_decode(&header, options) {
+        return scratch.decompress(&header, &bytes[payload], options, image);
+    }
+    *image = decompress_with_options(bytes, options)?;
+    Ok(())
+}
+
+/// Decompresses a compressed image exactly like `decompress_with_options`, and also adds the
+/// time spent in every stage of the pipeline to `timings`.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+/// * `options`: Settings used to decompress the image
+/// * `timings`: Timings the stages are added to
+pub fn decompress_with_timings(
+    bytes: &[u8],
+    options: &DecodeOptions,
+    timings: &Timings,
+) -> Result<RgbImage, String> {
+    decode(bytes, options, timings)
+}
+
+/// Decodes the tiles of a compressed image that overlap `options.region`, or the whole image,
+/// and assembles them into a single Rgb image.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+/// * `options`: Settings used to decompress the image
+/// * `timings`: Timings the stages are added to
+fn decode(bytes: &[u8], options: &DecodeOptions, timings: &Timings) -> Result<RgbImage, String> {
+    let Prelude {
+        header,
+        ranges,
+        payloads,
+        ..
+    } = timings.time("unpacking", || read_prelude(bytes, options))?;
+    let out_rect = output_rect(&header, options.region)?;
+    let tiles: Vec<(Rect, TileMode, &[u8])> = tile_rects(
+        header.coded_width(),
+        header.coded_height(),
+        header.tile_size,
+    )
+    .into_iter()
+    .enumerate()
+    .zip(payloads)
+    .filter(|((_, tile), _)| tile.overlaps(&out_rect))
+    .map(|((index, tile), payload)| (tile, header.tile_mode(index), payload))
+    .collect();
+    let decoded: Vec<Array2<Rgb>> = tiles
+        .par_iter()
+        .map(|(tile, mode, payload)| {
+            decode_tile(payload, tile, *mode, &header, &ranges, options, timings)
+        })
+        .collect::<Result<_, String>>()?;
+
+    let black = Rgb {
+        red: 0,
+        green: 0,
+        blue: 0,
+    };
+    let assembly = Instant::now();
+    let place = |pixels: &mut [Rgb], tile: &Rect, tile_pixels: &Array2<Rgb>| {
+        for (c, r, pixel) in tile_pixels.iter_row_major() {
+            let (x, y) = (tile.x + c as u32, tile.y + r as u32);
+            if x >= out_rect.x
+                && x < out_rect.x + out_rect.width
+                && y >= out_rect.y
+                && y < out_rect.y + out_rect.height
+            {
+                let index =
+                    (y - out_rect.y) as usize * out_rect.width as usize + (x - out_rect.x) as usize;
+                pixels[index] = pixel.clone();
+            }
+        }
+    };
+    let mut pixels = vec![black; out_rect.width as usize * out_rect.height as usize];
+    for ((tile, _, _), tile_pixels) in tiles.iter().zip(decoded.iter()) {
+        place(&mut pixels, tile, tile_pixels);
+    }
+    let mut image = RgbImage {
+        pixels,
+        width: out_rect.width,
+        height: out_rect.height,
+        denominator: PixelFormat::of(&header).denominator(),
+    };
+    timings.record("assembly", assembly.elapsed());
+    if let Some(refinement) = header.refinement.as_ref().filter(|_| !options.preview) {
+        timings.time("refinement", || {
+            refinement.apply(&mut image, header.width, header.height, Some(&out_rect))
+        })?;
+    }
+    if !options.deblock || tiles.iter().all(|(_, mode, _)| !mode.holds_words()) {
+        return Ok(adjusted(image, &header, options, timings));
+    }
+    // Palette-coded and raw tiles are exact, so they are put back once their neighbours are
+    // deblocked.
+    let mut deblocked = timings.time("deblock", || deblock(&image));
+    for ((tile, mode, _), tile_pixels) in tiles.iter().zip(decoded.iter()) {
+        if !mode.holds_words() {
+            place(&mut deblocked.pixels, tile, tile_pixels);
+        }
+    }
+    Ok(adjusted(deblocked, &header, options, timings))
+}
+
+/// Returns a decoded image after the tone mapping and the color adjustments of `options`, if
+/// there are any.
+///
+/// # Arguments
+/// * `image`: Decoded image
+/// * `header`: Header of the compressed image
+/// * `options`: Settings used to decompress the image
+/// * `timings`: Timings the stages are added to
+pub(crate) fn adjusted(
+    image: RgbImage,
+    header: &Header,
+    options: &DecodeOptions,
+    timings: &Timings,
+) -> RgbImage {
+    let image = match header.hdr_exponent {
+        Some(exponent) if options.tone_map != ToneMap::None => {
+            timings.time("tone map", || options.tone_map.apply(image, exponent))
+        }
+        _ => image,
+    };
+    let image = match &options.tint {
+        // The other images are tinted while they are decoded, in `decode_tile`.
+        Some(tint) if header.hdr_exponent.is_some() => timings.time("tint", || {
+            let (width, height) = (image.width as usize, image.height as usize);
+            let pixels = Array2::from_row_major(width, height, image.pixels);
+            let pixels = if image.denominator == u16::MAX {
+                tint_pixels::<Rgb16>(&pixels, image.denominator, tint)
+            } else {
+                tint_pixels::<Rgb>(&pixels, image.denominator, tint)
+            };
+            RgbImage {
+                pixels: pixels.data,
+                ..image
+            }
+        }),
+        _ => image,
+    };
+    if options.adjustments.is_empty() {
+        return image;
+    }
+    timings.time("adjust", || {
+        let (width, height) = (image.width as usize, image.height as usize);
+        let pixels = Array2::from_row_major(width, height, image.pixels);
+        RgbImage {
+            pixels: adjust(&pixels, &options.adjustments, image.denominator).data,
+            ..image
+        }
+    })
+}
+
+/// Returns the rectangle of the image decoded for `region`: the region clipped to the image,
+/// or the whole image when `region` is None. Returns an error when nothing of the region is
+/// left.
+///
+/// # Arguments
+/// * `header`: Header of the compressed image
+/// * `region`: Rectangle of the image to decode, or None
+pub(crate) fn output_rect(header: &Header, region: Option<Rect>) -> Result<Rect, String> {
+    let image_rect = Rect {
+        x: 0,
+        y: 0,
+        width: header.width,
+        height: header.height,
+    };
+    match region {
+        None => Ok(image_rect),
+        Some(region) if region.overlaps(&image_rect) => Ok(Rect {
+            x: region.x,
+            y: region.y,
+            width: region.width.min(header.width - region.x),
+            height: region.height.min(header.height - region.y),
+        }),
+        Some(_) => Err("The region lies outside of the image".to_string()),
+    }
+}
+
+/// Bytes of intermediate values held for every pixel of a tile while it is decoded: the
+/// unpacked coefficients, the component video, and the floating point pixels.
+const WORKING_BYTES_PER_PIXEL: u64 = 64;
+
+/// Memory a decode may take when `DecodeOptions::max_memory` is not set, enough for images of
+/// about 50 megapixels, so that a forged header fails instead of exhausting the memory.
+pub const DEFAULT_MAX_MEMORY: u64 = 4 << 30;
+
+/// Returns an error when decoding the image of `header` with `options` would need more memory
+/// than `options` allows.
+///
+/// # Arguments
+/// * `header`: Header of the compressed image
+/// * `options`: Settings used to decompress the image
+pub(crate) fn check_memory(header: &Header, options: &DecodeOptions) -> Result<(), String> {
+    let limit = options.max_memory.unwrap_or(DEFAULT_MAX_MEMORY);
+    let needed = decode_memory(header, options);
+    if needed > limit {
+        return Err(format!(
+            "Decoding the image needs about {needed} bytes, more than the limit of {limit}"
+        ));
+    }
+    Ok(())
+}
+
+/// Returns an error when `payload_len` bytes are fewer than the payload of the image of
+/// `header` can be stored in: a code word for every block, or, when tiles may be palette
+/// coded, two bytes for every run of up to 256 pixels. Checked before anything is sized by the
+/// header, so that a forged header fails without allocating.
+///
+/// # Arguments
+/// * `header`: Header of the compressed image
+/// * `payload_len`: Number of bytes of the payload
+pub(crate) fn check_payload_len(header: &Header, payload_len: usize) -> Result<(), String> {
+    let blocks = header.block_count() as u64;
+    let needed = if header.palette || header.tile_modes.contains(&TileMode::Graphic) {
+        blocks * 4 / 128
+    } else {
+        blocks * header.layout.word_bytes() as u64
+    };
+    if (payload_len as u64) < needed {
+        return Err(format!(
+            "Expected at least {needed} bytes of compressed data, found {payload_len}"
+        ));
+    }
+    Ok(())
+}
+
+/// Returns an estimate of the peak number of bytes decoding an image with `options` needs,
+/// from its header alone: the range of every block, the decoded pixels and intermediate values
+/// of every tile overlapping the decoded rectangle, and the assembled image, twice with
+/// `deblock`.
+///
+/// # Arguments
+/// * `header`: Header of the compressed image
+/// * `options`: Settings used to decompress the image
+pub fn decode_memory(header: &Header, options: &DecodeOptions) -> u64 {
+    let pixel_bytes = std::mem::size_of::<Rgb>() as u64;
+    let Ok(out_rect) = output_rect(header, options.region) else {
+        return 0;
+    };
+    let (coded_width, coded_height) = (header.coded_width() as u64, header.coded_height() as u64);
+    let tile_area = match header.tile_size as u64 {
+        0 => coded_width * coded_height,
+        size => {
+            let span = |start: u32, length: u32, coded: u64| {
+                let first = start as u64 / size * size;
+                let last = ((start as u64 + length as u64).div_ceil(size) * size).min(coded);
+                last - first
+            };
+            span(out_rect.x, out_rect.width, coded_width)
+                * span(out_rect.y, out_rect.height, coded_height)
+        }
+    };
+    let out_area = out_rect.width as u64 * out_rect.height as u64;
+    let block_bytes = (coded_width / 2 * (coded_height / 2))
+        .saturating_mul(std::mem::size_of::<f64>() as u64 + 1);
+    block_bytes
+        .saturating_add(tile_area.saturating_mul(pixel_bytes + WORKING_BYTES_PER_PIXEL))
+        .saturating_add(out_area.saturating_mul(pixel_bytes * (1 + options.deblock as u64)))
+}
+
+/// Everything needed to decode the tiles of a compressed image.
+pub(crate) struct Prelude<'a> {
+    pub(crate) header: Header,
+    /// Level of every block, as returned by `roi::block_levels`.
+    pub(crate) levels: Vec<u8>,
+    /// Range b, c, and d of every block were clamped to.
+    pub(crate) ranges: Vec<f64>,
+    /// Payload of every tile, in row-major tile order.
+    pub(crate) payloads: Vec<&'a [u8]>,
+}
+
+/// Parses everything in front of the tile payloads of a compressed image: the header, the
+/// level map when the image has regions of interest, and the tile directory when the image is
+/// tiled. The signature at the end of a signed image is left out of the payloads. Returns an
+/// error when the image cannot be decoded with `options`, or would need more than
+/// `options.max_memory`.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+/// * `options`: Settings used to decompress the image; with `preview`, a truncated payload
+///   cuts the payloads of the last tiles short
+pub(crate) fn read_prelude<'a>(
+    bytes: &'a [u8],
+    options: &DecodeOptions,
+) -> Result<Prelude<'a>, String> {
+    let partial = options.preview;
+    for adjustment in &options.adjustments {
+        adjustment.check()?;
+    }
+    let (header, payload) = Header::locate(bytes)?;
+    let bytes = &bytes[..payload.end];
+    let mut payload_start = payload.start;
+    if header.perceptual && options.arithmetic == Arithmetic::Fixed {
+        return Err(
+            "Perceptual quantization can only be decoded with floating point arithmetic"
+                .to_string(),
+        );
+    }
+    if header.srgb && options.arithmetic != Arithmetic::Float {
+        return Err(
+            "Linear light can only be decoded with double precision floating point arithmetic"
+                .to_string(),
+        );
+    }
+    if header.hdr_exponent.is_some() && options.arithmetic != Arithmetic::Float {
+        return Err(
+            "HDR images can only be decoded with double precision floating point arithmetic"
+                .to_string(),
+        );
+    }
+    check_memory(&header, options)?;
+    if !partial {
+        check_payload_len(&header, bytes.len() - payload_start)?;
+    }
+    let block_count = header.block_count();
+    let levels = if header.region_qualities.is_empty() {
+        vec![0; block_count]
+    } else {
+        let (levels, map_len) = read_level_map(
+            &bytes[payload_start..],
+            block_count,
+            header.region_qualities.len(),
+        )?;
+        payload_start += map_len;
+        levels
+    };
+    let ranges = block_ranges(&header, &levels);
+
+    let tiles = tile_rects(
+        header.coded_width(),
+        header.coded_height(),
+        header.tile_size,
+    );
+    let lengths: Vec<usize> = if header.tile_size == 0 {
+        vec![bytes.len() - payload_start]
+    } else {
+        let directory = bytes
+            .get(payload_start..payload_start + tiles.len() * 4)
+            .ok_or("Ran out of bytes while reading the tile directory")?;
+        payload_start += directory.len();
+        directory
+            .chunks_exact(4)
+            .map(|length| {
+                length
+                    .iter()
+                    .fold(0, |total, byte| total << 8 | *byte as usize)
+            })
+            .collect()
+    };
+    let mut payloads = Vec::with_capacity(lengths.len());
+    for length in lengths {
+        let start = payload_start.min(bytes.len());
+        let end = payload_start.saturating_add(length);
+        if end > bytes.len() && !partial {
+            return Err("Ran out of bytes while reading the compressed data".to_string());
+        }
+        payloads.push(&bytes[start..end.min(bytes.len())]);
+        payload_start = end;
+    }
+    if payload_start < bytes.len() {
+        return Err(format!(
+            "Found {} unexpected bytes after the compressed data",
+            bytes.len() - payload_start
+        ));
+    }
+    Ok(Prelude {
+        header,
+        levels,
+        ranges,
+        payloads,
+    })
+}
+
+/// Returns the size in bytes of the compressed image whose first bytes are `bytes`, signature
+/// included, read from the parts in front of its payloads. Returns None until those parts have
+/// all arrived, and for an untiled image whose payload is palette coded or raw, whose size is
+/// only known once it ends.
+///
+/// # Arguments
+/// * `bytes`: First bytes of a compressed image, possibly all of them
+pub(crate) fn stored_length(bytes: &[u8]) -> Option<usize> {
+    if read_version(bytes).ok()? == CHUNKED_VERSION {
+        return chunked_len(bytes);
+    }
+    let (header, mut payload_start) = Header::read(bytes).ok()?;
+    if !header.region_qualities.is_empty() {
+        let (_, map_len) = read_level_map(
+            &bytes[payload_start..],
+            header.block_count(),
+            header.region_qualities.len(),
+        )
+        .ok()?;
+        payload_start += map_len;
+    }
+    let payload_len = if header.tile_size == 0 {
+        if !header.tile_mode(0).holds_words() {
+            return None;
+        }
+        header.block_count() * header.layout.word_bytes()
+    } else {
+        let tiles = tile_rects(
+            header.coded_width(),
+            header.coded_height(),
+            header.tile_size,
+        );
+        let directory = bytes.get(payload_start..payload_start + tiles.len() * 4)?;
+        payload_start += directory.len();
+        directory
+            .chunks_exact(4)
+            .map(|length| u32::from_be_bytes(length.try_into().unwrap()) as usize)
+            .sum()
+    };
+    let signature_len = if header.signed { SIGNATURE_LEN } else { 0 };
+    Some(payload_start + payload_len + signature_len)
+}
+
+/// Decodes the payload of one tile back into its Rgb pixels.
 ///
 /// # Arguments
-/// * `filename`: A .txt extension file of raw 32 byte words in Bigendian format, or None to read from
-/// standard in
-pub fn decompress(filename: Option<&str>) {
-    let compressed_image = read_in_rpeg_data(Some(filename.as_deref()).unwrap());
-    let (byte_words, image_width, image_height) = compressed_image.unwrap();
-    let image_data: Vec<u32> = byte_words
+/// * `payload`: Code words of the tile, possibly truncated when `partial` is set
+/// * `tile`: Rectangle of the image covered by the tile
+/// * `mode`: Coding mode of the tile
+/// * `header`: Header of the compressed image
+/// * `ranges`: Range b, c, and d of every block of the image were clamped to
+/// * `options`: Settings used to decompress the image
+/// * `timings`: Timings the stages are added to
+pub(crate) fn decode_tile(
+    payload: &[u8],
+    tile: &Rect,
+    mode: TileMode,
+    header: &Header,
+    ranges: &[f64],
+    options: &DecodeOptions,
+    timings: &Timings,
+) -> Result<Array2<Rgb>, String> {
+    let format = PixelFormat::of(header);
+    // HDR images are tinted after their tone mapping, in `adjusted`.
+    let tint = options
+        .tint
+        .as_ref()
+        .filter(|_| format != PixelFormat::Rgb16);
+    if mode == TileMode::Graphic {
+        return timings.time("palette", || {
+            decode_palette(
+                payload,
+                tile.width as usize,
+                tile.height as usize,
+                options.preview,
+            )
+            .map(|image| tint_decoded(image, tint, format))
+        });
+    }
+    if mode == TileMode::Raw {
+        return decode_raw(
+            payload,
+            tile.width as usize,
+            tile.height as usize,
+            format.denominator(),
+            options.preview,
+        )
+        .map(|image| tint_decoded(image, tint, format));
+    }
+    let indices = tile_block_indices(tile, header.coded_width());
+    let layout = &header.layout;
+    let unpacking = Instant::now();
+    let image_data = read_tile_words(payload, indices.len(), header, options.preview)?;
+    let tile_ranges: Vec<f64> = indices.iter().map(|index| ranges[*index]).collect();
+    timings.record("unpacking", unpacking.elapsed());
+    let dither = options.dither.then_some((tile.x as usize, tile.y as usize));
+    decode_words(
+        &image_data,
+        &tile_ranges,
+        tile.width as usize,
+        tile.height as usize,
+        dither,
+        layout,
+        header.perceptual,
+        format,
+        tint,
+        options.arithmetic,
+        timings,
+    )
+}
+
+/// Reads the code words of one tile, in row-major block order.
+///
+/// # Arguments
+/// * `payload`: Code words of the tile, possibly truncated when `partial` is set
+/// * `block_count`: Number of 2x2 blocks in the tile
+/// * `header`: Header of the compressed image
+/// * `partial`: Accept a truncated payload, filling the missing blocks with black
+pub(crate) fn read_tile_words(
+    payload: &[u8],
+    block_count: usize,
+    header: &Header,
+    partial: bool,
+) -> Result<Vec<u64>, String> {
+    let layout = &header.layout;
+    let expected_len = block_count * layout.word_bytes();
+    if !partial && payload.len() != expected_len {
+        return Err(format!(
+            "Expected {} bytes of compressed data, found {}",
+            expected_len,
+            payload.len()
+        ));
+    }
+    Ok(match header.order {
+        WordOrder::Sequential => {
+            let mut words: Vec<u64> = payload
+                .chunks_exact(layout.word_bytes())
+                .take(block_count)
+                .map(|word| layout.read_word(word))
+                .collect();
+            words.resize(block_count, layout.missing_word());
+            words
+        }
+        WordOrder::Progressive => from_progressive(payload, block_count, layout),
+    })
+}
+
+/// Reads the header and the code words of a compressed image, with one code word per 2x2
+/// block in row-major block order across the whole image, whatever its tiling and word order.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+pub fn read_code_words(bytes: &[u8]) -> Result<(Header, Vec<u64>), String> {
+    let Prelude {
+        header, payloads, ..
+    } = read_prelude(bytes, &DecodeOptions::default())?;
+    let tile_count = payloads.len();
+    if (0..tile_count).any(|index| !header.tile_mode(index).holds_words()) {
+        return Err("Palette-coded and raw tiles hold no code words".to_string());
+    }
+    let mut words = vec![0; header.block_count()];
+    let tiles = tile_rects(
+        header.coded_width(),
+        header.coded_height(),
+        header.tile_size,
+    );
+    for (tile, payload) in tiles.iter().zip(payloads) {
+        let indices = tile_block_indices(tile, header.coded_width());
+        let tile_words = read_tile_words(payload, indices.len(), &header, false)?;
+        for (index, word) in indices.into_iter().zip(tile_words) {
+            words[index] = word;
+        }
+    }
+    Ok((header, words))
+}
+
+/// Returns a compressed image holding `words`, one code word per 2x2 block in row-major block
+/// order across the whole image, the inverse of `read_code_words`: the words are split into
+/// the tiles of `header` and written in its word order. Recorded tile modes are reset to
+/// `Photo`, since every tile holds code words.
+///
+/// # Arguments
+/// * `header`: Header of the compressed image, with its new dimensions
+/// * `levels`: Level of every block, as returned by `roi::block_levels`
+/// * `words`: Code word of every block
+pub(crate) fn write_code_words(mut header: Header, levels: &[u8], words: &[u64]) -> Vec<u8> {
+    let tiles = tile_rects(
+        header.coded_width(),
+        header.coded_height(),
+        header.tile_size,
+    );
+    if !header.tile_modes.is_empty() {
+        header.tile_modes = vec![TileMode::Photo; tiles.len()];
+    }
+    let payloads: Vec<Vec<u8>> = tiles
         .iter()
-        .map(|val| u32::from_be_bytes(*val))
+        .map(|tile| {
+            let tile_words: Vec<u64> = tile_block_indices(tile, header.coded_width())
+                .into_iter()
+                .map(|index| words[index])
+                .collect();
+            write_words(&tile_words, header.order, &header.layout)
+        })
         .collect();
-    let dct_arr = unpack_values(image_data, image_width as usize, image_height as usize);
-    let blocks = from_dct_to_component_video(dct_arr);
-    let cv_image = from_blocks_to_component_format(blocks);
-    let rgb_float = component_video_back_to_rbg_floats(cv_image);
-    let image = rgb_floats_to_rgb(rgb_float);
-    let output = fix_pixel_poss(image.clone());
-    let out_image: RgbImage = RgbImage {
-        pixels: output,
-        width: image.get_width() as u32,
-        height: image.get_height() as u32,
-        denominator: 255,
-    };
-    out_image.write(None).unwrap();
+    assemble(&header, levels, &payloads)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Pixels the decoded luma and chroma of an image are converted to
+///
+/// `Srgb` converts the linear light of the `srgb` option back to sRGB, and `Rgb16` keeps 16
+/// bits per channel for the log-coded densities of HDR images.
+pub(crate) enum PixelFormat {
+    Rgb,
+    Srgb,
+    Rgb16,
+}
+
+impl PixelFormat {
+    /// Returns the pixels the image of `header` is decoded to.
+    pub(crate) fn of(header: &Header) -> PixelFormat {
+        if header.hdr_exponent.is_some() {
+            PixelFormat::Rgb16
+        } else if header.srgb {
+            PixelFormat::Srgb
+        } else {
+            PixelFormat::Rgb
+        }
+    }
+
+    /// Returns the denominator of the decoded pixels.
+    pub(crate) fn denominator(self) -> u16 {
+        match self {
+            PixelFormat::Rgb16 => u16::MAX,
+            PixelFormat::Rgb | PixelFormat::Srgb => 255,
+        }
+    }
+}
+
+/// Runs the decompression pipeline over the code words of an image, or tile of an image.
+/// Returns an error unless there is exactly one code word and one range per 2x2 block.
+///
+/// # Arguments
+/// * `image_data`: One code word per 2x2 block, in row-major block order
+/// * `luma_ranges`: Range b, c, and d of every block were clamped to
+/// * `width`: Width of the image in pixels
+/// * `height`: Height of the image in pixels
+/// * `dither`: Column and row of the tile within the whole image to round the pixels with
+///   ordered dithering, or None to truncate them
+/// * `layout`: Layout of the code words
+/// * `perceptual`: The range of every block was weighted by its brightness, which needs
+///   `Float` arithmetic
+/// * `format`: Pixels the decoded luma and chroma are converted to, where anything but `Rgb`
+///   needs `Float` arithmetic
+/// * `tint`: Tint applied to the luma and chroma before they are converted, if any
+/// * `arithmetic`: Arithmetic of the inverse 2x2 transform and the inverse color transform
+/// * `timings`: Timings the unpacking, transform, and conversion stages are added to
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn decode_words(
+    image_data: &[u64],
+    luma_ranges: &[f64],
+    width: usize,
+    height: usize,
+    dither: Option<(usize, usize)>,
+    layout: &WordLayout,
+    perceptual: bool,
+    format: PixelFormat,
+    tint: Option<&Tint>,
+    arithmetic: Arithmetic,
+    timings: &Timings,
+) -> Result<Array2<Rgb>, String> {
+    let block_count = (width / 2) * (height / 2);
+    if luma_ranges.len() != block_count {
+        return Err(format!(
+            "Expected {block_count} block ranges, found {}",
+            luma_ranges.len()
+        ));
+    }
+    if arithmetic == Arithmetic::Fixed {
+        if image_data.len() != block_count {
+            return Err(format!(
+                "Expected {block_count} code words, found {}",
+                image_data.len()
+            ));
+        }
+        let image = timings.time("fixed point", || {
+            decode_words_fixed(image_data, luma_ranges, width, height, dither, layout)
+        });
+        return Ok(timings.time("conversion", || tint_decoded(image, tint, format)));
+    }
+    if arithmetic == Arithmetic::Single {
+        if image_data.len() != block_count {
+            return Err(format!(
+                "Expected {block_count} code words, found {}",
+                image_data.len()
+            ));
+        }
+        let image = timings.time("single precision", || {
+            decode_words_single(
+                image_data,
+                luma_ranges,
+                width,
+                height,
+                dither,
+                layout,
+                perceptual,
+            )
+        });
+        return Ok(timings.time("conversion", || tint_decoded(image, tint, format)));
+    }
+    let dct_arr = timings.time("unpacking", || {
+        unpack_values(image_data, width, height, layout)
+    })?;
+    let mut cv_image = timings.time("transform", || {
+        let blocks = if perceptual {
+            let ranges = masked_ranges(&dct_arr, luma_ranges, layout);
+            from_dct_to_component_video(&dct_arr, &ranges, layout)
+        } else {
+            from_dct_to_component_video(&dct_arr, luma_ranges, layout)
+        };
+        from_blocks_to_component_format(&blocks)
+    });
+    Ok(timings.time("conversion", || {
+        if let Some(tint) = tint {
+            match format {
+                PixelFormat::Rgb => tint_component_video::<Rgb>(&mut cv_image, tint),
+                PixelFormat::Srgb => tint_component_video::<Srgb>(&mut cv_image, tint),
+                PixelFormat::Rgb16 => tint_component_video::<Rgb16>(&mut cv_image, tint),
+            }
+        }
+        let image = match format {
+            PixelFormat::Rgb => component_video_to_pixels::<Rgb>(&cv_image, dither),
+            PixelFormat::Srgb => {
+                component_video_to_pixels::<Srgb>(&cv_image, dither).map(Srgb::rgb)
+            }
+            PixelFormat::Rgb16 => {
+                component_video_to_pixels::<Rgb16>(&cv_image, dither).map(Rgb16::rgb)
+            }
+        };
+        Array2::from_row_major(width, height, fix_pixel_poss(&image))
+    }))
+}
+
+/// Applies a tint to pixels decoded without going through luma and chroma, as pixels of
+/// `format`, or returns them as they are without a tint.
+fn tint_decoded(image: Array2<Rgb>, tint: Option<&Tint>, format: PixelFormat) -> Array2<Rgb> {
+    let Some(tint) = tint else {
+        return image;
+    };
+    let denominator = format.denominator();
+    match format {
+        PixelFormat::Rgb => tint_pixels::<Rgb>(&image, denominator, tint),
+        PixelFormat::Srgb => tint_pixels::<Srgb>(&image, denominator, tint),
+        PixelFormat::Rgb16 => tint_pixels::<Rgb16>(&image, denominator, tint),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Encoder;
+    use crate::layout::{FAST_CHROMA_LAYOUT, FINE_CHROMA_LAYOUT, FINE_TABLE_LAYOUT, WIDE_LAYOUT};
+
+    fn gradient(width: u32, height: u32) -> RgbImage {
+        let pixels = (0..width * height)
+            .map(|i| Rgb {
+                red: (i * 7 % 256) as u16,
+                green: (i * 3 % 256) as u16,
+                blue: (255 - i % 256) as u16,
+            })
+            .collect();
+        RgbImage {
+            pixels,
+            width,
+            height,
+            denominator: 255,
+        }
+    }
+
+    #[test]
+    fn progressive_matches_sequential() {
+        let image = gradient(16, 10);
+        let sequential = compress_image(&image, &EncoderOptions::default());
+        let progressive = compress_image(
+            &image,
+            &EncoderOptions {
+                progressive: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(sequential.len(), progressive.len());
+        let a = decompress_image(&sequential).unwrap();
+        let b = decompress_image(&progressive).unwrap();
+        assert!(a
+            .pixels
+            .iter()
+            .zip(b.pixels.iter())
+            .all(|(x, y)| { (x.red, x.green, x.blue) == (y.red, y.green, y.blue) }));
+    }
+
+    #[test]
+    fn progressive_prefix_decodes() {
+        let image = gradient(16, 10);
+        let progressive = compress_image(
+            &image,
+            &EncoderOptions {
+                progressive: true,
+                ..Default::default()
+            },
+        );
+        // Keep the header and the DC terms only.
+        let prefix = &progressive[..crate::format::HEADER_LEN + 40 * 17 / 8];
+        assert!(decompress_image(prefix).is_err());
+        let preview = decompress_preview(prefix).unwrap();
+        assert_eq!((preview.width, preview.height), (16, 10));
+    }
+
+    #[test]
+    fn regions_are_signaled_per_block() {
+        let image = gradient(16, 10);
+        let options = EncoderOptions {
+            regions: vec![Region::parse("4,2,6,4:90").unwrap()],
+            ..Default::default()
+        };
+        let compressed = compress_image(&image, &options);
+        let Prelude { header, ranges, .. } =
+            read_prelude(&compressed, &DecodeOptions::default()).unwrap();
+        assert_eq!(header.region_qualities, vec![90]);
+        let fine = crate::dct_coeff::luma_range_for_quality(90);
+        let fine_blocks: Vec<usize> = (0..ranges.len()).filter(|i| ranges[*i] == fine).collect();
+        assert_eq!(fine_blocks, vec![10, 11, 12, 18, 19, 20]);
+        assert!(decompress_image(&compressed).is_ok());
+    }
+
+    #[test]
+    fn tiles_decode_like_the_whole_image() {
+        let image = gradient(20, 12);
+        let whole = decompress_image(&compress_image(&image, &EncoderOptions::default())).unwrap();
+        let tiled = compress_image(
+            &image,
+            &EncoderOptions {
+                tile_size: 8,
+                ..Default::default()
+            },
+        );
+        let from_tiles = decompress_image(&tiled).unwrap();
+        assert_eq!((from_tiles.width, from_tiles.height), (20, 12));
+        let same = |a: &Rgb, b: &Rgb| (a.red, a.green, a.blue) == (b.red, b.green, b.blue);
+        // Blocks never straddle tiles, so tiling does not change any pixel.
+        assert!(whole
+            .pixels
+            .iter()
+            .zip(from_tiles.pixels.iter())
+            .all(|(a, b)| same(a, b)));
+
+        let region = Rect {
+            x: 6,
+            y: 4,
+            width: 10,
+            height: 20,
+        };
+        let cropped = decompress_region(&tiled, &region).unwrap();
+        assert_eq!((cropped.width, cropped.height), (10, 8));
+        assert!(same(&cropped.pixels[0], &whole.pixels[4 * 20 + 6]));
+        assert!(same(&cropped.pixels[79], &whole.pixels[11 * 20 + 15]));
+    }
+
+    #[test]
+    fn optimized_rounding_lowers_the_error() {
+        let image = gradient(16, 16);
+        let squared_error = |options: &EncoderOptions| -> u64 {
+            let decoded = decompress_image(&compress_image(&image, options)).unwrap();
+            image
+                .pixels
+                .iter()
+                .zip(decoded.pixels.iter())
+                .map(|(a, b)| {
+                    [(a.red, b.red), (a.green, b.green), (a.blue, b.blue)]
+                        .iter()
+                        .map(|(a, b)| (*a as i64 - *b as i64).pow(2) as u64)
+                        .sum::<u64>()
+                })
+                .sum()
+        };
+        let optimized = EncoderOptions {
+            optimize: true,
+            arithmetic: Arithmetic::Float,
+            ..Default::default()
+        };
+        let plain = EncoderOptions::default();
+        assert_eq!(
+            compress_image(&image, &optimized).len(),
+            compress_image(&image, &plain).len()
+        );
+        assert!(squared_error(&optimized) < squared_error(&plain));
+    }
+
+    #[test]
+    fn high_contrast_keeps_sharp_edges() {
+        let pixels = (0..8 * 8)
+            .map(|i| {
+                let value = if (i % 8 + i / 8) % 2 == 0 { 255 } else { 0 };
+                Rgb {
+                    red: value,
+                    green: value,
+                    blue: value,
+                }
+            })
+            .collect();
+        let image = RgbImage {
+            pixels,
+            width: 8,
+            height: 8,
+            denominator: 255,
+        };
+        let (_, report) = compress_image_with_report(&image, &EncoderOptions::default());
+        assert_eq!(report.clipped, 16);
+        assert_eq!(report.coefficients, 48);
+
+        let high_contrast = EncoderOptions {
+            luma_range: Some(HIGH_CONTRAST_LUMA_RANGE),
+            ..Default::default()
+        };
+        let (compressed, report) = compress_image_with_report(&image, &high_contrast);
+        assert_eq!(report.clipped, 0);
+        let (header, _) = Header::read(&compressed).unwrap();
+        assert_eq!(header.luma_range, 500);
+        let decoded = decompress_image(&compressed).unwrap();
+        assert!(decoded.pixels[0].red > 240 && decoded.pixels[1].red < 15);
+    }
+
+    #[test]
+    fn encode_report_measures_the_chroma_error() {
+        let image = gradient(16, 10);
+        let (_, report) = compress_image_with_report(&image, &EncoderOptions::default());
+        assert_eq!(report.coefficients, 120);
+        assert!(report.clipped_share() > CLIP_WARNING_SHARE);
+        assert!(report.chroma_error > 0.0 && report.chroma_error < 0.05);
+        let fixed = EncoderOptions {
+            arithmetic: Arithmetic::Fixed,
+            ..Default::default()
+        };
+        let (_, fixed_report) = compress_image_with_report(&image, &fixed);
+        assert_eq!(fixed_report.clipped, report.clipped);
+        assert!((fixed_report.chroma_error - report.chroma_error).abs() < 1e-3);
+
+        let flat = RgbImage {
+            pixels: vec![image.pixels[0].clone(); 16 * 10],
+            ..image
+        };
+        let palette = EncoderOptions {
+            palette: true,
+            ..Default::default()
+        };
+        let (_, report) = compress_image_with_report(&flat, &palette);
+        assert_eq!(
+            report,
+            EncodeReport {
+                coefficients: 120,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn chroma_weight_is_recorded_and_priority_fits_the_chroma() {
+        let image = gradient(16, 10);
+        let weighted = EncoderOptions {
+            layout: FINE_CHROMA_LAYOUT.with_chroma_weight(1.5),
+            ..Default::default()
+        };
+        let (compressed, report) = compress_image_with_report(&image, &weighted);
+        let (header, _) = Header::read(&compressed).unwrap();
+        assert_eq!(header.layout, weighted.layout);
+        assert_eq!(header.layout.chroma_weight, 6);
+        let fixed = EncoderOptions {
+            arithmetic: Arithmetic::Fixed,
+            ..weighted.clone()
+        };
+        let (_, fixed_report) = compress_image_with_report(&image, &fixed);
+        assert!((fixed_report.chroma_error - report.chroma_error).abs() < 1e-3);
+        assert!(Encoder::new().chroma_weight(5.0).compress(&image).is_err());
+
+        let priority = EncoderOptions {
+            chroma_priority: true,
+            ..Default::default()
+        };
+        let (compressed, report) = compress_image_with_report(&image, &priority);
+        let (header, _) = Header::read(&compressed).unwrap();
+        assert_eq!(header.layout.id, FINE_CHROMA_LAYOUT.id);
+        assert!(header.layout.chroma_weight >= NEUTRAL_CHROMA_WEIGHT);
+        let (_, neutral) = compress_image_with_report(
+            &image,
+            &EncoderOptions {
+                layout: FINE_CHROMA_LAYOUT,
+                ..Default::default()
+            },
+        );
+        assert!(report.chroma_error <= neutral.chroma_error);
+        assert!(decompress_image(&compressed).is_ok());
+    }
+
+    #[test]
+    fn tints_apply_to_code_words_and_palette_tiles() {
+        let image = gradient(16, 10);
+        let decode = |bytes: &[u8], tint: &Tint, arithmetic| {
+            let options = DecodeOptions {
+                tint: Some(tint.clone()),
+                arithmetic,
+                ..Default::default()
+            };
+            decompress_with_options(bytes, &options).unwrap()
+        };
+        let compressed = compress_image(&image, &EncoderOptions::default());
+        for arithmetic in [Arithmetic::Float, Arithmetic::Fixed] {
+            let gray = decode(&compressed, &Tint::Monochrome, arithmetic);
+            assert!(gray
+                .pixels
+                .iter()
+                .all(|pixel| pixel.red == pixel.green && pixel.green == pixel.blue));
+        }
+
+        let dark = Rgb {
+            red: 20,
+            green: 40,
+            blue: 80,
+        };
+        let light = Rgb {
+            red: 250,
+            green: 220,
+            blue: 160,
+        };
+        let duotone = Tint::Duotone {
+            dark: dark.clone(),
+            light: light.clone(),
+        };
+        let ends = RgbImage {
+            pixels: [0, 0, 0, 0, 255, 255, 255, 255]
+                .map(|value| Rgb {
+                    red: value,
+                    green: value,
+                    blue: value,
+                })
+                .to_vec(),
+            width: 2,
+            height: 4,
+            denominator: 255,
+        };
+        let near = |a: &Rgb, b: &Rgb| {
+            a.red.abs_diff(b.red) <= 2
+                && a.green.abs_diff(b.green) <= 2
+                && a.blue.abs_diff(b.blue) <= 2
+        };
+        let palette = EncoderOptions {
+            palette: true,
+            ..Default::default()
+        };
+        for options in [EncoderOptions::default(), palette] {
+            let tinted = decode(
+                &compress_image(&ends, &options),
+                &duotone,
+                Arithmetic::Float,
+            );
+            assert!(near(&tinted.pixels[0], &dark), "{:?}", tinted.pixels[0]);
+            assert!(near(&tinted.pixels[7], &light), "{:?}", tinted.pixels[7]);
+        }
+    }
+
+    #[test]
+    fn cached_transforms_give_the_same_bitstream() {
+        let image =
+            crate::testkit::synthetic_image(crate::testkit::Pattern::Noise { seed: 9 }, 37, 70);
+        let settings = [
+            EncoderOptions::default(),
+            EncoderOptions {
+                luma_range: Some(0.1),
+                tile_size: 16,
+                optimize: true,
+                progressive: true,
+                arithmetic: Arithmetic::Float,
+                ..EncoderOptions::default()
+            },
+            EncoderOptions {
+                perceptual: true,
+                optimize: true,
+                layout: WIDE_LAYOUT,
+                arithmetic: Arithmetic::Float,
+                ..EncoderOptions::default()
+            },
+            EncoderOptions {
+                two_pass: true,
+                chroma_priority: true,
+                ..EncoderOptions::default()
+            },
+            EncoderOptions {
+                arithmetic: Arithmetic::Fixed,
+                max_error: Some(2),
+                ..EncoderOptions::default()
+            },
+        ];
+        let cache = CoefficientCache::new(&image, &EncoderOptions::default());
+        let timings = Timings::new();
+        for options in &settings {
+            let transformed = timings.stage("transform");
+            assert_eq!(
+                compress_prepared(&cache, options, &timings).unwrap(),
+                compress_image_with_report(&image, options)
+            );
+            if transformed.is_some() {
+                assert_eq!(timings.stage("transform"), transformed);
+            }
+        }
+        let srgb = EncoderOptions {
+            srgb: true,
+            pad: PadPolicy::Replicate,
+            arithmetic: Arithmetic::Float,
+            ..EncoderOptions::default()
+        };
+        let cache = CoefficientCache::new(&image, &srgb);
+        assert!(cache.fits(&srgb) && !cache.fits(&EncoderOptions::default()));
+        assert_eq!(
+            Encoder::from(srgb.clone())
+                .compress_cached(&cache)
+                .unwrap()
+                .0,
+            compress_image(&image, &srgb)
+        );
+        assert!(Encoder::new().compress_cached(&cache).is_err());
+    }
+
+    #[test]
+    fn max_error_bounds_every_decoded_channel() {
+        let image =
+            crate::testkit::synthetic_image(crate::testkit::Pattern::Noise { seed: 6 }, 21, 13);
+        let worst = |decoded: &RgbImage, pixels: &[Rgb]| {
+            decoded
+                .pixels
+                .iter()
+                .zip(pixels)
+                .flat_map(|(a, b)| {
+                    [(a.red, b.red), (a.green, b.green), (a.blue, b.blue)]
+                        .map(|(a, b)| a.abs_diff(b))
+                })
+                .max()
+                .unwrap()
+        };
+        for (max_error, chunked) in [(0, false), (3, true)] {
+            let encoder = Encoder::new()
+                .tile_size(8)
+                .pad(PadPolicy::Replicate)
+                .chunked(chunked)
+                .max_error(max_error);
+            let compressed = encoder.compress(&image).unwrap();
+            let (header, _) = Header::read(&compressed).unwrap();
+            assert_eq!(header.refinement.as_ref().unwrap().max_error, max_error);
+            let decoded = decompress_image(&compressed).unwrap();
+            assert_eq!(worst(&decoded, &image.pixels), max_error as u16);
+
+            let rows: Vec<Rgb> = crate::decoder::Decoder::new()
+                .rows(&compressed)
+                .unwrap()
+                .flat_map(Result::unwrap)
+                .collect();
+            assert_eq!(rows, decoded.pixels);
+            let rect = Rect {
+                x: 5,
+                y: 3,
+                width: 12,
+                height: 9,
+            };
+            let region = decompress_region(&compressed, &rect).unwrap();
+            let (cropped, covered) = crate::crop::crop_image(&compressed, rect).unwrap();
+            let inside = |rect: &Rect| -> Vec<Rgb> {
+                decoded
+                    .pixels
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| {
+                        let (x, y) = (*index as u32 % 21, *index as u32 / 21);
+                        rect.overlaps(&Rect {
+                            x,
+                            y,
+                            width: 1,
+                            height: 1,
+                        })
+                    })
+                    .map(|(_, pixel)| pixel.clone())
+                    .collect()
+            };
+            assert_eq!(region.pixels, inside(&rect));
+            assert_eq!(decompress_image(&cropped).unwrap().pixels, inside(&covered));
+        }
+        let lossy = compress_image(&image, &EncoderOptions::default());
+        assert!(worst(&decompress_image(&lossy).unwrap(), &image.pixels) > 3);
+        assert!(Encoder::new().max_error(200).compress(&image).is_err());
+
+        // A block claiming corrections of 31 bits would overflow its channels.
+        let compressed = Encoder::new()
+            .max_error(MAX_ERROR)
+            .compress(&image)
+            .unwrap();
+        let (mut header, pos) = Header::read(&compressed).unwrap();
+        let refinement = header.refinement.as_mut().unwrap();
+        refinement.bits = vec![0xff; refinement.bits.len().max(64)];
+        let mut forged = Vec::new();
+        header.write(&mut forged);
+        forged.extend_from_slice(&compressed[pos..]);
+        let error = decompress_image(&forged).unwrap_err();
+        assert!(error.contains("Invalid refinement width 31"), "{error}");
+    }
+
+    #[test]
+    fn two_pass_picks_the_luma_range_of_the_image() {
+        let pixels = (0..16 * 12)
+            .map(|i| {
+                let value = (i % 16 * 3 + i / 16 * 2) as u16;
+                Rgb {
+                    red: value,
+                    green: 40 + value,
+                    blue: 90,
+                }
+            })
+            .collect();
+        let image = RgbImage {
+            pixels,
+            width: 16,
+            height: 12,
+            denominator: 255,
+        };
+        let single_pass = compress_image(&image, &EncoderOptions::default());
+        let two_pass = Encoder::new().two_pass(true).compress(&image).unwrap();
+        let (header, _) = Header::read(&two_pass).unwrap();
+        assert!(header.luma_range < DEFAULT_LUMA_RANGE_MILLIS);
+        let psnr = |bytes: &[u8]| crate::metrics::psnr(&image, &decompress_image(bytes).unwrap());
+        assert!(psnr(&two_pass) > psnr(&single_pass));
+        assert!(Encoder::new()
+            .two_pass(true)
+            .luma_range(0.2)
+            .compress(&image)
+            .is_err());
+    }
+
+    #[test]
+    fn perceptual_quantization_is_signaled_in_the_header() {
+        let layout = &crate::layout::NARROW_LAYOUT;
+        let range = |a| crate::dct_coeff::masked_luma_range(0.2, a, layout);
+        assert!(range(0.0) > 0.2 && range(layout.a_scale()) > 0.2);
+        assert!(range((layout.a_scale() / 2.0).round()) < 0.2);
+
+        let image = gradient(16, 12);
+        let single = compress_image(&image, &EncoderOptions::default());
+        let perceptual = Encoder::new()
+            .perceptual(true)
+            .arithmetic(Arithmetic::Float)
+            .compress(&image)
+            .unwrap();
+        assert_eq!(perceptual.len(), single.len());
+        assert!(Header::read(&perceptual).unwrap().0.perceptual);
+        let float = DecodeOptions {
+            arithmetic: Arithmetic::Float,
+            ..Default::default()
+        };
+        let psnr = |bytes: &[u8]| {
+            let decoded = decompress_with_options(bytes, &float).unwrap();
+            crate::metrics::psnr(&image, &decoded)
+        };
+        assert!(
+            psnr(&perceptual) > psnr(&single) - 1.0,
+            "{}",
+            psnr(&perceptual)
+        );
+        let fixed = DecodeOptions {
+            arithmetic: Arithmetic::Fixed,
+            ..Default::default()
+        };
+        assert!(decompress_with_options(&perceptual, &fixed).is_err());
+        assert!(Encoder::new()
+            .perceptual(true)
+            .arithmetic(Arithmetic::Fixed)
+            .compress(&image)
+            .is_err());
+    }
+
+    #[test]
+    fn srgb_images_round_trip_through_linear_light() {
+        let image = RgbImage {
+            pixels: (0..16 * 12)
+                .map(|i| Rgb {
+                    red: i % 16 * 16,
+                    green: i / 16 * 20,
+                    blue: 128,
+                })
+                .collect(),
+            ..gradient(16, 12)
+        };
+        let srgb = Encoder::new()
+            .srgb(true)
+            .arithmetic(Arithmetic::Float)
+            .compress(&image)
+            .unwrap();
+        assert!(Header::read(&srgb).unwrap().0.srgb);
+        let float = DecodeOptions {
+            arithmetic: Arithmetic::Float,
+            ..Default::default()
+        };
+        let decoded = decompress_with_options(&srgb, &float).unwrap();
+        let psnr = crate::metrics::psnr(&image, &decoded);
+        assert!(psnr > 25.0, "{psnr}");
+        let checker = RgbImage {
+            pixels: (0..16 * 16)
+                .map(|i| {
+                    let value = if (i % 16 + i / 16) % 2 == 0 { 0 } else { 255 };
+                    Rgb {
+                        red: value,
+                        green: value,
+                        blue: value,
+                    }
+                })
+                .collect(),
+            ..gradient(16, 16)
+        };
+        let light = |options: &EncoderOptions| {
+            let decoded =
+                decompress_with_options(&compress_image(&checker, options), &float).unwrap();
+            let total: f64 = decoded
+                .pixels
+                .iter()
+                .map(|pixel| conversions::srgb_to_linear(pixel.green as f64 / 255.0))
+                .sum();
+            total / decoded.pixels.len() as f64
+        };
+        let srgb_options = EncoderOptions {
+            srgb: true,
+            arithmetic: Arithmetic::Float,
+            ..Default::default()
+        };
+        assert!((light(&srgb_options) - 0.5).abs() < 0.05);
+        assert!(light(&EncoderOptions::default()) < 0.4);
+        let fixed = DecodeOptions {
+            arithmetic: Arithmetic::Fixed,
+            ..Default::default()
+        };
+        assert!(decompress_with_options(&srgb, &fixed).is_err());
+        assert!(Encoder::new()
+            .srgb(true)
+            .palette(true)
+            .compress(&image)
+            .is_err());
+    }
+
+    #[test]
+    fn few_colors_are_palette_coded_without_loss() {
+        let colors = [(250, 250, 250), (20, 20, 20), (0, 90, 200)];
+        let pixels = (0..32 * 24)
+            .map(|i| {
+                let (red, green, blue) = colors[if i % 32 < 4 { 1 } else { i / 256 }];
+                Rgb { red, green, blue }
+            })
+            .collect();
+        let image = RgbImage {
+            pixels,
+            width: 32,
+            height: 24,
+            denominator: 255,
+        };
+        let encoder = Encoder::new().palette(true).progressive(true);
+        for tile_size in [0, 16] {
+            let compressed = encoder
+                .clone()
+                .tile_size(tile_size)
+                .compress(&image)
+                .unwrap();
+            let (header, _) = Header::read(&compressed).unwrap();
+            assert!(header.palette);
+            assert_eq!(compressed[4], crate::format::EXTENDED_VERSION);
+            assert_eq!(decompress_image(&compressed).unwrap(), image);
+            assert!(read_code_words(&compressed).is_err());
+        }
+
+        let photo = gradient(16, 12);
+        assert_eq!(
+            Encoder::new().palette(true).compress(&photo).unwrap(),
+            compress_image(&photo, &EncoderOptions::default())
+        );
+    }
+
+    #[test]
+    fn images_below_the_minimum_ratio_follow_the_policy() {
+        let noise =
+            crate::testkit::synthetic_image(crate::testkit::Pattern::Noise { seed: 3 }, 40, 24);
+        let lossy = Encoder::new().tile_size(16).compress(&noise).unwrap();
+        assert!(compression_ratio(&noise, &lossy) < 3.0);
+        let encoder = |policy| Encoder::new().tile_size(16).min_ratio(3.0, policy);
+        assert_eq!(encoder(RatioPolicy::Warn).compress(&noise).unwrap(), lossy);
+        assert!(encoder(RatioPolicy::Fail).compress(&noise).is_err());
+
+        let stored = encoder(RatioPolicy::Store).compress(&noise).unwrap();
+        let (header, _) = Header::read(&stored).unwrap();
+        assert_eq!(header.tile_modes, [TileMode::Raw; 6]);
+        assert_eq!(decompress_image(&stored).unwrap(), noise);
+        let rows: Vec<Rgb> = crate::decoder::Decoder::new()
+            .rows(&stored)
+            .unwrap()
+            .flat_map(Result::unwrap)
+            .collect();
+        assert_eq!(rows, noise.pixels);
+        assert!(read_code_words(&stored).is_err());
+
+        let flat = RgbImage {
+            pixels: vec![
+                Rgb {
+                    red: 9,
+                    green: 80,
+                    blue: 200
+                };
+                40 * 24
+            ],
+            ..noise
+        };
+        let palette = encoder(RatioPolicy::Store).palette(true);
+        let (header, _) = Header::read(&palette.compress(&flat).unwrap()).unwrap();
+        assert!(header.palette);
+    }
+
+    #[test]
+    fn tiles_that_decode_poorly_are_stored_raw() {
+        let noise =
+            crate::testkit::synthetic_image(crate::testkit::Pattern::Noise { seed: 5 }, 32, 16);
+        let smooth = crate::testkit::synthetic_image(crate::testkit::Pattern::Gradient, 32, 16);
+        let pixels = (0..32 * 16)
+            .map(|i| {
+                let half = if i % 32 < 16 { &noise } else { &smooth };
+                half.pixels[i].clone()
+            })
+            .collect();
+        let image = RgbImage { pixels, ..smooth };
+        for progressive in [false, true] {
+            let encoder = Encoder::new()
+                .tile_size(16)
+                .progressive(progressive)
+                .raw_below_psnr(30.0);
+            let compressed = encoder.compress(&image).unwrap();
+            let (header, _) = Header::read(&compressed).unwrap();
+            assert_eq!(header.tile_modes, [TileMode::Raw, TileMode::Photo]);
+            let decoded = decompress_image(&compressed).unwrap();
+            let rows: Vec<Rgb> = crate::decoder::Decoder::new()
+                .rows(&compressed)
+                .unwrap()
+                .flat_map(Result::unwrap)
+                .collect();
+            assert_eq!(rows, decoded.pixels);
+            for (i, pixel) in decoded.pixels.iter().enumerate() {
+                assert!(i % 32 >= 16 || *pixel == image.pixels[i]);
+            }
+        }
+        assert_eq!(
+            Encoder::new().raw_below_psnr(0.0).compress(&image).unwrap(),
+            compress_image(&image, &EncoderOptions::default())
+        );
+    }
+
+    #[test]
+    fn every_tile_is_coded_for_its_content() {
+        let photo = gradient(16, 16);
+        let pixels = (0..32 * 16)
+            .map(|i| {
+                let (col, row) = (i % 32, i / 32);
+                if col >= 16 {
+                    photo.pixels[(row * 16 + col - 16) as usize].clone()
+                } else if col < 8 {
+                    Rgb {
+                        red: 255,
+                        green: 255,
+                        blue: 255,
+                    }
+                } else {
+                    Rgb {
+                        red: 0,
+                        green: 60,
+                        blue: 120,
+                    }
+                }
+            })
+            .collect();
+        let image = RgbImage {
+            pixels,
+            width: 32,
+            height: 16,
+            denominator: 255,
+        };
+        let encoder = Encoder::new().tile_size(16).detect_content(true);
+        let compressed = encoder.compress(&image).unwrap();
+        let (header, _) = Header::read(&compressed).unwrap();
+        assert_eq!(header.tile_modes, [TileMode::Graphic, TileMode::Text]);
+        let decoded = decompress_image(&compressed).unwrap();
+        let plain =
+            decompress_image(&Encoder::new().tile_size(16).compress(&image).unwrap()).unwrap();
+        for (index, pixel) in decoded.pixels.iter().enumerate() {
+            if index % 32 < 16 {
+                assert_eq!(*pixel, image.pixels[index]);
+            }
+        }
+        let deblock = DecodeOptions {
+            deblock: true,
+            ..Default::default()
+        };
+        let deblocked = decompress_with_options(&compressed, &deblock).unwrap();
+        assert_eq!(deblocked.pixels[..16], decoded.pixels[..16]);
+        assert_ne!(decoded.pixels, plain.pixels);
+
+        let photos = encoder.compress(&gradient(8, 8)).unwrap();
+        assert!(Header::read(&photos).unwrap().0.tile_modes.is_empty());
+    }
+
+    #[test]
+    fn one_pixel_wide_or_tall_images_round_trip() {
+        for (width, height, decoded_size) in [
+            (1, 1, (1, 1)),
+            (1, 7, (1, 6)),
+            (6, 1, (6, 1)),
+            (3, 1, (2, 1)),
+        ] {
+            let pixels = (0..width * height)
+                .map(|i| {
+                    let gray = 40 + i as u16 * 20;
+                    Rgb {
+                        red: gray,
+                        green: gray,
+                        blue: gray,
+                    }
+                })
+                .collect();
+            let image = RgbImage {
+                pixels,
+                width,
+                height,
+                denominator: 255,
+            };
+            for encoder in [
+                Encoder::new(),
+                Encoder::new().progressive(true).tile_size(2),
+                Encoder::new().detect_content(true),
+            ] {
+                let decoded = decompress_image(&encoder.compress(&image).unwrap()).unwrap();
+                assert_eq!((decoded.width, decoded.height), decoded_size);
+                for row in 0..decoded.height {
+                    for col in 0..decoded.width {
+                        let original = &image.pixels[(row * width + col) as usize];
+                        let pixel = &decoded.pixels[(row * decoded.width + col) as usize];
+                        assert!(pixel.red.abs_diff(original.red) <= 12);
+                        assert!(pixel.blue.abs_diff(original.blue) <= 12);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn replicated_padding_keeps_odd_dimensions() {
+        let pixels = (0..9 * 7)
+            .map(|i| {
+                let gray = 40 + (i % 9) as u16 * 15 + (i / 9) as u16 * 5;
+                Rgb {
+                    red: gray,
+                    green: gray,
+                    blue: gray,
+                }
+            })
+            .collect();
+        let image = RgbImage {
+            pixels,
+            width: 9,
+            height: 7,
+            denominator: 255,
+        };
+        let trimmed = decompress_image(&Encoder::new().compress(&image).unwrap()).unwrap();
+        assert_eq!((trimmed.width, trimmed.height), (8, 6));
+        for encoder in [
+            Encoder::new(),
+            Encoder::new().progressive(true).tile_size(4),
+            Encoder::new().detect_content(true).tile_size(4),
+        ] {
+            let compressed = encoder.pad(PadPolicy::Replicate).compress(&image).unwrap();
+            let header = Header::read(&compressed).unwrap().0;
+            assert_eq!((header.coded_width(), header.coded_height()), (10, 8));
+            let decoded = decompress_image(&compressed).unwrap();
+            assert_eq!((decoded.width, decoded.height), (9, 7));
+            for (pixel, original) in decoded.pixels.iter().zip(image.pixels.iter()) {
+                assert!(pixel.green.abs_diff(original.green) <= 12);
+            }
+        }
+    }
+
+    #[test]
+    fn csc411_compat_writes_the_course_format() {
+        let image = gradient(9, 5);
+        let compressed = Encoder::new().compress(&image).unwrap();
+        let (_, payload_start) = Header::read(&compressed).unwrap();
+        let compat = Encoder::new()
+            .compat(Compat::Csc411)
+            .compress(&image)
+            .unwrap();
+        let mut expected = b"Compressed image format 2\n8 4\n".to_vec();
+        expected.extend_from_slice(&compressed[payload_start..]);
+        assert_eq!(compat, expected);
+        assert_eq!(
+            decompress_image(&compat).unwrap(),
+            decompress_image(&compressed).unwrap()
+        );
+        for encoder in [
+            Encoder::new().progressive(true),
+            Encoder::new().tile_size(4),
+            Encoder::new().luma_range(0.2),
+            Encoder::new().pad(PadPolicy::Replicate),
+        ] {
+            assert!(encoder.compat(Compat::Csc411).compress(&image).is_err());
+        }
+        assert!(Encoder::new()
+            .luma_range(0.3)
+            .compat(Compat::Csc411)
+            .compress(&image)
+            .is_ok());
+    }
+
+    #[test]
+    fn the_memory_limit_is_checked_before_decoding() {
+        let compressed = compress_image(&gradient(64, 32), &EncoderOptions::default());
+        let header = Header::read(&compressed).unwrap().0;
+        let needed = decode_memory(&header, &DecodeOptions::default());
+        let limited = |max_memory| DecodeOptions {
+            max_memory: Some(max_memory),
+            ..Default::default()
+        };
+        assert!(decompress_with_options(&compressed, &limited(needed)).is_ok());
+        let error = decompress_with_options(&compressed, &limited(needed - 1)).unwrap_err();
+        assert!(error.contains("limit"), "{error}");
+        let region = DecodeOptions {
+            region: Some(Rect {
+                x: 0,
+                y: 0,
+                width: 8,
+                height: 8,
+            }),
+            ..limited(needed - 1)
+        };
+        assert!(decompress_with_options(&compressed, &region).is_ok());
+
+        let mut huge = Vec::new();
+        Header {
+            width: 1 << 30,
+            height: 1 << 30,
+            ..header.clone()
+        }
+        .write(&mut huge);
+        let error = decompress_with_options(&huge, &limited(1 << 30)).unwrap_err();
+        assert!(error.contains("limit"), "{error}");
+        let error = decompress_image(&huge).unwrap_err();
+        assert!(error.contains(&DEFAULT_MAX_MEMORY.to_string()), "{error}");
+        // Within the default limit, but with a payload far too short for its blocks.
+        let mut forged = Vec::new();
+        Header {
+            width: 4000,
+            height: 4000,
+            ..header
+        }
+        .write_image(&[&[0; 16]], &mut forged);
+        let error = decompress_image(&forged).unwrap_err();
+        assert!(error.contains("at least 16000000 bytes"), "{error}");
+    }
+
+    #[test]
+    fn mangled_inputs_are_errors_not_panics() {
+        let image = gradient(24, 18);
+        let streams = [
+            compress_image(&image, &EncoderOptions::default()),
+            Encoder::new()
+                .progressive(true)
+                .tile_size(8)
+                .region(Region::parse("0,0,8,8:90").unwrap())
+                .compress(&image)
+                .unwrap(),
+            Encoder::new()
+                .detect_content(true)
+                .tile_size(8)
+                .metadata("source", "gradient")
+                .embed_thumbnail(true)
+                .compress(&image)
+                .unwrap(),
+            b"Compressed image format 2\n4 2\n\0\0\0\0\0\0\0\0".to_vec(),
+        ];
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for stream in &streams {
+            assert_eq!(
+                decode_unchecked_input(stream).unwrap().into_image(),
+                decompress_image(stream).unwrap()
+            );
+            for _ in 0..2000 {
+                let mut mangled = stream.clone();
+                for _ in 0..1 + random() % 4 {
+                    // Half of the changes land in the header, where most of the parsing happens.
+                    let span = if random() % 2 == 0 { 48 } else { mangled.len() };
+                    let position = random() as usize % span.min(mangled.len());
+                    match random() % 3 {
+                        0 => mangled[position] = random() as u8,
+                        1 => mangled.truncate(position),
+                        _ => mangled.insert(position, random() as u8),
+                    }
+                    if mangled.is_empty() {
+                        break;
+                    }
+                }
+                let _ = decode_unchecked_input(&mangled);
+            }
+        }
+        let random_bytes: Vec<u8> = (0..64).map(|_| random() as u8).collect();
+        assert!(decode_unchecked_input(&random_bytes).is_err());
+        let header = Header::read(&streams[0]).unwrap().0;
+        for (width, height) in [(u32::MAX, 2), (2, u32::MAX), (1 << 20, 1 << 20)] {
+            let mut forged = Vec::new();
+            Header {
+                width,
+                height,
+                ..header.clone()
+            }
+            .write(&mut forged);
+            assert!(decode_unchecked_input(&forged).is_err());
+        }
+    }
+
+    #[test]
+    fn truncated_and_bit_flipped_inputs_never_panic() {
+        let image = gradient(10, 6);
+        let streams = [
+            compress_image(&image, &EncoderOptions::default()),
+            Encoder::new()
+                .chunked(true)
+                .tile_size(4)
+                .max_error(2)
+                .compress(&image)
+                .unwrap(),
+        ];
+        let decode = |bytes: &[u8]| {
+            std::panic::catch_unwind(|| decode_unchecked_input(bytes).map(|_| ())).is_ok()
+        };
+        for stream in &streams {
+            for len in 0..stream.len() {
+                assert!(decode(&stream[..len]), "Panicked on the first {len} bytes");
+            }
+            for bit in 0..stream.len() * 8 {
+                let mut flipped = stream.clone();
+                flipped[bit / 8] ^= 0x80 >> (bit % 8);
+                assert!(decode(&flipped), "Panicked with bit {bit} flipped");
+            }
+        }
+    }
+
+    #[test]
+    fn wide_words_trade_size_for_quality() {
+        let pixels = (0..16 * 12)
+            .map(|i| {
+                let value = (i % 16 * 8 + i / 16 * 4) as u16;
+                Rgb {
+                    red: value,
+                    green: value,
+                    blue: value,
+                }
+            })
+            .collect();
+        let image = RgbImage {
+            pixels,
+            width: 16,
+            height: 12,
+            denominator: 255,
+        };
+        let wide = EncoderOptions {
+            layout: WIDE_LAYOUT,
+            ..Default::default()
+        };
+        let narrow_bytes = compress_image(&image, &EncoderOptions::default());
+        let wide_bytes = compress_image(&image, &wide);
+        let header_len = |bytes: &[u8]| Header::read(bytes).unwrap().1;
+        assert_eq!(wide_bytes.len() - header_len(&wide_bytes), 48 * 8);
+        assert_eq!(narrow_bytes.len() - header_len(&narrow_bytes), 48 * 4);
+
+        let error = |bytes: &[u8]| -> i64 {
+            let decoded = decompress_image(bytes).unwrap();
+            image
+                .pixels
+                .iter()
+                .zip(decoded.pixels.iter())
+                .map(|(a, b)| (a.red as i64 - b.red as i64).abs())
+                .sum()
+        };
+        assert!(error(&wide_bytes) * 4 < error(&narrow_bytes));
+
+        let progressive = compress_image(
+            &image,
+            &EncoderOptions {
+                progressive: true,
+                ..wide
+            },
+        );
+        let from_progressive = decompress_image(&progressive).unwrap();
+        let from_sequential = decompress_image(&wide_bytes).unwrap();
+        assert!(from_progressive
+            .pixels
+            .iter()
+            .zip(from_sequential.pixels.iter())
+            .all(|(a, b)| (a.red, a.green, a.blue) == (b.red, b.green, b.blue)));
+    }
+
+    #[test]
+    fn fine_chroma_keeps_saturated_hues() {
+        let image = RgbImage {
+            pixels: (0..8 * 4)
+                .map(|_| Rgb {
+                    red: 200,
+                    green: 30,
+                    blue: 90,
+                })
+                .collect(),
+            width: 8,
+            height: 4,
+            denominator: 255,
+        };
+        let fine = EncoderOptions {
+            layout: FINE_CHROMA_LAYOUT,
+            ..Default::default()
+        };
+        let compressed = compress_image(&image, &fine);
+        assert_eq!(
+            Header::read(&compressed).unwrap().0.layout,
+            FINE_CHROMA_LAYOUT
+        );
+        let error = |options: &EncoderOptions| -> i64 {
+            let decoded = decompress_image(&compress_image(&image, options)).unwrap();
+            let pixel = &decoded.pixels[0];
+            (pixel.red as i64 - 200).abs()
+                + (pixel.green as i64 - 30).abs()
+                + (pixel.blue as i64 - 90).abs()
+        };
+        assert!(error(&fine) < error(&EncoderOptions::default()));
+    }
+
+    #[test]
+    fn chroma_tables_are_signaled_in_the_header() {
+        let image = gradient(16, 10);
+        for layout in [FAST_CHROMA_LAYOUT, FINE_TABLE_LAYOUT] {
+            let options = EncoderOptions {
+                layout,
+                ..Default::default()
+            };
+            let compressed = compress_image(&image, &options);
+            assert_eq!(Header::read(&compressed).unwrap().0.layout, layout);
+            let float = decompress_image(&compressed).unwrap();
+            let fixed = compress_image(
+                &image,
+                &EncoderOptions {
+                    arithmetic: Arithmetic::Fixed,
+                    ..options.clone()
+                },
+            );
+            let fixed = decompress_with_options(
+                &fixed,
+                &DecodeOptions {
+                    arithmetic: Arithmetic::Fixed,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            assert!(float.pixels.iter().zip(fixed.pixels.iter()).all(|(a, b)| {
+                a.red.abs_diff(b.red) <= 2
+                    && a.green.abs_diff(b.green) <= 2
+                    && a.blue.abs_diff(b.blue) <= 2
+            }));
+            assert!(Encoder::from(options)
+                .backend(Backend::Gpu)
+                .compress(&image)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn dithering_follows_the_whole_image() {
+        let image = gradient(20, 12);
+        let dither = DecodeOptions {
+            dither: true,
+            ..Default::default()
+        };
+        let plain = decompress_image(&compress_image(&image, &EncoderOptions::default())).unwrap();
+        let whole =
+            decompress_with_options(&compress_image(&image, &EncoderOptions::default()), &dither)
+                .unwrap();
+        let tiled = compress_image(
+            &image,
+            &EncoderOptions {
+                tile_size: 6,
+                ..Default::default()
+            },
+        );
+        let from_tiles = decompress_with_options(&tiled, &dither).unwrap();
+        let channels = |pixel: &Rgb| [pixel.red, pixel.green, pixel.blue];
+        // Dithering only ever rounds up the truncated value, by at most one.
+        assert!(plain.pixels.iter().zip(whole.pixels.iter()).all(|(a, b)| {
+            channels(a)
+                .iter()
+                .zip(channels(b).iter())
+                .all(|(a, b)| *b == *a || *b == *a + 1)
+        }));
+        assert!(whole
+            .pixels
+            .iter()
+            .zip(from_tiles.pixels.iter())
+            .all(|(a, b)| channels(a) == channels(b)));
+    }
+
+    #[test]
+    fn fixed_point_output_is_pinned() {
+        // FNV-1a, so that the expected values below do not depend on any floating point.
+        let fnv = |bytes: &[u8]| {
+            bytes.iter().fold(0xcbf29ce484222325_u64, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+            })
+        };
+        let fixed = EncoderOptions {
+            arithmetic: Arithmetic::Fixed,
+            ..Default::default()
+        };
+        let decode = DecodeOptions {
+            arithmetic: Arithmetic::Fixed,
+            dither: true,
+            ..Default::default()
+        };
+        let mut hashes = Vec::new();
+        for layout in [WordLayout::default(), WIDE_LAYOUT, FINE_CHROMA_LAYOUT] {
+            let options = EncoderOptions {
+                layout,
+                ..fixed.clone()
+            };
+            let compressed = compress_image(&gradient(16, 10), &options);
+            let decoded = decompress_with_options(&compressed, &decode).unwrap();
+            let pixels: Vec<u8> = decoded
+                .pixels
+                .iter()
+                .flat_map(|pixel| [pixel.red, pixel.green, pixel.blue])
+                .flat_map(u16::to_be_bytes)
+                .collect();
+            hashes.push((fnv(&compressed), fnv(&pixels)));
+        }
+        assert_eq!(
+            hashes,
+            vec![
+                (3597533641708138567, 17665294757461299580),
+                (13841178330683543801, 4629344124618217512),
+                (11581322456902688994, 12583674444869187328),
+            ]
+        );
+    }
+
+    #[test]
+    fn timings_cover_every_stage() {
+        let timings = Timings::new();
+        let image = gradient(16, 10);
+        let encoder_options = EncoderOptions {
+            arithmetic: Arithmetic::Float,
+            ..Default::default()
+        };
+        let decode_options = DecodeOptions {
+            arithmetic: Arithmetic::Float,
+            ..Default::default()
+        };
+        let (compressed, _) =
+            compress_image_with_timings(&image, &encoder_options, &timings).unwrap();
+        decompress_with_timings(&compressed, &decode_options, &timings).unwrap();
+        let stages: Vec<&str> = timings.stages().iter().map(|(name, _)| *name).collect();
+        assert_eq!(
+            stages,
+            vec![
+                "conversion",
+                "transform",
+                "quantization",
+                "packing",
+                "unpacking",
+                "assembly"
+            ]
+        );
+    }
+
+    #[test]
+    fn chunked_images_decode_like_the_flag_based_header_and_skip_unknown_chunks() {
+        use crate::format::{read_chunks, Chunk, INDEX_CHUNK, PAYLOAD_CHUNK};
+        let image =
+            crate::testkit::synthetic_image(crate::testkit::Pattern::Noise { seed: 9 }, 40, 24);
+        let encoder = Encoder::new()
+            .tile_size(16)
+            .layout(WIDE_LAYOUT)
+            .region(Region::parse("0,0,16,8:90").unwrap())
+            .metadata("source", "noise.ppm")
+            .embed_thumbnail(true);
+        let flat = encoder.clone().compress(&image).unwrap();
+        let chunked = encoder.chunked(true).compress(&image).unwrap();
+        assert_eq!(chunked[4], CHUNKED_VERSION);
+        assert_eq!(decompress_image(&chunked), decompress_image(&flat));
+        let (header, payload) = Header::locate(&chunked).unwrap();
+        let (flat_header, flat_payload) = Header::locate(&flat).unwrap();
+        assert_eq!(
+            header,
+            Header {
+                chunked: true,
+                ..flat_header
+            }
+        );
+        assert_eq!(chunked[payload.clone()], flat[flat_payload]);
+        assert_eq!(stored_length(&chunked), Some(chunked.len()));
+        assert_eq!(
+            stored_length(&chunked[..payload.start]),
+            Some(chunked.len())
+        );
+
+        let chunks = read_chunks(&chunked).unwrap();
+        let names: Vec<String> = chunks.iter().map(Chunk::name).collect();
+        assert_eq!(
+            names,
+            ["HEAD", "QUAN", "meta", "thmb", "fill", "PAYL", "INDX", "TAIL"]
+        );
+        let entry = |chunk: &Chunk| {
+            [
+                &chunk.tag[..],
+                &(chunk.offset as u32).to_be_bytes(),
+                &(chunk.len as u32).to_be_bytes(),
+            ]
+            .concat()
+        };
+        let index: Vec<u8> = chunks[..6].iter().flat_map(entry).collect();
+        assert_eq!(chunked[chunks[6].offset..chunks[6].end()], index);
+
+        // Another writer adds a chunk in front of the payload, and lists it in the index.
+        let with_chunk = |tag: &[u8; 4]| {
+            let position = |tag| chunks.iter().find(|chunk| chunk.tag == tag).unwrap().offset - 8;
+            let (payload_at, index_at) = (position(PAYLOAD_CHUNK), position(INDEX_CHUNK));
+            let mut bytes = chunked[..payload_at].to_vec();
+            bytes.extend_from_slice(tag);
+            bytes.extend_from_slice(&[0, 0, 0, 3, 1, 2, 3]);
+            bytes.extend_from_slice(&chunked[payload_at..index_at]);
+            let index: Vec<u8> = read_chunks(&bytes)
+                .unwrap()
+                .iter()
+                .flat_map(entry)
+                .collect();
+            bytes.extend_from_slice(&INDEX_CHUNK);
+            bytes.extend_from_slice(&(index.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&index);
+            bytes.extend_from_slice(&chunked[chunks[7].offset - 8..]);
+            bytes
+        };
+        assert_eq!(
+            decompress_image(&with_chunk(b"note")),
+            decompress_image(&flat)
+        );
+        assert!(decompress_image(&with_chunk(b"NOTE")).is_err());
+        assert!(decompress_image(&[&chunked[..], b"!"].concat()).is_err());
+
+        let secret = [3; 32];
+        let signed = crate::signature::sign_image(&chunked, &secret).unwrap();
+        assert_eq!(signed.len(), chunked.len() + SIGNATURE_LEN);
+        let public = crate::signature::public_key(&secret);
+        assert_eq!(crate::signature::verify_image(&signed, &public), Ok(()));
+        assert_eq!(decompress_image(&signed), decompress_image(&flat));
+    }
+
+    #[test]
+    fn chunked_tiles_start_aligned_in_mapped_files() {
+        use crate::format::{payload_offset, payload_words, PAYLOAD_ALIGNMENT};
+        let image =
+            crate::testkit::synthetic_image(crate::testkit::Pattern::Noise { seed: 4 }, 36, 20);
+        let path = std::env::temp_dir().join(format!("rpeg-aligned-{}.rpeg", std::process::id()));
+        for encoder in [
+            Encoder::new(),
+            Encoder::new().metadata("k", "odd"),
+            Encoder::new().tile_size(16).layout(WIDE_LAYOUT),
+            Encoder::new().region(Region::parse("2,2,8,8:90").unwrap()),
+        ] {
+            let flat = encoder.clone().compress(&image).unwrap();
+            let chunked = encoder.chunked(true).compress(&image).unwrap();
+            std::fs::write(&path, &chunked).unwrap();
+            let mapped = MappedFile::open(path.to_str().unwrap()).unwrap();
+            assert_eq!(payload_offset(&mapped).unwrap() % PAYLOAD_ALIGNMENT, 0);
+            let units = payload_words(&mapped).unwrap();
+            assert_eq!(units.as_ptr() as usize % PAYLOAD_ALIGNMENT, 0);
+            assert_eq!(units, payload_words(&flat).unwrap());
+            let (header, words) = read_code_words(&chunked).unwrap();
+            let first = units[..header.layout.word_bytes() / 4].concat();
+            assert!(words.contains(&header.layout.read_word(&first)));
+        }
+        std::fs::remove_file(&path).unwrap();
+        let palette = Encoder::new()
+            .palette(true)
+            .chunked(true)
+            .compress(&RgbImage {
+                pixels: vec![
+                    Rgb {
+                        red: 20,
+                        green: 90,
+                        blue: 200
+                    };
+                    32 * 24
+                ],
+                width: 32,
+                height: 24,
+                denominator: 255,
+            })
+            .unwrap();
+        assert!(Header::read(&palette).unwrap().0.palette);
+        assert!(payload_words(&palette).is_err());
+    }
 }
@@ -0,0 +1,194 @@
+use super::stats::Timings;
+use super::{block_aligned, EncoderOptions};
+use crate::conversions::{component_video_to_blocks, linearize, pixels_to_component_video};
+use crate::dct_coeff::transform_block;
+use crate::encoder::PadPolicy;
+use crate::pipeline::{chunk_starts, ordered, CHUNK_ROWS};
+use crate::ppm::{Rgb, RgbImage};
+use crate::preprocess::Preprocess;
+use crate::structs::TransformedBlock;
+use array2::array2::Array2;
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+#[derive(Debug)]
+/// ## Transform of an image shared by compressions that differ in their quantization
+///
+/// Holds the image after the steps that come before quantization, the pre-processing, the
+/// conversion to linear light, and the padding to whole blocks, and, from the first
+/// compression that needs them on, the float transforms of its 2x2 blocks, kept in chunks of
+/// `CHUNK_ROWS` rows of blocks that are each transformed when first read. Sweeping qualities,
+/// rate control, and two-pass encoding then only quantize and pack the code words again; only
+/// the rounding optimization, which compares every candidate with the pixels, converts the
+/// blocks it searches again. It fits every compression with the same `preprocess`, `srgb`,
+/// and `pad` settings; the luma range, layout, word order, tiles, and the rest may differ.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::codec::cache::CoefficientCache;
+/// use rpeg::codec::{compress_cached, compress_image, EncoderOptions};
+/// use rpeg::testkit::{synthetic_image, Pattern};
+///
+/// let image = synthetic_image(Pattern::Noise { seed: 1 }, 16, 16);
+/// let cache = CoefficientCache::new(&image, &EncoderOptions::default());
+/// for luma_range in [0.1, 0.3, 0.5] {
+///     let options = EncoderOptions { luma_range: Some(luma_range), ..Default::default() };
+///     assert!(cache.fits(&options));
+///     assert_eq!(compress_cached(&cache, &options).0, compress_image(&image, &options));
+/// }
+/// ```
+pub struct CoefficientCache<'a> {
+    input: &'a RgbImage,
+    preprocessed: Cow<'a, RgbImage>,
+    preprocess: Preprocess,
+    srgb: bool,
+    pad: PadPolicy,
+    /// Pixels on a grid of whole blocks, in linear light under `srgb`.
+    pixels: Array2<Rgb>,
+    denominator: u16,
+    /// Width and height of the image the decoder gives back.
+    decoded_size: (u32, u32),
+    /// Transforms of the blocks of every chunk of `CHUNK_ROWS` rows of blocks, once computed.
+    chunks: Vec<OnceLock<Vec<TransformedBlock>>>,
+    /// Keep the transforms for later compressions; a cache built for one compression leaves
+    /// the chunks no pass over the whole image needed to the encoder, which transforms them as
+    /// it quantizes them.
+    keep: bool,
+}
+
+impl<'a> CoefficientCache<'a> {
+    /// Returns the cache of an image for the compressions with the pre-processing, sRGB, and
+    /// padding settings of `options`. The blocks are transformed by the first compression.
+    ///
+    /// # Arguments
+    /// * `image`: Image to compress
+    /// * `options`: Settings of the compressions
+    pub fn new(image: &'a RgbImage, options: &EncoderOptions) -> Self {
+        CoefficientCache::with_timings(image, options, true, &Timings::new())
+    }
+
+    /// Returns the cache of an image like `new`, keeping the transforms for later compressions
+    /// only when `keep` is set, and adding the time spent to `timings`.
+    pub(crate) fn with_timings(
+        image: &'a RgbImage,
+        options: &EncoderOptions,
+        keep: bool,
+        timings: &Timings,
+    ) -> Self {
+        let preprocessed = if options.preprocess == Preprocess::default() {
+            Cow::Borrowed(image)
+        } else {
+            timings.time("preprocess", || options.preprocess.apply(image))
+        };
+        let linear = options
+            .srgb
+            .then(|| timings.time("linearize", || linearize(&preprocessed)));
+        let source = linear.as_ref().unwrap_or(&preprocessed);
+        let (pixels, width, height) = block_aligned(source, options.pad);
+        let chunks = chunk_starts(pixels.get_height())
+            .iter()
+            .map(|_| OnceLock::new())
+            .collect();
+        CoefficientCache {
+            input: image,
+            denominator: source.denominator,
+            preprocessed,
+            preprocess: options.preprocess,
+            srgb: options.srgb,
+            pad: options.pad,
+            pixels,
+            decoded_size: (width, height),
+            chunks,
+            keep,
+        }
+    }
+
+    /// Returns true if a compression with `options` can use the cache: if it has the same
+    /// pre-processing, sRGB, and padding settings as the cache.
+    pub fn fits(&self, options: &EncoderOptions) -> bool {
+        options.preprocess == self.preprocess
+            && options.srgb == self.srgb
+            && options.pad == self.pad
+    }
+
+    /// Returns the image as it was given.
+    pub(crate) fn input(&self) -> &RgbImage {
+        self.input
+    }
+
+    /// Returns the image after its pre-processing.
+    pub(crate) fn original(&self) -> &RgbImage {
+        &self.preprocessed
+    }
+
+    /// Returns the pixels on a grid of whole blocks, and the denominator of their densities.
+    pub(crate) fn pixels(&self) -> (&Array2<Rgb>, u16) {
+        (&self.pixels, self.denominator)
+    }
+
+    /// Returns the width and height of the image the decoder gives back.
+    pub(crate) fn decoded_size(&self) -> (u32, u32) {
+        self.decoded_size
+    }
+
+    /// Returns the transforms of the blocks of the image, in row-major block order, converting
+    /// and transforming the chunks not read yet first.
+    ///
+    /// # Arguments
+    /// * `sequential`: Process the chunks on the calling thread, one after the other
+    /// * `timings`: Timings the conversion and transform stages are added to
+    pub(crate) fn transforms(
+        &self,
+        sequential: bool,
+        timings: &Timings,
+    ) -> impl Iterator<Item = &TransformedBlock> {
+        let chunks: Vec<usize> = (0..self.chunks.len()).collect();
+        ordered(&chunks, sequential, |chunk| self.chunk(*chunk, timings))
+            .into_iter()
+            .flatten()
+    }
+
+    /// Returns the transforms of the blocks at `indices`, converting and transforming the
+    /// chunks not read yet first, or None when the cache was built for one compression and
+    /// some of these chunks were not read yet.
+    ///
+    /// # Arguments
+    /// * `indices`: Indices of the blocks, in row-major block order
+    /// * `timings`: Timings the conversion and transform stages are added to
+    pub(crate) fn blocks(
+        &self,
+        indices: &[usize],
+        timings: &Timings,
+    ) -> Option<Vec<&TransformedBlock>> {
+        let chunk_blocks = CHUNK_ROWS * (self.pixels.get_width() / 2);
+        let computed = |index: &usize| self.chunks[index / chunk_blocks].get().is_some();
+        if !self.keep && !indices.iter().all(computed) {
+            return None;
+        }
+        Some(
+            indices
+                .iter()
+                .map(|index| &self.chunk(index / chunk_blocks, timings)[index % chunk_blocks])
+                .collect(),
+        )
+    }
+
+    /// Returns the transforms of the blocks of a chunk, computing them on its first read.
+    fn chunk(&self, chunk: usize, timings: &Timings) -> &[TransformedBlock] {
+        self.chunks[chunk].get_or_init(|| {
+            let rows = self.pixels.crop(
+                0,
+                chunk * CHUNK_ROWS * 2,
+                self.pixels.get_width(),
+                CHUNK_ROWS * 2,
+            );
+            let blocks = timings.time("conversion", || {
+                component_video_to_blocks(&pixels_to_component_video(&rows, self.denominator))
+            });
+            timings.time("transform", || {
+                blocks.data.iter().map(transform_block).collect()
+            })
+        })
+    }
+}
@@ -0,0 +1,301 @@
+use super::{
+    aligned_length, check_memory, check_payload_len, check_settings, DecodeOptions, EncodeReport,
+    EncoderOptions, QuantizationStats,
+};
+use crate::conversions::dither_threshold;
+use crate::dct_coeff::{
+    clipped_transform, from_dct_to_block, masked_luma_range, quantize_block, transform_block,
+    transform_chroma_error,
+};
+use crate::encoder::Backend;
+use crate::error::{CliError, ErrorKind, RpegError};
+use crate::fixed::Arithmetic;
+use crate::format::{Header, WordOrder};
+use crate::layout::WordLayout;
+use crate::pipeline::CHUNK_ROWS;
+use crate::pixel::Pixel;
+use crate::ppm::{Rgb, RgbImage};
+use crate::preprocess::Preprocess;
+use crate::structs::{
+    Block, BlockLayout, ComponentVideo, DCTCoefficient, QuantizedBlock, TransformedBlock,
+};
+
+#[derive(Debug, Default)]
+/// ## Buffers reused by the compressions and decompressions of a service
+///
+/// Holds the planes of one chunk of `CHUNK_ROWS` rows of blocks at every stage of the pipeline:
+/// the component video of its pixels, its blocks, their transforms, and their coefficients.
+/// `codec::compress_into` and `codec::decompress_into` run the pipeline one chunk after the
+/// other through them, writing into a buffer and an image of the caller, so that repeated
+/// calls on images of the same size allocate nothing once the buffers have grown. The settings
+/// they cover, `covers` and `covers_decode` say, are those of plain code words; with the other
+/// settings, they fall back to `compress_image` and `decompress_with_options`.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::codec::scratch::ScratchBuffers;
+/// use rpeg::codec::{compress_image, compress_into, decompress_into, DecodeOptions};
+/// use rpeg::ppm::RgbImage;
+/// use rpeg::testkit::{synthetic_image, Pattern};
+///
+/// let mut scratch = ScratchBuffers::new();
+/// let mut compressed = Vec::new();
+/// let mut decoded = RgbImage { pixels: Vec::new(), width: 0, height: 0, denominator: 255 };
+/// for seed in 0..3 {
+///     let image = synthetic_image(Pattern::Noise { seed }, 16, 16);
+///     compress_into(&image, &Default::default(), &mut scratch, &mut compressed);
+///     assert_eq!(compressed, compress_image(&image, &Default::default()));
+///     decompress_into(&compressed, &DecodeOptions::default(), &mut scratch, &mut decoded)
+///         .unwrap();
+/// }
+/// ```
+pub struct ScratchBuffers {
+    /// Luma and chroma of the pixels of a chunk, in row-major pixel order.
+    component_video: Vec<ComponentVideo>,
+    /// Blocks of a chunk, in row-major block order.
+    blocks: Vec<Block>,
+    /// Transforms of the blocks of a chunk.
+    transforms: Vec<TransformedBlock>,
+    /// Quantized coefficients of the blocks of a chunk, or those unpacked from its code words.
+    coefficients: Vec<DCTCoefficient>,
+    /// Layout of the code words of the last compression, which was verified then.
+    verified: Option<WordLayout>,
+}
+
+impl ScratchBuffers {
+    /// Returns empty buffers, which grow to the size of a chunk on their first use.
+    pub fn new() -> Self {
+        ScratchBuffers::default()
+    }
+
+    /// Returns true if the compressions with `options` go through the buffers: those of
+    /// sequential code words of the whole image in floating point on the CPU, untiled, without
+    /// regions of interest, and with none of the steps that need the whole image at once, such
+    /// as pre-processing, two-pass encoding, rounding optimization, or a max error.
+    ///
+    /// # Arguments
+    /// * `options`: Settings of the compressions
+    pub fn covers(options: &EncoderOptions) -> bool {
+        !options.progressive
+            && options.regions.is_empty()
+            && options.tile_size == 0
+            && !options.optimize
+            && options.arithmetic == Arithmetic::Float
+            && options.backend == Backend::Cpu
+            && !options.embed_thumbnail
+            && !options.two_pass
+            && !options.palette
+            && !options.detect_content
+            && options.preprocess == Preprocess::default()
+            && !options.srgb
+            && options.compat.is_none()
+            && !options.stores_raw()
+            && !options.chunked
+            && !options.chroma_priority
+            && options.max_error.is_none()
+            && options.target.is_none()
+    }
+
+    /// Returns true if the decompressions with `options` of the images of `header` go through
+    /// the buffers: those of sequential code words of 8-bit images, untiled and without regions
+    /// of interest or refinement, decoded whole in floating point with at most `dither`.
+    ///
+    /// # Arguments
+    /// * `header`: Header of the compressed image
+    /// * `options`: Settings of the decompressions
+    pub fn covers_decode(header: &Header, options: &DecodeOptions) -> bool {
+        header.order == WordOrder::Sequential
+            && header.tile_size == 0
+            && header.region_qualities.is_empty()
+            && !header.palette
+            && header.tile_modes.is_empty()
+            && !header.srgb
+            && header.hdr_exponent.is_none()
+            && header.refinement.is_none()
+            && !options.preview
+            && options.region.is_none()
+            && !options.deblock
+            && options.arithmetic == Arithmetic::Float
+            && options.adjustments.is_empty()
+            && options.tint.is_none()
+    }
+
+    /// Verifies `layout` like `WordLayout::verify`, unless it is the layout verified last.
+    pub(crate) fn verify(&mut self, layout: &WordLayout) -> Result<(), String> {
+        if self.verified != Some(*layout) {
+            layout.verify()?;
+            self.verified = Some(*layout);
+        }
+        Ok(())
+    }
+
+    /// Compresses an image whose settings `covers` accepts into `output`, replacing what it
+    /// held, and returns its quantization report, or an error like `check_encodable` if the
+    /// settings cannot be combined.
+    ///
+    /// # Arguments
+    /// * `image`: Image to compress
+    /// * `options`: Settings used to compress the image
+    /// * `output`: Buffer receiving the compressed image
+    pub(crate) fn compress(
+        &mut self,
+        image: &RgbImage,
+        options: &EncoderOptions,
+        output: &mut Vec<u8>,
+    ) -> Result<EncodeReport, RpegError> {
+        self.verify(&options.layout)
+            .map_err(|message| CliError::new(ErrorKind::BadArguments, message))?;
+        let luma_range = check_settings(options)?;
+        let (width, height) = (image.width as usize, image.height as usize);
+        let (coded_width, decoded_width) = aligned_length(width, options.pad);
+        let (coded_height, decoded_height) = aligned_length(height, options.pad);
+        let header = Header {
+            width: decoded_width,
+            height: decoded_height,
+            order: WordOrder::Sequential,
+            region_qualities: Vec::new(),
+            tile_size: 0,
+            luma_range,
+            layout: options.layout,
+            metadata: options.metadata.clone(),
+            thumbnail: None,
+            perceptual: options.perceptual,
+            palette: false,
+            tile_modes: Vec::new(),
+            srgb: false,
+            hdr_exponent: None,
+            signed: false,
+            chunked: false,
+            refinement: None,
+        };
+        let (layout, range) = (&header.layout, header.background_range());
+        let denominator = image.denominator as f64;
+        let ScratchBuffers {
+            component_video,
+            blocks,
+            transforms,
+            coefficients,
+            ..
+        } = self;
+        output.clear();
+        header.write(output);
+        output.reserve(header.block_count() * layout.word_bytes());
+        let mut stats = QuantizationStats::default();
+        let columns = coded_width / 2;
+        for row in (0..coded_height).step_by(CHUNK_ROWS * 2) {
+            if options.is_cancelled() {
+                break;
+            }
+            let rows = (CHUNK_ROWS * 2).min(coded_height - row);
+            // Block-linear, as `pixels_to_component_video` stores the pixels.
+            component_video.clear();
+            component_video.extend((0..rows * columns * 2).map(|index| {
+                let (block, corner) = (index / 4, index % 4);
+                let (x, y) = BlockLayout::RowMajor.offset(corner);
+                let x = (block % columns * 2 + x).min(width - 1);
+                let y = (row + block / columns * 2 + y).min(height - 1);
+                image.pixels[y * width + x].to_ypbpr(denominator)
+            }));
+            blocks.clear();
+            blocks.extend(component_video.chunks_exact(4).map(|pixels| {
+                Block::from_pixels(pixels.try_into().unwrap(), BlockLayout::RowMajor)
+            }));
+            transforms.clear();
+            transforms.extend(blocks.iter().map(transform_block));
+            // The statistics add up chunk by chunk, in the order of `compress_image`.
+            let mut chunk_stats = QuantizationStats::default();
+            coefficients.clear();
+            coefficients.extend(transforms.iter().map(|transform| {
+                let (coefficient, range) =
+                    quantize_block(transform, range, options.perceptual, layout, None);
+                let indices = (coefficient.index_of_pb, coefficient.index_of_pr);
+                chunk_stats.clipped += clipped_transform(transform, range);
+                chunk_stats.chroma_error += transform_chroma_error(transform, indices, layout);
+                chunk_stats.blocks += 1;
+                coefficient
+            }));
+            stats = stats + chunk_stats;
+            for coefficient in coefficients.iter() {
+                layout.write_word(layout.pack(&QuantizedBlock::from(coefficient)), output);
+            }
+        }
+        Ok(stats.report(header.block_count() * 3))
+    }
+
+    /// Decompresses the payload of an image whose header and settings `covers_decode` accepts
+    /// into `image`, replacing what it held. Returns an error if the payload does not hold
+    /// exactly one code word per block, or the image needs more memory than `options` allows.
+    ///
+    /// # Arguments
+    /// * `header`: Header of the compressed image
+    /// * `payload`: Code words of the image
+    /// * `options`: Settings used to decompress the image
+    /// * `image`: Image receiving the decoded pixels
+    pub(crate) fn decompress(
+        &mut self,
+        header: &Header,
+        payload: &[u8],
+        options: &DecodeOptions,
+        image: &mut RgbImage,
+    ) -> Result<(), String> {
+        check_memory(header, options)?;
+        check_payload_len(header, payload.len())?;
+        let layout = &header.layout;
+        let expected_len = header.block_count() * layout.word_bytes();
+        if payload.len() != expected_len {
+            return Err(format!(
+                "Expected {expected_len} bytes of compressed data, found {}",
+                payload.len()
+            ));
+        }
+        let range = header.background_range();
+        let (width, height) = (header.width as usize, header.height as usize);
+        let columns = header.coded_width() as usize / 2;
+        let chunk_blocks = CHUNK_ROWS * columns;
+        image.width = header.width;
+        image.height = header.height;
+        image.denominator = 255;
+        image.pixels.clear();
+        image.pixels.resize(width * height, Rgb::default());
+        let ScratchBuffers {
+            blocks,
+            coefficients,
+            ..
+        } = self;
+        let chunks = payload.chunks(chunk_blocks.max(1) * layout.word_bytes());
+        for (chunk, words) in chunks.enumerate() {
+            coefficients.clear();
+            coefficients.extend(
+                words
+                    .chunks_exact(layout.word_bytes())
+                    .map(|word| DCTCoefficient::from(layout.unpack(layout.read_word(word)))),
+            );
+            blocks.clear();
+            blocks.extend(coefficients.iter().map(|coefficient| {
+                let range = if header.perceptual {
+                    masked_luma_range(range, coefficient.a, layout)
+                } else {
+                    range
+                };
+                from_dct_to_block(coefficient, range, layout)
+            }));
+            for (index, block) in blocks.iter().enumerate() {
+                let index = chunk * chunk_blocks + index;
+                let (column, row) = (index % columns, index / columns);
+                for (corner, pixel) in block.pixels(BlockLayout::RowMajor).iter().enumerate() {
+                    let (x, y) = BlockLayout::RowMajor.offset(corner);
+                    let (x, y) = (column * 2 + x, row * 2 + y);
+                    if x < width && y < height {
+                        image.pixels[y * width + x] = if options.dither {
+                            Rgb::from_ypbpr_dithered(pixel, dither_threshold(x, y))
+                        } else {
+                            Rgb::from_ypbpr(pixel)
+                        };
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct Recorded {
+    stages: Vec<(&'static str, Duration)>,
+    total: Duration,
+    bytes: usize,
+}
+
+#[derive(Debug, Default)]
+/// ## Time spent in every stage of a compression or decompression
+///
+/// Stages are recorded in the order they first run. Tiles are processed in parallel, so the
+/// time of a stage is summed over every tile and the stages can add up to more than `total`,
+/// the wall-clock time of the whole run. `bytes` is the size of the uncompressed pixels,
+/// three bytes per pixel, from which the throughput follows.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::codec::stats::Timings;
+///
+/// let timings = Timings::new();
+/// let sum = timings.time("packing", || (0..100).sum::<u32>());
+/// assert_eq!(sum, 4950);
+/// assert!(timings.stage("packing").is_some());
+/// ```
+pub struct Timings {
+    recorded: Mutex<Recorded>,
+}
+
+impl Timings {
+    /// Returns an empty set of timings.
+    pub fn new() -> Self {
+        Timings::default()
+    }
+
+    /// Runs `work` and adds the time it took to `stage`.
+    ///
+    /// # Arguments
+    /// * `stage`: Name of the stage
+    /// * `work`: Work done by the stage
+    pub fn time<T>(&self, stage: &'static str, work: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = work();
+        self.record(stage, start.elapsed());
+        result
+    }
+
+    /// Adds `duration` to the time spent in `stage`.
+    pub fn record(&self, stage: &'static str, duration: Duration) {
+        let mut recorded = self.recorded.lock().unwrap();
+        match recorded.stages.iter_mut().find(|(name, _)| *name == stage) {
+            Some((_, time)) => *time += duration,
+            None => recorded.stages.push((stage, duration)),
+        }
+    }
+
+    /// Records the wall-clock time of the whole run and the size of the uncompressed pixels.
+    pub fn finish(&self, total: Duration, bytes: usize) {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.total = total;
+        recorded.bytes = bytes;
+    }
+
+    /// Returns every stage with the time spent in it, in the order they first ran.
+    pub fn stages(&self) -> Vec<(&'static str, Duration)> {
+        self.recorded.lock().unwrap().stages.clone()
+    }
+
+    /// Returns the time spent in `stage`, if it ran.
+    pub fn stage(&self, stage: &str) -> Option<Duration> {
+        self.stages()
+            .into_iter()
+            .find(|(name, _)| *name == stage)
+            .map(|(_, time)| time)
+    }
+
+    /// Returns the wall-clock time of the whole run.
+    pub fn total(&self) -> Duration {
+        self.recorded.lock().unwrap().total
+    }
+
+    /// Returns the throughput of the whole run, in megabytes of uncompressed pixels per second.
+    pub fn megabytes_per_second(&self) -> f64 {
+        let recorded = self.recorded.lock().unwrap();
+        recorded.bytes as f64 / 1e6 / recorded.total.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+impl fmt::Display for Timings {
+    /// Writes one line per stage with its time and share of the time of every stage, followed
+    /// by the total and the throughput.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stages = self.stages();
+        let sum: Duration = stages.iter().map(|(_, time)| *time).sum();
+        for (name, time) in stages.iter() {
+            writeln!(
+                f,
+                "{name:<12}{:>10.2} ms{:>7.1}%",
+                time.as_secs_f64() * 1e3,
+                time.as_secs_f64() * 100.0 / sum.as_secs_f64().max(f64::MIN_POSITIVE)
+            )?;
+        }
+        let bytes = self.recorded.lock().unwrap().bytes;
+        write!(
+            f,
+            "{:<12}{:>10.2} ms  {:.2} MB at {:.1} MB/s",
+            "total",
+            self.total().as_secs_f64() * 1e3,
+            bytes as f64 / 1e6,
+            self.megabytes_per_second()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_accumulate_in_order() {
+        let timings = Timings::new();
+        timings.record("conversion", Duration::from_millis(3));
+        timings.record("packing", Duration::from_millis(1));
+        timings.record("conversion", Duration::from_millis(2));
+        timings.finish(Duration::from_millis(10), 5_000_000);
+        assert_eq!(
+            timings.stages(),
+            vec![
+                ("conversion", Duration::from_millis(5)),
+                ("packing", Duration::from_millis(1))
+            ]
+        );
+        assert!((timings.megabytes_per_second() - 500.0).abs() < 1e-6);
+        assert!(timings.to_string().contains("conversion"));
+    }
+}
@@ -0,0 +1,221 @@
+/// Metadata key of a named color space.
+pub const COLOR_SPACE_KEY: &str = "color-space";
+
+/// Metadata key of an ICC profile, whose value is the profile in base64.
+pub const ICC_PROFILE_KEY: &str = "icc-profile";
+
+/// Largest ICC profile that fits in a metadata value once it is encoded in base64.
+pub const MAX_ICC_PROFILE_LEN: usize = crate::format::MAX_METADATA_LEN / 4 * 3;
+
+/// Alphabet of the standard base64 encoding.
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Named color space the densities of an image are encoded in
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::color_tag::ColorSpace;
+///
+/// assert_eq!(ColorSpace::from_name("display-p3"), Some(ColorSpace::DisplayP3));
+/// assert_eq!(ColorSpace::Rec2020.name(), "rec2020");
+/// ```
+pub enum ColorSpace {
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+    Rec2020,
+}
+
+impl ColorSpace {
+    /// Every color space, in the order `--color-space` lists them.
+    pub const ALL: [ColorSpace; 4] = [
+        ColorSpace::Srgb,
+        ColorSpace::DisplayP3,
+        ColorSpace::AdobeRgb,
+        ColorSpace::Rec2020,
+    ];
+
+    /// Returns the color space called `name`: srgb, display-p3, adobe-rgb, or rec2020.
+    ///
+    /// # Arguments
+    /// * `name`: Name of the color space, as given to `--color-space`
+    pub fn from_name(name: &str) -> Option<ColorSpace> {
+        ColorSpace::ALL
+            .into_iter()
+            .find(|space| space.name() == name)
+    }
+
+    /// Returns the name of the color space.
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorSpace::Srgb => "srgb",
+            ColorSpace::DisplayP3 => "display-p3",
+            ColorSpace::AdobeRgb => "adobe-rgb",
+            ColorSpace::Rec2020 => "rec2020",
+        }
+    }
+
+    /// Returns the CIE xy chromaticities of the white point and of the red, green, and blue
+    /// primaries of the color space.
+    pub fn chromaticities(self) -> [(f32, f32); 4] {
+        let d65 = (0.3127, 0.3290);
+        match self {
+            ColorSpace::Srgb => [d65, (0.64, 0.33), (0.30, 0.60), (0.15, 0.06)],
+            ColorSpace::DisplayP3 => [d65, (0.680, 0.320), (0.265, 0.690), (0.150, 0.060)],
+            ColorSpace::AdobeRgb => [d65, (0.64, 0.33), (0.21, 0.71), (0.15, 0.06)],
+            ColorSpace::Rec2020 => [d65, (0.708, 0.292), (0.170, 0.797), (0.131, 0.046)],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Color space an image is tagged with
+///
+/// The tag is kept in the metadata of the header, under `COLOR_SPACE_KEY` for a named color
+/// space or `ICC_PROFILE_KEY` for an ICC profile, so that files without one are unchanged. rpeg
+/// does not convert the pixels: the tag only travels with them, and decoding to PNG writes it
+/// back as an `sRGB`, `cHRM` and `gAMA`, or `iCCP` chunk.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::color_tag::{ColorSpace, ColorTag};
+///
+/// let profile = ColorTag::Icc(vec![0, 1, 2, 254, 255]);
+/// let metadata = vec![("source".to_string(), "scan.ppm".to_string())];
+/// let metadata = profile.replace_in(&metadata);
+/// assert_eq!(ColorTag::from_metadata(&metadata), Ok(Some(profile)));
+/// let named = ColorTag::Space(ColorSpace::DisplayP3).replace_in(&metadata);
+/// assert_eq!(named[1], ("color-space".to_string(), "display-p3".to_string()));
+/// assert_eq!(named.len(), 2);
+/// ```
+pub enum ColorTag {
+    Space(ColorSpace),
+    Icc(Vec<u8>),
+}
+
+impl ColorTag {
+    /// Returns the metadata entry holding the tag. The value of an ICC profile larger than
+    /// `MAX_ICC_PROFILE_LEN` bytes is too long for the header.
+    pub fn to_metadata(&self) -> (String, String) {
+        match self {
+            ColorTag::Space(space) => (COLOR_SPACE_KEY.to_string(), space.name().to_string()),
+            ColorTag::Icc(profile) => (ICC_PROFILE_KEY.to_string(), encode_base64(profile)),
+        }
+    }
+
+    /// Returns `metadata` with the tag in place of any tag it held before.
+    ///
+    /// # Arguments
+    /// * `metadata`: Metadata of a header
+    pub fn replace_in(&self, metadata: &[(String, String)]) -> Vec<(String, String)> {
+        metadata
+            .iter()
+            .filter(|(key, _)| key != COLOR_SPACE_KEY && key != ICC_PROFILE_KEY)
+            .cloned()
+            .chain([self.to_metadata()])
+            .collect()
+    }
+
+    /// Returns the tag stored in `metadata`, or None if there is none. An ICC profile wins over
+    /// a named color space. Returns an error if the stored tag is malformed.
+    ///
+    /// # Arguments
+    /// * `metadata`: Metadata of a header
+    pub fn from_metadata(metadata: &[(String, String)]) -> Result<Option<ColorTag>, String> {
+        let value = |key: &str| {
+            metadata
+                .iter()
+                .find(|(entry, _)| entry == key)
+                .map(|(_, value)| value)
+        };
+        if let Some(profile) = value(ICC_PROFILE_KEY) {
+            return decode_base64(profile)
+                .map(|profile| Some(ColorTag::Icc(profile)))
+                .ok_or("The ICC profile of the header is not valid base64".to_string());
+        }
+        match value(COLOR_SPACE_KEY) {
+            Some(name) => ColorSpace::from_name(name)
+                .map(|space| Some(ColorTag::Space(space)))
+                .ok_or(format!("Unknown color space {name}")),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Returns `bytes` in base64, padded with `=`.
+///
+/// # Arguments
+/// * `bytes`: Bytes to encode
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0_u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Returns the bytes of padded base64 `text`, or None if it is malformed.
+///
+/// # Arguments
+/// * `text`: Text to decode
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    for (index, chunk) in text.chunks(4).enumerate() {
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && index + 1 != text.len() / 4) {
+            return None;
+        }
+        let mut group = 0_u32;
+        for c in &chunk[..4 - padding] {
+            let value = BASE64.iter().position(|letter| letter == c)?;
+            group = group << 6 | value as u32;
+        }
+        group <<= 6 * padding;
+        bytes.extend_from_slice(&group.to_be_bytes()[1..4 - padding]);
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_matches_the_standard_encoding() {
+        for (bytes, text) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(encode_base64(bytes), text);
+            assert_eq!(decode_base64(text).as_deref(), Some(bytes));
+        }
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode_base64(&encode_base64(&all)), Some(all));
+        assert_eq!(decode_base64("Zg=a"), None);
+        assert_eq!(decode_base64("Zg==Zm8="), None);
+        assert_eq!(decode_base64("Z!=="), None);
+        let unknown = [(COLOR_SPACE_KEY.to_string(), "cmyk".to_string())];
+        assert!(ColorTag::from_metadata(&unknown).is_err());
+        let largest = ColorTag::Icc(vec![0; MAX_ICC_PROFILE_LEN]).to_metadata();
+        assert!(largest.1.len() <= crate::format::MAX_METADATA_LEN);
+    }
+}
@@ -0,0 +1,457 @@
+use crate::error::ErrorKind;
+
+/// Subcommands that are also reached through a short flag, merged into one command.
+const ALIASES: [(&str, &str); 2] = [("-c", "compress"), ("-d", "decompress")];
+
+/// Failures listed in the man page, with what causes them.
+const EXIT_STATUSES: [(ErrorKind, &str); 8] = [
+    (
+        ErrorKind::BadArguments,
+        "Bad arguments: unknown command, missing or invalid flag value.",
+    ),
+    (
+        ErrorKind::UnreadableInput,
+        "Unreadable input: missing file, or invalid PPM or PNG image.",
+    ),
+    (
+        ErrorKind::CorruptStream,
+        "Corrupt stream: compressed image, archive, or multi-frame stream that cannot be decoded.",
+    ),
+    (
+        ErrorKind::UnsupportedVersion,
+        "Unsupported version: file written by a newer version of rpeg.",
+    ),
+    (
+        ErrorKind::Io,
+        "I/O error: output that cannot be written, or that exists without --force.",
+    ),
+    (
+        ErrorKind::PoorRatio,
+        "Poor ratio: image compressed less than --min-ratio, with --ratio-policy fail.",
+    ),
+    (
+        ErrorKind::BadSignature,
+        "Bad signature: unsigned image, or signature not matching the image and --pubkey.",
+    ),
+    (
+        ErrorKind::Cancelled,
+        "Cancelled: compression interrupted by SIGINT or SIGTERM.",
+    ),
+];
+
+/// Placeholders of the usage text standing for the flags of `-c`, of `-d`, or of both.
+const FLAG_GROUPS: [(&str, &[&str]); 3] = [
+    ("[compression flags]", &["-c"]),
+    ("[decompression flags]", &["-d"]),
+    ("[compression and decompression flags]", &["-c", "-d"]),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Shell `rpeg completions` writes a completion script for
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::completions::Shell;
+///
+/// assert_eq!(Shell::from_name("zsh"), Some(Shell::Zsh));
+/// assert_eq!(Shell::from_name("tcsh"), None);
+/// ```
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    /// Returns the shell called `name`: bash, zsh, or fish.
+    ///
+    /// # Arguments
+    /// * `name`: Name of the shell, as given to `rpeg completions`
+    pub fn from_name(name: &str) -> Option<Shell> {
+        match name {
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// ## Flag of a command, as found in the usage text
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::completions::parse_usage;
+///
+/// let commands = parse_usage("rpeg info [--pad trim|replicate] [filename]");
+/// assert_eq!(commands[0].flags[0].name, "--pad");
+/// assert_eq!(commands[0].flags[0].choices, ["trim", "replicate"]);
+/// ```
+pub struct Flag {
+    /// Name of the flag, with its dashes.
+    pub name: String,
+    /// Values the flag accepts, if the usage lists them.
+    pub choices: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// ## Subcommand of rpeg, as found in the usage text
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::completions::parse_usage;
+///
+/// let commands = parse_usage("rpeg -c [--wide] [filename]\nrpeg compress --frames f_%04d.ppm");
+/// assert_eq!(commands[0].names, ["-c", "compress"]);
+/// assert_eq!(commands[0].flags.len(), 2);
+/// ```
+pub struct Command {
+    /// Names the command is reached with.
+    pub names: Vec<String>,
+    /// Words that can follow the name, such as the actions of `rpeg archive`.
+    pub actions: Vec<String>,
+    /// Flags of the command, in the order the usage gives them.
+    pub flags: Vec<Flag>,
+    /// Lines of the usage text describing the command.
+    pub synopsis: Vec<String>,
+}
+
+/// Returns the words of `choices`, such as `fast|balanced|best]`, if it only holds plain words,
+/// separated by bars.
+fn words_of(choices: &str) -> Option<Vec<String>> {
+    let choices = choices.trim_end_matches(']');
+    let words: Vec<String> = choices.split('|').map(str::to_string).collect();
+    let plain = |word: &String| {
+        word.starts_with(|char: char| char.is_ascii_alphanumeric())
+            && word
+                .chars()
+                .all(|char| char == '-' || char.is_ascii_lowercase() || char.is_ascii_digit())
+    };
+    (words.iter().all(plain)).then_some(words)
+}
+
+/// Returns the commands of a usage text: every line starting with `rpeg` describes a command,
+/// commands reached through `-c` or `-d` are merged with their long names, and the flags of
+/// `[compression flags]` and the like are those of `-c` and `-d`. A flag mentioned on a line
+/// that does not start with `rpeg` is given to every command.
+///
+/// # Arguments
+/// * `usage`: Usage text of rpeg
+pub fn parse_usage(usage: &str) -> Vec<Command> {
+    let mut commands: Vec<Command> = Vec::new();
+    let mut groups: Vec<(usize, &[&str])> = Vec::new();
+    let mut global_flags = Vec::new();
+    for line in usage.lines() {
+        let line = line.trim_start_matches("Usage: ");
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let flags = tokens.iter().enumerate().filter_map(|(index, token)| {
+            let name = token
+                .trim_start_matches(['[', '('])
+                .trim_end_matches([']', ')', '.', ',']);
+            let is_flag = name.starts_with('-')
+                && name
+                    .trim_start_matches('-')
+                    .starts_with(|char: char| char.is_ascii_lowercase())
+                && name
+                    .chars()
+                    .all(|char| char == '-' || char.is_ascii_alphanumeric());
+            let choices = tokens
+                .get(index + 1)
+                .filter(|_| !token.ends_with(']'))
+                .and_then(|next| words_of(next))
+                .filter(|choices| choices.len() > 1)
+                .unwrap_or_default();
+            is_flag.then(|| Flag {
+                name: name.to_string(),
+                choices,
+            })
+        });
+        if tokens.first() != Some(&"rpeg") {
+            global_flags.extend(flags);
+            continue;
+        }
+        let Some(name) = tokens.get(1) else {
+            continue;
+        };
+        let name = ALIASES
+            .iter()
+            .find(|(_, long)| long == name)
+            .map_or(*name, |(short, _)| *short);
+        let index = match commands.iter().position(|command| command.names[0] == name) {
+            Some(index) => index,
+            None => {
+                let mut names = vec![name.to_string()];
+                names.extend(
+                    ALIASES
+                        .iter()
+                        .filter(|(short, _)| *short == name)
+                        .map(|(_, long)| long.to_string()),
+                );
+                commands.push(Command {
+                    names,
+                    ..Default::default()
+                });
+                commands.len() - 1
+            }
+        };
+        let command = &mut commands[index];
+        command.synopsis.push(line.to_string());
+        for flag in flags.skip(usize::from(tokens[1].starts_with('-'))) {
+            if !command.flags.iter().any(|known| known.name == flag.name) {
+                command.flags.push(flag);
+            }
+        }
+        if let Some(actions) = tokens.get(2).and_then(|token| words_of(token)) {
+            for action in actions {
+                if !command.actions.contains(&action) {
+                    command.actions.push(action);
+                }
+            }
+        }
+        for (placeholder, sources) in FLAG_GROUPS {
+            if line.contains(placeholder) {
+                groups.push((index, sources));
+            }
+        }
+    }
+    for (index, sources) in groups {
+        let inherited: Vec<Flag> = commands
+            .iter()
+            .filter(|command| sources.contains(&command.names[0].as_str()))
+            .flat_map(|command| command.flags.clone())
+            .collect();
+        for flag in inherited {
+            if !commands[index]
+                .flags
+                .iter()
+                .any(|known| known.name == flag.name)
+            {
+                commands[index].flags.push(flag);
+            }
+        }
+    }
+    for command in &mut commands {
+        for flag in &global_flags {
+            if !command.flags.iter().any(|known| known.name == flag.name) {
+                command.flags.push(flag.clone());
+            }
+        }
+    }
+    commands
+}
+
+/// Returns the names of the flags of `command`, separated by spaces.
+fn flag_names(command: &Command) -> String {
+    let names: Vec<&str> = command
+        .flags
+        .iter()
+        .map(|flag| flag.name.as_str())
+        .collect();
+    names.join(" ")
+}
+
+/// Returns a bash completion script, completing the commands, their actions and flags, the
+/// values of flags that take one of a few words, and file names otherwise.
+fn bash(commands: &[Command]) -> String {
+    let names: Vec<&str> = commands
+        .iter()
+        .flat_map(|command| command.names.iter().map(String::as_str))
+        .collect();
+    let mut script = String::from("_rpeg() {\n    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\" words=\"\"\n    if [[ $COMP_CWORD -eq 1 ]]; then\n");
+    script += &format!(
+        "        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n        return\n    fi\n    case \"$prev\" in\n",
+        names.join(" ")
+    );
+    let mut choices: Vec<&Flag> = Vec::new();
+    for flag in commands.iter().flat_map(|command| &command.flags) {
+        if !flag.choices.is_empty() && !choices.iter().any(|known| known.name == flag.name) {
+            choices.push(flag);
+        }
+    }
+    for flag in choices {
+        script += &format!(
+            "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n",
+            flag.name,
+            flag.choices.join(" ")
+        );
+    }
+    script += "    esac\n    case \"${COMP_WORDS[1]}\" in\n";
+    for command in commands {
+        let mut words = flag_names(command);
+        if !command.actions.is_empty() {
+            words = format!("{} {words}", command.actions.join(" "));
+        }
+        script += &format!(
+            "        {}) words=\"{words}\" ;;\n",
+            command.names.join("|")
+        );
+    }
+    script += "    esac\n    COMPREPLY=($(compgen -W \"$words\" -- \"$cur\") $(compgen -f -- \"$cur\"))\n}\ncomplete -o filenames -F _rpeg rpeg\n";
+    script
+}
+
+/// Returns a zsh completion script, completing the commands, their actions and flags, and file
+/// names.
+fn zsh(commands: &[Command]) -> String {
+    let names: Vec<&str> = commands
+        .iter()
+        .flat_map(|command| command.names.iter().map(String::as_str))
+        .collect();
+    let mut script = format!(
+        "#compdef rpeg\n\n_rpeg() {{\n    if (( CURRENT == 2 )); then\n        compadd -- {}\n        return\n    fi\n    case $words[2] in\n",
+        names.join(" ")
+    );
+    for command in commands {
+        let mut words = flag_names(command);
+        if !command.actions.is_empty() {
+            words = format!("{} {words}", command.actions.join(" "));
+        }
+        script += &format!(
+            "        {}) compadd -- {words} ;;\n",
+            command.names.join("|")
+        );
+    }
+    script += "    esac\n    _files\n}\n\n_rpeg \"$@\"\n";
+    script
+}
+
+/// Returns a fish completion script, completing the commands, their actions and flags, and
+/// the values of flags that take one of a few words.
+fn fish(commands: &[Command]) -> String {
+    let names: Vec<&str> = commands
+        .iter()
+        .flat_map(|command| command.names.iter().map(String::as_str))
+        .collect();
+    let mut script = format!(
+        "complete -c rpeg -n \"__fish_is_nth_token 1\" -f -a \"{}\"\n",
+        names.join(" ")
+    );
+    for command in commands {
+        let condition = format!("__fish_seen_subcommand_from {}", command.names.join(" "));
+        if !command.actions.is_empty() {
+            script += &format!(
+                "complete -c rpeg -n \"{condition}\" -f -a \"{}\"\n",
+                command.actions.join(" ")
+            );
+        }
+        for flag in &command.flags {
+            let option = match flag.name.strip_prefix("--") {
+                Some(long) => format!("-l {long}"),
+                None => format!("-s {}", flag.name.trim_start_matches('-')),
+            };
+            let values = match flag.choices.as_slice() {
+                [] => String::new(),
+                choices => format!(" -x -a \"{}\"", choices.join(" ")),
+            };
+            script += &format!("complete -c rpeg -n \"{condition}\" {option}{values}\n");
+        }
+    }
+    script
+}
+
+/// Returns the completion script of `shell` for the commands and flags of `usage`.
+///
+/// # Arguments
+/// * `usage`: Usage text of rpeg
+/// * `shell`: Shell the script is written for
+pub fn completions(usage: &str, shell: Shell) -> String {
+    let commands = parse_usage(usage);
+    match shell {
+        Shell::Bash => bash(&commands),
+        Shell::Zsh => zsh(&commands),
+        Shell::Fish => fish(&commands),
+    }
+}
+
+/// Returns `text` with the characters roff gives a meaning to escaped.
+fn roff(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with(['.', '\'']) {
+        format!("\\&{text}")
+    } else {
+        text
+    }
+}
+
+/// Returns a man page in roff, with the synopsis, flags, and actions of every command of
+/// `usage`, and the exit statuses of the failures.
+///
+/// # Arguments
+/// * `usage`: Usage text of rpeg
+/// * `version`: Version of rpeg, shown in the footer
+pub fn man_page(usage: &str, version: &str) -> String {
+    let commands = parse_usage(usage);
+    let mut page = format!(
+        ".TH RPEG 1 \"\" \"rpeg {version}\" \"User Commands\"\n.SH NAME\nrpeg \\- lossy image compression in 2x2 blocks\n.SH SYNOPSIS\n"
+    );
+    for line in commands.iter().flat_map(|command| &command.synopsis) {
+        page += &format!(".B {}\n.br\n", roff(line));
+    }
+    page += ".SH COMMANDS\n";
+    for command in commands {
+        let names: Vec<String> = command.names.iter().map(|name| roff(name)).collect();
+        page += &format!(".TP\n.B {}\n", names.join(", "));
+        if !command.actions.is_empty() {
+            page += &format!("Actions: {}.\n.br\n", command.actions.join(", "));
+        }
+        let flags: Vec<String> = command
+            .flags
+            .iter()
+            .map(|flag| match flag.choices.as_slice() {
+                [] => format!("\\fB{}\\fR", roff(&flag.name)),
+                choices => format!("\\fB{}\\fR {}", roff(&flag.name), choices.join("|")),
+            })
+            .collect();
+        page += &format!("Flags: {}.\n", flags.join(", "));
+    }
+    page += ".SH EXIT STATUS\n.TP\n.B 0\nSuccess.\n.TP\n.B 1\nThe images given to \\fBrpeg diff\\fR differ.\n";
+    for (kind, description) in EXIT_STATUSES {
+        page += &format!(".TP\n.B {}\n{}\n", kind.exit_code(), roff(description));
+    }
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USAGE: &str = "Usage: rpeg -d [--dither] [filename]
+rpeg -c [--pad trim|replicate] [-o output [--force]] [filename]
+rpeg compress --frames frame_%04d.ppm
+rpeg archive add [compression flags] archive image.ppm...
+rpeg archive list|extract [archive]
+Every command accepts --json-errors.";
+
+    #[test]
+    fn usage_gives_commands_flags_and_scripts() {
+        let commands = parse_usage(USAGE);
+        let names: Vec<&[String]> = commands.iter().map(|c| c.names.as_slice()).collect();
+        assert_eq!(
+            names,
+            [&["-d", "decompress"][..], &["-c", "compress"], &["archive"]]
+        );
+        let compress: Vec<&str> = commands[1].flags.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            compress,
+            ["--pad", "-o", "--force", "--frames", "--json-errors"]
+        );
+        assert_eq!(commands[1].flags[0].choices, ["trim", "replicate"]);
+        assert_eq!(commands[2].actions, ["add", "list", "extract"]);
+        assert!(commands[2].flags.iter().any(|flag| flag.name == "--pad"));
+
+        let bash = completions(USAGE, Shell::Bash);
+        assert!(bash.contains("--pad) COMPREPLY=($(compgen -W \"trim replicate\""));
+        assert!(bash.ends_with("complete -o filenames -F _rpeg rpeg\n"));
+        assert!(completions(USAGE, Shell::Zsh).contains("-c|compress) compadd -- --pad"));
+        let fish = completions(USAGE, Shell::Fish);
+        assert!(fish.contains("-l pad -x -a \"trim replicate\""));
+        assert!(fish.contains("-s o\n"));
+        let man = man_page(USAGE, "0.1.0");
+        assert!(man.starts_with(".TH RPEG 1"));
+        assert!(man.contains(".B 6\nI/O error"));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::encoder::Preset;
+use std::path::Path;
+
+/// File the defaults are read from, in the current directory, unless `RPEG_CONFIG` names
+/// another one.
+pub const CONFIG_FILE: &str = "rpeg.toml";
+
+/// Flags that set the luma range, which a default quality must not be combined with.
+const QUALITY_FLAGS: [&str; 4] = ["--quality", "--luma-range", "--high-contrast", "--two-pass"];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// ## Defaults for the flags of rpeg, from `rpeg.toml` and `RPEG_*` environment variables
+///
+/// `rpeg.toml` holds `key = value` lines, where the keys are `quality`, `threads`,
+/// `output_dir`, and `preset`, strings are quoted, and `#` starts a comment. The environment
+/// variables `RPEG_QUALITY`, `RPEG_THREADS`, `RPEG_OUTPUT_DIR`, and `RPEG_PRESET` replace the
+/// values of the file, and flags given on the command line replace both.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::config::Config;
+///
+/// let file = Config::parse("quality = 80\npreset = \"fast\" # for the nightly build\n").unwrap();
+/// let env = Config::from_vars([("RPEG_QUALITY".to_string(), "60".to_string())]).unwrap();
+/// let config = env.or(file);
+/// assert_eq!((config.quality, config.preset.as_deref()), (Some(60), Some("fast")));
+/// let args = vec!["--luma-range".to_string(), "0.1".to_string()];
+/// assert_eq!(config.flags(&args), ["--preset", "fast"]);
+/// ```
+pub struct Config {
+    /// Quality between 0 and 100, as given to `--quality`.
+    pub quality: Option<u8>,
+    /// Number of threads compressing and decompressing, as given to `--threads`.
+    pub threads: Option<usize>,
+    /// Directory receiving the outputs of the commands that write several files, when `-o` is
+    /// not given.
+    pub output_dir: Option<String>,
+    /// Name of the preset, as given to `--preset`.
+    pub preset: Option<String>,
+}
+
+impl Config {
+    /// Returns `config` with `key` set to `value`, or an error if either is invalid.
+    fn set(mut self, key: &str, value: &str) -> Result<Config, String> {
+        match key {
+            "quality" => match value.parse::<u8>() {
+                Ok(quality) if quality <= 100 => self.quality = Some(quality),
+                _ => return Err(format!("quality must be between 0 and 100, not {value}")),
+            },
+            "threads" => match value.parse::<usize>() {
+                Ok(threads) if threads > 0 => self.threads = Some(threads),
+                _ => return Err(format!("threads must be a positive number, not {value}")),
+            },
+            "output_dir" => self.output_dir = Some(value.to_string()),
+            "preset" => match Preset::from_name(value) {
+                Some(_) => self.preset = Some(value.to_string()),
+                None => {
+                    return Err(format!(
+                        "preset must be fast, balanced, or best, not {value}"
+                    ))
+                }
+            },
+            _ => {
+                return Err(format!(
+                    "Unknown setting {key}, expected quality, threads, output_dir, or preset"
+                ))
+            }
+        }
+        Ok(self)
+    }
+
+    /// Parses the contents of `rpeg.toml`.
+    ///
+    /// # Arguments
+    /// * `text`: Contents of the file
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: String| format!("{CONFIG_FILE} line {}: {message}", number + 1);
+            let line = match line.find('#') {
+                Some(start) if line[..start].matches('"').count() % 2 == 0 => &line[..start],
+                _ => line,
+            }
+            .trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error("expected key = value".to_string()));
+            };
+            let value = value.trim();
+            let value = match value.strip_prefix('"') {
+                Some(quoted) => quoted
+                    .strip_suffix('"')
+                    .ok_or_else(|| error("unterminated string".to_string()))?,
+                None => value,
+            };
+            config = config.set(key.trim(), value).map_err(error)?;
+        }
+        Ok(config)
+    }
+
+    /// Returns the settings of the `RPEG_*` variables among `vars`.
+    ///
+    /// # Arguments
+    /// * `vars`: Environment variables, as returned by `std::env::vars`
+    pub fn from_vars(vars: impl IntoIterator<Item = (String, String)>) -> Result<Config, String> {
+        let mut config = Config::default();
+        for (name, value) in vars {
+            let key = match name.as_str() {
+                "RPEG_QUALITY" => "quality",
+                "RPEG_THREADS" => "threads",
+                "RPEG_OUTPUT_DIR" => "output_dir",
+                "RPEG_PRESET" => "preset",
+                _ => continue,
+            };
+            config = config
+                .set(key, value.trim())
+                .map_err(|message| format!("{name}: {message}"))?;
+        }
+        Ok(config)
+    }
+
+    /// Returns the settings of `self`, completed by those of `lower` it does not have.
+    ///
+    /// # Arguments
+    /// * `lower`: Settings of lower precedence
+    pub fn or(self, lower: Config) -> Config {
+        Config {
+            quality: self.quality.or(lower.quality),
+            threads: self.threads.or(lower.threads),
+            output_dir: self.output_dir.or(lower.output_dir),
+            preset: self.preset.or(lower.preset),
+        }
+    }
+
+    /// Returns the defaults of this process: the `RPEG_*` environment variables over the file
+    /// named by `RPEG_CONFIG`, or over `rpeg.toml` in the current directory if there is one.
+    pub fn load() -> Result<Config, String> {
+        let file = match std::env::var("RPEG_CONFIG") {
+            Ok(path) => Some(path),
+            Err(_) => Path::new(CONFIG_FILE)
+                .is_file()
+                .then(|| CONFIG_FILE.to_string()),
+        };
+        let from_file = match file {
+            Some(path) => {
+                let text = std::fs::read_to_string(&path)
+                    .map_err(|error| format!("Failed to read {path}: {error}"))?;
+                Config::parse(&text)?
+            }
+            None => Config::default(),
+        };
+        Ok(Config::from_vars(std::env::vars())?.or(from_file))
+    }
+
+    /// Returns the flags that apply the defaults, to be parsed ahead of `args` so that the
+    /// flags of the command line win. A default is left out when `args` set it themselves,
+    /// so that, for example, a default quality does not clash with `--two-pass`.
+    ///
+    /// # Arguments
+    /// * `args`: Flags given on the command line
+    pub fn flags(&self, args: &[String]) -> Vec<String> {
+        let given = |names: &[&str]| args.iter().any(|arg| names.contains(&arg.as_str()));
+        let mut flags = Vec::new();
+        if let Some(preset) = self.preset.as_ref().filter(|_| !given(&["--preset"])) {
+            flags.extend(["--preset".to_string(), preset.clone()]);
+        }
+        if let Some(quality) = self.quality.filter(|_| !given(&QUALITY_FLAGS)) {
+            flags.extend(["--quality".to_string(), quality.to_string()]);
+        }
+        if let Some(threads) = self.threads.filter(|_| !given(&["--threads"])) {
+            flags.extend(["--threads".to_string(), threads.to_string()]);
+        }
+        flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_and_variables_merge_under_the_command_line() {
+        let file = Config::parse(
+            "# team defaults\nquality = 70\nthreads = 4\noutput_dir = \"out # here\"\n\n",
+        )
+        .unwrap();
+        assert_eq!(file.output_dir.as_deref(), Some("out # here"));
+        assert!(Config::parse("quality = 101")
+            .unwrap_err()
+            .contains("line 1"));
+        assert!(Config::parse("level = 3").is_err());
+        assert!(Config::parse("\npreset").unwrap_err().contains("line 2"));
+        let vars = [
+            ("RPEG_THREADS".to_string(), "2".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ];
+        let config = Config::from_vars(vars).unwrap().or(file);
+        assert_eq!((config.quality, config.threads), (Some(70), Some(2)));
+        assert!(Config::from_vars([("RPEG_PRESET".to_string(), "slow".to_string())]).is_err());
+        assert_eq!(
+            config.flags(&[]),
+            ["--quality", "70", "--threads", "2"].map(String::from)
+        );
+        assert_eq!(
+            config.flags(&["--two-pass".to_string(), "--threads".to_string()]),
+            Vec::<String>::new()
+        );
+    }
+}
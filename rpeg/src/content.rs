@@ -0,0 +1,163 @@
+use crate::palette::build_palette;
+use crate::ppm::Rgb;
+use array2::array2::Array2;
+
+/// Difference of luma between two neighbouring pixels above which they are counted as an edge.
+const EDGE_THRESHOLD: f64 = 0.25;
+
+/// Fraction of the neighbouring pixel pairs of a tile that must be edges for it to be text.
+pub const TEXT_EDGE_DENSITY: f64 = 0.06;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// ## Kind of content of a tile, which decides how the tile is coded
+///
+/// `Photo` tiles are packed into code words with the range of their blocks. `Graphic` tiles
+/// hold few colors and are palette coded without loss (see `rpeg::palette`). `Text` tiles have
+/// many sharp edges and are packed into code words whose background blocks use
+/// `MAX_LUMA_RANGE`, which never clips an edge. `Raw` tiles store their pixels as they are
+/// (see `rpeg::raw`), for images that do not compress. The id of every mode is the byte that
+/// records it in the header.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::content::TileMode;
+///
+/// assert_eq!(TileMode::from_id(TileMode::Text.id()), Some(TileMode::Text));
+/// assert_eq!(TileMode::from_id(4), None);
+/// ```
+pub enum TileMode {
+    #[default]
+    Photo,
+    Graphic,
+    Text,
+    Raw,
+}
+
+impl TileMode {
+    /// Returns the byte recording the mode in the header.
+    pub fn id(self) -> u8 {
+        match self {
+            TileMode::Photo => 0,
+            TileMode::Graphic => 1,
+            TileMode::Text => 2,
+            TileMode::Raw => 3,
+        }
+    }
+
+    /// Returns the mode recorded by `id`, or None for an unknown id.
+    ///
+    /// # Arguments
+    /// * `id`: Byte recording the mode in the header
+    pub fn from_id(id: u8) -> Option<TileMode> {
+        [
+            TileMode::Photo,
+            TileMode::Graphic,
+            TileMode::Text,
+            TileMode::Raw,
+        ]
+        .into_iter()
+        .find(|mode| mode.id() == id)
+    }
+
+    /// Returns the name of the mode, as printed by `rpeg info`.
+    pub fn name(self) -> &'static str {
+        match self {
+            TileMode::Photo => "photo",
+            TileMode::Graphic => "graphic",
+            TileMode::Text => "text",
+            TileMode::Raw => "raw",
+        }
+    }
+
+    /// Returns true if tiles of this mode are stored as code words, rather than as exact
+    /// pixels.
+    pub fn holds_words(self) -> bool {
+        matches!(self, TileMode::Photo | TileMode::Text)
+    }
+}
+
+/// Returns the fraction of horizontally and vertically neighbouring pixel pairs of an image
+/// whose luma differs by more than `EDGE_THRESHOLD`.
+///
+/// # Arguments
+/// * `image`: Image, or tile of an image
+/// * `denominator`: Denominator of the Rgb values of the image
+pub fn edge_density(image: &Array2<Rgb>, denominator: u16) -> f64 {
+    let (width, height) = (image.get_width(), image.get_height());
+    let luma: Vec<f64> = image
+        .data
+        .iter()
+        .map(|pixel| {
+            (0.299 * pixel.red as f64 + 0.587 * pixel.green as f64 + 0.114 * pixel.blue as f64)
+                / denominator.max(1) as f64
+        })
+        .collect();
+    let (mut edges, mut pairs) = (0, 0);
+    for row in 0..height {
+        for col in 0..width {
+            let here = luma[row * width + col];
+            for (next_col, next_row) in [(col + 1, row), (col, row + 1)] {
+                if next_col < width && next_row < height {
+                    pairs += 1;
+                    if (here - luma[next_row * width + next_col]).abs() > EDGE_THRESHOLD {
+                        edges += 1;
+                    }
+                }
+            }
+        }
+    }
+    edges as f64 / pairs.max(1) as f64
+}
+
+/// Classifies the content of a tile: tiles with at most `MAX_PALETTE_COLORS` colors are
+/// graphics, tiles whose edge density exceeds `TEXT_EDGE_DENSITY` are text, and the others are
+/// photos.
+///
+/// # Arguments
+/// * `image`: Tile of an image
+/// * `denominator`: Denominator of the Rgb values of the image
+pub fn classify(image: &Array2<Rgb>, denominator: u16) -> TileMode {
+    if build_palette(image, denominator).is_some() {
+        TileMode::Graphic
+    } else if edge_density(image, denominator) > TEXT_EDGE_DENSITY {
+        TileMode::Text
+    } else {
+        TileMode::Photo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_are_classified_by_colors_and_edges() {
+        let image = |pixel: &dyn Fn(u16, u16) -> (u16, u16)| {
+            let pixels = (0..32 * 32)
+                .map(|i| {
+                    let (luma, shade) = pixel(i % 32, i / 32);
+                    Rgb {
+                        red: luma,
+                        green: luma,
+                        blue: (luma + shade).min(255),
+                    }
+                })
+                .collect();
+            Array2::from_row_major(32, 32, pixels)
+        };
+        let diagram = image(&|col, _| (if col < 16 { 0 } else { 255 }, 0));
+        assert_eq!(classify(&diagram, 255), TileMode::Graphic);
+        let smooth = image(&|col, row| (col * 4 + row * 3, row % 8));
+        assert_eq!(classify(&smooth, 255), TileMode::Photo);
+        let strokes = image(&|col, row| {
+            if col % 4 == 0 {
+                (col + row, row % 8)
+            } else {
+                (180 + (col * 7 + row * 13) % 50, (col * 3 + row) % 16)
+            }
+        });
+        assert!(edge_density(&strokes, 255) > TEXT_EDGE_DENSITY);
+        assert_eq!(classify(&strokes, 255), TileMode::Text);
+    }
+}
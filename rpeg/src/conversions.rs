@@ -1,60 +1,161 @@
-use crate::structs::{Block, ComponentVideo, DCTCoefficient, RgbFloats};
+use crate::dct_coeff::{
+    chroma_error, clipped_coefficients, compute_dct, from_dct_to_block, masked_luma_range,
+    optimize_dct,
+};
+use crate::layout::WordLayout;
+use crate::pixel::Pixel;
+use crate::ppm::{Rgb, RgbImage};
+use crate::structs::{
+    Block, BlockLayout, ComponentVideo, DCTCoefficient, QuantizedBlock, RgbFloats,
+};
 use array2::array2::Array2;
-use bitpack::bitpack::{gets, getu, news, newu};
-//use csc411_arith::{chroma_of_index, index_of_chroma};
-use crate::component_video_and_blocks::{compute_component_video, get_block};
-use crate::dct_coeff::{compute_dct, from_dct_to_block};
-use crate::rgb::{component_back_to_rgb_floats, compute_rgb_floats, from_rgb_float_to_rgb};
-use csc411_image::Rgb;
-
-/// This functions takes an Rgb image stored in Array2 Struct with its denominator and converts
-/// each pixel in the image into a floating point representation of the Rgb. It returns a new
-/// Array2 of RgbFloats.
-///
-/// # Arguments
-/// * `image` : Array2 of Rgb's representing the original image
-pub fn rgb_to_floats(image: Array2<Rgb>, image_denominator: u16) -> Array2<RgbFloats> {
-    let rgb_data: Vec<RgbFloats> = image
-        .iter_row_major()
-        .map(|pixel| compute_rgb_floats(pixel.2.clone(), image_denominator as f64))
-        .collect();
 
-    Array2::from_row_major(image.get_width(), image.get_height(), rgb_data)
+/// Denominator of the images `linearize` returns, fine enough that the darkest steps of an
+/// 8-bit sRGB image stay apart in linear light.
+pub const LINEAR_DENOMINATOR: u16 = u16::MAX;
+
+/// Returns the linear light of an sRGB encoded density, both between 0 and 1.
+///
+/// # Arguments
+/// * `value`: sRGB encoded density
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
 }
 
-/// This function takes an image where each Rgb value is represented as a floating
-/// point, and it turns the floating point representation into a Component Video
-/// representation of the pixels.
+/// Returns the sRGB encoding of a linear light, both between 0 and 1.
 ///
 /// # Arguments
-/// * `image`: Array2 of RgbFloats representing the image data
-pub fn rbg_floats_to_component_video(image: Array2<RgbFloats>) -> Array2<ComponentVideo> {
-    let cv: Vec<ComponentVideo> = image
-        .iter_row_major()
-        .map(|pixel| compute_component_video(pixel.2.clone()))
-        .collect();
+/// * `value`: Linear light
+pub fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
 
-    Array2::from_row_major(image.get_width(), image.get_height(), cv)
+/// Returns a copy of an sRGB encoded image with every density converted to linear light, with a
+/// denominator of `LINEAR_DENOMINATOR`.
+///
+/// # Arguments
+/// * `image`: Image whose densities follow the sRGB transfer curve
+pub fn linearize(image: &RgbImage) -> RgbImage {
+    let denominator = image.denominator.max(1) as f64;
+    let linear = |value: u16| {
+        (srgb_to_linear(value as f64 / denominator) * LINEAR_DENOMINATOR as f64).round() as u16
+    };
+    RgbImage {
+        pixels: image
+            .pixels
+            .iter()
+            .map(|pixel| Rgb {
+                red: linear(pixel.red),
+                green: linear(pixel.green),
+                blue: linear(pixel.blue),
+            })
+            .collect(),
+        denominator: LINEAR_DENOMINATOR,
+        ..*image
+    }
 }
 
-/// This function takes a Array2 of ComponentVideo struct which represent an image
-/// in component video format, and it extracts the 2x2 block of pixels to further
-/// undergo under compression.
+/// This function takes and a Rgb pixel with the image denominator, and it turns the pixel
+/// into a Floating point representation stored as an RgbFloats.
+///
+/// # Arguments
+/// * `pixel`: Rgb struct representing the red, green, and blue color density of the image
+pub fn compute_rgb_floats(pixel: &Rgb, denominator: f64) -> RgbFloats {
+    let r = pixel.red as f64 / denominator;
+    let g = pixel.green as f64 / denominator;
+    let b = pixel.blue as f64 / denominator;
+
+    RgbFloats {
+        red: r,
+        green: g,
+        blue: b,
+    }
+}
+
+/// This function takes the floating point representation of an Rgb and turns it
+/// into a Component Video representation.
+///
+/// # Arguments
+/// * `pixel`: Floating representation of the pixel red, green, and blue color density.
+pub fn compute_component_video(pixel: &RgbFloats) -> ComponentVideo {
+    let y = 0.299 * pixel.red + 0.587 * pixel.green + 0.114 * pixel.blue;
+    let pb = -0.168736 * pixel.red - 0.331264 * pixel.green + 0.5 * pixel.blue;
+    let pr = 0.5 * pixel.red - 0.418688 * pixel.green - 0.081312 * pixel.blue;
+
+    ComponentVideo { y, pb, pr }
+}
+
+/// This function takes an image with even dimensions and turns each of its pixels into a
+/// Component Video representation, whatever the type of its pixels. The pixels are stored
+/// block-linear: the 2x2 blocks in row-major order, with the four pixels of every block in turn
+/// in `BlockLayout::RowMajor` order, as `from_blocks_to_component_format` gives them back, so
+/// that gathering a block reads four neighbouring pixels instead of two rows of the image.
 ///
 /// # Arguments
-/// * `image_in_component_vid`: Array2 where each pixel is represent in Component Video format
-pub fn component_video_to_blocks(image_in_component_vid: Array2<ComponentVideo>) -> Array2<Block> {
-    let width = image_in_component_vid.get_width();
-    let height = image_in_component_vid.get_height();
-    let mut block_arr: Vec<Block> = Vec::new();
-    for row in (0..height).step_by(2) {
-        for col in (0..width).step_by(2) {
-            let block = get_block(&image_in_component_vid, row, col);
-            block_arr.push(block);
+/// * `image`: Array2 of pixels representing the image data
+/// * `image_denominator`: Denominator of the densities of the pixels
+pub fn pixels_to_component_video<P: Pixel>(
+    image: &Array2<P>,
+    image_denominator: u16,
+) -> Array2<ComponentVideo> {
+    let width = image.get_width();
+    let denominator = image_denominator as f64;
+    let mut cv: Vec<ComponentVideo> = Vec::with_capacity(image.data.len());
+    for rows in image.data.chunks_exact(2 * width) {
+        let (top, bottom) = rows.split_at(width);
+        for (top, bottom) in top.chunks_exact(2).zip(bottom.chunks_exact(2)) {
+            let pixels = [&top[0], &top[1], &bottom[0], &bottom[1]];
+            cv.extend(pixels.map(|pixel| pixel.to_ypbpr(denominator)));
         }
     }
 
-    Array2::from_row_major(width, height, block_arr)
+    Array2::from_row_major(width, image.get_height(), cv)
+}
+
+/// This functions gets the 2x2 Block whose top-left pixel is at the current `row` and `col`.
+/// `row` `col` + 1 is the top-right pixel, `row` + 1 `col` the bottom-left one, and `row` + 1
+/// `col` + 1 the bottom-right one, as `BlockLayout::RowMajor` says. This function servers as a
+/// helper function to component_video_to_blocks
+///
+/// # Arguments
+/// * `cv_arr` : Block-linear Array2 of ComponentVideo pixels, as `pixels_to_component_video`
+///   returns it
+/// * `row` : Current row that you are trying to get the block for "it must be step by 2"
+/// * `col`: Current col that you are trying to get the block for "it must be step by 2"
+pub fn get_block(cv_arr: &Array2<ComponentVideo>, row: usize, col: usize) -> Block {
+    let start = ((row / 2) * (cv_arr.get_width() / 2) + col / 2) * 4;
+    let pixels = cv_arr.data[start..start + 4].try_into().unwrap();
+
+    Block::from_pixels(pixels, BlockLayout::RowMajor)
+}
+
+/// This function takes a block-linear Array2 of ComponentVideo struct which represent an image
+/// in component video format, and it extracts the 2x2 block of pixels to further
+/// undergo under compression.
+///
+/// # Arguments
+/// * `image_in_component_vid`: Array2 where each pixel is represent in Component Video format,
+///   as `pixels_to_component_video` returns it
+pub fn component_video_to_blocks(image_in_component_vid: &Array2<ComponentVideo>) -> Array2<Block> {
+    let block_arr: Vec<Block> = image_in_component_vid
+        .data
+        .chunks_exact(4)
+        .map(|pixels| Block::from_pixels(pixels.try_into().unwrap(), BlockLayout::RowMajor))
+        .collect();
+
+    Array2::from_row_major(
+        image_in_component_vid.get_width(),
+        image_in_component_vid.get_height(),
+        block_arr,
+    )
 }
 
 /// Takes a 2x2 block of pixels represented in ComponentVideo format, and turn this block
@@ -62,72 +163,167 @@ pub fn component_video_to_blocks(image_in_component_vid: Array2<ComponentVideo>)
 ///
 /// # Arguments
 /// `blocks`: block of 2x2 pixels of ComponentVideo format
-pub fn blocks_to_dct(blocks: Array2<Block>) -> Array2<DCTCoefficient> {
+/// `luma_ranges`: Range b, c, and d are clamped to, one per block
+/// `optimize`: Search the neighbouring coefficients of every block for the ones that decode
+/// closest to the block
+/// `layout`: Layout of the code words the coefficients are quantized for
+pub fn blocks_to_dct(
+    blocks: &Array2<Block>,
+    luma_ranges: &[f64],
+    optimize: bool,
+    layout: &WordLayout,
+) -> Array2<DCTCoefficient> {
     let dct_arr: Vec<DCTCoefficient> = blocks
         .data
         .iter()
-        .map(|block| compute_dct((*block).clone()))
+        .zip(luma_ranges.iter())
+        .map(|(block, luma_range)| {
+            let coefficient = compute_dct(block, *luma_range, layout);
+            if optimize {
+                optimize_dct(block, coefficient, *luma_range, layout)
+            } else {
+                coefficient
+            }
+        })
         .collect();
 
     Array2::from_row_major(blocks.get_width(), blocks.get_height(), dct_arr)
 }
 
+/// Quantizes the 2x2 blocks of an image like `blocks_to_dct`, with the range of every block
+/// weighted by its brightness by `masked_luma_range`. Returns the weighted ranges along with
+/// the coefficients. A block whose `a` was moved by the rounding optimization onto a different
+/// weight is quantized again without it, since the decoder derives the range from the `a` it
+/// reads.
+///
+/// # Arguments
+/// `blocks`: block of 2x2 pixels of ComponentVideo format
+/// `luma_ranges`: Range b, c, and d are clamped to before weighting, one per block
+/// `optimize`: Search the neighbouring coefficients of every block for the ones that decode
+/// closest to the block
+/// `layout`: Layout of the code words the coefficients are quantized for
+pub fn blocks_to_masked_dct(
+    blocks: &Array2<Block>,
+    luma_ranges: &[f64],
+    optimize: bool,
+    layout: &WordLayout,
+) -> (Vec<f64>, Array2<DCTCoefficient>) {
+    let ranges = masked_ranges(
+        &blocks_to_dct(blocks, luma_ranges, false, layout),
+        luma_ranges,
+        layout,
+    );
+    let mut dct_arr = blocks_to_dct(blocks, &ranges, optimize, layout);
+    for (index, coefficient) in dct_arr.data.iter_mut().enumerate() {
+        if masked_luma_range(luma_ranges[index], coefficient.a, layout) != ranges[index] {
+            *coefficient = compute_dct(&blocks.data[index], ranges[index], layout);
+        }
+    }
+    (ranges, dct_arr)
+}
+
+/// Returns the range of every block weighted by its brightness by `masked_luma_range`.
+///
+/// # Arguments
+/// `dct_arr`: Quantized coefficients of the blocks, of which only `a` is used
+/// `luma_ranges`: Range b, c, and d are clamped to before weighting, one per block
+/// `layout`: Layout of the code words of the blocks
+pub fn masked_ranges(
+    dct_arr: &Array2<DCTCoefficient>,
+    luma_ranges: &[f64],
+    layout: &WordLayout,
+) -> Vec<f64> {
+    dct_arr
+        .data
+        .iter()
+        .zip(luma_ranges.iter())
+        .map(|(coefficient, luma_range)| masked_luma_range(*luma_range, coefficient.a, layout))
+        .collect()
+}
+
+/// Takes the 2x2 blocks of an image and returns how many of their b, c, and d coefficients
+/// fall outside of the range they are clamped to, and are therefore clipped by `blocks_to_dct`.
+///
+/// # Arguments
+/// `blocks`: block of 2x2 pixels of ComponentVideo format
+/// `luma_ranges`: Range b, c, and d are clamped to, one per block
+pub fn count_clipped(blocks: &Array2<Block>, luma_ranges: &[f64]) -> usize {
+    blocks
+        .data
+        .iter()
+        .zip(luma_ranges.iter())
+        .map(|(block, luma_range)| clipped_coefficients(block, *luma_range))
+        .sum()
+}
+
+/// Takes the 2x2 blocks of an image and the Pb and Pr indices of their code words, and returns
+/// the sum over the blocks of how far the chroma of the indices is from their average Pb and Pr.
+///
+/// # Arguments
+/// `blocks`: block of 2x2 pixels of ComponentVideo format
+/// `indices`: Pb and Pr indices of the code words, one pair per block
+/// `layout`: Layout of the code words
+pub fn chroma_error_sum(
+    blocks: &Array2<Block>,
+    indices: impl Iterator<Item = (usize, usize)>,
+    layout: &WordLayout,
+) -> f64 {
+    blocks
+        .data
+        .iter()
+        .zip(indices)
+        .map(|(block, indices)| chroma_error(block, indices, layout))
+        .sum()
+}
+
 /// This function takes Array2 Struct of the DCTCoefficient that are obtained from each
 /// 2x2 block of pixel inside the original image, and it pack each DCTCoefficient word
-/// into a 32 bit word that is been represented as a 64 bit word for the purpose of not
-/// over-floating the bit word.
+/// into a code word of the given layout, held in a 64 bit word whatever the width of the
+/// layout.
 ///
 /// # Arguments
 /// * `dct_arr`: Array2 Struct of dct coefficient values calculated from the 2x2 blocks of pixels
-pub fn pack_values_into_word(dct_arr: Array2<DCTCoefficient>) -> Array2<[u8; 4]> {
-    let mut output_image = Vec::new();
-    for value in dct_arr.data.iter() {
-        let mut word = 0_u64;
-        word = newu(word, 9, 23, value.a as u64).unwrap();
-        word = news(word, 5, 18, value.b as i64).unwrap();
-        word = news(word, 5, 13, value.c as i64).unwrap();
-        word = news(word, 5, 8, value.d as i64).unwrap();
-        word = newu(word, 4, 4, value.index_of_pb as u64).unwrap();
-        word = newu(word, 4, 0, value.index_of_pr as u64).unwrap();
-        output_image.push((word as u32).to_be_bytes());
-    }
+/// * `layout`: Layout of the code words
+pub fn pack_values_into_word(dct_arr: &Array2<DCTCoefficient>, layout: &WordLayout) -> Array2<u64> {
+    let output_image: Vec<u64> = dct_arr
+        .data
+        .iter()
+        .map(|value| layout.pack(&QuantizedBlock::from(value)))
+        .collect();
 
     Array2::from_row_major(dct_arr.get_width(), dct_arr.get_height(), output_image)
 }
 
 // Decompression
 
-/// Takes a binary representation of pack DTCCoefficient values into 32 bit words, and it converges
-/// the values back to DCTCoefficients. Returns an Array2 Struct of DTCCoefficients.
+/// Takes a binary representation of pack DTCCoefficient values into code words, and it converges
+/// the values back to DCTCoefficients. Returns an Array2 Struct of DTCCoefficients, or an error
+/// when there is not exactly one code word per 2x2 block of the image.
 ///
 /// # Arguments:
-/// `compressed_imag`: A compressed image into 32 bits code words.
+/// `compressed_imag`: A compressed image into code words of the given layout.
+/// `image_width`: Width of the image in pixels
+/// `image_height`: Height of the image in pixels
+/// `layout`: Layout of the code words
 pub fn unpack_values(
-    compressed_imag: Vec<u32>,
+    compressed_imag: &[u64],
     image_width: usize,
     image_height: usize,
-) -> Array2<DCTCoefficient> {
-    let mut dct_arr: Vec<DCTCoefficient> = Vec::new();
-    for image_data in compressed_imag.iter() {
-        let word = image_data.clone() as u64;
-        let a = getu(word, 9, 23);
-        let b = gets(word, 5, 18);
-        let c = gets(word, 5, 13);
-        let d = gets(word, 5, 8);
-        let pb = getu(word, 4, 4);
-        let pr = getu(word, 4, 0);
-
-        dct_arr.push(DCTCoefficient {
-            a: a as f64,
-            b: b as f64,
-            c: c as f64,
-            d: d as f64,
-            index_of_pb: pb as usize,
-            index_of_pr: pr as usize,
-        });
+    layout: &WordLayout,
+) -> Result<Array2<DCTCoefficient>, String> {
+    let block_count = (image_width / 2) * (image_height / 2);
+    if compressed_imag.len() != block_count {
+        return Err(format!(
+            "Expected {block_count} code words, found {}",
+            compressed_imag.len()
+        ));
     }
+    let dct_arr: Vec<DCTCoefficient> = compressed_imag
+        .iter()
+        .map(|word| DCTCoefficient::from(layout.unpack(*word)))
+        .collect();
 
-    Array2::from_row_major(image_width, image_height, dct_arr)
+    Ok(Array2::from_row_major(image_width, image_height, dct_arr))
 }
 
 /// This functions takes an Array2 Struct of DCTCoefficients and convert each coefficient back
@@ -135,96 +331,271 @@ pub fn unpack_values(
 ///
 /// # Arguments:
 /// * `dct_arr`: Array2 of DCTCoefficient representing block of 2x2 pixels
-pub fn from_dct_to_component_video(dct_arr: Array2<DCTCoefficient>) -> Array2<Block> {
+/// * `luma_ranges`: Range b, c, and d were clamped to, one per block
+/// * `layout`: Layout of the code words the coefficients were unpacked from
+pub fn from_dct_to_component_video(
+    dct_arr: &Array2<DCTCoefficient>,
+    luma_ranges: &[f64],
+    layout: &WordLayout,
+) -> Array2<Block> {
     let block: Vec<Block> = dct_arr
         .data
         .iter()
-        .map(|coefficient| from_dct_to_block((*coefficient).clone()))
+        .zip(luma_ranges.iter())
+        .map(|(coefficient, luma_range)| from_dct_to_block(coefficient, *luma_range, layout))
         .collect();
     Array2::from_row_major(dct_arr.get_width(), dct_arr.get_height(), block)
 }
 
 /// This function takes an Array2 Struct 2x2 block of ComponentVideo representing pixel in this format
-/// , and it return an Array2 struct of ComponentVideo where each pixel is located at its core spot.
+/// , and it return an Array2 struct of ComponentVideo holding the four pixels of every block in
+/// turn, in `BlockLayout::RowMajor` order. `fix_pixel_poss` later moves them to their place.
 /// Returns a Array2 Struct of ComponentVideo.
 ///
 /// # Argument
 /// * `block`: Block of 2x2 pixel represented in ComponentVideo format
-pub fn from_blocks_to_component_format(block: Array2<Block>) -> Array2<ComponentVideo> {
+pub fn from_blocks_to_component_format(block: &Array2<Block>) -> Array2<ComponentVideo> {
     let mut cv_image: Vec<ComponentVideo> = Vec::new();
     for pixel_block in block.data.iter() {
-        cv_image.push(pixel_block.y1.clone());
-        cv_image.push(pixel_block.y2.clone());
-        cv_image.push(pixel_block.y3.clone());
-        cv_image.push(pixel_block.y4.clone());
+        cv_image.extend(pixel_block.pixels(BlockLayout::RowMajor));
     }
     Array2::from_row_major(block.get_width(), block.get_height(), cv_image)
 }
 
-/// This function takes an Array2 of pixels in ComponentVideo format and translate each pixel
-/// back into its RgbFloat representation. Return Array2 Struct of RgbFloats.
+/// This function takes a pixel represented in ComponentVideo format, and it converges the pixel
+/// back to RgbFloat format. Returns a RgbFloat struct with the pixel data.
 ///
 /// # Argument
-/// * `cv_image`: Image of pixels in ComponentVideo format
-pub fn component_video_back_to_rbg_floats(cv_image: Array2<ComponentVideo>) -> Array2<RgbFloats> {
-    let rgb_float_arr: Vec<RgbFloats> = cv_image
-        .iter_row_major()
-        .map(|cv| component_back_to_rgb_floats(cv.2.clone()))
-        .collect();
+/// * `cv`: pixel in ComponentVideo format
+pub fn component_back_to_rgb_floats(cv: &ComponentVideo) -> RgbFloats {
+    let red = (1.0 * cv.y + 0.0 * cv.pb + 1.402 * cv.pr) * 255.0;
+    let green = (1.0 * cv.y - 0.344136 * cv.pb - 0.714136 * cv.pr) * 255.0;
+    let blue = (1.0 * cv.y + 1.772 * cv.pb + 0.0 * cv.pr) * 255.0;
+
+    RgbFloats { red, green, blue }
+}
+
+/// This function takes an pixel represented as floating point value and converges the pixel back
+/// to a normal Rgb pixel. Returns a pixel on Rgb format.
+///
+/// # Argument
+/// * `pixel`: pixel which red, green, and blue density are represented as floating point
+pub fn from_rgb_float_to_rgb(pixel: &RgbFloats) -> Rgb {
+    let red = pixel.red as u16;
+    let green = pixel.green as u16;
+    let blue = pixel.blue as u16;
+
+    Rgb { red, green, blue }
+}
 
-    Array2::from_row_major(cv_image.get_width(), cv_image.get_height(), rgb_float_arr)
+/// 4x4 Bayer matrix giving the order in which the pixels of a 4x4 square round up.
+pub(crate) const BAYER: [[u8; 4]; 4] =
+    [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Returns the ordered dithering threshold, between 0 and 1, of the pixel at column `x` and row
+/// `y` of the image.
+pub fn dither_threshold(x: usize, y: usize) -> f64 {
+    (BAYER[y % 4][x % 4] as f64 + 0.5) / 16.0
 }
 
-/// This function takes an Array2 Struct of pixels represented as RgbFloats and normalizes
-/// each pixel back to Rgb format. Returns a new Array2 struct of Rgb which can be consider
-/// an full decompressed image.
+/// This function takes a pixel represented as floating point values and converges it back to a
+/// normal Rgb pixel, rounding each density up when its fractional part exceeds `threshold`
+/// instead of always truncating it. Returns a pixel on Rgb format.
+///
+/// # Argument
+/// * `pixel`: pixel which red, green, and blue density are represented as floating point
+/// * `threshold`: Dithering threshold of the pixel, as returned by `dither_threshold`
+pub fn from_rgb_float_to_rgb_dithered(pixel: &RgbFloats, threshold: f64) -> Rgb {
+    let red = (pixel.red + 1.0 - threshold) as u16;
+    let green = (pixel.green + 1.0 - threshold) as u16;
+    let blue = (pixel.blue + 1.0 - threshold) as u16;
+
+    Rgb { red, green, blue }
+}
+
+/// This function takes an Array2 Struct of pixels in ComponentVideo format, still grouped by
+/// 2x2 block, and converts each pixel back to the pixel type `P`. With `dither`, the densities
+/// are rounded with ordered dithering, which hides the banding left by the quantization of the
+/// code words; without, they are truncated. Returns a new Array2 struct of pixels.
 ///
 /// # Arguments
-/// * `rgb_float_arr`: Array2 of Rgb's represented as floating point values
-pub fn rgb_floats_to_rgb(rgb_float_arr: Array2<RgbFloats>) -> Array2<Rgb> {
-    let image: Vec<Rgb> = rgb_float_arr
-        .iter_row_major()
-        .map(|pixel| from_rgb_float_to_rgb(pixel.2.clone()))
+/// * `cv_image`: Image of pixels in ComponentVideo format, grouped by block
+/// * `dither`: Column and row of the top-left pixel within the whole image, so that tiles share
+///   one dithering pattern, or None to truncate the densities
+pub fn component_video_to_pixels<P: Pixel>(
+    cv_image: &Array2<ComponentVideo>,
+    dither: Option<(usize, usize)>,
+) -> Array2<P> {
+    let blocks_per_row = cv_image.get_width() / 2;
+    let image: Vec<P> = cv_image
+        .data
+        .iter()
+        .enumerate()
+        .map(|(index, pixel)| match dither {
+            Some(origin) => {
+                let (block, corner) = (index / 4, index % 4);
+                let x = origin.0 + (block % blocks_per_row) * 2 + corner % 2;
+                let y = origin.1 + (block / blocks_per_row) * 2 + corner / 2;
+                P::from_ypbpr_dithered(pixel, dither_threshold(x, y))
+            }
+            None => P::from_ypbpr(pixel),
+        })
         .collect();
 
-    Array2::from_row_major(rgb_float_arr.get_width(), rgb_float_arr.get_height(), image)
+    Array2::from_row_major(cv_image.get_width(), cv_image.get_height(), image)
 }
-
 /// This function takes a decompressed image inside Array2 Struct of Rgb's and fix the pixels
 /// at their expected position. Returns a fully decompressed image.
 ///
-/// When decompressing the 2x2 Block of pixels, the blocks are stored in row-major order. The
-/// main purpose of this function is to return the blocks back to block 2x2 order.
+/// When decompressing the 2x2 Block of pixels, the blocks are stored in row-major order, with
+/// the four pixels of every block in turn in `BlockLayout::RowMajor` order. The main purpose of
+/// this function is to move every pixel back to its place in the image.
 ///
 /// # Arguments
 /// * `image`: Array2 Struct of Rgb where blocks are stored in row-major order.
-pub fn fix_pixel_poss(image: Array2<Rgb>) -> Vec<Rgb> {
+pub fn fix_pixel_poss<P: Pixel>(image: &Array2<P>) -> Vec<P> {
     let width = image.get_width();
     let height = image.get_height();
-    let mut output: Vec<Rgb> = vec![
-        Rgb {
-            red: 0,
-            blue: 0,
-            green: 0
-        };
-        width * height
-    ];
-    let mut counter = 0;
-    for r in (0..height).step_by(2) {
-        for c in (0..width).step_by(2) {
-            let mut idx = r * width + c;
-            output[idx] = image.data[counter].clone();
-            counter += 1;
-            idx = r * width + (c + 1);
-            output[idx] = image.data[counter].clone();
-            counter += 1;
-            idx = (r + 1) * width + c;
-            output[idx] = image.data[counter].clone();
-            counter += 1;
-            idx = (r + 1) * width + (c + 1);
-            output[idx] = image.data[counter].clone();
-            counter += 1;
+    let mut output: Vec<P> = vec![P::default(); width * height];
+    let layout = BlockLayout::RowMajor;
+    let positions = (0..height).step_by(2).flat_map(|r| {
+        (0..width).step_by(2).flat_map(move |c| {
+            (0..4).map(move |index| {
+                let (x, y) = layout.offset(index);
+                (r + y) * width + c + x
+            })
+        })
+    });
+    for (position, pixel) in positions.zip(image.data.iter()) {
+        if let Some(slot) = output.get_mut(position) {
+            *slot = pixel.clone();
         }
     }
     output
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{FINE_CHROMA_LAYOUT, NARROW_LAYOUT, WIDE_LAYOUT};
+
+    #[test]
+    fn pixels_convert_between_rgb_and_component_video() {
+        let pixel = Rgb {
+            red: 200,
+            green: 100,
+            blue: 50,
+        };
+        let floats = compute_rgb_floats(&pixel, 255.0);
+        assert_eq!(floats.green, 100.0 / 255.0);
+        let video = compute_component_video(&floats);
+        assert!((video.y - (0.299 * 200.0 + 0.587 * 100.0 + 0.114 * 50.0) / 255.0).abs() < 1e-12);
+        let back = component_back_to_rgb_floats(&video);
+        for (decoded, original) in [(back.red, 200.0), (back.green, 100.0), (back.blue, 50.0)] {
+            assert!((decoded - original).abs() < 1e-3);
+        }
+        let gray = compute_component_video(&compute_rgb_floats(
+            &Rgb {
+                red: 9,
+                green: 9,
+                blue: 9,
+            },
+            9.0,
+        ));
+        assert!(gray.pb.abs() < 1e-12 && gray.pr.abs() < 1e-12);
+
+        let float = RgbFloats {
+            red: 10.2,
+            green: 10.8,
+            blue: 7.0,
+        };
+        assert_eq!(
+            from_rgb_float_to_rgb(&float),
+            Rgb {
+                red: 10,
+                green: 10,
+                blue: 7
+            }
+        );
+        assert_eq!(
+            from_rgb_float_to_rgb_dithered(&float, 0.5),
+            Rgb {
+                red: 10,
+                green: 11,
+                blue: 7
+            }
+        );
+        let mut thresholds: Vec<f64> = (0..16).map(|i| dither_threshold(i % 4, i / 4)).collect();
+        thresholds.sort_by(f64::total_cmp);
+        assert_eq!(thresholds.first(), Some(&(0.5 / 16.0)));
+        assert_eq!(thresholds.last(), Some(&(15.5 / 16.0)));
+        assert_eq!(dither_threshold(1, 2), dither_threshold(5, 6));
+    }
+
+    #[test]
+    fn blocks_are_gathered_and_scattered_in_order() {
+        use crate::pixel::Gray;
+
+        for (width, height) in [(2, 2), (4, 2), (2, 6), (6, 4), (10, 8)] {
+            let gray = Array2::from_row_major(
+                width,
+                height,
+                (0..width * height)
+                    .map(|i| Gray { value: i as u16 })
+                    .collect(),
+            );
+            let image = pixels_to_component_video(&gray, 1);
+            assert_eq!(
+                image.data,
+                from_blocks_to_component_format(&component_video_to_blocks(&image)).data
+            );
+            let blocks = component_video_to_blocks(&image);
+            assert_eq!(blocks.data.len(), width * height / 4);
+            for (index, block) in blocks.data.iter().enumerate() {
+                let (col, row) = ((index % (width / 2)) * 2, (index / (width / 2)) * 2);
+                let at = |x: usize, y: usize| (y * width + x) as f64;
+                assert_eq!(block.top_left().y, at(col, row));
+                assert_eq!(block.top_right().y, at(col + 1, row));
+                assert_eq!(block.bottom_left().y, at(col, row + 1));
+                assert_eq!(block.bottom_right().y, at(col + 1, row + 1));
+                assert_eq!(get_block(&image, row, col), *block);
+                for layout in [BlockLayout::RowMajor, BlockLayout::ColumnMajor] {
+                    assert_eq!(Block::from_pixels(block.pixels(layout), layout), *block);
+                }
+            }
+
+            let by_block = from_blocks_to_component_format(&blocks);
+            let rgb: Vec<Rgb> = by_block
+                .data
+                .iter()
+                .map(|pixel| Rgb {
+                    red: pixel.y as u16,
+                    green: 0,
+                    blue: 0,
+                })
+                .collect();
+            let pixels = fix_pixel_poss(&Array2::from_row_major(width, height, rgb));
+            let reds: Vec<u16> = pixels.iter().map(|pixel| pixel.red).collect();
+            assert_eq!(reds, (0..(width * height) as u16).collect::<Vec<u16>>());
+        }
+    }
+
+    #[test]
+    fn coefficients_survive_packing_in_every_layout() {
+        let coefficient = DCTCoefficient {
+            a: 200.0,
+            b: -3.0,
+            c: 2.0,
+            d: 0.0,
+            index_of_pb: 7,
+            index_of_pr: 11,
+        };
+        let coefficients = Array2::from_row_major(2, 2, vec![coefficient]);
+        for layout in [NARROW_LAYOUT, WIDE_LAYOUT, FINE_CHROMA_LAYOUT] {
+            let words = pack_values_into_word(&coefficients, &layout);
+            let unpacked = unpack_values(&words.data, 2, 2, &layout).unwrap();
+            assert_eq!(unpacked.data, coefficients.data);
+        }
+        assert!(unpack_values(&[0, 0], 2, 2, &NARROW_LAYOUT).is_err());
+    }
+}
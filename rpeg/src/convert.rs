@@ -0,0 +1,312 @@
+use crate::codec::stats::Timings;
+use crate::codec::{decompress_with_options, DecodeOptions, EncoderOptions};
+use crate::color_tag::ColorTag;
+use crate::encoder::Encoder;
+use crate::error::{CliError, ErrorKind};
+use crate::format::{Header, MAGIC};
+use crate::hdr::{compress_hdr_image, HdrImage, ToneMap};
+use crate::io::{read_input, Output};
+use crate::png_image::{read_png, read_png_color_tag, write_png, PNG_SIGNATURE};
+use crate::ppm::RgbImage;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## File format `rpeg convert` reads or writes
+///
+/// Inputs are told apart by their magic number and outputs by their extension. JPEG images
+/// can only be read, with the `jpeg` feature.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::convert::ImageFormat;
+///
+/// assert_eq!(ImageFormat::of_filename("scan.PNG"), Some(ImageFormat::Png));
+/// assert_eq!(ImageFormat::of_bytes(b"RPEG\x01"), ImageFormat::Rpeg);
+/// assert_eq!(ImageFormat::of_bytes(b"P6\n1 1\n255\n"), ImageFormat::Ppm);
+/// ```
+pub enum ImageFormat {
+    Rpeg,
+    Png,
+    Ppm,
+    Pfm,
+    Jpeg,
+}
+
+impl ImageFormat {
+    /// Returns the format of a file from its first bytes. Anything that is not an rpeg, PNG,
+    /// PFM, or JPEG file is read as a PPM.
+    ///
+    /// # Arguments
+    /// * `bytes`: Contents of the file
+    pub fn of_bytes(bytes: &[u8]) -> ImageFormat {
+        if bytes.starts_with(MAGIC) || Header::read(bytes).is_ok() {
+            ImageFormat::Rpeg
+        } else if bytes.starts_with(&PNG_SIGNATURE) {
+            ImageFormat::Png
+        } else if bytes.starts_with(b"PF") || bytes.starts_with(b"Pf") {
+            ImageFormat::Pfm
+        } else if bytes.starts_with(&[0xFF, 0xD8]) {
+            ImageFormat::Jpeg
+        } else {
+            ImageFormat::Ppm
+        }
+    }
+
+    /// Returns the format named by the extension of `filename`, ignoring case, or None if the
+    /// extension is unknown.
+    ///
+    /// # Arguments
+    /// * `filename`: Name of the file
+    pub fn of_filename(filename: &str) -> Option<ImageFormat> {
+        let (_, extension) = filename.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "rpeg" => Some(ImageFormat::Rpeg),
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "pfm" => Some(ImageFormat::Pfm),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            _ => None,
+        }
+    }
+}
+
+/// ## Image held in memory between the decoding and the encoding of `rpeg convert`
+///
+/// A standard image keeps the metadata its rpeg file is written with: that of the settings,
+/// plus the color tag of the input unless the settings set one.
+pub enum Decoded {
+    Standard {
+        image: RgbImage,
+        metadata: Vec<(String, String)>,
+    },
+    Hdr(HdrImage),
+}
+
+/// Returns `metadata` with `color_tag` added, unless it already holds a tag.
+///
+/// # Arguments
+/// * `metadata`: Metadata of the settings
+/// * `color_tag`: Color tag of the input, if any
+fn tagged(
+    metadata: &[(String, String)],
+    color_tag: Option<ColorTag>,
+) -> Result<Vec<(String, String)>, String> {
+    match color_tag {
+        Some(tag) if ColorTag::from_metadata(metadata)?.is_none() => Ok(tag.replace_in(metadata)),
+        _ => Ok(metadata.to_vec()),
+    }
+}
+
+/// Decodes a file of any format `ImageFormat::of_bytes` recognizes. Compressed images are
+/// decoded with `decode_options`, and HDR ones are kept in light unless they are tone mapped.
+///
+/// # Arguments
+/// * `bytes`: Contents of the file
+/// * `encoder_options`: Settings the image is compressed with, whose metadata is kept
+/// * `decode_options`: Settings used to decompress a compressed image
+pub fn decode_any(
+    bytes: &[u8],
+    encoder_options: &EncoderOptions,
+    decode_options: &DecodeOptions,
+) -> Result<Decoded, String> {
+    let metadata = &encoder_options.metadata;
+    let standard = |image, color_tag| {
+        Ok(Decoded::Standard {
+            image,
+            metadata: tagged(metadata, color_tag)?,
+        })
+    };
+    match ImageFormat::of_bytes(bytes) {
+        ImageFormat::Rpeg => {
+            let (header, _) = Header::read(bytes)?;
+            let image = decompress_with_options(bytes, decode_options)?;
+            match header.hdr_exponent {
+                Some(exponent) if decode_options.tone_map == ToneMap::None => {
+                    Ok(Decoded::Hdr(HdrImage::from_coded(&image, exponent)))
+                }
+                _ => standard(image, ColorTag::from_metadata(&header.metadata)?),
+            }
+        }
+        ImageFormat::Png => standard(read_png(bytes)?, read_png_color_tag(bytes)?),
+        ImageFormat::Ppm => standard(RgbImage::from_reader(bytes)?, None),
+        ImageFormat::Pfm => HdrImage::from_reader(bytes).map(Decoded::Hdr),
+        #[cfg(feature = "jpeg")]
+        ImageFormat::Jpeg => {
+            let (image, orientation, color_tag) = crate::jpeg::read_jpeg(bytes)?;
+            let (image, options) =
+                crate::jpeg::prepare(image, orientation, color_tag, encoder_options);
+            Ok(Decoded::Standard {
+                image,
+                metadata: options.metadata,
+            })
+        }
+        #[cfg(not(feature = "jpeg"))]
+        ImageFormat::Jpeg => {
+            Err("Reading JPEG images needs rpeg built with the jpeg feature".to_string())
+        }
+    }
+}
+
+/// Encodes a decoded image in `format`. HDR images need a tone mapper in `decode_options` to
+/// be written as PNG or PPM, and standard images are written to PFM with their densities
+/// between 0 and 1.
+///
+/// # Arguments
+/// * `decoded`: Image returned by `decode_any`
+/// * `format`: Format of the output
+/// * `encoder_options`: Settings used to compress the image
+/// * `decode_options`: Settings holding the tone mapper
+pub fn encode_as(
+    decoded: &Decoded,
+    format: ImageFormat,
+    encoder_options: &EncoderOptions,
+    decode_options: &DecodeOptions,
+) -> Result<Vec<u8>, String> {
+    let standard;
+    let (image, metadata) = match (decoded, format) {
+        (Decoded::Hdr(image), ImageFormat::Rpeg) => {
+            return compress_hdr_image(image, encoder_options, &Timings::new())
+                .map(|(bytes, _)| bytes);
+        }
+        (Decoded::Hdr(image), ImageFormat::Pfm) => {
+            let mut bytes = Vec::new();
+            image.write_to(&mut bytes)?;
+            return Ok(bytes);
+        }
+        (Decoded::Hdr(_), _) if decode_options.tone_map == ToneMap::None => {
+            return Err("HDR images are written as PNG or PPM only with a --tone-map".to_string());
+        }
+        (Decoded::Hdr(image), _) => {
+            standard = decode_options.tone_map.to_standard(image);
+            (&standard, &encoder_options.metadata)
+        }
+        (Decoded::Standard { image, metadata }, _) => (image, metadata),
+    };
+    let mut bytes = Vec::new();
+    match format {
+        ImageFormat::Rpeg => {
+            let options = EncoderOptions {
+                metadata: metadata.clone(),
+                ..encoder_options.clone()
+            };
+            bytes = Encoder::from(options).compress(image)?;
+        }
+        ImageFormat::Png => bytes = write_png(image, ColorTag::from_metadata(metadata)?.as_ref())?,
+        ImageFormat::Ppm => image.write_to(&mut bytes)?,
+        ImageFormat::Pfm => HdrImage::from_decoded(image, None).write_to(&mut bytes)?,
+        ImageFormat::Jpeg => return Err("JPEG images can only be read".to_string()),
+    }
+    Ok(bytes)
+}
+
+/// Converts the image `input` to the format named by the extension of `output`, decoding and
+/// encoding it in memory, without an intermediate file.
+///
+/// # Arguments
+/// * `input`: Location of an rpeg, PNG, PPM, PFM, or JPEG image
+/// * `output`: Destination of the converted image, ending in `.rpeg`, `.png`, `.ppm`, or `.pfm`
+/// * `encoder_options`: Settings used to compress the image
+/// * `decode_options`: Settings used to decompress the image
+pub fn convert(
+    input: &str,
+    output: &Output,
+    encoder_options: &EncoderOptions,
+    decode_options: &DecodeOptions,
+) -> Result<(), CliError> {
+    let filename = output.filename.as_deref().unwrap_or_default();
+    let format = ImageFormat::of_filename(filename)
+        .filter(|format| *format != ImageFormat::Jpeg)
+        .ok_or_else(|| {
+            CliError::new(
+                ErrorKind::BadArguments,
+                format!("Cannot tell the format of {filename}: use .rpeg, .png, .ppm, or .pfm"),
+            )
+        })?;
+    let bytes = read_input(Some(input))
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let decoded = decode_any(&bytes, encoder_options, decode_options).map_err(|message| {
+        match ImageFormat::of_bytes(&bytes) {
+            ImageFormat::Rpeg => CliError::stream(&bytes, message),
+            _ => CliError::new(ErrorKind::UnreadableInput, message),
+        }
+    })?;
+    let converted = encode_as(&decoded, format, encoder_options, decode_options)
+        .map_err(|message| CliError::new(ErrorKind::BadArguments, message))?;
+    output
+        .write(&converted)
+        .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::compress_image;
+    use crate::fixed::Arithmetic;
+    use crate::ppm::Rgb;
+
+    #[test]
+    fn images_convert_between_formats_in_memory() {
+        let image = RgbImage {
+            pixels: (0..64_u16)
+                .map(|i| Rgb {
+                    red: i * 4,
+                    green: 128,
+                    blue: 255 - i * 4,
+                })
+                .collect(),
+            width: 8,
+            height: 8,
+            denominator: 255,
+        };
+        let encoder_options = EncoderOptions {
+            arithmetic: Arithmetic::Float,
+            ..Default::default()
+        };
+        let decode_options = DecodeOptions {
+            arithmetic: Arithmetic::Float,
+            ..Default::default()
+        };
+        let convert = |bytes: &[u8], format| {
+            let decoded = decode_any(bytes, &encoder_options, &decode_options).unwrap();
+            encode_as(&decoded, format, &encoder_options, &decode_options).unwrap()
+        };
+        let mut ppm = Vec::new();
+        image.write_to(&mut ppm).unwrap();
+        let compressed = convert(&ppm, ImageFormat::Rpeg);
+        assert_eq!(compressed, compress_image(&image, &encoder_options));
+        let mut decompressed = Vec::new();
+        let decoded = decompress_with_options(&compressed, &decode_options).unwrap();
+        decoded.write_to(&mut decompressed).unwrap();
+        assert_eq!(convert(&compressed, ImageFormat::Ppm), decompressed);
+        let png = read_png(&convert(&compressed, ImageFormat::Png)).unwrap();
+        assert_eq!((png.width, png.height), (decoded.width, decoded.height));
+
+        let profile = ColorTag::Icc(b"wide gamut".to_vec());
+        let tagged_png = write_png(&image, Some(&profile)).unwrap();
+        let compressed = convert(&tagged_png, ImageFormat::Rpeg);
+        let (header, _) = Header::read(&compressed).unwrap();
+        assert_eq!(ColorTag::from_metadata(&header.metadata), Ok(Some(profile)));
+
+        let hdr = HdrImage {
+            pixels: vec![[4.0, 1.0, 0.25]; 4 * 4],
+            width: 4,
+            height: 4,
+        };
+        let mut pfm = Vec::new();
+        hdr.write_to(&mut pfm).unwrap();
+        let compressed = convert(&pfm, ImageFormat::Rpeg);
+        assert!(Header::read(&compressed).unwrap().0.hdr_exponent.is_some());
+        let decoded = decode_any(&compressed, &encoder_options, &decode_options).unwrap();
+        assert!(encode_as(
+            &decoded,
+            ImageFormat::Png,
+            &encoder_options,
+            &decode_options
+        )
+        .is_err());
+        assert!(matches!(
+            ImageFormat::of_bytes(&convert(&compressed, ImageFormat::Pfm)),
+            ImageFormat::Pfm
+        ));
+    }
+}
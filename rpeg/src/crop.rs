@@ -0,0 +1,142 @@
+use crate::codec::{output_rect, read_code_words, read_prelude, write_code_words, DecodeOptions};
+use crate::content::TileMode;
+use crate::error::{CliError, ErrorKind};
+use crate::io::{read_input, Output};
+use crate::tiles::Rect;
+
+/// Crops a compressed image to the 2x2 blocks covering `rect`, without going back to pixels:
+/// the rectangle is clipped to the image and widened to block boundaries, the code words and
+/// region levels of the blocks it covers are kept, and the header is rewritten with the new
+/// dimensions. An odd side keeps its padding when the crop reaches it. The image is split
+/// again into tiles of the same size, and its thumbnail, which no longer matches, is dropped.
+/// The refinement of a near-lossless image keeps the corrections of the blocks covered.
+/// Returns the cropped image and the rectangle it covers, or an error for palette-coded, text,
+/// and raw tiles, and for a rectangle outside of the image.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+/// * `rect`: Rectangle of the image to keep
+pub fn crop_image(bytes: &[u8], rect: Rect) -> Result<(Vec<u8>, Rect), String> {
+    let levels = read_prelude(bytes, &DecodeOptions::default())?.levels;
+    let (mut header, words) = read_code_words(bytes)?;
+    if header
+        .tile_modes
+        .iter()
+        .any(|mode| *mode != TileMode::Photo)
+    {
+        return Err("Text tiles cannot be cropped in the compressed domain".to_string());
+    }
+    let clipped = output_rect(&header, Some(rect))?;
+    let (left, top) = (clipped.x & !1, clipped.y & !1);
+    let right = (clipped.x + clipped.width).next_multiple_of(2);
+    let bottom = (clipped.y + clipped.height).next_multiple_of(2);
+    let columns = header.coded_width() as usize / 2;
+    let mut kept_words = Vec::new();
+    let mut kept_levels = Vec::new();
+    for row in top / 2..bottom / 2 {
+        let start = row as usize * columns;
+        let blocks = start + left as usize / 2..start + right as usize / 2;
+        kept_words.extend_from_slice(&words[blocks.clone()]);
+        kept_levels.extend_from_slice(&levels[blocks]);
+    }
+    let covered = Rect {
+        x: left,
+        y: top,
+        width: right.min(header.width) - left,
+        height: bottom.min(header.height) - top,
+    };
+    header.refinement = header
+        .refinement
+        .map(|refinement| refinement.crop(header.width, header.height, &covered))
+        .transpose()?;
+    (header.width, header.height) = (covered.width, covered.height);
+    header.thumbnail = None;
+    Ok((write_code_words(header, &kept_levels, &kept_words), covered))
+}
+
+/// Crops the compressed image `filename` with `crop_image` and writes the result to `output`.
+/// Failures are returned with the category the CLI exits with.
+///
+/// # Arguments
+/// * `filename`: Compressed image to crop, or None to read from standard in
+/// * `output`: Destination of the cropped image
+/// * `rect`: Rectangle of the image to keep, or None when `--rect` was not given
+pub fn crop(filename: Option<&str>, output: &Output, rect: Option<Rect>) -> Result<(), CliError> {
+    let rect =
+        rect.ok_or_else(|| CliError::new(ErrorKind::BadArguments, "crop expects --rect x,y,w,h"))?;
+    let bytes = read_input(filename)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let (cropped, _) = crop_image(&bytes, rect).map_err(|message| {
+        if read_code_words(&bytes).is_ok() {
+            CliError::new(ErrorKind::BadArguments, message)
+        } else {
+            CliError::stream(&bytes, message)
+        }
+    })?;
+    output
+        .write(&cropped)
+        .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{compress_image, decompress_image, decompress_with_options, EncoderOptions};
+    use crate::ppm::{Rgb, RgbImage};
+    use crate::roi::Region;
+
+    #[test]
+    fn cropped_blocks_decode_to_the_same_pixels() {
+        let image = RgbImage {
+            pixels: (0..21 * 14_u16)
+                .map(|i| Rgb {
+                    red: (i % 21) * 12,
+                    green: (i / 21) * 18,
+                    blue: (i * 29) % 256,
+                })
+                .collect(),
+            width: 21,
+            height: 14,
+            denominator: 255,
+        };
+        let options = EncoderOptions {
+            tile_size: 8,
+            progressive: true,
+            pad: crate::encoder::PadPolicy::Replicate,
+            regions: vec![Region {
+                x: 4,
+                y: 2,
+                width: 8,
+                height: 6,
+                quality: 90,
+            }],
+            embed_thumbnail: true,
+            ..EncoderOptions::default()
+        };
+        let compressed = compress_image(&image, &options);
+        let rect = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        let crops = [
+            (rect(3, 1, 6, 5), rect(2, 0, 8, 6)),
+            (rect(10, 6, 40, 40), rect(10, 6, 11, 8)),
+            (rect(0, 0, 21, 14), rect(0, 0, 21, 14)),
+        ];
+        for (asked, expected) in crops {
+            let (cropped, covered) = crop_image(&compressed, asked).unwrap();
+            assert_eq!(covered, expected);
+            let region = DecodeOptions {
+                region: Some(covered),
+                ..DecodeOptions::default()
+            };
+            assert_eq!(
+                decompress_image(&cropped).unwrap(),
+                decompress_with_options(&compressed, &region).unwrap()
+            );
+        }
+        assert!(crop_image(&compressed, rect(30, 0, 4, 4)).is_err());
+    }
+}
@@ -1,66 +1,430 @@
-use crate::structs::{Block, ComponentVideo, DCTCoefficient};
-use csc411_arith::{chroma_of_index, index_of_chroma};
+use crate::chroma::ChromaQuantizer;
+use crate::conversions::component_back_to_rgb_floats;
+use crate::layout::{ChromaCoding, WordLayout};
+use crate::structs::{
+    Block, ComponentVideo, DCTCoefficient, LiftedBlock, RgbFloats, TransformedBlock,
+};
 
-/// This function compute the DCTCoefficient of a 2x2 Block of ComponentVideos. It serves
-/// as a helper function for block_to_dct.
+/// Range in which b, c, and d are clamped before quantization when no quality is requested.
+pub const DEFAULT_LUMA_RANGE: f64 = 0.3;
+
+/// Largest range b, c, and d can be clamped to. Since every luma lies between 0 and 1, no
+/// coefficient is ever clipped with this range, which is what high-contrast images such as text
+/// and line art need.
+pub const MAX_LUMA_RANGE: f64 = 0.5;
+
+/// Powers 2^(i/25) for i from 0 to 24, correctly rounded. Qualities are mapped to ranges
+/// with them instead of `powf`, whose last bit may differ between platforms.
+const QUALITY_STEPS: [f64; 25] = [
+    1.0,
+    1.0281138266560665,
+    1.0570180405613803,
+    1.086734862526058,
+    1.11728713807222,
+    1.148698354997035,
+    1.1809926614295303,
+    1.214194884395047,
+    1.2483305489016119,
+    1.2834258975629043,
+    1.3195079107728942,
+    1.3566043274476718,
+    1.3947436663504054,
+    1.4339552480158273,
+    1.4742692172911012,
+    1.515716566510398,
+    1.5583291593209998,
+    1.6021397551792442,
+    1.6471820345351462,
+    1.6934906247250543,
+    1.7411011265922482,
+    1.790050141855945,
+    1.8403753012497501,
+    1.8921152934511918,
+    1.945309894824571,
+];
+
+/// Takes a quality between 0 and 100 and returns the range b, c, and d are clamped to before
+/// being quantized into their signed fields. Quality 50 gives the default range of 0.3; every 25
+/// points above it halve the range, and with it the quantization step, while lower qualities
+/// widen it up to 0.5, the largest value b, c, or d can take.
+///
+/// A narrower range gives finer steps to the small coefficients found in smooth areas, at the
+/// cost of clipping the large coefficients found along high-contrast edges.
+///
+/// # Arguments
+/// * `quality`: Quality between 0 and 100
+pub fn luma_range_for_quality(quality: u8) -> f64 {
+    let steps = 50 - quality.min(100) as i32;
+    let (octaves, step) = (steps.div_euclid(25), steps.rem_euclid(25));
+    (DEFAULT_LUMA_RANGE * QUALITY_STEPS[step as usize] * 2_f64.powi(octaves)).min(MAX_LUMA_RANGE)
+}
+
+/// Weight of the luma range of the darkest and brightest blocks under perceptual quantization.
+const MASKED_WEIGHT: f64 = 1.5;
+
+/// Weight of the luma range of the mid-gray blocks under perceptual quantization.
+const UNMASKED_WEIGHT: f64 = 0.75;
+
+/// Returns the range b, c, and d of a block are clamped to under perceptual quantization. The
+/// eye notices errors the least in very dark and very bright blocks, so their range is widened,
+/// clipping fewer edges at the cost of coarser steps, while the range of mid-gray blocks is
+/// narrowed to give them finer steps. The weight only depends on the quantized `a` of the
+/// block, which the decoder reads before b, c, and d.
+///
+/// # Arguments
+/// * `luma_range`: Range of the block without perceptual quantization
+/// * `a`: Quantized average luma of the block, as stored in the code word
+/// * `layout`: Layout of the code word of the block
+pub fn masked_luma_range(luma_range: f64, a: f64, layout: &WordLayout) -> f64 {
+    let distance = (a / layout.a_scale()).clamp(0.0, 1.0) * 2.0 - 1.0;
+    let weight = UNMASKED_WEIGHT + (MASKED_WEIGHT - UNMASKED_WEIGHT) * distance * distance;
+    (luma_range * weight).clamp(0.001, MAX_LUMA_RANGE)
+}
+
+/// Returns the a, b, c, and d luma coefficients of a 2x2 block, before any quantization.
+fn luma_coefficients(block: &Block) -> [f64; 4] {
+    let denominator: f64 = 4.0;
+    let (y1, y2, y3, y4) = (block.y1.y, block.y2.y, block.y3.y, block.y4.y);
+    [
+        (y4 + y3 + y2 + y1) / denominator,
+        (y4 + y3 - y2 - y1) / denominator,
+        (y4 - y3 + y2 - y1) / denominator,
+        (y4 - y3 - y2 + y1) / denominator,
+    ]
+}
+
+/// Returns how many of the b, c, and d coefficients of a block fall outside of `luma_range`,
+/// and are therefore clipped by `compute_dct`.
+///
+/// # Arguments
+/// `block`: 2x2 block of ComponentVideo
+/// `luma_range`: Range b, c, and d are clamped to before quantization
+pub fn clipped_coefficients(block: &Block, luma_range: f64) -> usize {
+    luma_coefficients(block)[1..]
+        .iter()
+        .filter(|coefficient| coefficient.abs() > luma_range)
+        .count()
+}
+
+/// Returns the sum of the absolute differences between the average Pb and Pr of a block and
+/// the chroma of the indices they were quantized to.
 ///
 /// # Arguments
 /// `block`: 2x2 block of ComponentVideo
-pub fn compute_dct(block: Block) -> DCTCoefficient {
+/// `indices`: Pb and Pr indices of the code word of the block
+/// `layout`: Layout of the code word
+pub fn chroma_error(block: &Block, indices: (usize, usize), layout: &WordLayout) -> f64 {
+    transform_chroma_error(&transform_block(block), indices, layout)
+}
+
+/// Returns the chroma error of a block like `chroma_error`, from its transform.
+pub(crate) fn transform_chroma_error(
+    transform: &TransformedBlock,
+    indices: (usize, usize),
+    layout: &WordLayout,
+) -> f64 {
+    (transform.pb - dequantize_chroma(indices.0, layout)).abs()
+        + (transform.pr - dequantize_chroma(indices.1, layout)).abs()
+}
+
+/// Quantizes an average chroma, scaled by the chroma weight of `layout`, into its field.
+pub(crate) fn quantize_chroma(chroma: f64, layout: &WordLayout) -> usize {
+    let chroma = chroma * layout.chroma_scale();
+    match layout.chroma {
+        ChromaCoding::Indexed(table) => table.index_of_chroma(chroma as f32),
+        ChromaCoding::Direct => {
+            let levels = layout.chroma_levels();
+            ((chroma.clamp(-0.5, 0.5) * 2.0 * levels).round() + levels) as usize
+        }
+    }
+}
+
+/// Returns the chroma a field of `layout` was quantized from.
+pub(crate) fn dequantize_chroma(index: usize, layout: &WordLayout) -> f64 {
+    let chroma = match layout.chroma {
+        ChromaCoding::Indexed(table) => table.chroma_of_index(index) as f64,
+        ChromaCoding::Direct => {
+            let levels = layout.chroma_levels();
+            (index as f64 - levels) / (2.0 * levels)
+        }
+    };
+    chroma / layout.chroma_scale()
+}
+
+/// Returns the 2x2 transform of a block of ComponentVideo: its a, b, c, and d luma
+/// coefficients and its average Pb and Pr, before any quantization.
+///
+/// # Arguments
+/// `block`: 2x2 block of ComponentVideo
+pub fn transform_block(block: &Block) -> TransformedBlock {
     let denominator: f64 = 4.0;
-    let y1 = block.y1;
-    let y2 = block.y2;
-    let y3 = block.y3;
-    let y4 = block.y4;
-    let a = (y4.y + y3.y + y2.y + y1.y) / denominator;
-    let b = (y4.y + y3.y - y2.y - y1.y) / denominator;
-    let c = (y4.y - y3.y + y2.y - y1.y) / denominator;
-    let d = (y4.y - y3.y - y2.y + y1.y) / denominator;
-    let average_pb = (y1.pb + y2.pb + y3.pb + y4.pb) / denominator;
-    let average_pr = (y1.pr + y2.pr + y3.pr + y4.pr) / denominator;
-    // Quantized DCTCoefficients values
+    let [a, b, c, d] = luma_coefficients(block);
+    let (y1, y2, y3, y4) = (&block.y1, &block.y2, &block.y3, &block.y4);
+    TransformedBlock {
+        a,
+        b,
+        c,
+        d,
+        pb: (y1.pb + y2.pb + y3.pb + y4.pb) / denominator,
+        pr: (y1.pr + y2.pr + y3.pr + y4.pr) / denominator,
+    }
+}
+
+/// Quantizes the transform of a block into the values packed into a code word: a is scaled
+/// to its unsigned field, b, c, and d are clamped to `luma_range` and scaled to their signed
+/// fields, and the chroma values are quantized as `layout` says.
+///
+/// # Arguments
+/// `transform`: Transform of the block, as returned by `transform_block`
+/// `luma_range`: Range b, c, and d are clamped to before quantization
+/// `layout`: Layout of the code word the coefficient is quantized for
+pub fn quantize(
+    transform: &TransformedBlock,
+    luma_range: f64,
+    layout: &WordLayout,
+) -> DCTCoefficient {
+    let scale = layout.bcd_levels() / luma_range;
     DCTCoefficient {
-        a: (a * 511.0).round(),
-        b: (b.clamp(-0.3, 0.3) * 50.0).round(),
-        c: (c.clamp(-0.3, 0.3) * 50.0).round(),
-        d: (d.clamp(-0.3, 0.3) * 50.0).round(),
-        index_of_pb: index_of_chroma(average_pb as f32),
-        index_of_pr: index_of_chroma(average_pr as f32),
+        a: (transform.a * layout.a_scale()).round(),
+        b: (transform.b.clamp(-luma_range, luma_range) * scale).round(),
+        c: (transform.c.clamp(-luma_range, luma_range) * scale).round(),
+        d: (transform.d.clamp(-luma_range, luma_range) * scale).round(),
+        index_of_pb: quantize_chroma(transform.pb, layout),
+        index_of_pr: quantize_chroma(transform.pr, layout),
     }
 }
 
-/// Takes a DCTCoefficient and converges the coefficient to a 2x2 block of component video;
+/// Returns the transform a quantized coefficient stands for, the inverse of `quantize` up to
+/// the quantization error.
 ///
-/// # Argument
-/// * `coefficient`: DCTCoefficient storing the information of the 2x2 block of pixels
-pub fn from_dct_to_block(coefficient: DCTCoefficient) -> Block {
-    // Quantized representation of DCTCoefficient
-    let a = (coefficient.a / 511.0).clamp(0.0, 1.0);
-    let b = (coefficient.b / 50.0).clamp(-0.3, 0.3);
-    let c = (coefficient.c / 50.0).clamp(-0.3, 0.3);
-    let d = (coefficient.d / 50.0).clamp(-0.3, 0.3);
-    // Compute the y value for each block
-    let y1 = a - b - c + d;
-    let y2 = a - b + c - d;
-    let y3 = a + b - c - d;
-    let y4 = a + b + c + d;
-    // Get the lumin of each block
-    let pb = chroma_of_index(coefficient.index_of_pb) as f64;
-    let pr = chroma_of_index(coefficient.index_of_pr) as f64;
-    // get block
-    let top_left = dct_to_component_video(y1, pb, pr);
-    let top_right = dct_to_component_video(y2, pb, pr);
-    let bottom_left = dct_to_component_video(y3, pb, pr);
-    let bottom_right = dct_to_component_video(y4, pb, pr);
+/// # Arguments
+/// `coefficient`: Quantized values of the block
+/// `luma_range`: Range b, c, and d were clamped to when the block was quantized
+/// `layout`: Layout of the code word the coefficient was unpacked from
+pub fn dequantize(
+    coefficient: &DCTCoefficient,
+    luma_range: f64,
+    layout: &WordLayout,
+) -> TransformedBlock {
+    let scale = layout.bcd_levels() / luma_range;
+    TransformedBlock {
+        a: (coefficient.a / layout.a_scale()).clamp(0.0, 1.0),
+        b: (coefficient.b / scale).clamp(-luma_range, luma_range),
+        c: (coefficient.c / scale).clamp(-luma_range, luma_range),
+        d: (coefficient.d / scale).clamp(-luma_range, luma_range),
+        pb: dequantize_chroma(coefficient.index_of_pb, layout),
+        pr: dequantize_chroma(coefficient.index_of_pr, layout),
+    }
+}
 
+/// Returns the 2x2 block of ComponentVideo a transform stands for, the exact inverse of
+/// `transform_block` up to the chroma, which is the average of the block for every pixel.
+///
+/// # Arguments
+/// `transform`: Transform of the block
+pub fn inverse_transform(transform: &TransformedBlock) -> Block {
+    let TransformedBlock { a, b, c, d, pb, pr } = *transform;
     Block {
-        y1: top_left,
-        y2: top_right,
-        y3: bottom_left,
-        y4: bottom_right,
+        y1: dct_to_component_video(a - b - c + d, pb, pr),
+        y2: dct_to_component_video(a - b + c - d, pb, pr),
+        y3: dct_to_component_video(a + b - c - d, pb, pr),
+        y4: dct_to_component_video(a + b + c + d, pb, pr),
+    }
+}
+
+/// Returns the average, rounded down, and the difference of two integer samples: the lifting
+/// step `unlift_pair` inverts exactly.
+fn lift_pair(first: i32, second: i32) -> (i32, i32) {
+    let difference = second - first;
+    (first + (difference >> 1), difference)
+}
+
+/// Returns the two samples `lift_pair` turned into `low` and `difference`.
+fn unlift_pair(low: i32, difference: i32) -> (i32, i32) {
+    let first = low - (difference >> 1);
+    (first, first + difference)
+}
+
+/// Returns the exact integer 2x2 transform of four samples of a block, such as its luma or one
+/// of its channels as integer densities: a lifting step on each row, then one on each column.
+/// Unlike `transform_block`, no rounding is lost, so `unlift_block` gives the samples back bit
+/// for bit when the transform is not quantized, the base of lossless coding.
+///
+/// # Arguments
+/// `samples`: Samples of the block in `BlockLayout::RowMajor` order
+pub fn lift_block(samples: [i32; 4]) -> LiftedBlock {
+    let [top_left, top_right, bottom_left, bottom_right] = samples;
+    let (top, top_difference) = lift_pair(top_left, top_right);
+    let (bottom, bottom_difference) = lift_pair(bottom_left, bottom_right);
+    let (a, b) = lift_pair(top, bottom);
+    let (c, d) = lift_pair(top_difference, bottom_difference);
+    LiftedBlock { a, b, c, d }
+}
+
+/// Returns the four samples a lifted block stands for, the exact inverse of `lift_block`.
+///
+/// # Arguments
+/// `lifted`: Integer transform of the block
+pub fn unlift_block(lifted: &LiftedBlock) -> [i32; 4] {
+    let (top, bottom) = unlift_pair(lifted.a, lifted.b);
+    let (top_difference, bottom_difference) = unlift_pair(lifted.c, lifted.d);
+    let (top_left, top_right) = unlift_pair(top, top_difference);
+    let (bottom_left, bottom_right) = unlift_pair(bottom, bottom_difference);
+    [top_left, top_right, bottom_left, bottom_right]
+}
+
+/// This function compute the DCTCoefficient of a 2x2 Block of ComponentVideos: the block is
+/// transformed by `transform_block`, then quantized by `quantize`. It serves as a helper
+/// function for block_to_dct.
+///
+/// # Arguments
+/// `block`: 2x2 block of ComponentVideo
+/// `luma_range`: Range b, c, and d are clamped to before quantization
+/// `layout`: Layout of the code word the coefficient is quantized for
+pub fn compute_dct(block: &Block, luma_range: f64, layout: &WordLayout) -> DCTCoefficient {
+    quantize(&transform_block(block), luma_range, layout)
+}
+
+/// Quantizes a block from its transform like `compute_dct`, with the range weighted by the
+/// brightness of the block by `masked_luma_range` when `perceptual` is set, and searched for
+/// the neighbours closest to `block` by `optimize_dct` when it is given. A block whose `a` was moved by
+/// the search onto a different weight is quantized again without it, since the decoder derives
+/// the range from the `a` it reads. Returns the coefficient and the range it was quantized with.
+///
+/// # Arguments
+/// * `transform`: Transform of the block, as returned by `transform_block`
+/// * `luma_range`: Range b, c, and d are clamped to before weighting
+/// * `perceptual`: Weight the range by the brightness of the block
+/// * `layout`: Layout of the code word the coefficient is quantized for
+/// * `block`: Original 2x2 block of ComponentVideo, to search the neighbouring values for the
+///   ones that decode closest to it, or None to round every value on its own
+pub fn quantize_block(
+    transform: &TransformedBlock,
+    luma_range: f64,
+    perceptual: bool,
+    layout: &WordLayout,
+    block: Option<&Block>,
+) -> (DCTCoefficient, f64) {
+    let range = if perceptual {
+        masked_luma_range(
+            luma_range,
+            quantize(transform, luma_range, layout).a,
+            layout,
+        )
+    } else {
+        luma_range
+    };
+    let coefficient = quantize(transform, range, layout);
+    let Some(block) = block else {
+        return (coefficient, range);
+    };
+    let optimized = optimize_dct(block, coefficient, range, layout);
+    if perceptual && masked_luma_range(luma_range, optimized.a, layout) != range {
+        (quantize(transform, range, layout), range)
+    } else {
+        (optimized, range)
     }
 }
 
+/// Returns how many of the b, c, and d coefficients of a transform fall outside of
+/// `luma_range`, and are therefore clipped by `quantize`.
+///
+/// # Arguments
+/// * `transform`: Transform of a block
+/// * `luma_range`: Range b, c, and d are clamped to before quantization
+pub fn clipped_transform(transform: &TransformedBlock, luma_range: f64) -> usize {
+    [transform.b, transform.c, transform.d]
+        .iter()
+        .filter(|coefficient| coefficient.abs() > luma_range)
+        .count()
+}
+
+/// Returns the squared error between the pixels a decoder reconstructs from `coefficient`,
+/// truncated to integers exactly as the decoder does, and the `target` pixels.
+fn reconstruction_error(
+    coefficient: &DCTCoefficient,
+    luma_range: f64,
+    layout: &WordLayout,
+    target: &[RgbFloats; 4],
+) -> f64 {
+    let block = from_dct_to_block(coefficient, luma_range, layout);
+    [&block.y1, &block.y2, &block.y3, &block.y4]
+        .into_iter()
+        .zip(target.iter())
+        .map(|(cv, target)| {
+            let pixel = component_back_to_rgb_floats(cv);
+            [
+                (pixel.red as u16 as f64, target.red),
+                (pixel.green as u16 as f64, target.green),
+                (pixel.blue as u16 as f64, target.blue),
+            ]
+            .iter()
+            .map(|(decoded, target)| (decoded - target) * (decoded - target))
+            .sum::<f64>()
+        })
+        .sum()
+}
+
+/// Takes the quantized DCTCoefficient of a block and searches the neighbouring values of a, b,
+/// c, and d, one quantization step up or down each, for the combination whose decoded pixels
+/// are closest to the original block. Rounding every coefficient on its own ignores how the
+/// decoder clamps and truncates the pixels, so a neighbour often decodes closer to the
+/// original at no cost in size. The chroma indices are kept as they are.
+///
+/// # Arguments
+/// * `block`: Original 2x2 block of ComponentVideo
+/// * `coefficient`: Block quantized by `compute_dct`
+/// * `luma_range`: Range b, c, and d are clamped to before quantization
+/// * `layout`: Layout of the code word the coefficient is quantized for
+pub fn optimize_dct(
+    block: &Block,
+    coefficient: DCTCoefficient,
+    luma_range: f64,
+    layout: &WordLayout,
+) -> DCTCoefficient {
+    let target = [&block.y1, &block.y2, &block.y3, &block.y4].map(component_back_to_rgb_floats);
+    let steps = [0.0, -1.0, 1.0];
+    let levels = layout.bcd_levels();
+    let mut best_error = reconstruction_error(&coefficient, luma_range, layout, &target);
+    let mut best = coefficient;
+    for da in steps {
+        for db in steps {
+            for dc in steps {
+                for dd in steps {
+                    let candidate = DCTCoefficient {
+                        a: (coefficient.a + da).clamp(0.0, layout.a_scale()),
+                        b: (coefficient.b + db).clamp(-levels, levels),
+                        c: (coefficient.c + dc).clamp(-levels, levels),
+                        d: (coefficient.d + dd).clamp(-levels, levels),
+                        ..coefficient
+                    };
+                    let error = reconstruction_error(&candidate, luma_range, layout, &target);
+                    if error < best_error {
+                        best_error = error;
+                        best = candidate;
+                    }
+                }
+            }
+        }
+    }
+    best
+}
+
+/// Takes a DCTCoefficient and converges the coefficient to a 2x2 block of component video:
+/// the coefficient is dequantized by `dequantize`, then inverted by `inverse_transform`.
+///
+/// # Argument
+/// * `coefficient`: DCTCoefficient storing the information of the 2x2 block of pixels
+/// * `luma_range`: Range b, c, and d were clamped to when the block was quantized
+/// * `layout`: Layout of the code word the coefficient was unpacked from
+pub fn from_dct_to_block(
+    coefficient: &DCTCoefficient,
+    luma_range: f64,
+    layout: &WordLayout,
+) -> Block {
+    inverse_transform(&dequantize(coefficient, luma_range, layout))
+}
+
 /// Takes a y, pb, pr and returns a ComponentVideo Struct storing the values;
 ///
 /// # Arguments
@@ -74,3 +438,105 @@ fn dct_to_component_video(y: f64, index_of_pb: f64, index_of_pr: f64) -> Compone
         pr: index_of_pr,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{NARROW_LAYOUT, WIDE_LAYOUT};
+
+    #[test]
+    fn quantization_error_is_bounded_by_half_a_step() {
+        let pixel = |y: f64, pb: f64| ComponentVideo { y, pb, pr: -pb };
+        let block = Block {
+            y1: pixel(0.31, 0.02),
+            y2: pixel(0.47, 0.03),
+            y3: pixel(0.52, 0.01),
+            y4: pixel(0.2, 0.04),
+        };
+        let transform = transform_block(&block);
+        let inverse = inverse_transform(&transform);
+        for (original, decoded) in [
+            (&block.y1, &inverse.y1),
+            (&block.y2, &inverse.y2),
+            (&block.y3, &inverse.y3),
+            (&block.y4, &inverse.y4),
+        ] {
+            assert!((original.y - decoded.y).abs() < 1e-12);
+        }
+
+        for layout in [NARROW_LAYOUT, WIDE_LAYOUT] {
+            let quantized = quantize(&transform, DEFAULT_LUMA_RANGE, &layout);
+            let restored = dequantize(&quantized, DEFAULT_LUMA_RANGE, &layout);
+            let step = DEFAULT_LUMA_RANGE / layout.bcd_levels();
+            assert!((restored.a - transform.a).abs() <= 0.5 / layout.a_scale());
+            for (restored, original) in [
+                (restored.b, transform.b),
+                (restored.c, transform.c),
+                (restored.d, transform.d),
+            ] {
+                assert!((restored - original).abs() <= step / 2.0 + 1e-12);
+            }
+            let decoded = from_dct_to_block(&quantized, DEFAULT_LUMA_RANGE, &layout);
+            assert_eq!(decoded.y4.y, inverse_transform(&restored).y4.y);
+        }
+    }
+
+    #[test]
+    fn lifting_is_exact_and_follows_the_float_transform() {
+        let mut state: u32 = 1;
+        let mut sample = |range: i32| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 8) as i32 % range
+        };
+        for _ in 0..10_000 {
+            let samples = [(); 4].map(|_| sample(65_536) - sample(2) * 32_768);
+            assert_eq!(unlift_block(&lift_block(samples)), samples);
+        }
+        for samples in [[0; 4], [255; 4], [0, 255, 255, 0], [-3, 7, -1, 2]] {
+            assert_eq!(unlift_block(&lift_block(samples)), samples);
+        }
+
+        for samples in [[31, 47, 52, 20], [1, 2, 2, 3], [-9, 4, 0, 13]] {
+            let lifted = lift_block(samples);
+            let pixel = |y: i32| ComponentVideo {
+                y: y as f64,
+                pb: 0.0,
+                pr: 0.0,
+            };
+            let transform = transform_block(&Block {
+                y1: pixel(samples[0]),
+                y2: pixel(samples[1]),
+                y3: pixel(samples[2]),
+                y4: pixel(samples[3]),
+            });
+            assert!((lifted.a as f64 - transform.a).abs() <= 1.0);
+            assert!((lifted.b as f64 - 2.0 * transform.b).abs() <= 1.0);
+            assert!((lifted.c as f64 - 2.0 * transform.c).abs() <= 1.0);
+            assert_eq!(lifted.d as f64, 4.0 * transform.d);
+        }
+    }
+
+    #[test]
+    fn quantized_coefficients_survive_a_round_trip() {
+        let coefficient = DCTCoefficient {
+            a: 301.0,
+            b: -7.0,
+            c: 3.0,
+            d: 0.0,
+            index_of_pb: 5,
+            index_of_pr: 9,
+        };
+        for layout in [NARROW_LAYOUT, WIDE_LAYOUT] {
+            let transform = dequantize(&coefficient, DEFAULT_LUMA_RANGE, &layout);
+            assert_eq!(
+                quantize(&transform, DEFAULT_LUMA_RANGE, &layout),
+                coefficient
+            );
+            let block = inverse_transform(&transform);
+            assert_eq!(
+                block,
+                from_dct_to_block(&coefficient, DEFAULT_LUMA_RANGE, &layout)
+            );
+        }
+    }
+}
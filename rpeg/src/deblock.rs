@@ -0,0 +1,137 @@
+use crate::ppm::{Rgb, RgbImage};
+use array2::array2::Array2;
+
+/// Largest step between the two pixels facing each other across a block boundary that is still
+/// taken for a quantization artifact. Larger steps are real edges of the image and are kept.
+const EDGE_THRESHOLD: i32 = 24;
+
+/// Largest step between the two pixels of the same block, on either side of the boundary, for
+/// that side to count as smooth.
+const FLAT_THRESHOLD: i32 = 12;
+
+/// Returns the red, green, and blue density of a pixel as signed integers.
+fn channels(pixel: &Rgb) -> [i32; 3] {
+    [pixel.red as i32, pixel.green as i32, pixel.blue as i32]
+}
+
+/// Takes the four pixels of a window straddling a block boundary, two on each side, and returns
+/// the new values of the two pixels facing each other across it. Each of them moves a quarter of
+/// the step towards the other, unless the step looks like a real edge of the image.
+///
+/// # Arguments
+/// * `window`: Pixels `p1`, `p0`, `q0`, and `q1`, with the boundary between `p0` and `q0`
+fn smooth_boundary(window: &[&Rgb]) -> Option<(Rgb, Rgb)> {
+    let [p1, p0, q0, q1] = [window[0], window[1], window[2], window[3]].map(channels);
+    let mut before = [0; 3];
+    let mut after = [0; 3];
+    let mut changed = false;
+    for channel in 0..3 {
+        let step = q0[channel] - p0[channel];
+        let flat = (p1[channel] - p0[channel]).abs() <= FLAT_THRESHOLD
+            && (q1[channel] - q0[channel]).abs() <= FLAT_THRESHOLD;
+        let delta = if step.abs() < EDGE_THRESHOLD && flat {
+            step / 4
+        } else {
+            0
+        };
+        changed |= delta != 0;
+        before[channel] = p0[channel] + delta;
+        after[channel] = q0[channel] - delta;
+    }
+    let to_rgb = |pixel: [i32; 3]| Rgb {
+        red: pixel[0] as u16,
+        green: pixel[1] as u16,
+        blue: pixel[2] as u16,
+    };
+    changed.then(|| (to_rgb(before), to_rgb(after)))
+}
+
+/// Smooths every boundary between two 2x2 blocks running in one direction. `vertical` selects
+/// the boundaries between block columns; otherwise the boundaries between block rows are
+/// smoothed. Windows are read from `image` and the smoothed pixels are written to a copy, so that
+/// every boundary sees the unfiltered pixels.
+///
+/// # Arguments
+/// * `image`: Decompressed image
+/// * `vertical`: Smooth the boundaries between block columns instead of block rows
+fn deblock_pass(image: &Array2<Rgb>, vertical: bool) -> Array2<Rgb> {
+    let (width, height) = (image.get_width(), image.get_height());
+    let mut filtered = image.clone();
+    let boundaries: Vec<(usize, usize)> = if vertical {
+        (0..height)
+            .flat_map(|r| (2..width.saturating_sub(1)).step_by(2).map(move |c| (c, r)))
+            .collect()
+    } else {
+        (2..height.saturating_sub(1))
+            .step_by(2)
+            .flat_map(|r| (0..width).map(move |c| (c, r)))
+            .collect()
+    };
+    for (c, r) in boundaries {
+        let window: Vec<&Rgb> = if vertical {
+            image.window(c - 2, r, 4, 1).collect()
+        } else {
+            image.window(c, r - 2, 1, 4).collect()
+        };
+        if let Some((before, after)) = smooth_boundary(&window) {
+            let (before_pos, after_pos) = if vertical {
+                ((c - 1, r), (c, r))
+            } else {
+                ((c, r - 1), (c, r))
+            };
+            *filtered.get_mut(before_pos.0, before_pos.1).unwrap() = before;
+            *filtered.get_mut(after_pos.0, after_pos.1).unwrap() = after;
+        }
+    }
+    filtered
+}
+
+/// Takes a decompressed image and smooths the steps across the boundaries of its 2x2 blocks,
+/// which coarse b, c, and d quantization leaves visible as blockiness. Every boundary is looked
+/// at through a window of two pixels on each side; steps that are large, or that sit next to
+/// detail inside the blocks, are kept as they are likely to be real edges of the image.
+///
+/// # Arguments
+/// * `image`: Decompressed image, with a denominator of 255
+pub fn deblock(image: &RgbImage) -> RgbImage {
+    let pixels = Array2::from_row_major(
+        image.width as usize,
+        image.height as usize,
+        image.pixels.clone(),
+    );
+    let filtered = deblock_pass(&deblock_pass(&pixels, true), false);
+    RgbImage {
+        pixels: filtered.data,
+        width: image.width,
+        height: image.height,
+        denominator: image.denominator,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(values: &[u16]) -> RgbImage {
+        RgbImage {
+            pixels: values
+                .iter()
+                .map(|value| Rgb {
+                    red: *value,
+                    green: *value,
+                    blue: *value,
+                })
+                .collect(),
+            width: values.len() as u32,
+            height: 1,
+            denominator: 255,
+        }
+    }
+
+    #[test]
+    fn small_steps_are_smoothed_and_edges_kept() {
+        let smoothed = deblock(&row(&[100, 100, 108, 108, 108, 108, 200, 200]));
+        let reds: Vec<u16> = smoothed.pixels.iter().map(|pixel| pixel.red).collect();
+        assert_eq!(reds, vec![100, 102, 106, 108, 108, 108, 200, 200]);
+    }
+}
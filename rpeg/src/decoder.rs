@@ -0,0 +1,857 @@
+use crate::adjust::Adjustment;
+use crate::animation::seek_frame;
+use crate::codec::scratch::ScratchBuffers;
+use crate::codec::stats::Timings;
+use crate::codec::{
+    adjusted, decode_tile, decompress_into, decompress_with_options, output_rect, read_code_words,
+    read_prelude, stored_length, DecodeOptions, PixelFormat, Prelude,
+};
+use crate::content::TileMode;
+use crate::fixed::Arithmetic;
+use crate::format::WordOrder;
+use crate::hdr::ToneMap;
+use crate::io::RpegSource;
+use crate::layout::WordLayout;
+use crate::ppm::{Rgb, RgbImage};
+use crate::structs::QuantizedBlock;
+use crate::tiles::{tile_rects, Rect};
+use crate::tint::Tint;
+use rayon::prelude::*;
+use std::io::Read;
+
+/// Largest number of bytes `Download` asks its reader for at a time.
+const READ_CHUNK: usize = 64 * 1024;
+
+#[derive(Clone, Debug, Default)]
+/// ## Builder of the settings used to decompress images
+///
+/// Every method sets one field of the `DecodeOptions`; unset fields keep their default. The
+/// color adjustments are applied in the order their methods are called.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::decoder::Decoder;
+/// use rpeg::encoder::Encoder;
+/// use rpeg::ppm::{Rgb, RgbImage};
+///
+/// let image = RgbImage {
+///     pixels: vec![Rgb { red: 10, green: 20, blue: 30 }; 16],
+///     width: 4,
+///     height: 4,
+///     denominator: 255,
+/// };
+/// let compressed = Encoder::new().compress(&image).unwrap();
+/// let decoder = Decoder::new().dither(true);
+/// let rows: Vec<Vec<Rgb>> = decoder.rows(&compressed).unwrap().map(Result::unwrap).collect();
+/// assert_eq!(rows.concat(), decoder.decompress(&compressed).unwrap().pixels);
+/// ```
+pub struct Decoder {
+    options: DecodeOptions,
+}
+
+impl Decoder {
+    /// Returns a builder with the default settings.
+    pub fn new() -> Self {
+        Decoder::default()
+    }
+
+    /// Accepts a truncated compressed image and decodes whatever part of it is present.
+    pub fn preview(mut self, preview: bool) -> Self {
+        self.options.preview = preview;
+        self
+    }
+
+    /// Decodes only the pixels inside `region`.
+    pub fn region(mut self, region: Rect) -> Self {
+        self.options.region = Some(region);
+        self
+    }
+
+    /// Smooths the boundaries between 2x2 blocks after decoding.
+    pub fn deblock(mut self, deblock: bool) -> Self {
+        self.options.deblock = deblock;
+        self
+    }
+
+    /// Rounds the decoded pixels with ordered dithering.
+    pub fn dither(mut self, dither: bool) -> Self {
+        self.options.dither = dither;
+        self
+    }
+
+    /// Selects the arithmetic of the inverse 2x2 transform and the inverse color transform.
+    pub fn arithmetic(mut self, arithmetic: Arithmetic) -> Self {
+        self.options.arithmetic = arithmetic;
+        self
+    }
+
+    /// Fails cleanly when decoding would need more than `max_memory` bytes, instead of
+    /// `codec::DEFAULT_MAX_MEMORY`.
+    pub fn max_memory(mut self, max_memory: u64) -> Self {
+        self.options.max_memory = Some(max_memory);
+        self
+    }
+
+    /// Adjusts the gamma of the decoded pixels by `gamma`.
+    pub fn gamma(mut self, gamma: f64) -> Self {
+        self.options.adjustments.push(Adjustment::Gamma(gamma));
+        self
+    }
+
+    /// Adds `brightness` to every channel of the decoded pixels, and then scales their distance
+    /// from mid-gray by `contrast`.
+    pub fn brightness_contrast(mut self, brightness: f64, contrast: f64) -> Self {
+        self.options.adjustments.extend([
+            Adjustment::Brightness(brightness),
+            Adjustment::Contrast(contrast),
+        ]);
+        self
+    }
+
+    /// Scales the saturation of the decoded pixels by `saturation`.
+    pub fn saturation(mut self, saturation: f64) -> Self {
+        self.options
+            .adjustments
+            .push(Adjustment::Saturation(saturation));
+        self
+    }
+
+    /// Renders the decoded luma alone, or through a two-color gradient, with `tint`.
+    pub fn tint(mut self, tint: Tint) -> Self {
+        self.options.tint = Some(tint);
+        self
+    }
+
+    /// Fits the light of HDR images to 8 bits with `tone_map`.
+    pub fn tone_map(mut self, tone_map: ToneMap) -> Self {
+        self.options.tone_map = tone_map;
+        self
+    }
+
+    /// Returns the settings built so far.
+    pub fn options(&self) -> &DecodeOptions {
+        &self.options
+    }
+
+    /// Decompresses a whole compressed image with the settings built so far.
+    ///
+    /// # Arguments
+    /// * `bytes`: Compressed image, header included
+    pub fn decompress(&self, bytes: &[u8]) -> Result<RgbImage, String> {
+        decompress_with_options(bytes, &self.options)
+    }
+
+    /// Decompresses a whole compressed image with the settings built so far into `image`,
+    /// replacing its pixels, with the temporaries of `scratch`. Once `scratch` and `image` have
+    /// grown, the images `ScratchBuffers::covers_decode` accepts allocate nothing.
+    ///
+    /// # Arguments
+    /// * `bytes`: Compressed image, header included
+    /// * `scratch`: Buffers of the temporaries, reused across calls
+    /// * `image`: Image receiving the decompressed pixels
+    pub fn decompress_into(
+        &self,
+        bytes: &[u8],
+        scratch: &mut ScratchBuffers,
+        image: &mut RgbImage,
+    ) -> Result<(), String> {
+        decompress_into(bytes, &self.options, scratch, image)
+    }
+
+    /// Reads a whole compressed image from `source`, such as a file or a socket, and
+    /// decompresses it with the settings built so far.
+    ///
+    /// # Arguments
+    /// * `source`: Origin of the compressed image
+    pub fn decompress_from(&self, source: &mut dyn RpegSource) -> Result<RgbImage, String> {
+        self.decompress(&source.read_rpeg()?)
+    }
+
+    /// Decodes frame `frame` of a multi-frame stream, counted from 0, starting from the last
+    /// key frame before it instead of from the first frame. Only `dither` and `arithmetic`
+    /// apply to the frames of a stream.
+    ///
+    /// # Arguments
+    /// * `bytes`: Raw bytes of the multi-frame stream
+    /// * `frame`: Number of the frame
+    pub fn seek_frame(&self, bytes: &[u8], frame: usize) -> Result<RgbImage, String> {
+        seek_frame(bytes, frame, &self.options)
+    }
+
+    /// Returns an iterator over the rows of pixels of a compressed image, top to bottom, each
+    /// decoded from the compressed bytes when it is asked for. Returns an error right away when
+    /// the header or the layout of the payload is malformed.
+    ///
+    /// # Arguments
+    /// * `bytes`: Compressed image, header included
+    pub fn rows<'a>(&self, bytes: &'a [u8]) -> Result<Rows<'a>, String> {
+        Rows::new(bytes, &self.options)
+    }
+
+    /// Returns an iterator over the quantized values of every 2x2 block of a compressed image,
+    /// read straight from its code words without reconstructing any pixel. Returns an error
+    /// when the image is malformed, or has palette-coded or raw tiles, which hold no code words.
+    ///
+    /// # Arguments
+    /// * `bytes`: Compressed image, header included
+    pub fn blocks(&self, bytes: &[u8]) -> Result<Blocks, String> {
+        let (header, words) = read_code_words(bytes)?;
+        Ok(Blocks {
+            layout: header.layout,
+            columns: header.coded_width() as usize / 2,
+            rows: header.coded_height() as usize / 2,
+            words: words.into_iter().enumerate(),
+        })
+    }
+
+    /// Returns an iterator decoding a compressed image as its bytes arrive from `reader`, such
+    /// as a TCP stream or the body of an HTTP response (see `Download`). It stops reading once
+    /// as many bytes as the header announces have arrived, without waiting for the reader to
+    /// end.
+    ///
+    /// # Arguments
+    /// * `reader`: Source of the compressed image, header first
+    pub fn from_reader<R: Read>(&self, reader: R) -> Download<R> {
+        Download {
+            reader,
+            options: self.options.clone(),
+            bytes: Vec::new(),
+            expected: None,
+            previews: 3,
+            shown: 0,
+            done: false,
+        }
+    }
+}
+
+impl From<DecodeOptions> for Decoder {
+    /// Returns a builder starting from existing settings.
+    fn from(options: DecodeOptions) -> Self {
+        Decoder { options }
+    }
+}
+
+/// ## Iterator over the decoded rows of a compressed image
+///
+/// Every row holds `width()` pixels with a denominator of 255 (65535 for HDR images that are
+/// not tone mapped, see `rpeg::hdr`), and there are `height()` rows,
+/// which together match the image `Decoder::decompress` returns. Only the band of rows being
+/// returned is held in memory. Sequential code words are decoded one row of 2x2 blocks at a
+/// time; tiles that are progressive or palette coded are decoded a row of tiles at a time,
+/// since their payload is not laid out by rows. Deblocking needs the neighbours of every
+/// block, so with `deblock` the whole image is decoded before the first row is returned.
+/// After an error the iterator ends.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::decoder::Decoder;
+/// use rpeg::encoder::Encoder;
+/// use rpeg::ppm::{Rgb, RgbImage};
+///
+/// let image = RgbImage {
+///     pixels: vec![Rgb { red: 200, green: 100, blue: 50 }; 8 * 6],
+///     width: 8,
+///     height: 6,
+///     denominator: 255,
+/// };
+/// let compressed = Encoder::new().tile_size(4).compress(&image).unwrap();
+/// let rows = Decoder::new().rows(&compressed).unwrap();
+/// assert_eq!((rows.width(), rows.height()), (8, 6));
+/// assert!(rows.map(Result::unwrap).all(|row| row.len() == 8));
+/// ```
+pub struct Rows<'a> {
+    prelude: Prelude<'a>,
+    options: DecodeOptions,
+    tiles: Vec<Rect>,
+    out_rect: Rect,
+    /// Decoded pixels of the rows `band_start..band_end` of `out_rect`, in row-major order.
+    band: Vec<Rgb>,
+    band_start: u32,
+    band_end: u32,
+    /// Row of `out_rect` returned next.
+    next_row: u32,
+    /// Position in bits of the corrections of every row of blocks, when the image has a
+    /// refinement that applies to its bands.
+    refinement_rows: Option<Vec<usize>>,
+    timings: Timings,
+}
+
+impl<'a> Rows<'a> {
+    /// Parses the header and the layout of the payload of a compressed image, ready to decode
+    /// its rows.
+    ///
+    /// # Arguments
+    /// * `bytes`: Compressed image, header included
+    /// * `options`: Settings used to decompress the image
+    fn new(bytes: &'a [u8], options: &DecodeOptions) -> Result<Rows<'a>, String> {
+        let prelude = read_prelude(bytes, options)?;
+        let header = &prelude.header;
+        let out_rect = output_rect(header, options.region)?;
+        let tiles = tile_rects(
+            header.coded_width(),
+            header.coded_height(),
+            header.tile_size,
+        );
+        let word_bytes = header.layout.word_bytes();
+        for (index, (tile, payload)) in tiles.iter().zip(prelude.payloads.iter()).enumerate() {
+            let expected_len = (tile.width / 2 * tile.height / 2) as usize * word_bytes;
+            if !options.preview
+                && header.tile_mode(index).holds_words()
+                && payload.len() != expected_len
+            {
+                return Err(format!(
+                    "Expected {} bytes of compressed data, found {}",
+                    expected_len,
+                    payload.len()
+                ));
+            }
+        }
+        let (band, band_end) = if options.deblock {
+            (
+                decompress_with_options(bytes, options)?.pixels,
+                out_rect.height,
+            )
+        } else {
+            (Vec::new(), 0)
+        };
+        let refinement_rows = match &header.refinement {
+            Some(refinement) if !options.preview && !options.deblock => {
+                Some(refinement.row_offsets(header.width, header.height)?)
+            }
+            _ => None,
+        };
+        Ok(Rows {
+            prelude,
+            options: options.clone(),
+            tiles,
+            out_rect,
+            band,
+            band_start: 0,
+            band_end,
+            next_row: 0,
+            refinement_rows,
+            timings: Timings::new(),
+        })
+    }
+
+    /// Returns the width in pixels of every row.
+    pub fn width(&self) -> u32 {
+        self.out_rect.width
+    }
+
+    /// Returns the number of rows.
+    pub fn height(&self) -> u32 {
+        self.out_rect.height
+    }
+
+    /// Decodes the band of rows holding `next_row`: the row of 2x2 blocks holding it when
+    /// every tile it crosses is stored as sequential code words, and the row of tiles holding
+    /// it otherwise.
+    fn decode_band(&mut self) -> Result<(), String> {
+        let (header, out_rect) = (&self.prelude.header, self.out_rect);
+        let y = out_rect.y + self.next_row;
+        let tile_row: Vec<(usize, &Rect)> = self
+            .tiles
+            .iter()
+            .enumerate()
+            .filter(|(_, tile)| tile.y <= y && y < tile.y + tile.height)
+            .filter(|(_, tile)| {
+                tile.x < out_rect.x + out_rect.width && out_rect.x < tile.x + tile.width
+            })
+            .collect();
+        let by_block_row = header.order == WordOrder::Sequential
+            && tile_row
+                .iter()
+                .all(|(index, _)| header.tile_mode(*index).holds_words());
+        let word_bytes = header.layout.word_bytes();
+        let pieces: Vec<(Rect, TileMode, &[u8])> = tile_row
+            .iter()
+            .map(|(index, tile)| {
+                let payload = self.prelude.payloads[*index];
+                if !by_block_row {
+                    return (**tile, header.tile_mode(*index), payload);
+                }
+                let piece = Rect {
+                    y: y & !1,
+                    height: 2,
+                    ..**tile
+                };
+                let start = ((piece.y - tile.y) / 2 * tile.width / 2) as usize * word_bytes;
+                let end = start + (tile.width / 2) as usize * word_bytes;
+                let slice = &payload[start.min(payload.len())..end.min(payload.len())];
+                (piece, header.tile_mode(*index), slice)
+            })
+            .collect();
+        let decoded: Vec<_> = pieces
+            .par_iter()
+            .map(|(piece, mode, payload)| {
+                decode_tile(
+                    payload,
+                    piece,
+                    *mode,
+                    header,
+                    &self.prelude.ranges,
+                    &self.options,
+                    &self.timings,
+                )
+            })
+            .collect::<Result<_, String>>()?;
+
+        let (top, bottom) = (pieces[0].0.y, pieces[0].0.y + pieces[0].0.height);
+        self.band_start = top.max(out_rect.y) - out_rect.y;
+        self.band_end = bottom.min(out_rect.y + out_rect.height) - out_rect.y;
+        let black = Rgb {
+            red: 0,
+            green: 0,
+            blue: 0,
+        };
+        self.band = vec![black; ((self.band_end - self.band_start) * out_rect.width) as usize];
+        for ((piece, _, _), piece_pixels) in pieces.iter().zip(decoded.iter()) {
+            for (c, r, pixel) in piece_pixels.iter_row_major() {
+                let (x, y) = (piece.x + c as u32, piece.y + r as u32);
+                if x >= out_rect.x
+                    && x < out_rect.x + out_rect.width
+                    && y >= out_rect.y + self.band_start
+                    && y < out_rect.y + self.band_end
+                {
+                    let row = y - out_rect.y - self.band_start;
+                    let index = row * out_rect.width + (x - out_rect.x);
+                    self.band[index as usize] = pixel.clone();
+                }
+            }
+        }
+        let mut band = RgbImage {
+            pixels: std::mem::take(&mut self.band),
+            width: out_rect.width,
+            height: self.band_end - self.band_start,
+            denominator: PixelFormat::of(header).denominator(),
+        };
+        if let (Some(refinement), Some(offsets)) = (&header.refinement, &self.refinement_rows) {
+            let rect = Rect {
+                y: out_rect.y + self.band_start,
+                height: band.height,
+                ..out_rect
+            };
+            let row = rect.y / 2;
+            refinement.apply_from(
+                &mut band,
+                header.width,
+                header.height,
+                &rect,
+                (row, offsets[row as usize]),
+            )?;
+        }
+        self.band = adjusted(band, header, &self.options, &self.timings).pixels;
+        Ok(())
+    }
+}
+
+/// ## Iterator over the quantized blocks of a compressed image
+///
+/// Yields the column and row of every 2x2 block, counted in blocks, with its `QuantizedBlock`,
+/// in row-major order across the whole coded image, whatever its tiling and word order. Tools
+/// computing statistics such as mean luminance maps or chroma histograms read the blocks
+/// through `layout()`: `a / layout().a_scale()` is the mean luma of the block.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::decoder::Decoder;
+/// use rpeg::encoder::Encoder;
+/// use rpeg::ppm::{Rgb, RgbImage};
+///
+/// let image = RgbImage {
+///     pixels: vec![Rgb { red: 255, green: 255, blue: 255 }; 8 * 6],
+///     width: 8,
+///     height: 6,
+///     denominator: 255,
+/// };
+/// let compressed = Encoder::new().tile_size(4).compress(&image).unwrap();
+/// let mut blocks = Decoder::new().blocks(&compressed).unwrap();
+/// assert_eq!((blocks.columns(), blocks.rows()), (4, 3));
+/// let scale = blocks.layout().a_scale();
+/// assert!(blocks.all(|(_, _, block)| block.a as f64 / scale > 0.99));
+/// ```
+pub struct Blocks {
+    layout: WordLayout,
+    columns: usize,
+    rows: usize,
+    words: std::iter::Enumerate<std::vec::IntoIter<u64>>,
+}
+
+impl Blocks {
+    /// Returns the number of blocks in every row.
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// Returns the number of rows of blocks.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Returns the layout of the code words, which gives the quantization steps of the values.
+    pub fn layout(&self) -> &WordLayout {
+        &self.layout
+    }
+}
+
+impl Iterator for Blocks {
+    type Item = (usize, usize, QuantizedBlock);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (index, word) = self.words.next()?;
+        Some((
+            index % self.columns,
+            index / self.columns,
+            self.layout.unpack(word),
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.words.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Blocks {}
+
+#[derive(Clone, Debug, PartialEq)]
+/// ## Image decoded by `Download` from the bytes that arrived so far
+///
+/// `received` is the number of bytes the image was decoded from, and `expected` the size of
+/// the whole compressed image, once its header has arrived and when its size can be told from
+/// it. `complete` is only set on the last update, which holds the whole image.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::decoder::Update;
+/// use rpeg::ppm::RgbImage;
+///
+/// let update = Update {
+///     image: RgbImage { pixels: Vec::new(), width: 0, height: 0, denominator: 255 },
+///     received: 300,
+///     expected: Some(1200),
+///     complete: false,
+/// };
+/// assert_eq!(update.received * 4, update.expected.unwrap());
+/// ```
+pub struct Update {
+    pub image: RgbImage,
+    pub received: usize,
+    pub expected: Option<usize>,
+    pub complete: bool,
+}
+
+/// ## Decoder of a compressed image that is still arriving from a reader
+///
+/// Every call to `next` reads from the reader until another quarter of the image has arrived,
+/// or another share set with `previews`, and returns the image decoded as a preview from the
+/// bytes so far, as `-d --preview` would, so that a viewer can show it while the rest
+/// downloads. Blocks that have not arrived are left black. With the progressive order, the DC
+/// terms of every block come first, so once they have arrived the previews cover the whole
+/// image at low quality, and the b/c/d refinements then sharpen it from the top. The last
+/// update decodes the whole image with the settings of the `Decoder`. An untiled palette-coded
+/// or raw image gives no preview, since its size is only known once it ends. After an
+/// error the iterator ends.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::decoder::Decoder;
+/// use rpeg::encoder::Encoder;
+/// use rpeg::ppm::{Rgb, RgbImage};
+///
+/// let image = RgbImage {
+///     pixels: vec![Rgb { red: 30, green: 160, blue: 90 }; 16 * 16],
+///     width: 16,
+///     height: 16,
+///     denominator: 255,
+/// };
+/// let compressed = Encoder::new().progressive(true).compress(&image).unwrap();
+/// // A slice gives all of its bytes at once, so that no preview is due before the whole image.
+/// let updates: Vec<_> = Decoder::new()
+///     .from_reader(&compressed[..])
+///     .map(Result::unwrap)
+///     .collect();
+/// assert_eq!(updates.len(), 1);
+/// assert!(updates[0].complete);
+/// assert_eq!(updates[0].image, Decoder::new().decompress(&compressed).unwrap());
+/// ```
+pub struct Download<R> {
+    reader: R,
+    options: DecodeOptions,
+    /// Bytes of the image that arrived so far.
+    bytes: Vec<u8>,
+    /// Size of the whole image, once it can be told from its header.
+    expected: Option<usize>,
+    previews: usize,
+    /// Number of the previews that are due or were skipped so far.
+    shown: usize,
+    done: bool,
+}
+
+impl<R: Read> Download<R> {
+    /// Sets the number of previews decoded before the whole image, 3 by default: the image is
+    /// split into `previews + 1` equal shares, and a preview is decoded every time another
+    /// share has arrived. With 0, only the whole image is decoded.
+    ///
+    /// # Arguments
+    /// * `previews`: Number of previews
+    pub fn previews(mut self, previews: usize) -> Self {
+        self.previews = previews;
+        self
+    }
+
+    /// Returns the update of the image decoded from the bytes that arrived so far, as a
+    /// preview unless `complete` is set. The iterator ends after an error.
+    fn decode(&mut self, complete: bool) -> Result<Update, String> {
+        let options = DecodeOptions {
+            preview: !complete,
+            ..self.options.clone()
+        };
+        let decoded = decompress_with_options(&self.bytes, &options);
+        self.done |= complete || decoded.is_err();
+        Ok(Update {
+            image: decoded?,
+            received: self.bytes.len(),
+            expected: self.expected,
+            complete,
+        })
+    }
+}
+
+impl<R: Read> Iterator for Download<R> {
+    type Item = Result<Update, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut chunk = vec![0; READ_CHUNK];
+        loop {
+            let wanted = self
+                .expected
+                .map_or(READ_CHUNK, |expected| {
+                    expected.saturating_sub(self.bytes.len())
+                })
+                .min(READ_CHUNK);
+            let read = match self.reader.read(&mut chunk[..wanted]) {
+                Ok(read) => read,
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(format!("Failed to read the compressed image: {error}")));
+                }
+            };
+            self.bytes.extend_from_slice(&chunk[..read]);
+            if self.expected.is_none() {
+                self.expected = stored_length(&self.bytes);
+            }
+            let arrived = self
+                .expected
+                .is_some_and(|expected| self.bytes.len() >= expected);
+            if read == 0 || arrived {
+                // The first read may have gone past the end the header then announced.
+                self.bytes.truncate(self.expected.unwrap_or(usize::MAX));
+                return Some(self.decode(true));
+            }
+            let Some(expected) = self.expected else {
+                continue;
+            };
+            let share = |count: usize| count * expected / (self.previews + 1);
+            if self.shown < self.previews && self.bytes.len() >= share(self.shown + 1) {
+                // A read that brought several shares at once only gives one preview.
+                while self.shown < self.previews && self.bytes.len() >= share(self.shown + 1) {
+                    self.shown += 1;
+                }
+                return Some(self.decode(false));
+            }
+        }
+    }
+}
+
+impl Iterator for Rows<'_> {
+    type Item = Result<Vec<Rgb>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_row >= self.out_rect.height {
+            return None;
+        }
+        if self.next_row >= self.band_end {
+            if let Err(message) = self.decode_band() {
+                self.next_row = self.out_rect.height;
+                return Some(Err(message));
+            }
+        }
+        let width = self.out_rect.width as usize;
+        let start = (self.next_row - self.band_start) as usize * width;
+        self.next_row += 1;
+        Some(Ok(self.band[start..start + width].to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{Encoder, RatioPolicy};
+
+    #[test]
+    fn rows_match_the_whole_image() {
+        let image = RgbImage {
+            pixels: (0..30 * 22)
+                .map(|i| Rgb {
+                    red: (i * 7 % 256) as u16,
+                    green: (i * 3 % 256) as u16,
+                    blue: if i % 30 < 15 { 0 } else { 255 },
+                })
+                .collect(),
+            width: 30,
+            height: 22,
+            denominator: 255,
+        };
+        let region = Rect {
+            x: 5,
+            y: 3,
+            width: 20,
+            height: 14,
+        };
+        for encoder in [
+            Encoder::new(),
+            Encoder::new().tile_size(8),
+            Encoder::new().progressive(true).tile_size(8),
+            Encoder::new().detect_content(true).tile_size(8),
+        ] {
+            let compressed = encoder.compress(&image).unwrap();
+            for decoder in [
+                Decoder::new(),
+                Decoder::new().dither(true),
+                Decoder::new().region(region),
+                Decoder::new().deblock(true),
+                Decoder::new().arithmetic(Arithmetic::Fixed),
+                Decoder::new()
+                    .brightness_contrast(10.0, 1.5)
+                    .saturation(0.5),
+            ] {
+                let whole = decoder.decompress(&compressed).unwrap();
+                let rows = decoder.rows(&compressed).unwrap();
+                assert_eq!((rows.width(), rows.height()), (whole.width, whole.height));
+                let rows: Vec<Vec<Rgb>> = rows.map(Result::unwrap).collect();
+                assert_eq!(rows.len(), whole.height as usize);
+                assert_eq!(rows.concat(), whole.pixels);
+            }
+            let truncated = &compressed[..compressed.len() - 1];
+            assert!(Decoder::new().rows(truncated).is_err());
+            let preview = Decoder::new().preview(true);
+            let rows: Vec<Vec<Rgb>> = preview
+                .rows(truncated)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(rows.concat(), preview.decompress(truncated).unwrap().pixels);
+        }
+    }
+
+    #[test]
+    fn blocks_are_read_from_the_code_words() {
+        let image = RgbImage {
+            pixels: (0..12 * 10)
+                .map(|i| Rgb {
+                    red: (i * 2) as u16,
+                    green: 90,
+                    blue: 0,
+                })
+                .collect(),
+            width: 12,
+            height: 10,
+            denominator: 255,
+        };
+        for encoder in [
+            Encoder::new(),
+            Encoder::new().tile_size(4).progressive(true),
+        ] {
+            let compressed = encoder.compress(&image).unwrap();
+            let (header, words) = read_code_words(&compressed).unwrap();
+            let blocks = Decoder::new().blocks(&compressed).unwrap();
+            assert_eq!(blocks.len(), 30);
+            let blocks: Vec<_> = blocks.collect();
+            assert_eq!(blocks[7].0, 1);
+            assert_eq!(blocks[7].1, 1);
+            for ((_, _, block), word) in blocks.iter().zip(words) {
+                assert_eq!(*block, header.layout.unpack(word));
+            }
+            // The mean luma of the last block matches that of its four pixels.
+            let luma = blocks[29].2.a as f64 / header.layout.a_scale();
+            let expected: f64 = [106, 107, 118, 119]
+                .iter()
+                .map(|&i| (0.299 * (i * 2) as f64 + 0.587 * 90.0) / 255.0 / 4.0)
+                .sum();
+            assert!((luma - expected).abs() < 0.01);
+        }
+        let raw = Encoder::new()
+            .min_ratio(10.0, RatioPolicy::Store)
+            .compress(&image)
+            .unwrap();
+        assert!(Decoder::new().blocks(&raw).is_err());
+    }
+
+    /// Gives at most 100 bytes per read, as a slow connection would.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+            let count = buffer.len().min(100).min(self.0.len());
+            buffer[..count].copy_from_slice(&self.0[..count]);
+            self.0 = &self.0[count..];
+            Ok(count)
+        }
+    }
+
+    #[test]
+    fn downloads_show_previews_while_the_bytes_arrive() {
+        let image =
+            crate::testkit::synthetic_image(crate::testkit::Pattern::Noise { seed: 4 }, 40, 30);
+        for encoder in [
+            Encoder::new().progressive(true),
+            Encoder::new().tile_size(16).region(crate::roi::Region {
+                x: 4,
+                y: 4,
+                width: 10,
+                height: 10,
+                quality: 90,
+            }),
+        ] {
+            let compressed = encoder.compress(&image).unwrap();
+            let mut sent = compressed.clone();
+            sent.extend_from_slice(b"next response");
+            let updates: Vec<Update> = Decoder::new()
+                .from_reader(Trickle(&sent))
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(updates.len(), 4);
+            for (index, update) in updates.iter().enumerate() {
+                assert_eq!(update.expected, Some(compressed.len()));
+                assert!(update.received * 4 >= (index + 1) * compressed.len());
+                assert_eq!(update.complete, index == 3);
+                let options = DecodeOptions {
+                    preview: !update.complete,
+                    ..DecodeOptions::default()
+                };
+                let arrived = &compressed[..update.received];
+                assert_eq!(
+                    update.image,
+                    decompress_with_options(arrived, &options).unwrap()
+                );
+            }
+            assert_eq!(updates[3].received, compressed.len());
+        }
+        let truncated = Encoder::new().compress(&image).unwrap();
+        let mut download = Decoder::new()
+            .from_reader(&truncated[..truncated.len() - 1])
+            .previews(0);
+        assert!(download.next().unwrap().is_err());
+        assert!(download.next().is_none());
+    }
+}
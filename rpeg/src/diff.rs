@@ -0,0 +1,263 @@
+use crate::codec::read_code_words;
+use crate::error::{CliError, ErrorKind};
+use crate::format::Header;
+use crate::io::read_input;
+use std::fmt;
+
+/// Names of the quantized values of a code word, in the order of `FieldDiff`s.
+pub const FIELD_NAMES: [&str; 6] = ["a", "b", "c", "d", "pb", "pr"];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// ## How much one quantized value differs between two compressed images
+pub struct FieldDiff {
+    /// Number of blocks whose value differs.
+    pub blocks: usize,
+    /// Largest absolute difference, in quantization levels.
+    pub max: u64,
+    /// Sum of the absolute differences over every block, in quantization levels.
+    pub total: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// ## Structural differences between two compressed images
+///
+/// Blocks are only compared when both images have the same dimensions and code word layout;
+/// otherwise `blocks` is 0 and the headers tell why.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::codec::{compress_image, EncoderOptions};
+/// use rpeg::diff::diff_streams;
+/// use rpeg::ppm::{Rgb, RgbImage};
+///
+/// let image = RgbImage {
+///     pixels: vec![Rgb { red: 10, green: 20, blue: 30 }; 16],
+///     width: 4,
+///     height: 4,
+///     denominator: 255,
+/// };
+/// let sequential = compress_image(&image, &EncoderOptions::default());
+/// let progressive = compress_image(
+///     &image,
+///     &EncoderOptions { progressive: true, ..Default::default() },
+/// );
+/// let diff = diff_streams(&sequential, &progressive).unwrap();
+/// assert_eq!(diff.headers.len(), 1);
+/// assert_eq!((diff.blocks, diff.differing_blocks), (4, 0));
+/// ```
+pub struct StreamDiff {
+    /// Every header field that differs, with its value in both images.
+    pub headers: Vec<String>,
+    /// Number of blocks compared.
+    pub blocks: usize,
+    /// Number of blocks whose code words differ.
+    pub differing_blocks: usize,
+    /// Differences of every quantized value, named by `FIELD_NAMES`.
+    pub fields: [FieldDiff; 6],
+    /// Column and row, in blocks, of the first block that differs.
+    pub first_difference: Option<(usize, usize)>,
+}
+
+impl StreamDiff {
+    /// Returns true if the images hold the same header and code words.
+    pub fn is_identical(&self) -> bool {
+        self.headers.is_empty() && self.differing_blocks == 0
+    }
+}
+
+/// Returns the dimensions of the thumbnail embedded in a header, or "none".
+fn thumbnail_size(header: &Header) -> String {
+    header
+        .thumbnail
+        .as_ref()
+        .map_or("none".to_string(), |thumbnail| {
+            format!("{}x{}", thumbnail.width, thumbnail.height)
+        })
+}
+
+/// Returns a description of every header field that differs between `a` and `b`.
+fn header_differences(a: &Header, b: &Header) -> Vec<String> {
+    let fields = [
+        ("width", a.width.to_string(), b.width.to_string()),
+        ("height", a.height.to_string(), b.height.to_string()),
+        ("order", format!("{:?}", a.order), format!("{:?}", b.order)),
+        (
+            "region qualities",
+            format!("{:?}", a.region_qualities),
+            format!("{:?}", b.region_qualities),
+        ),
+        (
+            "tile size",
+            a.tile_size.to_string(),
+            b.tile_size.to_string(),
+        ),
+        (
+            "luma range",
+            format!("{:.3}", a.luma_range as f64 / 1000.0),
+            format!("{:.3}", b.luma_range as f64 / 1000.0),
+        ),
+        ("layout", a.layout.id.to_string(), b.layout.id.to_string()),
+        (
+            "metadata",
+            format!("{:?}", a.metadata),
+            format!("{:?}", b.metadata),
+        ),
+        ("thumbnail", thumbnail_size(a), thumbnail_size(b)),
+    ];
+    fields
+        .into_iter()
+        .filter(|(_, a, b)| a != b)
+        .map(|(name, a, b)| format!("{name}: {a} vs {b}"))
+        .collect()
+}
+
+/// Compares the headers and the code words of two compressed images, block by block.
+///
+/// # Arguments
+/// * `a`: First compressed image, header included
+/// * `b`: Second compressed image, header included
+pub fn diff_streams(a: &[u8], b: &[u8]) -> Result<StreamDiff, String> {
+    let first = read_code_words(a).map_err(|message| format!("First image: {message}"))?;
+    let second = read_code_words(b).map_err(|message| format!("Second image: {message}"))?;
+    Ok(diff_words(first, second))
+}
+
+/// Compares the headers and the code words of two compressed images, as read by
+/// `read_code_words`, block by block.
+fn diff_words(first: (Header, Vec<u64>), second: (Header, Vec<u64>)) -> StreamDiff {
+    let ((header_a, words_a), (header_b, words_b)) = (first, second);
+    let mut diff = StreamDiff {
+        headers: header_differences(&header_a, &header_b),
+        ..StreamDiff::default()
+    };
+    let comparable = header_a.width == header_b.width
+        && header_a.height == header_b.height
+        && header_a.layout == header_b.layout;
+    if !comparable {
+        return diff;
+    }
+    let layout = header_a.layout;
+    let blocks_per_row = (header_a.coded_width() / 2).max(1) as usize;
+    diff.blocks = words_a.len();
+    for (index, (word_a, word_b)) in words_a.iter().zip(words_b.iter()).enumerate() {
+        if word_a == word_b {
+            continue;
+        }
+        diff.differing_blocks += 1;
+        diff.first_difference
+            .get_or_insert((index % blocks_per_row, index / blocks_per_row));
+        let (value_a, value_b) = (layout.unpack(*word_a), layout.unpack(*word_b));
+        let differences = [
+            value_a.a.abs_diff(value_b.a) as u64,
+            value_a.b.abs_diff(value_b.b) as u64,
+            value_a.c.abs_diff(value_b.c) as u64,
+            value_a.d.abs_diff(value_b.d) as u64,
+            value_a.pb.abs_diff(value_b.pb) as u64,
+            value_a.pr.abs_diff(value_b.pr) as u64,
+        ];
+        for (field, difference) in diff.fields.iter_mut().zip(differences) {
+            if difference > 0 {
+                field.blocks += 1;
+                field.max = field.max.max(difference);
+                field.total += difference;
+            }
+        }
+    }
+    diff
+}
+
+impl fmt::Display for StreamDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for header in &self.headers {
+            writeln!(f, "header {header}")?;
+        }
+        if self.blocks == 0 {
+            return writeln!(f, "blocks not compared: dimensions or layouts differ");
+        }
+        writeln!(
+            f,
+            "{} of {} blocks differ ({:.2}%)",
+            self.differing_blocks,
+            self.blocks,
+            self.differing_blocks as f64 * 100.0 / self.blocks as f64
+        )?;
+        if let Some((col, row)) = self.first_difference {
+            writeln!(f, "first difference at block column {col}, row {row}")?;
+        }
+        for (name, field) in FIELD_NAMES.iter().zip(self.fields.iter()) {
+            if field.blocks > 0 {
+                writeln!(
+                    f,
+                    "{name:>3}: {} blocks, max {} levels, mean {:.2} levels over differing blocks",
+                    field.blocks,
+                    field.max,
+                    field.total as f64 / field.blocks as f64
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares two compressed image files, prints a summary of their differences to standard out,
+/// and returns true if they hold the same header and code words.
+///
+/// # Arguments
+/// * `first`: Location of the first compressed image
+/// * `second`: Location of the second compressed image
+pub fn diff(first: &str, second: &str) -> Result<bool, CliError> {
+    let read = |filename: &str| {
+        let bytes = read_input(Some(filename))
+            .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+        read_code_words(&bytes)
+            .map_err(|message| CliError::stream(&bytes, format!("{filename}: {message}")))
+    };
+    let diff = diff_words(read(first)?, read(second)?);
+    if diff.is_identical() {
+        println!("identical");
+    } else {
+        print!("{diff}");
+    }
+    Ok(diff.is_identical())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{compress_image, EncoderOptions};
+    use crate::ppm::{Rgb, RgbImage};
+
+    #[test]
+    fn differing_blocks_are_counted() {
+        let mut image = RgbImage {
+            pixels: vec![
+                Rgb {
+                    red: 100,
+                    green: 100,
+                    blue: 100
+                };
+                64
+            ],
+            width: 8,
+            height: 8,
+            denominator: 255,
+        };
+        let options = EncoderOptions {
+            tile_size: 4,
+            ..Default::default()
+        };
+        let before = compress_image(&image, &options);
+        image.pixels[8 * 5 + 6] = Rgb {
+            red: 255,
+            green: 255,
+            blue: 255,
+        };
+        let after = compress_image(&image, &options);
+        let diff = diff_streams(&before, &after).unwrap();
+        assert_eq!((diff.blocks, diff.differing_blocks), (16, 1));
+        assert_eq!(diff.first_difference, Some((3, 2)));
+        assert!(diff.fields[0].max > 0);
+        assert!(diff_streams(&before, &before).unwrap().is_identical());
+    }
+}
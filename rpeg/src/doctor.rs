@@ -0,0 +1,186 @@
+use crate::codec::EncoderOptions;
+use crate::error::{CliError, ErrorKind};
+use crate::layout::LAYOUTS;
+use crate::testkit::{check_vector, roundtrip_psnr, synthetic_image, Pattern, VECTORS};
+use std::time::Instant;
+
+/// Lowest PSNR the round trip of the built-in test image may reach, in decibels. The default
+/// settings give 32.2 dB.
+const MIN_ROUNDTRIP_PSNR: f64 = 30.0;
+
+/// Side of the built-in test image, in pixels.
+const TEST_IMAGE_SIZE: u32 = 256;
+
+/// Cargo features rpeg can be built with.
+const FEATURES: [(&str, bool); 7] = [
+    ("fixed-point", cfg!(feature = "fixed-point")),
+    ("single-precision", cfg!(feature = "single-precision")),
+    ("gpu", cfg!(feature = "gpu")),
+    ("tokio", cfg!(feature = "tokio")),
+    ("serde", cfg!(feature = "serde")),
+    ("jpeg", cfg!(feature = "jpeg")),
+    ("proptest", cfg!(feature = "proptest")),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+/// ## Outcome of one self-check of `rpeg doctor`
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::doctor::run_checks;
+///
+/// assert!(run_checks().iter().all(|check| check.result.is_ok()));
+/// ```
+pub struct Check {
+    /// What was checked.
+    pub name: String,
+    /// What was found, or why the check failed.
+    pub result: Result<String, String>,
+}
+
+/// Runs every self-check: a round trip of a built-in test image at the default settings, the
+/// golden vectors of `rpeg::testkit`, and the verification of every code word layout.
+pub fn run_checks() -> Vec<Check> {
+    let mut checks = Vec::new();
+    let image = synthetic_image(Pattern::Gradient, TEST_IMAGE_SIZE, TEST_IMAGE_SIZE);
+    let start = Instant::now();
+    let result = roundtrip_psnr(&image, &EncoderOptions::default()).and_then(|psnr| {
+        let seconds = start.elapsed().as_secs_f64();
+        if psnr >= MIN_ROUNDTRIP_PSNR {
+            Ok(format!("{psnr:.1} dB PSNR in {:.1} ms", seconds * 1000.0))
+        } else {
+            Err(format!(
+                "{psnr:.1} dB PSNR, below the expected {MIN_ROUNDTRIP_PSNR} dB"
+            ))
+        }
+    });
+    checks.push(Check {
+        name: format!("round trip of a {TEST_IMAGE_SIZE}x{TEST_IMAGE_SIZE} gradient"),
+        result,
+    });
+    for vector in &VECTORS {
+        checks.push(Check {
+            name: format!("golden vector {}", vector.name),
+            result: check_vector(vector).map(|_| "bitstream and pixels match".to_string()),
+        });
+    }
+    for layout in &LAYOUTS {
+        checks.push(Check {
+            name: format!("code word layout {} ({} bits)", layout.id, layout.word_bits),
+            result: layout
+                .verify()
+                .map(|_| "every field round trips".to_string()),
+        });
+    }
+    checks
+}
+
+/// Returns the processor features the build can use and the threads available, one
+/// `name: value` pair each.
+pub fn capabilities() -> Vec<(&'static str, String)> {
+    let detected: Vec<&str> = {
+        #[cfg(target_arch = "x86_64")]
+        {
+            [
+                ("sse2", is_x86_feature_detected!("sse2")),
+                ("sse4.1", is_x86_feature_detected!("sse4.1")),
+                ("avx", is_x86_feature_detected!("avx")),
+                ("avx2", is_x86_feature_detected!("avx2")),
+                ("fma", is_x86_feature_detected!("fma")),
+                ("avx512f", is_x86_feature_detected!("avx512f")),
+            ]
+            .into_iter()
+            .filter_map(|(name, present)| present.then_some(name))
+            .collect()
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            [("neon", std::arch::is_aarch64_feature_detected!("neon"))]
+                .into_iter()
+                .filter_map(|(name, present)| present.then_some(name))
+                .collect()
+        }
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        {
+            Vec::new()
+        }
+    };
+    let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+    vec![
+        (
+            "target",
+            format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS),
+        ),
+        (
+            "simd",
+            if detected.is_empty() {
+                "none detected".to_string()
+            } else {
+                detected.join(" ")
+            },
+        ),
+        ("cores", cores.to_string()),
+        ("rayon threads", rayon::current_num_threads().to_string()),
+    ]
+}
+
+/// Returns the report of `rpeg doctor`: the version, the capabilities, the enabled features,
+/// and the outcome of every check, one per line, and whether every check passed.
+pub fn report() -> (String, bool) {
+    let mut text = format!("rpeg {}\n", env!("CARGO_PKG_VERSION"));
+    for (name, value) in capabilities() {
+        text += &format!("{name}: {value}\n");
+    }
+    let enabled: Vec<&str> = FEATURES
+        .iter()
+        .filter_map(|(name, enabled)| enabled.then_some(*name))
+        .collect();
+    text += &format!(
+        "features: {}\n",
+        if enabled.is_empty() {
+            "none".to_string()
+        } else {
+            enabled.join(" ")
+        }
+    );
+    let checks = run_checks();
+    for check in &checks {
+        text += &match &check.result {
+            Ok(found) => format!("ok    {}: {found}\n", check.name),
+            Err(message) => format!("FAIL  {}: {message}\n", check.name),
+        };
+    }
+    (text, checks.iter().all(|check| check.result.is_ok()))
+}
+
+/// Prints the report of `rpeg doctor`, and fails if any check did.
+pub fn doctor() -> Result<(), CliError> {
+    let (text, passed) = report();
+    print!("{text}");
+    if passed {
+        Ok(())
+    } else {
+        Err(CliError::new(
+            ErrorKind::CorruptStream,
+            "Self-checks failed; include this report when filing an issue",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_lists_capabilities_features_and_checks() {
+        let (text, passed) = report();
+        assert!(passed, "{text}");
+        assert!(text.contains("\nrayon threads: "));
+        assert!(text.contains("\nfeatures: "));
+        assert_eq!(
+            text.lines().filter(|line| line.starts_with("ok ")).count(),
+            1 + VECTORS.len() + LAYOUTS.len()
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use crate::codec::EncoderOptions;
+use crate::error::{CliError, ErrorKind};
+use crate::io::read_input;
+use crate::png_image::read_png_or_ppm;
+use crate::ppm::RgbImage;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Intermediate stage of the compression pipeline written by `--dump-stage`
+///
+/// `ComponentVideo` is the luma and chroma of every pixel, `Dct` the unquantized transform of
+/// every 2x2 block, and `Quantized` the fields of the code word of every block, as stored in
+/// the compressed image. The first two are computed with floating point arithmetic, after the
+/// preprocessing, linearization, and padding the settings ask for.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::dump::DumpStage;
+///
+/// assert_eq!(DumpStage::from_name("dct"), Some(DumpStage::Dct));
+/// assert_eq!(DumpStage::Quantized.name(), "quantized");
+/// ```
+pub enum DumpStage {
+    ComponentVideo,
+    Dct,
+    Quantized,
+}
+
+impl DumpStage {
+    /// Returns the stage called `name`: cv, dct, or quantized.
+    ///
+    /// # Arguments
+    /// * `name`: Name of the stage, as given to `--dump-stage`
+    pub fn from_name(name: &str) -> Option<DumpStage> {
+        match name {
+            "cv" => Some(DumpStage::ComponentVideo),
+            "dct" => Some(DumpStage::Dct),
+            "quantized" => Some(DumpStage::Quantized),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the stage, as given to `--dump-stage` and used in the name of the
+    /// dumped file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DumpStage::ComponentVideo => "cv",
+            DumpStage::Dct => "dct",
+            DumpStage::Quantized => "quantized",
+        }
+    }
+}
+
+/// Returns the `stage` of the compression of `image` with `options` as JSON: a serialized
+/// Array2 of ComponentVideo per pixel, of TransformedBlock per block, or of QuantizedBlock per
+/// block. The blocks are laid out on the grid of blocks, one column per 2 pixels.
+///
+/// # Arguments
+/// * `image`: Image to compress
+/// * `options`: Settings used to compress the image
+/// * `stage`: Stage to return
+#[cfg(feature = "serde")]
+pub fn stage_json(
+    image: &RgbImage,
+    options: &EncoderOptions,
+    stage: DumpStage,
+) -> Result<String, String> {
+    use crate::codec::{block_aligned, compress_image, read_code_words};
+    use crate::conversions::{component_video_to_blocks, linearize, pixels_to_component_video};
+    use crate::dct_coeff::transform_block;
+    use crate::pixel::Pixel;
+    use crate::structs::TransformedBlock;
+    use array2::array2::Array2;
+
+    let to_json = |value: Result<String, serde_json::Error>| value.map_err(|e| e.to_string());
+    if stage == DumpStage::Quantized {
+        let compressed = compress_image(image, options);
+        let (header, words) = read_code_words(&compressed)?;
+        let blocks = words
+            .iter()
+            .map(|word| header.layout.unpack(*word))
+            .collect();
+        let (width, height) = (header.coded_width() / 2, header.coded_height() / 2);
+        let blocks = Array2::from_row_major(width as usize, height as usize, blocks);
+        return to_json(serde_json::to_string(&blocks));
+    }
+    let preprocessed = options.preprocess.apply(image);
+    let linear = options.srgb.then(|| linearize(&preprocessed));
+    let source = linear.as_ref().unwrap_or(&preprocessed);
+    let (aligned, _, _) = block_aligned(source, options.pad);
+    if stage == DumpStage::ComponentVideo {
+        // The encoder keeps the pixels block-linear; the dump lists them in row-major order.
+        let denominator = source.denominator as f64;
+        let component_video = aligned.map(|pixel| pixel.to_ypbpr(denominator));
+        return to_json(serde_json::to_string(&component_video));
+    }
+    let component_video = pixels_to_component_video(&aligned, source.denominator);
+    let transforms: Vec<TransformedBlock> = component_video_to_blocks(&component_video)
+        .data
+        .iter()
+        .map(transform_block)
+        .collect();
+    let transforms = Array2::from_row_major(
+        aligned.get_width() / 2,
+        aligned.get_height() / 2,
+        transforms,
+    );
+    to_json(serde_json::to_string(&transforms))
+}
+
+/// Returns an error in place of the stage, since serializing it needs the `serde` feature.
+///
+/// # Arguments
+/// * `image`: Image to compress
+/// * `options`: Settings used to compress the image
+/// * `stage`: Stage to return
+#[cfg(not(feature = "serde"))]
+pub fn stage_json(
+    _image: &RgbImage,
+    _options: &EncoderOptions,
+    _stage: DumpStage,
+) -> Result<String, String> {
+    Err("Dumping pipeline stages needs rpeg built with the serde feature".to_string())
+}
+
+/// Writes the `stage` of the compression of the PPM or PNG image `filename` to `dump_dir`, as
+/// `<name>.<stage>.json`, where `<name>` is the file name of the image without its extension,
+/// or `stdin`. The directory is created if it does not exist. Failures are returned with the
+/// category the CLI exits with.
+///
+/// # Arguments
+/// * `filename`: Location of the image, or None to read from standard in
+/// * `stage`: Stage to write
+/// * `dump_dir`: Directory the stage is written to
+/// * `options`: Settings used to compress the image
+pub fn dump_stage(
+    filename: Option<&str>,
+    stage: DumpStage,
+    dump_dir: &str,
+    options: &EncoderOptions,
+) -> Result<(), CliError> {
+    let bytes = read_input(filename)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let image = read_png_or_ppm(&bytes)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let json = stage_json(&image, options, stage)
+        .map_err(|message| CliError::new(ErrorKind::BadArguments, message))?;
+    let name = filename
+        .and_then(|filename| Path::new(filename).file_stem())
+        .map_or("stdin".into(), |stem| stem.to_string_lossy());
+    let dump_dir = Path::new(dump_dir);
+    std::fs::create_dir_all(dump_dir)
+        .and_then(|_| std::fs::write(dump_dir.join(format!("{name}.{}.json", stage.name())), json))
+        .map_err(|e| CliError::new(ErrorKind::Io, format!("{}: {e}", dump_dir.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_are_named_and_serialized() {
+        for stage in [
+            DumpStage::ComponentVideo,
+            DumpStage::Dct,
+            DumpStage::Quantized,
+        ] {
+            assert_eq!(DumpStage::from_name(stage.name()), Some(stage));
+        }
+        assert_eq!(DumpStage::from_name("words"), None);
+        let image = RgbImage {
+            pixels: vec![crate::ppm::Rgb::default(); 4 * 3],
+            width: 4,
+            height: 3,
+            denominator: 255,
+        };
+        let options = EncoderOptions::default();
+        let json = stage_json(&image, &options, DumpStage::Quantized);
+        #[cfg(feature = "serde")]
+        {
+            use crate::structs::{ComponentVideo, QuantizedBlock, TransformedBlock};
+            use array2::array2::Array2;
+
+            let quantized: Array2<QuantizedBlock> = serde_json::from_str(&json.unwrap()).unwrap();
+            assert_eq!((quantized.get_width(), quantized.get_height()), (2, 1));
+            let dct = stage_json(&image, &options, DumpStage::Dct).unwrap();
+            let dct: Array2<TransformedBlock> = serde_json::from_str(&dct).unwrap();
+            assert_eq!(dct.data.len(), 2);
+            let cv = stage_json(&image, &options, DumpStage::ComponentVideo).unwrap();
+            let cv: Array2<ComponentVideo> = serde_json::from_str(&cv).unwrap();
+            assert_eq!((cv.get_width(), cv.get_height()), (4, 2));
+        }
+        #[cfg(not(feature = "serde"))]
+        assert!(json.is_err());
+    }
+}
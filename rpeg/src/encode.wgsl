@@ -0,0 +1,133 @@
+// Compression of 2x2 blocks on the GPU: one invocation per block converts its four pixels to
+// component video, computes a, b, c, and d, quantizes everything, and packs the code word.
+
+struct Params {
+    // Width of the image in pixels.
+    width: u32,
+    blocks_per_row: u32,
+    block_count: u32,
+    // Number of invocations in a row of the dispatch grid.
+    row_stride: u32,
+    denominator: f32,
+    a_scale: f32,
+    bcd_levels: f32,
+    chroma_levels: f32,
+    // 1 when Pb and Pr are quantized directly, 0 when they index the chroma table.
+    chroma_direct: u32,
+    padding_0: u32,
+    padding_1: u32,
+    padding_2: u32,
+    // Widths and least significant bits of a and b, c and d, and Pb and Pr.
+    fields: array<vec4<u32>, 3>,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+// Red, green, and blue of every pixel, two 16-bit samples per element.
+@group(0) @binding(1) var<storage, read> samples: array<u32>;
+// Range b, c, and d of every block are clamped to.
+@group(0) @binding(2) var<storage, read> ranges: array<f32>;
+// High half, low half, and number of clipped coefficients of every block.
+@group(0) @binding(3) var<storage, read_write> words: array<u32>;
+
+const CHROMA_TABLE = array<f32, 16>(
+    -0.35, -0.20, -0.15, -0.10, -0.077, -0.055, -0.033, -0.011,
+    0.011, 0.033, 0.055, 0.077, 0.10, 0.15, 0.20, 0.35,
+);
+
+fn sample(index: u32) -> f32 {
+    let pair = samples[index / 2u];
+    return f32((pair >> ((index % 2u) * 16u)) & 0xffffu);
+}
+
+fn component_video(pixel: u32) -> vec3<f32> {
+    let rgb = vec3<f32>(
+        sample(pixel * 3u),
+        sample(pixel * 3u + 1u),
+        sample(pixel * 3u + 2u),
+    ) / params.denominator;
+    return vec3<f32>(
+        dot(vec3<f32>(0.299, 0.587, 0.114), rgb),
+        dot(vec3<f32>(-0.168736, -0.331264, 0.5), rgb),
+        dot(vec3<f32>(0.5, -0.418688, -0.081312), rgb),
+    );
+}
+
+// Rounds halves away from zero like the CPU encoder; the builtin round goes to even.
+fn round_away(value: vec3<f32>) -> vec3<f32> {
+    return sign(value) * floor(abs(value) + 0.5);
+}
+
+fn quantize_chroma(chroma: f32) -> u32 {
+    if params.chroma_direct != 0u {
+        let levels = params.chroma_levels;
+        return u32(round_away(vec3<f32>(clamp(chroma, -0.5, 0.5) * 2.0 * levels)).x + levels);
+    }
+    // First strict minimum, starting from a distance of 1, like the CPU table search.
+    var table = CHROMA_TABLE;
+    var best = 0u;
+    var best_distance = 1.0;
+    for (var index = 0u; index < 16u; index++) {
+        let distance = abs(table[index] - chroma);
+        if distance < best_distance {
+            best = index;
+            best_distance = distance;
+        }
+    }
+    return best;
+}
+
+// Stores the low `field.x` bits of `value` at bit `field.y` of a 64-bit word held as
+// (high half, low half).
+fn put(word: vec2<u32>, field: vec2<u32>, value: u32) -> vec2<u32> {
+    let masked = value & ((1u << field.x) - 1u);
+    var result = word;
+    if field.y >= 32u {
+        result.x |= masked << (field.y - 32u);
+    } else {
+        result.y |= masked << field.y;
+        if field.y + field.x > 32u {
+            result.x |= masked >> (32u - field.y);
+        }
+    }
+    return result;
+}
+
+@compute @workgroup_size(64)
+fn encode(@builtin(global_invocation_id) id: vec3<u32>) {
+    let block = id.y * params.row_stride + id.x;
+    if block >= params.block_count {
+        return;
+    }
+    let row = block / params.blocks_per_row;
+    let col = block % params.blocks_per_row;
+    let top_left = row * 2u * params.width + col * 2u;
+    let y1 = component_video(top_left);
+    let y2 = component_video(top_left + 1u);
+    let y3 = component_video(top_left + params.width);
+    let y4 = component_video(top_left + params.width + 1u);
+
+    let a = (y4.x + y3.x + y2.x + y1.x) / 4.0;
+    let bcd = vec3<f32>(
+        y4.x + y3.x - y2.x - y1.x,
+        y4.x - y3.x + y2.x - y1.x,
+        y4.x - y3.x - y2.x + y1.x,
+    ) / 4.0;
+    let range = ranges[block];
+    let quantized = vec3<i32>(
+        round_away(clamp(bcd, vec3<f32>(-range), vec3<f32>(range)) * (params.bcd_levels / range)),
+    );
+    let outside = abs(bcd) > vec3<f32>(range);
+    let clipped = select(0u, 1u, outside.x) + select(0u, 1u, outside.y) + select(0u, 1u, outside.z);
+    let chroma = (y1.yz + y2.yz + y3.yz + y4.yz) / 4.0;
+
+    var word = vec2<u32>(0u, 0u);
+    word = put(word, params.fields[0].xy, u32(round_away(vec3<f32>(a * params.a_scale)).x));
+    word = put(word, params.fields[0].zw, bitcast<u32>(quantized.x));
+    word = put(word, params.fields[1].xy, bitcast<u32>(quantized.y));
+    word = put(word, params.fields[1].zw, bitcast<u32>(quantized.z));
+    word = put(word, params.fields[2].xy, quantize_chroma(chroma.x));
+    word = put(word, params.fields[2].zw, quantize_chroma(chroma.y));
+    words[block * 3u] = word.x;
+    words[block * 3u + 1u] = word.y;
+    words[block * 3u + 2u] = clipped;
+}
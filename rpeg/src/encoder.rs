@@ -0,0 +1,747 @@
+use crate::cancel::{CancellationToken, CANCELLED};
+use crate::codec::cache::CoefficientCache;
+use crate::codec::scratch::ScratchBuffers;
+use crate::codec::{
+    check_settings, compress_cached, compress_image_with_report, compress_into, compression_ratio,
+    EncodeReport, EncoderOptions,
+};
+use crate::color_tag::ColorTag;
+use crate::fixed::Arithmetic;
+use crate::format::Compat;
+use crate::io::RpegSink;
+use crate::layout::{WordLayout, FINE_TABLE_LAYOUT, NARROW_LAYOUT};
+use crate::ppm::{Rgb, RgbImage};
+use crate::preprocess::Rotation;
+use crate::roi::Region;
+use crate::target::QualityTarget;
+use array2::array2::Array2;
+use rayon::ThreadPool;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// ## Hardware the color conversion, 2x2 transform, and quantization run on
+///
+/// `Gpu` runs them in a wgpu compute shader and needs rpeg to be built with the `gpu` feature.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::encoder::Backend;
+///
+/// assert_eq!(Backend::default(), Backend::Cpu);
+/// ```
+pub enum Backend {
+    #[default]
+    Cpu,
+    Gpu,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// ## How an odd width or height is fitted to the 2x2 blocks
+///
+/// `Trim` drops the last column or row, unless the image is a single pixel wide or tall.
+/// `Replicate` repeats the last column or row instead, so that the edge blocks average real
+/// pixels rather than padding, and the decoder drops the copy: the decoded image keeps the
+/// dimensions of the original.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::encoder::PadPolicy;
+///
+/// assert_eq!(PadPolicy::default(), PadPolicy::Trim);
+/// assert_eq!(PadPolicy::from_name("replicate"), Some(PadPolicy::Replicate));
+/// ```
+pub enum PadPolicy {
+    #[default]
+    Trim,
+    Replicate,
+}
+
+impl PadPolicy {
+    /// Returns the pad policy called `name`: trim or replicate.
+    ///
+    /// # Arguments
+    /// * `name`: Name of the policy, as given to `--pad`
+    pub fn from_name(name: &str) -> Option<PadPolicy> {
+        match name {
+            "trim" => Some(PadPolicy::Trim),
+            "replicate" => Some(PadPolicy::Replicate),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// ## What the encoder does with an image that compresses less than the minimum ratio
+///
+/// The ratio is the size of the pixels at one byte per channel over the size of the
+/// compressed image. `Warn` keeps the compressed image, and the CLI prints a warning. `Store`
+/// replaces it with the pixels as they are, in raw tiles recorded in the header, so that noise
+/// and other images that do not compress keep every detail. `Fail` makes the compression fail.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::encoder::RatioPolicy;
+///
+/// assert_eq!(RatioPolicy::default(), RatioPolicy::Warn);
+/// assert_eq!(RatioPolicy::from_name("store"), Some(RatioPolicy::Store));
+/// ```
+pub enum RatioPolicy {
+    #[default]
+    Warn,
+    Store,
+    Fail,
+}
+
+impl RatioPolicy {
+    /// Returns the ratio policy called `name`: warn, store, or fail.
+    ///
+    /// # Arguments
+    /// * `name`: Name of the policy, as given to `--ratio-policy`
+    pub fn from_name(name: &str) -> Option<RatioPolicy> {
+        match name {
+            "warn" => Some(RatioPolicy::Warn),
+            "store" => Some(RatioPolicy::Store),
+            "fail" => Some(RatioPolicy::Fail),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// ## Named trade-offs between encoding time and quality
+///
+/// `Fast` compresses the image in 256x256 tiles, which are encoded in parallel. `Balanced` is
+/// the default settings. `Best` indexes the chroma in the 64-entry fine table and, with double
+/// precision floating point arithmetic, searches for the rounding of every block that decodes
+/// closest to it, which takes about eight times as long. Every preset keeps 32-bit code words,
+/// so the payload keeps its size.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::codec::EncoderOptions;
+/// use rpeg::encoder::Preset;
+/// use rpeg::fixed::Arithmetic;
+///
+/// assert_eq!(Preset::from_name("best"), Some(Preset::Best));
+/// let mut options = EncoderOptions {
+///     arithmetic: Arithmetic::Float,
+///     ..Default::default()
+/// };
+/// Preset::Best.apply(&mut options);
+/// assert!(options.optimize);
+/// ```
+pub enum Preset {
+    Fast,
+    #[default]
+    Balanced,
+    Best,
+}
+
+impl Preset {
+    /// Returns the preset called `name`: fast, balanced, or best.
+    ///
+    /// # Arguments
+    /// * `name`: Name of the preset, as given to `--preset`
+    pub fn from_name(name: &str) -> Option<Preset> {
+        match name {
+            "fast" => Some(Preset::Fast),
+            "balanced" => Some(Preset::Balanced),
+            "best" => Some(Preset::Best),
+            _ => None,
+        }
+    }
+
+    /// Sets the layout, rounding optimization, and tile size of `options` to those of the
+    /// preset, keeping every other setting. The rounding optimization is left off unless
+    /// `options` compute in double precision floating point, the only arithmetic it supports.
+    ///
+    /// # Arguments
+    /// * `options`: Settings to update
+    pub fn apply(self, options: &mut EncoderOptions) {
+        let (layout, optimize, tile_size) = match self {
+            Preset::Fast => (NARROW_LAYOUT, false, 256),
+            Preset::Balanced => (NARROW_LAYOUT, false, 0),
+            Preset::Best => (FINE_TABLE_LAYOUT, true, 0),
+        };
+        options.layout = layout;
+        options.optimize = optimize && options.arithmetic == Arithmetic::Float;
+        options.tile_size = tile_size;
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// ## Builder of the settings used to compress images
+///
+/// Every method sets one field of the `EncoderOptions`; unset fields keep their default.
+/// Unlike `compress_image`, `compress` reports settings that cannot be combined, or a GPU
+/// that cannot be used, as errors.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::encoder::{Backend, Encoder};
+/// use rpeg::ppm::{Rgb, RgbImage};
+///
+/// let image = RgbImage {
+///     pixels: vec![Rgb { red: 10, green: 20, blue: 30 }; 16],
+///     width: 4,
+///     height: 4,
+///     denominator: 255,
+/// };
+/// let encoder = Encoder::new().progressive(true).tile_size(2).backend(Backend::Cpu);
+/// assert!(encoder.compress(&image).is_ok());
+/// ```
+pub struct Encoder {
+    options: EncoderOptions,
+    pool: Option<Arc<ThreadPool>>,
+}
+
+impl Encoder {
+    /// Returns a builder with the default settings.
+    pub fn new() -> Self {
+        Encoder::default()
+    }
+
+    /// Stores the DC terms of every block ahead of the b/c/d refinements.
+    pub fn progressive(mut self, progressive: bool) -> Self {
+        self.options.progressive = progressive;
+        self
+    }
+
+    /// Adds a region of interest quantized with its own quality.
+    pub fn region(mut self, region: Region) -> Self {
+        self.options.regions.push(region);
+        self
+    }
+
+    /// Compresses the image as independent tiles of `tile_size` pixels, or as a single tile
+    /// for 0.
+    pub fn tile_size(mut self, tile_size: u32) -> Self {
+        self.options.tile_size = tile_size;
+        self
+    }
+
+    /// Searches the neighbouring quantized values of every block for the ones that decode
+    /// closest to the original pixels.
+    pub fn optimize(mut self, optimize: bool) -> Self {
+        self.options.optimize = optimize;
+        self
+    }
+
+    /// Clamps b, c, and d of the background blocks to ±`luma_range`.
+    pub fn luma_range(mut self, luma_range: f64) -> Self {
+        self.options.luma_range = Some(luma_range);
+        self
+    }
+
+    /// Picks the luma range of the background blocks from a first pass over the image.
+    pub fn two_pass(mut self, two_pass: bool) -> Self {
+        self.options.two_pass = two_pass;
+        self
+    }
+
+    /// Weights the range of every block by its brightness.
+    pub fn perceptual(mut self, perceptual: bool) -> Self {
+        self.options.perceptual = perceptual;
+        self
+    }
+
+    /// Stores images with few colors as a palette and run-length coded indices.
+    pub fn palette(mut self, palette: bool) -> Self {
+        self.options.palette = palette;
+        self
+    }
+
+    /// Picks the coding mode of every tile from its content.
+    pub fn detect_content(mut self, detect_content: bool) -> Self {
+        self.options.detect_content = detect_content;
+        self
+    }
+
+    /// Fits an odd width or height to the blocks as `pad` says.
+    pub fn pad(mut self, pad: PadPolicy) -> Self {
+        self.options.pad = pad;
+        self
+    }
+
+    /// Converts the image from sRGB to linear light before the color transform.
+    pub fn srgb(mut self, srgb: bool) -> Self {
+        self.options.srgb = srgb;
+        self
+    }
+
+    /// Keeps the EXIF orientation of a JPEG input in the metadata instead of turning the pixels
+    /// upright.
+    pub fn keep_orientation(mut self, keep_orientation: bool) -> Self {
+        self.options.keep_orientation = keep_orientation;
+        self
+    }
+
+    /// Stores the tiles whose code words decode below `min_psnr` decibels as raw pixels.
+    pub fn raw_below_psnr(mut self, min_psnr: f64) -> Self {
+        self.options.raw_below_psnr = Some(min_psnr);
+        self
+    }
+
+    /// Applies `policy` to images whose compression ratio is below `min_ratio`.
+    pub fn min_ratio(mut self, min_ratio: f64, policy: RatioPolicy) -> Self {
+        self.options.min_ratio = Some(min_ratio);
+        self.options.ratio_policy = policy;
+        self
+    }
+
+    /// Stops the compression, with an error, once `token` is cancelled.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.options.cancel = Some(token);
+        self
+    }
+
+    /// Runs every compression in `pool` instead of the global rayon pool. Services compressing
+    /// many small images build the pool once and share it between their encoders, rather than
+    /// paying for the threads of a new pool on every call.
+    pub fn with_thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Writes the earlier format `compat` instead of the rpeg container.
+    pub fn compat(mut self, compat: Compat) -> Self {
+        self.options.compat = Some(compat);
+        self
+    }
+
+    /// Stores the image in the chunked container, whose decoders skip the ancillary chunks
+    /// they do not know.
+    pub fn chunked(mut self, chunked: bool) -> Self {
+        self.options.chunked = chunked;
+        self
+    }
+
+    /// Scales Pb and Pr by `weight`, rounded to a quarter between 0.25 and 4, before they are
+    /// quantized: above 1 in finer steps that clip the most saturated colors, below 1 in
+    /// coarser ones. It applies to the layout set so far, which `layout` and `preset` replace.
+    pub fn chroma_weight(mut self, weight: f64) -> Self {
+        self.options.layout = self.options.layout.with_chroma_weight(weight);
+        self
+    }
+
+    /// Gives the hue priority over the luma detail, with the layout and chroma weight fitted
+    /// to the image. See `EncoderOptions::chroma_priority`.
+    pub fn chroma_priority(mut self, chroma_priority: bool) -> Self {
+        self.options.chroma_priority = chroma_priority;
+        self
+    }
+
+    /// Stores the corrections that bring every channel of the decoded image within
+    /// `max_error` 8-bit steps of the original, 0 for a lossless image. See
+    /// `EncoderOptions::max_error`.
+    pub fn max_error(mut self, max_error: u8) -> Self {
+        self.options.max_error = Some(max_error);
+        self
+    }
+
+    /// Compresses the image with the smallest settings found whose round trip reaches
+    /// `target`. See `EncoderOptions::target`.
+    pub fn target(mut self, target: QualityTarget) -> Self {
+        self.options.target = Some(target);
+        self
+    }
+
+    /// Shrinks the image before compressing it, so that neither side exceeds `max` pixels.
+    pub fn max_dimension(mut self, max: u32) -> Self {
+        self.options.preprocess.max_dimension = Some(max);
+        self
+    }
+
+    /// Rotates the image clockwise by `rotation` before compressing it.
+    pub fn rotate(mut self, rotation: Rotation) -> Self {
+        self.options.preprocess.rotation = rotation;
+        self
+    }
+
+    /// Adjusts the gamma of the image by `gamma` before compressing it.
+    pub fn gamma(mut self, gamma: f64) -> Self {
+        self.options.preprocess.gamma = Some(gamma);
+        self
+    }
+
+    /// Replaces every pixel by its luma before compressing the image.
+    pub fn grayscale(mut self, grayscale: bool) -> Self {
+        self.options.preprocess.grayscale = grayscale;
+        self
+    }
+
+    /// Packs every block into a code word of `layout`.
+    pub fn layout(mut self, layout: WordLayout) -> Self {
+        self.options.layout = layout;
+        self
+    }
+
+    /// Selects the arithmetic of the color transform and the 2x2 transform.
+    pub fn arithmetic(mut self, arithmetic: Arithmetic) -> Self {
+        self.options.arithmetic = arithmetic;
+        self
+    }
+
+    /// Selects the hardware the blocks are encoded on.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.options.backend = backend;
+        self
+    }
+
+    /// Encodes the tiles one after the other on the calling thread, which gives the same output
+    /// as the multi-threaded CPU encoder, and refuses the GPU backend.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.options.deterministic = deterministic;
+        self
+    }
+
+    /// Stores a key/value pair, such as the source filename or a comment, in the header.
+    pub fn metadata(mut self, key: &str, value: &str) -> Self {
+        self.options
+            .metadata
+            .push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Tags the image with a named color space or an ICC profile, kept in the metadata in place
+    /// of any earlier tag (see `rpeg::color_tag`).
+    pub fn color_tag(mut self, tag: &ColorTag) -> Self {
+        self.options.metadata = tag.replace_in(&self.options.metadata);
+        self
+    }
+
+    /// Stores a small copy of the image in the header for previews.
+    pub fn embed_thumbnail(mut self, embed_thumbnail: bool) -> Self {
+        self.options.embed_thumbnail = embed_thumbnail;
+        self
+    }
+
+    /// Returns the settings gathered so far.
+    pub fn options(&self) -> &EncoderOptions {
+        &self.options
+    }
+
+    /// Compresses an Rgb image with the settings of the builder.
+    ///
+    /// # Arguments
+    /// * `image`: Image to compress
+    pub fn compress(&self, image: &RgbImage) -> Result<Vec<u8>, String> {
+        self.compress_with_report(image).map(|(bytes, _)| bytes)
+    }
+
+    /// Compresses an Rgb image with the settings of the builder, and writes it to `sink`, such
+    /// as a file, a buffer, or a socket.
+    ///
+    /// # Arguments
+    /// * `image`: Image to compress
+    /// * `sink`: Destination of the compressed image
+    pub fn compress_to(&self, image: &RgbImage, sink: &mut dyn RpegSink) -> Result<(), String> {
+        sink.write_rpeg(&self.compress(image)?)
+    }
+
+    /// Compresses an Rgb image with the settings of the builder, and also reports how many of
+    /// its b, c, and d coefficients were clipped and the mean error of its quantized chroma.
+    ///
+    /// # Arguments
+    /// * `image`: Image to compress
+    pub fn compress_with_report(
+        &self,
+        image: &RgbImage,
+    ) -> Result<(Vec<u8>, EncodeReport), String> {
+        self.check()?;
+        let mut output = Vec::new();
+        let report = self.run(image, &mut output, |output| {
+            let (compressed, report) = compress_image_with_report(image, &self.options);
+            *output = compressed;
+            report
+        })?;
+        Ok((output, report))
+    }
+
+    /// Compresses an Rgb image with the settings of the builder into `output`, replacing what
+    /// it held, like `compress_with_report`, with the temporaries of `scratch`. Once `scratch`
+    /// and `output` have grown, the settings `ScratchBuffers::covers` accepts allocate nothing.
+    ///
+    /// # Arguments
+    /// * `image`: Image to compress
+    /// * `scratch`: Buffers of the temporaries, reused across calls
+    /// * `output`: Buffer receiving the compressed image
+    pub fn compress_into(
+        &self,
+        image: &RgbImage,
+        scratch: &mut ScratchBuffers,
+        output: &mut Vec<u8>,
+    ) -> Result<EncodeReport, String> {
+        scratch.verify(&self.options.layout)?;
+        self.check_settings()?;
+        self.run(image, output, |output| {
+            compress_into(image, &self.options, scratch, output)
+        })
+    }
+
+    /// Compresses the image of `cache` with the settings of the builder, like
+    /// `compress_with_report`, reusing its transforms. Returns an error if the cache was built
+    /// with other pre-processing, sRGB, or padding settings.
+    ///
+    /// # Arguments
+    /// * `cache`: Image to compress, along with its transforms
+    pub fn compress_cached(
+        &self,
+        cache: &CoefficientCache,
+    ) -> Result<(Vec<u8>, EncodeReport), String> {
+        if !cache.fits(&self.options) {
+            return Err(
+                "The cache was built with other pre-processing, sRGB, or padding settings"
+                    .to_string(),
+            );
+        }
+        self.check()?;
+        let mut output = Vec::new();
+        let report = self.run(cache.input(), &mut output, |output| {
+            let (compressed, report) = compress_cached(cache, &self.options);
+            *output = compressed;
+            report
+        })?;
+        Ok((output, report))
+    }
+
+    /// Returns an error if the settings of the builder cannot be combined, or the compression
+    /// was cancelled.
+    fn check(&self) -> Result<(), String> {
+        self.options.layout.verify()?;
+        self.check_settings()
+    }
+
+    /// Returns an error like `check`, leaving out the layout of the code words.
+    fn check_settings(&self) -> Result<(), String> {
+        check_settings(&self.options).map_err(|error| error.message)?;
+        if self.options.backend == Backend::Gpu {
+            check_gpu()?;
+        }
+        if self.options.is_cancelled() {
+            return Err(CANCELLED.to_string());
+        }
+        Ok(())
+    }
+
+    /// Runs `compress`, which writes the compression of `image` to `output`, in the thread
+    /// pool of the builder, and applies the checks that follow it.
+    fn run(
+        &self,
+        image: &RgbImage,
+        output: &mut Vec<u8>,
+        compress: impl FnOnce(&mut Vec<u8>) -> EncodeReport + Send,
+    ) -> Result<EncodeReport, String> {
+        let options = &self.options;
+        let report = match &self.pool {
+            Some(pool) => pool.install(|| compress(output)),
+            None => compress(output),
+        };
+        if options.is_cancelled() {
+            return Err(CANCELLED.to_string());
+        }
+        if let Some(min_ratio) = options.min_ratio {
+            let ratio = compression_ratio(image, output);
+            if options.ratio_policy == RatioPolicy::Fail && ratio < min_ratio {
+                return Err(format!(
+                    "The compression ratio of {ratio:.2} is below the minimum of {min_ratio}"
+                ));
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl From<EncoderOptions> for Encoder {
+    /// Returns a builder starting from existing settings.
+    fn from(options: EncoderOptions) -> Self {
+        Encoder {
+            options,
+            pool: None,
+        }
+    }
+}
+
+/// Returns an error if no GPU can be used to compress images.
+#[cfg(feature = "gpu")]
+fn check_gpu() -> Result<(), String> {
+    crate::gpu::check_available()
+}
+
+/// Returns an error if no GPU can be used to compress images.
+#[cfg(not(feature = "gpu"))]
+fn check_gpu() -> Result<(), String> {
+    Err("rpeg was built without the gpu feature".to_string())
+}
+
+/// Encodes the blocks of an image, or tile of an image, on the GPU. See `gpu::encode_words_gpu`.
+#[cfg(feature = "gpu")]
+pub(crate) fn encode_words_on_gpu(
+    image: &Array2<Rgb>,
+    image_denominator: u16,
+    luma_ranges: &[f64],
+    layout: &WordLayout,
+) -> Result<(Vec<u64>, usize), String> {
+    crate::gpu::encode_words_gpu(image, image_denominator, luma_ranges, layout)
+}
+
+/// Encodes the blocks of an image, or tile of an image, on the GPU, which needs the `gpu`
+/// feature.
+#[cfg(not(feature = "gpu"))]
+pub(crate) fn encode_words_on_gpu(
+    _image: &Array2<Rgb>,
+    _image_denominator: u16,
+    _luma_ranges: &[f64],
+    _layout: &WordLayout,
+) -> Result<(Vec<u64>, usize), String> {
+    check_gpu().map(|_| (Vec::new(), 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::compress_image;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn gradient(width: u32, height: u32) -> RgbImage {
+        RgbImage {
+            pixels: (0..width * height)
+                .map(|i| Rgb {
+                    red: (i * 7 % 256) as u16,
+                    green: (i * 3 % 256) as u16,
+                    blue: (255 - i % 256) as u16,
+                })
+                .collect(),
+            width,
+            height,
+            denominator: 255,
+        }
+    }
+
+    #[test]
+    fn builder_matches_the_options() {
+        let image = gradient(16, 10);
+        let encoder = Encoder::new().progressive(true).luma_range(0.2);
+        assert_eq!(
+            encoder.compress(&image).unwrap(),
+            compress_image(&image, encoder.options())
+        );
+        assert!(Encoder::new().tile_size(3).compress(&image).is_err());
+    }
+
+    #[test]
+    fn presets_trade_time_for_quality() {
+        let image = gradient(40, 24);
+        let float = EncoderOptions {
+            arithmetic: Arithmetic::Float,
+            ..Default::default()
+        };
+        let compress = |preset: Preset| {
+            let mut options = float.clone();
+            preset.apply(&mut options);
+            compress_image(&image, &options)
+        };
+        let balanced = compress(Preset::Balanced);
+        assert_eq!(balanced, compress_image(&image, &float));
+        let error = |compressed: &[u8]| -> u64 {
+            let decoded = crate::codec::decompress_image(compressed).unwrap();
+            decoded
+                .pixels
+                .iter()
+                .zip(image.pixels.iter())
+                .map(|(a, b)| {
+                    (a.red.abs_diff(b.red) + a.green.abs_diff(b.green) + a.blue.abs_diff(b.blue))
+                        as u64
+                })
+                .sum()
+        };
+        let (fast, best) = (compress(Preset::Fast), compress(Preset::Best));
+        assert_eq!(error(&fast), error(&balanced));
+        assert!(error(&best) < error(&balanced));
+        // Only the layout id is added to the header.
+        assert_eq!(best.len(), balanced.len() + 1);
+
+        let mut options = EncoderOptions {
+            progressive: true,
+            ..float.clone()
+        };
+        Preset::Best.apply(&mut options);
+        assert!(options.progressive && options.optimize);
+        for arithmetic in [Arithmetic::Fixed, Arithmetic::Single] {
+            let mut options = EncoderOptions {
+                arithmetic,
+                ..Default::default()
+            };
+            Preset::Best.apply(&mut options);
+            assert!(!options.optimize);
+            assert!(Encoder::from(options).compress(&image).is_ok());
+        }
+        assert_eq!(Preset::from_name("slow"), None);
+    }
+
+    #[test]
+    fn deterministic_output_is_repeatable() {
+        let image = gradient(40, 24);
+        let encoder = Encoder::new().tile_size(8).deterministic(true);
+        let compressed = encoder.compress(&image).unwrap();
+        assert_eq!(compressed, encoder.compress(&image).unwrap());
+        assert_eq!(
+            compressed,
+            Encoder::new().tile_size(8).compress(&image).unwrap()
+        );
+        let gpu = Encoder::new().deterministic(true).backend(Backend::Gpu);
+        assert!(gpu.compress(&image).is_err());
+    }
+
+    #[test]
+    fn a_shared_pool_is_reused_across_images() {
+        let started = Arc::new(AtomicUsize::new(0));
+        let counter = started.clone();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .start_handler(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .build()
+            .unwrap();
+        let encoder = Encoder::new().tile_size(8).with_thread_pool(Arc::new(pool));
+        let image = gradient(40, 24);
+        let expected = Encoder::new().tile_size(8).compress(&image).unwrap();
+        for _ in 0..20 {
+            assert_eq!(encoder.clone().compress(&image).unwrap(), expected);
+        }
+        // The threads start once, with the pool, however many images go through it.
+        assert!(started.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    #[cfg(not(feature = "gpu"))]
+    fn gpu_backend_needs_the_feature() {
+        let encoder = Encoder::new().backend(Backend::Gpu);
+        assert!(encoder.compress(&gradient(4, 4)).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "gpu")]
+    fn gpu_words_match_the_cpu_ones() {
+        let image = gradient(64, 48);
+        let gpu = Encoder::new().backend(Backend::Gpu);
+        let Ok(compressed) = gpu.compress(&image) else {
+            // No adapter in this environment.
+            return;
+        };
+        let cpu = compress_image(&image, &EncoderOptions::default());
+        assert_eq!(compressed.len(), cpu.len());
+        let differing = compressed
+            .iter()
+            .zip(cpu.iter())
+            .filter(|(a, b)| a != b)
+            .count();
+        assert!(differing * 50 < cpu.len(), "{differing} bytes differ");
+    }
+}
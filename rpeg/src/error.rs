@@ -0,0 +1,179 @@
+use crate::animation::{ANIMATION_MAGIC, ANIMATION_VERSION};
+use crate::archive::{ARCHIVE_MAGIC, ARCHIVE_VERSION};
+use crate::format::{CHUNKED_VERSION, EXTENDED_VERSION, MAGIC, VERSION};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Category of a failed command, each with its own exit status
+///
+/// The exit statuses are stable, so that scripts wrapping rpeg can branch on them. 1 is left
+/// to `rpeg diff`, which exits with it when the images differ.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::error::ErrorKind;
+///
+/// assert_eq!(ErrorKind::CorruptStream.exit_code(), 4);
+/// assert_eq!(ErrorKind::CorruptStream.name(), "corrupt_stream");
+/// ```
+pub enum ErrorKind {
+    /// Unknown flag, missing argument, or settings that cannot be combined.
+    BadArguments,
+    /// Input image that cannot be opened or is not a valid PPM or PNG image.
+    UnreadableInput,
+    /// Compressed image, archive, or multi-frame stream that cannot be decoded.
+    CorruptStream,
+    /// Compressed file written by a newer version of rpeg.
+    UnsupportedVersion,
+    /// Output that cannot be written.
+    Io,
+    /// Image compressed less than `--min-ratio` with `--ratio-policy fail`.
+    PoorRatio,
+    /// Signature missing, or not matching the image and the public key, in `rpeg verify`.
+    BadSignature,
+    /// Compression stopped by SIGINT or SIGTERM, or by a cancellation token.
+    Cancelled,
+}
+
+impl ErrorKind {
+    /// Returns the exit status of the process for this kind of failure.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::BadArguments => 2,
+            ErrorKind::UnreadableInput => 3,
+            ErrorKind::CorruptStream => 4,
+            ErrorKind::UnsupportedVersion => 5,
+            ErrorKind::Io => 6,
+            ErrorKind::PoorRatio => 7,
+            ErrorKind::BadSignature => 8,
+            ErrorKind::Cancelled => 130,
+        }
+    }
+
+    /// Returns the name of this kind of failure in machine-readable diagnostics.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::BadArguments => "bad_arguments",
+            ErrorKind::UnreadableInput => "unreadable_input",
+            ErrorKind::CorruptStream => "corrupt_stream",
+            ErrorKind::UnsupportedVersion => "unsupported_version",
+            ErrorKind::Io => "io",
+            ErrorKind::PoorRatio => "poor_ratio",
+            ErrorKind::BadSignature => "bad_signature",
+            ErrorKind::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Failure of a command, with its category and a message for the user
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::error::{CliError, ErrorKind};
+///
+/// let error = CliError::new(ErrorKind::Io, "Failed to write out.rpeg");
+/// assert_eq!(
+///     error.to_json(),
+///     r#"{"error":"io","exit_code":6,"message":"Failed to write out.rpeg"}"#
+/// );
+/// ```
+pub struct CliError {
+    /// Category of the failure.
+    pub kind: ErrorKind,
+    /// Description of the failure for the user.
+    pub message: String,
+}
+
+impl CliError {
+    /// Returns a failure of the given kind.
+    ///
+    /// # Arguments
+    /// * `kind`: Category of the failure
+    /// * `message`: Description of the failure for the user
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        CliError {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// Returns a failure to decode `bytes`, which is an `UnsupportedVersion` if they start with
+    /// the magic bytes of an rpeg format followed by a version this build does not know, and a
+    /// `CorruptStream` otherwise.
+    ///
+    /// # Arguments
+    /// * `bytes`: Compressed image, archive, or multi-frame stream that failed to decode
+    /// * `message`: Description of the failure for the user
+    pub fn stream(bytes: &[u8], message: impl Into<String>) -> Self {
+        let formats: [(&[u8; 4], &[u8]); 3] = [
+            (MAGIC, &[VERSION, EXTENDED_VERSION, CHUNKED_VERSION]),
+            (ARCHIVE_MAGIC, &[ARCHIVE_VERSION]),
+            (ANIMATION_MAGIC, &[ANIMATION_VERSION]),
+        ];
+        let unsupported = formats.iter().any(|(magic, versions)| {
+            bytes.starts_with(*magic) && bytes.get(4).is_some_and(|found| !versions.contains(found))
+        });
+        let kind = if unsupported {
+            ErrorKind::UnsupportedVersion
+        } else {
+            ErrorKind::CorruptStream
+        };
+        CliError::new(kind, message)
+    }
+
+    /// Returns the failure as a single line JSON object, with the name of its kind, its exit
+    /// status, and its message.
+    pub fn to_json(&self) -> String {
+        let mut message = String::new();
+        for character in self.message.chars() {
+            match character {
+                '"' => message.push_str("\\\""),
+                '\\' => message.push_str("\\\\"),
+                '\n' => message.push_str("\\n"),
+                character if (character as u32) < 0x20 => {
+                    message.push_str(&format!("\\u{:04x}", character as u32))
+                }
+                character => message.push(character),
+            }
+        }
+        format!(
+            r#"{{"error":"{}","exit_code":{},"message":"{message}"}}"#,
+            self.kind.name(),
+            self.kind.exit_code()
+        )
+    }
+}
+
+/// Classified failure the library returns to callers outside the command line, such as
+/// `codec::decode_unchecked_input`; the same value a command fails with.
+pub type RpegError = CliError;
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_failures_are_classified() {
+        let newer = [b'R', b'P', b'E', b'G', CHUNKED_VERSION + 1, 0];
+        assert_eq!(
+            CliError::stream(&newer, "").kind,
+            ErrorKind::UnsupportedVersion
+        );
+        let truncated = [b'R', b'P', b'E', b'G', VERSION];
+        assert_eq!(
+            CliError::stream(&truncated, "").kind,
+            ErrorKind::CorruptStream
+        );
+        let error = CliError::new(ErrorKind::BadArguments, "bad \"x\"\n");
+        assert!(error.to_json().contains(r#""message":"bad \"x\"\n""#));
+    }
+}
@@ -0,0 +1,352 @@
+use crate::chroma::ChromaQuantizer;
+use crate::codec::QuantizationStats;
+use crate::conversions::BAYER;
+use crate::layout::{ChromaCoding, WordLayout, NEUTRAL_CHROMA_WEIGHT};
+use crate::ppm::Rgb;
+use crate::structs::QuantizedBlock;
+use array2::array2::Array2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Arithmetic used by the color transform and the 2x2 transform
+///
+/// `Float` computes in `f64`. `Fixed` computes in 16.16 fixed point with integers only, so
+/// that its output is byte-identical on every platform and fast on targets without a strong
+/// FPU. `Single` computes in `f32`, which holds the 5 to 9 bits of the quantized values with room
+/// to spare, twice as many values per SIMD register, and half the memory. All three decode each
+/// other's files; their pixels may differ by a density step. Building with the `fixed-point`
+/// feature makes `Fixed` the default, and with the `single-precision` feature `Single`.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::fixed::Arithmetic;
+///
+/// let arithmetic = Arithmetic::Fixed;
+/// assert_ne!(arithmetic, Arithmetic::Float);
+/// ```
+pub enum Arithmetic {
+    Float,
+    Fixed,
+    Single,
+}
+
+impl Default for Arithmetic {
+    fn default() -> Self {
+        if cfg!(feature = "fixed-point") {
+            Arithmetic::Fixed
+        } else if cfg!(feature = "single-precision") {
+            Arithmetic::Single
+        } else {
+            Arithmetic::Float
+        }
+    }
+}
+
+/// Number of fractional bits of every fixed-point value.
+const FRACTION_BITS: u32 = 16;
+
+/// The value 1 in fixed point.
+const ONE: i64 = 1 << FRACTION_BITS;
+
+/// Rows of the RGB to Y/Pb/Pr matrix, in fixed point. Every row sums to exactly 1 or 0.
+const TO_Y: [i64; 3] = [19595, 38470, 7471];
+const TO_PB: [i64; 3] = [-11058, -21710, 32768];
+const TO_PR: [i64; 3] = [32768, -27439, -5329];
+
+/// Weights of the Y/Pb/Pr to RGB transform, in fixed point: Pr in red, Pb and Pr in green,
+/// and Pb in blue.
+const PR_TO_RED: i64 = 91881;
+const PB_TO_GREEN: i64 = -22554;
+const PR_TO_GREEN: i64 = -46802;
+const PB_TO_BLUE: i64 = 116130;
+
+/// Returns an entry of a chroma table in fixed point.
+fn fixed_chroma(entry: f32) -> i64 {
+    (entry as f64 * ONE as f64).round() as i64
+}
+
+/// Divides `numerator` by a positive `denominator`, rounding halves away from zero like
+/// `f64::round`.
+fn div_round(numerator: i64, denominator: i64) -> i64 {
+    let half = denominator / 2;
+    if numerator >= 0 {
+        (numerator + half) / denominator
+    } else {
+        -((-numerator + half) / denominator)
+    }
+}
+
+/// Converts a luma range to thousandths, the precision the header stores it with.
+fn range_millis(luma_range: f64) -> i64 {
+    (luma_range * 1000.0).round() as i64
+}
+
+/// Returns the quantized Pb or Pr of a block from the sum of its four chroma values, scaled by
+/// the chroma weight of `layout`.
+fn quantize_chroma(sum: i64, layout: &WordLayout) -> usize {
+    let sum = div_round(
+        sum * layout.chroma_weight as i64,
+        NEUTRAL_CHROMA_WEIGHT as i64,
+    );
+    match layout.chroma {
+        // Distances are compared at four times the scale of the table, that of the sum. Ties
+        // go to the lower index, like the floating point table search.
+        ChromaCoding::Indexed(table) => {
+            table
+                .entries()
+                .iter()
+                .enumerate()
+                .fold((0, i64::MAX), |(best, best_distance), (index, entry)| {
+                    let distance = (fixed_chroma(*entry) * 4 - sum).abs();
+                    if distance < best_distance {
+                        (index, distance)
+                    } else {
+                        (best, best_distance)
+                    }
+                })
+                .0
+        }
+        ChromaCoding::Direct => {
+            let levels = layout.chroma_levels() as i64;
+            let half = ONE * 2;
+            (div_round(sum.clamp(-half, half) * levels, half) + levels) as usize
+        }
+    }
+}
+
+/// Returns the chroma, in fixed point, a quantized Pb or Pr stands for.
+fn dequantize_chroma(index: usize, layout: &WordLayout) -> i64 {
+    let chroma = match layout.chroma {
+        ChromaCoding::Indexed(table) => fixed_chroma(table.chroma_of_index(index)),
+        ChromaCoding::Direct => {
+            let levels = layout.chroma_levels() as i64;
+            div_round((index as i64 - levels) * ONE, 2 * levels)
+        }
+    };
+    div_round(
+        chroma * NEUTRAL_CHROMA_WEIGHT as i64,
+        layout.chroma_weight as i64,
+    )
+}
+
+/// Quantizes a b, c, or d coefficient given as the signed sum of the four lumas of a block.
+/// Returns the quantized value and whether the coefficient was clipped.
+fn quantize_luma(sum: i64, millis: i64, levels: i64) -> (i64, bool) {
+    // The coefficient is sum / (4 * ONE) and the range is millis / 1000.
+    let limit = millis * 4 * ONE;
+    let scaled = sum * 1000;
+    let clamped = scaled.clamp(-limit, limit);
+    (div_round(clamped * levels, limit), clamped != scaled)
+}
+
+/// Integer counterpart of `encode_words`: converts every 2x2 block of an image to Y/Pb/Pr,
+/// transforms, quantizes, and packs it into a code word. Returns the words in row-major block
+/// order along with their quantization statistics, as `encode_words` does.
+///
+/// # Arguments
+/// * `image`: Image, or tile of an image, with even dimensions
+/// * `image_denominator`: Denominator of the Rgb values of the image
+/// * `luma_ranges`: Range b, c, and d of every block are clamped to, used to the thousandth
+/// * `layout`: Layout of the code words
+pub(crate) fn encode_words_fixed(
+    image: &Array2<Rgb>,
+    image_denominator: u16,
+    luma_ranges: &[f64],
+    layout: &WordLayout,
+) -> (Vec<u64>, QuantizationStats) {
+    let denominator = image_denominator as i64;
+    let to_component = |pixel: &Rgb| {
+        let rgb = [pixel.red, pixel.green, pixel.blue]
+            .map(|value| div_round(value as i64 * ONE, denominator));
+        [TO_Y, TO_PB, TO_PR]
+            .map(|row| div_round(row.iter().zip(rgb.iter()).map(|(w, v)| w * v).sum(), ONE))
+    };
+    let pixel = |col: usize, row: usize| to_component(image.get(col, row).unwrap());
+    let (a_scale, levels) = (layout.a_scale() as i64, layout.bcd_levels() as i64);
+    let mut clipped = 0;
+    let mut chroma_error = 0.0;
+    let mut words = Vec::with_capacity(luma_ranges.len());
+    let corners = (0..image.get_height())
+        .step_by(2)
+        .flat_map(|row| (0..image.get_width()).step_by(2).map(move |col| (col, row)));
+    for ((col, row), luma_range) in corners.zip(luma_ranges.iter()) {
+        let [y1, y2, y3, y4] = [
+            pixel(col, row),
+            pixel(col + 1, row),
+            pixel(col, row + 1),
+            pixel(col + 1, row + 1),
+        ];
+        let millis = range_millis(*luma_range);
+        let mut luma = |sum: i64| {
+            let (value, was_clipped) = quantize_luma(sum, millis, levels);
+            clipped += was_clipped as usize;
+            value
+        };
+        let (b, c, d) = (
+            luma(y4[0] + y3[0] - y2[0] - y1[0]),
+            luma(y4[0] - y3[0] + y2[0] - y1[0]),
+            luma(y4[0] - y3[0] - y2[0] + y1[0]),
+        );
+        let a = div_round((y1[0] + y2[0] + y3[0] + y4[0]) * a_scale, 4 * ONE).clamp(0, a_scale);
+        let mut chroma = |sum: i64| {
+            let index = quantize_chroma(sum, layout);
+            // The average is sum / 4, at the scale of ONE.
+            chroma_error +=
+                (sum - 4 * dequantize_chroma(index, layout)).abs() as f64 / (4 * ONE) as f64;
+            index
+        };
+        let (pb, pr) = (
+            chroma(y1[1] + y2[1] + y3[1] + y4[1]),
+            chroma(y1[2] + y2[2] + y3[2] + y4[2]),
+        );
+        words.push(layout.pack(&QuantizedBlock {
+            a: a as u16,
+            b: b as i16,
+            c: c as i16,
+            d: d as i16,
+            pb: pb as u16,
+            pr: pr as u16,
+        }));
+    }
+    let stats = QuantizationStats {
+        clipped,
+        chroma_error,
+        blocks: words.len(),
+    };
+    (words, stats)
+}
+
+/// Integer counterpart of `decode_words`: rebuilds the pixels of an image, or tile of an
+/// image, from its code words.
+///
+/// # Arguments
+/// * `image_data`: One code word per 2x2 block, in row-major block order
+/// * `luma_ranges`: Range b, c, and d of every block were clamped to, used to the thousandth
+/// * `width`: Width of the image in pixels
+/// * `height`: Height of the image in pixels
+/// * `dither`: Column and row of the tile within the whole image to round the pixels with
+///   ordered dithering, or None to truncate them
+/// * `layout`: Layout of the code words
+pub(crate) fn decode_words_fixed(
+    image_data: &[u64],
+    luma_ranges: &[f64],
+    width: usize,
+    height: usize,
+    dither: Option<(usize, usize)>,
+    layout: &WordLayout,
+) -> Array2<Rgb> {
+    let (a_scale, levels) = (layout.a_scale() as i64, layout.bcd_levels() as i64);
+    let mut pixels = vec![
+        Rgb {
+            red: 0,
+            green: 0,
+            blue: 0
+        };
+        width * height
+    ];
+    let blocks_per_row = width / 2;
+    for (index, (word, luma_range)) in image_data.iter().zip(luma_ranges.iter()).enumerate() {
+        let coefficient = layout.unpack(*word);
+        let millis = range_millis(*luma_range);
+        let limit = div_round(millis * ONE, 1000);
+        let luma =
+            |value: i16| div_round(value as i64 * millis * ONE, levels * 1000).clamp(-limit, limit);
+        let a = div_round(coefficient.a as i64 * ONE, a_scale).clamp(0, ONE);
+        let (b, c, d) = (
+            luma(coefficient.b),
+            luma(coefficient.c),
+            luma(coefficient.d),
+        );
+        let pb = dequantize_chroma(coefficient.pb as usize, layout);
+        let pr = dequantize_chroma(coefficient.pr as usize, layout);
+        let (col, row) = ((index % blocks_per_row) * 2, (index / blocks_per_row) * 2);
+        let corners = [
+            (col, row, a - b - c + d),
+            (col + 1, row, a - b + c - d),
+            (col, row + 1, a + b - c - d),
+            (col + 1, row + 1, a + b + c + d),
+        ];
+        for (x, y, luma) in corners {
+            let channels = [
+                luma + div_round(PR_TO_RED * pr, ONE),
+                luma + div_round(PB_TO_GREEN * pb + PR_TO_GREEN * pr, ONE),
+                luma + div_round(PB_TO_BLUE * pb, ONE),
+            ];
+            // Densities are truncated, or rounded up past the dithering threshold, exactly
+            // like the floating point pipeline truncates them.
+            let bias = match dither {
+                Some(origin) => ONE - dither_threshold(origin.0 + x, origin.1 + y),
+                None => 0,
+            };
+            let [red, green, blue] = channels
+                .map(|channel| ((channel * 255 + bias) >> FRACTION_BITS).clamp(0, 65535) as u16);
+            pixels[y * width + x] = Rgb { red, green, blue };
+        }
+    }
+    Array2::from_row_major(width, height, pixels)
+}
+
+/// Fixed-point counterpart of `conversions::dither_threshold`.
+fn dither_threshold(x: usize, y: usize) -> i64 {
+    (BAYER[y % 4][x % 4] as i64 * 2 + 1) * ONE / 32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chroma::ChromaTable;
+    use crate::layout::{FINE_CHROMA_LAYOUT, FINE_TABLE_LAYOUT, NARROW_LAYOUT, WIDE_LAYOUT};
+
+    #[test]
+    fn fixed_tables_match_the_floating_point_ones() {
+        let standard: Vec<i64> = ChromaTable::Standard
+            .entries()
+            .iter()
+            .map(|entry| fixed_chroma(*entry))
+            .collect();
+        assert_eq!(
+            standard,
+            [
+                -22938, -13107, -9830, -6554, -5046, -3604, -2163, -721, 721, 2163, 3604, 5046,
+                6554, 9830, 13107, 22938,
+            ]
+        );
+        assert_eq!(TO_Y.iter().sum::<i64>(), ONE);
+        assert_eq!(TO_PB.iter().sum::<i64>(), 0);
+        assert_eq!(TO_PR.iter().sum::<i64>(), 0);
+    }
+
+    #[test]
+    fn fixed_point_words_round_trip_flat_blocks() {
+        let image = Array2::from_row_major(
+            2,
+            2,
+            vec![
+                Rgb {
+                    red: 200,
+                    green: 100,
+                    blue: 50
+                };
+                4
+            ],
+        );
+        for layout in [
+            NARROW_LAYOUT,
+            WIDE_LAYOUT,
+            FINE_CHROMA_LAYOUT,
+            FINE_TABLE_LAYOUT,
+        ] {
+            let (words, stats) = encode_words_fixed(&image, 255, &[0.3], &layout);
+            assert_eq!(stats.clipped, 0);
+            let decoded = decode_words_fixed(&words, &[0.3], 2, 2, None, &layout);
+            for pixel in decoded.data.iter() {
+                assert!((pixel.red as i32 - 200).abs() <= 24, "{layout:?} {pixel:?}");
+                assert!(
+                    (pixel.green as i32 - 100).abs() <= 24,
+                    "{layout:?} {pixel:?}"
+                );
+                assert!((pixel.blue as i32 - 50).abs() <= 24, "{layout:?} {pixel:?}");
+            }
+        }
+    }
+}
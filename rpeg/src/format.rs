@@ -0,0 +1,1281 @@
+use crate::animation::ANIMATION_MAGIC;
+use crate::archive::ARCHIVE_MAGIC;
+use crate::content::TileMode;
+use crate::layout::{WordLayout, MAX_CHROMA_WEIGHT, NARROW_LAYOUT, NEUTRAL_CHROMA_WEIGHT};
+use crate::ppm::{Rgb, RgbImage};
+use crate::refine::{Refinement, MAX_ERROR};
+use crate::signature::SIGNATURE_LEN;
+use std::ops::Range;
+
+/// Magic bytes that open every rpeg container.
+pub const MAGIC: &[u8; 4] = b"RPEG";
+
+/// Container version written by the encoder when none of the extended flags is set.
+pub const VERSION: u8 = 1;
+
+/// Container version whose header holds a second flags byte right after the fixed part. The
+/// encoder only writes it when one of the extended flags is set, so that the other files stay
+/// readable by decoders that only know `VERSION`.
+pub const EXTENDED_VERSION: u8 = 2;
+
+/// Container version made of typed chunks (see `Chunk`), each a 4-byte tag, the length of its
+/// body as a Bigendian u32, and the body. A decoder skips the ancillary chunks it does not
+/// know, so that optional parts can be added without breaking it. The encoder only writes it
+/// when asked to.
+pub const CHUNKED_VERSION: u8 = 3;
+
+/// First line of the headerless course format, which is still accepted by the decoder.
+const LEGACY_MAGIC: &[u8] = b"Compressed image format 2";
+
+/// Version `read_version` gives the course format, whose header holds no version byte.
+pub const LEGACY_VERSION: u8 = 0;
+
+/// Function parsing the header of one format version, returning the header and the offset at
+/// which the payload starts.
+type HeaderReader = fn(&[u8]) -> Result<(Header, usize), String>;
+
+/// Header reader of every format version the decoder understands, keyed on the version. A new
+/// version adds its reader here; the older ones stay, so that no file becomes unreadable.
+const HEADER_READERS: [(u8, HeaderReader); 4] = [
+    (LEGACY_VERSION, read_legacy),
+    (VERSION, read_container),
+    (EXTENDED_VERSION, read_container),
+    (CHUNKED_VERSION, read_chunked),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Earlier format the encoder can write instead of the rpeg container
+///
+/// `Csc411` is the format of the course reference implementation: the
+/// `Compressed image format 2` header followed by the 32-bit code words in Bigendian format,
+/// with odd dimensions trimmed. Its output stays bit-identical to the reference as the rpeg
+/// container evolves, so that graders and older tools keep reading it.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::format::Compat;
+///
+/// assert_eq!(Compat::from_name("csc411"), Some(Compat::Csc411));
+/// assert_eq!(Compat::from_name("rpeg"), None);
+/// ```
+pub enum Compat {
+    Csc411,
+}
+
+impl Compat {
+    /// Returns the format called `name`: csc411.
+    ///
+    /// # Arguments
+    /// * `name`: Name of the format, as given to `--compat`
+    pub fn from_name(name: &str) -> Option<Compat> {
+        match name {
+            "csc411" => Some(Compat::Csc411),
+            _ => None,
+        }
+    }
+}
+
+/// Size in bytes of the fixed part of the header written in front of every payload.
+pub const HEADER_LEN: usize = 14;
+
+/// Size in bytes of the tag and the length in front of the body of every chunk.
+const CHUNK_HEADER_LEN: usize = 8;
+
+/// Size in bytes of the entry of one chunk in the index: its tag, and the position and length
+/// of its body, as Bigendian u32s.
+const INDEX_ENTRY_LEN: usize = 12;
+
+/// Tag of the chunk holding the flags, the dimensions, the HDR exponent, and the tile size,
+/// which opens every image in the chunked container.
+pub const HEAD_CHUNK: [u8; 4] = *b"HEAD";
+
+/// Tag of the chunk holding the quantization settings: the luma range, the code word layout,
+/// the qualities of the regions of interest, and the coding mode of every tile. It is left out
+/// when they all have their default.
+pub const QUANT_CHUNK: [u8; 4] = *b"QUAN";
+
+/// Tag of the ancillary chunk holding the key/value metadata.
+pub const METADATA_CHUNK: [u8; 4] = *b"meta";
+
+/// Tag of the ancillary chunk holding the embedded thumbnail.
+pub const THUMBNAIL_CHUNK: [u8; 4] = *b"thmb";
+
+/// Tag of the ancillary chunk holding the refinement of a near-lossless image, which a decoder
+/// that does not know it may skip for the image the code words alone decode to.
+pub const REFINEMENT_CHUNK: [u8; 4] = *b"refn";
+
+/// Tag of the chunk holding the payload: the level map, the tile directory, and the tiles. The
+/// chunks in front of it describe the image; it is followed by the index and the trailer alone.
+pub const PAYLOAD_CHUNK: [u8; 4] = *b"PAYL";
+
+/// Tag of the ancillary chunk of zero bytes written right in front of the payload chunk when
+/// the tiles would otherwise not start at a multiple of `PAYLOAD_ALIGNMENT`.
+pub const PADDING_CHUNK: [u8; 4] = *b"fill";
+
+/// Alignment in bytes of the tiles of an image in the chunked container, from the start of the
+/// image. Every code word takes a multiple of it, so a mapped file whose tiles all hold code
+/// words can be read in place as 4-byte units (see `payload_words`).
+pub const PAYLOAD_ALIGNMENT: usize = 4;
+
+/// Tag of the chunk listing every chunk in front of it, so that a reader holding the end of the
+/// file can seek to any of them.
+pub const INDEX_CHUNK: [u8; 4] = *b"INDX";
+
+/// Tag of the chunk closing the image, whose body is the signature of every byte in front of
+/// it when the image is signed, and empty otherwise.
+pub const TRAILER_CHUNK: [u8; 4] = *b"TAIL";
+
+/// Header flag set when the payload is stored in progressive order.
+const FLAG_PROGRESSIVE: u8 = 1;
+
+/// Header flag set when regions of interest follow the fixed part of the header.
+const FLAG_REGIONS: u8 = 1 << 1;
+
+/// Header flag set when the image is split into tiles whose size follows the fixed part of
+/// the header.
+const FLAG_TILED: u8 = 1 << 2;
+
+/// Header flag set when the background blocks are clamped to a range other than the default,
+/// which follows the fixed part of the header.
+const FLAG_LUMA_RANGE: u8 = 1 << 3;
+
+/// Header flag set when the code words use a layout other than the 32-bit one, whose id
+/// follows the fixed part of the header.
+const FLAG_LAYOUT: u8 = 1 << 4;
+
+/// Header flag set when key/value metadata follows the fixed part of the header.
+const FLAG_METADATA: u8 = 1 << 5;
+
+/// Header flag set when a small pre-decoded copy of the image follows the fixed part of the
+/// header.
+const FLAG_THUMBNAIL: u8 = 1 << 6;
+
+/// Header flag set when the range of every block is weighted by its brightness (see
+/// `dct_coeff::masked_luma_range`).
+const FLAG_PERCEPTUAL: u8 = 1 << 7;
+
+/// Extended header flag set when every tile is stored as a palette followed by run-length
+/// coded indices (see `rpeg::palette`) instead of code words.
+const EXTENDED_FLAG_PALETTE: u8 = 1;
+
+/// Extended header flag set when the coding mode of every tile follows the region qualities,
+/// one byte per tile.
+const EXTENDED_FLAG_TILE_MODES: u8 = 1 << 1;
+
+/// Extended header flag set when the code words hold linear light, which the decoder converts
+/// back to sRGB.
+const EXTENDED_FLAG_SRGB: u8 = 1 << 2;
+
+/// Extended header flag set when the image is a log-coded HDR image (see `rpeg::hdr`), followed
+/// by the exponent of its peak, one signed byte.
+const EXTENDED_FLAG_HDR: u8 = 1 << 3;
+
+/// Extended header flag set when the payload is followed by an Ed25519 signature of every byte
+/// in front of it (see `rpeg::signature`).
+const EXTENDED_FLAG_SIGNED: u8 = 1 << 4;
+
+/// Extended header flag set when Pb and Pr are weighted against the luma (see
+/// `layout::WordLayout::with_chroma_weight`), followed by the weight in quarters, one byte
+/// after the layout id.
+const EXTENDED_FLAG_CHROMA_WEIGHT: u8 = 1 << 5;
+
+/// Extended header flag set when the corrections bounding the error of every channel (see
+/// `rpeg::refine`) follow the thumbnail.
+const EXTENDED_FLAG_REFINEMENT: u8 = 1 << 6;
+
+/// Header flags a decoder of `VERSION` understands.
+const KNOWN_FLAGS: u8 = FLAG_PROGRESSIVE
+    | FLAG_REGIONS
+    | FLAG_TILED
+    | FLAG_LUMA_RANGE
+    | FLAG_LAYOUT
+    | FLAG_METADATA
+    | FLAG_THUMBNAIL
+    | FLAG_PERCEPTUAL;
+
+/// Header flags of the chunked container, where the metadata and the thumbnail are told by
+/// their chunks.
+const CHUNKED_FLAGS: u8 = KNOWN_FLAGS & !(FLAG_METADATA | FLAG_THUMBNAIL);
+
+/// Extended header flags a decoder of `EXTENDED_VERSION` understands.
+const KNOWN_EXTENDED_FLAGS: u8 = EXTENDED_FLAG_PALETTE
+    | EXTENDED_FLAG_TILE_MODES
+    | EXTENDED_FLAG_SRGB
+    | EXTENDED_FLAG_HDR
+    | EXTENDED_FLAG_SIGNED
+    | EXTENDED_FLAG_CHROMA_WEIGHT
+    | EXTENDED_FLAG_REFINEMENT;
+
+/// Extended header flags of the chunked container, where the refinement is told by its chunk.
+const CHUNKED_EXTENDED_FLAGS: u8 = KNOWN_EXTENDED_FLAGS & !EXTENDED_FLAG_REFINEMENT;
+
+/// Largest number of metadata entries, and largest size in bytes of a metadata key or value.
+pub const MAX_METADATA_LEN: usize = u16::MAX as usize;
+
+/// Range b, c, and d of the background blocks are clamped to by default, in thousandths.
+pub const DEFAULT_LUMA_RANGE_MILLIS: u16 = 300;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Order in which the code words of the image are stored in the payload
+///
+/// `Sequential` stores one 32-bit word per 2x2 block in row-major order. `Progressive`
+/// stores the DC terms (`a`, `index_of_pb`, `index_of_pr`) of every block first, followed
+/// by the b/c/d refinements, so any prefix of the payload can be decoded into a preview.
+pub enum WordOrder {
+    Sequential,
+    Progressive,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Represent the header of a compressed image
+///
+/// The header records the dimensions of the decoded image and how the payload
+/// following it is laid out. The blocks cover the dimensions rounded up to even; along an odd
+/// dimension, the decoder drops the padding pixels of the last blocks. The encoder only writes
+/// an odd dimension when it is 1 or when the image was padded with `PadPolicy::Replicate`.
+/// When `region_qualities` is not empty, the header is followed by a map giving the
+/// quantization level of every block (see `rpeg::roi`). When `tile_size` is not 0, the payload
+/// starts with the length of every tile (see `rpeg::tiles`).
+/// `luma_range` is the range b, c, and d of the background blocks are clamped to, in
+/// thousandths; it is only stored when it differs from the default of 300. `layout` gives the
+/// bit fields of the code words and the weight of their chroma (see `rpeg::layout`). `metadata` holds key/value pairs such as
+/// the source filename or a comment, in the order they were given; it is only stored when not
+/// empty. `thumbnail` is a small copy of the image stored as raw 8-bit RGB, so that previews
+/// can be shown without decoding the payload (see `rpeg::thumbnail`). `perceptual` is set when
+/// the range of every block is weighted by the brightness of the block. `palette` is set when
+/// every tile is palette coded (see `rpeg::palette`), in which case the order, the luma range,
+/// and the layout do not apply to the payload. `tile_modes` holds the content chosen for every
+/// tile in row-major tile order (see `rpeg::content`); it is only stored when not empty, and
+/// when empty every tile is a photo, or a graphic if `palette` is set. `srgb` is set when the
+/// image was converted from sRGB to linear light before the color transform, so that the
+/// decoder converts it back. `hdr_exponent` is set for an HDR image whose densities were log
+/// coded below a peak of 2^`hdr_exponent` (see `rpeg::hdr`). `signed` is set when the payload
+/// is followed by a signature of the file (see `rpeg::signature`). `chunked` is set when the
+/// image is stored in the chunked container of `CHUNKED_VERSION`, which `write_image` writes.
+/// `refinement` holds the corrections of a near-lossless image, which bound the error of every
+/// decoded channel (see `rpeg::refine`).
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::format::{Header, WordOrder};
+/// use rpeg::layout::WIDE_LAYOUT;
+///
+/// let header = Header {
+///     width: 4,
+///     height: 2,
+///     order: WordOrder::Progressive,
+///     region_qualities: vec![90],
+///     tile_size: 512,
+///     luma_range: 500,
+///     layout: WIDE_LAYOUT,
+///     metadata: vec![("source".to_string(), "chart.ppm".to_string())],
+///     thumbnail: None,
+///     perceptual: true,
+///     palette: false,
+///     tile_modes: Vec::new(),
+///     srgb: false,
+///     hdr_exponent: Some(3),
+///     signed: false,
+///     chunked: false,
+///     refinement: None,
+/// };
+/// let mut bytes = Vec::new();
+/// header.write(&mut bytes);
+/// let (read_back, payload_start) = Header::read(&bytes).unwrap();
+/// assert_eq!(header, read_back);
+/// assert_eq!(payload_start, bytes.len());
+/// ```
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub order: WordOrder,
+    pub region_qualities: Vec<u8>,
+    pub tile_size: u32,
+    pub luma_range: u16,
+    pub layout: WordLayout,
+    pub metadata: Vec<(String, String)>,
+    pub thumbnail: Option<RgbImage>,
+    pub perceptual: bool,
+    pub palette: bool,
+    pub tile_modes: Vec<TileMode>,
+    pub srgb: bool,
+    pub hdr_exponent: Option<i8>,
+    pub signed: bool,
+    pub chunked: bool,
+    pub refinement: Option<Refinement>,
+}
+
+impl Header {
+    /// Returns the number of 2x2 blocks, and therefore code words, stored in the payload.
+    pub fn block_count(&self) -> usize {
+        (self.coded_width() as usize / 2) * (self.coded_height() as usize / 2)
+    }
+
+    /// Returns the width in pixels of the grid of blocks, which is `width` rounded up to even.
+    pub fn coded_width(&self) -> u32 {
+        self.width + self.width % 2
+    }
+
+    /// Returns the height in pixels of the grid of blocks, which is `height` rounded up to even.
+    pub fn coded_height(&self) -> u32 {
+        self.height + self.height % 2
+    }
+
+    /// Returns the number of tiles of the image, 1 when it is untiled.
+    pub fn tile_count(&self) -> usize {
+        let (width, height) = (self.coded_width() as u64, self.coded_height() as u64);
+        match self.tile_size as u64 {
+            0 => 1,
+            tile_size => (width.div_ceil(tile_size) * height.div_ceil(tile_size)) as usize,
+        }
+    }
+
+    /// Returns the number of bytes in front of the tiles within the payload: the level map and
+    /// the tile directory, or None while the count of runs opening the level map is cut short.
+    ///
+    /// # Arguments
+    /// * `payload`: First bytes of the payload
+    fn tiles_start(&self, payload: &[u8]) -> Option<usize> {
+        let level_map = if self.region_qualities.is_empty() {
+            0
+        } else {
+            let runs = u32::from_be_bytes(payload.get(..4)?.try_into().ok()?);
+            4 + runs as usize * 5
+        };
+        let directory = if self.tile_size == 0 {
+            0
+        } else {
+            self.tile_count() * 4
+        };
+        Some(level_map + directory)
+    }
+
+    /// Returns the coding mode of the tile at `index` in row-major tile order.
+    ///
+    /// # Arguments
+    /// * `index`: Index of the tile
+    pub fn tile_mode(&self, index: usize) -> TileMode {
+        match self.tile_modes.get(index) {
+            Some(mode) => *mode,
+            None if self.palette => TileMode::Graphic,
+            None => TileMode::Photo,
+        }
+    }
+
+    /// Returns the range b, c, and d of the background blocks are clamped to.
+    pub fn background_range(&self) -> f64 {
+        self.luma_range as f64 / 1000.0
+    }
+
+    /// Appends the `Compressed image format 2\n{width} {height}\n` header of the course
+    /// format to `out`, which only holds the dimensions: the payload must be sequential 32-bit
+    /// code words of the default luma range, without tiles, regions, or metadata.
+    ///
+    /// # Arguments
+    /// * `out`: Buffer receiving the header bytes
+    pub fn write_legacy(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(LEGACY_MAGIC);
+        out.extend_from_slice(format!("\n{} {}\n", self.width, self.height).as_bytes());
+    }
+
+    /// Returns the header flags and the extended header flags of the header.
+    fn flags(&self) -> (u8, u8) {
+        let mut flags: u8 = 0;
+        if self.order == WordOrder::Progressive {
+            flags |= FLAG_PROGRESSIVE;
+        }
+        if !self.region_qualities.is_empty() {
+            flags |= FLAG_REGIONS;
+        }
+        if self.tile_size != 0 {
+            flags |= FLAG_TILED;
+        }
+        if self.luma_range != DEFAULT_LUMA_RANGE_MILLIS {
+            flags |= FLAG_LUMA_RANGE;
+        }
+        if self.layout.id != NARROW_LAYOUT.id {
+            flags |= FLAG_LAYOUT;
+        }
+        if !self.metadata.is_empty() {
+            flags |= FLAG_METADATA;
+        }
+        if self.thumbnail.is_some() {
+            flags |= FLAG_THUMBNAIL;
+        }
+        if self.perceptual {
+            flags |= FLAG_PERCEPTUAL;
+        }
+        let mut extended_flags: u8 = 0;
+        if self.palette {
+            extended_flags |= EXTENDED_FLAG_PALETTE;
+        }
+        if !self.tile_modes.is_empty() {
+            extended_flags |= EXTENDED_FLAG_TILE_MODES;
+        }
+        if self.srgb {
+            extended_flags |= EXTENDED_FLAG_SRGB;
+        }
+        if self.hdr_exponent.is_some() {
+            extended_flags |= EXTENDED_FLAG_HDR;
+        }
+        if self.signed {
+            extended_flags |= EXTENDED_FLAG_SIGNED;
+        }
+        if self.layout.chroma_weight != NEUTRAL_CHROMA_WEIGHT {
+            extended_flags |= EXTENDED_FLAG_CHROMA_WEIGHT;
+        }
+        if self.refinement.is_some() {
+            extended_flags |= EXTENDED_FLAG_REFINEMENT;
+        }
+        (flags, extended_flags)
+    }
+
+    /// Appends the binary representation of the header to `out`, in the container of
+    /// `VERSION` or `EXTENDED_VERSION` whatever `chunked` says: the chunked container frames
+    /// the payload too, so `write_image` writes it.
+    ///
+    /// # Arguments
+    /// * `out`: Buffer receiving the header bytes
+    pub fn write(&self, out: &mut Vec<u8>) {
+        let (flags, extended_flags) = self.flags();
+        out.extend_from_slice(MAGIC);
+        out.push(if extended_flags == 0 {
+            VERSION
+        } else {
+            EXTENDED_VERSION
+        });
+        out.push(flags);
+        out.extend_from_slice(&self.width.to_be_bytes());
+        out.extend_from_slice(&self.height.to_be_bytes());
+        if extended_flags != 0 {
+            out.push(extended_flags);
+        }
+        self.write_tiling(out);
+        self.write_quantization(out);
+        self.write_metadata(out);
+        self.write_thumbnail(out);
+        self.write_refinement(out);
+    }
+
+    /// Appends the HDR exponent and the tile size, when the header has them, to `out`.
+    fn write_tiling(&self, out: &mut Vec<u8>) {
+        if let Some(exponent) = self.hdr_exponent {
+            out.push(exponent as u8);
+        }
+        if self.tile_size != 0 {
+            out.extend_from_slice(&self.tile_size.to_be_bytes());
+        }
+    }
+
+    /// Appends the luma range, the layout id, the chroma weight, the region qualities, and the
+    /// tile modes, when they differ from their defaults, to `out`.
+    fn write_quantization(&self, out: &mut Vec<u8>) {
+        if self.luma_range != DEFAULT_LUMA_RANGE_MILLIS {
+            out.extend_from_slice(&self.luma_range.to_be_bytes());
+        }
+        if self.layout.id != NARROW_LAYOUT.id {
+            out.push(self.layout.id);
+        }
+        if self.layout.chroma_weight != NEUTRAL_CHROMA_WEIGHT {
+            out.push(self.layout.chroma_weight);
+        }
+        if !self.region_qualities.is_empty() {
+            out.push(self.region_qualities.len() as u8);
+            out.extend_from_slice(&self.region_qualities);
+        }
+        out.extend(self.tile_modes.iter().map(|mode| mode.id()));
+    }
+
+    /// Appends the metadata, when the header has some, to `out`.
+    fn write_metadata(&self, out: &mut Vec<u8>) {
+        if !self.metadata.is_empty() {
+            out.extend_from_slice(&(self.metadata.len() as u16).to_be_bytes());
+            for text in self.metadata.iter().flat_map(|(key, value)| [key, value]) {
+                out.extend_from_slice(&(text.len() as u16).to_be_bytes());
+                out.extend_from_slice(text.as_bytes());
+            }
+        }
+    }
+
+    /// Appends the thumbnail, when the header has one, to `out`.
+    fn write_thumbnail(&self, out: &mut Vec<u8>) {
+        if let Some(thumbnail) = &self.thumbnail {
+            out.extend_from_slice(&(thumbnail.width as u16).to_be_bytes());
+            out.extend_from_slice(&(thumbnail.height as u16).to_be_bytes());
+            let scale = |value: u16| {
+                (value as u32 * 255 / thumbnail.denominator.max(1) as u32).min(255) as u8
+            };
+            for pixel in &thumbnail.pixels {
+                out.extend_from_slice(&[pixel.red, pixel.green, pixel.blue].map(scale));
+            }
+        }
+    }
+
+    /// Appends the refinement, when the header has one, to `out`: the max error, followed by
+    /// the length of the corrections as a Bigendian u32 and the corrections.
+    fn write_refinement(&self, out: &mut Vec<u8>) {
+        if let Some(refinement) = &self.refinement {
+            out.push(refinement.max_error);
+            out.extend_from_slice(&(refinement.bits.len() as u32).to_be_bytes());
+            out.extend_from_slice(&refinement.bits);
+        }
+    }
+
+    /// Appends a whole compressed image to `out`: the header followed by the payload, in the
+    /// container `chunked` selects. In the chunked container, the header is split into the
+    /// chunks of its parts, with a padding chunk when needed so that the tiles start at a
+    /// multiple of `PAYLOAD_ALIGNMENT`, and the payload chunk is followed by the index and the
+    /// trailer. When
+    /// `signed` is set, the image is left without its last `SIGNATURE_LEN` bytes, the
+    /// signature `rpeg::signature` appends.
+    ///
+    /// # Arguments
+    /// * `payload`: Parts of the payload, in order: the level map, the tile directory, and the
+    ///   tiles
+    /// * `out`: Buffer receiving the image
+    pub fn write_image(&self, payload: &[&[u8]], out: &mut Vec<u8>) {
+        if !self.chunked {
+            self.write(out);
+            payload.iter().for_each(|part| out.extend_from_slice(part));
+            return;
+        }
+        let start = out.len();
+        let mut index = Vec::new();
+        let mut write_chunk = |out: &mut Vec<u8>, tag: [u8; 4], parts: &[&[u8]]| {
+            let len: usize = parts.iter().map(|part| part.len()).sum();
+            let offset = out.len() + CHUNK_HEADER_LEN - start;
+            index.extend_from_slice(&tag);
+            index.extend_from_slice(&(offset as u32).to_be_bytes());
+            index.extend_from_slice(&(len as u32).to_be_bytes());
+            out.extend_from_slice(&tag);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+            parts.iter().for_each(|part| out.extend_from_slice(part));
+        };
+        out.extend_from_slice(MAGIC);
+        out.push(CHUNKED_VERSION);
+        let (flags, extended_flags) = self.flags();
+        let mut head = vec![flags & CHUNKED_FLAGS];
+        head.extend_from_slice(&self.width.to_be_bytes());
+        head.extend_from_slice(&self.height.to_be_bytes());
+        head.push(extended_flags & CHUNKED_EXTENDED_FLAGS);
+        self.write_tiling(&mut head);
+        write_chunk(out, HEAD_CHUNK, &[&head]);
+        let mut quantization = Vec::new();
+        self.write_quantization(&mut quantization);
+        if !quantization.is_empty() {
+            write_chunk(out, QUANT_CHUNK, &[&quantization]);
+        }
+        if !self.metadata.is_empty() {
+            let mut metadata = Vec::new();
+            self.write_metadata(&mut metadata);
+            write_chunk(out, METADATA_CHUNK, &[&metadata]);
+        }
+        if self.thumbnail.is_some() {
+            let mut thumbnail = Vec::new();
+            self.write_thumbnail(&mut thumbnail);
+            write_chunk(out, THUMBNAIL_CHUNK, &[&thumbnail]);
+        }
+        if self.refinement.is_some() {
+            let mut refinement = Vec::new();
+            self.write_refinement(&mut refinement);
+            write_chunk(out, REFINEMENT_CHUNK, &[&refinement]);
+        }
+        let opening: Vec<u8> = payload
+            .iter()
+            .flat_map(|part| part.iter())
+            .take(4)
+            .copied()
+            .collect();
+        let tiles_start =
+            out.len() - start + CHUNK_HEADER_LEN + self.tiles_start(&opening).unwrap_or(0);
+        if !tiles_start.is_multiple_of(PAYLOAD_ALIGNMENT) {
+            // The padding chunk moves the payload by its header, a multiple of the alignment, and
+            // by its body.
+            let fill = PAYLOAD_ALIGNMENT - tiles_start % PAYLOAD_ALIGNMENT;
+            write_chunk(out, PADDING_CHUNK, &[&[0; PAYLOAD_ALIGNMENT][..fill]]);
+        }
+        write_chunk(out, PAYLOAD_CHUNK, payload);
+        let trailer_len = if self.signed { SIGNATURE_LEN } else { 0 };
+        out.extend_from_slice(&INDEX_CHUNK);
+        out.extend_from_slice(&(index.len() as u32).to_be_bytes());
+        out.extend_from_slice(&index);
+        out.extend_from_slice(&TRAILER_CHUNK);
+        out.extend_from_slice(&(trailer_len as u32).to_be_bytes());
+    }
+
+    /// Parses the header at the start of `bytes` with the reader of its version in
+    /// `HEADER_READERS`. Returns the header and the offset at which the payload starts. Both
+    /// the rpeg container and the legacy `Compressed image format 2` header are understood.
+    ///
+    /// # Arguments
+    /// * `bytes`: Raw bytes of a compressed image
+    pub fn read(bytes: &[u8]) -> Result<(Header, usize), String> {
+        let version = read_version(bytes)?;
+        let (_, reader) = HEADER_READERS
+            .iter()
+            .find(|(known, _)| *known == version)
+            .ok_or_else(|| {
+                let known: Vec<String> = HEADER_READERS
+                    .iter()
+                    .map(|(known, _)| known.to_string())
+                    .collect();
+                format!(
+                    "Unsupported rpeg version {version}, this decoder reads versions {}",
+                    known.join(", ")
+                )
+            })?;
+        reader(bytes)
+    }
+
+    /// Parses the header at the start of `bytes` like `read`, and returns it with the range of
+    /// `bytes` holding the payload: the level map, the tile directory, and the tiles, without
+    /// the chunks and the signature that follow them. For a truncated image, the range ends
+    /// with the part of the payload that arrived.
+    ///
+    /// # Arguments
+    /// * `bytes`: Raw bytes of a compressed image
+    pub fn locate(bytes: &[u8]) -> Result<(Header, Range<usize>), String> {
+        let (header, start) = Header::read(bytes)?;
+        if !header.chunked {
+            let signature_len = if header.signed { SIGNATURE_LEN } else { 0 };
+            let end = bytes
+                .len()
+                .checked_sub(signature_len)
+                .filter(|end| *end >= start)
+                .ok_or("Ran out of bytes while reading the signature")?;
+            return Ok((header, start..end));
+        }
+        let chunks = read_chunks(bytes)?;
+        let payload = chunks
+            .iter()
+            .position(|chunk| chunk.offset == start)
+            .ok_or("The chunks do not hold the payload of the header")?;
+        let trailer_len = if header.signed { SIGNATURE_LEN } else { 0 };
+        let expected = [
+            (INDEX_CHUNK, (payload + 1) * INDEX_ENTRY_LEN),
+            (TRAILER_CHUNK, trailer_len),
+        ];
+        if chunks[payload + 1..]
+            .iter()
+            .zip(expected)
+            .any(|(chunk, (tag, len))| chunk.tag != tag || chunk.len != len)
+        {
+            return Err(
+                "The payload chunk is not followed by the index and the trailer".to_string(),
+            );
+        }
+        Ok((header, start..chunks[payload].end().min(bytes.len())))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## One chunk of an image in the chunked container
+///
+/// `tag` names what the chunk holds, `offset` is the position of its body in the file, and
+/// `len` is the length of its body, which runs past the end of a truncated file. A chunk whose
+/// tag starts with a lowercase letter is ancillary: a decoder that does not know it skips it.
+/// An unknown chunk whose tag starts with an uppercase letter is needed to decode the image,
+/// and the decoder fails rather than misread it. New chunks go in front of the payload chunk.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::encoder::Encoder;
+/// use rpeg::format::{
+///     read_chunks, HEAD_CHUNK, INDEX_CHUNK, PADDING_CHUNK, PAYLOAD_CHUNK, TRAILER_CHUNK,
+/// };
+/// use rpeg::ppm::{Rgb, RgbImage};
+///
+/// let image = RgbImage {
+///     pixels: vec![Rgb { red: 120, green: 90, blue: 60 }; 4 * 4],
+///     width: 4,
+///     height: 4,
+///     denominator: 255,
+/// };
+/// let compressed = Encoder::new().chunked(true).compress(&image).unwrap();
+/// let chunks = read_chunks(&compressed).unwrap();
+/// let tags: Vec<[u8; 4]> = chunks.iter().map(|chunk| chunk.tag).collect();
+/// assert_eq!(tags, [HEAD_CHUNK, PADDING_CHUNK, PAYLOAD_CHUNK, INDEX_CHUNK, TRAILER_CHUNK]);
+/// assert!(chunks[1].is_ancillary());
+/// assert_eq!(chunks[2].len, 4 * 4);
+/// assert!(!chunks[2].is_ancillary());
+/// ```
+pub struct Chunk {
+    pub tag: [u8; 4],
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl Chunk {
+    /// Returns true if a decoder that does not know the chunk may skip it.
+    pub fn is_ancillary(&self) -> bool {
+        self.tag[0].is_ascii_lowercase()
+    }
+
+    /// Returns the position right after the body of the chunk.
+    pub fn end(&self) -> usize {
+        self.offset.saturating_add(self.len)
+    }
+
+    /// Returns the tag of the chunk as text.
+    pub fn name(&self) -> String {
+        String::from_utf8_lossy(&self.tag).into_owned()
+    }
+}
+
+/// Returns the chunks of an image in the chunked container, in the order they are stored, up
+/// to the trailer or the end of `bytes`, whichever comes first; the last one may be cut short.
+/// Returns an error for images in another container, and for bytes after the trailer.
+///
+/// # Arguments
+/// * `bytes`: Raw bytes of a compressed image
+pub fn read_chunks(bytes: &[u8]) -> Result<Vec<Chunk>, String> {
+    if read_version(bytes)? != CHUNKED_VERSION {
+        return Err("The image is not in the chunked container".to_string());
+    }
+    let mut chunks = Vec::new();
+    let mut pos = MAGIC.len() + 1;
+    while let (Ok(tag), Ok(len)) = (read_bytes(bytes, pos), read_bytes(bytes, pos + 4)) {
+        let chunk = Chunk {
+            tag,
+            offset: pos + CHUNK_HEADER_LEN,
+            len: u32::from_be_bytes(len) as usize,
+        };
+        chunks.push(chunk);
+        pos = chunk.end();
+        if chunk.tag == TRAILER_CHUNK {
+            if pos < bytes.len() {
+                return Err(format!(
+                    "Found {} unexpected bytes after the trailer",
+                    bytes.len() - pos
+                ));
+            }
+            break;
+        }
+    }
+    Ok(chunks)
+}
+
+/// Returns the position in `bytes` of the tiles of a compressed image, right after its level
+/// map and tile directory, which is that of its first code word when its first tile holds
+/// code words. In the chunked container, it is a multiple of `PAYLOAD_ALIGNMENT`.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+pub fn payload_offset(bytes: &[u8]) -> Result<usize, String> {
+    let (header, payload) = Header::locate(bytes)?;
+    let tiles_start = header
+        .tiles_start(&bytes[payload.clone()])
+        .filter(|tiles_start| *tiles_start <= payload.len())
+        .ok_or("Ran out of bytes while reading the tile directory")?;
+    Ok(payload.start + tiles_start)
+}
+
+/// Returns the tiles of a compressed image as 4-byte units, without copying them: one unit per
+/// code word of a 32-bit layout, and two, most significant first, per word of a 64-bit one, in
+/// the order the tiles and their words are stored. In the chunked container, the units of a
+/// mapped file are aligned for reading as `u32`. Returns an error when a tile is palette coded
+/// or raw, which hold no code words.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+pub fn payload_words(bytes: &[u8]) -> Result<&[[u8; 4]], String> {
+    let (header, payload) = Header::locate(bytes)?;
+    if (0..header.tile_count()).any(|index| !header.tile_mode(index).holds_words()) {
+        return Err("Palette-coded and raw tiles hold no code words".to_string());
+    }
+    let (units, rest) = bytes[payload_offset(bytes)?..payload.end].as_chunks::<4>();
+    if !rest.is_empty() {
+        return Err("Ran out of bytes while reading the compressed data".to_string());
+    }
+    Ok(units)
+}
+
+/// Returns the size in bytes of the chunked image whose first bytes are `bytes`, signature
+/// included, once the chunks in front of the payload and the length of the payload have
+/// arrived, or None before.
+///
+/// # Arguments
+/// * `bytes`: First bytes of a compressed image in the chunked container
+pub(crate) fn chunked_len(bytes: &[u8]) -> Option<usize> {
+    let (header, start) = read_chunked(bytes).ok()?;
+    let chunks = read_chunks(bytes).ok()?;
+    let payload = chunks.iter().position(|chunk| chunk.offset == start)?;
+    let trailer_len = if header.signed { SIGNATURE_LEN } else { 0 };
+    Some(
+        chunks[payload].end()
+            + CHUNK_HEADER_LEN
+            + (payload + 1) * INDEX_ENTRY_LEN
+            + CHUNK_HEADER_LEN
+            + trailer_len,
+    )
+}
+
+/// Returns the format version of a compressed image: `LEGACY_VERSION` for the course format,
+/// or the version byte of the rpeg container, which may be one this decoder cannot read.
+///
+/// # Arguments
+/// * `bytes`: Raw bytes of a compressed image
+pub fn read_version(bytes: &[u8]) -> Result<u8, String> {
+    if bytes.starts_with(LEGACY_MAGIC) {
+        return Ok(LEGACY_VERSION);
+    }
+    let other = if bytes.starts_with(ARCHIVE_MAGIC) {
+        Some(("an rpeg archive", "rpeg unpack or rpeg archive extract"))
+    } else if bytes.starts_with(ANIMATION_MAGIC) {
+        Some((
+            "a multi-frame rpeg stream",
+            "rpeg decompress --frames or --frame n",
+        ))
+    } else {
+        None
+    };
+    if let Some((kind, command)) = other {
+        return Err(format!(
+            "Input is {kind}, not a compressed image; use {command}"
+        ));
+    }
+    if !bytes.starts_with(MAGIC) {
+        return Err("Input is not a compressed rpeg image".to_string());
+    }
+    bytes
+        .get(4)
+        .copied()
+        .ok_or_else(|| "Ran out of bytes while reading the header".to_string())
+}
+
+/// Parses the header of the rpeg container, versions `VERSION` and `EXTENDED_VERSION`.
+///
+/// # Arguments
+/// * `bytes`: Raw bytes starting with the container magic bytes
+fn read_container(bytes: &[u8]) -> Result<(Header, usize), String> {
+    if bytes.len() < HEADER_LEN {
+        return Err("Ran out of bytes while reading the header".to_string());
+    }
+    let extended = bytes[4] == EXTENDED_VERSION;
+    let (mut header, flags, extended_flags, pos) =
+        read_fixed(bytes, 5, extended, KNOWN_FLAGS, KNOWN_EXTENDED_FLAGS)?;
+    let pos = read_tiling(&mut header, flags, extended_flags, bytes, pos)?;
+    let mut pos = read_quantization(&mut header, flags, extended_flags, bytes, pos)?;
+    if flags & FLAG_METADATA != 0 {
+        (header.metadata, pos) = read_metadata(bytes, pos)?;
+    }
+    if flags & FLAG_THUMBNAIL != 0 {
+        let (thumbnail, next) = read_thumbnail(bytes, pos)?;
+        (header.thumbnail, pos) = (Some(thumbnail), next);
+    }
+    if extended_flags & EXTENDED_FLAG_REFINEMENT != 0 {
+        let (refinement, next) = read_refinement(bytes, pos)?;
+        (header.refinement, pos) = (Some(refinement), next);
+    }
+    Ok((header, pos))
+}
+
+/// Parses the chunks of the chunked container in front of the payload, returning the header
+/// and the position of the body of the payload chunk. Unknown ancillary chunks are skipped.
+///
+/// # Arguments
+/// * `bytes`: Raw bytes starting with the container magic bytes
+fn read_chunked(bytes: &[u8]) -> Result<(Header, usize), String> {
+    let chunks = read_chunks(bytes)?;
+    let body = |chunk: &Chunk| {
+        bytes
+            .get(chunk.offset..chunk.end())
+            .ok_or_else(|| "Ran out of bytes while reading the header".to_string())
+    };
+    let check_end = |chunk: &Chunk, pos: usize| {
+        if pos == chunk.len {
+            Ok(())
+        } else {
+            Err(format!(
+                "Found {} unexpected bytes in the {} chunk",
+                chunk.len - pos,
+                chunk.name()
+            ))
+        }
+    };
+    let head = match chunks.first() {
+        None => return Err("Ran out of bytes while reading the header".to_string()),
+        Some(head) if head.tag != HEAD_CHUNK => {
+            return Err(format!(
+                "The first chunk is {} instead of {}",
+                head.name(),
+                String::from_utf8_lossy(&HEAD_CHUNK)
+            ))
+        }
+        Some(head) => head,
+    };
+    let (mut header, flags, extended_flags, pos) =
+        read_fixed(body(head)?, 0, true, CHUNKED_FLAGS, CHUNKED_EXTENDED_FLAGS)?;
+    let pos = read_tiling(&mut header, flags, extended_flags, body(head)?, pos)?;
+    check_end(head, pos)?;
+    header.chunked = true;
+    let mut quantized = false;
+    for chunk in &chunks[1..] {
+        match chunk.tag {
+            QUANT_CHUNK => {
+                let pos = read_quantization(&mut header, flags, extended_flags, body(chunk)?, 0)?;
+                check_end(chunk, pos)?;
+                quantized = true;
+            }
+            METADATA_CHUNK => {
+                let (metadata, pos) = read_metadata(body(chunk)?, 0)?;
+                check_end(chunk, pos)?;
+                header.metadata = metadata;
+            }
+            PADDING_CHUNK => {}
+            THUMBNAIL_CHUNK => {
+                let (thumbnail, pos) = read_thumbnail(body(chunk)?, 0)?;
+                check_end(chunk, pos)?;
+                header.thumbnail = Some(thumbnail);
+            }
+            REFINEMENT_CHUNK => {
+                let (refinement, pos) = read_refinement(body(chunk)?, 0)?;
+                check_end(chunk, pos)?;
+                header.refinement = Some(refinement);
+            }
+            PAYLOAD_CHUNK => {
+                let quantization_flags = FLAG_LUMA_RANGE | FLAG_LAYOUT | FLAG_REGIONS;
+                let extended_quantization_flags =
+                    EXTENDED_FLAG_TILE_MODES | EXTENDED_FLAG_CHROMA_WEIGHT;
+                let needs_quantization = flags & quantization_flags != 0
+                    || extended_flags & extended_quantization_flags != 0;
+                if needs_quantization && !quantized {
+                    return Err(format!(
+                        "The header flags need a {} chunk in front of the payload",
+                        String::from_utf8_lossy(&QUANT_CHUNK)
+                    ));
+                }
+                return Ok((header, chunk.offset));
+            }
+            _ if chunk.is_ancillary() => {}
+            _ => {
+                return Err(format!(
+                    "Unknown chunk {} needed to decode the image",
+                    chunk.name()
+                ))
+            }
+        }
+    }
+    Err("Ran out of bytes while reading the header".to_string())
+}
+
+/// Parses the flags and the dimensions at `pos`, followed by the extended flags when
+/// `extended` is set, returning a header holding them with the other fields at their defaults,
+/// the flags, the extended flags, and the position right after them.
+///
+/// # Arguments
+/// * `bytes`: Raw bytes of the header
+/// * `pos`: Position of the flags
+/// * `extended`: Whether the extended flags follow the dimensions
+/// * `known`: Header flags the container may set
+/// * `known_extended`: Extended header flags the container may set
+fn read_fixed(
+    bytes: &[u8],
+    pos: usize,
+    extended: bool,
+    known: u8,
+    known_extended: u8,
+) -> Result<(Header, u8, u8, usize), String> {
+    let [flags] = read_bytes(bytes, pos)?;
+    if flags & !known != 0 {
+        return Err(format!("Unknown header flags 0x{flags:02X}"));
+    }
+    let order = if flags & FLAG_PROGRESSIVE != 0 {
+        WordOrder::Progressive
+    } else {
+        WordOrder::Sequential
+    };
+    let width = u32::from_be_bytes(read_bytes(bytes, pos + 1)?);
+    let height = u32::from_be_bytes(read_bytes(bytes, pos + 5)?);
+    check_dimensions(width, height)?;
+    let mut pos = pos + 9;
+    let mut extended_flags = 0;
+    if extended {
+        [extended_flags] = read_bytes(bytes, pos)?;
+        if extended_flags & !known_extended != 0 {
+            return Err(format!(
+                "Unknown extended header flags 0x{extended_flags:02X}"
+            ));
+        }
+        pos += 1;
+    }
+    let header = Header {
+        width,
+        height,
+        order,
+        region_qualities: Vec::new(),
+        tile_size: 0,
+        luma_range: DEFAULT_LUMA_RANGE_MILLIS,
+        layout: NARROW_LAYOUT,
+        metadata: Vec::new(),
+        thumbnail: None,
+        perceptual: flags & FLAG_PERCEPTUAL != 0,
+        palette: extended_flags & EXTENDED_FLAG_PALETTE != 0,
+        tile_modes: Vec::new(),
+        srgb: extended_flags & EXTENDED_FLAG_SRGB != 0,
+        hdr_exponent: None,
+        signed: extended_flags & EXTENDED_FLAG_SIGNED != 0,
+        chunked: false,
+        refinement: None,
+    };
+    Ok((header, flags, extended_flags, pos))
+}
+
+/// Parses the HDR exponent and the tile size the flags announce at `pos` into `header`,
+/// returning the position right after them.
+fn read_tiling(
+    header: &mut Header,
+    flags: u8,
+    extended_flags: u8,
+    bytes: &[u8],
+    mut pos: usize,
+) -> Result<usize, String> {
+    if extended_flags & EXTENDED_FLAG_HDR != 0 {
+        let [exponent] = read_bytes(bytes, pos)?;
+        header.hdr_exponent = Some(exponent as i8);
+        pos += 1;
+    }
+    if flags & FLAG_TILED != 0 {
+        let tile_size = u32::from_be_bytes(read_bytes(bytes, pos)?);
+        if tile_size == 0 || !tile_size.is_multiple_of(2) {
+            return Err(format!("Invalid tile size {tile_size}"));
+        }
+        header.tile_size = tile_size;
+        pos += 4;
+    }
+    Ok(pos)
+}
+
+/// Parses the luma range, the layout id, the chroma weight, the region qualities, and the tile
+/// modes the flags announce at `pos` into `header`, whose dimensions and tile size are already read, returning
+/// the position right after them.
+fn read_quantization(
+    header: &mut Header,
+    flags: u8,
+    extended_flags: u8,
+    bytes: &[u8],
+    mut pos: usize,
+) -> Result<usize, String> {
+    if flags & FLAG_LUMA_RANGE != 0 {
+        let luma_range = u16::from_be_bytes(read_bytes(bytes, pos)?);
+        if luma_range == 0 || luma_range > 500 {
+            return Err(format!("Invalid luma range {luma_range}"));
+        }
+        header.luma_range = luma_range;
+        pos += 2;
+    }
+    if flags & FLAG_LAYOUT != 0 {
+        let [id] = read_bytes(bytes, pos)?;
+        header.layout =
+            WordLayout::from_id(id).ok_or_else(|| format!("Unknown code word layout {id}"))?;
+        pos += 1;
+    }
+    if extended_flags & EXTENDED_FLAG_CHROMA_WEIGHT != 0 {
+        let [weight] = read_bytes(bytes, pos)?;
+        if weight == 0 || weight > MAX_CHROMA_WEIGHT {
+            return Err(format!("Invalid chroma weight {weight}"));
+        }
+        header.layout.chroma_weight = weight;
+        pos += 1;
+    }
+    if flags & FLAG_REGIONS != 0 {
+        let [count] = read_bytes(bytes, pos)?;
+        header.region_qualities = bytes
+            .get(pos + 1..pos + 1 + count as usize)
+            .ok_or("Ran out of bytes while reading the header")?
+            .to_vec();
+        pos += 1 + count as usize;
+    }
+    if extended_flags & EXTENDED_FLAG_TILE_MODES != 0 {
+        let count = header.tile_count();
+        let ids = bytes
+            .get(pos..pos + count)
+            .ok_or("Ran out of bytes while reading the header")?;
+        for id in ids {
+            header
+                .tile_modes
+                .push(TileMode::from_id(*id).ok_or(format!("Unknown tile coding mode {id}"))?);
+        }
+        pos += count;
+    }
+    Ok(pos)
+}
+
+/// Parses the metadata at `pos`, returning it with the position right after it.
+fn read_metadata(bytes: &[u8], pos: usize) -> Result<(Vec<(String, String)>, usize), String> {
+    let (count, mut pos) = read_length(bytes, pos)?;
+    let mut metadata = Vec::new();
+    for _ in 0..count {
+        let (key, next) = read_text(bytes, pos)?;
+        let (value, next) = read_text(bytes, next)?;
+        metadata.push((key, value));
+        pos = next;
+    }
+    Ok((metadata, pos))
+}
+
+/// Parses the thumbnail at `pos`, returning it with the position right after it.
+fn read_thumbnail(bytes: &[u8], pos: usize) -> Result<(RgbImage, usize), String> {
+    let (width, next) = read_length(bytes, pos)?;
+    let (height, next) = read_length(bytes, next)?;
+    let channels = bytes
+        .get(next..next + width * height * 3)
+        .ok_or("Ran out of bytes while reading the header")?;
+    let thumbnail = RgbImage {
+        pixels: channels
+            .chunks_exact(3)
+            .map(|rgb| Rgb {
+                red: rgb[0] as u16,
+                green: rgb[1] as u16,
+                blue: rgb[2] as u16,
+            })
+            .collect(),
+        width: width as u32,
+        height: height as u32,
+        denominator: 255,
+    };
+    Ok((thumbnail, next + channels.len()))
+}
+
+/// Parses the refinement at `pos`, returning it with the position right after it.
+fn read_refinement(bytes: &[u8], pos: usize) -> Result<(Refinement, usize), String> {
+    let [max_error] = read_bytes(bytes, pos)?;
+    if max_error > MAX_ERROR {
+        return Err(format!("Invalid max error {max_error}"));
+    }
+    let len = u32::from_be_bytes(read_bytes(bytes, pos + 1)?) as usize;
+    let bits = bytes
+        .get(pos + 5..(pos + 5).saturating_add(len))
+        .ok_or("Ran out of bytes while reading the header")?
+        .to_vec();
+    Ok((Refinement { max_error, bits }, pos + 5 + len))
+}
+
+/// Returns the `N` bytes at `pos`, or an error when the header ends before them.
+fn read_bytes<const N: usize>(bytes: &[u8], pos: usize) -> Result<[u8; N], String> {
+    bytes
+        .get(pos..pos.saturating_add(N))
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| "Ran out of bytes while reading the header".to_string())
+}
+
+/// Returns an error for dimensions whose blocks cannot be counted: rounding a width or height
+/// of `u32::MAX` up to even overflows.
+fn check_dimensions(width: u32, height: u32) -> Result<(), String> {
+    if width == u32::MAX || height == u32::MAX {
+        return Err(format!("Invalid image dimensions {width}x{height}"));
+    }
+    Ok(())
+}
+
+/// Reads the 16-bit Bigendian length at `pos`, returning it with the position right after it.
+fn read_length(bytes: &[u8], pos: usize) -> Result<(usize, usize), String> {
+    Ok((
+        u16::from_be_bytes(read_bytes(bytes, pos)?) as usize,
+        pos + 2,
+    ))
+}
+
+/// Reads a metadata key or value, stored as its length followed by its UTF-8 bytes, at `pos`,
+/// returning it with the position right after it.
+fn read_text(bytes: &[u8], pos: usize) -> Result<(String, usize), String> {
+    let (length, start) = read_length(bytes, pos)?;
+    let text = bytes
+        .get(start..start + length)
+        .ok_or("Ran out of bytes while reading the header")?;
+    let text = String::from_utf8(text.to_vec())
+        .map_err(|_| "The metadata of the header is not valid UTF-8".to_string())?;
+    Ok((text, start + length))
+}
+
+/// Parses a `key=value` metadata entry, as given to `--meta`.
+///
+/// # Arguments
+/// * `text`: Key and value separated by the first `=`
+pub fn parse_metadata(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((key, value)) if !key.is_empty() => {
+            if key.len() > MAX_METADATA_LEN || value.len() > MAX_METADATA_LEN {
+                return Err(format!(
+                    "Metadata keys and values are limited to {MAX_METADATA_LEN} bytes"
+                ));
+            }
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(format!("Expected metadata as key=value, found {text}")),
+    }
+}
+
+/// Parses the `Compressed image format 2\n{width} {height}\n` header of the course format.
+///
+/// # Arguments
+/// * `bytes`: Raw bytes starting with the legacy magic line
+fn read_legacy(bytes: &[u8]) -> Result<(Header, usize), String> {
+    let mut pos = LEGACY_MAGIC.len();
+    pos = skip_newline(bytes, pos)?;
+    let (width, next) = read_number(bytes, pos)?;
+    if bytes.get(next) != Some(&b' ') {
+        return Err("Expected a space between the width and height".to_string());
+    }
+    let (height, next) = read_number(bytes, next + 1)?;
+    check_dimensions(width, height)?;
+    pos = skip_newline(bytes, next)?;
+
+    Ok((
+        Header {
+            width,
+            height,
+            order: WordOrder::Sequential,
+            region_qualities: Vec::new(),
+            tile_size: 0,
+            luma_range: DEFAULT_LUMA_RANGE_MILLIS,
+            layout: NARROW_LAYOUT,
+            metadata: Vec::new(),
+            thumbnail: None,
+            perceptual: false,
+            palette: false,
+            tile_modes: Vec::new(),
+            srgb: false,
+            hdr_exponent: None,
+            signed: false,
+            chunked: false,
+            refinement: None,
+        },
+        pos,
+    ))
+}
+
+/// Consumes a `\n` or `\r\n` at `pos`, returning the position right after it.
+fn skip_newline(bytes: &[u8], pos: usize) -> Result<usize, String> {
+    match bytes.get(pos) {
+        Some(b'\n') => Ok(pos + 1),
+        Some(b'\r') if bytes.get(pos + 1) == Some(&b'\n') => Ok(pos + 2),
+        Some(b'\r') => Ok(pos + 1),
+        _ => Err("Expected a newline in the header".to_string()),
+    }
+}
+
+/// Reads an ascii decimal number at `pos`, returning it with the position right after it.
+fn read_number(bytes: &[u8], pos: usize) -> Result<(u32, usize), String> {
+    let digits: Vec<u32> = bytes
+        .get(pos..)
+        .unwrap_or_default()
+        .iter()
+        .take_while(|byte| byte.is_ascii_digit())
+        .map(|byte| (byte - b'0') as u32)
+        .collect();
+    if digits.is_empty() {
+        return Err("Expected a number in the header".to_string());
+    }
+    let number = digits
+        .iter()
+        .try_fold(0_u32, |number, digit| {
+            number.checked_mul(10)?.checked_add(*digit)
+        })
+        .ok_or("Integer overflow while parsing the header")?;
+
+    Ok((number, pos + digits.len()))
+}
@@ -0,0 +1,241 @@
+use crate::layout::{ChromaCoding, WordLayout};
+use crate::ppm::Rgb;
+use array2::array2::Array2;
+use std::sync::{mpsc, OnceLock};
+use wgpu::util::DeviceExt;
+
+/// Compute shader converting, transforming, quantizing, and packing one block per invocation.
+const SHADER: &str = include_str!("encode.wgsl");
+
+/// Number of invocations of a workgroup, as declared by the shader.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Largest number of workgroups a dispatch can have along one dimension.
+const MAX_WORKGROUPS: u32 = 65535;
+
+/// Device, queue, and compiled pipeline, created once and reused by every image.
+struct GpuContext {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+static CONTEXT: OnceLock<Result<GpuContext, String>> = OnceLock::new();
+
+/// Returns the GPU context, creating it on first use. Returns an error if no adapter is
+/// available or the device cannot be created.
+fn context() -> Result<&'static GpuContext, String> {
+    CONTEXT
+        .get_or_init(|| pollster::block_on(create_context()))
+        .as_ref()
+        .map_err(Clone::clone)
+}
+
+/// Requests an adapter and a device, and compiles the encoding pipeline.
+async fn create_context() -> Result<GpuContext, String> {
+    let instance =
+        wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle_from_env());
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        })
+        .await
+        .map_err(|error| format!("No GPU adapter is available: {error}"))?;
+    let (device, queue) = adapter
+        .request_device(&wgpu::DeviceDescriptor {
+            label: Some("rpeg"),
+            required_limits: adapter.limits(),
+            ..Default::default()
+        })
+        .await
+        .map_err(|error| format!("Failed to open the GPU: {error}"))?;
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("rpeg encoder"),
+        source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+    });
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("rpeg encoder"),
+        layout: None,
+        module: &module,
+        entry_point: Some("encode"),
+        compilation_options: Default::default(),
+        cache: None,
+    });
+    Ok(GpuContext {
+        device,
+        queue,
+        pipeline,
+    })
+}
+
+/// Returns an error if no GPU can be used to compress images.
+pub(crate) fn check_available() -> Result<(), String> {
+    context().map(|_| ())
+}
+
+/// Returns the uniform parameters of the shader for an image and a layout.
+fn params(
+    width: usize,
+    block_count: usize,
+    row_stride: u32,
+    image_denominator: u16,
+    layout: &WordLayout,
+) -> Vec<u8> {
+    let words: [u32; 12] = [
+        width as u32,
+        (width / 2) as u32,
+        block_count as u32,
+        row_stride,
+        (image_denominator as f32).to_bits(),
+        (layout.a_scale() as f32).to_bits(),
+        (layout.bcd_levels() as f32).to_bits(),
+        (layout.chroma_levels() as f32).to_bits(),
+        (layout.chroma == ChromaCoding::Direct) as u32,
+        0,
+        0,
+        0,
+    ];
+    let fields = [layout.a, layout.b, layout.c, layout.d, layout.pb, layout.pr]
+        .into_iter()
+        .flat_map(|field| [field.width as u32, field.lsb as u32]);
+    words
+        .into_iter()
+        .chain(fields)
+        .flat_map(u32::to_le_bytes)
+        .collect()
+}
+
+/// GPU counterpart of `encode_words`: uploads the pixels of an image, runs the color
+/// conversion, the 2x2 transform, and the quantization in a compute shader, and reads back
+/// one code word per 2x2 block, in row-major block order, along with the number of b, c, and
+/// d coefficients clipped. The shader computes in `f32`, so a few words may differ from the
+/// CPU encoder by one quantization step.
+///
+/// # Arguments
+/// * `image`: Image, or tile of an image, with even dimensions
+/// * `image_denominator`: Denominator of the Rgb values of the image
+/// * `luma_ranges`: Range b, c, and d of every block are clamped to
+/// * `layout`: Layout of the code words
+pub(crate) fn encode_words_gpu(
+    image: &Array2<Rgb>,
+    image_denominator: u16,
+    luma_ranges: &[f64],
+    layout: &WordLayout,
+) -> Result<(Vec<u64>, usize), String> {
+    let block_count = luma_ranges.len();
+    if block_count == 0 {
+        return Ok((Vec::new(), 0));
+    }
+    let context = context()?;
+    let device = &context.device;
+    let mut samples: Vec<u8> = image
+        .data
+        .iter()
+        .flat_map(|pixel| [pixel.red, pixel.green, pixel.blue])
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    samples.resize(samples.len().next_multiple_of(4), 0);
+    let limit = device.limits().max_storage_buffer_binding_size as usize;
+    if samples.len() > limit {
+        return Err(format!(
+            "The image takes {} bytes on the GPU, which holds at most {limit}; use tiles",
+            samples.len()
+        ));
+    }
+    let ranges: Vec<u8> = luma_ranges
+        .iter()
+        .flat_map(|range| (*range as f32).to_le_bytes())
+        .collect();
+
+    let workgroups = (block_count as u32).div_ceil(WORKGROUP_SIZE);
+    let columns = workgroups.min(MAX_WORKGROUPS);
+    let rows = workgroups.div_ceil(columns);
+    let params = params(
+        image.get_width(),
+        block_count,
+        columns * WORKGROUP_SIZE,
+        image_denominator,
+        layout,
+    );
+
+    let init = |label, contents: &[u8], usage| {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents,
+            usage,
+        })
+    };
+    let params_buffer = init("params", &params, wgpu::BufferUsages::UNIFORM);
+    let samples_buffer = init("samples", &samples, wgpu::BufferUsages::STORAGE);
+    let ranges_buffer = init("ranges", &ranges, wgpu::BufferUsages::STORAGE);
+    let output_size = (block_count * 3 * 4) as u64;
+    let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("words"),
+        size: output_size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback"),
+        size: output_size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("rpeg encoder"),
+        layout: &context.pipeline.get_bind_group_layout(0),
+        entries: &[
+            params_buffer.as_entire_binding(),
+            samples_buffer.as_entire_binding(),
+            ranges_buffer.as_entire_binding(),
+            output_buffer.as_entire_binding(),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(binding, resource)| wgpu::BindGroupEntry {
+            binding: binding as u32,
+            resource,
+        })
+        .collect::<Vec<_>>(),
+    });
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&context.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(columns, rows, 1);
+    }
+    encoder.copy_buffer_to_buffer(&output_buffer, 0, &readback_buffer, 0, output_size);
+    context.queue.submit([encoder.finish()]);
+
+    let (sender, receiver) = mpsc::channel();
+    readback_buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+    device
+        .poll(wgpu::PollType::wait_indefinitely())
+        .map_err(|error| format!("Failed to wait for the GPU: {error}"))?;
+    receiver
+        .recv()
+        .map_err(|error| error.to_string())?
+        .map_err(|error| format!("Failed to read the code words back: {error}"))?;
+    let output: Vec<u32> = readback_buffer
+        .slice(..)
+        .get_mapped_range()
+        .map_err(|error| format!("Failed to read the code words back: {error}"))?
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+    readback_buffer.unmap();
+
+    let words = output
+        .chunks_exact(3)
+        .map(|block| ((block[0] as u64) << 32) | block[1] as u64)
+        .collect();
+    let clipped = output.chunks_exact(3).map(|block| block[2] as usize).sum();
+    Ok((words, clipped))
+}
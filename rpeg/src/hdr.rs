@@ -0,0 +1,579 @@
+use crate::codec::stats::Timings;
+use crate::codec::{
+    compress_image_with_timings, decompress_with_options, DecodeOptions, EncodeReport,
+    EncoderOptions,
+};
+#[cfg(feature = "jpeg")]
+use crate::color_tag::ColorTag;
+use crate::conversions::linear_to_srgb;
+use crate::fixed::Arithmetic;
+use crate::format::Header;
+#[cfg(feature = "jpeg")]
+use crate::orientation::Orientation;
+use crate::ppm::{Rgb, RgbImage};
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// Number of stops, factors of two in brightness, the log curve of HDR images spans below the
+/// peak of the image. Densities more than this many stops below the peak are coded as black.
+pub const HDR_STOPS: f64 = 16.0;
+
+#[derive(Clone, Debug, PartialEq)]
+/// ## Represent a high dynamic range image
+///
+/// `pixels` holds the linear red, green, and blue light of every pixel in row-major order, as
+/// read from a PFM file. The values are not limited to 0..1: a light of 4 is four times as
+/// bright as the white of a standard image.
+///
+/// An HDR image is compressed by coding every channel on a log curve relative to a power of two
+/// at or above the brightest channel of the image, as a 16-bit image that goes through the
+/// usual pipeline, and storing that power of two as the `hdr_exponent` of the header. The size
+/// of a step of the curve is then a fraction of a stop, so dark and bright pixels keep the same
+/// relative precision and nothing brighter than 1 is clipped.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::hdr::HdrImage;
+///
+/// let image = HdrImage {
+///     pixels: vec![[0.25, 1.0, 6.0]; 2 * 2],
+///     width: 2,
+///     height: 2,
+/// };
+/// assert_eq!(image.exponent(), 3);
+/// let mut pfm = Vec::new();
+/// image.write_to(&mut pfm).unwrap();
+/// assert!(pfm.starts_with(b"PF\n2 2\n-1.0\n"));
+/// assert_eq!(HdrImage::from_reader(&pfm[..]).unwrap(), image);
+/// ```
+pub struct HdrImage {
+    pub pixels: Vec<[f32; 3]>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl HdrImage {
+    /// Reads a PFM image from `filename`, or from standard in.
+    ///
+    /// # Arguments
+    /// * `filename`: Location of the PFM within your disk, or None to read from standard in
+    pub fn read(filename: Option<&str>) -> Result<HdrImage, String> {
+        match filename {
+            Some(filename) => {
+                let file = std::fs::File::open(filename)
+                    .map_err(|error| format!("Failed to open {filename}: {error}"))?;
+                HdrImage::from_reader(BufReader::new(file))
+            }
+            None => HdrImage::from_reader(std::io::stdin().lock()),
+        }
+    }
+
+    /// Reads a color (PF) or grayscale (Pf) PFM image from `reader`. The sign of the scale in
+    /// the header gives the byte order of the samples, negative for Littleendian, and the rows
+    /// are stored from the bottom of the image to the top.
+    ///
+    /// # Arguments
+    /// * `reader`: Source of the PFM image
+    pub fn from_reader<R: BufRead>(mut reader: R) -> Result<HdrImage, String> {
+        let mut magic = [0_u8; 2];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| "The input is not a PFM image (PF or Pf)".to_string())?;
+        let channels = match &magic {
+            b"PF" => 3,
+            b"Pf" => 1,
+            _ => return Err("The input is not a PFM image (PF or Pf)".to_string()),
+        };
+        let width: u32 = parse_token(&mut reader, "width")?;
+        let height: u32 = parse_token(&mut reader, "height")?;
+        let scale: f32 = parse_token(&mut reader, "scale")?;
+        if width == 0 || height == 0 {
+            return Err(format!("The image is {width}x{height} and has no pixels"));
+        }
+        if scale == 0.0 || !scale.is_finite() {
+            return Err(format!("The scale {scale} of the image is not a number"));
+        }
+        let little_endian = scale < 0.0;
+        let samples = width as usize * channels;
+        let mut row = vec![0_u8; samples * 4];
+        let mut rows = vec![Vec::new(); height as usize];
+        for r in 0..height {
+            reader
+                .read_exact(&mut row)
+                .map_err(|error| format!("Failed to read row {r} of {height}: {error}"))?;
+            rows[(height - 1 - r) as usize] = row
+                .chunks_exact(4 * channels)
+                .map(|pixel| {
+                    let mut values = pixel.chunks_exact(4).map(|bytes| {
+                        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+                        if little_endian {
+                            f32::from_le_bytes(bytes)
+                        } else {
+                            f32::from_be_bytes(bytes)
+                        }
+                    });
+                    match channels {
+                        1 => [values.next().unwrap_or(0.0); 3],
+                        _ => [(); 3].map(|_| values.next().unwrap_or(0.0)),
+                    }
+                })
+                .collect();
+        }
+        Ok(HdrImage {
+            pixels: rows.concat(),
+            width,
+            height,
+        })
+    }
+
+    /// Writes the image as a Littleendian color PFM to `writer`.
+    ///
+    /// # Arguments
+    /// * `writer`: Destination of the PFM image
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), String> {
+        let expected = self.width as usize * self.height as usize;
+        if self.pixels.len() != expected {
+            return Err(format!(
+                "The image has {} pixels instead of {expected}",
+                self.pixels.len()
+            ));
+        }
+        let write_error = |error: std::io::Error| format!("Failed to write the image: {error}");
+        write!(writer, "PF\n{} {}\n-1.0\n", self.width, self.height).map_err(write_error)?;
+        for row in self.pixels.chunks(self.width.max(1) as usize).rev() {
+            let bytes: Vec<u8> = row
+                .iter()
+                .flatten()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+            writer.write_all(&bytes).map_err(write_error)?;
+        }
+        writer.flush().map_err(write_error)
+    }
+
+    /// Returns the smallest power of two, as its exponent, that is at least as bright as every
+    /// channel of the image. An image without any light has an exponent of 0.
+    pub fn exponent(&self) -> i8 {
+        let peak = self
+            .pixels
+            .iter()
+            .flatten()
+            .filter(|value| value.is_finite())
+            .fold(0.0_f32, |peak, value| peak.max(*value));
+        if peak <= 0.0 {
+            0
+        } else {
+            (peak as f64)
+                .log2()
+                .ceil()
+                .clamp(i8::MIN as f64, i8::MAX as f64) as i8
+        }
+    }
+
+    /// Returns the image coded on the log curve below 2^`exponent`, as a 16-bit image.
+    /// Negative channels are coded as black and channels above 2^`exponent` as white.
+    ///
+    /// # Arguments
+    /// * `exponent`: Exponent of the brightest light that can be coded
+    pub fn to_coded(&self, exponent: i8) -> RgbImage {
+        let peak = (exponent as f64).exp2();
+        let top = HDR_STOPS.exp2() - 1.0;
+        let code = |value: f32| {
+            let light = (value as f64 / peak).clamp(0.0, 1.0);
+            let light = if light.is_nan() { 0.0 } else { light };
+            ((1.0 + light * top).log2() / HDR_STOPS * u16::MAX as f64).round() as u16
+        };
+        RgbImage {
+            pixels: self
+                .pixels
+                .iter()
+                .map(|&[red, green, blue]| Rgb {
+                    red: code(red),
+                    green: code(green),
+                    blue: code(blue),
+                })
+                .collect(),
+            width: self.width,
+            height: self.height,
+            denominator: u16::MAX,
+        }
+    }
+
+    /// Returns the light of an image coded by `to_coded`.
+    ///
+    /// # Arguments
+    /// * `coded`: Densities on the log curve
+    /// * `exponent`: Exponent the image was coded with
+    pub fn from_coded(coded: &RgbImage, exponent: i8) -> HdrImage {
+        let peak = (exponent as f64).exp2();
+        let top = HDR_STOPS.exp2() - 1.0;
+        let denominator = coded.denominator.max(1) as f64;
+        let light = |value: u16| {
+            let curve = value as f64 / denominator;
+            (((curve * HDR_STOPS).exp2() - 1.0) / top * peak) as f32
+        };
+        HdrImage {
+            pixels: coded
+                .pixels
+                .iter()
+                .map(|pixel| [light(pixel.red), light(pixel.green), light(pixel.blue)])
+                .collect(),
+            width: coded.width,
+            height: coded.height,
+        }
+    }
+
+    /// Returns the light of a decoded image: the inverse of the log curve when the image was
+    /// coded with `exponent`, or the densities divided by the denominator otherwise.
+    ///
+    /// # Arguments
+    /// * `decoded`: Decoded image
+    /// * `exponent`: HDR exponent of the header of the image
+    pub fn from_decoded(decoded: &RgbImage, exponent: Option<i8>) -> HdrImage {
+        match exponent {
+            Some(exponent) => HdrImage::from_coded(decoded, exponent),
+            None => {
+                let denominator = decoded.denominator.max(1) as f32;
+                HdrImage {
+                    pixels: decoded
+                        .pixels
+                        .iter()
+                        .map(|pixel| {
+                            [pixel.red, pixel.green, pixel.blue].map(|v| v as f32 / denominator)
+                        })
+                        .collect(),
+                    width: decoded.width,
+                    height: decoded.height,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// ## How the light of an HDR image is fitted to a standard 8-bit image when it is decoded
+///
+/// `None` keeps the image HDR: the decoder returns the 16-bit log curve, which `-d` writes as a
+/// PFM. `Clamp` cuts every channel at a light of 1, and `Reinhard` divides the channels of every
+/// pixel by 1 plus its luminance, which compresses the highlights smoothly and keeps their hue.
+/// Both then encode the light with the sRGB transfer curve, with a denominator of 255. Images
+/// that are not HDR are decoded as usual by every tone mapper.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::hdr::ToneMap;
+///
+/// assert_eq!(ToneMap::default(), ToneMap::None);
+/// assert_eq!(ToneMap::from_name("reinhard"), Some(ToneMap::Reinhard));
+/// assert_eq!(ToneMap::Clamp.map([4.0, 0.5, 0.0]), [1.0, 0.5, 0.0]);
+/// assert_eq!(ToneMap::Reinhard.map([1.0, 1.0, 1.0]), [0.5, 0.5, 0.5]);
+/// ```
+pub enum ToneMap {
+    #[default]
+    None,
+    Clamp,
+    Reinhard,
+}
+
+impl ToneMap {
+    /// Returns the tone mapper called `name`: none, clamp, or reinhard.
+    ///
+    /// # Arguments
+    /// * `name`: Name of the tone mapper, as given to `--tone-map`
+    pub fn from_name(name: &str) -> Option<ToneMap> {
+        match name {
+            "none" => Some(ToneMap::None),
+            "clamp" => Some(ToneMap::Clamp),
+            "reinhard" => Some(ToneMap::Reinhard),
+            _ => None,
+        }
+    }
+
+    /// Returns the linear light of a pixel fitted between 0 and 1, or left as it is by `None`.
+    ///
+    /// # Arguments
+    /// * `light`: Red, green, and blue light of the pixel
+    pub fn map(self, light: [f64; 3]) -> [f64; 3] {
+        match self {
+            ToneMap::None => light,
+            ToneMap::Clamp => light.map(|value| value.clamp(0.0, 1.0)),
+            ToneMap::Reinhard => {
+                let luminance = 0.2126 * light[0] + 0.7152 * light[1] + 0.0722 * light[2];
+                let scale = 1.0 / (1.0 + luminance.max(0.0));
+                light.map(|value| (value * scale).clamp(0.0, 1.0))
+            }
+        }
+    }
+
+    /// Returns an image decoded by `from_coded` with its light tone mapped and sRGB encoded,
+    /// with a denominator of 255, or the image itself for `None`.
+    ///
+    /// # Arguments
+    /// * `coded`: Decoded densities on the log curve
+    /// * `exponent`: Exponent the image was coded with
+    pub fn apply(self, coded: RgbImage, exponent: i8) -> RgbImage {
+        if self == ToneMap::None {
+            return coded;
+        }
+        self.to_standard(&HdrImage::from_coded(&coded, exponent))
+    }
+
+    /// Returns the light of `image` tone mapped and sRGB encoded, with a denominator of 255.
+    /// `None` only clamps the light to 0..1.
+    ///
+    /// # Arguments
+    /// * `image`: Light of an HDR image
+    pub fn to_standard(self, image: &HdrImage) -> RgbImage {
+        let encode = |value: f64| (linear_to_srgb(value.clamp(0.0, 1.0)) * 255.0).round() as u16;
+        RgbImage {
+            pixels: image
+                .pixels
+                .iter()
+                .map(|pixel| {
+                    let [red, green, blue] = self.map(pixel.map(f64::from)).map(encode);
+                    Rgb { red, green, blue }
+                })
+                .collect(),
+            width: image.width,
+            height: image.height,
+            denominator: 255,
+        }
+    }
+}
+
+/// ## Image read by the `compress` command
+///
+/// A PPM image, a PFM image with a high dynamic range, or, with the `jpeg` feature, a JPEG
+/// image with its EXIF orientation and ICC profile.
+pub enum InputImage {
+    Standard(RgbImage),
+    Hdr(HdrImage),
+    #[cfg(feature = "jpeg")]
+    Jpeg {
+        image: RgbImage,
+        orientation: Orientation,
+        color_tag: Option<ColorTag>,
+    },
+}
+
+impl InputImage {
+    /// Reads a PPM, PFM, or JPEG image from `filename`, or from standard in, telling them apart
+    /// by their magic number. JPEG images are an error without the `jpeg` feature.
+    ///
+    /// # Arguments
+    /// * `filename`: Location of the image within your disk, or None to read from standard in
+    pub fn read(filename: Option<&str>) -> Result<InputImage, String> {
+        let reader: Box<dyn BufRead> = match filename {
+            Some(filename) => Box::new(BufReader::new(
+                std::fs::File::open(filename)
+                    .map_err(|error| format!("Failed to open {filename}: {error}"))?,
+            )),
+            None => Box::new(std::io::stdin().lock()),
+        };
+        let mut magic = Vec::with_capacity(2);
+        let mut reader = reader;
+        (&mut reader)
+            .take(2)
+            .read_to_end(&mut magic)
+            .map_err(|error| format!("Failed to read the image: {error}"))?;
+        let mut image = magic.as_slice().chain(reader);
+        if magic == b"PF" || magic == b"Pf" {
+            HdrImage::from_reader(image).map(InputImage::Hdr)
+        } else if magic == [0xFF, 0xD8] {
+            let mut bytes = Vec::new();
+            image
+                .read_to_end(&mut bytes)
+                .map_err(|error| format!("Failed to read the image: {error}"))?;
+            read_jpeg_input(&bytes)
+        } else {
+            RgbImage::from_reader(image).map(InputImage::Standard)
+        }
+    }
+}
+
+/// Decodes the JPEG image of `InputImage::read`.
+///
+/// # Arguments
+/// * `bytes`: Contents of the JPEG file
+#[cfg(feature = "jpeg")]
+fn read_jpeg_input(bytes: &[u8]) -> Result<InputImage, String> {
+    let (image, orientation, color_tag) = crate::jpeg::read_jpeg(bytes)?;
+    Ok(InputImage::Jpeg {
+        image,
+        orientation,
+        color_tag,
+    })
+}
+
+/// Rejects the JPEG image of `InputImage::read` when rpeg is built without the `jpeg`
+/// feature.
+///
+/// # Arguments
+/// * `bytes`: Contents of the JPEG file
+#[cfg(not(feature = "jpeg"))]
+fn read_jpeg_input(_bytes: &[u8]) -> Result<InputImage, String> {
+    Err("Reading JPEG images needs rpeg built with the jpeg feature".to_string())
+}
+
+/// Compresses an HDR image like `compress_image_with_timings` compresses the coded image
+/// returned by `HdrImage::to_coded`, with the exponent of the image in the header.
+///
+/// # Arguments
+/// * `image`: Image to compress
+/// * `options`: Settings used to compress the image, which cannot convert from sRGB, use a
+///   palette, or detect the content of the tiles
+/// * `timings`: Timings the stages are added to
+pub fn compress_hdr_image(
+    image: &HdrImage,
+    options: &EncoderOptions,
+    timings: &Timings,
+) -> Result<(Vec<u8>, EncodeReport), String> {
+    if options.srgb || options.palette || options.detect_content {
+        return Err(
+            "HDR images cannot be combined with sRGB conversion, palette coding, or content \
+             detection"
+                .to_string(),
+        );
+    }
+    if options.compat.is_some() {
+        return Err("HDR images need the rpeg container, which --compat replaces".to_string());
+    }
+    if options.stores_raw() {
+        return Err(
+            "HDR images cannot be stored raw; leave out --raw-below-psnr and --ratio-policy store"
+                .to_string(),
+        );
+    }
+    if options.max_error.is_some() || options.target.is_some() {
+        return Err(
+            "HDR images cannot be given a max error or a quality target, whose steps and metrics are those of 8-bit pixels"
+                .to_string(),
+        );
+    }
+    let exponent = image.exponent();
+    let coded = timings.time("log curve", || image.to_coded(exponent));
+    let (bytes, report) =
+        compress_image_with_timings(&coded, options, timings).map_err(|error| error.message)?;
+    let (mut header, payload) = Header::locate(&bytes)?;
+    header.hdr_exponent = Some(exponent);
+    let mut compressed = Vec::with_capacity(bytes.len() + 2);
+    header.write_image(&[&bytes[payload]], &mut compressed);
+    Ok((compressed, report))
+}
+
+/// Decompresses an image back into light, in double precision floating point whatever the
+/// default arithmetic, the only one HDR images decode with. Images that are not HDR are
+/// returned with their densities divided by the denominator, between 0 and 1.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+pub fn decompress_hdr_image(bytes: &[u8]) -> Result<HdrImage, String> {
+    let (header, _) = Header::read(bytes)?;
+    let options = DecodeOptions {
+        arithmetic: Arithmetic::Float,
+        ..Default::default()
+    };
+    let decoded = decompress_with_options(bytes, &options)?;
+    Ok(HdrImage::from_decoded(&decoded, header.hdr_exponent))
+}
+
+/// Reads the next whitespace separated field of a PFM header and parses it.
+///
+/// # Arguments
+/// * `reader`: Source positioned within the header
+/// * `name`: Name of the field, used in the error messages
+fn parse_token<R: BufRead, T: std::str::FromStr>(reader: &mut R, name: &str) -> Result<T, String> {
+    let mut token = Vec::new();
+    let mut byte = [0_u8; 1];
+    loop {
+        match reader.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) if byte[0].is_ascii_whitespace() && token.is_empty() => continue,
+            Ok(_) if byte[0].is_ascii_whitespace() => break,
+            Ok(_) if token.len() < 32 => token.push(byte[0]),
+            Ok(_) => return Err(format!("The {name} of the image is too long")),
+            Err(error) => return Err(format!("Failed to read the {name} of the image: {error}")),
+        }
+    }
+    if token.is_empty() {
+        return Err(format!("The image ends before its {name}"));
+    }
+    std::str::from_utf8(&token)
+        .ok()
+        .and_then(|text| text.parse().ok())
+        .ok_or_else(|| {
+            format!(
+                "Expected the {name} of the image, found {:?}",
+                String::from_utf8_lossy(&token)
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn light_above_one_round_trips_within_a_few_percent() {
+        let (width, height) = (32, 32);
+        let pixels: Vec<[f32; 3]> = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f32, (i / width) as f32);
+                let light = 0.05 * ((x + y) / 6.0).exp2();
+                [light, light * 0.8, light * 0.6]
+            })
+            .collect();
+        let image = HdrImage {
+            pixels,
+            width,
+            height,
+        };
+        assert!(image.pixels.iter().flatten().any(|value| *value > 10.0));
+        let options = EncoderOptions {
+            layout: crate::layout::WIDE_LAYOUT,
+            arithmetic: Arithmetic::Float,
+            ..Default::default()
+        };
+        let (bytes, _) = compress_hdr_image(&image, &options, &Timings::new()).unwrap();
+        assert_eq!(
+            Header::read(&bytes).unwrap().0.hdr_exponent,
+            Some(image.exponent())
+        );
+        let decoded = decompress_hdr_image(&bytes).unwrap();
+        let (mut worst, mut total) = (0.0_f32, 0.0_f32);
+        for (original, decoded) in image.pixels.iter().zip(&decoded.pixels) {
+            let error = (decoded[1] - original[1]).abs() / original[1];
+            worst = worst.max(error);
+            total += error;
+        }
+        let mean = total / image.pixels.len() as f32;
+        assert!(mean <= 0.01, "mean error {mean}");
+        assert!(worst <= 0.05, "worst error {worst}");
+        let brightest = decoded
+            .pixels
+            .iter()
+            .flatten()
+            .fold(0.0_f32, |a, b| a.max(*b));
+        assert!(brightest > 10.0);
+        let decode = |tone_map| {
+            let options = crate::codec::DecodeOptions {
+                tone_map,
+                arithmetic: Arithmetic::Float,
+                ..Default::default()
+            };
+            crate::codec::decompress_with_options(&bytes, &options).unwrap()
+        };
+        let (clamped, reinhard) = (decode(ToneMap::Clamp), decode(ToneMap::Reinhard));
+        assert_eq!((clamped.denominator, reinhard.denominator), (255, 255));
+        let last = image.pixels.len() - 1;
+        assert_eq!(clamped.pixels[last].green, 255);
+        assert!(reinhard.pixels[last].green < 255);
+        assert!(reinhard.pixels[last].green > reinhard.pixels[last / 2].green);
+        let standard = EncoderOptions {
+            srgb: true,
+            ..Default::default()
+        };
+        assert!(compress_hdr_image(&image, &standard, &Timings::new()).is_err());
+    }
+}
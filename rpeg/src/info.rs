@@ -0,0 +1,251 @@
+use crate::animation::{FrameIndex, ANIMATION_MAGIC};
+use crate::archive::{list_entries, read_archive, ARCHIVE_MAGIC, ARCHIVE_VERSION};
+use crate::color_tag::ColorTag;
+use crate::content::TileMode;
+use crate::error::{CliError, ErrorKind};
+use crate::format::{read_chunks, read_version, Header, WordOrder, LEGACY_VERSION};
+use crate::io::read_input;
+use crate::layout::NEUTRAL_CHROMA_WEIGHT;
+use crate::signature::SIGNATURE_LEN;
+
+/// Returns a description of the header of a compressed image, one field per line, starting with
+/// its format version, followed by its metadata. Archives and multi-frame streams are summed up
+/// instead.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included, archive, or multi-frame stream
+pub fn describe(bytes: &[u8]) -> Result<String, String> {
+    if bytes.starts_with(ARCHIVE_MAGIC) {
+        let entries = read_archive(bytes)?;
+        return Ok(format!(
+            "archive: version {ARCHIVE_VERSION}\nentries: {}\n{}",
+            entries.len(),
+            list_entries(&entries)
+        ));
+    }
+    if bytes.starts_with(ANIMATION_MAGIC) {
+        let index = FrameIndex::read(bytes)?;
+        return Ok(format!(
+            "multi-frame stream: version {}\n\
+             dimensions: {}x{}\n\
+             frames: {}\n\
+             key frames: {}\n\
+             size: {} bytes\n",
+            bytes[4],
+            index.width,
+            index.height,
+            index.offsets.len(),
+            index.key_frames.iter().filter(|key| **key).count(),
+            bytes.len()
+        ));
+    }
+    let (header, payload) = Header::locate(bytes)?;
+    let version = match read_version(bytes)? {
+        LEGACY_VERSION => format!("{LEGACY_VERSION} (Compressed image format 2)"),
+        version => version.to_string(),
+    };
+    let order = match header.order {
+        WordOrder::Sequential => "sequential",
+        WordOrder::Progressive => "progressive",
+    };
+    let coding = if !header.tile_modes.is_empty() {
+        let count = |mode| {
+            header
+                .tile_modes
+                .iter()
+                .filter(|tile| **tile == mode)
+                .count()
+        };
+        [
+            TileMode::Photo,
+            TileMode::Graphic,
+            TileMode::Text,
+            TileMode::Raw,
+        ]
+        .map(|mode| format!("{} {}", count(mode), mode.name()))
+        .join(", ")
+            + " tiles"
+    } else if header.palette {
+        "palette".to_string()
+    } else {
+        "code words".to_string()
+    } + if header.srgb { " in linear light" } else { "" }
+        + &header
+            .hdr_exponent
+            .map(|exponent| format!(" on an HDR log curve up to 2^{exponent}"))
+            .unwrap_or_default();
+    let mut text = format!(
+        "version: {version}\n\
+         dimensions: {}x{}\n\
+         blocks: {}\n\
+         coding: {}\n\
+         layout: {} ({}-bit code words{})\n\
+         order: {order}\n\
+         tile size: {}\n\
+         luma range: {:.3}{}\n\
+         regions of interest: {}\n\
+         thumbnail: {}\n\
+         header: {} bytes\n\
+         payload: {} bytes\n",
+        header.width,
+        header.height,
+        header.block_count(),
+        coding,
+        header.layout.id,
+        header.layout.word_bits,
+        if header.layout.chroma_weight == NEUTRAL_CHROMA_WEIGHT {
+            String::new()
+        } else {
+            format!(", chroma weight {}", header.layout.chroma_scale())
+        },
+        if header.tile_size == 0 {
+            "untiled".to_string()
+        } else {
+            header.tile_size.to_string()
+        },
+        header.background_range(),
+        if header.perceptual {
+            " (perceptual)"
+        } else {
+            ""
+        },
+        header.region_qualities.len(),
+        header
+            .thumbnail
+            .as_ref()
+            .map_or("none".to_string(), |thumbnail| {
+                format!("{}x{}", thumbnail.width, thumbnail.height)
+            }),
+        payload.start,
+        payload.len(),
+    );
+    if header.chunked {
+        let chunks: Vec<String> = read_chunks(bytes)?
+            .iter()
+            .map(|chunk| format!("{} ({} bytes)", chunk.name(), chunk.len))
+            .collect();
+        text.push_str(&format!("chunks: {}\n", chunks.join(", ")));
+    }
+    if header.signed {
+        text.push_str(&format!("signature: Ed25519, {SIGNATURE_LEN} bytes\n"));
+    }
+    if let Some(refinement) = &header.refinement {
+        text.push_str(&format!(
+            "refinement: max error {}, {} bytes\n",
+            refinement.max_error,
+            refinement.bits.len()
+        ));
+    }
+    for (key, value) in &header.metadata {
+        match ColorTag::from_metadata(&[(key.clone(), value.clone())]) {
+            Ok(Some(ColorTag::Icc(profile))) => {
+                text.push_str(&format!("meta {key}=({} bytes)\n", profile.len()))
+            }
+            _ => text.push_str(&format!("meta {key}={value}\n")),
+        }
+    }
+    Ok(text)
+}
+
+/// Prints the header fields and the metadata of a compressed image, or the summary of an archive
+/// or a multi-frame stream, to standard out.
+///
+/// # Arguments
+/// * `filename`: Location of the input, or None to read it from standard in
+pub fn info(filename: Option<&str>) -> Result<(), CliError> {
+    let bytes = read_input(filename)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let text = describe(&bytes).map_err(|message| CliError::stream(&bytes, message))?;
+    print!("{text}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::{compress_frames, SequenceOptions};
+    use crate::archive::{write_archive, ArchiveEntry};
+    use crate::codec::{compress_image, decompress_image};
+    use crate::encoder::Encoder;
+    use crate::ppm::{Rgb, RgbImage};
+
+    #[test]
+    fn metadata_is_stored_in_the_header() {
+        let image = RgbImage {
+            pixels: vec![
+                Rgb {
+                    red: 40,
+                    green: 80,
+                    blue: 120
+                };
+                16
+            ],
+            width: 4,
+            height: 4,
+            denominator: 255,
+        };
+        let encoder = Encoder::new()
+            .metadata("source", "chart.ppm")
+            .metadata("comment", "a = b");
+        let compressed = encoder.compress(&image).unwrap();
+        let (header, _) = Header::read(&compressed).unwrap();
+        assert_eq!(header.metadata, encoder.options().metadata);
+        assert_eq!(
+            decompress_image(&compressed).unwrap(),
+            decompress_image(&compress_image(&image, &Default::default())).unwrap()
+        );
+        let text = describe(&compressed).unwrap();
+        assert!(text.contains("meta source=chart.ppm\nmeta comment=a = b\n"));
+        assert!(describe(&compressed[..compressed.len() - 20]).is_err());
+        assert!(text.starts_with("version: 1\n"));
+        let legacy = Encoder::new()
+            .compat(crate::format::Compat::Csc411)
+            .compress(&image)
+            .unwrap();
+        assert!(describe(&legacy)
+            .unwrap()
+            .starts_with("version: 0 (Compressed image format 2)\ndimensions: 4x4\n"));
+        let mut future = compressed.clone();
+        future[4] = 9;
+        assert_eq!(read_version(&future), Ok(9));
+        assert!(describe(&future)
+            .unwrap_err()
+            .ends_with("this decoder reads versions 0, 1, 2, 3"));
+    }
+
+    #[test]
+    fn archives_and_frames_are_summed_up() {
+        let image = RgbImage {
+            pixels: vec![
+                Rgb {
+                    red: 40,
+                    green: 80,
+                    blue: 120
+                };
+                16
+            ],
+            width: 4,
+            height: 4,
+            denominator: 255,
+        };
+        let compressed = compress_image(&image, &Default::default());
+        let entry = ArchiveEntry::from_compressed("chart", compressed.clone()).unwrap();
+        let archive = write_archive(&[entry]);
+        assert_eq!(
+            describe(&archive).unwrap(),
+            format!(
+                "archive: version 1\nentries: 1\nchart  4x4  {} bytes\n",
+                compressed.len()
+            )
+        );
+        let frames = [image.clone(), image.clone(), image];
+        let stream = compress_frames(&frames, &SequenceOptions::default()).unwrap();
+        let text = describe(&stream).unwrap();
+        assert!(text.starts_with("multi-frame stream: version 2\ndimensions: 4x4\nframes: 3\n"));
+        assert!(text.contains("key frames: 1\n"));
+        let error = decompress_image(&stream).unwrap_err();
+        assert!(error.contains("rpeg decompress --frames"), "{error}");
+        let error = decompress_image(&archive).unwrap_err();
+        assert!(error.contains("rpeg unpack"), "{error}");
+    }
+}
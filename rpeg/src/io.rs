@@ -0,0 +1,559 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Stdin, Stdout, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Set by `--quiet`: leave out every message that is not an error.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Number of temporary files `Output` created, which tells apart those of the threads of a
+/// process writing the same file.
+static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Turns the notices of `notice` off or back on, for the whole process.
+///
+/// # Arguments
+/// * `quiet`: Leave out every notice from now on
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Prints a message that is not an error, such as a warning, a progress report, or timings, on
+/// standard error, unless `--quiet` turned notices off.
+///
+/// # Arguments
+/// * `message`: Message to print
+pub fn notice(message: impl std::fmt::Display) {
+    if !QUIET.load(Ordering::Relaxed) {
+        eprintln!("{message}");
+    }
+}
+
+/// Reads every byte of `filename`, or of standard in.
+///
+/// # Arguments
+/// * `filename`: Location of the file within your disk, or None to read from standard in
+pub fn read_input(filename: Option<&str>) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    match filename {
+        Some(filename) => std::fs::File::open(filename)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|error| format!("Failed to read {filename}: {error}"))?,
+        None => return std::io::stdin().read_rpeg(),
+    };
+    Ok(bytes)
+}
+
+/// ## Contents of a file mapped into memory
+///
+/// The file is mapped read-only on Unix, so that the operating system only pages in the parts
+/// of it that are read, such as the tiles of a region. On other systems it is read into
+/// memory instead. The contents are only valid as long as no other program truncates the
+/// file.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::io::MappedFile;
+///
+/// let path = std::env::temp_dir().join("rpeg-mapped-example.rpeg");
+/// std::fs::write(&path, b"RPEG").unwrap();
+/// let mapped = MappedFile::open(path.to_str().unwrap()).unwrap();
+/// assert_eq!(&mapped[..], b"RPEG");
+/// ```
+pub struct MappedFile {
+    #[cfg(unix)]
+    pointer: *mut libc::c_void,
+    #[cfg(unix)]
+    len: usize,
+    #[cfg(not(unix))]
+    bytes: Vec<u8>,
+}
+
+// The mapping is read-only and never changes once created.
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Maps every byte of `filename` into memory.
+    ///
+    /// # Arguments
+    /// * `filename`: Location of the file within your disk
+    #[cfg(unix)]
+    pub fn open(filename: &str) -> Result<MappedFile, String> {
+        use std::os::unix::io::AsRawFd;
+        let error = |error: std::io::Error| format!("Failed to map {filename}: {error}");
+        let file = File::open(filename).map_err(error)?;
+        let len = file.metadata().map_err(error)?.len() as usize;
+        if len == 0 {
+            return Ok(MappedFile {
+                pointer: std::ptr::null_mut(),
+                len,
+            });
+        }
+        // SAFETY: the file is open for reading, and a private read-only mapping of its length
+        // is only ever read through `deref`.
+        let pointer = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if pointer == libc::MAP_FAILED {
+            return Err(error(std::io::Error::last_os_error()));
+        }
+        Ok(MappedFile { pointer, len })
+    }
+
+    /// Reads every byte of `filename` into memory, on systems without mappings.
+    ///
+    /// # Arguments
+    /// * `filename`: Location of the file within your disk
+    #[cfg(not(unix))]
+    pub fn open(filename: &str) -> Result<MappedFile, String> {
+        Ok(MappedFile {
+            bytes: read_input(Some(filename))?,
+        })
+    }
+}
+
+impl std::ops::Deref for MappedFile {
+    type Target = [u8];
+
+    #[cfg(unix)]
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // SAFETY: `pointer` maps `len` readable bytes until the mapping is dropped.
+        unsafe { std::slice::from_raw_parts(self.pointer as *const u8, self.len) }
+    }
+
+    #[cfg(not(unix))]
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.len > 0 {
+            // SAFETY: `pointer` and `len` describe a mapping created by `open`.
+            unsafe {
+                libc::munmap(self.pointer, self.len);
+            }
+        }
+    }
+}
+
+/// Writes `bytes` to `filename`, or to standard out. An existing file is replaced, atomically
+/// like `Output::write`.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, archive, or stream to write
+/// * `filename`: Location of the file to write, or None to write to standard out
+pub fn write_output(bytes: &[u8], filename: Option<&str>) -> Result<(), String> {
+    let output = Output {
+        filename: filename.map(str::to_string),
+        force: true,
+    };
+    output.write(bytes)
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// ## Destination of the result of a command
+///
+/// Files are written next to their destination under a temporary name, and renamed over it
+/// once complete, so that a failure never leaves a truncated file behind. An existing file is
+/// only replaced if `force` is set.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::io::Output;
+///
+/// let output = Output { filename: None, force: false };
+/// assert!(output.write(b"").is_ok());
+/// ```
+pub struct Output {
+    /// Location of the file to write, or None to write to standard out.
+    pub filename: Option<String>,
+    /// Replace the file if it already exists.
+    pub force: bool,
+}
+
+impl Output {
+    /// Writes `bytes` to the destination.
+    ///
+    /// # Arguments
+    /// * `bytes`: Compressed image, image, or archive to write
+    pub fn write(&self, bytes: &[u8]) -> Result<(), String> {
+        self.write_with(|writer| {
+            writer
+                .write_all(bytes)
+                .map_err(|error| format!("Failed to write: {error}"))
+        })
+    }
+
+    /// Writes `bytes` to the destination like `write`, into a file that on Unix only its owner
+    /// can read or write from the moment it is created, temporary file included.
+    ///
+    /// # Arguments
+    /// * `bytes`: Secret to write, such as a secret key
+    pub fn write_private(&self, bytes: &[u8]) -> Result<(), String> {
+        self.write_file(true, |writer| {
+            writer
+                .write_all(bytes)
+                .map_err(|error| format!("Failed to write: {error}"))
+        })
+    }
+
+    /// Calls `write` with a writer to the destination. The file is only created, or replaced,
+    /// once `write` succeeds, and not at all if SIGINT or SIGTERM arrived meanwhile, once
+    /// `cancel::on_signals` installed their handlers; the temporary file is removed whenever
+    /// it is not. Without `force`, a file is never replaced, even one created while `write`
+    /// ran: the temporary file is hard linked into place, or copied into a newly created file
+    /// on file systems without hard links.
+    ///
+    /// # Arguments
+    /// * `write`: Writes the contents of the file
+    pub fn write_with(
+        &self,
+        write: impl FnOnce(&mut dyn Write) -> Result<(), String>,
+    ) -> Result<(), String> {
+        self.write_file(false, write)
+    }
+
+    /// Calls `write` like `write_with`, into files created owner-only on Unix if `private`.
+    fn write_file(
+        &self,
+        private: bool,
+        write: impl FnOnce(&mut dyn Write) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let Some(filename) = &self.filename else {
+            let mut stdout = std::io::stdout().lock();
+            write(&mut stdout)?;
+            return stdout
+                .flush()
+                .map_err(|error| format!("Failed to write to standard out: {error}"));
+        };
+        let path = Path::new(filename);
+        if !self.force && path.exists() {
+            return Err(format!(
+                "{filename} already exists; use --force to overwrite it"
+            ));
+        }
+        let name = path
+            .file_name()
+            .ok_or_else(|| format!("{filename} is not a file name"))?;
+        let guard = TemporaryFile(path.with_file_name(format!(
+            ".{}.{}.{}.tmp",
+            name.to_string_lossy(),
+            std::process::id(),
+            TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+        )));
+        let temporary = guard.0.as_path();
+        create_new(temporary, private)
+            .map_err(|error| error.to_string())
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                write(&mut writer)?;
+                writer
+                    .into_inner()
+                    .map_err(|error| error.to_string())?
+                    .sync_all()
+                    .map_err(|error| error.to_string())
+            })
+            .and_then(|_| {
+                if crate::cancel::interrupted() {
+                    return Err("interrupted by a signal".to_string());
+                }
+                if self.force {
+                    return std::fs::rename(temporary, path).map_err(|error| error.to_string());
+                }
+                // Unlike a rename, a hard link fails instead of replacing its target.
+                let linked =
+                    std::fs::hard_link(temporary, path).or_else(|error| match error.kind() {
+                        std::io::ErrorKind::Unsupported | std::io::ErrorKind::PermissionDenied => {
+                            copy_to_new_file(temporary, path, private)
+                        }
+                        _ => Err(error),
+                    });
+                match linked {
+                    Ok(()) => Ok(()),
+                    Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+                        Err("it was created meanwhile; use --force to overwrite it".to_string())
+                    }
+                    Err(error) => Err(error.to_string()),
+                }
+            })
+            .map_err(|error| format!("Failed to write {filename}: {error}"))
+    }
+}
+
+/// Temporary file of `Output::write_with`, removed once dropped, whether it was renamed into
+/// place, linked, or left behind by an error, a cancellation, or a panic.
+struct TemporaryFile(PathBuf);
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Creates the file `path` for writing, failing if it already exists. If `private`, the file is
+/// created readable and writable by its owner alone on Unix, and with the defaults of the
+/// system elsewhere.
+fn create_new(path: &Path, private: bool) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    options.open(path)
+}
+
+/// Copies `source` into `destination`, which is created like `create_new` and must not exist
+/// yet; a partial copy is removed.
+///
+/// # Arguments
+/// * `source`: File to copy
+/// * `destination`: Location of the new file
+/// * `private`: Create the file readable by its owner alone
+fn copy_to_new_file(source: &Path, destination: &Path, private: bool) -> std::io::Result<()> {
+    let mut file = create_new(destination, private)?;
+    let copied = File::open(source)
+        .and_then(|mut source| std::io::copy(&mut source, &mut file))
+        .and_then(|_| file.sync_all());
+    if copied.is_err() {
+        let _ = std::fs::remove_file(destination);
+    }
+    copied
+}
+
+/// ## Destination the codec writes a compressed image to
+///
+/// `Encoder::compress_to` hands the sink the whole compressed image at once, so that a
+/// transport only has to deliver bytes, whether to a file, a buffer, a socket, or standard out.
+/// It is implemented for `File`, `Vec<u8>`, which appends the image, `TcpStream`, `Stdout`,
+/// and `Output`, which replaces its file atomically. Other transports implement it themselves.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::io::{RpegSink, RpegSource};
+///
+/// let mut buffer = Vec::new();
+/// buffer.write_rpeg(b"RPEG").unwrap();
+/// assert_eq!(buffer.read_rpeg().unwrap(), b"RPEG");
+/// assert!(buffer.is_empty());
+/// ```
+pub trait RpegSink {
+    /// Writes a whole compressed image, or returns an error saying why it could not be written.
+    ///
+    /// # Arguments
+    /// * `bytes`: Compressed image, header included
+    fn write_rpeg(&mut self, bytes: &[u8]) -> Result<(), String>;
+}
+
+/// ## Origin the codec reads a compressed image from
+///
+/// `Decoder::decompress_from` takes every byte the source gives as the compressed image. It is
+/// implemented for `File` and `TcpStream`, which are read to their end, `Vec<u8>`, whose bytes
+/// are taken out of it, and `Stdin`.
+pub trait RpegSource {
+    /// Reads a whole compressed image, or returns an error saying why it could not be read.
+    fn read_rpeg(&mut self) -> Result<Vec<u8>, String>;
+}
+
+/// Writes `bytes` to `writer` and flushes it, naming `destination` in the error.
+fn write_all_to(writer: &mut impl Write, bytes: &[u8], destination: &str) -> Result<(), String> {
+    writer
+        .write_all(bytes)
+        .and_then(|_| writer.flush())
+        .map_err(|error| format!("Failed to write to {destination}: {error}"))
+}
+
+/// Reads `reader` to its end, naming `origin` in the error.
+fn read_all_from(reader: &mut impl Read, origin: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    reader
+        .read_to_end(&mut bytes)
+        .map_err(|error| format!("Failed to read {origin}: {error}"))?;
+    Ok(bytes)
+}
+
+impl RpegSink for File {
+    fn write_rpeg(&mut self, bytes: &[u8]) -> Result<(), String> {
+        write_all_to(self, bytes, "the file")
+    }
+}
+
+impl RpegSink for Vec<u8> {
+    fn write_rpeg(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+impl RpegSink for TcpStream {
+    fn write_rpeg(&mut self, bytes: &[u8]) -> Result<(), String> {
+        write_all_to(self, bytes, "the connection")
+    }
+}
+
+impl RpegSink for Stdout {
+    fn write_rpeg(&mut self, bytes: &[u8]) -> Result<(), String> {
+        write_all_to(&mut self.lock(), bytes, "standard out")
+    }
+}
+
+impl RpegSink for Output {
+    fn write_rpeg(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.write(bytes)
+    }
+}
+
+impl RpegSource for File {
+    fn read_rpeg(&mut self) -> Result<Vec<u8>, String> {
+        read_all_from(self, "the file")
+    }
+}
+
+impl RpegSource for Vec<u8> {
+    fn read_rpeg(&mut self) -> Result<Vec<u8>, String> {
+        Ok(std::mem::take(self))
+    }
+}
+
+impl RpegSource for TcpStream {
+    fn read_rpeg(&mut self) -> Result<Vec<u8>, String> {
+        read_all_from(self, "the connection")
+    }
+}
+
+impl RpegSource for Stdin {
+    fn read_rpeg(&mut self) -> Result<Vec<u8>, String> {
+        read_all_from(&mut self.lock(), "standard in")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_replaced_only_when_forced() {
+        let path = std::env::temp_dir().join(format!("rpeg-io-{}.rpeg", std::process::id()));
+        let filename = path.to_str().unwrap().to_string();
+        let mut output = Output {
+            filename: Some(filename.clone()),
+            force: false,
+        };
+        let error = output
+            .write_with(|writer| {
+                std::fs::write(&path, b"first").unwrap();
+                writer
+                    .write_all(b"second")
+                    .map_err(|error| error.to_string())
+            })
+            .unwrap_err();
+        assert!(error.contains("created meanwhile"), "{error}");
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+        std::fs::remove_file(&path).unwrap();
+        output.write(b"first").unwrap();
+        assert!(output.write(b"second").is_err());
+        assert!(output
+            .write_with(|_| Err("compression failed".to_string()))
+            .is_err());
+        output.force = true;
+        assert!(output
+            .write_with(|writer| {
+                writer.write_all(b"partial").unwrap();
+                Err("compression failed".to_string())
+            })
+            .is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+        output.write(b"second").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+
+        // Threads writing the same file each use a temporary file of their own.
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| output.write(b"third").unwrap());
+            }
+        });
+        assert_eq!(std::fs::read(&path).unwrap(), b"third");
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let temporaries = std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .filter(|entry| {
+                let entry = entry.as_ref().unwrap().file_name();
+                entry.to_string_lossy().starts_with(&format!(".{name}."))
+            })
+            .count();
+        assert_eq!(temporaries, 0);
+
+        // The copy used on file systems without hard links never replaces a file either.
+        let copy = path.with_extension("copy");
+        copy_to_new_file(&path, &copy, false).unwrap();
+        assert_eq!(std::fs::read(&copy).unwrap(), b"third");
+        let error = copy_to_new_file(&path, &copy, false).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        std::fs::remove_file(copy).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn mapped_files_hold_their_contents() {
+        let path = std::env::temp_dir().join(format!("rpeg-mmap-{}.rpeg", std::process::id()));
+        let filename = path.to_str().unwrap();
+        std::fs::write(&path, b"").unwrap();
+        assert!(MappedFile::open(filename).unwrap().is_empty());
+        let bytes: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(&MappedFile::open(filename).unwrap()[..], &bytes[..]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(MappedFile::open(filename).is_err());
+    }
+
+    #[test]
+    fn images_travel_through_files_and_sockets() {
+        use crate::codec::{compress_image, decompress_image};
+        use crate::decoder::Decoder;
+        use crate::encoder::Encoder;
+        use crate::testkit::{synthetic_image, Pattern};
+        use std::net::TcpListener;
+
+        let image = synthetic_image(Pattern::Noise { seed: 2 }, 12, 10);
+        let expected = decompress_image(&compress_image(&image, &Default::default())).unwrap();
+        let path = std::env::temp_dir().join(format!("rpeg-sink-{}.rpeg", std::process::id()));
+        Encoder::new()
+            .compress_to(&image, &mut File::create(&path).unwrap())
+            .unwrap();
+        let decoded = Decoder::new().decompress_from(&mut File::open(&path).unwrap());
+        assert_eq!(decoded.unwrap(), expected);
+        std::fs::remove_file(&path).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let receiver = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            Decoder::new().decompress_from(&mut stream)
+        });
+        let mut stream = TcpStream::connect(address).unwrap();
+        Encoder::new().compress_to(&image, &mut stream).unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        assert_eq!(receiver.join().unwrap().unwrap(), expected);
+    }
+}
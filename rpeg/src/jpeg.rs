@@ -0,0 +1,90 @@
+use crate::codec::EncoderOptions;
+use crate::color_tag::ColorTag;
+use crate::orientation::{Orientation, ORIENTATION_KEY};
+use crate::ppm::{Rgb, RgbImage};
+
+/// Decodes a baseline or progressive JPEG image, with a denominator of 255. Gray levels are
+/// copied to the three channels and CMYK is converted to RGB. Returns the pixels as they are
+/// stored, with the EXIF orientation of the file and its ICC profile, if any.
+///
+/// # Arguments
+/// * `bytes`: Contents of the JPEG file
+pub fn read_jpeg(bytes: &[u8]) -> Result<(RgbImage, Orientation, Option<ColorTag>), String> {
+    let invalid = |error: jpeg_decoder::Error| format!("Invalid JPEG image: {error}");
+    let mut decoder = jpeg_decoder::Decoder::new(bytes);
+    let data = decoder.decode().map_err(invalid)?;
+    let info = decoder.info().ok_or("The JPEG image has no frame")?;
+    let channel = |value: u8| value as u16;
+    let pixels = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => data
+            .iter()
+            .map(|gray| Rgb {
+                red: channel(*gray),
+                green: channel(*gray),
+                blue: channel(*gray),
+            })
+            .collect(),
+        jpeg_decoder::PixelFormat::RGB24 => data
+            .chunks_exact(3)
+            .map(|pixel| Rgb {
+                red: channel(pixel[0]),
+                green: channel(pixel[1]),
+                blue: channel(pixel[2]),
+            })
+            .collect(),
+        jpeg_decoder::PixelFormat::CMYK32 => data
+            .chunks_exact(4)
+            .map(|pixel| {
+                let ink = |value: u8| (255 - value as u16) * (255 - pixel[3] as u16) / 255;
+                Rgb {
+                    red: ink(pixel[0]),
+                    green: ink(pixel[1]),
+                    blue: ink(pixel[2]),
+                }
+            })
+            .collect(),
+    };
+    let image = RgbImage {
+        pixels,
+        width: info.width as u32,
+        height: info.height as u32,
+        denominator: 255,
+    };
+    let orientation = Orientation::of_jpeg(bytes).unwrap_or_default();
+    Ok((image, orientation, decoder.icc_profile().map(ColorTag::Icc)))
+}
+
+/// Returns a decoded JPEG image and the settings it is compressed with: the image turned
+/// upright, or its orientation kept in the metadata under `ORIENTATION_KEY` when
+/// `options.keep_orientation` is set, and its ICC profile as the color tag unless `options`
+/// set one.
+///
+/// # Arguments
+/// * `image`: Pixels as they are stored
+/// * `orientation`: EXIF orientation of the file
+/// * `color_tag`: ICC profile of the file, if any
+/// * `options`: Settings used to compress the image
+pub fn prepare(
+    image: RgbImage,
+    orientation: Orientation,
+    color_tag: Option<ColorTag>,
+    options: &EncoderOptions,
+) -> (RgbImage, EncoderOptions) {
+    let mut options = options.clone();
+    if let Some(tag) = color_tag {
+        if matches!(ColorTag::from_metadata(&options.metadata), Ok(None)) {
+            options.metadata = tag.replace_in(&options.metadata);
+        }
+    }
+    if orientation == Orientation::Normal {
+        (image, options)
+    } else if options.keep_orientation {
+        options.metadata.retain(|(key, _)| key != ORIENTATION_KEY);
+        options
+            .metadata
+            .push((ORIENTATION_KEY.to_string(), orientation.tag().to_string()));
+        (image, options)
+    } else {
+        (orientation.apply(&image), options)
+    }
+}
@@ -0,0 +1,373 @@
+use crate::chroma::{ChromaQuantizer, ChromaTable};
+use crate::structs::QuantizedBlock;
+use bitpack::bitpack::{gets, getu, verify_layout, Field as BitField, Layout};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Represent a bit field of a code word
+///
+/// `lsb` is the position of the least significant bit of the field within the word.
+pub struct Field {
+    pub width: u64,
+    pub lsb: u64,
+}
+
+impl Field {
+    /// Returns `word` with the low `width` bits of `value` placed into the field, which must
+    /// be zero in `word`. Unlike `newu`, it cannot fail, so decoding never unwraps.
+    ///
+    /// # Arguments
+    /// * `word`: Code word whose field is still zero
+    /// * `value`: Value whose low bits are stored
+    pub fn place(&self, word: u64, value: u64) -> u64 {
+        word | (getu(value, self.width, 0) << self.lsb)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## How the average Pb and Pr of a block are quantized
+///
+/// `Indexed` stores the index of the closest entry of a chroma table.
+/// `Direct` quantizes the chroma uniformly between -0.5 and 0.5, storing the level counted from
+/// the most negative one.
+pub enum ChromaCoding {
+    Indexed(ChromaTable),
+    Direct,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Represent the layout of the code word of a 2x2 block
+///
+/// The layout gives the bit field of every quantized value, from which the quantization steps
+/// follow: `a` uses every level of its unsigned field, while `b`, `c`, and `d` use the
+/// symmetric range of their signed fields. `id` is the number recorded in the header.
+/// `chroma_weight` scales Pb and Pr before they are quantized, and back after, in quarters: at
+/// `NEUTRAL_CHROMA_WEIGHT` they are quantized as they are, above it in finer steps that clip
+/// the most saturated colors, and below it in coarser steps. It is recorded in the header
+/// next to the layout id.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::layout::{WordLayout, NARROW_LAYOUT, WIDE_LAYOUT};
+///
+/// assert_eq!(NARROW_LAYOUT.word_bytes(), 4);
+/// assert_eq!(WordLayout::from_id(WIDE_LAYOUT.id), Some(WIDE_LAYOUT));
+/// ```
+pub struct WordLayout {
+    pub id: u8,
+    pub word_bits: u64,
+    pub a: Field,
+    pub b: Field,
+    pub c: Field,
+    pub d: Field,
+    pub pb: Field,
+    pub pr: Field,
+    pub chroma: ChromaCoding,
+    pub chroma_weight: u8,
+}
+
+/// Chroma weight, in quarters, under which Pb and Pr are quantized as they are.
+pub const NEUTRAL_CHROMA_WEIGHT: u8 = 4;
+
+/// Largest chroma weight, in quarters: Pb and Pr in steps four times finer.
+pub const MAX_CHROMA_WEIGHT: u8 = 16;
+
+/// The original 32-bit code word: a (9 bits), b, c, and d (5 bits each), and the chroma table
+/// indices of Pb and Pr (4 bits each).
+pub const NARROW_LAYOUT: WordLayout = WordLayout {
+    id: 0,
+    word_bits: 32,
+    a: Field { width: 9, lsb: 23 },
+    b: Field { width: 5, lsb: 18 },
+    c: Field { width: 5, lsb: 13 },
+    d: Field { width: 5, lsb: 8 },
+    pb: Field { width: 4, lsb: 4 },
+    pr: Field { width: 4, lsb: 0 },
+    chroma: ChromaCoding::Indexed(ChromaTable::Standard),
+    chroma_weight: NEUTRAL_CHROMA_WEIGHT,
+};
+
+/// A 64-bit code word for quality over size: a (16 bits), b, c, and d (10 bits each), and
+/// Pb and Pr quantized directly (9 bits each).
+pub const WIDE_LAYOUT: WordLayout = WordLayout {
+    id: 1,
+    word_bits: 64,
+    a: Field { width: 16, lsb: 48 },
+    b: Field { width: 10, lsb: 38 },
+    c: Field { width: 10, lsb: 28 },
+    d: Field { width: 10, lsb: 18 },
+    pb: Field { width: 9, lsb: 9 },
+    pr: Field { width: 9, lsb: 0 },
+    chroma: ChromaCoding::Direct,
+    chroma_weight: NEUTRAL_CHROMA_WEIGHT,
+};
+
+/// A 32-bit code word for saturated images, where the 4-bit chroma table causes visible hue
+/// shifts: a (8 bits), b, c, and d (4 bits each), and Pb and Pr quantized directly (6 bits each).
+pub const FINE_CHROMA_LAYOUT: WordLayout = WordLayout {
+    id: 2,
+    word_bits: 32,
+    a: Field { width: 8, lsb: 24 },
+    b: Field { width: 4, lsb: 20 },
+    c: Field { width: 4, lsb: 16 },
+    d: Field { width: 4, lsb: 12 },
+    pb: Field { width: 6, lsb: 6 },
+    pr: Field { width: 6, lsb: 0 },
+    chroma: ChromaCoding::Direct,
+    chroma_weight: NEUTRAL_CHROMA_WEIGHT,
+};
+
+/// A 32-bit code word trading chroma for luma: a (11 bits), b, c, and d (5 bits each), and the
+/// 3-bit indices of Pb and Pr in the 8-entry chroma table.
+pub const FAST_CHROMA_LAYOUT: WordLayout = WordLayout {
+    id: 3,
+    word_bits: 32,
+    a: Field { width: 11, lsb: 21 },
+    b: Field { width: 5, lsb: 16 },
+    c: Field { width: 5, lsb: 11 },
+    d: Field { width: 5, lsb: 6 },
+    pb: Field { width: 3, lsb: 3 },
+    pr: Field { width: 3, lsb: 0 },
+    chroma: ChromaCoding::Indexed(ChromaTable::Fast),
+    chroma_weight: NEUTRAL_CHROMA_WEIGHT,
+};
+
+/// The fields of `FINE_CHROMA_LAYOUT`, with Pb and Pr indexing the 64-entry chroma table,
+/// whose entries are denser around zero than uniform levels.
+pub const FINE_TABLE_LAYOUT: WordLayout = WordLayout {
+    id: 4,
+    chroma: ChromaCoding::Indexed(ChromaTable::Fine),
+    ..FINE_CHROMA_LAYOUT
+};
+
+/// Every layout the decoder understands.
+pub const LAYOUTS: [WordLayout; 5] = [
+    NARROW_LAYOUT,
+    WIDE_LAYOUT,
+    FINE_CHROMA_LAYOUT,
+    FAST_CHROMA_LAYOUT,
+    FINE_TABLE_LAYOUT,
+];
+
+impl Default for WordLayout {
+    fn default() -> Self {
+        NARROW_LAYOUT
+    }
+}
+
+impl WordLayout {
+    /// Returns the layout recorded in the header as `id`, if there is one.
+    pub fn from_id(id: u8) -> Option<WordLayout> {
+        LAYOUTS.iter().find(|layout| layout.id == id).copied()
+    }
+
+    /// Returns the layout with Pb and Pr scaled by `weight` before they are quantized, rounded
+    /// to a quarter. `verify` rejects weights outside of 0.25 to 4.
+    ///
+    /// # Arguments
+    /// * `weight`: Scale of Pb and Pr, 1 to quantize them as they are
+    pub fn with_chroma_weight(self, weight: f64) -> WordLayout {
+        WordLayout {
+            chroma_weight: (weight * NEUTRAL_CHROMA_WEIGHT as f64)
+                .round()
+                .clamp(0.0, 255.0) as u8,
+            ..self
+        }
+    }
+
+    /// Returns the scale Pb and Pr are multiplied by before they are quantized.
+    pub fn chroma_scale(&self) -> f64 {
+        self.chroma_weight as f64 / NEUTRAL_CHROMA_WEIGHT as f64
+    }
+
+    /// Returns the number of bytes a code word takes in the payload.
+    pub fn word_bytes(&self) -> usize {
+        (self.word_bits / 8) as usize
+    }
+
+    /// Appends a code word to `out` in Bigendian format.
+    pub fn write_word(&self, word: u64, out: &mut Vec<u8>) {
+        out.extend_from_slice(&word.to_be_bytes()[8 - self.word_bytes()..]);
+    }
+
+    /// Reads a code word stored in Bigendian format by `write_word`.
+    ///
+    /// # Arguments
+    /// * `bytes`: The `word_bytes` bytes of the code word
+    pub fn read_word(&self, bytes: &[u8]) -> u64 {
+        bytes
+            .iter()
+            .fold(0, |word, byte| (word << 8) | *byte as u64)
+    }
+
+    /// Returns the quantized value of `a` for a luma of 1.
+    pub fn a_scale(&self) -> f64 {
+        ((1_u64 << self.a.width) - 1) as f64
+    }
+
+    /// Returns the largest magnitude a quantized b, c, or d can take in its signed field.
+    pub fn bcd_levels(&self) -> f64 {
+        ((1_u64 << (self.b.width - 1)) - 1) as f64
+    }
+
+    /// Returns the number of levels on each side of zero of a directly quantized chroma.
+    pub fn chroma_levels(&self) -> f64 {
+        ((1_u64 << (self.pb.width - 1)) - 1) as f64
+    }
+
+    /// Returns whether the GPU encoder can quantize Pb and Pr for this layout. The shader only
+    /// knows the standard chroma table, without a chroma weight.
+    pub fn supported_on_gpu(&self) -> bool {
+        !matches!(self.chroma, ChromaCoding::Indexed(table) if table != ChromaTable::Standard)
+            && self.chroma_weight == NEUTRAL_CHROMA_WEIGHT
+    }
+
+    /// Returns an error unless every field lies within the code word and every value in its
+    /// range survives packing and unpacking, as checked by `bitpack::verify_layout`, and the
+    /// chroma weight lies between 0.25 and 4.
+    pub fn verify(&self) -> Result<(), String> {
+        if !(1..=MAX_CHROMA_WEIGHT).contains(&self.chroma_weight) {
+            return Err("The chroma weight must lie between 0.25 and 4".to_string());
+        }
+        let fields = [
+            (self.a, false),
+            (self.b, true),
+            (self.c, true),
+            (self.d, true),
+            (self.pb, false),
+            (self.pr, false),
+        ];
+        if let Some((field, _)) = fields
+            .iter()
+            .find(|(field, _)| field.width + field.lsb > self.word_bits)
+        {
+            return Err(format!(
+                "A {}-bit field at bit {} does not fit a {}-bit code word",
+                field.width, field.lsb, self.word_bits
+            ));
+        }
+        let fields = fields
+            .into_iter()
+            .map(|(field, signed)| BitField {
+                width: field.width,
+                lsb: field.lsb,
+                signed,
+            })
+            .collect();
+        verify_layout(&Layout { fields })
+            .map_err(|message| format!("Code word layout {}: {message}", self.id))
+    }
+
+    /// Packs a quantized block into a code word. A value that does not fit its field keeps only
+    /// its low bits.
+    ///
+    /// # Arguments
+    /// * `value`: Quantized block whose values fit their fields
+    pub fn pack(&self, value: &QuantizedBlock) -> u64 {
+        let mut word = self.a.place(0, value.a as u64);
+        word = self.b.place(word, value.b as i64 as u64);
+        word = self.c.place(word, value.c as i64 as u64);
+        word = self.d.place(word, value.d as i64 as u64);
+        word = self.pb.place(word, value.pb as u64);
+        self.pr.place(word, value.pr as u64)
+    }
+
+    /// Unpacks a code word back into its quantized block.
+    ///
+    /// # Arguments
+    /// * `word`: Code word in this layout
+    pub fn unpack(&self, word: u64) -> QuantizedBlock {
+        QuantizedBlock {
+            a: getu(word, self.a.width, self.a.lsb) as u16,
+            b: gets(word, self.b.width, self.b.lsb) as i16,
+            c: gets(word, self.c.width, self.c.lsb) as i16,
+            d: gets(word, self.d.width, self.d.lsb) as i16,
+            pb: getu(word, self.pb.width, self.pb.lsb) as u16,
+            pr: getu(word, self.pr.width, self.pr.lsb) as u16,
+        }
+    }
+
+    /// Returns the code word used for a block that has not been received yet: black, with both
+    /// chroma values at (or closest to) zero.
+    pub fn missing_word(&self) -> u64 {
+        let zero_chroma = match self.chroma {
+            ChromaCoding::Indexed(table) => table.index_of_chroma(0.0) as u64,
+            ChromaCoding::Direct => self.chroma_levels() as u64,
+        };
+        self.pb.place(self.pr.place(0, zero_chroma), zero_chroma)
+    }
+
+    /// Returns the width of the DC part of a code word: `a` and both chroma values.
+    pub fn dc_width(&self) -> u64 {
+        self.a.width + self.pb.width + self.pr.width
+    }
+
+    /// Returns the width of the refinement part of a code word: `b`, `c`, and `d`.
+    pub fn ac_width(&self) -> u64 {
+        self.b.width + self.c.width + self.d.width
+    }
+
+    /// Splits a code word into its DC part (`a`, `pb`, and `pr` concatenated) and its
+    /// refinement part (`b`, `c`, and `d` concatenated).
+    pub fn split(&self, word: u64) -> (u64, u64) {
+        let concat = |fields: [Field; 3]| {
+            fields.iter().fold(0, |acc, field| {
+                (acc << field.width) | getu(word, field.width, field.lsb)
+            })
+        };
+        (
+            concat([self.a, self.pb, self.pr]),
+            concat([self.b, self.c, self.d]),
+        )
+    }
+
+    /// Rebuilds a code word from the parts returned by `split`. A missing refinement part
+    /// leaves `b`, `c`, and `d` at zero.
+    pub fn join(&self, dc: u64, ac: Option<u64>) -> u64 {
+        let scatter = |word: u64, value: u64, fields: [Field; 3]| {
+            let mut shift = fields.iter().map(|field| field.width).sum::<u64>();
+            fields.iter().fold(word, |word, field| {
+                shift -= field.width;
+                field.place(word, getu(value, field.width, shift))
+            })
+        };
+        let word = scatter(0, dc, [self.a, self.pb, self.pr]);
+        match ac {
+            Some(ac) => scatter(word, ac, [self.b, self.c, self.d]),
+            None => word,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extreme_values_survive_packing_in_every_layout() {
+        for layout in LAYOUTS {
+            assert_eq!(layout.verify(), Ok(()));
+            let signed_max = |field: Field| ((1_i64 << (field.width - 1)) - 1) as i16;
+            let unsigned_max = |field: Field| ((1_u64 << field.width) - 1) as u16;
+            let largest = QuantizedBlock {
+                a: unsigned_max(layout.a),
+                b: signed_max(layout.b),
+                c: -signed_max(layout.c) - 1,
+                d: signed_max(layout.d),
+                pb: unsigned_max(layout.pb),
+                pr: unsigned_max(layout.pr),
+            };
+            let smallest = QuantizedBlock {
+                b: -signed_max(layout.b) - 1,
+                c: signed_max(layout.c),
+                d: -1,
+                ..Default::default()
+            };
+            for block in [largest, smallest] {
+                let word = layout.pack(&block);
+                assert_eq!(word.checked_shr(layout.word_bits as u32).unwrap_or(0), 0);
+                assert_eq!(layout.unpack(word), block);
+            }
+        }
+    }
+}
@@ -1,11 +1,128 @@
+pub mod adjust;
+
+pub mod animation;
+
+pub mod archive;
+
+pub mod batch;
+
+pub mod bench;
+
+pub mod cancel;
+
 pub mod codec;
 
+pub mod color_tag;
+
+pub mod completions;
+
+pub mod config;
+
+pub mod content;
+
+pub mod conversions;
+
+pub mod convert;
+
+pub mod crop;
+
+pub mod deblock;
+
+pub mod decoder;
+
+pub mod diff;
+
+pub mod doctor;
+
+pub mod dump;
+
+pub mod hdr;
+
+pub mod encoder;
+
+pub mod error;
+
+pub mod fixed;
+
+mod single;
+
+pub mod format;
+
+pub mod info;
+
+pub mod layout;
+
+pub mod metrics;
+
+pub mod orientation;
+
+pub mod stats;
+
 pub mod structs;
 
-mod conversions;
+pub mod sweep;
+
+pub mod dct_coeff;
+
+mod progressive;
+
+pub mod refine;
+
+pub mod roi;
+
+pub mod serve;
+
+pub mod signature;
+
+pub mod target;
+
+pub mod thumbnail;
+
+pub mod tiles;
+
+pub mod tint;
+
+pub mod transcode;
+
+pub mod visualdiff;
+
+pub mod watch;
+
+pub mod watermark;
+
+pub mod yuv;
+
+pub mod testkit;
+
+pub mod io;
+
+#[cfg(feature = "jpeg")]
+pub mod jpeg;
+
+pub mod ppm;
+
+pub mod palette;
+
+pub mod pixel;
+
+pub mod pipeline;
+
+pub mod porcelain;
+
+pub mod preprocess;
+
+pub mod raw;
+
+pub mod reorient;
+
+pub mod png_image;
+
+pub mod chroma;
 
-mod rgb;
+pub mod two_pass;
 
-mod component_video_and_blocks;
+#[cfg(feature = "gpu")]
+mod gpu;
 
-mod dct_coeff;
+#[cfg(feature = "tokio")]
+pub mod stream;
@@ -1,16 +1,834 @@
-use rpeg::codec::{compress, decompress};
+use rpeg::adjust::{adjust_compressed, Adjustment};
+use rpeg::animation::{compress_sequence, decompress_frame, decompress_sequence, SequenceOptions};
+use rpeg::archive::{archive_add, archive_extract, archive_list, archive_remove, pack, unpack};
+use rpeg::batch::{compress_batch, NameTemplate, OnError, DEFAULT_NAME_TEMPLATE};
+use rpeg::cancel::on_signals;
+use rpeg::codec::{compress, decompress, DecodeOptions, EncoderOptions, HIGH_CONTRAST_LUMA_RANGE};
+use rpeg::color_tag::{ColorSpace, ColorTag, MAX_ICC_PROFILE_LEN};
+use rpeg::completions::{completions, man_page, Shell};
+use rpeg::config::Config;
+use rpeg::convert::convert;
+use rpeg::crop::crop;
+use rpeg::dct_coeff::luma_range_for_quality;
+use rpeg::diff::diff;
+use rpeg::doctor::doctor;
+use rpeg::dump::{dump_stage, DumpStage};
+use rpeg::encoder::{PadPolicy, Preset, RatioPolicy};
+use rpeg::error::{CliError, ErrorKind};
+use rpeg::fixed::Arithmetic;
+use rpeg::format::{parse_metadata, Compat};
+use rpeg::hdr::ToneMap;
+use rpeg::info::info;
+use rpeg::io::{set_quiet, Output};
+use rpeg::layout::{
+    FAST_CHROMA_LAYOUT, FINE_CHROMA_LAYOUT, FINE_TABLE_LAYOUT, NARROW_LAYOUT, WIDE_LAYOUT,
+};
+use rpeg::metrics::metrics;
+use rpeg::orientation::Orientation;
+use rpeg::porcelain::FileResult;
+use rpeg::preprocess::Rotation;
+use rpeg::refine::MAX_ERROR;
+use rpeg::reorient::reorient;
+use rpeg::roi::Region;
+use rpeg::serve::serve;
+use rpeg::signature::{keygen, sign, verify};
+use rpeg::stats::stats;
+use rpeg::sweep::{sweep, DEFAULT_QUALITIES};
+use rpeg::target::QualityTarget;
+use rpeg::thumbnail::thumb;
+use rpeg::tiles::Rect;
+use rpeg::tint::Tint;
+use rpeg::transcode::transcode;
+use rpeg::visualdiff::visualdiff;
+use rpeg::watch::watch;
+use rpeg::yuv::{compress_yuv, parse_size};
 use std::env;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const USAGE: &str =
+    "Usage: rpeg -d [--preview] [--region x,y,w,h] [--deblock] [--dither] [--fixed-point | --single-precision] [--max-memory bytes] [--output-gamma g] [--brightness b] [--contrast c] [--saturation s] [--tone-map none|clamp|reinhard] [--monochrome | --duotone dark,light] [--mmap] [--profile] [-o output.ppm|output.png [--force]] [filename]
+rpeg -c [--preset fast|balanced|best] [--progressive] [--optimize] [--wide | --fine-chroma | --chroma-table standard|fast|fine] [--chroma-weight w|priority] [--high-contrast | --luma-range r | --quality q | --two-pass] [--max-error n | --target-psnr db | --target-ssim s] [--perceptual] [--palette] [--detect-content] [--pad trim|replicate] [--max-dimension n] [--rotate 90|180|270] [--gamma g] [--grayscale] [--srgb] [--keep-orientation] [--roi x,y,w,h:quality]... [--tile-size n] [--fixed-point | --single-precision] [--deterministic] [--meta key=value]... [--icc profile.icc | --color-space srgb|display-p3|adobe-rgb|rec2020] [--embed-thumbnail] [--chunked | --compat csc411] [--min-ratio r [--ratio-policy warn|store|fail]] [--raw-below-psnr db] [--profile] [--report metrics.json] [--dump-stage cv|dct|quantized --dump-dir directory] [-o output [--force]] [filename]
+rpeg -c [compression flags] [--qualities 10,50,90] [--name-template {stem}-q{quality}.rpeg] [--on-error skip|abort|retry:N] [-o directory] [--force] image...
+rpeg compress --frames frame_%04d.ppm [--threshold n] [--motion] [--keyframe-interval n]
+rpeg compress --input-format yuv420p --size WxH [compression flags] [--threshold n] [--motion] [--keyframe-interval n] [-o output [--force]] [filename]
+rpeg decompress --frames out_%04d.ppm [filename]
+rpeg decompress --frame n [--dither] [-o frame.ppm [--force]] [filename]
+rpeg pack [compression flags] [-o archive [--force]] image.ppm...
+rpeg metrics [compression flags] [--report metrics.json|metrics.csv] image.ppm...
+rpeg sweep [compression flags] [--qualities 10,30,50,70,90] [--csv sweep.csv] [--gnuplot sweep.gp] image.ppm
+rpeg info [filename]
+rpeg thumb [-o thumb.ppm [--force]] [filename]
+rpeg stats [compression flags] image.ppm|file.rpeg
+rpeg diff a.rpeg b.rpeg
+rpeg transcode [--quality q | --luma-range r | --high-contrast] [--wide | --fine-chroma | --chroma-table standard|fast|fine] [--chroma-weight w] [-o output.rpeg [--force]] [filename]
+rpeg adjust --brightness b [-o output.rpeg [--force]] [filename]
+rpeg crop --rect x,y,w,h [-o output.rpeg [--force]] [filename]
+rpeg reorient [--rotate 90|180|270] [--flip horizontal|vertical] [-o output.rpeg [--force]] [filename]
+rpeg keygen -o secret.key [--force]
+rpeg sign --key secret.key [-o output.rpeg [--force]] [filename]
+rpeg verify --pubkey secret.key.pub [filename]
+rpeg convert [compression and decompression flags] [--force] input output.rpeg|output.png|output.ppm|output.pfm
+rpeg visualdiff [--block n] [--out heatmap.ppm [--force]] original.ppm decoded.ppm
+rpeg unpack [-o directory] [archive]
+rpeg archive add [compression flags] archive image.ppm|image.png|image.rpeg...
+rpeg archive remove archive name...
+rpeg archive list [archive]
+rpeg archive extract [-o directory] archive [name]...
+rpeg watch [compression flags] --out-dir directory directory
+rpeg serve [--host address] [--port n] [compression and decompression flags]
+rpeg completions bash|zsh|fish
+rpeg man
+rpeg doctor
+Every command accepts --json-errors to report failures as JSON on standard error, --threads n to limit the worker threads, and --quiet to print nothing but errors and requested results.
+-c, -d, and metrics accept --porcelain to print one tab-separated line per file on standard out.
+Defaults for --quality, --threads, --preset, and the output directory are read from rpeg.toml and RPEG_* variables.";
+
+/// Settings gathered from the flags following the subcommand.
+#[derive(Default)]
+struct Flags {
+    encoder_options: EncoderOptions,
+    decode_options: DecodeOptions,
+    output: Option<String>,
+    frames: Option<String>,
+    profile: bool,
+    mmap: bool,
+    quiet: bool,
+    porcelain: bool,
+    force: bool,
+    report: Option<String>,
+    quality: Option<u8>,
+    optimize: bool,
+    chroma_weight: Option<f64>,
+    qualities: Option<Vec<u8>>,
+    name_template: Option<NameTemplate>,
+    on_error: OnError,
+    csv: Option<String>,
+    gnuplot: Option<String>,
+    block: Option<usize>,
+    flip: Option<Orientation>,
+    rect: Option<Rect>,
+    key: Option<String>,
+    pubkey: Option<String>,
+    sequence_options: SequenceOptions,
+    frame: Option<usize>,
+    threads: Option<usize>,
+    yuv_size: Option<(u32, u32)>,
+    raw_yuv: bool,
+    dump_stage: Option<DumpStage>,
+    dump_dir: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    files: Vec<String>,
+}
+
+/// Set by `--json-errors`: report failures as JSON objects instead of plain messages.
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Prints `error` to standard error and exits with the status of its kind.
+fn exit_with(error: CliError) -> ! {
+    if JSON_ERRORS.load(Ordering::Relaxed) {
+        eprintln!("{}", error.to_json());
+    } else {
+        eprintln!("{error}");
+    }
+    std::process::exit(error.kind.exit_code())
+}
+
+/// Reports invalid arguments and exits.
+fn fail(message: &str) -> ! {
+    exit_with(CliError::new(ErrorKind::BadArguments, message))
+}
+
+/// Prints the `--porcelain` record of a file that was compressed or decompressed, if asked to.
+/// The record goes to standard out, which must not hold the file itself.
+fn report(result: Result<FileResult, CliError>, porcelain: bool) -> Result<(), CliError> {
+    let result = result?;
+    if porcelain {
+        print!("{}", result.to_porcelain());
+    }
+    Ok(())
+}
+
+/// Parses the flags following the subcommand. Anything that is not a flag is taken as a file,
+/// and an argument starting with `-` that no flag matches is rejected.
+fn parse_flags(args: &[String]) -> Flags {
+    let mut parsed = Flags::default();
+    // The last preset is applied before every other flag, so that the flags given with it
+    // override its settings whatever their order.
+    let preset = args.windows(2).rfind(|pair| pair[0] == "--preset");
+    if let Some(preset) = preset.and_then(|pair| Preset::from_name(&pair[1])) {
+        preset.apply(&mut parsed.encoder_options);
+    }
+    let mut flags = args.iter();
+    while let Some(arg) = flags.next() {
+        match arg.as_str() {
+            "--progressive" => parsed.encoder_options.progressive = true,
+            "--optimize" => {
+                parsed.optimize = true;
+                parsed.encoder_options.optimize = true;
+            }
+            "--wide" => parsed.encoder_options.layout = WIDE_LAYOUT,
+            "--fine-chroma" => parsed.encoder_options.layout = FINE_CHROMA_LAYOUT,
+            "--chroma-table" => {
+                parsed.encoder_options.layout = match flags.next().map(String::as_str) {
+                    Some("standard") => NARROW_LAYOUT,
+                    Some("fast") => FAST_CHROMA_LAYOUT,
+                    Some("fine") => FINE_TABLE_LAYOUT,
+                    _ => fail("--chroma-table expects standard, fast, or fine"),
+                }
+            }
+            "--chroma-weight" => match flags.next().map(String::as_str) {
+                Some("priority") => {
+                    parsed.encoder_options.chroma_priority = true;
+                    parsed.chroma_weight = None;
+                }
+                Some(text) => match text.parse::<f64>() {
+                    Ok(weight) if (0.25..=4.0).contains(&weight) => {
+                        parsed.encoder_options.chroma_priority = false;
+                        parsed.chroma_weight = Some(weight);
+                    }
+                    _ => fail("--chroma-weight expects a number between 0.25 and 4, or priority"),
+                },
+                None => fail("--chroma-weight expects a number between 0.25 and 4, or priority"),
+            },
+            "--preset" => {
+                if flags
+                    .next()
+                    .and_then(|name| Preset::from_name(name))
+                    .is_none()
+                {
+                    fail("--preset expects fast, balanced, or best");
+                }
+            }
+            "--high-contrast" => parsed.encoder_options.luma_range = Some(HIGH_CONTRAST_LUMA_RANGE),
+            "--max-error" => match flags.next().and_then(|text| text.parse::<u8>().ok()) {
+                Some(max_error) if max_error <= MAX_ERROR => {
+                    parsed.encoder_options.max_error = Some(max_error)
+                }
+                _ => fail(&format!(
+                    "--max-error expects a number from 0 to {MAX_ERROR}"
+                )),
+            },
+            "--target-psnr" => match flags.next().and_then(|text| text.parse::<f64>().ok()) {
+                Some(db) if QualityTarget::Psnr(db).check().is_ok() => {
+                    parsed.encoder_options.target = Some(QualityTarget::Psnr(db))
+                }
+                _ => fail("--target-psnr expects a positive PSNR in decibels, such as 40"),
+            },
+            "--target-ssim" => match flags.next().and_then(|text| text.parse::<f64>().ok()) {
+                Some(value) if QualityTarget::Ssim(value).check().is_ok() => {
+                    parsed.encoder_options.target = Some(QualityTarget::Ssim(value))
+                }
+                _ => fail("--target-ssim expects an SSIM above 0 and at most 1, such as 0.99"),
+            },
+            "--luma-range" => match flags.next().and_then(|text| text.parse::<f64>().ok()) {
+                Some(range) if (0.001..=0.5).contains(&range) => {
+                    parsed.encoder_options.luma_range = Some(range)
+                }
+                _ => fail("--luma-range expects a number between 0.001 and 0.5"),
+            },
+            "--quality" => match flags.next().and_then(|text| text.parse::<u8>().ok()) {
+                Some(quality) if quality <= 100 => {
+                    parsed.quality = Some(quality);
+                    parsed.encoder_options.luma_range = Some(luma_range_for_quality(quality))
+                }
+                _ => fail("--quality expects a number between 0 and 100"),
+            },
+            "--fixed-point" => {
+                parsed.encoder_options.arithmetic = Arithmetic::Fixed;
+                parsed.decode_options.arithmetic = Arithmetic::Fixed;
+            }
+            "--single-precision" => {
+                parsed.encoder_options.arithmetic = Arithmetic::Single;
+                parsed.decode_options.arithmetic = Arithmetic::Single;
+            }
+            "--two-pass" => parsed.encoder_options.two_pass = true,
+            "--perceptual" => parsed.encoder_options.perceptual = true,
+            "--palette" => parsed.encoder_options.palette = true,
+            "--detect-content" => parsed.encoder_options.detect_content = true,
+            "--pad" => match flags.next().and_then(|name| PadPolicy::from_name(name)) {
+                Some(pad) => parsed.encoder_options.pad = pad,
+                None => fail("--pad expects trim or replicate"),
+            },
+            "--min-ratio" => match flags.next().and_then(|text| text.parse::<f64>().ok()) {
+                Some(ratio) if ratio > 0.0 && ratio.is_finite() => {
+                    parsed.encoder_options.min_ratio = Some(ratio)
+                }
+                _ => fail("--min-ratio expects a positive ratio, such as 1.5"),
+            },
+            "--raw-below-psnr" => match flags.next().and_then(|text| text.parse::<f64>().ok()) {
+                Some(psnr) if psnr.is_finite() => {
+                    parsed.encoder_options.raw_below_psnr = Some(psnr)
+                }
+                _ => fail("--raw-below-psnr expects a PSNR in decibels, such as 20"),
+            },
+            "--ratio-policy" => match flags.next().and_then(|name| RatioPolicy::from_name(name)) {
+                Some(policy) => parsed.encoder_options.ratio_policy = policy,
+                None => fail("--ratio-policy expects warn, store, or fail"),
+            },
+            "--max-dimension" => match flags.next().and_then(|text| text.parse::<u32>().ok()) {
+                Some(max) if max > 0 => parsed.encoder_options.preprocess.max_dimension = Some(max),
+                _ => fail("--max-dimension expects a positive number of pixels"),
+            },
+            "--rotate" => match flags
+                .next()
+                .and_then(|text| text.parse::<u32>().ok())
+                .and_then(Rotation::from_degrees)
+                .filter(|rotation| *rotation != Rotation::None)
+            {
+                Some(rotation) => parsed.encoder_options.preprocess.rotation = rotation,
+                None => fail("--rotate expects 90, 180, or 270"),
+            },
+            "--flip" => match flags.next().map(String::as_str) {
+                Some("horizontal") => parsed.flip = Some(Orientation::Mirror),
+                Some("vertical") => parsed.flip = Some(Orientation::MirrorRotate180),
+                _ => fail("--flip expects horizontal or vertical"),
+            },
+            "--gamma" => match flags.next().and_then(|text| text.parse::<f64>().ok()) {
+                Some(gamma) if gamma.is_finite() && gamma > 0.0 => {
+                    parsed.encoder_options.preprocess.gamma = Some(gamma)
+                }
+                _ => fail("--gamma expects a positive number"),
+            },
+            "--grayscale" => parsed.encoder_options.preprocess.grayscale = true,
+            "--srgb" => parsed.encoder_options.srgb = true,
+            "--keep-orientation" => parsed.encoder_options.keep_orientation = true,
+            "--deterministic" => parsed.encoder_options.deterministic = true,
+            "--embed-thumbnail" => parsed.encoder_options.embed_thumbnail = true,
+            "--chunked" => parsed.encoder_options.chunked = true,
+            "--compat" => match flags.next().and_then(|name| Compat::from_name(name)) {
+                Some(compat) => parsed.encoder_options.compat = Some(compat),
+                None => fail("--compat expects csc411"),
+            },
+            "--profile" => parsed.profile = true,
+            "--mmap" => parsed.mmap = true,
+            "--quiet" => parsed.quiet = true,
+            "--porcelain" => parsed.porcelain = true,
+            "--force" => parsed.force = true,
+            "--json-errors" => {}
+            "--qualities" => {
+                let qualities = flags
+                    .next()
+                    .map(|text| {
+                        text.split(',')
+                            .map(|quality| quality.trim().parse::<u8>().ok().filter(|q| *q <= 100))
+                            .collect::<Option<Vec<u8>>>()
+                    })
+                    .unwrap_or_default()
+                    .filter(|qualities| !qualities.is_empty())
+                    .unwrap_or_else(|| {
+                        fail("--qualities expects qualities between 0 and 100, such as 10,50,90")
+                    });
+                parsed.qualities = Some(qualities);
+            }
+            "--name-template" => {
+                let template = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--name-template expects a template"));
+                match NameTemplate::parse(template) {
+                    Ok(template) => parsed.name_template = Some(template),
+                    Err(message) => fail(&message),
+                }
+            }
+            "--on-error" => {
+                let policy = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--on-error expects skip, abort, or retry:N"));
+                parsed.on_error = OnError::parse(policy).unwrap_or_else(|message| fail(&message));
+            }
+            "--csv" => {
+                let csv = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--csv expects a location"));
+                parsed.csv = Some(csv.clone());
+            }
+            "--gnuplot" => {
+                let gnuplot = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--gnuplot expects a location"));
+                parsed.gnuplot = Some(gnuplot.clone());
+            }
+            "--report" => {
+                let report = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--report expects a location such as metrics.json"));
+                parsed.report = Some(report.clone());
+            }
+            "--block" => match flags.next().and_then(|text| text.parse::<usize>().ok()) {
+                Some(block) if block > 0 => parsed.block = Some(block),
+                _ => fail("--block expects a positive number of pixels"),
+            },
+            "--meta" => {
+                let text = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--meta expects key=value"));
+                let entry = parse_metadata(text).unwrap_or_else(|message| fail(&message));
+                parsed.encoder_options.metadata.push(entry);
+            }
+            "--icc" => {
+                let filename = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--icc expects an ICC profile"));
+                let profile = std::fs::read(filename)
+                    .unwrap_or_else(|error| fail(&format!("Failed to read {filename}: {error}")));
+                if profile.len() > MAX_ICC_PROFILE_LEN {
+                    fail(&format!(
+                        "ICC profiles are limited to {MAX_ICC_PROFILE_LEN} bytes"
+                    ));
+                }
+                let metadata = &parsed.encoder_options.metadata;
+                parsed.encoder_options.metadata = ColorTag::Icc(profile).replace_in(metadata);
+            }
+            "--color-space" => match flags.next().and_then(|name| ColorSpace::from_name(name)) {
+                Some(space) => {
+                    let metadata = &parsed.encoder_options.metadata;
+                    parsed.encoder_options.metadata = ColorTag::Space(space).replace_in(metadata);
+                }
+                None => fail("--color-space expects srgb, display-p3, adobe-rgb, or rec2020"),
+            },
+            "--preview" => parsed.decode_options.preview = true,
+            "--deblock" => parsed.decode_options.deblock = true,
+            "--dither" => parsed.decode_options.dither = true,
+            "--output-gamma" | "--brightness" | "--contrast" | "--saturation" => {
+                let value = flags.next().and_then(|text| text.parse::<f64>().ok());
+                let adjustment = match (arg.as_str(), value) {
+                    ("--output-gamma", Some(gamma)) => Adjustment::Gamma(gamma),
+                    ("--brightness", Some(brightness)) => Adjustment::Brightness(brightness),
+                    ("--contrast", Some(contrast)) => Adjustment::Contrast(contrast),
+                    ("--saturation", Some(saturation)) => Adjustment::Saturation(saturation),
+                    _ => fail(&format!("{arg} expects a number")),
+                };
+                adjustment.check().unwrap_or_else(|message| fail(&message));
+                parsed.decode_options.adjustments.push(adjustment);
+            }
+            "--tone-map" => match flags.next().and_then(|name| ToneMap::from_name(name)) {
+                Some(tone_map) => parsed.decode_options.tone_map = tone_map,
+                None => fail("--tone-map expects none, clamp, or reinhard"),
+            },
+            "--monochrome" => parsed.decode_options.tint = Some(Tint::Monochrome),
+            "--duotone" => {
+                let colors = flags.next().unwrap_or_else(|| {
+                    fail("--duotone expects two hex colors, such as 1a2b3c,ffe0a0")
+                });
+                parsed.decode_options.tint =
+                    Some(Tint::parse_duotone(colors).unwrap_or_else(|message| fail(&message)));
+            }
+            "--max-memory" => match flags.next().and_then(|text| text.parse::<u64>().ok()) {
+                Some(bytes) => parsed.decode_options.max_memory = Some(bytes),
+                None => fail("--max-memory expects a number of bytes"),
+            },
+            "--roi" => {
+                let text = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--roi expects x,y,w,h:quality"));
+                let region = Region::parse(text).unwrap_or_else(|message| fail(&message));
+                parsed.encoder_options.regions.push(region);
+            }
+            "--tile-size" => match flags.next().and_then(|text| text.parse::<u32>().ok()) {
+                Some(size) if size > 0 && size.is_multiple_of(2) => {
+                    parsed.encoder_options.tile_size = size
+                }
+                _ => fail("--tile-size expects a positive even number of pixels"),
+            },
+            "--region" => {
+                let text = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--region expects x,y,w,h"));
+                let region = Rect::parse(text).unwrap_or_else(|message| fail(&message));
+                parsed.decode_options.region = Some(region);
+            }
+            "--rect" => {
+                let text = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--rect expects x,y,w,h"));
+                parsed.rect = Some(Rect::parse(text).unwrap_or_else(|message| fail(&message)));
+            }
+            "--key" => {
+                let key = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--key expects a secret key file"));
+                parsed.key = Some(key.clone());
+            }
+            "--pubkey" => {
+                let pubkey = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--pubkey expects a public key file"));
+                parsed.pubkey = Some(pubkey.clone());
+            }
+            "--frames" => {
+                let pattern = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--frames expects a pattern such as frame_%04d.ppm"));
+                parsed.frames = Some(pattern.clone());
+            }
+            "--motion" => parsed.sequence_options.motion = true,
+            "--dump-stage" => match flags.next().and_then(|name| DumpStage::from_name(name)) {
+                Some(stage) => parsed.dump_stage = Some(stage),
+                None => fail("--dump-stage expects cv, dct, or quantized"),
+            },
+            "--dump-dir" => {
+                let dump_dir = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--dump-dir expects a directory"));
+                parsed.dump_dir = Some(dump_dir.clone());
+            }
+            "--input-format" => match flags.next().map(String::as_str) {
+                Some("yuv420p") => parsed.raw_yuv = true,
+                _ => fail("--input-format expects yuv420p"),
+            },
+            "--size" => {
+                let text = flags.next().unwrap_or_else(|| fail("--size expects WxH"));
+                parsed.yuv_size = Some(parse_size(text).unwrap_or_else(|message| fail(&message)));
+            }
+            "--keyframe-interval" => match flags.next().and_then(|text| text.parse::<usize>().ok())
+            {
+                Some(interval) if interval > 0 => {
+                    parsed.sequence_options.keyframe_interval = Some(interval)
+                }
+                _ => fail("--keyframe-interval expects a positive number of frames"),
+            },
+            "--frame" => match flags.next().and_then(|text| text.parse::<usize>().ok()) {
+                Some(frame) => parsed.frame = Some(frame),
+                None => fail("--frame expects a frame number"),
+            },
+            "--threshold" => match flags.next().and_then(|text| text.parse::<u64>().ok()) {
+                Some(threshold) => parsed.sequence_options.threshold = threshold,
+                None => fail("--threshold expects a non-negative number"),
+            },
+            "--host" => {
+                let host = flags
+                    .next()
+                    .unwrap_or_else(|| fail("--host expects an address"));
+                parsed.host = Some(host.clone());
+            }
+            "--threads" => match flags.next().and_then(|text| text.parse::<usize>().ok()) {
+                Some(threads) if threads > 0 => parsed.threads = Some(threads),
+                _ => fail("--threads expects a positive number"),
+            },
+            "--port" => match flags.next().and_then(|text| text.parse::<u16>().ok()) {
+                Some(port) => parsed.port = Some(port),
+                None => fail("--port expects a port number"),
+            },
+            "-o" | "--output" | "--out" | "--out-dir" => {
+                let output = flags
+                    .next()
+                    .unwrap_or_else(|| fail("-o expects a location"));
+                parsed.output = Some(output.clone());
+            }
+            flag if flag.starts_with('-') => fail(&format!("Unknown flag {flag}\n{USAGE}")),
+            _ => parsed.files.push(arg.clone()),
+        }
+    }
+    // Applied once every flag is read, so that the layout flags do not reset the weight.
+    if let Some(weight) = parsed.chroma_weight {
+        parsed.encoder_options.layout = parsed.encoder_options.layout.with_chroma_weight(weight);
+    }
+    // A preset only turns the rounding optimization on where the arithmetic supports it.
+    if !parsed.optimize && parsed.encoder_options.arithmetic != Arithmetic::Float {
+        parsed.encoder_options.optimize = false;
+    }
+    if parsed.encoder_options.optimize && parsed.encoder_options.arithmetic != Arithmetic::Float {
+        fail("--optimize is only available with double precision floating point arithmetic");
+    }
+    if parsed.encoder_options.perceptual && parsed.encoder_options.arithmetic == Arithmetic::Fixed {
+        fail("--perceptual is only available with floating point arithmetic");
+    }
+    if parsed.encoder_options.two_pass && parsed.encoder_options.luma_range.is_some() {
+        fail("--two-pass picks the luma range itself");
+    }
+    if parsed.encoder_options.target.is_some() && parsed.encoder_options.max_error.is_some() {
+        fail("--target-psnr and --target-ssim pick the max error themselves");
+    }
+    parsed
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let argnum = args.len();
-    assert!(argnum == 2 || argnum == 3);
-    let filename = args.iter().nth(2).unwrap();
-    match args[1].as_str() {
-        "-c" => compress(Some(filename)),
-        "-d" => decompress(Some(filename)),
-        _ => {
-            eprintln!("Usage: rpeg -d [filename]\nrpeg -c [filename]")
+    // Read ahead of the other flags, so that their own errors are reported as JSON too.
+    JSON_ERRORS.store(
+        args.iter().any(|arg| arg == "--json-errors"),
+        Ordering::Relaxed,
+    );
+    let config = Config::load().unwrap_or_else(|message| fail(&message));
+    let command_line = args.get(2..).unwrap_or_default();
+    let mut with_defaults = config.flags(command_line);
+    with_defaults.extend_from_slice(command_line);
+    let mut flags = parse_flags(&with_defaults);
+    if let Some(threads) = flags.threads {
+        // Only fails if a pool was already built, which nothing does before this point.
+        let _ = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global();
+    }
+    let out_dir = flags.output.as_deref().or(config.output_dir.as_deref());
+    set_quiet(flags.quiet);
+    let single_file = flags.frames.is_none() && flags.frame.is_none() && flags.files.len() <= 1;
+    let porcelain_to_stdout = matches!(
+        args.get(1).map(String::as_str),
+        Some("-c" | "compress" | "-d" | "decompress")
+    ) && single_file
+        && flags.name_template.is_none()
+        && flags.qualities.is_none()
+        && flags.output.is_none();
+    if flags.porcelain && porcelain_to_stdout {
+        fail("--porcelain prints its records on standard out, so the output needs -o");
+    }
+    let filename = flags.files.first().map(String::as_str);
+    let output = Output {
+        filename: flags.output.clone(),
+        force: flags.force,
+    };
+    let single_or_batch = flags.frames.is_none() && !flags.raw_yuv;
+    if matches!(args.get(1).map(String::as_str), Some("-c" | "compress")) && single_or_batch {
+        // Lets an interrupted compression remove its partial output before exiting.
+        flags.encoder_options.cancel = Some(on_signals());
+    }
+    let result = match args.get(1).map(String::as_str) {
+        Some("-c" | "compress") => match &flags.frames {
+            Some(pattern) => compress_sequence(pattern, &flags.sequence_options),
+            None if flags.raw_yuv => match flags.yuv_size {
+                Some(size) => compress_yuv(
+                    filename,
+                    size,
+                    &output,
+                    &flags.encoder_options,
+                    &flags.sequence_options,
+                ),
+                None => fail("--input-format yuv420p needs the --size of the frames"),
+            },
+            None if flags.name_template.is_some()
+                || flags.qualities.is_some()
+                || flags.files.len() > 1 =>
+            {
+                if flags.report.is_some() || flags.dump_stage.is_some() {
+                    fail("--report and --dump-stage take a single image");
+                }
+                let filenames: Vec<&str> = flags.files.iter().map(String::as_str).collect();
+                let default_template = || NameTemplate::parse(DEFAULT_NAME_TEMPLATE).unwrap();
+                let qualities = flags.qualities.clone().or(flags.quality.map(|q| vec![q]));
+                compress_batch(
+                    &filenames,
+                    &flags.name_template.clone().unwrap_or_else(default_template),
+                    Path::new(out_dir.unwrap_or(".")),
+                    qualities.as_deref(),
+                    &flags.encoder_options,
+                    flags.force,
+                    flags.porcelain,
+                    flags.on_error,
+                )
+            }
+            None => match (flags.dump_stage, &flags.dump_dir) {
+                (None, None) => report(
+                    compress(
+                        filename,
+                        &output,
+                        &flags.encoder_options,
+                        flags.profile,
+                        flags.report.as_deref(),
+                    ),
+                    flags.porcelain,
+                ),
+                (Some(stage), Some(dump_dir)) => {
+                    dump_stage(filename, stage, dump_dir, &flags.encoder_options).and_then(|_| {
+                        report(
+                            compress(
+                                filename,
+                                &output,
+                                &flags.encoder_options,
+                                flags.profile,
+                                flags.report.as_deref(),
+                            ),
+                            flags.porcelain,
+                        )
+                    })
+                }
+                _ => fail("--dump-stage and --dump-dir go together"),
+            },
+        },
+        Some("-d" | "decompress") => match &flags.frames {
+            Some(pattern) => decompress_sequence(filename, pattern),
+            None if flags.frame.is_some() => decompress_frame(
+                filename,
+                flags.frame.unwrap_or_default(),
+                &output,
+                &flags.decode_options,
+            ),
+            None if flags.mmap && filename.is_none() => fail("--mmap needs a file to map"),
+            None => report(
+                decompress(
+                    filename,
+                    &output,
+                    &flags.decode_options,
+                    flags.profile,
+                    flags.mmap,
+                ),
+                flags.porcelain,
+            ),
+        },
+        Some("pack") => {
+            if flags.files.is_empty() {
+                fail("pack expects at least one image");
+            }
+            let filenames: Vec<&str> = flags.files.iter().map(String::as_str).collect();
+            pack(&filenames, &output, &flags.encoder_options)
+        }
+        Some("metrics") => {
+            if flags.files.is_empty() {
+                fail("metrics expects at least one image");
+            }
+            let filenames: Vec<&str> = flags.files.iter().map(String::as_str).collect();
+            metrics(
+                &filenames,
+                &flags.encoder_options,
+                flags.report.as_deref(),
+                flags.porcelain,
+            )
+        }
+        Some("sweep") => {
+            let Some(filename) = filename else {
+                fail("sweep expects an image");
+            };
+            sweep(
+                filename,
+                flags.qualities.as_deref().unwrap_or(&DEFAULT_QUALITIES),
+                &flags.encoder_options,
+                flags.csv.as_deref(),
+                flags.gnuplot.as_deref(),
+            )
+        }
+        Some("info") => info(filename),
+        Some("thumb") => thumb(filename, &output),
+        Some("stats") => {
+            let Some(filename) = filename else {
+                fail("stats expects an image or a compressed image");
+            };
+            stats(filename, &flags.encoder_options)
+        }
+        Some("diff") => match flags.files.as_slice() {
+            [first, second] => diff(first, second).map(|identical| {
+                if !identical {
+                    std::process::exit(1);
+                }
+            }),
+            _ => fail("diff expects two compressed images"),
+        },
+        Some("visualdiff") => match flags.files.as_slice() {
+            [original, decoded] => visualdiff(original, decoded, &output, flags.block),
+            _ => fail("visualdiff expects the original and the decoded image"),
+        },
+        Some("transcode") => transcode(filename, &output, &flags.encoder_options),
+        Some("adjust") => adjust_compressed(filename, &output, &flags.decode_options.adjustments),
+        Some("crop") => crop(filename, &output, flags.rect),
+        Some("reorient") => {
+            let rotation = Orientation::from(flags.encoder_options.preprocess.rotation);
+            let steps: Vec<Orientation> = [rotation].into_iter().chain(flags.flip).collect();
+            reorient(filename, &output, &steps)
+        }
+        Some("keygen") => keygen(&output),
+        Some("sign") => sign(filename, &output, flags.key.as_deref()),
+        Some("verify") => verify(filename, flags.pubkey.as_deref()),
+        Some("convert") => match flags.files.as_slice() {
+            [input, converted] => convert(
+                input,
+                &Output {
+                    filename: Some(converted.clone()),
+                    force: flags.force,
+                },
+                &flags.encoder_options,
+                &flags.decode_options,
+            ),
+            _ => fail("convert expects an input and an output image"),
+        },
+        Some("unpack") => unpack(filename, out_dir.unwrap_or(".")),
+        Some("archive") => {
+            let names: Vec<&str> = flags.files.iter().skip(2).map(String::as_str).collect();
+            match flags.files.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+                ["add", archive, _, ..] => archive_add(archive, &names, &flags.encoder_options),
+                ["remove", archive, _, ..] => archive_remove(archive, &names),
+                ["list"] => archive_list(None),
+                ["list", archive] => archive_list(Some(archive)),
+                ["extract", archive, ..] => {
+                    archive_extract(Some(archive), &names, out_dir.unwrap_or("."))
+                }
+                _ => fail("archive expects add, remove, list, or extract and an archive"),
+            }
+        }
+        Some("watch") => {
+            let (Some(source_dir), Some(out_dir)) = (filename, out_dir) else {
+                fail("watch expects a directory and --out-dir");
+            };
+            watch(
+                Path::new(source_dir),
+                Path::new(out_dir),
+                &flags.encoder_options,
+            )
+            .map_err(|message| CliError::new(ErrorKind::Io, message))
+        }
+        Some("serve") => {
+            let host = flags.host.as_deref().unwrap_or("127.0.0.1");
+            let address = format!("{host}:{}", flags.port.unwrap_or(8080));
+            serve(&address, &flags.encoder_options, &flags.decode_options)
+                .map_err(|message| CliError::new(ErrorKind::Io, message))
+        }
+        Some("completions") => match filename.and_then(Shell::from_name) {
+            Some(shell) => output
+                .write(completions(USAGE, shell).as_bytes())
+                .map_err(|message| CliError::new(ErrorKind::Io, message)),
+            None => fail("completions expects bash, zsh, or fish"),
+        },
+        Some("doctor") => doctor(),
+        Some("man") => output
+            .write(man_page(USAGE, env!("CARGO_PKG_VERSION")).as_bytes())
+            .map_err(|message| CliError::new(ErrorKind::Io, message)),
+        _ => Err(CliError::new(ErrorKind::BadArguments, USAGE)),
+    };
+    if let Err(error) = result {
+        exit_with(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the flags parsed from `args`, separated by spaces.
+    fn flags(args: &str) -> Flags {
+        let args: Vec<String> = args.split(' ').map(String::from).collect();
+        parse_flags(&args)
+    }
+
+    #[test]
+    fn flags_override_the_preset_whatever_their_order() {
+        for args in ["--wide --preset balanced", "--preset balanced --wide"] {
+            assert_eq!(flags(args).encoder_options.layout, WIDE_LAYOUT, "{args}");
+        }
+        for args in [
+            "--tile-size 8 --preset balanced",
+            "--preset balanced --tile-size 8",
+        ] {
+            assert_eq!(flags(args).encoder_options.tile_size, 8, "{args}");
+        }
+        // Builds defaulting to another arithmetic exit on --optimize.
+        if Arithmetic::default() == Arithmetic::Float {
+            for args in ["--optimize --preset fast", "--preset fast --optimize"] {
+                let options = flags(args).encoder_options;
+                assert!(options.optimize && options.tile_size == 256, "{args}");
+            }
+        }
+        for args in [
+            "--chroma-table standard --preset best",
+            "--preset best --chroma-weight 2",
+        ] {
+            assert_ne!(
+                flags(args).encoder_options.layout,
+                FINE_TABLE_LAYOUT,
+                "{args}"
+            );
         }
+        let options = flags("--preset fast --preset best").encoder_options;
+        assert_eq!((options.layout, options.tile_size), (FINE_TABLE_LAYOUT, 0));
+        let options = flags("--preset best --fixed-point").encoder_options;
+        assert!(!options.optimize && options.arithmetic == Arithmetic::Fixed);
     }
 }
@@ -0,0 +1,477 @@
+use crate::codec::cache::CoefficientCache;
+use crate::codec::{
+    check_encodable, compress_cached, compress_image, decompress_image, EncoderOptions,
+};
+use crate::dct_coeff::DEFAULT_LUMA_RANGE;
+use crate::error::{CliError, ErrorKind};
+use crate::porcelain::record;
+use crate::ppm::RgbImage;
+use std::time::Instant;
+
+/// Returns the channels of `pixel` scaled to 0..255 from the denominator of `image`.
+fn channels(image: &RgbImage, index: usize) -> [f64; 3] {
+    let pixel = &image.pixels[index];
+    let scale = 255.0 / image.denominator.max(1) as f64;
+    [pixel.red, pixel.green, pixel.blue].map(|value| value as f64 * scale)
+}
+
+/// Returns the width and height of the area both images cover: unless they were padded with
+/// `PadPolicy::Replicate`, the decoder drops the last row and column of images with odd
+/// dimensions.
+fn common_size(original: &RgbImage, decoded: &RgbImage) -> (usize, usize) {
+    (
+        original.width.min(decoded.width) as usize,
+        original.height.min(decoded.height) as usize,
+    )
+}
+
+/// Returns the mean squared error between two images, over the red, green, and blue channels
+/// scaled to 0..255, and over the area both images cover.
+///
+/// # Arguments
+/// * `original`: Reference image
+/// * `decoded`: Image compared to the reference
+pub fn mse(original: &RgbImage, decoded: &RgbImage) -> f64 {
+    let (width, height) = common_size(original, decoded);
+    if width == 0 || height == 0 {
+        return 0.0;
+    }
+    let mut sum = 0.0;
+    for row in 0..height {
+        for col in 0..width {
+            let a = channels(original, row * original.width as usize + col);
+            let b = channels(decoded, row * decoded.width as usize + col);
+            sum += (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum::<f64>();
+        }
+    }
+    sum / (width * height * 3) as f64
+}
+
+/// Returns the peak signal-to-noise ratio between two images in decibels, from their mean
+/// squared error, or infinity for identical images.
+///
+/// # Arguments
+/// * `original`: Reference image
+/// * `decoded`: Image compared to the reference
+pub fn psnr(original: &RgbImage, decoded: &RgbImage) -> f64 {
+    let error = mse(original, decoded);
+    if error == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / error).log10()
+    }
+}
+
+/// Returns the luma of every pixel of the area `width` x `height` of `image`, on 0..255.
+fn luma(image: &RgbImage, width: usize, height: usize) -> Vec<f64> {
+    (0..height)
+        .flat_map(|row| (0..width).map(move |col| row * image.width as usize + col))
+        .map(|index| {
+            let [red, green, blue] = channels(image, index);
+            0.299 * red + 0.587 * green + 0.114 * blue
+        })
+        .collect()
+}
+
+/// Side of the windows the structural similarity is computed over.
+const SSIM_WINDOW: usize = 8;
+
+/// Returns the mean structural similarity (SSIM) of the luma of two images, between -1 and 1,
+/// with 1 for identical images. It is computed over 8x8 windows, 4 pixels apart, or over the
+/// whole image if it is smaller.
+///
+/// # Arguments
+/// * `original`: Reference image
+/// * `decoded`: Image compared to the reference
+pub fn ssim(original: &RgbImage, decoded: &RgbImage) -> f64 {
+    let (width, height) = common_size(original, decoded);
+    if width == 0 || height == 0 {
+        return 1.0;
+    }
+    let (a, b) = (luma(original, width, height), luma(decoded, width, height));
+    let (c1, c2) = ((0.01 * 255.0_f64).powi(2), (0.03 * 255.0_f64).powi(2));
+    let (window_width, window_height) = (SSIM_WINDOW.min(width), SSIM_WINDOW.min(height));
+    let starts = |size: usize, window: usize| (0..=size - window).step_by(SSIM_WINDOW / 2);
+    let mut total = 0.0;
+    let mut windows = 0;
+    for top in starts(height, window_height) {
+        for left in starts(width, window_width) {
+            let indices = (top..top + window_height)
+                .flat_map(|row| (left..left + window_width).map(move |col| row * width + col));
+            let count = (window_width * window_height) as f64;
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+                (0.0, 0.0, 0.0, 0.0, 0.0);
+            for index in indices {
+                let (x, y) = (a[index], b[index]);
+                sum_a += x;
+                sum_b += y;
+                sum_aa += x * x;
+                sum_bb += y * y;
+                sum_ab += x * y;
+            }
+            let (mean_a, mean_b) = (sum_a / count, sum_b / count);
+            let variance_a = sum_aa / count - mean_a * mean_a;
+            let variance_b = sum_bb / count - mean_b * mean_b;
+            let covariance = sum_ab / count - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + c1) * (2.0 * covariance + c2))
+                / ((mean_a * mean_a + mean_b * mean_b + c1) * (variance_a + variance_b + c2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// ## Size, quality, and speed of the compression of one image
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::codec::EncoderOptions;
+/// use rpeg::metrics::FileMetrics;
+/// use rpeg::ppm::{Rgb, RgbImage};
+///
+/// let image = RgbImage {
+///     pixels: vec![Rgb { red: 120, green: 110, blue: 100 }; 64],
+///     width: 8,
+///     height: 8,
+///     denominator: 255,
+/// };
+/// let (compressed, metrics) = FileMetrics::measure("flat", &image, 200, &EncoderOptions::default());
+/// assert_eq!(metrics.output_bytes, compressed.len());
+/// assert!(metrics.psnr > 20.0 && metrics.ssim > 0.9);
+/// ```
+pub struct FileMetrics {
+    /// Name of the image.
+    pub file: String,
+    /// Size of the image before compression, in bytes.
+    pub input_bytes: usize,
+    /// Size of the compressed image, in bytes.
+    pub output_bytes: usize,
+    /// Input size divided by output size.
+    pub ratio: f64,
+    /// Peak signal-to-noise ratio of the decoded image, in decibels.
+    pub psnr: f64,
+    /// Structural similarity of the decoded image.
+    pub ssim: f64,
+    /// Time spent compressing the image, in seconds.
+    pub seconds: f64,
+    /// Settings the image was compressed with, as a JSON object.
+    pub settings: String,
+}
+
+impl FileMetrics {
+    /// Compresses an image, decodes it back to measure its quality, and returns the compressed
+    /// image along with its metrics.
+    ///
+    /// # Arguments
+    /// * `file`: Name of the image in the report
+    /// * `image`: Image to compress
+    /// * `input_bytes`: Size of the image before compression, such as the size of its file
+    /// * `options`: Settings used to compress the image
+    pub fn measure(
+        file: &str,
+        image: &RgbImage,
+        input_bytes: usize,
+        options: &EncoderOptions,
+    ) -> (Vec<u8>, FileMetrics) {
+        let start = Instant::now();
+        let compressed = compress_image(image, options);
+        let seconds = start.elapsed().as_secs_f64();
+        let metrics = FileMetrics::of_compressed(file, image, input_bytes, &compressed, options);
+        (compressed, FileMetrics { seconds, ..metrics })
+    }
+
+    /// Compresses an image like `measure`, reusing the transforms of `cache`, which must fit
+    /// `options`. The time spent building the cache is left out of `seconds`.
+    ///
+    /// # Arguments
+    /// * `file`: Name of the image in the report
+    /// * `cache`: Image to compress, along with its transforms
+    /// * `input_bytes`: Size of the image before compression, such as the size of its file
+    /// * `options`: Settings used to compress the image
+    pub fn measure_cached(
+        file: &str,
+        cache: &CoefficientCache,
+        input_bytes: usize,
+        options: &EncoderOptions,
+    ) -> (Vec<u8>, FileMetrics) {
+        let start = Instant::now();
+        let (compressed, _) = compress_cached(cache, options);
+        let seconds = start.elapsed().as_secs_f64();
+        let metrics =
+            FileMetrics::of_compressed(file, cache.input(), input_bytes, &compressed, options);
+        (compressed, FileMetrics { seconds, ..metrics })
+    }
+
+    /// Returns the metrics of an image compressed beforehand, with no compression time.
+    ///
+    /// # Arguments
+    /// * `file`: Name of the image in the report
+    /// * `image`: Image before compression, measured after the pre-processing steps of `options`
+    /// * `input_bytes`: Size of the image before compression, such as the size of its file
+    /// * `compressed`: Compressed image
+    /// * `options`: Settings the image was compressed with
+    pub fn of_compressed(
+        file: &str,
+        image: &RgbImage,
+        input_bytes: usize,
+        compressed: &[u8],
+        options: &EncoderOptions,
+    ) -> FileMetrics {
+        let decoded = decompress_image(compressed).expect("rpeg decodes its own output");
+        let image = &*options.preprocess.apply(image);
+        FileMetrics {
+            file: file.to_string(),
+            input_bytes,
+            output_bytes: compressed.len(),
+            ratio: input_bytes as f64 / compressed.len().max(1) as f64,
+            psnr: psnr(image, &decoded),
+            ssim: ssim(image, &decoded),
+            seconds: 0.0,
+            settings: settings_json(options),
+        }
+    }
+}
+
+/// Returns the settings of `options` as a JSON object.
+fn settings_json(options: &EncoderOptions) -> String {
+    format!(
+        r#"{{"progressive":{},"tile_size":{},"optimize":{},"luma_range":{},"layout":{},"arithmetic":"{}","regions":{},"deterministic":{},"two_pass":{},"perceptual":{},"palette":{},"detect_content":{},"pad":"{}","max_dimension":{},"rotation":{},"gamma":{},"grayscale":{},"srgb":{},"keep_orientation":{},"compat":{},"min_ratio":{},"ratio_policy":"{}","raw_below_psnr":{}}}"#,
+        options.progressive,
+        options.tile_size,
+        options.optimize,
+        options.luma_range.unwrap_or(DEFAULT_LUMA_RANGE),
+        options.layout.id,
+        format!("{:?}", options.arithmetic).to_lowercase(),
+        options.regions.len(),
+        options.deterministic,
+        options.two_pass,
+        options.perceptual,
+        options.palette,
+        options.detect_content,
+        format!("{:?}", options.pad).to_lowercase(),
+        options
+            .preprocess
+            .max_dimension
+            .map_or("null".to_string(), |max| max.to_string()),
+        options.preprocess.rotation.degrees(),
+        options
+            .preprocess
+            .gamma
+            .map_or("null".to_string(), json_number),
+        options.preprocess.grayscale,
+        options.srgb,
+        options.keep_orientation,
+        options
+            .compat
+            .map_or("null".to_string(), |compat| format!(r#""{compat:?}""#)
+                .to_lowercase()),
+        options.min_ratio.map_or("null".to_string(), json_number),
+        format!("{:?}", options.ratio_policy).to_lowercase(),
+        options
+            .raw_below_psnr
+            .map_or("null".to_string(), json_number)
+    )
+}
+
+/// Returns `value` as a JSON number, or null for infinities, which JSON cannot hold.
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+/// Returns `text` as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for character in text.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            character if (character as u32) < 0x20 => {
+                quoted.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => quoted.push(character),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Returns `text` as a CSV field, quoted if it holds a comma, a quote, or a line break.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Returns `reports` as a JSON array with one object per image.
+///
+/// # Arguments
+/// * `reports`: Metrics of every image
+pub fn to_json(reports: &[FileMetrics]) -> String {
+    let objects: Vec<String> = reports
+        .iter()
+        .map(|report| {
+            format!(
+                r#"  {{"file":{},"input_bytes":{},"output_bytes":{},"ratio":{},"psnr":{},"ssim":{},"seconds":{},"settings":{}}}"#,
+                json_string(&report.file),
+                report.input_bytes,
+                report.output_bytes,
+                json_number(report.ratio),
+                json_number(report.psnr),
+                json_number(report.ssim),
+                json_number(report.seconds),
+                report.settings
+            )
+        })
+        .collect();
+    format!("[\n{}\n]\n", objects.join(",\n"))
+}
+
+/// Returns `reports` as CSV with a header row and one row per image. The settings are kept as
+/// a JSON object in the last column.
+///
+/// # Arguments
+/// * `reports`: Metrics of every image
+pub fn to_csv(reports: &[FileMetrics]) -> String {
+    let mut csv = String::from("file,input_bytes,output_bytes,ratio,psnr,ssim,seconds,settings\n");
+    for report in reports {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{}\n",
+            csv_field(&report.file),
+            report.input_bytes,
+            report.output_bytes,
+            report.ratio,
+            report.psnr,
+            report.ssim,
+            report.seconds,
+            csv_field(&report.settings)
+        ));
+    }
+    csv
+}
+
+/// Returns `reports` as `--porcelain` records, one line of tab-separated fields per image: the
+/// file, the input and output sizes in bytes, the ratio, the PSNR, the SSIM, and the time in
+/// seconds.
+///
+/// # Arguments
+/// * `reports`: Metrics of every image
+pub fn to_porcelain(reports: &[FileMetrics]) -> String {
+    reports
+        .iter()
+        .map(|report| {
+            record(&[
+                report.file.clone(),
+                report.input_bytes.to_string(),
+                report.output_bytes.to_string(),
+                format!("{:.3}", report.ratio),
+                format!("{:.3}", report.psnr),
+                format!("{:.6}", report.ssim),
+                format!("{:.6}", report.seconds),
+            ])
+        })
+        .collect()
+}
+
+/// Writes `reports` to `filename`, as CSV if its extension is `csv` and as JSON otherwise.
+///
+/// # Arguments
+/// * `filename`: Location of the report
+/// * `reports`: Metrics of every image
+pub fn write_report(filename: &str, reports: &[FileMetrics]) -> Result<(), String> {
+    let contents = if filename.to_ascii_lowercase().ends_with(".csv") {
+        to_csv(reports)
+    } else {
+        to_json(reports)
+    };
+    std::fs::write(filename, contents)
+        .map_err(|error| format!("Failed to write {filename}: {error}"))
+}
+
+/// Returns the size of the file holding an image, or the size of its pixels at one byte per
+/// channel for standard in.
+///
+/// # Arguments
+/// * `filename`: Location of the image, or None if it was read from standard in
+/// * `image`: Image read from that location
+pub fn input_size(filename: Option<&str>, image: &RgbImage) -> usize {
+    filename
+        .and_then(|filename| std::fs::metadata(filename).ok())
+        .map_or(image.pixels.len() * 3, |metadata| metadata.len() as usize)
+}
+
+/// Compresses every image in `filenames` in memory and reports its metrics to `report`, or to
+/// standard out as JSON, or as `--porcelain` records.
+///
+/// # Arguments
+/// * `filenames`: Locations of the PPM images within your disk
+/// * `options`: Settings used to compress every image
+/// * `report`: Location of the JSON or CSV report, or None to print to standard out
+/// * `porcelain`: Print tab-separated records to standard out instead of JSON
+pub fn metrics(
+    filenames: &[&str],
+    options: &EncoderOptions,
+    report: Option<&str>,
+    porcelain: bool,
+) -> Result<(), CliError> {
+    check_encodable(options)?;
+    let mut reports = Vec::new();
+    for filename in filenames {
+        let image = RgbImage::read(Some(filename))
+            .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+        let input_bytes = input_size(Some(filename), &image);
+        reports.push(FileMetrics::measure(filename, &image, input_bytes, options).1);
+    }
+    match report {
+        Some(report) => write_report(report, &reports),
+        None if porcelain => crate::io::write_output(to_porcelain(&reports).as_bytes(), None),
+        None => crate::io::write_output(to_json(&reports).as_bytes(), None),
+    }
+    .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppm::Rgb;
+
+    fn image(shift: u16) -> RgbImage {
+        RgbImage {
+            pixels: (0..256u16)
+                .map(|i| Rgb {
+                    red: (i + shift).min(255),
+                    green: i / 2,
+                    blue: 255 - i,
+                })
+                .collect(),
+            width: 16,
+            height: 16,
+            denominator: 255,
+        }
+    }
+
+    #[test]
+    fn identical_images_are_perfect() {
+        assert_eq!(psnr(&image(0), &image(0)), f64::INFINITY);
+        assert!((ssim(&image(0), &image(0)) - 1.0).abs() < 1e-12);
+        assert!(mse(&image(0), &image(3)) > 0.0);
+        assert!(ssim(&image(0), &image(40)) < 1.0);
+    }
+
+    #[test]
+    fn reports_hold_every_image() {
+        let (_, metrics) = FileMetrics::measure("a,b", &image(0), 783, &EncoderOptions::default());
+        let json = to_json(&[metrics.clone(), metrics.clone()]);
+        assert_eq!(json.matches(r#""file":"a,b""#).count(), 2);
+        let csv = to_csv(&[metrics]);
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.lines().nth(1).unwrap().starts_with("\"a,b\",783,"));
+    }
+}
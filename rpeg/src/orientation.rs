@@ -0,0 +1,275 @@
+use crate::ppm::RgbImage;
+use crate::preprocess::Rotation;
+
+/// Metadata key the EXIF orientation of an input is kept under by `keep_orientation`.
+pub const ORIENTATION_KEY: &str = "orientation";
+
+/// Tag of the orientation in the first image file directory of EXIF data.
+const ORIENTATION_TAG: u16 = 0x0112;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// ## EXIF orientation of a photo
+///
+/// Cameras and phones store the pixels as the sensor read them and record in the EXIF data how
+/// the image has to be turned to be displayed upright. The variants are numbered like the EXIF
+/// tag, 1 to 8, and are named after the transform that puts the image upright: `Rotate90`
+/// turns it a quarter clockwise, and the mirrored variants flip it left to right first.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::orientation::Orientation;
+/// use rpeg::ppm::{Rgb, RgbImage};
+///
+/// let gray = |value| Rgb { red: value, green: value, blue: value };
+/// let image = RgbImage {
+///     pixels: vec![gray(1), gray(2), gray(3), gray(4), gray(5), gray(6)],
+///     width: 3,
+///     height: 2,
+///     denominator: 255,
+/// };
+/// let upright = Orientation::from_tag(6).unwrap().apply(&image);
+/// assert_eq!((upright.width, upright.height), (2, 3));
+/// assert_eq!(upright.pixels[..2], [gray(4), gray(1)]);
+/// ```
+pub enum Orientation {
+    #[default]
+    Normal,
+    Mirror,
+    Rotate180,
+    MirrorRotate180,
+    MirrorRotate270,
+    Rotate90,
+    MirrorRotate90,
+    Rotate270,
+}
+
+impl Orientation {
+    /// Every orientation, in the order of their EXIF tag values.
+    const ALL: [Orientation; 8] = [
+        Orientation::Normal,
+        Orientation::Mirror,
+        Orientation::Rotate180,
+        Orientation::MirrorRotate180,
+        Orientation::MirrorRotate270,
+        Orientation::Rotate90,
+        Orientation::MirrorRotate90,
+        Orientation::Rotate270,
+    ];
+
+    /// Returns the orientation of the EXIF tag value `tag`, between 1 and 8.
+    ///
+    /// # Arguments
+    /// * `tag`: Value of the EXIF orientation tag
+    pub fn from_tag(tag: u16) -> Option<Orientation> {
+        Orientation::ALL
+            .get((tag as usize).checked_sub(1)?)
+            .copied()
+    }
+
+    /// Returns the value of the EXIF orientation tag.
+    pub fn tag(self) -> u16 {
+        Orientation::ALL
+            .iter()
+            .position(|orientation| *orientation == self)
+            .map_or(1, |index| index as u16 + 1)
+    }
+
+    /// Returns the orientation recorded in EXIF data, as stored in the APP1 segment of a JPEG
+    /// file, with or without its `Exif\0\0` prefix. Returns None if the data is malformed or
+    /// has no orientation.
+    ///
+    /// # Arguments
+    /// * `exif`: EXIF data, a TIFF header followed by image file directories
+    pub fn from_exif(exif: &[u8]) -> Option<Orientation> {
+        let tiff = exif.strip_prefix(b"Exif\0\0").unwrap_or(exif);
+        let big_endian = match tiff.get(..2)? {
+            b"MM" => true,
+            b"II" => false,
+            _ => return None,
+        };
+        let read_u16 = |pos: usize| {
+            let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?];
+            Some(if big_endian {
+                u16::from_be_bytes(bytes)
+            } else {
+                u16::from_le_bytes(bytes)
+            })
+        };
+        let read_u32 = |pos: usize| {
+            let high = read_u16(pos)? as u32;
+            let low = read_u16(pos + 2)? as u32;
+            Some(if big_endian {
+                high << 16 | low
+            } else {
+                low << 16 | high
+            })
+        };
+        if read_u16(2)? != 42 {
+            return None;
+        }
+        let directory = read_u32(4)? as usize;
+        let entries = read_u16(directory)? as usize;
+        (0..entries)
+            .map(|entry| directory + 2 + entry * 12)
+            .find(|pos| read_u16(*pos) == Some(ORIENTATION_TAG))
+            .and_then(|pos| Orientation::from_tag(read_u16(pos + 8)?))
+    }
+
+    /// Returns the orientation recorded in the EXIF data of a JPEG file, or None if it has
+    /// none.
+    ///
+    /// # Arguments
+    /// * `jpeg`: Contents of the JPEG file
+    pub fn of_jpeg(jpeg: &[u8]) -> Option<Orientation> {
+        let mut pos = 2;
+        if !jpeg.starts_with(&[0xFF, 0xD8]) {
+            return None;
+        }
+        // The EXIF data precedes the frame, so the segments are only walked up to the first
+        // start of frame or start of scan.
+        while let [0xFF, marker, high, low, ..] = *jpeg.get(pos..)? {
+            let length = u16::from_be_bytes([high, low]) as usize;
+            let segment = jpeg.get(pos + 4..pos + 2 + length)?;
+            match marker {
+                0xE1 if segment.starts_with(b"Exif\0\0") => return Orientation::from_exif(segment),
+                0xC0..=0xC3 | 0xDA => return None,
+                _ => pos += 2 + length,
+            }
+        }
+        None
+    }
+
+    /// Returns `image` turned upright.
+    ///
+    /// # Arguments
+    /// * `image`: Pixels as they are stored
+    pub fn apply(self, image: &RgbImage) -> RgbImage {
+        let (width, height) = (image.width as usize, image.height as usize);
+        let (upright_width, upright_height) = self.dimensions(image.width, image.height);
+        RgbImage {
+            pixels: self.remap(&image.pixels, width, height),
+            width: upright_width,
+            height: upright_height,
+            denominator: image.denominator,
+        }
+    }
+
+    /// Returns whether the transform swaps the width and the height.
+    pub(crate) fn transposes(self) -> bool {
+        matches!(
+            self,
+            Orientation::MirrorRotate270
+                | Orientation::Rotate90
+                | Orientation::MirrorRotate90
+                | Orientation::Rotate270
+        )
+    }
+
+    /// Returns the width and height of a `width` by `height` grid once turned upright.
+    pub(crate) fn dimensions(self, width: u32, height: u32) -> (u32, u32) {
+        if self.transposes() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Returns the cells of a `width` by `height` grid, in row-major order, turned upright:
+    /// the pixels of an image, or the code words of its 2x2 blocks.
+    ///
+    /// # Arguments
+    /// * `cells`: Cells of the grid as they are stored, in row-major order
+    /// * `width`: Number of cells in every row
+    /// * `height`: Number of rows
+    pub(crate) fn remap<T: Clone>(self, cells: &[T], width: usize, height: usize) -> Vec<T> {
+        let (upright_width, upright_height) = self.dimensions(width as u32, height as u32);
+        let mut remapped = Vec::with_capacity(cells.len());
+        for row in 0..upright_height as usize {
+            for col in 0..upright_width as usize {
+                let (x, y) = match self {
+                    Orientation::Normal => (col, row),
+                    Orientation::Mirror => (width - 1 - col, row),
+                    Orientation::Rotate180 => (width - 1 - col, height - 1 - row),
+                    Orientation::MirrorRotate180 => (col, height - 1 - row),
+                    Orientation::MirrorRotate270 => (row, col),
+                    Orientation::Rotate90 => (row, height - 1 - col),
+                    Orientation::MirrorRotate90 => (width - 1 - row, height - 1 - col),
+                    Orientation::Rotate270 => (width - 1 - row, col),
+                };
+                remapped.push(cells[y * width + x].clone());
+            }
+        }
+        remapped
+    }
+}
+
+impl From<Rotation> for Orientation {
+    /// Returns the orientation that turns an image clockwise by `rotation`.
+    fn from(rotation: Rotation) -> Self {
+        match rotation {
+            Rotation::None => Orientation::Normal,
+            Rotation::Quarter => Orientation::Rotate90,
+            Rotation::Half => Orientation::Rotate180,
+            Rotation::ThreeQuarters => Orientation::Rotate270,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppm::Rgb;
+
+    #[test]
+    fn exif_orientations_turn_the_image_upright() {
+        // A 3x2 image and how every orientation stores it, read back upright.
+        let gray = |value| Rgb {
+            red: value,
+            green: value,
+            blue: value,
+        };
+        let upright = RgbImage {
+            pixels: (1..=6).map(gray).collect(),
+            width: 3,
+            height: 2,
+            denominator: 255,
+        };
+        let stored: [(u16, u32, [u16; 6]); 8] = [
+            (1, 3, [1, 2, 3, 4, 5, 6]),
+            (2, 3, [3, 2, 1, 6, 5, 4]),
+            (3, 3, [6, 5, 4, 3, 2, 1]),
+            (4, 3, [4, 5, 6, 1, 2, 3]),
+            (5, 2, [1, 4, 2, 5, 3, 6]),
+            (6, 2, [3, 6, 2, 5, 1, 4]),
+            (7, 2, [6, 3, 5, 2, 4, 1]),
+            (8, 2, [4, 1, 5, 2, 6, 3]),
+        ];
+        for (tag, width, values) in stored {
+            let image = RgbImage {
+                pixels: values.map(gray).to_vec(),
+                width,
+                height: 6 / width,
+                denominator: 255,
+            };
+            let orientation = Orientation::from_tag(tag).unwrap();
+            assert_eq!(orientation.tag(), tag);
+            assert_eq!(orientation.apply(&image), upright, "orientation {tag}");
+        }
+        assert_eq!(Orientation::from_tag(9), None);
+
+        // A JPEG prelude with a big-endian EXIF segment holding orientation 6.
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        exif.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0]);
+        exif.extend_from_slice(&[0, 0, 0, 0]);
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0, 4, b'J', b'F'];
+        jpeg.extend_from_slice(&[0xFF, 0xE1]);
+        jpeg.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        jpeg.extend_from_slice(&exif);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0, 2]);
+        assert_eq!(Orientation::of_jpeg(&jpeg), Some(Orientation::Rotate90));
+        let little = b"II\x2a\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x03\0\0\0";
+        assert_eq!(Orientation::from_exif(little), Some(Orientation::Rotate180));
+        assert_eq!(Orientation::of_jpeg(&jpeg[..12]), None);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::ppm::Rgb;
+use array2::array2::Array2;
+use std::collections::HashMap;
+
+/// Largest number of distinct colors a palette-coded tile can hold, so that every index fits in
+/// a byte.
+pub const MAX_PALETTE_COLORS: usize = 256;
+
+/// Longest run of equal indices a single run can hold.
+const MAX_RUN: usize = 256;
+
+/// Returns the distinct colors of an image, scaled to a denominator of 255, in the order in
+/// which they first appear in row-major order, along with the index of every pixel into them.
+/// Returns None when the image is empty or holds more than `MAX_PALETTE_COLORS` colors.
+///
+/// # Arguments
+/// * `image`: Image, or tile of an image
+/// * `denominator`: Denominator of the Rgb values of the image
+pub fn build_palette(image: &Array2<Rgb>, denominator: u16) -> Option<(Vec<Rgb>, Vec<u8>)> {
+    let scale = |value: u16| (value as u32 * 255 / denominator.max(1) as u32).min(255) as u16;
+    let mut palette = Vec::new();
+    let mut positions: HashMap<(u16, u16, u16), u8> = HashMap::new();
+    let mut indices = Vec::with_capacity(image.data.len());
+    for (_, _, pixel) in image.iter_row_major() {
+        let color = (scale(pixel.red), scale(pixel.green), scale(pixel.blue));
+        let index = match positions.get(&color) {
+            Some(index) => *index,
+            None => {
+                if palette.len() == MAX_PALETTE_COLORS {
+                    return None;
+                }
+                palette.push(Rgb {
+                    red: color.0,
+                    green: color.1,
+                    blue: color.2,
+                });
+                positions.insert(color, (palette.len() - 1) as u8);
+                (palette.len() - 1) as u8
+            }
+        };
+        indices.push(index);
+    }
+    (!palette.is_empty()).then_some((palette, indices))
+}
+
+/// Compresses an image with few distinct colors into its palette followed by the run-length
+/// coded index of every pixel, in row-major order. The stream starts with the number of colors
+/// minus one, followed by the 8-bit red, green, and blue densities of every color. Every run is
+/// then stored as the index of its color and its length minus one, one byte each. Returns None
+/// when the image cannot be palette coded, as decided by `build_palette`.
+///
+/// Colors with a denominator of 255 are stored exactly, so the image decodes without loss.
+///
+/// # Arguments
+/// * `image`: Image, or tile of an image
+/// * `denominator`: Denominator of the Rgb values of the image
+pub fn encode_palette(image: &Array2<Rgb>, denominator: u16) -> Option<Vec<u8>> {
+    let (palette, indices) = build_palette(image, denominator)?;
+    let mut output = vec![(palette.len() - 1) as u8];
+    for color in &palette {
+        output.extend_from_slice(&[color.red as u8, color.green as u8, color.blue as u8]);
+    }
+    let mut position = 0;
+    while position < indices.len() {
+        let index = indices[position];
+        let run = indices[position..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|other| **other == index)
+            .count();
+        output.extend_from_slice(&[index, (run - 1) as u8]);
+        position += run;
+    }
+    Some(output)
+}
+
+/// Decodes a stream written by `encode_palette` back into the pixels of the image, with a
+/// denominator of 255.
+///
+/// # Arguments
+/// * `payload`: Palette-coded stream, possibly truncated when `partial` is set
+/// * `width`: Width of the image in pixels
+/// * `height`: Height of the image in pixels
+/// * `partial`: Accept a truncated stream, leaving the pixels that have not arrived black
+pub fn decode_palette(
+    payload: &[u8],
+    width: usize,
+    height: usize,
+    partial: bool,
+) -> Result<Array2<Rgb>, String> {
+    let truncated = || "Ran out of bytes while reading the palette".to_string();
+    let pixel_count = width * height;
+    let mut pixels = Vec::with_capacity(pixel_count);
+    let palette: Vec<Rgb> = match payload.first() {
+        Some(count) => payload
+            .get(1..1 + (*count as usize + 1) * 3)
+            .ok_or_else(truncated)?
+            .chunks_exact(3)
+            .map(|rgb| Rgb {
+                red: rgb[0] as u16,
+                green: rgb[1] as u16,
+                blue: rgb[2] as u16,
+            })
+            .collect(),
+        None if partial => Vec::new(),
+        None => return Err(truncated()),
+    };
+    let runs = payload.get(1 + palette.len() * 3..).unwrap_or_default();
+    for run in runs.chunks(2) {
+        let [index, length] = run else {
+            return Err(truncated());
+        };
+        let color = palette
+            .get(*index as usize)
+            .ok_or(format!("Palette index {index} is out of range"))?;
+        if pixels.len() + *length as usize + 1 > pixel_count {
+            return Err("The runs of the palette cover more pixels than the image".to_string());
+        }
+        pixels.extend(std::iter::repeat_n(color.clone(), *length as usize + 1));
+    }
+    if pixels.len() < pixel_count && !partial {
+        return Err(truncated());
+    }
+    pixels.resize(pixel_count, Rgb::default());
+    Ok(Array2::from_row_major(width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn few_colors_round_trip_without_loss() {
+        let colors = [(255, 255, 255), (0, 0, 0), (200, 30, 30)];
+        let pixels: Vec<Rgb> = (0..64 * 8)
+            .map(|i| {
+                let (red, green, blue) = colors[(i / 100 + i / 37 % 2) % 3];
+                Rgb { red, green, blue }
+            })
+            .collect();
+        let image = Array2::from_row_major(64, 8, pixels.clone());
+        let encoded = encode_palette(&image, 255).unwrap();
+        assert!(encoded.len() < pixels.len());
+        assert_eq!(decode_palette(&encoded, 64, 8, false).unwrap().data, pixels);
+
+        let truncated = decode_palette(&encoded[..encoded.len() - 2], 64, 8, true).unwrap();
+        assert_eq!(truncated.data[..400], pixels[..400]);
+        assert!(decode_palette(&encoded[..encoded.len() - 2], 64, 8, false).is_err());
+
+        let photo: Vec<Rgb> = (0..300)
+            .map(|i| Rgb {
+                red: i % 256,
+                green: i / 256,
+                blue: 0,
+            })
+            .collect();
+        assert!(encode_palette(&Array2::from_row_major(20, 15, photo), 255).is_none());
+    }
+}
@@ -0,0 +1,77 @@
+use rayon::prelude::*;
+
+/// Rows of 2x2 blocks in every chunk a tile is split into, so that an untiled image keeps
+/// every thread busy; 32 rows of a 4K image take about a millisecond to encode.
+pub const CHUNK_ROWS: usize = 32;
+
+/// Runs the stages of the encoder as an ordered pipeline: `items` are split off, `work` runs on
+/// each of them in the rayon pool, or one after the other when `sequential` is set, and the
+/// results are reassembled in the order of the items. Every result only depends on its own
+/// item, so the output is the same whatever the number of threads and however the pool
+/// schedules them.
+///
+/// # Arguments
+/// * `items`: Pieces of work, such as tiles or chunks of block rows
+/// * `sequential`: Process the items on the calling thread, one after the other
+/// * `work`: Processes one item
+pub fn ordered<I, T>(items: &[I], sequential: bool, work: impl Fn(&I) -> T + Sync) -> Vec<T>
+where
+    I: Sync,
+    T: Send,
+{
+    if sequential {
+        items.iter().map(work).collect()
+    } else {
+        items.par_iter().map(&work).collect()
+    }
+}
+
+/// Returns the first pixel row of every chunk of `CHUNK_ROWS` rows of blocks of a tile.
+///
+/// # Arguments
+/// * `height`: Height of the tile in pixels, which is even
+pub fn chunk_starts(height: usize) -> Vec<usize> {
+    (0..height).step_by(CHUNK_ROWS * 2).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::compress_image;
+    use crate::codec::EncoderOptions;
+    use crate::testkit::{synthetic_image, Pattern};
+
+    #[test]
+    fn every_thread_count_gives_the_same_bitstream() {
+        assert_eq!(chunk_starts(130), [0, 64, 128]);
+        let image = synthetic_image(Pattern::Noise { seed: 11 }, 70, 300);
+        let options = [
+            EncoderOptions::default(),
+            EncoderOptions {
+                tile_size: 64,
+                optimize: true,
+                arithmetic: crate::fixed::Arithmetic::Float,
+                ..EncoderOptions::default()
+            },
+        ];
+        for options in options {
+            let sequential = compress_image(
+                &image,
+                &EncoderOptions {
+                    deterministic: true,
+                    ..options.clone()
+                },
+            );
+            for threads in [1, 3, 8] {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .unwrap();
+                assert_eq!(
+                    pool.install(|| compress_image(&image, &options)),
+                    sequential
+                );
+            }
+        }
+    }
+}
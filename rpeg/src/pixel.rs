@@ -0,0 +1,426 @@
+use crate::conversions::{
+    component_back_to_rgb_floats, compute_component_video, compute_rgb_floats,
+    from_rgb_float_to_rgb, from_rgb_float_to_rgb_dithered, linear_to_srgb, srgb_to_linear,
+};
+use crate::ppm::Rgb;
+use crate::structs::ComponentVideo;
+use crate::structs::RgbFloats;
+
+/// ## A pixel the conversion stages can read and write
+///
+/// Every channel holds a density between 0 and the denominator of the image. The conversion
+/// stages only see pixels through `to_ypbpr` and `from_ypbpr`, so they work the same for every
+/// type implementing the trait. Decoded pixels have a denominator of 255, except for `Rgb16`.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::pixel::{Gray, Pixel};
+///
+/// let pixel = Gray { value: 51 };
+/// let video = pixel.to_ypbpr(255.0);
+/// assert_eq!((video.y, video.pb), (0.2, 0.0));
+/// assert_eq!(Gray::from_ypbpr(&video), pixel);
+/// assert_eq!(pixel.channel(0), 51);
+/// ```
+pub trait Pixel: Clone + Default {
+    /// Number of channels of the pixel.
+    const CHANNELS: usize;
+
+    /// Returns the density of channel `index`, which must be below `CHANNELS`.
+    fn channel(&self, index: usize) -> u16;
+
+    /// Sets the density of channel `index`, which must be below `CHANNELS`.
+    fn set_channel(&mut self, index: usize, value: u16);
+
+    /// Returns the luma and chroma of the pixel.
+    ///
+    /// # Arguments
+    /// * `denominator`: Denominator of the densities of the pixel
+    fn to_ypbpr(&self, denominator: f64) -> ComponentVideo;
+
+    /// Returns the pixel of the given luma and chroma, truncating every density.
+    ///
+    /// # Arguments
+    /// * `video`: Luma and chroma of the pixel
+    fn from_ypbpr(video: &ComponentVideo) -> Self;
+
+    /// Returns the pixel of the given luma and chroma, rounding every density up when its
+    /// fractional part exceeds `threshold`.
+    ///
+    /// # Arguments
+    /// * `video`: Luma and chroma of the pixel
+    /// * `threshold`: Dithering threshold of the pixel, as returned by `dither_threshold`
+    fn from_ypbpr_dithered(video: &ComponentVideo, threshold: f64) -> Self;
+}
+
+impl Pixel for Rgb {
+    const CHANNELS: usize = 3;
+
+    fn channel(&self, index: usize) -> u16 {
+        [self.red, self.green, self.blue][index]
+    }
+
+    fn set_channel(&mut self, index: usize, value: u16) {
+        *[&mut self.red, &mut self.green, &mut self.blue][index] = value;
+    }
+
+    fn to_ypbpr(&self, denominator: f64) -> ComponentVideo {
+        compute_component_video(&compute_rgb_floats(self, denominator))
+    }
+
+    fn from_ypbpr(video: &ComponentVideo) -> Self {
+        from_rgb_float_to_rgb(&component_back_to_rgb_floats(video))
+    }
+
+    fn from_ypbpr_dithered(video: &ComponentVideo, threshold: f64) -> Self {
+        from_rgb_float_to_rgb_dithered(&component_back_to_rgb_floats(video), threshold)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// ## Represent a pixel with an alpha channel
+///
+/// The color is compressed like an Rgb pixel. The code words have no room for the alpha
+/// channel, so decoded pixels are opaque.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::pixel::{Pixel, Rgba};
+///
+/// let pixel = Rgba { red: 255, green: 0, blue: 0, alpha: 128 };
+/// let decoded = Rgba::from_ypbpr(&pixel.to_ypbpr(255.0));
+/// assert_eq!((decoded.green, decoded.alpha), (0, 255));
+/// ```
+pub struct Rgba {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+}
+
+impl Rgba {
+    /// Returns the color of the pixel, without its alpha channel.
+    pub fn rgb(&self) -> Rgb {
+        Rgb {
+            red: self.red,
+            green: self.green,
+            blue: self.blue,
+        }
+    }
+
+    /// Returns an opaque pixel of the given color.
+    ///
+    /// # Arguments
+    /// * `rgb`: Color of the pixel, with a denominator of 255
+    fn opaque(rgb: Rgb) -> Rgba {
+        Rgba {
+            red: rgb.red,
+            green: rgb.green,
+            blue: rgb.blue,
+            alpha: 255,
+        }
+    }
+}
+
+impl Pixel for Rgba {
+    const CHANNELS: usize = 4;
+
+    fn channel(&self, index: usize) -> u16 {
+        [self.red, self.green, self.blue, self.alpha][index]
+    }
+
+    fn set_channel(&mut self, index: usize, value: u16) {
+        *[
+            &mut self.red,
+            &mut self.green,
+            &mut self.blue,
+            &mut self.alpha,
+        ][index] = value;
+    }
+
+    fn to_ypbpr(&self, denominator: f64) -> ComponentVideo {
+        self.rgb().to_ypbpr(denominator)
+    }
+
+    fn from_ypbpr(video: &ComponentVideo) -> Self {
+        Rgba::opaque(Rgb::from_ypbpr(video))
+    }
+
+    fn from_ypbpr_dithered(video: &ComponentVideo, threshold: f64) -> Self {
+        Rgba::opaque(Rgb::from_ypbpr_dithered(video, threshold))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// ## Represent a grayscale pixel
+///
+/// Its luma is its density and its chroma is zero, so only the luma of the code words carries
+/// information.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::pixel::{Gray, Pixel};
+///
+/// assert_eq!(Gray { value: 3 }.to_ypbpr(3.0).y, 1.0);
+/// ```
+pub struct Gray {
+    pub value: u16,
+}
+
+impl Pixel for Gray {
+    const CHANNELS: usize = 1;
+
+    fn channel(&self, index: usize) -> u16 {
+        [self.value][index]
+    }
+
+    fn set_channel(&mut self, index: usize, value: u16) {
+        *[&mut self.value][index] = value;
+    }
+
+    fn to_ypbpr(&self, denominator: f64) -> ComponentVideo {
+        ComponentVideo {
+            y: self.value as f64 / denominator,
+            pb: 0.0,
+            pr: 0.0,
+        }
+    }
+
+    fn from_ypbpr(video: &ComponentVideo) -> Self {
+        Gray {
+            value: (video.y * 255.0) as u16,
+        }
+    }
+
+    fn from_ypbpr_dithered(video: &ComponentVideo, threshold: f64) -> Self {
+        Gray {
+            value: (video.y * 255.0 + 1.0 - threshold) as u16,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// ## Represent an Rgb pixel whose densities follow the sRGB transfer curve
+///
+/// The color transform of the codec averages the pixels of every block, which is only exact
+/// in linear light. `to_ypbpr` removes the transfer curve before the color transform, and
+/// `from_ypbpr` puts it back after the inverse transform, in floating point, so that the dark
+/// densities are not truncated in linear light.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::pixel::{Pixel, Srgb};
+///
+/// let pixel = Srgb { red: 128, green: 128, blue: 128 };
+/// assert!((pixel.to_ypbpr(255.0).y - 0.216).abs() < 0.001);
+/// assert_eq!(Srgb::from_ypbpr(&pixel.to_ypbpr(255.0)), pixel);
+/// ```
+pub struct Srgb {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+}
+
+impl Srgb {
+    /// Returns the densities of the pixel as an Rgb pixel.
+    pub fn rgb(&self) -> Rgb {
+        Rgb {
+            red: self.red,
+            green: self.green,
+            blue: self.blue,
+        }
+    }
+
+    /// Returns the sRGB pixel of the linear light `linear`, given out of 255.
+    ///
+    /// # Arguments
+    /// * `linear`: Linear light of the pixel, out of 255
+    fn encoded(linear: &RgbFloats) -> RgbFloats {
+        let encode = |value: f64| linear_to_srgb((value / 255.0).clamp(0.0, 1.0)) * 255.0;
+        RgbFloats {
+            red: encode(linear.red),
+            green: encode(linear.green),
+            blue: encode(linear.blue),
+        }
+    }
+
+    /// Returns an sRGB pixel of the densities of `rgb`.
+    fn from_rgb(rgb: Rgb) -> Srgb {
+        Srgb {
+            red: rgb.red,
+            green: rgb.green,
+            blue: rgb.blue,
+        }
+    }
+}
+
+impl Pixel for Srgb {
+    const CHANNELS: usize = 3;
+
+    fn channel(&self, index: usize) -> u16 {
+        [self.red, self.green, self.blue][index]
+    }
+
+    fn set_channel(&mut self, index: usize, value: u16) {
+        *[&mut self.red, &mut self.green, &mut self.blue][index] = value;
+    }
+
+    fn to_ypbpr(&self, denominator: f64) -> ComponentVideo {
+        let encoded = compute_rgb_floats(&self.rgb(), denominator);
+        compute_component_video(&RgbFloats {
+            red: srgb_to_linear(encoded.red),
+            green: srgb_to_linear(encoded.green),
+            blue: srgb_to_linear(encoded.blue),
+        })
+    }
+
+    fn from_ypbpr(video: &ComponentVideo) -> Self {
+        let linear = component_back_to_rgb_floats(video);
+        Srgb::from_rgb(from_rgb_float_to_rgb(&Srgb::encoded(&linear)))
+    }
+
+    fn from_ypbpr_dithered(video: &ComponentVideo, threshold: f64) -> Self {
+        let linear = component_back_to_rgb_floats(video);
+        Srgb::from_rgb(from_rgb_float_to_rgb_dithered(
+            &Srgb::encoded(&linear),
+            threshold,
+        ))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// ## Represent an Rgb pixel decoded with 16 bits per channel
+///
+/// It is compressed like an Rgb pixel, but decoded pixels have a denominator of 65535 instead
+/// of 255, which keeps the precision of the log-coded HDR images of `rpeg::hdr`.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::pixel::{Pixel, Rgb16};
+///
+/// let pixel = Rgb16 { red: 1000, green: 30000, blue: 65535 };
+/// let decoded = Rgb16::from_ypbpr(&pixel.to_ypbpr(65535.0));
+/// assert!(decoded.red.abs_diff(1000) <= 1 && decoded.blue.abs_diff(65535) <= 1);
+/// ```
+pub struct Rgb16 {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+}
+
+impl Rgb16 {
+    /// Returns the densities of the pixel as an Rgb pixel.
+    pub fn rgb(&self) -> Rgb {
+        Rgb {
+            red: self.red,
+            green: self.green,
+            blue: self.blue,
+        }
+    }
+
+    /// Returns the densities of `video` scaled to 65535, rounding every density up when its
+    /// fractional part exceeds `threshold`.
+    ///
+    /// # Arguments
+    /// * `video`: Luma and chroma of the pixel
+    /// * `threshold`: Dithering threshold of the pixel, or 1 to truncate the densities
+    fn scaled(video: &ComponentVideo, threshold: f64) -> Rgb16 {
+        let floats = component_back_to_rgb_floats(video);
+        let scale = |value: f64| (value * 257.0 + 1.0 - threshold) as u16;
+        Rgb16 {
+            red: scale(floats.red),
+            green: scale(floats.green),
+            blue: scale(floats.blue),
+        }
+    }
+}
+
+impl Pixel for Rgb16 {
+    const CHANNELS: usize = 3;
+
+    fn channel(&self, index: usize) -> u16 {
+        [self.red, self.green, self.blue][index]
+    }
+
+    fn set_channel(&mut self, index: usize, value: u16) {
+        *[&mut self.red, &mut self.green, &mut self.blue][index] = value;
+    }
+
+    fn to_ypbpr(&self, denominator: f64) -> ComponentVideo {
+        self.rgb().to_ypbpr(denominator)
+    }
+
+    fn from_ypbpr(video: &ComponentVideo) -> Self {
+        Rgb16::scaled(video, 1.0)
+    }
+
+    fn from_ypbpr_dithered(video: &ComponentVideo, threshold: f64) -> Self {
+        Rgb16::scaled(video, threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversions::{
+        blocks_to_dct, component_video_to_blocks, component_video_to_pixels, fix_pixel_poss,
+        from_blocks_to_component_format, from_dct_to_component_video, pixels_to_component_video,
+    };
+    use crate::dct_coeff::DEFAULT_LUMA_RANGE;
+    use crate::layout::NARROW_LAYOUT;
+    use array2::array2::Array2;
+
+    /// Runs `image` through the floating point stages of the codec and back.
+    fn round_trip<P: Pixel>(image: &Array2<P>, denominator: u16) -> Vec<P> {
+        let ranges = vec![DEFAULT_LUMA_RANGE; image.data.len() / 4];
+        let video = pixels_to_component_video(image, denominator);
+        let blocks = component_video_to_blocks(&video);
+        let coefficients = blocks_to_dct(&blocks, &ranges, false, &NARROW_LAYOUT);
+        let blocks = from_dct_to_component_video(&coefficients, &ranges, &NARROW_LAYOUT);
+        let pixels =
+            component_video_to_pixels::<P>(&from_blocks_to_component_format(&blocks), None);
+        fix_pixel_poss(&pixels)
+    }
+
+    #[test]
+    fn every_pixel_type_goes_through_the_pipeline() {
+        let gray: Vec<Gray> = (0..16).map(|i| Gray { value: i * 16 }).collect();
+        let decoded = round_trip(&Array2::from_row_major(4, 4, gray.clone()), 255);
+        for (original, decoded) in gray.iter().zip(decoded.iter()) {
+            assert!(original.value.abs_diff(decoded.value) <= 8);
+        }
+
+        let rgba: Vec<Rgba> = (0..16)
+            .map(|i| Rgba {
+                red: 200,
+                green: 40 + i,
+                blue: 90,
+                alpha: i,
+            })
+            .collect();
+        let decoded = round_trip(&Array2::from_row_major(4, 4, rgba.clone()), 255);
+        let rgb = round_trip(
+            &Array2::from_row_major(4, 4, rgba.iter().map(Rgba::rgb).collect()),
+            255,
+        );
+        for ((pixel, color), original) in decoded.iter().zip(rgb.iter()).zip(rgba.iter()) {
+            assert_eq!((pixel.rgb(), pixel.alpha), (color.clone(), 255));
+            assert!(pixel.red.abs_diff(original.red) <= 32);
+        }
+
+        let mut pixel = Rgba::default();
+        pixel.set_channel(3, 7);
+        pixel.set_channel(1, 2);
+        assert_eq!(
+            (0..Rgba::CHANNELS)
+                .map(|i| pixel.channel(i))
+                .collect::<Vec<_>>(),
+            [0, 2, 0, 7]
+        );
+    }
+}
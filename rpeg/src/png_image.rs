@@ -0,0 +1,184 @@
+use crate::color_tag::{ColorSpace, ColorTag};
+use crate::ppm::{Rgb, RgbImage};
+use std::io::Cursor;
+
+/// Signature every PNG file starts with.
+pub const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Decodes a PNG image. Palettes are expanded, gray levels are copied to the three channels,
+/// and the alpha channel is dropped. 16-bit images keep their precision with a denominator of
+/// 65535.
+///
+/// # Arguments
+/// * `bytes`: Contents of the PNG file
+pub fn read_png(bytes: &[u8]) -> Result<RgbImage, String> {
+    let invalid = |error: png::DecodingError| format!("Invalid PNG image: {error}");
+    let mut decoder = png::Decoder::new(Cursor::new(bytes));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(invalid)?;
+    let size = reader
+        .output_buffer_size()
+        .ok_or("The PNG image is too large")?;
+    let mut buffer = vec![0; size];
+    let info = reader.next_frame(&mut buffer).map_err(invalid)?;
+    if info.color_type == png::ColorType::Indexed {
+        return Err("The PNG palette was not expanded".to_string());
+    }
+    let channels = info.color_type.samples();
+    let (sample_size, denominator) = match info.bit_depth {
+        png::BitDepth::Sixteen => (2, 65535),
+        _ => (1, 255),
+    };
+    let samples: Vec<u16> = buffer[..info.buffer_size()]
+        .chunks_exact(sample_size)
+        .map(|sample| match sample {
+            [high, low] => u16::from_be_bytes([*high, *low]),
+            [value] => *value as u16,
+            _ => unreachable!(),
+        })
+        .collect();
+    let row_length = info.line_size / sample_size;
+    let pixels = samples
+        .chunks_exact(row_length)
+        .flat_map(|row| row[..info.width as usize * channels].chunks_exact(channels))
+        .map(|pixel| match pixel {
+            [gray] | [gray, _] => Rgb {
+                red: *gray,
+                green: *gray,
+                blue: *gray,
+            },
+            [red, green, blue, ..] => Rgb {
+                red: *red,
+                green: *green,
+                blue: *blue,
+            },
+            _ => unreachable!(),
+        })
+        .collect();
+    Ok(RgbImage {
+        pixels,
+        width: info.width,
+        height: info.height,
+        denominator,
+    })
+}
+
+/// Reads a PNG or PPM image, told apart by their signatures.
+///
+/// # Arguments
+/// * `bytes`: Contents of the image file
+pub fn read_png_or_ppm(bytes: &[u8]) -> Result<RgbImage, String> {
+    if bytes.starts_with(&PNG_SIGNATURE) {
+        read_png(bytes)
+    } else {
+        RgbImage::from_reader(bytes)
+    }
+}
+
+/// Returns the width and height of a PNG or PPM image, read from its header without decoding
+/// its pixels.
+///
+/// # Arguments
+/// * `bytes`: Contents of the image file
+pub fn read_png_or_ppm_dimensions(bytes: &[u8]) -> Result<(u32, u32), String> {
+    if bytes.starts_with(&PNG_SIGNATURE) {
+        let reader = png::Decoder::new(Cursor::new(bytes))
+            .read_info()
+            .map_err(|error| format!("Invalid PNG image: {error}"))?;
+        Ok((reader.info().width, reader.info().height))
+    } else {
+        RgbImage::read_dimensions(bytes)
+    }
+}
+
+/// Returns the color space a PNG image is tagged with by its `iCCP` or `sRGB` chunk, if any.
+///
+/// # Arguments
+/// * `bytes`: Contents of the PNG file
+pub fn read_png_color_tag(bytes: &[u8]) -> Result<Option<ColorTag>, String> {
+    let reader = png::Decoder::new(Cursor::new(bytes))
+        .read_info()
+        .map_err(|error| format!("Invalid PNG image: {error}"))?;
+    let info = reader.info();
+    Ok(match (&info.icc_profile, info.srgb) {
+        (Some(profile), _) => Some(ColorTag::Icc(profile.to_vec())),
+        (None, Some(_)) => Some(ColorTag::Space(ColorSpace::Srgb)),
+        (None, None) => None,
+    })
+}
+
+/// Encodes an image as an 8-bit RGB PNG. Channels are scaled to 255 from the denominator of
+/// the image. A named color space is written as an `sRGB` chunk, or as the `cHRM`
+/// chromaticities and a `gAMA` of 1/2.2 for the other spaces, and an ICC profile as an `iCCP`
+/// chunk.
+///
+/// # Arguments
+/// * `image`: Image to encode
+/// * `color_tag`: Color space the image is tagged with, or None
+pub fn write_png(image: &RgbImage, color_tag: Option<&ColorTag>) -> Result<Vec<u8>, String> {
+    let failed = |error: png::EncodingError| format!("Failed to encode the PNG image: {error}");
+    let denominator = image.denominator.max(1) as u32;
+    let scale = |value: u16| ((value as u32).min(denominator) * 255 / denominator) as u8;
+    let data: Vec<u8> = image
+        .pixels
+        .iter()
+        .flat_map(|pixel| [scale(pixel.red), scale(pixel.green), scale(pixel.blue)])
+        .collect();
+    let mut output = Vec::new();
+    let mut info = png::Info::with_size(image.width, image.height);
+    info.color_type = png::ColorType::Rgb;
+    info.bit_depth = png::BitDepth::Eight;
+    match color_tag {
+        Some(ColorTag::Icc(profile)) => info.icc_profile = Some(profile.as_slice().into()),
+        Some(ColorTag::Space(ColorSpace::Srgb)) => {
+            info.srgb = Some(png::SrgbRenderingIntent::Perceptual)
+        }
+        Some(ColorTag::Space(space)) => {
+            let [white, red, green, blue] = space.chromaticities();
+            info.source_chromaticities =
+                Some(png::SourceChromaticities::new(white, red, green, blue));
+            info.source_gamma = Some(png::ScaledFloat::new(1.0 / 2.2));
+        }
+        None => {}
+    }
+    let encoder = png::Encoder::with_info(&mut output, info).map_err(failed)?;
+    let mut writer = encoder.write_header().map_err(failed)?;
+    writer.write_image_data(&data).map_err(failed)?;
+    writer.finish().map_err(failed)?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_round_trip() {
+        let image = RgbImage {
+            pixels: (0..15u16)
+                .map(|i| Rgb {
+                    red: i * 17,
+                    green: 255 - i,
+                    blue: i,
+                })
+                .collect(),
+            width: 5,
+            height: 3,
+            denominator: 255,
+        };
+        let bytes = write_png(&image, None).unwrap();
+        assert!(bytes.starts_with(&PNG_SIGNATURE));
+        assert_eq!(read_png(&bytes).unwrap(), image);
+        assert_eq!(read_png_color_tag(&bytes), Ok(None));
+        let profile = ColorTag::Icc(b"not really an ICC profile".to_vec());
+        let tagged = write_png(&image, Some(&profile)).unwrap();
+        assert_eq!(read_png(&tagged).unwrap(), image);
+        assert_eq!(read_png_color_tag(&tagged), Ok(Some(profile)));
+        let srgb = write_png(&image, Some(&ColorTag::Space(ColorSpace::Srgb))).unwrap();
+        assert_eq!(
+            read_png_color_tag(&srgb),
+            Ok(Some(ColorTag::Space(ColorSpace::Srgb)))
+        );
+        assert!(read_png(&bytes[..20]).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+#[derive(Clone, Debug, PartialEq)]
+/// ## Outcome of compressing or decompressing one file
+///
+/// With `--porcelain`, every file a command writes is reported on standard out as one line of
+/// tab-separated fields, in this order: the input, the output, their sizes in bytes, the input
+/// size divided by the output size, and the time taken in seconds. Standard in and standard
+/// out are written `-`. The fields and their order are stable, and new fields are only ever
+/// added at the end.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::porcelain::FileResult;
+///
+/// let result = FileResult {
+///     input: "cat.ppm".to_string(),
+///     output: "cat.rpeg".to_string(),
+///     input_bytes: 3000,
+///     output_bytes: 1000,
+///     seconds: 0.25,
+/// };
+/// assert_eq!(result.to_porcelain(), "cat.ppm\tcat.rpeg\t3000\t1000\t3.000\t0.250000\n");
+/// ```
+pub struct FileResult {
+    /// Location of the file that was read, or `-` for standard in.
+    pub input: String,
+    /// Location of the file that was written, or `-` for standard out.
+    pub output: String,
+    /// Size of the input, in bytes.
+    pub input_bytes: usize,
+    /// Size of the output, in bytes.
+    pub output_bytes: usize,
+    /// Time taken, from reading the input to writing the output, in seconds.
+    pub seconds: f64,
+}
+
+impl FileResult {
+    /// Returns the result as a line of `--porcelain` output.
+    pub fn to_porcelain(&self) -> String {
+        record(&[
+            self.input.clone(),
+            self.output.clone(),
+            self.input_bytes.to_string(),
+            self.output_bytes.to_string(),
+            format!(
+                "{:.3}",
+                self.input_bytes as f64 / self.output_bytes.max(1) as f64
+            ),
+            format!("{:.6}", self.seconds),
+        ])
+    }
+}
+
+/// Returns `fields` as a line of tab-separated fields. Backslashes, tabs, and line breaks
+/// within a field are written `\\`, `\t`, `\n`, and `\r`, so that every record takes exactly
+/// one line.
+///
+/// # Arguments
+/// * `fields`: Fields of the record
+pub fn record(fields: &[String]) -> String {
+    let escaped: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            field
+                .replace('\\', "\\\\")
+                .replace('\t', "\\t")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r")
+        })
+        .collect();
+    escaped.join("\t") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_never_break_the_record() {
+        let line = record(&["a\tb".to_string(), "c\\d\ne".to_string(), String::new()]);
+        assert_eq!(line, "a\\tb\tc\\\\d\\ne\t\n");
+        assert_eq!(line.matches('\t').count(), 2);
+    }
+}
@@ -0,0 +1,333 @@
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// ## Represent a pixel of a PPM image
+///
+/// Every channel holds a density between 0 and the denominator of the image.
+pub struct Rgb {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Represent a PPM image
+///
+/// `pixels` holds the image in row-major order and `denominator` is the maxval of the file.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::ppm::RgbImage;
+///
+/// let bytes = b"P3\n# a single red pixel\n1 1\n255\n255 0 0\n";
+/// let image = RgbImage::from_reader(&bytes[..]).unwrap();
+/// assert_eq!((image.width, image.height, image.denominator), (1, 1, 255));
+/// assert_eq!(image.pixels[0].red, 255);
+/// ```
+pub struct RgbImage {
+    pub pixels: Vec<Rgb>,
+    pub width: u32,
+    pub height: u32,
+    pub denominator: u16,
+}
+
+/// Reads the bytes of a PPM header one at a time, skipping the whitespace and the `#` comments
+/// between its fields.
+struct HeaderReader<R: BufRead> {
+    reader: R,
+}
+
+impl<R: BufRead> HeaderReader<R> {
+    /// Returns the next byte of the input, or None at the end of the input.
+    fn next_byte(&mut self) -> Result<Option<u8>, String> {
+        let byte = match self.reader.fill_buf() {
+            Ok(buffer) => buffer.first().copied(),
+            Err(error) => return Err(format!("Failed to read the image: {error}")),
+        };
+        if byte.is_some() {
+            self.reader.consume(1);
+        }
+        Ok(byte)
+    }
+
+    /// Reads the magic number and the dimensions of the header, and returns whether the image
+    /// is binary along with its width and height.
+    fn magic_and_dimensions(&mut self) -> Result<(bool, u32, u32), String> {
+        let binary = match [self.next_byte()?, self.next_byte()?] {
+            [Some(b'P'), Some(b'6')] => true,
+            [Some(b'P'), Some(b'3')] => false,
+            _ => return Err("The input is not a PPM image (P3 or P6)".to_string()),
+        };
+        let (width, _) = self.field("width")?;
+        let (height, _) = self.field("height")?;
+        Ok((binary, width, height))
+    }
+
+    /// Reads the next decimal field of the header, along with the byte that ended it.
+    ///
+    /// # Arguments
+    /// * `name`: Name of the field, used in error messages
+    fn field(&mut self, name: &str) -> Result<(u32, Option<u8>), String> {
+        let mut byte = self.next_byte()?;
+        loop {
+            match byte {
+                Some(b'#') => {
+                    while !matches!(byte, None | Some(b'\n') | Some(b'\r')) {
+                        byte = self.next_byte()?;
+                    }
+                }
+                Some(space) if space.is_ascii_whitespace() => byte = self.next_byte()?,
+                _ => break,
+            }
+        }
+        let mut value: Option<u32> = None;
+        while let Some(digit @ b'0'..=b'9') = byte {
+            value = value
+                .unwrap_or(0)
+                .checked_mul(10)
+                .and_then(|value| value.checked_add((digit - b'0') as u32));
+            if value.is_none() {
+                return Err(format!("The {name} of the image is too large"));
+            }
+            byte = self.next_byte()?;
+        }
+        if value.is_some() && byte == Some(b'#') {
+            while !matches!(byte, None | Some(b'\n') | Some(b'\r')) {
+                byte = self.next_byte()?;
+            }
+        }
+        match (value, byte) {
+            (Some(value), None) => Ok((value, None)),
+            (Some(value), Some(end)) if end.is_ascii_whitespace() => Ok((value, Some(end))),
+            (_, None) => Err(format!("The image ends before its {name}")),
+            (_, Some(other)) => Err(format!(
+                "Expected the {name} of the image, found 0x{other:02X}"
+            )),
+        }
+    }
+}
+
+impl RgbImage {
+    /// Reads a PPM image from `filename`, or from standard in.
+    ///
+    /// # Arguments
+    /// * `filename`: Location of the PPM within your disk, or None to read from standard in
+    pub fn read(filename: Option<&str>) -> Result<RgbImage, String> {
+        match filename {
+            Some(filename) => {
+                let file = std::fs::File::open(filename)
+                    .map_err(|error| format!("Failed to open {filename}: {error}"))?;
+                RgbImage::from_reader(BufReader::new(file))
+            }
+            None => RgbImage::from_reader(std::io::stdin().lock()),
+        }
+    }
+
+    /// Reads a binary (P6) or plain (P3) PPM image from `reader`. Only the header and one row
+    /// of the raster are held in memory besides the pixels, and nothing past the last pixel is
+    /// read. Buffers grow with the samples that are actually read rather than with the size the
+    /// header claims. Maxvals above 255 use two Bigendian bytes per binary sample.
+    ///
+    /// # Arguments
+    /// * `reader`: Source of the PPM image
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<RgbImage, String> {
+        let mut header = HeaderReader { reader };
+        let (binary, width, height) = header.magic_and_dimensions()?;
+        let (maxval, end) = header.field("maxval")?;
+        if width == 0 || height == 0 {
+            return Err(format!("The image is {width}x{height} and has no pixels"));
+        }
+        if maxval == 0 || maxval > u16::MAX as u32 {
+            return Err(format!("The maxval {maxval} is not between 1 and 65535"));
+        }
+        if binary && end.is_none() {
+            return Err("The image ends after its maxval".to_string());
+        }
+        let samples = (width as usize)
+            .checked_mul(3)
+            .filter(|samples| samples.checked_mul(height as usize).is_some())
+            .ok_or_else(|| format!("The image is {width}x{height}, which is too large"))?;
+        let pixels = if binary {
+            read_binary_pixels(&mut header.reader, samples, height, maxval)?
+        } else {
+            let mut values = Vec::new();
+            for _ in 0..samples * height as usize {
+                values.push(header.field("sample")?.0);
+            }
+            values
+        };
+        if let Some(sample) = pixels.iter().find(|sample| **sample > maxval) {
+            return Err(format!("The sample {sample} is above the maxval {maxval}"));
+        }
+        Ok(RgbImage {
+            pixels: pixels
+                .chunks_exact(3)
+                .map(|channels| Rgb {
+                    red: channels[0] as u16,
+                    green: channels[1] as u16,
+                    blue: channels[2] as u16,
+                })
+                .collect(),
+            width,
+            height,
+            denominator: maxval as u16,
+        })
+    }
+
+    /// Returns the width and height of the PPM image of `reader`, read from its header alone.
+    ///
+    /// # Arguments
+    /// * `reader`: Source of the PPM image
+    pub fn read_dimensions<R: BufRead>(reader: R) -> Result<(u32, u32), String> {
+        let (_, width, height) = HeaderReader { reader }.magic_and_dimensions()?;
+        Ok((width, height))
+    }
+
+    /// Writes the image as a binary PPM with a maxval of 255 to `filename`, or to standard out.
+    /// Channels above 255 are written as 255.
+    ///
+    /// # Arguments
+    /// * `filename`: Location of the PPM to write, or None to write to standard out
+    pub fn write(&self, filename: Option<&str>) -> Result<(), String> {
+        match filename {
+            Some(filename) => {
+                let file = std::fs::File::create(filename)
+                    .map_err(|error| format!("Failed to create {filename}: {error}"))?;
+                self.write_to(BufWriter::new(file))
+            }
+            None => self.write_to(BufWriter::new(std::io::stdout().lock())),
+        }
+    }
+
+    /// Writes the image as a binary PPM with a maxval of 255 to `writer`.
+    ///
+    /// # Arguments
+    /// * `writer`: Destination of the PPM image
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), String> {
+        let expected = self.width as usize * self.height as usize;
+        if self.pixels.len() != expected {
+            return Err(format!(
+                "The image has {} pixels instead of {expected}",
+                self.pixels.len()
+            ));
+        }
+        let write_error = |error: std::io::Error| format!("Failed to write the image: {error}");
+        write!(writer, "P6\n{} {} 255\n", self.width, self.height).map_err(write_error)?;
+        for row in self.pixels.chunks(self.width.max(1) as usize) {
+            let bytes: Vec<u8> = row
+                .iter()
+                .flat_map(|pixel| [pixel.red, pixel.green, pixel.blue])
+                .map(|value| value.min(255) as u8)
+                .collect();
+            writer.write_all(&bytes).map_err(write_error)?;
+        }
+        writer.flush().map_err(write_error)
+    }
+}
+
+/// Reads the raster of a binary PPM one row at a time, growing the row and the samples as the
+/// bytes arrive, so that a header claiming more rows than the input holds fails at its end.
+///
+/// # Arguments
+/// * `reader`: Source positioned right after the header
+/// * `samples`: Number of samples in a row, three per pixel
+/// * `height`: Number of rows
+/// * `maxval`: Maxval of the image, which selects one or two bytes per sample
+fn read_binary_pixels<R: BufRead>(
+    reader: &mut R,
+    samples: usize,
+    height: u32,
+    maxval: u32,
+) -> Result<Vec<u32>, String> {
+    let sample_bytes = if maxval > 255 { 2 } else { 1 };
+    let row_bytes = samples
+        .checked_mul(sample_bytes)
+        .ok_or_else(|| "The rows of the image are too large".to_string())?;
+    let mut row = Vec::new();
+    let mut values = Vec::new();
+    for r in 0..height {
+        row.clear();
+        let read = reader
+            .take(row_bytes as u64)
+            .read_to_end(&mut row)
+            .map_err(|error| format!("Failed to read row {r} of {height}: {error}"))?;
+        if read < row_bytes {
+            return Err(format!(
+                "Failed to read row {r} of {height}: the image ends after {read} of its \
+                 {row_bytes} bytes"
+            ));
+        }
+        if sample_bytes == 2 {
+            values.extend(
+                row.chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]) as u32),
+            );
+        } else {
+            values.extend(row.iter().map(|byte| *byte as u32));
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_images_round_trip() {
+        let image = RgbImage {
+            pixels: (0..6)
+                .map(|i| Rgb {
+                    red: i * 40,
+                    green: 255 - i,
+                    blue: i,
+                })
+                .collect(),
+            width: 3,
+            height: 2,
+            denominator: 255,
+        };
+        let mut bytes = Vec::new();
+        image.write_to(&mut bytes).unwrap();
+        assert!(bytes.starts_with(b"P6\n3 2 255\n"));
+        assert_eq!(RgbImage::from_reader(&bytes[..]).unwrap(), image);
+    }
+
+    #[test]
+    fn sixteen_bit_samples_keep_their_maxval() {
+        let mut bytes = b"P6 1 1 # comment\n65535\n".to_vec();
+        bytes.extend_from_slice(&[0xFF, 0xFF, 0x01, 0x00, 0x00, 0x02]);
+        let image = RgbImage::from_reader(&bytes[..]).unwrap();
+        assert_eq!(image.denominator, 65535);
+        assert_eq!(
+            image.pixels[0],
+            Rgb {
+                red: 65535,
+                green: 256,
+                blue: 2
+            }
+        );
+    }
+
+    #[test]
+    fn malformed_images_are_errors() {
+        assert!(RgbImage::from_reader(&b"P5\n1 1\n255\n\0"[..]).is_err());
+        assert!(RgbImage::from_reader(&b"P6\n2 2\n255\n\0\0\0"[..])
+            .unwrap_err()
+            .contains("row 0"));
+        assert!(RgbImage::from_reader(&b"P3\n1 1\n100\n200 0 0\n"[..]).is_err());
+    }
+
+    #[test]
+    fn huge_headers_fail_at_the_end_of_the_input() {
+        let error = RgbImage::from_reader(&b"P6\n4000000000 4000000000\n65535\n\0\0"[..]);
+        assert!(error.unwrap_err().contains("too large"));
+        let error = RgbImage::from_reader(&b"P6\n4000000000 2\n255\n\0\0\0"[..]);
+        assert!(error
+            .unwrap_err()
+            .contains("ends after 3 of its 12000000000 bytes"));
+        let error = RgbImage::from_reader(&b"P3\n1000000 1000000\n255\n1 2 3\n"[..]);
+        assert!(error.unwrap_err().contains("ends before its sample"));
+    }
+}
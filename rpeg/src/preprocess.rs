@@ -0,0 +1,266 @@
+use crate::ppm::{Rgb, RgbImage};
+use crate::thumbnail::shrink;
+use std::borrow::Cow;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// ## Clockwise rotation applied to an image before it is compressed
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::preprocess::Rotation;
+///
+/// assert_eq!(Rotation::from_degrees(270), Some(Rotation::ThreeQuarters));
+/// assert_eq!(Rotation::Half.degrees(), 180);
+/// assert_eq!(Rotation::from_degrees(45), None);
+/// ```
+pub enum Rotation {
+    #[default]
+    None,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+impl Rotation {
+    /// Returns the rotation by `degrees` clockwise: 0, 90, 180, or 270.
+    ///
+    /// # Arguments
+    /// * `degrees`: Angle of the rotation, as given to `--rotate`
+    pub fn from_degrees(degrees: u32) -> Option<Rotation> {
+        [
+            Rotation::None,
+            Rotation::Quarter,
+            Rotation::Half,
+            Rotation::ThreeQuarters,
+        ]
+        .into_iter()
+        .find(|rotation| rotation.degrees() == degrees)
+    }
+
+    /// Returns the angle of the rotation in degrees clockwise.
+    pub fn degrees(self) -> u32 {
+        match self {
+            Rotation::None => 0,
+            Rotation::Quarter => 90,
+            Rotation::Half => 180,
+            Rotation::ThreeQuarters => 270,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+/// ## Steps applied to an image before it is compressed
+///
+/// The steps run in this order: the image is shrunk so that neither side exceeds
+/// `max_dimension`, keeping its aspect ratio, then rotated, then every channel is raised to
+/// the power of 1 / `gamma` (as ImageMagick's `-gamma` does, so a gamma above 1 brightens the
+/// mid-tones), and last the pixels are replaced by their luma when `grayscale` is set. The
+/// default settings leave the image untouched.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::ppm::{Rgb, RgbImage};
+/// use rpeg::preprocess::{Preprocess, Rotation};
+///
+/// let image = RgbImage {
+///     pixels: vec![Rgb { red: 255, green: 0, blue: 0 }; 8 * 4],
+///     width: 8,
+///     height: 4,
+///     denominator: 255,
+/// };
+/// let steps = Preprocess {
+///     max_dimension: Some(4),
+///     rotation: Rotation::Quarter,
+///     grayscale: true,
+///     ..Default::default()
+/// };
+/// let prepared = steps.apply(&image);
+/// assert_eq!((prepared.width, prepared.height), (2, 4));
+/// assert_eq!(prepared.pixels[0], Rgb { red: 76, green: 76, blue: 76 });
+/// ```
+pub struct Preprocess {
+    /// Largest width and height of the image, or None to keep its size. Larger images are
+    /// shrunk by averaging; smaller ones are not enlarged.
+    pub max_dimension: Option<u32>,
+    /// Clockwise rotation of the image.
+    pub rotation: Rotation,
+    /// Gamma the channels are adjusted by, which must be positive, or None to keep them.
+    pub gamma: Option<f64>,
+    /// Replace every pixel by its luma.
+    pub grayscale: bool,
+}
+
+impl Preprocess {
+    /// Returns an error message if a setting is out of range.
+    pub fn check(&self) -> Result<(), String> {
+        if self.max_dimension == Some(0) {
+            return Err("The largest dimension must be at least 1 pixel".to_string());
+        }
+        if self
+            .gamma
+            .is_some_and(|gamma| !(gamma.is_finite() && gamma > 0.0))
+        {
+            return Err("The gamma must be a positive number".to_string());
+        }
+        Ok(())
+    }
+
+    /// Returns `image` after every step, or `image` itself when there is nothing to do.
+    ///
+    /// # Arguments
+    /// * `image`: Image about to be compressed
+    pub fn apply<'a>(&self, image: &'a RgbImage) -> Cow<'a, RgbImage> {
+        let mut image = Cow::Borrowed(image);
+        if let Some((width, height)) = self
+            .max_dimension
+            .and_then(|max| shrunk_size(image.width, image.height, max))
+        {
+            image = Cow::Owned(shrink(&image, width, height, image.denominator));
+        }
+        if self.rotation != Rotation::None {
+            image = Cow::Owned(rotate(&image, self.rotation));
+        }
+        if self.gamma.is_some() || self.grayscale {
+            image = Cow::Owned(self.adjust_pixels(&image));
+        }
+        image
+    }
+
+    /// Returns `image` with the gamma adjustment and the grayscale conversion applied to every
+    /// pixel.
+    ///
+    /// # Arguments
+    /// * `image`: Image to adjust
+    fn adjust_pixels(&self, image: &RgbImage) -> RgbImage {
+        let denominator = image.denominator.max(1) as f64;
+        let exponent = 1.0 / self.gamma.unwrap_or(1.0);
+        let adjust = |value: u16| {
+            if exponent == 1.0 {
+                value as f64
+            } else {
+                (value as f64 / denominator).powf(exponent) * denominator
+            }
+        };
+        let pixels = image
+            .pixels
+            .iter()
+            .map(|pixel| {
+                let channels = [pixel.red, pixel.green, pixel.blue].map(adjust);
+                let [red, green, blue] = if self.grayscale {
+                    [0.299 * channels[0] + 0.587 * channels[1] + 0.114 * channels[2]; 3]
+                } else {
+                    channels
+                }
+                .map(|value| value.round().min(denominator) as u16);
+                Rgb { red, green, blue }
+            })
+            .collect();
+        RgbImage { pixels, ..*image }
+    }
+}
+
+/// Returns the size of a `width` by `height` image shrunk so that neither side exceeds `max`,
+/// or None if it already fits.
+///
+/// # Arguments
+/// * `width`: Width of the image
+/// * `height`: Height of the image
+/// * `max`: Largest width and height, at least 1
+fn shrunk_size(width: u32, height: u32, max: u32) -> Option<(u32, u32)> {
+    let longest = width.max(height) as u64;
+    if longest <= max as u64 {
+        return None;
+    }
+    let scaled = |side: u32| ((side as u64 * max as u64 + longest / 2) / longest).max(1) as u32;
+    Some((scaled(width), scaled(height)))
+}
+
+/// Returns a copy of `image` rotated clockwise by `rotation`.
+///
+/// # Arguments
+/// * `image`: Image to rotate
+/// * `rotation`: Clockwise rotation
+fn rotate(image: &RgbImage, rotation: Rotation) -> RgbImage {
+    let (width, height) = (image.width as usize, image.height as usize);
+    let (rotated_width, rotated_height) = match rotation {
+        Rotation::Quarter | Rotation::ThreeQuarters => (height, width),
+        Rotation::None | Rotation::Half => (width, height),
+    };
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in 0..rotated_height {
+        for col in 0..rotated_width {
+            let (x, y) = match rotation {
+                Rotation::None => (col, row),
+                Rotation::Quarter => (row, height - 1 - col),
+                Rotation::Half => (width - 1 - col, height - 1 - row),
+                Rotation::ThreeQuarters => (width - 1 - row, col),
+            };
+            pixels.push(image.pixels[y * width + x].clone());
+        }
+    }
+    RgbImage {
+        pixels,
+        width: rotated_width as u32,
+        height: rotated_height as u32,
+        denominator: image.denominator,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_resize_rotate_and_adjust_the_image() {
+        let image = RgbImage {
+            pixels: (0..6 * 3)
+                .map(|i| Rgb {
+                    red: i * 10,
+                    green: 100,
+                    blue: 255 - i * 10,
+                })
+                .collect(),
+            width: 6,
+            height: 3,
+            denominator: 255,
+        };
+        assert!(matches!(
+            Preprocess::default().apply(&image),
+            Cow::Borrowed(_)
+        ));
+        let turned = Preprocess {
+            rotation: Rotation::Quarter,
+            ..Default::default()
+        }
+        .apply(&image);
+        assert_eq!((turned.width, turned.height), (3, 6));
+        assert_eq!(turned.pixels[0], image.pixels[2 * 6]);
+        assert_eq!(turned.pixels[2], image.pixels[0]);
+        let back = Preprocess {
+            rotation: Rotation::ThreeQuarters,
+            ..Default::default()
+        }
+        .apply(&turned);
+        assert_eq!(*back, image);
+        let halved = Preprocess {
+            max_dimension: Some(3),
+            ..Default::default()
+        }
+        .apply(&image);
+        assert_eq!((halved.width, halved.height), (3, 2));
+        let brightened = Preprocess {
+            gamma: Some(2.0),
+            ..Default::default()
+        }
+        .apply(&image);
+        assert_eq!(brightened.pixels[0].green, 160);
+        assert!(Preprocess {
+            gamma: Some(0.0),
+            ..Default::default()
+        }
+        .check()
+        .is_err());
+    }
+}
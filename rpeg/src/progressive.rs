@@ -0,0 +1,115 @@
+use crate::layout::WordLayout;
+use bitpack::bitpack::getu;
+
+/// Accumulates values of arbitrary bit width into a big-endian byte stream.
+struct BitWriter {
+    bytes: Vec<u8>,
+    pending: u64,
+    pending_bits: u64,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            pending: 0,
+            pending_bits: 0,
+        }
+    }
+
+    /// Appends the `width` least-significant bits of `value`, most-significant bit first.
+    fn write(&mut self, value: u64, width: u64) {
+        self.pending = (self.pending << width) | value;
+        self.pending_bits += width;
+        while self.pending_bits >= 8 {
+            self.pending_bits -= 8;
+            self.bytes
+                .push(getu(self.pending, 8, self.pending_bits) as u8);
+        }
+        self.pending &= (1 << self.pending_bits) - 1;
+    }
+
+    /// Flushes any pending bits, padding the last byte with zeros.
+    fn finish(mut self) -> Vec<u8> {
+        if self.pending_bits > 0 {
+            self.bytes
+                .push((self.pending << (8 - self.pending_bits)) as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Reads values of arbitrary bit width back out of a big-endian byte stream.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: u64,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, position: 0 }
+    }
+
+    /// Returns the next `width` bits, or None if the stream ends before all of them are available.
+    fn read(&mut self, width: u64) -> Option<u64> {
+        if self.position + width > self.bytes.len() as u64 * 8 {
+            return None;
+        }
+        let mut value = 0_u64;
+        let mut remaining = width;
+        while remaining > 0 {
+            let byte = self.bytes[(self.position / 8) as usize] as u64;
+            let offset = self.position % 8;
+            let take = remaining.min(8 - offset);
+            value = (value << take) | getu(byte, take, 8 - offset - take);
+            self.position += take;
+            remaining -= take;
+        }
+        Some(value)
+    }
+}
+
+/// Takes the code words of an image in row-major block order and reorders their bits so that
+/// the DC terms (`a` and both chroma values) of every block come first, followed by the b/c/d
+/// refinements of every block. The result has the same size as the sequential payload, up to
+/// the padding of the last byte.
+///
+/// # Arguments
+/// * `words`: Code words, one per 2x2 block
+/// * `layout`: Layout of the code words
+pub fn to_progressive(words: &[u64], layout: &WordLayout) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    for word in words.iter() {
+        writer.write(layout.split(*word).0, layout.dc_width());
+    }
+    for word in words.iter() {
+        writer.write(layout.split(*word).1, layout.ac_width());
+    }
+    writer.finish()
+}
+
+/// Takes a progressive payload, possibly truncated, and rebuilds the sequential code words of
+/// `block_count` blocks. Blocks whose DC terms are missing become black, and blocks whose
+/// refinements are missing are decoded as flat blocks.
+///
+/// # Arguments
+/// * `payload`: Bytes of a progressive payload, or any prefix of one
+/// * `block_count`: Number of 2x2 blocks in the image
+/// * `layout`: Layout of the code words
+pub fn from_progressive(payload: &[u8], block_count: usize, layout: &WordLayout) -> Vec<u64> {
+    let mut reader = BitReader::new(payload);
+    let dcs: Vec<Option<u64>> = (0..block_count)
+        .map(|_| reader.read(layout.dc_width()))
+        .collect();
+    // The refinements start right after the last DC term, even when that one was cut short.
+    reader.position = reader.position.max(block_count as u64 * layout.dc_width());
+    dcs.into_iter()
+        .map(|dc| {
+            let ac = reader.read(layout.ac_width());
+            match dc {
+                Some(dc) => layout.join(dc, ac),
+                None => layout.missing_word(),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,112 @@
+use crate::ppm::Rgb;
+use array2::array2::Array2;
+
+/// Stores the pixels of an image as they are, in row-major order: the red, green, and blue
+/// densities of every pixel scaled to `denominator`, one byte each when it is at most 255, and
+/// two big-endian bytes each otherwise. Images whose denominator is already `denominator`
+/// decode without loss.
+///
+/// # Arguments
+/// * `image`: Image, or tile of an image
+/// * `image_denominator`: Denominator of the Rgb values of the image
+/// * `denominator`: Denominator of the stored densities, that of the decoded image
+pub fn encode_raw(image: &Array2<Rgb>, image_denominator: u16, denominator: u16) -> Vec<u8> {
+    let scale = |value: u16| {
+        let scaled = (value as u64 * denominator as u64 + image_denominator.max(1) as u64 / 2)
+            / image_denominator.max(1) as u64;
+        scaled.min(denominator as u64) as u16
+    };
+    let mut output = Vec::with_capacity(image.data.len() * 3 * sample_bytes(denominator));
+    for (_, _, pixel) in image.iter_row_major() {
+        for value in [pixel.red, pixel.green, pixel.blue].map(scale) {
+            if denominator <= 255 {
+                output.push(value as u8);
+            } else {
+                output.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+    output
+}
+
+/// Decodes a stream written by `encode_raw` back into the pixels of the image.
+///
+/// # Arguments
+/// * `payload`: Raw stream, possibly truncated when `partial` is set
+/// * `width`: Width of the image in pixels
+/// * `height`: Height of the image in pixels
+/// * `denominator`: Denominator the densities were stored with
+/// * `partial`: Accept a truncated stream, leaving the pixels that have not arrived black
+pub fn decode_raw(
+    payload: &[u8],
+    width: usize,
+    height: usize,
+    denominator: u16,
+    partial: bool,
+) -> Result<Array2<Rgb>, String> {
+    let pixel_bytes = 3 * sample_bytes(denominator);
+    let expected_len = width * height * pixel_bytes;
+    if payload.len() > expected_len || (payload.len() < expected_len && !partial) {
+        return Err(format!(
+            "Expected {expected_len} bytes of raw pixels, found {}",
+            payload.len()
+        ));
+    }
+    let sample = |bytes: &[u8]| match bytes {
+        [high, low] => u16::from_be_bytes([*high, *low]),
+        [value] => *value as u16,
+        _ => 0,
+    };
+    let mut pixels: Vec<Rgb> = payload
+        .chunks_exact(pixel_bytes)
+        .map(|pixel| {
+            let mut samples = pixel.chunks_exact(pixel_bytes / 3).map(sample);
+            Rgb {
+                red: samples.next().unwrap_or_default(),
+                green: samples.next().unwrap_or_default(),
+                blue: samples.next().unwrap_or_default(),
+            }
+        })
+        .collect();
+    pixels.resize(width * height, Rgb::default());
+    Ok(Array2::from_row_major(width, height, pixels))
+}
+
+/// Returns the number of bytes every stored density takes.
+fn sample_bytes(denominator: u16) -> usize {
+    if denominator <= 255 {
+        1
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_round_trip_at_the_decoded_depth() {
+        let pixels: Vec<Rgb> = (0..6u16)
+            .map(|i| Rgb {
+                red: i * 40,
+                green: 255 - i,
+                blue: i * i,
+            })
+            .collect();
+        let image = Array2::from_row_major(3, 2, pixels);
+        let payload = encode_raw(&image, 255, 255);
+        assert_eq!(payload.len(), 18);
+        assert_eq!(decode_raw(&payload, 3, 2, 255, false).unwrap(), image);
+        assert!(decode_raw(&payload[..17], 3, 2, 255, false).is_err());
+        assert_eq!(
+            decode_raw(&payload[..9], 3, 2, 255, true).unwrap().data[5],
+            Rgb::default()
+        );
+
+        let deep = encode_raw(&image, 255, u16::MAX);
+        assert_eq!(deep.len(), 36);
+        let decoded = decode_raw(&deep, 3, 2, u16::MAX, false).unwrap();
+        assert_eq!(decoded.data[1].red, 40 * 257);
+    }
+}
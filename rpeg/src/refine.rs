@@ -0,0 +1,322 @@
+use crate::pixel::Pixel;
+use crate::ppm::RgbImage;
+use crate::tiles::Rect;
+use bitpack::bitpack::fitss;
+
+/// Number of bits giving the width of the corrections of a block.
+const WIDTH_BITS: u32 = 5;
+
+/// Widest correction a block can need: every difference of two 16-bit channels, divided by a
+/// step of at least 1, fits in 17 signed bits.
+const MAX_WIDTH: u32 = 17;
+
+/// Largest bound `Refinement` takes, in steps of the decoded densities.
+pub const MAX_ERROR: u8 = 127;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Corrections bounding the error of every decoded channel
+///
+/// `max_error` is the largest difference left between a channel of the decoded image and the
+/// original, in steps of the decoded densities, from 0 for a lossless image to `MAX_ERROR`.
+/// `bits` holds, for every 2x2 block in row-major block order, the width of its corrections in
+/// 5 bits, followed by the correction of every channel of its pixels inside the image, in
+/// row-major pixel order, as signed values of that width. A correction of q moves its channel
+/// by q times 2 * `max_error` + 1, which brings it within `max_error` of the original, so the
+/// blocks the code words already decode closely enough take their 5 bits alone.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::ppm::{Rgb, RgbImage};
+/// use rpeg::refine::Refinement;
+///
+/// let image = |value| RgbImage {
+///     pixels: vec![Rgb { red: value, green: 20, blue: 30 }; 4],
+///     width: 2,
+///     height: 2,
+///     denominator: 255,
+/// };
+/// let refinement = Refinement::compute(&image(40), &image(48), 2);
+/// let mut decoded = image(48);
+/// refinement.apply(&mut decoded, 2, 2, None).unwrap();
+/// assert_eq!(decoded.pixels[0].red, 38);
+/// ```
+pub struct Refinement {
+    pub max_error: u8,
+    pub bits: Vec<u8>,
+}
+
+impl Refinement {
+    /// Returns the corrections that bring every channel of `decoded` within `max_error` of
+    /// `original`.
+    ///
+    /// # Arguments
+    /// * `original`: Image that was compressed, with the dimensions and the denominator of the
+    ///   decoded image
+    /// * `decoded`: Image as the code words decode it
+    /// * `max_error`: Largest difference left, at most `MAX_ERROR`
+    pub fn compute(original: &RgbImage, decoded: &RgbImage, max_error: u8) -> Refinement {
+        assert!(
+            max_error <= MAX_ERROR,
+            "The max error must be at most {MAX_ERROR}"
+        );
+        let (width, height) = (decoded.width, decoded.height);
+        let step = 2 * max_error as i32 + 1;
+        let mut writer = BitWriter::default();
+        for row in 0..height.div_ceil(2) {
+            for column in 0..width.div_ceil(2) {
+                let corrections: Vec<i32> = block_pixels(column, row, width, height)
+                    .flat_map(|index| {
+                        let (original, decoded) = (&original.pixels[index], &decoded.pixels[index]);
+                        (0..3).map(|channel| {
+                            let difference =
+                                original.channel(channel) as i32 - decoded.channel(channel) as i32;
+                            div_round(difference, step)
+                        })
+                    })
+                    .collect();
+                let bits = if corrections.iter().all(|correction| *correction == 0) {
+                    0
+                } else {
+                    (1..=MAX_WIDTH as u64)
+                        .find(|bits| {
+                            corrections
+                                .iter()
+                                .all(|correction| fitss(*correction as i64, *bits))
+                        })
+                        .unwrap_or(MAX_WIDTH as u64) as u32
+                };
+                writer.write(bits as u64, WIDTH_BITS);
+                for correction in corrections.iter().filter(|_| bits > 0) {
+                    writer.write(*correction as u64, bits);
+                }
+            }
+        }
+        Refinement {
+            max_error,
+            bits: writer.bytes,
+        }
+    }
+
+    /// Applies the corrections to the pixels of `image`, the rectangle `rect` of a decoded image
+    /// of `width` by `height` pixels, or the whole of it when `rect` is None. Returns an error
+    /// when the corrections end before the last block of the rectangle.
+    ///
+    /// # Arguments
+    /// * `image`: Decoded pixels
+    /// * `width`: Width of the whole image in pixels
+    /// * `height`: Height of the whole image in pixels
+    /// * `rect`: Rectangle of the image `image` holds, or None for the whole image
+    pub fn apply(
+        &self,
+        image: &mut RgbImage,
+        width: u32,
+        height: u32,
+        rect: Option<&Rect>,
+    ) -> Result<(), String> {
+        let whole = Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        self.apply_from(image, width, height, rect.unwrap_or(&whole), (0, 0))
+    }
+
+    /// Returns the position, in bits, of the corrections of the first block of every row of
+    /// blocks of a decoded image of `width` by `height` pixels, so that the rows of a band can
+    /// be corrected without reading the rows above it.
+    ///
+    /// # Arguments
+    /// * `width`: Width of the image in pixels
+    /// * `height`: Height of the image in pixels
+    pub(crate) fn row_offsets(&self, width: u32, height: u32) -> Result<Vec<usize>, String> {
+        let mut reader = BitReader {
+            bytes: &self.bits,
+            pos: 0,
+        };
+        let mut offsets = Vec::with_capacity(height.div_ceil(2) as usize);
+        for row in 0..height.div_ceil(2) {
+            offsets.push(reader.pos);
+            for column in 0..width.div_ceil(2) {
+                let bits = reader.read_width()? as usize;
+                reader.pos += block_pixels(column, row, width, height).count() * 3 * bits;
+            }
+        }
+        if reader.pos > self.bits.len() * 8 {
+            return Err("Ran out of bytes while reading the refinement".to_string());
+        }
+        Ok(offsets)
+    }
+
+    /// Returns the corrections of the blocks inside `rect`, a rectangle of whole 2x2 blocks of a
+    /// decoded image of `width` by `height` pixels, for the image cropped to it.
+    ///
+    /// # Arguments
+    /// * `width`: Width of the image in pixels
+    /// * `height`: Height of the image in pixels
+    /// * `rect`: Rectangle kept, starting on even coordinates
+    pub(crate) fn crop(&self, width: u32, height: u32, rect: &Rect) -> Result<Refinement, String> {
+        let mut reader = BitReader {
+            bytes: &self.bits,
+            pos: 0,
+        };
+        let mut writer = BitWriter::default();
+        for row in 0..height.div_ceil(2) {
+            for column in 0..width.div_ceil(2) {
+                let bits = reader.read_width()?;
+                let count = match bits {
+                    0 => 0,
+                    _ => block_pixels(column, row, width, height).count() * 3,
+                };
+                let inside = (rect.x / 2..(rect.x + rect.width).div_ceil(2)).contains(&column)
+                    && (rect.y / 2..(rect.y + rect.height).div_ceil(2)).contains(&row);
+                if inside {
+                    writer.write(bits as u64, WIDTH_BITS);
+                }
+                for _ in 0..count {
+                    let correction = reader.read(bits)?;
+                    if inside {
+                        writer.write(correction, bits);
+                    }
+                }
+            }
+        }
+        Ok(Refinement {
+            max_error: self.max_error,
+            bits: writer.bytes,
+        })
+    }
+
+    /// Applies the corrections like `apply`, starting from the row of blocks `start.0`, whose
+    /// corrections start at bit `start.1`, which must not lie below the top of `rect`.
+    ///
+    /// # Arguments
+    /// * `image`: Decoded pixels of `rect`
+    /// * `width`: Width of the whole image in pixels
+    /// * `height`: Height of the whole image in pixels
+    /// * `rect`: Rectangle of the image `image` holds
+    /// * `start`: Row of blocks the corrections are read from, and their position in bits
+    pub(crate) fn apply_from(
+        &self,
+        image: &mut RgbImage,
+        width: u32,
+        height: u32,
+        rect: &Rect,
+        start: (u32, usize),
+    ) -> Result<(), String> {
+        let step = 2 * self.max_error as i64 + 1;
+        let denominator = image.denominator as i64;
+        let mut reader = BitReader {
+            bytes: &self.bits,
+            pos: start.1,
+        };
+        let end = (rect.y + rect.height).div_ceil(2).min(height.div_ceil(2));
+        for row in start.0..end {
+            for column in 0..width.div_ceil(2) {
+                let bits = reader.read_width()?;
+                for index in block_pixels(column, row, width, height) {
+                    let (x, y) = (index as u32 % width, index as u32 / width);
+                    let inside = x >= rect.x
+                        && x < rect.x + rect.width
+                        && y >= rect.y
+                        && y < rect.y + rect.height;
+                    for channel in 0..3 {
+                        let correction = match bits {
+                            0 => 0,
+                            bits => reader.read_signed(bits)?,
+                        };
+                        if inside && correction != 0 {
+                            let position =
+                                (y - rect.y) as usize * rect.width as usize + (x - rect.x) as usize;
+                            let pixel = &mut image.pixels[position];
+                            let value = (correction as i64)
+                                .checked_mul(step)
+                                .and_then(|shift| shift.checked_add(pixel.channel(channel) as i64))
+                                .ok_or("The refinement overflows a channel")?;
+                            pixel.set_channel(channel, value.clamp(0, denominator) as u16);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Divides `numerator` by a positive `denominator`, rounding to the nearest integer; for an odd
+/// `denominator`, the remainder is then at most half of it.
+fn div_round(numerator: i32, denominator: i32) -> i32 {
+    (2 * numerator + denominator).div_euclid(2 * denominator)
+}
+
+/// Returns the row-major indices of the pixels inside an image of `width` by `height` pixels of
+/// the 2x2 block at `column` and `row`, counted in blocks, in row-major pixel order.
+fn block_pixels(column: u32, row: u32, width: u32, height: u32) -> impl Iterator<Item = usize> {
+    (0..4)
+        .map(move |offset| (2 * column + offset % 2, 2 * row + offset / 2))
+        .filter(move |(x, y)| *x < width && *y < height)
+        .map(move |(x, y)| y as usize * width as usize + x as usize)
+}
+
+#[derive(Default)]
+/// Writer of values of any width up to 32 bits, most significant bit first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// Number of bits written.
+    len: usize,
+}
+
+impl BitWriter {
+    /// Appends the low `width` bits of `value`.
+    fn write(&mut self, value: u64, width: u32) {
+        for bit in (0..width).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if let Some(byte) = self.bytes.last_mut().filter(|_| value >> bit & 1 != 0) {
+                *byte |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+}
+
+/// Reader of the values a `BitWriter` wrote.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    /// Number of bits read.
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    /// Returns the next `width` bits as an unsigned value.
+    fn read(&mut self, width: u32) -> Result<u64, String> {
+        let mut value = 0;
+        for _ in 0..width {
+            let byte = self
+                .bytes
+                .get(self.pos / 8)
+                .ok_or("Ran out of bytes while reading the refinement")?;
+            value = value << 1 | (byte >> (7 - self.pos % 8) & 1) as u64;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+
+    /// Returns the next width of the corrections of a block, or an error when it is wider than
+    /// any correction can need.
+    fn read_width(&mut self) -> Result<u32, String> {
+        let width = self.read(WIDTH_BITS)? as u32;
+        if width > MAX_WIDTH {
+            return Err(format!("Invalid refinement width {width}"));
+        }
+        Ok(width)
+    }
+
+    /// Returns the next `width` bits as a signed value in two's complement.
+    fn read_signed(&mut self, width: u32) -> Result<i32, String> {
+        let value = self.read(width)?;
+        Ok(((value << (64 - width)) as i64 >> (64 - width)) as i32)
+    }
+}
@@ -0,0 +1,211 @@
+use crate::codec::{read_code_words, read_prelude, write_code_words, DecodeOptions};
+use crate::content::TileMode;
+use crate::error::{CliError, ErrorKind};
+use crate::io::{read_input, Output};
+use crate::orientation::Orientation;
+use crate::structs::QuantizedBlock;
+
+/// Returns the quantized values of a block whose four pixels were turned by `orientation`. The
+/// average luma and chroma stay, while b, the bottom row minus the top one, c, the right
+/// column minus the left one, and d, the diagonal difference, are swapped and negated. Their
+/// quantization is symmetric, so that no level is lost.
+///
+/// # Arguments
+/// * `block`: Quantized values of the block
+/// * `orientation`: Transform applied to the image
+/// * `levels`: Largest magnitude of b, c, and d, which a negated value is clamped to
+fn turn_block(block: QuantizedBlock, orientation: Orientation, levels: i16) -> QuantizedBlock {
+    let negate = |value: i16| (-value).min(levels);
+    let QuantizedBlock { b, c, d, .. } = block;
+    let (b, c, d) = match orientation {
+        Orientation::Normal => (b, c, d),
+        Orientation::Mirror => (b, negate(c), negate(d)),
+        Orientation::Rotate180 => (negate(b), negate(c), d),
+        Orientation::MirrorRotate180 => (negate(b), c, negate(d)),
+        Orientation::MirrorRotate270 => (c, b, d),
+        Orientation::Rotate90 => (c, negate(b), negate(d)),
+        Orientation::MirrorRotate90 => (negate(c), negate(b), d),
+        Orientation::Rotate270 => (negate(c), b, negate(d)),
+    };
+    QuantizedBlock { b, c, d, ..block }
+}
+
+/// Returns whether `orientation` moves the last column or row of a `width` by `height` image,
+/// where an odd side is padded to whole blocks, to the left or the top, where the decoder does
+/// not trim it.
+fn moves_padding(orientation: Orientation, width: u32, height: u32) -> bool {
+    let (odd_width, odd_height) = (width % 2 == 1, height % 2 == 1);
+    match orientation {
+        Orientation::Normal | Orientation::MirrorRotate270 => false,
+        Orientation::Mirror | Orientation::Rotate270 => odd_width,
+        Orientation::MirrorRotate180 | Orientation::Rotate90 => odd_height,
+        Orientation::Rotate180 | Orientation::MirrorRotate90 => odd_width || odd_height,
+    }
+}
+
+/// Turns or mirrors a compressed image by `orientation` on its grid of code words rather than
+/// on its pixels, so that orientation fixes add no generation loss: every block moves to its
+/// new position, with its b, c, and d swapped and negated to match, and the result decodes to
+/// the decoded pixels of the image, turned. The level map of the regions of interest and the
+/// thumbnail are turned along, and the image is split again into tiles of the same size. The
+/// refinement of a near-lossless image is dropped, which leaves the error of the code words.
+/// Returns an error for palette-coded, text, and raw tiles, whose content is not laid out in
+/// words or depends on the tile grid, and when an odd side would move its padding.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+/// * `orientation`: Transform applied to the image, as `Orientation::apply` does to pixels
+pub fn reorient_image(bytes: &[u8], orientation: Orientation) -> Result<Vec<u8>, String> {
+    let levels = read_prelude(bytes, &DecodeOptions::default())?.levels;
+    let (mut header, words) = read_code_words(bytes)?;
+    if header
+        .tile_modes
+        .iter()
+        .any(|mode| *mode != TileMode::Photo)
+    {
+        return Err("Text tiles cannot be turned in the compressed domain".to_string());
+    }
+    let layout = header.layout;
+    if layout.b.width != layout.c.width {
+        return Err("Turning the code words needs b and c fields of the same width".to_string());
+    }
+    if moves_padding(orientation, header.width, header.height) {
+        return Err(format!(
+            "A {}x{} image cannot be turned this way without moving its padding",
+            header.width, header.height
+        ));
+    }
+    let (columns, rows) = (
+        header.coded_width() as usize / 2,
+        header.coded_height() as usize / 2,
+    );
+    let bcd_levels = layout.bcd_levels() as i16;
+    let words: Vec<u64> = orientation
+        .remap(&words, columns, rows)
+        .into_iter()
+        .map(|word| layout.pack(&turn_block(layout.unpack(word), orientation, bcd_levels)))
+        .collect();
+    let levels = orientation.remap(&levels, columns, rows);
+    header.thumbnail = header
+        .thumbnail
+        .map(|thumbnail| orientation.apply(&thumbnail));
+    header.refinement = None;
+    (header.width, header.height) = orientation.dimensions(header.width, header.height);
+    Ok(write_code_words(header, &levels, &words))
+}
+
+/// Turns the compressed image `filename` by every orientation of `steps` in turn with
+/// `reorient_image`, and writes the result to `output`. Failures are returned with the
+/// category the CLI exits with.
+///
+/// # Arguments
+/// * `filename`: Compressed image to turn, or None to read from standard in
+/// * `output`: Destination of the turned image
+/// * `steps`: Transforms applied one after the other
+pub fn reorient(
+    filename: Option<&str>,
+    output: &Output,
+    steps: &[Orientation],
+) -> Result<(), CliError> {
+    let mut bytes = read_input(filename)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    for orientation in steps {
+        bytes = reorient_image(&bytes, *orientation).map_err(|message| {
+            if read_code_words(&bytes).is_ok() {
+                CliError::new(ErrorKind::BadArguments, message)
+            } else {
+                CliError::stream(&bytes, message)
+            }
+        })?;
+    }
+    output
+        .write(&bytes)
+        .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{compress_image, decompress_image, EncoderOptions};
+    use crate::encoder::PadPolicy;
+    use crate::ppm::{Rgb, RgbImage};
+    use crate::roi::Region;
+
+    #[test]
+    fn turned_code_words_decode_to_the_turned_pixels() {
+        let image = RgbImage {
+            pixels: (0..20 * 12_u16)
+                .map(|i| Rgb {
+                    red: (i % 20) * 12,
+                    green: (i / 20) * 20,
+                    blue: (i * 37) % 256,
+                })
+                .collect(),
+            width: 20,
+            height: 12,
+            denominator: 255,
+        };
+        let options = EncoderOptions {
+            tile_size: 8,
+            progressive: true,
+            regions: vec![Region {
+                x: 0,
+                y: 0,
+                width: 8,
+                height: 4,
+                quality: 90,
+            }],
+            embed_thumbnail: true,
+            ..EncoderOptions::default()
+        };
+        let compressed = compress_image(&image, &options);
+        let decoded = decompress_image(&compressed).unwrap();
+        for tag in 1..=8 {
+            let orientation = Orientation::from_tag(tag).unwrap();
+            let turned = reorient_image(&compressed, orientation).unwrap();
+            let turned = decompress_image(&turned).unwrap();
+            let expected = orientation.apply(&decoded);
+            assert_eq!(
+                (turned.width, turned.height),
+                (expected.width, expected.height)
+            );
+            // The inverse transform adds and subtracts in another order, so the truncated
+            // pixels can differ by one.
+            let close = turned.pixels.iter().zip(&expected.pixels).all(|(a, b)| {
+                [(a.red, b.red), (a.green, b.green), (a.blue, b.blue)]
+                    .iter()
+                    .all(|(a, b)| a.abs_diff(*b) <= 1)
+            });
+            assert!(close, "orientation {tag}");
+        }
+        // Four quarter turns, or two mirrors, give the original code words back.
+        let mut turned = compressed.clone();
+        for _ in 0..4 {
+            turned = reorient_image(&turned, Orientation::Rotate90).unwrap();
+        }
+        assert_eq!(turned, compressed);
+        let mirrored = reorient_image(&compressed, Orientation::Mirror).unwrap();
+        assert_ne!(mirrored, compressed);
+        assert_eq!(
+            reorient_image(&mirrored, Orientation::Mirror),
+            Ok(compressed)
+        );
+
+        let odd = RgbImage {
+            width: 19,
+            pixels: (0..19 * 12)
+                .map(|i| image.pixels[i / 19 * 20 + i % 19].clone())
+                .collect(),
+            ..image
+        };
+        let odd = compress_image(
+            &odd,
+            &EncoderOptions {
+                pad: PadPolicy::Replicate,
+                ..EncoderOptions::default()
+            },
+        );
+        assert!(reorient_image(&odd, Orientation::Rotate90).is_ok());
+        assert!(reorient_image(&odd, Orientation::Mirror).is_err());
+    }
+}
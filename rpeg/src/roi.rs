@@ -0,0 +1,160 @@
+use crate::dct_coeff::luma_range_for_quality;
+use scan_fmt::scan_fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Represent a region of interest of the image
+///
+/// Blocks touching the rectangle are quantized with `quality` instead of the background
+/// quality. The rectangle is given in pixels, with `x` and `y` being its top-left corner.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::roi::Region;
+///
+/// let face = Region { x: 10, y: 20, width: 64, height: 64, quality: 90 };
+/// assert_eq!(Region::parse("10,20,64,64:90"), Ok(face));
+/// ```
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub quality: u8,
+}
+
+impl Region {
+    /// Parses a region written as `x,y,w,h:quality`.
+    ///
+    /// # Arguments
+    /// * `text`: Region description, as passed to `--roi`
+    pub fn parse(text: &str) -> Result<Region, String> {
+        let (x, y, width, height, quality) =
+            scan_fmt!(text, "{d},{d},{d},{d}:{d}", u32, u32, u32, u32, u8)
+                .map_err(|_| format!("Expected a region as x,y,w,h:quality, found \"{text}\""))?;
+        if quality > 100 {
+            return Err(format!(
+                "Region quality must be at most 100, found {quality}"
+            ));
+        }
+
+        Ok(Region {
+            x,
+            y,
+            width,
+            height,
+            quality,
+        })
+    }
+
+    /// Returns true iff the 2x2 block at block column `col` and block row `row` overlaps the region.
+    fn covers_block(&self, col: u32, row: u32) -> bool {
+        let (left, top) = (col * 2, row * 2);
+        left + 2 > self.x
+            && left < self.x.saturating_add(self.width)
+            && top + 2 > self.y
+            && top < self.y.saturating_add(self.height)
+    }
+}
+
+/// Takes the regions of interest of an image and returns, for every 2x2 block in row-major order,
+/// the level it is quantized with: 0 for the background, or `i + 1` for `regions[i]`. A block
+/// covered by several regions takes the level of the one with the highest quality.
+///
+/// # Arguments
+/// * `regions`: Regions of interest, at most 255 of them
+/// * `width`: Width of the (trimmed) image in pixels
+/// * `height`: Height of the (trimmed) image in pixels
+pub fn block_levels(regions: &[Region], width: usize, height: usize) -> Vec<u8> {
+    let mut levels = Vec::with_capacity((width / 2) * (height / 2));
+    for row in 0..(height / 2) as u32 {
+        for col in 0..(width / 2) as u32 {
+            let level = regions
+                .iter()
+                .enumerate()
+                .filter(|(_, region)| region.covers_block(col, row))
+                .max_by_key(|(_, region)| region.quality)
+                .map_or(0, |(i, _)| i + 1);
+            levels.push(level as u8);
+        }
+    }
+    levels
+}
+
+/// Takes the level of every block and the quality of every region, and returns the range b, c,
+/// and d of every block are clamped to.
+///
+/// # Arguments
+/// * `levels`: Quantization level of every block, as returned by `block_levels`
+/// * `background`: Range the blocks outside of every region are clamped to
+/// * `qualities`: Quality of every region, in the order the levels refer to them
+pub fn luma_ranges(levels: &[u8], background: f64, qualities: &[u8]) -> Vec<f64> {
+    let ranges: Vec<f64> = std::iter::once(background)
+        .chain(
+            qualities
+                .iter()
+                .map(|quality| luma_range_for_quality(*quality)),
+        )
+        .collect();
+    levels.iter().map(|level| ranges[*level as usize]).collect()
+}
+
+/// Appends the run-length encoded level of every block to `out`: a 32-bit count of runs followed
+/// by each run as an 8-bit level and a 32-bit length, all in Bigendian format.
+///
+/// # Arguments
+/// * `levels`: Quantization level of every block
+/// * `out`: Buffer receiving the encoded levels
+pub fn write_level_map(levels: &[u8], out: &mut Vec<u8>) {
+    let mut runs: Vec<(u8, u32)> = Vec::new();
+    for level in levels.iter() {
+        match runs.last_mut() {
+            Some((last, length)) if last == level => *length += 1,
+            _ => runs.push((*level, 1)),
+        }
+    }
+    out.extend_from_slice(&(runs.len() as u32).to_be_bytes());
+    for (level, length) in runs.iter() {
+        out.push(*level);
+        out.extend_from_slice(&length.to_be_bytes());
+    }
+}
+
+/// Parses a level map written by `write_level_map` at the start of `bytes`. Returns the level of
+/// every block and the number of bytes the map took.
+///
+/// # Arguments
+/// * `bytes`: Bytes starting with an encoded level map
+/// * `block_count`: Number of 2x2 blocks in the image
+/// * `region_count`: Number of regions the levels may refer to
+pub fn read_level_map(
+    bytes: &[u8],
+    block_count: usize,
+    region_count: usize,
+) -> Result<(Vec<u8>, usize), String> {
+    let truncated = || "Ran out of bytes while reading the region map".to_string();
+    let count: [u8; 4] = bytes
+        .get(0..4)
+        .and_then(|count| count.try_into().ok())
+        .ok_or_else(truncated)?;
+    let mut levels = Vec::with_capacity(block_count);
+    let mut pos = 4;
+    for _ in 0..u32::from_be_bytes(count) {
+        let Some(&[level, a, b, c, d]) = bytes.get(pos..pos + 5) else {
+            return Err(truncated());
+        };
+        let length = u32::from_be_bytes([a, b, c, d]) as usize;
+        if level as usize > region_count {
+            return Err(format!("Region map refers to unknown region {level}"));
+        }
+        if levels.len() + length > block_count {
+            return Err("Region map covers more blocks than the image has".to_string());
+        }
+        levels.resize(levels.len() + length, level);
+        pos += 5;
+    }
+    if levels.len() != block_count {
+        return Err("Region map covers fewer blocks than the image has".to_string());
+    }
+    Ok((levels, pos))
+}
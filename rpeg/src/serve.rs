@@ -0,0 +1,643 @@
+use crate::codec::{decode_memory, decompress_with_options, DecodeOptions, EncoderOptions};
+use crate::color_tag::ColorTag;
+use crate::encoder::Encoder;
+use crate::format::Header;
+use crate::io::notice;
+use crate::png_image::{
+    read_png_color_tag, read_png_or_ppm, read_png_or_ppm_dimensions, write_png, PNG_SIGNATURE,
+};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Largest request body accepted, in bytes.
+pub const MAX_BODY_SIZE: usize = 256 << 20;
+
+/// Largest image `POST /compress` accepts, in pixels: 4096x4096.
+pub const MAX_UPLOAD_PIXELS: u64 = 1 << 24;
+
+/// Memory a decode of `POST /decompress` may take when the decode options set no
+/// `max_memory`, enough for images of about 13 megapixels.
+pub const DEFAULT_UPLOAD_MEMORY: u64 = 1 << 30;
+
+/// Largest number of connections answered at once; the others are answered with status 503.
+pub const MAX_CONNECTIONS: usize = 16;
+
+/// Memory the connections answered at once take together, for their bodies and for the images
+/// they compress or decompress; a request that would take more is answered with status 503.
+pub const MAX_SERVICE_MEMORY: u64 = 4 << 30;
+
+/// Memory a compression takes per pixel of its image, about 28 bytes on `original.ppm`.
+const COMPRESS_BYTES_PER_PIXEL: u64 = 32;
+
+/// Longest wait for a read or a write on a connection, after which it is dropped.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest time a client may take to send its whole request, head and body, however slowly
+/// the bytes arrive; it is then answered with status 408.
+const REQUEST_DEADLINE: Duration = Duration::from_secs(60);
+
+/// Largest request line or header accepted, in bytes.
+const MAX_LINE_SIZE: usize = 8 << 10;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Answer of the service to a request
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::codec::{DecodeOptions, EncoderOptions};
+/// use rpeg::serve::handle;
+///
+/// let response = handle("GET", "/", b"", &EncoderOptions::default(), &DecodeOptions::default());
+/// assert_eq!(response.status, 404);
+/// ```
+pub struct Response {
+    /// HTTP status code.
+    pub status: u16,
+    /// Media type of the body.
+    pub content_type: &'static str,
+    /// Body of the response.
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: Vec<u8>) -> Self {
+        Response {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{message}\n").into_bytes(),
+        }
+    }
+
+    /// Writes the status line, the headers, and the body of the response.
+    fn write_to(&self, mut writer: impl Write) -> std::io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            408 => "Request Timeout",
+            405 => "Method Not Allowed",
+            411 => "Length Required",
+            413 => "Payload Too Large",
+            503 => "Service Unavailable",
+            _ => "Error",
+        };
+        write!(
+            writer,
+            "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        )?;
+        writer.write_all(&self.body)?;
+        writer.flush()
+    }
+}
+
+/// Answers one request of the service:
+/// * `POST /compress` compresses the PPM or PNG image in the body with `encoder_options`;
+///   the ICC profile or sRGB chunk of a PNG image is kept as its color tag, unless
+///   `encoder_options` set one. Images of more than `MAX_UPLOAD_PIXELS` are rejected from
+///   their header, before their pixels are decoded;
+/// * `POST /decompress` decompresses the rpeg image in the body with `decode_options`, into a
+///   PNG image tagged with the color space of the header. Decodes take at most the
+///   `max_memory` of `decode_options`, or `DEFAULT_UPLOAD_MEMORY`.
+///
+/// Invalid images are answered with status 400 and the error message.
+///
+/// # Arguments
+/// * `method`: Method of the request
+/// * `path`: Target of the request, whose query is ignored
+/// * `body`: Body of the request
+/// * `encoder_options`: Settings used to compress the images
+/// * `decode_options`: Settings used to decompress the images
+pub fn handle(
+    method: &str,
+    path: &str,
+    body: &[u8],
+    encoder_options: &EncoderOptions,
+    decode_options: &DecodeOptions,
+) -> Response {
+    let path = path.split('?').next().unwrap_or_default();
+    if path != "/compress" && path != "/decompress" {
+        return Response::error(404, "Use POST /compress or POST /decompress");
+    }
+    if method != "POST" {
+        return Response::error(405, "Only POST is supported");
+    }
+    let result = if path == "/compress" {
+        check_upload_dimensions(body)
+            .and_then(|_| read_png_or_ppm(body))
+            .and_then(|image| {
+                let mut options = encoder_options.clone();
+                if body.starts_with(&PNG_SIGNATURE)
+                    && ColorTag::from_metadata(&options.metadata)?.is_none()
+                {
+                    if let Some(tag) = read_png_color_tag(body)? {
+                        options.metadata = tag.replace_in(&options.metadata);
+                    }
+                }
+                Encoder::from(options).compress(&image)
+            })
+            .map(|compressed| Response::ok("application/octet-stream", compressed))
+    } else {
+        let decode_options = DecodeOptions {
+            max_memory: Some(decode_options.max_memory.unwrap_or(DEFAULT_UPLOAD_MEMORY)),
+            ..decode_options.clone()
+        };
+        decompress_with_options(body, &decode_options)
+            .and_then(|image| {
+                let tag = ColorTag::from_metadata(&Header::read(body)?.0.metadata)?;
+                write_png(&image, tag.as_ref())
+            })
+            .map(|png| Response::ok("image/png", png))
+    };
+    result.unwrap_or_else(|message| Response::error(400, &message))
+}
+
+/// Returns an error when the PPM or PNG image of `body` holds more than `MAX_UPLOAD_PIXELS`.
+fn check_upload_dimensions(body: &[u8]) -> Result<(), String> {
+    let (width, height) = read_png_or_ppm_dimensions(body)?;
+    if width as u64 * height as u64 > MAX_UPLOAD_PIXELS {
+        return Err(format!(
+            "The image is {width}x{height}, more than the {MAX_UPLOAD_PIXELS} pixels the service compresses"
+        ));
+    }
+    Ok(())
+}
+
+/// Reads one line of the request head, without its line ending.
+fn read_line(reader: &mut impl BufRead) -> Result<String, Response> {
+    let mut line = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_LINE_SIZE as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .map_err(read_error)?;
+    if line.len() > MAX_LINE_SIZE || !line.ends_with(b"\n") {
+        return Err(Response::error(400, "Malformed request head"));
+    }
+    let line =
+        String::from_utf8(line).map_err(|_| Response::error(400, "Malformed request head"))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Returns the response to a request that could not be read: status 408 once the client took
+/// too long, and 400 otherwise.
+fn read_error(error: std::io::Error) -> Response {
+    match error.kind() {
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => {
+            Response::error(408, "The request took too long to arrive")
+        }
+        _ => Response::error(400, &error.to_string()),
+    }
+}
+
+/// Request of a client, holding the memory of its body.
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+    _memory: Reservation,
+}
+
+/// Reads the method, the target, and the body of an HTTP/1.1 request, or returns the error
+/// response to send instead. The memory of the body is taken from `memory` before it is read.
+///
+/// # Arguments
+/// * `reader`: Reader of the connection
+/// * `memory`: Memory budget shared by the connections
+fn read_request(reader: &mut impl BufRead, memory: &Pool) -> Result<Request, Response> {
+    let request_line = read_line(reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(Response::error(400, "Malformed request line"));
+    };
+    let mut content_length = None;
+    loop {
+        let header = read_line(reader)?;
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                let length = value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| Response::error(400, "Invalid Content-Length"))?;
+                content_length = Some(length);
+            }
+        }
+    }
+    let mut body = Vec::new();
+    let mut reserved = memory.take(0).ok_or_else(out_of_memory)?;
+    if method == "POST" {
+        let length =
+            content_length.ok_or_else(|| Response::error(411, "Missing Content-Length"))?;
+        if length > MAX_BODY_SIZE {
+            return Err(Response::error(413, "The upload is too large"));
+        }
+        reserved = memory.take(length as u64).ok_or_else(out_of_memory)?;
+        // The body grows with the bytes that arrive, not with the length the client claims.
+        let read = reader
+            .take(length as u64)
+            .read_to_end(&mut body)
+            .map_err(read_error)?;
+        if read < length {
+            return Err(Response::error(
+                400,
+                "The body is shorter than its Content-Length",
+            ));
+        }
+    }
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        body,
+        _memory: reserved,
+    })
+}
+
+/// Returns the response to a request that does not fit the memory left to the service.
+fn out_of_memory() -> Response {
+    Response::error(503, "The service is out of memory, try again later")
+}
+
+/// Returns the memory `handle` takes to answer a request beyond its body: that of compressing
+/// the image of `body`, or of decompressing it with `decode_options`. Bodies that cannot be
+/// parsed take none, as `handle` rejects them from their first bytes.
+fn work_memory(path: &str, body: &[u8], decode_options: &DecodeOptions) -> u64 {
+    if path.split('?').next() == Some("/compress") {
+        read_png_or_ppm_dimensions(body).map_or(0, |(width, height)| {
+            (width as u64 * height as u64).saturating_mul(COMPRESS_BYTES_PER_PIXEL)
+        })
+    } else {
+        let limit = decode_options.max_memory.unwrap_or(DEFAULT_UPLOAD_MEMORY);
+        Header::read(body).map_or(0, |(header, _)| {
+            decode_memory(&header, decode_options).min(limit)
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+/// ## Resource shared by the connections, counted as an amount up to a limit
+///
+/// Holds the connections answered at once, or the bytes of memory they take. Clones share
+/// the count.
+///
+/// # Usage Example
+///
+/// ```ignore
+/// let pool = Pool::new(2);
+/// let taken = pool.take(2).unwrap();
+/// assert!(pool.take(1).is_none());
+/// drop(taken);
+/// assert!(pool.take(1).is_some());
+/// ```
+struct Pool {
+    used: Arc<AtomicU64>,
+    limit: u64,
+}
+
+impl Pool {
+    /// Returns a pool of which nothing is taken.
+    fn new(limit: u64) -> Self {
+        Pool {
+            used: Arc::new(AtomicU64::new(0)),
+            limit,
+        }
+    }
+
+    /// Takes `amount` of the pool, or returns None when less than that is left.
+    fn take(&self, amount: u64) -> Option<Reservation> {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(amount).filter(|used| *used <= self.limit)
+            })
+            .ok()
+            .map(|_| Reservation {
+                used: Arc::clone(&self.used),
+                amount,
+            })
+    }
+}
+
+/// Amount taken from a `Pool`, given back when dropped.
+#[derive(Debug)]
+struct Reservation {
+    used: Arc<AtomicU64>,
+    amount: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.used.fetch_sub(self.amount, Ordering::AcqRel);
+    }
+}
+
+/// Reader of a connection that fails with `TimedOut` once `deadline` has passed, so that a
+/// client sending a byte at a time cannot hold it open.
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left.min(IO_TIMEOUT)))?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+/// Answers the single request of a connection, which must arrive within `deadline` and fit
+/// the memory left in `memory`.
+///
+/// # Arguments
+/// * `stream`: Connection of the client
+/// * `encoder_options`: Settings used to compress the images
+/// * `decode_options`: Settings used to decompress the images
+/// * `memory`: Memory budget shared by the connections
+/// * `deadline`: Longest time the client may take to send its request
+fn answer(
+    stream: TcpStream,
+    encoder_options: &EncoderOptions,
+    decode_options: &DecodeOptions,
+    memory: &Pool,
+    deadline: Duration,
+) {
+    let mut reader = BufReader::new(DeadlineReader {
+        stream: &stream,
+        deadline: Instant::now() + deadline,
+    });
+    let response = read_request(&mut reader, memory).and_then(|request| {
+        let work = work_memory(&request.path, &request.body, decode_options);
+        let _work = memory.take(work).ok_or_else(out_of_memory)?;
+        Ok(handle(
+            &request.method,
+            &request.path,
+            &request.body,
+            encoder_options,
+            decode_options,
+        ))
+    });
+    // The client may already be gone; there is nobody to report it to.
+    let _ = response
+        .unwrap_or_else(|response| response)
+        .write_to(&stream);
+}
+
+/// Listens on `address` and answers every connection on its own thread, with `handle`, up to
+/// `MAX_CONNECTIONS` at once, which take at most `MAX_SERVICE_MEMORY` together. Connections
+/// idle for 30 seconds, or whose request takes more than 60 seconds to arrive, are dropped.
+/// Only returns if the address cannot be bound.
+///
+/// # Arguments
+/// * `address`: Address and port to listen on, such as `127.0.0.1:8080`
+/// * `encoder_options`: Settings used to compress the images
+/// * `decode_options`: Settings used to decompress the images
+pub fn serve(
+    address: &str,
+    encoder_options: &EncoderOptions,
+    decode_options: &DecodeOptions,
+) -> Result<(), String> {
+    let listener = TcpListener::bind(address)
+        .map_err(|error| format!("Failed to listen on {address}: {error}"))?;
+    notice(format!("Listening on {address}"));
+    let options = Arc::new((encoder_options.clone(), decode_options.clone()));
+    let connections = Pool::new(MAX_CONNECTIONS as u64);
+    let memory = Pool::new(MAX_SERVICE_MEMORY);
+    for stream in listener.incoming().flatten() {
+        if stream.set_write_timeout(Some(IO_TIMEOUT)).is_err() {
+            continue;
+        }
+        let Some(slot) = connections.take(1) else {
+            let busy = Response::error(503, "Too many connections, try again later");
+            let _ = busy.write_to(&stream);
+            continue;
+        };
+        let (options, memory) = (Arc::clone(&options), memory.clone());
+        thread::spawn(move || {
+            answer(stream, &options.0, &options.1, &memory, REQUEST_DEADLINE);
+            drop(slot);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png_image::read_png;
+    use crate::ppm::{Rgb, RgbImage};
+
+    #[test]
+    fn compresses_and_decompresses_uploads() {
+        let image = RgbImage {
+            pixels: vec![
+                Rgb {
+                    red: 200,
+                    green: 100,
+                    blue: 50
+                };
+                16
+            ],
+            width: 4,
+            height: 4,
+            denominator: 255,
+        };
+        let (encoder_options, decode_options) =
+            (EncoderOptions::default(), DecodeOptions::default());
+        let profile = ColorTag::Icc(b"display profile".to_vec());
+        let png = write_png(&image, Some(&profile)).unwrap();
+        let compressed = handle("POST", "/compress", &png, &encoder_options, &decode_options);
+        assert_eq!(compressed.status, 200);
+        let decompressed = handle(
+            "POST",
+            "/decompress?format=png",
+            &compressed.body,
+            &encoder_options,
+            &decode_options,
+        );
+        assert_eq!(decompressed.content_type, "image/png");
+        assert_eq!(read_png(&decompressed.body).unwrap().width, 4);
+        assert_eq!(read_png_color_tag(&decompressed.body), Ok(Some(profile)));
+        let invalid = handle(
+            "POST",
+            "/decompress",
+            b"P6",
+            &encoder_options,
+            &decode_options,
+        );
+        assert_eq!(invalid.status, 400);
+    }
+
+    #[test]
+    fn request_heads_are_parsed() {
+        let memory = Pool::new(MAX_SERVICE_MEMORY);
+        let request = b"POST /compress HTTP/1.1\r\nHost: x\r\ncontent-length: 3\r\n\r\nabcdef";
+        let request = read_request(&mut &request[..], &memory).unwrap();
+        assert_eq!(
+            (
+                request.method.as_str(),
+                request.path.as_str(),
+                request.body.as_slice()
+            ),
+            ("POST", "/compress", &b"abc"[..])
+        );
+        let missing = read_request(&mut &b"POST / HTTP/1.1\r\n\r\n"[..], &memory).unwrap_err();
+        assert_eq!(missing.status, 411);
+    }
+
+    #[test]
+    fn uploads_and_connections_are_bounded() {
+        let memory = Pool::new(MAX_SERVICE_MEMORY);
+        let short = b"POST /decompress HTTP/1.1\r\nContent-Length: 200000000\r\n\r\nabc";
+        assert_eq!(
+            read_request(&mut &short[..], &memory).unwrap_err().status,
+            400
+        );
+        let (encoder_options, decode_options) =
+            (EncoderOptions::default(), DecodeOptions::default());
+        let large = handle(
+            "POST",
+            "/compress",
+            b"P6\n5000 5000\n255\n",
+            &encoder_options,
+            &decode_options,
+        );
+        assert_eq!(large.status, 400);
+        assert!(String::from_utf8(large.body).unwrap().contains("5000x5000"));
+
+        let image = crate::testkit::synthetic_image(crate::testkit::Pattern::Gradient, 4, 4);
+        let compressed = crate::codec::compress_image(&image, &encoder_options);
+        let mut forged = Vec::new();
+        Header {
+            width: 8192,
+            height: 8192,
+            ..Header::read(&compressed).unwrap().0
+        }
+        .write_image(&[&[0; 8]], &mut forged);
+        let response = handle(
+            "POST",
+            "/decompress",
+            &forged,
+            &encoder_options,
+            &decode_options,
+        );
+        let message = String::from_utf8(response.body).unwrap();
+        assert!(
+            message.contains(&DEFAULT_UPLOAD_MEMORY.to_string()),
+            "{message}"
+        );
+
+        let connections = Pool::new(MAX_CONNECTIONS as u64);
+        let slots: Vec<Reservation> = (0..MAX_CONNECTIONS)
+            .map(|_| connections.take(1).unwrap())
+            .collect();
+        assert!(connections.take(1).is_none());
+        drop(slots);
+        assert!(connections.take(1).is_some());
+        assert_eq!(memory.used.load(Ordering::Acquire), 0);
+    }
+
+    /// Sends `request` to a connection answered by `answer`, `chunk` bytes every `pause`, and
+    /// returns the status of the response and how long it took.
+    fn exchange(request: &[u8], chunk: usize, pause: Duration, memory: &Pool) -> (u16, Duration) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let start = Instant::now();
+        let memory = memory.clone();
+        let answering = thread::spawn(move || {
+            let options = (EncoderOptions::default(), DecodeOptions::default());
+            answer(
+                server,
+                &options.0,
+                &options.1,
+                &memory,
+                Duration::from_millis(300),
+            );
+        });
+        for part in request.chunks(chunk) {
+            if answering.is_finished() || client.write_all(part).is_err() {
+                break;
+            }
+            thread::sleep(pause);
+        }
+        answering.join().unwrap();
+        // The connection may be reset once the response is read, for the bytes left unsent.
+        let mut response = Vec::new();
+        let _ = client.read_to_end(&mut response);
+        let status = String::from_utf8_lossy(&response[..12])
+            .split(' ')
+            .nth(1)
+            .unwrap()
+            .parse();
+        (status.unwrap(), start.elapsed())
+    }
+
+    #[test]
+    fn slow_clients_and_full_budgets_are_turned_away() {
+        let image = crate::testkit::synthetic_image(crate::testkit::Pattern::Gradient, 64, 64);
+        let mut ppm = Vec::new();
+        image.write_to(&mut ppm).unwrap();
+        let mut request = format!(
+            "POST /compress HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            ppm.len()
+        )
+        .into_bytes();
+        request.extend_from_slice(&ppm);
+        let memory = Pool::new(MAX_SERVICE_MEMORY);
+        assert_eq!(
+            exchange(&request, request.len(), Duration::ZERO, &memory).0,
+            200
+        );
+
+        // One byte every 20 ms, each well within the timeout of a read, but the whole request
+        // would take far longer than the deadline.
+        let (status, elapsed) = exchange(&request, 1, Duration::from_millis(20), &memory);
+        assert_eq!(status, 408);
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+
+        // Concurrent uploads share the budget: with room for the body alone, the compression
+        // is turned away until the other uploads give their memory back.
+        let work = 64 * 64 * COMPRESS_BYTES_PER_PIXEL;
+        let uploads: Vec<Reservation> = (0..MAX_CONNECTIONS - 1)
+            .map(|_| memory.take(work).unwrap())
+            .collect();
+        let tight = Pool::new(ppm.len() as u64 + work - 1);
+        assert_eq!(
+            exchange(&request, request.len(), Duration::ZERO, &tight).0,
+            503
+        );
+        let full = memory.take(MAX_SERVICE_MEMORY - memory.used.load(Ordering::Acquire));
+        assert_eq!(
+            exchange(&request, request.len(), Duration::ZERO, &memory).0,
+            503
+        );
+        drop((uploads, full));
+        assert_eq!(
+            exchange(&request, request.len(), Duration::ZERO, &memory).0,
+            200
+        );
+        assert_eq!(memory.used.load(Ordering::Acquire), 0);
+    }
+}
@@ -0,0 +1,303 @@
+use crate::codec::{read_prelude, DecodeOptions};
+use crate::error::{CliError, ErrorKind};
+use crate::format::{read_version, Header, LEGACY_VERSION};
+use crate::io::{read_input, Output};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+/// Size in bytes of a secret key, the seed the signing scalar is hashed from.
+pub const SECRET_KEY_LEN: usize = ed25519_dalek::SECRET_KEY_LENGTH;
+
+/// Size in bytes of a public key, an encoded point of the curve.
+pub const PUBLIC_KEY_LEN: usize = ed25519_dalek::PUBLIC_KEY_LENGTH;
+
+/// Size in bytes of a signature: the encoded point R followed by the scalar S.
+pub const SIGNATURE_LEN: usize = ed25519_dalek::SIGNATURE_LENGTH;
+
+/// Returns a new secret key: 32 bytes from the random source of the operating system.
+pub fn generate_secret_key() -> Result<[u8; SECRET_KEY_LEN], String> {
+    let mut secret = [0; SECRET_KEY_LEN];
+    getrandom::fill(&mut secret)
+        .map_err(|error| format!("Failed to read random bytes from the system: {error}"))?;
+    Ok(secret)
+}
+
+/// Returns the public key that checks the signatures made with a secret key.
+///
+/// # Arguments
+/// * `secret`: Secret key, as returned by `generate_secret_key`
+pub fn public_key(secret: &[u8; SECRET_KEY_LEN]) -> [u8; PUBLIC_KEY_LEN] {
+    SigningKey::from_bytes(secret).verifying_key().to_bytes()
+}
+
+/// Returns a key as the line of 64 hex digits a key file holds.
+///
+/// # Arguments
+/// * `key`: Secret or public key
+pub fn format_key(key: &[u8; 32]) -> String {
+    let digits: String = key.iter().map(|byte| format!("{byte:02x}")).collect();
+    digits + "\n"
+}
+
+/// Parses the 64 hex digits of a key file, surrounded by any whitespace.
+///
+/// # Arguments
+/// * `text`: Contents of the key file
+pub fn parse_key(text: &str) -> Result<[u8; 32], String> {
+    let digits = text.trim();
+    if digits.len() != 64 || !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return Err("A key file holds a key of 64 hex digits".to_string());
+    }
+    let mut key = [0; 32];
+    for (byte, pair) in key.iter_mut().zip(digits.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap();
+    }
+    Ok(key)
+}
+
+/// Signs a compressed image with Ed25519, so that anyone holding the public key can tell that
+/// it was written by the holder of the secret key and not altered since: the header is marked
+/// as signed, and the signature of every byte of the marked image is appended to it. Unlike a
+/// checksum, which a tool altering the image can write again, the signature cannot be made
+/// without the secret key. The signature of an image that was already signed is replaced. The
+/// image decodes as before, and tools rewriting it, such as `rpeg crop`, drop the signature.
+/// Returns an error for images in the course format, which have no room for it.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+/// * `secret`: Secret key of the signer
+pub fn sign_image(bytes: &[u8], secret: &[u8; SECRET_KEY_LEN]) -> Result<Vec<u8>, String> {
+    if read_version(bytes)? == LEGACY_VERSION {
+        return Err("Images in the course format have no room for a signature".to_string());
+    }
+    // Only signs complete images, whose signature then covers every part of them.
+    read_prelude(bytes, &DecodeOptions::default())?;
+    let (mut header, payload) = Header::locate(bytes)?;
+    header.signed = true;
+    let mut signed = Vec::with_capacity(bytes.len() + SIGNATURE_LEN);
+    header.write_image(&[&bytes[payload]], &mut signed);
+    let signature = SigningKey::from_bytes(secret).sign(&signed);
+    signed.extend_from_slice(&signature.to_bytes());
+    Ok(signed)
+}
+
+/// Checks the signature `sign_image` appended to a compressed image against a public key.
+/// Returns an error when the image is not signed, or when it was altered since, or signed with
+/// another key.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+/// * `public`: Public key of the signer
+pub fn verify_image(bytes: &[u8], public: &[u8; PUBLIC_KEY_LEN]) -> Result<(), String> {
+    let (header, payload_start) = Header::read(bytes)?;
+    if !header.signed {
+        return Err("The image is not signed".to_string());
+    }
+    let end = bytes
+        .len()
+        .checked_sub(SIGNATURE_LEN)
+        .filter(|end| *end >= payload_start)
+        .ok_or("Ran out of bytes while reading the signature")?;
+    let signature = Signature::from_slice(&bytes[end..])
+        .map_err(|_| "Ran out of bytes while reading the signature")?;
+    VerifyingKey::from_bytes(public)
+        .and_then(|public| public.verify(&bytes[..end], &signature))
+        .map_err(|_| "The signature does not match the image and the public key".to_string())
+}
+
+/// Reads the key file `filename`.
+///
+/// # Arguments
+/// * `filename`: Location of the key file
+fn read_key(filename: &str) -> Result<[u8; 32], CliError> {
+    std::fs::read_to_string(filename)
+        .map_err(|error| format!("Failed to read {filename}: {error}"))
+        .and_then(|text| parse_key(&text).map_err(|message| format!("{filename}: {message}")))
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))
+}
+
+/// Writes a new secret key to `output`, and its public key next to it, with `.pub` appended to
+/// the file name. On Unix, the secret key is only readable by its owner, from the moment its
+/// file is created.
+///
+/// # Arguments
+/// * `output`: Destination of the secret key, which must be a file
+pub fn keygen(output: &Output) -> Result<(), CliError> {
+    let Some(filename) = &output.filename else {
+        return Err(CliError::new(
+            ErrorKind::BadArguments,
+            "keygen expects -o secret.key",
+        ));
+    };
+    let secret = generate_secret_key().map_err(|message| CliError::new(ErrorKind::Io, message))?;
+    let public = Output {
+        filename: Some(format!("{filename}.pub")),
+        force: output.force,
+    };
+    output
+        .write_private(format_key(&secret).as_bytes())
+        .and_then(|_| public.write(format_key(&public_key(&secret)).as_bytes()))
+        .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+/// Signs the compressed image `filename` with `sign_image` and the secret key in `key`, and
+/// writes the signed image to `output`. Failures are returned with the category the CLI exits
+/// with.
+///
+/// # Arguments
+/// * `filename`: Compressed image to sign, or None to read from standard in
+/// * `output`: Destination of the signed image
+/// * `key`: Location of the secret key file, or None when `--key` was not given
+pub fn sign(filename: Option<&str>, output: &Output, key: Option<&str>) -> Result<(), CliError> {
+    let key = key.ok_or_else(|| CliError::new(ErrorKind::BadArguments, "sign expects --key"))?;
+    let secret = read_key(key)?;
+    let bytes = read_input(filename)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let signed =
+        sign_image(&bytes, &secret).map_err(|message| CliError::stream(&bytes, message))?;
+    output
+        .write(&signed)
+        .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+/// Checks the signature of the compressed image `filename` with `verify_image` and the public
+/// key in `pubkey`, and prints `valid signature` when it matches. A missing or mismatched
+/// signature fails with `ErrorKind::BadSignature`.
+///
+/// # Arguments
+/// * `filename`: Compressed image to check, or None to read from standard in
+/// * `pubkey`: Location of the public key file, or None when `--pubkey` was not given
+pub fn verify(filename: Option<&str>, pubkey: Option<&str>) -> Result<(), CliError> {
+    let pubkey =
+        pubkey.ok_or_else(|| CliError::new(ErrorKind::BadArguments, "verify expects --pubkey"))?;
+    let public = read_key(pubkey)?;
+    let bytes = read_input(filename)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    Header::read(&bytes).map_err(|message| CliError::stream(&bytes, message))?;
+    verify_image(&bytes, &public)
+        .map_err(|message| CliError::new(ErrorKind::BadSignature, message))?;
+    println!("valid signature");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{compress_image, decompress_image, EncoderOptions};
+    use crate::crop::crop_image;
+    use crate::testkit::{synthetic_image, Pattern};
+    use crate::tiles::Rect;
+
+    #[test]
+    fn signed_images_decode_and_reject_tampering() {
+        let image = synthetic_image(Pattern::Noise { seed: 5 }, 24, 16);
+        let compressed = compress_image(
+            &image,
+            &EncoderOptions {
+                tile_size: 8,
+                ..EncoderOptions::default()
+            },
+        );
+        let secret = [7; SECRET_KEY_LEN];
+        let public = public_key(&secret);
+        assert_eq!(parse_key(&format_key(&public)), Ok(public));
+        assert!(verify_image(&compressed, &public).is_err());
+
+        let signed = sign_image(&compressed, &secret).unwrap();
+        // The header gains the byte of the extended flags.
+        assert_eq!(signed.len(), compressed.len() + 1 + SIGNATURE_LEN);
+        assert_eq!(verify_image(&signed, &public), Ok(()));
+        assert_eq!(
+            decompress_image(&signed).unwrap(),
+            decompress_image(&compressed).unwrap()
+        );
+        assert_eq!(sign_image(&signed, &secret).unwrap(), signed);
+        assert!(verify_image(&signed, &public_key(&[8; SECRET_KEY_LEN])).is_err());
+
+        let mut tampered = signed.clone();
+        tampered[signed.len() - SIGNATURE_LEN - 1] ^= 1;
+        assert!(verify_image(&tampered, &public).is_err());
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width: 8,
+            height: 8,
+        };
+        let (cropped, _) = crop_image(&signed, rect).unwrap();
+        assert!(!Header::read(&cropped).unwrap().0.signed);
+        assert!(decompress_image(&cropped).is_ok());
+    }
+
+    #[test]
+    fn the_commands_sign_and_reject_tampered_or_foreign_images() {
+        let dir = std::env::temp_dir().join(format!("rpeg-signature-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let output = |name: &str| Output {
+            filename: Some(path(name)),
+            force: false,
+        };
+        keygen(&output("secret.key")).unwrap();
+        keygen(&output("other.key")).unwrap();
+        let read = |name: &str| std::fs::read(path(name)).unwrap();
+        assert_ne!(read("secret.key.pub"), read("other.key.pub"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(path("secret.key"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let existing = keygen(&output("secret.key")).unwrap_err();
+        assert_eq!(existing.kind, ErrorKind::Io);
+        let leftovers = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with('.')
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+
+        let image = synthetic_image(Pattern::Gradient, 8, 8);
+        output("image.rpeg")
+            .write(&compress_image(&image, &EncoderOptions::default()))
+            .unwrap();
+        let secret = path("secret.key");
+        sign(
+            Some(&path("image.rpeg")),
+            &output("signed.rpeg"),
+            Some(&secret),
+        )
+        .unwrap();
+        let signed = std::fs::read(path("signed.rpeg")).unwrap();
+        let public = path("secret.key.pub");
+        assert!(verify(Some(&path("signed.rpeg")), Some(&public)).is_ok());
+
+        let mut tampered = signed.clone();
+        let last_payload_byte = signed.len() - SIGNATURE_LEN - 1;
+        tampered[last_payload_byte] ^= 1;
+        output("tampered.rpeg").write(&tampered).unwrap();
+        let truncated = &signed[..signed.len() - 1];
+        output("truncated.rpeg").write(truncated).unwrap();
+        for (image, pubkey) in [
+            ("tampered.rpeg", public.as_str()),
+            ("truncated.rpeg", public.as_str()),
+            ("signed.rpeg", &path("other.key.pub")),
+            ("image.rpeg", public.as_str()),
+        ] {
+            let error = verify(Some(&path(image)), Some(pubkey)).unwrap_err();
+            assert_eq!(
+                (error.kind, error.kind.exit_code()),
+                (ErrorKind::BadSignature, 8)
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+use crate::codec::QuantizationStats;
+use crate::conversions::dither_threshold;
+use crate::dct_coeff::{dequantize_chroma, masked_luma_range, quantize_chroma};
+use crate::layout::WordLayout;
+use crate::ppm::Rgb;
+use crate::structs::QuantizedBlock;
+use array2::array2::Array2;
+
+/// Returns the luma, Pb, and Pr of a pixel, like `conversions::compute_component_video`.
+fn to_component(pixel: &Rgb, denominator: f32) -> [f32; 3] {
+    let [red, green, blue] = [pixel.red, pixel.green, pixel.blue].map(|v| v as f32 / denominator);
+    [
+        0.299 * red + 0.587 * green + 0.114 * blue,
+        -0.168736 * red - 0.331264 * green + 0.5 * blue,
+        0.5 * red - 0.418688 * green - 0.081312 * blue,
+    ]
+}
+
+/// Returns the range b, c, and d of a block are clamped to, weighted by its brightness when
+/// `perceptual` is set, like `dct_coeff::quantize_block`.
+fn block_range(luma_range: f64, a: f32, perceptual: bool, layout: &WordLayout) -> f32 {
+    if perceptual {
+        masked_luma_range(luma_range, a as f64, layout) as f32
+    } else {
+        luma_range as f32
+    }
+}
+
+/// Single precision counterpart of `encode_words`: converts every 2x2 block of an image to
+/// Y/Pb/Pr, transforms, quantizes, and packs it into a code word, computing in `f32`. Returns
+/// the words in row-major block order along with their quantization statistics, as
+/// `encode_words` does.
+///
+/// # Arguments
+/// * `image`: Image, or tile of an image, with even dimensions
+/// * `image_denominator`: Denominator of the Rgb values of the image
+/// * `luma_ranges`: Range b, c, and d of every block are clamped to
+/// * `perceptual`: Weight the range of every block by its brightness
+/// * `layout`: Layout of the code words
+pub(crate) fn encode_words_single(
+    image: &Array2<Rgb>,
+    image_denominator: u16,
+    luma_ranges: &[f64],
+    perceptual: bool,
+    layout: &WordLayout,
+) -> (Vec<u64>, QuantizationStats) {
+    let denominator = image_denominator as f32;
+    let width = image.get_width();
+    let (a_scale, levels) = (layout.a_scale() as f32, layout.bcd_levels() as f32);
+    let mut clipped = 0;
+    let mut chroma_error = 0.0;
+    let mut words = Vec::with_capacity(luma_ranges.len());
+    let blocks = image.data.chunks_exact(2 * width).flat_map(|rows| {
+        let (top, bottom) = rows.split_at(width);
+        top.chunks_exact(2).zip(bottom.chunks_exact(2))
+    });
+    for ((top, bottom), luma_range) in blocks.zip(luma_ranges.iter()) {
+        let [y1, y2, y3, y4] = [&top[0], &top[1], &bottom[0], &bottom[1]]
+            .map(|pixel| to_component(pixel, denominator));
+        let a = ((y4[0] + y3[0] + y2[0] + y1[0]) / 4.0 * a_scale).round();
+        let range = block_range(*luma_range, a, perceptual, layout);
+        let scale = levels / range;
+        let mut luma = |coefficient: f32| {
+            clipped += (coefficient.abs() > range) as usize;
+            (coefficient.clamp(-range, range) * scale).round()
+        };
+        let (b, c, d) = (
+            luma((y4[0] + y3[0] - y2[0] - y1[0]) / 4.0),
+            luma((y4[0] - y3[0] + y2[0] - y1[0]) / 4.0),
+            luma((y4[0] - y3[0] - y2[0] + y1[0]) / 4.0),
+        );
+        let mut chroma = |channel: usize| {
+            let average = (y1[channel] + y2[channel] + y3[channel] + y4[channel]) / 4.0;
+            let index = quantize_chroma(average as f64, layout);
+            chroma_error += (average - dequantize_chroma(index, layout) as f32).abs() as f64;
+            index
+        };
+        let (pb, pr) = (chroma(1), chroma(2));
+        words.push(layout.pack(&QuantizedBlock {
+            a: a as u16,
+            b: b as i16,
+            c: c as i16,
+            d: d as i16,
+            pb: pb as u16,
+            pr: pr as u16,
+        }));
+    }
+    let stats = QuantizationStats {
+        clipped,
+        chroma_error,
+        blocks: words.len(),
+    };
+    (words, stats)
+}
+
+/// Single precision counterpart of `decode_words`: rebuilds the pixels of an image, or tile of
+/// an image, from its code words, computing in `f32`.
+///
+/// # Arguments
+/// * `image_data`: One code word per 2x2 block, in row-major block order
+/// * `luma_ranges`: Range b, c, and d of every block were clamped to, before weighting
+/// * `width`: Width of the image in pixels
+/// * `height`: Height of the image in pixels
+/// * `dither`: Column and row of the tile within the whole image to round the pixels with
+///   ordered dithering, or None to truncate them
+/// * `layout`: Layout of the code words
+/// * `perceptual`: The range of every block was weighted by its brightness
+pub(crate) fn decode_words_single(
+    image_data: &[u64],
+    luma_ranges: &[f64],
+    width: usize,
+    height: usize,
+    dither: Option<(usize, usize)>,
+    layout: &WordLayout,
+    perceptual: bool,
+) -> Array2<Rgb> {
+    let (a_scale, levels) = (layout.a_scale() as f32, layout.bcd_levels() as f32);
+    let mut pixels = vec![
+        Rgb {
+            red: 0,
+            green: 0,
+            blue: 0
+        };
+        width * height
+    ];
+    let blocks_per_row = width / 2;
+    for (index, (word, luma_range)) in image_data.iter().zip(luma_ranges.iter()).enumerate() {
+        let coefficient = layout.unpack(*word);
+        let range = block_range(*luma_range, coefficient.a as f32, perceptual, layout);
+        let scale = levels / range;
+        let luma = |value: i16| (value as f32 / scale).clamp(-range, range);
+        let a = (coefficient.a as f32 / a_scale).clamp(0.0, 1.0);
+        let (b, c, d) = (
+            luma(coefficient.b),
+            luma(coefficient.c),
+            luma(coefficient.d),
+        );
+        let pb = dequantize_chroma(coefficient.pb as usize, layout) as f32;
+        let pr = dequantize_chroma(coefficient.pr as usize, layout) as f32;
+        let (col, row) = ((index % blocks_per_row) * 2, (index / blocks_per_row) * 2);
+        let corners = [
+            (col, row, a - b - c + d),
+            (col + 1, row, a - b + c - d),
+            (col, row + 1, a + b - c - d),
+            (col + 1, row + 1, a + b + c + d),
+        ];
+        for (x, y, luma) in corners {
+            let channels = [
+                (luma + 1.402 * pr) * 255.0,
+                (luma - 0.344136 * pb - 0.714136 * pr) * 255.0,
+                (luma + 1.772 * pb) * 255.0,
+            ];
+            // Densities are truncated, or rounded up past the dithering threshold, like the
+            // double precision pipeline rounds them.
+            let bias = match dither {
+                Some(origin) => 1.0 - dither_threshold(origin.0 + x, origin.1 + y) as f32,
+                None => 0.0,
+            };
+            let [red, green, blue] = channels.map(|channel| (channel + bias) as u16);
+            pixels[y * width + x] = Rgb { red, green, blue };
+        }
+    }
+    Array2::from_row_major(width, height, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::stats::Timings;
+    use crate::codec::{decode_words, encode_words, PixelFormat};
+    use crate::fixed::Arithmetic;
+    use crate::layout::{FINE_CHROMA_LAYOUT, FINE_TABLE_LAYOUT, NARROW_LAYOUT, WIDE_LAYOUT};
+    use crate::pixel::Pixel;
+    use crate::testkit::{synthetic_image, Pattern};
+
+    #[test]
+    fn single_precision_words_match_the_double_precision_ones() {
+        let image = synthetic_image(Pattern::Noise { seed: 7 }, 32, 24);
+        let pixels = Array2::from_row_major(32, 24, image.pixels.clone());
+        let ranges = vec![0.3; 16 * 12];
+        for layout in [
+            NARROW_LAYOUT,
+            WIDE_LAYOUT,
+            FINE_CHROMA_LAYOUT,
+            FINE_TABLE_LAYOUT,
+        ] {
+            for perceptual in [false, true] {
+                let (words, stats) =
+                    encode_words_single(&pixels, 255, &ranges, perceptual, &layout);
+                let (expected, expected_stats) = encode_words(
+                    &pixels,
+                    255,
+                    &ranges,
+                    false,
+                    perceptual,
+                    &layout,
+                    Arithmetic::Float,
+                    &Timings::new(),
+                );
+                // Rounding may go the other way for a value that lands on a half step.
+                let differing = words.iter().zip(&expected).filter(|(a, b)| a != b).count();
+                assert!(differing <= 2, "{layout:?} {differing}");
+                assert_eq!(stats.blocks, expected_stats.blocks);
+                assert!((stats.chroma_error - expected_stats.chroma_error).abs() < 1e-3);
+                for dither in [None, Some((0, 0))] {
+                    let decoded = decode_words_single(
+                        &expected, &ranges, 32, 24, dither, &layout, perceptual,
+                    );
+                    let expected = decode_words(
+                        &expected,
+                        &ranges,
+                        32,
+                        24,
+                        dither,
+                        &layout,
+                        perceptual,
+                        PixelFormat::Rgb,
+                        None,
+                        Arithmetic::Float,
+                        &Timings::new(),
+                    )
+                    .unwrap();
+                    for (pixel, expected) in decoded.data.iter().zip(&expected.data) {
+                        for channel in 0..3 {
+                            let difference =
+                                pixel.channel(channel).abs_diff(expected.channel(channel));
+                            assert!(difference <= 1, "{layout:?} {pixel:?} {expected:?}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use crate::codec::{
+    check_encodable, compress_image, decompress_image, read_code_words, EncoderOptions,
+};
+use crate::conversions::{compute_component_video, compute_rgb_floats};
+use crate::diff::FIELD_NAMES;
+use crate::error::{CliError, ErrorKind};
+use crate::format::{Header, MAGIC};
+use crate::io::read_input;
+use crate::layout::Field;
+use crate::png_image::read_png_or_ppm;
+use crate::ppm::RgbImage;
+use crate::structs::QuantizedBlock;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Number of bins of every histogram `rpeg stats` prints.
+const BINS: usize = 16;
+
+/// Width of the longest bar of a printed histogram, in characters.
+const BAR_WIDTH: u64 = 40;
+
+#[derive(Clone, Debug, PartialEq)]
+/// ## Counts of values falling into equal bins between `low` and `high`
+///
+/// Values outside the range are counted in the first or last bin.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::stats::Histogram;
+///
+/// let mut histogram = Histogram::new(0.0, 1.0, 4);
+/// for value in [0.1, 0.2, 0.6, 1.0] {
+///     histogram.add(value);
+/// }
+/// assert_eq!(histogram.counts, vec![2, 0, 1, 1]);
+/// ```
+pub struct Histogram {
+    /// Lower bound of the first bin.
+    pub low: f64,
+    /// Upper bound of the last bin.
+    pub high: f64,
+    /// Number of values in every bin.
+    pub counts: Vec<u64>,
+}
+
+impl Histogram {
+    /// Returns an empty histogram.
+    ///
+    /// # Arguments
+    /// * `low`: Lower bound of the first bin
+    /// * `high`: Upper bound of the last bin
+    /// * `bins`: Number of bins
+    pub fn new(low: f64, high: f64, bins: usize) -> Self {
+        Histogram {
+            low,
+            high,
+            counts: vec![0; bins.max(1)],
+        }
+    }
+
+    /// Counts `value` in its bin.
+    pub fn add(&mut self, value: f64) {
+        let bins = self.counts.len();
+        let position = (value - self.low) / (self.high - self.low) * bins as f64;
+        self.counts[(position.max(0.0) as usize).min(bins - 1)] += 1;
+    }
+
+    /// Returns the histogram as one line per bin, with its range, its count, and a bar.
+    ///
+    /// # Arguments
+    /// * `integers`: Print the ranges as the whole levels of integer symbols each bin holds,
+    ///   instead of with decimals
+    fn render(&self, integers: bool) -> String {
+        let largest = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        let width = (self.high - self.low) / self.counts.len() as f64;
+        let mut text = String::new();
+        for (bin, &count) in self.counts.iter().enumerate() {
+            let start = self.low + width * bin as f64;
+            let range = if integers {
+                format!("{:>7} ..{:>7}", start.ceil(), (start + width).ceil() - 1.0)
+            } else {
+                format!("{:>7.3} ..{:>7.3}", start, start + width)
+            };
+            text.push_str(&format!(
+                "  {range} {count:>9} {}\n",
+                "#".repeat((count * BAR_WIDTH).div_ceil(largest) as usize)
+            ));
+        }
+        text
+    }
+}
+
+/// Returns the Shannon entropy of the symbols counted in `counts`, in bits per symbol.
+///
+/// # Arguments
+/// * `counts`: Number of occurrences of every distinct symbol
+pub fn entropy(counts: impl IntoIterator<Item = u64>) -> f64 {
+    let counts: Vec<u64> = counts.into_iter().filter(|&count| count > 0).collect();
+    let total = counts.iter().sum::<u64>() as f64;
+    counts
+        .iter()
+        .map(|&count| {
+            let probability = count as f64 / total;
+            -probability * probability.log2()
+        })
+        .sum()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// ## Distribution of one quantized value of the code words
+pub struct SymbolStats {
+    /// Width of the field of the value in the code word, in bits.
+    pub bits: u64,
+    /// Smallest value.
+    pub min: i64,
+    /// Largest value.
+    pub max: i64,
+    /// Mean value.
+    pub mean: f64,
+    /// Fraction of the blocks where the value is zero.
+    pub zeros: f64,
+    /// Entropy of the value, in bits per block: the size an ideal order-0 entropy coder would
+    /// spend on it.
+    pub entropy: f64,
+    /// Counts of the values, in bins spanning every level of the field.
+    pub histogram: Histogram,
+}
+
+impl SymbolStats {
+    /// Returns the distribution of `values`, stored in `field`.
+    ///
+    /// # Arguments
+    /// * `values`: Quantized value of every block
+    /// * `field`: Field of the value in the code word
+    /// * `signed`: Whether the field holds a two's complement value
+    fn of(values: &[i64], field: Field, signed: bool) -> Self {
+        let mut counts = BTreeMap::new();
+        for &value in values {
+            *counts.entry(value).or_insert(0_u64) += 1;
+        }
+        let levels = 1_i64 << field.width;
+        let low = if signed { -levels / 2 } else { 0 };
+        let mut histogram =
+            Histogram::new(low as f64, (low + levels) as f64, BINS.min(levels as usize));
+        for &value in values {
+            histogram.add(value as f64);
+        }
+        let blocks = values.len().max(1) as f64;
+        SymbolStats {
+            bits: field.width,
+            min: counts.keys().next().copied().unwrap_or(0),
+            max: counts.keys().next_back().copied().unwrap_or(0),
+            mean: values.iter().sum::<i64>() as f64 / blocks,
+            zeros: counts.get(&0).copied().unwrap_or(0) as f64 / blocks,
+            entropy: entropy(counts.into_values()),
+            histogram,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// ## Statistics of an image and of its quantized code words
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::codec::{compress_image, EncoderOptions};
+/// use rpeg::ppm::{Rgb, RgbImage};
+/// use rpeg::stats::ImageStats;
+///
+/// let image = RgbImage {
+///     pixels: vec![Rgb { red: 100, green: 100, blue: 100 }; 16],
+///     width: 4,
+///     height: 4,
+///     denominator: 255,
+/// };
+/// let compressed = compress_image(&image, &EncoderOptions::default());
+/// let stats = ImageStats::of(&image, &compressed).unwrap();
+/// assert_eq!(stats.blocks, 4);
+/// // Every block holds the same code word, which an entropy coder would store for free.
+/// assert_eq!(stats.predicted_bytes(), 0.0);
+/// ```
+pub struct ImageStats {
+    /// Header of the compressed image.
+    pub header: Header,
+    /// Luma of every pixel, between 0 and 1.
+    pub luma: Histogram,
+    /// Pb of every pixel, between -0.5 and 0.5.
+    pub pb: Histogram,
+    /// Pr of every pixel, between -0.5 and 0.5.
+    pub pr: Histogram,
+    /// Number of code words.
+    pub blocks: usize,
+    /// Distribution of every quantized value, named by `FIELD_NAMES`.
+    pub fields: Vec<SymbolStats>,
+}
+
+impl ImageStats {
+    /// Returns the statistics of an image and of its compressed version.
+    ///
+    /// # Arguments
+    /// * `image`: Image whose pixels the histograms are made of
+    /// * `compressed`: Compressed image whose code words the distributions are made of
+    pub fn of(image: &RgbImage, compressed: &[u8]) -> Result<Self, String> {
+        let (header, words) = read_code_words(compressed)?;
+        let mut luma = Histogram::new(0.0, 1.0, BINS);
+        let mut pb = Histogram::new(-0.5, 0.5, BINS);
+        let mut pr = Histogram::new(-0.5, 0.5, BINS);
+        let denominator = image.denominator.max(1) as f64;
+        for pixel in &image.pixels {
+            let video = compute_component_video(&compute_rgb_floats(pixel, denominator));
+            luma.add(video.y);
+            pb.add(video.pb);
+            pr.add(video.pr);
+        }
+
+        let layout = header.layout;
+        let values: Vec<_> = words.iter().map(|&word| layout.unpack(word)).collect();
+        let column =
+            |value: fn(&QuantizedBlock) -> i64| -> Vec<i64> { values.iter().map(value).collect() };
+        let fields = vec![
+            SymbolStats::of(&column(|value| value.a as i64), layout.a, false),
+            SymbolStats::of(&column(|value| value.b as i64), layout.b, true),
+            SymbolStats::of(&column(|value| value.c as i64), layout.c, true),
+            SymbolStats::of(&column(|value| value.d as i64), layout.d, true),
+            SymbolStats::of(&column(|value| value.pb as i64), layout.pb, false),
+            SymbolStats::of(&column(|value| value.pr as i64), layout.pr, false),
+        ];
+        Ok(ImageStats {
+            header,
+            luma,
+            pb,
+            pr,
+            blocks: words.len(),
+            fields,
+        })
+    }
+
+    /// Returns the size of the code words as stored, in bytes.
+    pub fn payload_bytes(&self) -> f64 {
+        (self.blocks * self.header.layout.word_bytes()) as f64
+    }
+
+    /// Returns the size of the code words after an ideal order-0 entropy coder codes every
+    /// quantized value on its own, in bytes.
+    pub fn predicted_bytes(&self) -> f64 {
+        let bits_per_block: f64 = self.fields.iter().map(|field| field.entropy).sum();
+        bits_per_block * self.blocks as f64 / 8.0
+    }
+}
+
+impl fmt::Display for ImageStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}x{} pixels, {} blocks, layout {}",
+            self.header.width, self.header.height, self.blocks, self.header.layout.id
+        )?;
+        for (name, histogram) in [("luma", &self.luma), ("pb", &self.pb), ("pr", &self.pr)] {
+            write!(f, "\n{name} histogram\n{}", histogram.render(false))?;
+        }
+        writeln!(
+            f,
+            "\n{:>5} {:>5} {:>7} {:>7} {:>9} {:>7} {:>9}",
+            "field", "bits", "min", "max", "mean", "zeros", "entropy"
+        )?;
+        for (name, field) in FIELD_NAMES.iter().zip(&self.fields) {
+            writeln!(
+                f,
+                "{name:>5} {:>5} {:>7} {:>7} {:>9.3} {:>6.1}% {:>9.3}",
+                field.bits,
+                field.min,
+                field.max,
+                field.mean,
+                field.zeros * 100.0,
+                field.entropy
+            )?;
+        }
+        for (name, field) in FIELD_NAMES.iter().zip(&self.fields) {
+            write!(f, "\n{name} distribution\n{}", field.histogram.render(true))?;
+        }
+        let (payload, predicted) = (self.payload_bytes(), self.predicted_bytes());
+        writeln!(
+            f,
+            "\ncode words: {payload} bytes; order-0 entropy estimate: {predicted:.0} bytes ({:.1}% of the code words)",
+            predicted * 100.0 / payload.max(1.0)
+        )
+    }
+}
+
+/// Prints the histograms of the luma and chroma of an image, the distributions of its
+/// quantized values, and their entropy to standard out. A PPM or PNG image is compressed with
+/// `options` first; a compressed image is decompressed for its histograms.
+///
+/// # Arguments
+/// * `filename`: Location of the PPM, PNG, or compressed image
+/// * `options`: Settings used to compress a PPM or PNG image
+pub fn stats(filename: &str, options: &EncoderOptions) -> Result<(), CliError> {
+    check_encodable(options)?;
+    let bytes = read_input(Some(filename))
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let corrupt = |message: String| CliError::stream(&bytes, format!("{filename}: {message}"));
+    let (image, compressed) = if bytes.starts_with(MAGIC) {
+        (decompress_image(&bytes).map_err(corrupt)?, bytes.clone())
+    } else {
+        let image = read_png_or_ppm(&bytes)
+            .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+        let compressed = compress_image(&image, options);
+        (image, compressed)
+    };
+    let stats = ImageStats::of(&image, &compressed).map_err(corrupt)?;
+    print!("{stats}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppm::Rgb;
+
+    #[test]
+    fn distributions_count_every_block() {
+        assert_eq!(entropy([1, 1, 1, 1]), 2.0);
+        assert_eq!(entropy([5, 0]), 0.0);
+
+        let image = RgbImage {
+            pixels: (0..64u16)
+                .map(|i| Rgb {
+                    red: i * 4,
+                    green: 100,
+                    blue: 255 - i * 4,
+                })
+                .collect(),
+            width: 8,
+            height: 8,
+            denominator: 255,
+        };
+        let compressed = compress_image(&image, &EncoderOptions::default());
+        let stats = ImageStats::of(&image, &compressed).unwrap();
+        assert_eq!(stats.luma.counts.iter().sum::<u64>(), 64);
+        for field in &stats.fields {
+            assert_eq!(field.histogram.counts.iter().sum::<u64>(), 16);
+            assert!(field.entropy <= field.bits as f64);
+        }
+        assert_eq!(stats.fields[0].histogram.counts.len(), BINS);
+        assert!(stats.predicted_bytes() < stats.payload_bytes());
+        assert!(stats.to_string().contains("order-0 entropy estimate"));
+    }
+}
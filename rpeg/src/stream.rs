@@ -0,0 +1,76 @@
+use crate::codec::{EncodeReport, EncoderOptions};
+use crate::encoder::Encoder;
+use crate::ppm::RgbImage;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Reads a PPM image from `reader`, compresses it, and writes the compressed image to `writer`,
+/// without blocking the async runtime: the input and output are awaited, and the compression
+/// itself runs on tokio's blocking thread pool. Invalid images and settings are reported as
+/// errors, like `Encoder::compress`.
+///
+/// # Arguments
+/// * `reader`: Source of the PPM image, for example the body of an upload
+/// * `writer`: Destination of the compressed image
+/// * `options`: Settings used to compress the image
+pub async fn compress_stream(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    options: &EncoderOptions,
+) -> Result<EncodeReport, String> {
+    let mut input = Vec::new();
+    reader
+        .read_to_end(&mut input)
+        .await
+        .map_err(|error| format!("Failed to read the image: {error}"))?;
+    let encoder = Encoder::from(options.clone());
+    let (compressed, report) = tokio::task::spawn_blocking(move || {
+        let image = RgbImage::from_reader(input.as_slice())?;
+        encoder.compress_with_report(&image)
+    })
+    .await
+    .map_err(|error| format!("Compression failed: {error}"))??;
+    writer
+        .write_all(&compressed)
+        .await
+        .map_err(|error| format!("Failed to write the compressed image: {error}"))?;
+    writer
+        .flush()
+        .await
+        .map_err(|error| format!("Failed to write the compressed image: {error}"))?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::compress_image;
+    use crate::ppm::Rgb;
+
+    #[tokio::test]
+    async fn stream_matches_compress_image() {
+        let image = RgbImage {
+            pixels: (0..48u16)
+                .map(|i| Rgb {
+                    red: i * 5,
+                    green: 255 - i,
+                    blue: i * 2,
+                })
+                .collect(),
+            width: 8,
+            height: 6,
+            denominator: 255,
+        };
+        let mut ppm = Vec::new();
+        image.write_to(&mut ppm).unwrap();
+        let options = EncoderOptions::default();
+        let mut compressed = Vec::new();
+        compress_stream(ppm.as_slice(), &mut compressed, &options)
+            .await
+            .unwrap();
+        assert_eq!(compressed, compress_image(&image, &options));
+
+        let mut output = Vec::new();
+        let error = compress_stream(&b"P6\n2 2"[..], &mut output, &options).await;
+        assert!(error.is_err() && output.is_empty());
+    }
+}
@@ -1,4 +1,5 @@
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// ## Represent a Rgb value as a floating point value.
 ///
 /// This struct is used to represent an Rgb value as a floating point value
@@ -20,7 +21,8 @@ pub struct RgbFloats {
     pub blue: f64,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// ## Represent the Component video format
 ///
 /// This struct is used within arith in the compression and decompression. In the compression
@@ -40,22 +42,28 @@ pub struct ComponentVideo {
     pub pr: f64,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// # Represent the 2x2 block of pixels converted into ComponentVideo format
 ///
 /// This struct is used within arith in the compression and decompression. In the compression
 /// step, 2x2 block of pixel translated into ComponentVideo are stored as single struct . In the
 /// decompression step, the Block is translated back into 4 different ComponentVideos.
 ///
+/// The pixels are stored in `BlockLayout::RowMajor` order: `y1` is the top-left pixel, `y2`
+/// the top-right, `y3` the bottom-left, and `y4` the bottom-right one.
+///
 /// # Usage Example
 /// ```
-/// use rpeg::structs::{ComponentVideo, Block};
+/// use rpeg::structs::{ComponentVideo, Block, BlockLayout};
 ///
 /// let cv_1 = ComponentVideo{y: 0.16, pb: 0.14, pr: 0.16};
 /// let cv_2 = ComponentVideo{y: 0.22, pb: 0.22, pr: 0.};
 /// let cv_3 = ComponentVideo{y: 0.16, pb: 0.14, pr: 0.16};
 /// let cv_4 = ComponentVideo{y: 0.16, pb: 0.14, pr: 0.16};
 /// let dst_format = Block{y1: cv_1, y2: cv_2, y3: cv_3, y4: cv_4};
+/// assert_eq!(dst_format.top_right(), &cv_2);
+/// assert_eq!(dst_format.pixels(BlockLayout::ColumnMajor), [cv_1, cv_3, cv_2, cv_4]);
 /// ```
 pub struct Block {
     pub y1: ComponentVideo,
@@ -64,7 +72,149 @@ pub struct Block {
     pub y4: ComponentVideo,
 }
 
-#[derive(Clone, Debug)]
+impl Block {
+    /// Returns the top-left pixel of the block, `y1`.
+    pub fn top_left(&self) -> &ComponentVideo {
+        &self.y1
+    }
+
+    /// Returns the top-right pixel of the block, `y2`.
+    pub fn top_right(&self) -> &ComponentVideo {
+        &self.y2
+    }
+
+    /// Returns the bottom-left pixel of the block, `y3`.
+    pub fn bottom_left(&self) -> &ComponentVideo {
+        &self.y3
+    }
+
+    /// Returns the bottom-right pixel of the block, `y4`.
+    pub fn bottom_right(&self) -> &ComponentVideo {
+        &self.y4
+    }
+
+    /// Returns the four pixels of the block, listed in the order of `layout`.
+    ///
+    /// # Arguments
+    /// * `layout`: Order to list the pixels in
+    pub fn pixels(&self, layout: BlockLayout) -> [ComponentVideo; 4] {
+        let row_major = [self.y1, self.y2, self.y3, self.y4];
+        [0, 1, 2, 3].map(|index| {
+            let (col, row) = layout.offset(index);
+            row_major[row * 2 + col]
+        })
+    }
+
+    /// Builds a block from its four pixels, listed in the order of `layout`. The inverse of
+    /// `pixels`.
+    ///
+    /// # Arguments
+    /// * `pixels`: Pixels of the block
+    /// * `layout`: Order the pixels are listed in
+    pub fn from_pixels(pixels: [ComponentVideo; 4], layout: BlockLayout) -> Block {
+        let mut row_major = [ComponentVideo::default(); 4];
+        for (index, pixel) in pixels.into_iter().enumerate() {
+            let (col, row) = layout.offset(index);
+            row_major[row * 2 + col] = pixel;
+        }
+        let [y1, y2, y3, y4] = row_major;
+        Block { y1, y2, y3, y4 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// ## Order in which the four pixels of a 2x2 block are listed
+///
+/// `RowMajor` lists the top-left, top-right, bottom-left, and bottom-right pixels. It is the
+/// order of the fields of Block, and the order in which the decoder lays out the pixels of
+/// every block before moving them to their place in the image. `ColumnMajor` lists the
+/// top-left, bottom-left, top-right, and bottom-right pixels.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::structs::BlockLayout;
+///
+/// assert_eq!(BlockLayout::RowMajor.offset(1), (1, 0));
+/// assert_eq!(BlockLayout::ColumnMajor.offset(1), (0, 1));
+/// ```
+pub enum BlockLayout {
+    #[default]
+    RowMajor,
+    ColumnMajor,
+}
+
+impl BlockLayout {
+    /// Returns the column and row, within the block, of the pixel listed at `index`.
+    ///
+    /// # Arguments
+    /// * `index`: Position of the pixel in this order, from 0 to 3
+    pub fn offset(self, index: usize) -> (usize, usize) {
+        match self {
+            BlockLayout::RowMajor => (index % 2, index / 2),
+            BlockLayout::ColumnMajor => (index / 2, index % 2),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// # Represent the 2x2 transform of a block before any quantization
+///
+/// `a` is the average luma of the block, `b`, `c`, and `d` its vertical, horizontal, and
+/// diagonal luma differences, and `pb` and `pr` its average chroma. Quantizing it gives the
+/// DCTCoefficient packed into the code word.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::dct_coeff::{inverse_transform, transform_block};
+/// use rpeg::structs::{Block, ComponentVideo};
+///
+/// let pixel = |y| ComponentVideo { y, pb: 0.1, pr: -0.1 };
+/// let block = Block { y1: pixel(0.2), y2: pixel(0.4), y3: pixel(0.6), y4: pixel(0.8) };
+/// let transform = transform_block(&block);
+/// assert!((transform.a - 0.5).abs() < 1e-12);
+/// assert!((inverse_transform(&transform).y4.y - 0.8).abs() < 1e-12);
+/// ```
+pub struct TransformedBlock {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+    pub pb: f64,
+    pub pr: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// # Represent the exact integer 2x2 transform of a block
+///
+/// The integer counterpart of TransformedBlock, computed by lifting steps from four integer
+/// samples so that `unlift_block` gives them back bit for bit. `a` is the average of the block
+/// and `b` and `c` twice its vertical and horizontal differences, each within 1 of the exact
+/// value, and `d` is exactly four times its diagonal difference.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::dct_coeff::{lift_block, unlift_block};
+///
+/// let samples = [12, 200, 37, 255];
+/// let lifted = lift_block(samples);
+/// assert_eq!(lifted.a, 126);
+/// assert_eq!(unlift_block(&lifted), samples);
+/// ```
+pub struct LiftedBlock {
+    pub a: i32,
+    pub b: i32,
+    pub c: i32,
+    pub d: i32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// # Represent a discrete cosine transformation of the 4 pixels (luminance/luma)
 ///
 /// This struct stores the discrete cosine transformation apply to the 2x2 pixel blocks
@@ -86,3 +236,58 @@ pub struct DCTCoefficient {
     pub index_of_pb: usize,
     pub index_of_pr: usize,
 }
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// # Represent the quantized fields of one code word
+///
+/// This struct stores the integers that the packing layer writes into a code word: the
+/// unsigned luma average `a`, the signed luma gradients `b`, `c` and `d`, and the unsigned
+/// chroma indices `pb` and `pr`. The fields are wide enough for every word layout, the widest
+/// of which uses 10-bit gradients and 9-bit chroma indices. Converting a DCTCoefficient into a
+/// QuantizedBlock expects its values to be quantized already.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::structs::{DCTCoefficient, QuantizedBlock};
+///
+/// let quantized = QuantizedBlock { a: 320, b: -3, c: 0, d: 1, pb: 7, pr: 8 };
+/// let coefficient = DCTCoefficient::from(quantized);
+/// assert_eq!(coefficient.b, -3.0);
+/// assert_eq!(QuantizedBlock::from(&coefficient), quantized);
+/// ```
+pub struct QuantizedBlock {
+    pub a: u16,
+    pub b: i16,
+    pub c: i16,
+    pub d: i16,
+    pub pb: u16,
+    pub pr: u16,
+}
+
+impl From<&DCTCoefficient> for QuantizedBlock {
+    fn from(coefficient: &DCTCoefficient) -> Self {
+        QuantizedBlock {
+            a: coefficient.a as u16,
+            b: coefficient.b as i16,
+            c: coefficient.c as i16,
+            d: coefficient.d as i16,
+            pb: coefficient.index_of_pb as u16,
+            pr: coefficient.index_of_pr as u16,
+        }
+    }
+}
+
+impl From<QuantizedBlock> for DCTCoefficient {
+    fn from(block: QuantizedBlock) -> Self {
+        DCTCoefficient {
+            a: block.a as f64,
+            b: block.b as f64,
+            c: block.c as f64,
+            d: block.d as f64,
+            index_of_pb: block.pb as usize,
+            index_of_pr: block.pr as usize,
+        }
+    }
+}
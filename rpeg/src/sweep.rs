@@ -0,0 +1,223 @@
+use crate::codec::cache::CoefficientCache;
+use crate::codec::{check_encodable, EncoderOptions};
+use crate::dct_coeff::luma_range_for_quality;
+use crate::error::{CliError, ErrorKind};
+use crate::metrics::{input_size, FileMetrics};
+use crate::ppm::RgbImage;
+
+/// Qualities `rpeg sweep` compresses at when none are given.
+pub const DEFAULT_QUALITIES: [u8; 5] = [10, 30, 50, 70, 90];
+
+#[derive(Clone, Debug, PartialEq)]
+/// ## Operating point of a rate-distortion sweep
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::codec::EncoderOptions;
+/// use rpeg::ppm::{Rgb, RgbImage};
+/// use rpeg::sweep::sweep_image;
+///
+/// let image = RgbImage {
+///     pixels: (0..64).map(|i| Rgb { red: i * 4, green: 100, blue: 255 - i * 4 }).collect(),
+///     width: 8,
+///     height: 8,
+///     denominator: 255,
+/// };
+/// let points = sweep_image(&image, 200, &[10, 90], &EncoderOptions::default());
+/// assert_eq!(points[1].quality, 90);
+/// // Code words have a fixed size: the quality trades clipping for finer steps.
+/// assert_eq!(points[0].metrics.output_bytes, points[1].metrics.output_bytes);
+/// ```
+pub struct SweepPoint {
+    /// Quality the background blocks were compressed with.
+    pub quality: u8,
+    /// Size and quality of the compressed image.
+    pub metrics: FileMetrics,
+    /// Bits of compressed data per pixel.
+    pub bits_per_pixel: f64,
+}
+
+/// Compresses an image once per quality, with the luma range of that quality and otherwise
+/// `options`, and measures every result. The blocks are transformed once, by the first
+/// quality, and only quantized again for the others.
+///
+/// # Arguments
+/// * `image`: Image to compress
+/// * `input_bytes`: Size of the image before compression, such as the size of its file
+/// * `qualities`: Qualities between 0 and 100 to compress at
+/// * `options`: Settings shared by every compression
+pub fn sweep_image(
+    image: &RgbImage,
+    input_bytes: usize,
+    qualities: &[u8],
+    options: &EncoderOptions,
+) -> Vec<SweepPoint> {
+    let cache = CoefficientCache::new(image, options);
+    qualities
+        .iter()
+        .map(|&quality| {
+            let options = EncoderOptions {
+                luma_range: Some(luma_range_for_quality(quality)),
+                ..options.clone()
+            };
+            let (compressed, metrics) =
+                FileMetrics::measure_cached(&format!("q{quality}"), &cache, input_bytes, &options);
+            SweepPoint {
+                quality,
+                metrics,
+                bits_per_pixel: (compressed.len() * 8) as f64 / image.pixels.len().max(1) as f64,
+            }
+        })
+        .collect()
+}
+
+/// Returns the points of a sweep as an aligned table, one row per quality.
+///
+/// # Arguments
+/// * `points`: Operating points of the sweep
+pub fn to_table(points: &[SweepPoint]) -> String {
+    let mut table = format!(
+        "{:>7} {:>10} {:>8} {:>7} {:>9} {:>7}\n",
+        "quality", "bytes", "bpp", "ratio", "psnr (dB)", "ssim"
+    );
+    for point in points {
+        table.push_str(&format!(
+            "{:>7} {:>10} {:>8.3} {:>7.2} {:>9.2} {:>7.4}\n",
+            point.quality,
+            point.metrics.output_bytes,
+            point.bits_per_pixel,
+            point.metrics.ratio,
+            point.metrics.psnr,
+            point.metrics.ssim
+        ));
+    }
+    table
+}
+
+/// Returns the points of a sweep as CSV with a header row.
+///
+/// # Arguments
+/// * `points`: Operating points of the sweep
+pub fn to_csv(points: &[SweepPoint]) -> String {
+    let mut csv = String::from("quality,output_bytes,bits_per_pixel,ratio,psnr,ssim\n");
+    for point in points {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            point.quality,
+            point.metrics.output_bytes,
+            point.bits_per_pixel,
+            point.metrics.ratio,
+            point.metrics.psnr,
+            point.metrics.ssim
+        ));
+    }
+    csv
+}
+
+/// Returns a gnuplot script plotting the PSNR and the SSIM of a sweep against its bits per
+/// pixel, with the data inline.
+///
+/// # Arguments
+/// * `points`: Operating points of the sweep
+/// * `title`: Title of the plot, such as the name of the image
+pub fn to_gnuplot(points: &[SweepPoint], title: &str) -> String {
+    let data: String = points
+        .iter()
+        .map(|point| {
+            format!(
+                "{} {} {} {}\n",
+                point.bits_per_pixel, point.metrics.psnr, point.metrics.ssim, point.quality
+            )
+        })
+        .collect();
+    format!(
+        "$sweep << EOD\n{data}EOD\n\
+         set title \"{}\"\n\
+         set xlabel \"bits per pixel\"\n\
+         set ylabel \"PSNR (dB)\"\n\
+         set y2label \"SSIM\"\n\
+         set ytics nomirror\n\
+         set y2tics\n\
+         set key bottom right\n\
+         plot $sweep using 1:2 with linespoints title \"PSNR\", \\\n     \
+         $sweep using 1:2:(sprintf(\"q%d\", $4)) with labels offset 0,1 notitle, \\\n     \
+         $sweep using 1:3 axes x1y2 with linespoints title \"SSIM\"\n",
+        title.replace('"', "'")
+    )
+}
+
+/// Compresses the image `filename` at every quality, prints a table of the size and quality of
+/// every result to standard out, and optionally writes them as CSV and as a gnuplot script.
+///
+/// # Arguments
+/// * `filename`: Location of the PPM image within your disk
+/// * `qualities`: Qualities between 0 and 100 to compress at
+/// * `options`: Settings shared by every compression
+/// * `csv`: Location of the CSV file to write, or None
+/// * `gnuplot`: Location of the gnuplot script to write, or None
+pub fn sweep(
+    filename: &str,
+    qualities: &[u8],
+    options: &EncoderOptions,
+    csv: Option<&str>,
+    gnuplot: Option<&str>,
+) -> Result<(), CliError> {
+    for &quality in qualities {
+        check_encodable(&EncoderOptions {
+            luma_range: Some(luma_range_for_quality(quality)),
+            ..options.clone()
+        })?;
+    }
+    let image = RgbImage::read(Some(filename))
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let points = sweep_image(
+        &image,
+        input_size(Some(filename), &image),
+        qualities,
+        options,
+    );
+    print!("{}", to_table(&points));
+    let write = |location: &str, contents: String| {
+        std::fs::write(location, contents).map_err(|error| {
+            CliError::new(
+                ErrorKind::Io,
+                format!("Failed to write {location}: {error}"),
+            )
+        })
+    };
+    if let Some(location) = csv {
+        write(location, to_csv(&points))?;
+    }
+    if let Some(location) = gnuplot {
+        write(location, to_gnuplot(&points, filename))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppm::Rgb;
+
+    #[test]
+    fn sweep_outputs_have_one_row_per_quality() {
+        let image = RgbImage {
+            pixels: (0..256u16)
+                .map(|i| Rgb {
+                    red: i,
+                    green: (i * 7) % 256,
+                    blue: 255 - i,
+                })
+                .collect(),
+            width: 16,
+            height: 16,
+            denominator: 255,
+        };
+        let points = sweep_image(&image, 800, &DEFAULT_QUALITIES, &EncoderOptions::default());
+        assert_eq!(to_table(&points).lines().count(), 6);
+        assert_eq!(to_csv(&points).lines().count(), 6);
+        assert!(to_csv(&points).lines().nth(3).unwrap().starts_with("50,"));
+        assert!(to_gnuplot(&points, "x").contains(" 90\nEOD\n"));
+    }
+}
@@ -0,0 +1,199 @@
+use crate::codec::cache::CoefficientCache;
+use crate::codec::stats::Timings;
+use crate::codec::{compress_prepared, decompress_image, EncodeReport, EncoderOptions};
+use crate::layout::{WordLayout, WIDE_LAYOUT};
+use crate::metrics::{psnr, ssim};
+use crate::ppm::RgbImage;
+use crate::refine::MAX_ERROR;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// ## Quality the round trip of an image must reach
+///
+/// `Psnr` is a peak signal-to-noise ratio in decibels and `Ssim` a structural similarity, up
+/// to 1, both measured between the image and its decode as `metrics::psnr` and `metrics::ssim`
+/// do. Code words have a fixed size, so the encoder reaches the target by trying settings in
+/// order of size: those it was given, the luma range of a two-pass encode, and then whichever
+/// is smaller of the refinement of `EncoderOptions::max_error` and the 64-bit layout.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::target::QualityTarget;
+///
+/// let target = QualityTarget::Psnr(40.0);
+/// assert!(target.is_met(40.5));
+/// assert!(!target.is_met(39.9));
+/// assert!(QualityTarget::Ssim(1.5).check().is_err());
+/// ```
+pub enum QualityTarget {
+    Psnr(f64),
+    Ssim(f64),
+}
+
+impl QualityTarget {
+    /// Returns an error unless the PSNR is a positive number of decibels, or the SSIM lies
+    /// above 0 and at most 1.
+    pub fn check(&self) -> Result<(), String> {
+        match *self {
+            QualityTarget::Psnr(db) if db > 0.0 && db.is_finite() => Ok(()),
+            QualityTarget::Psnr(_) => {
+                Err("The target PSNR must be a positive number of decibels".to_string())
+            }
+            QualityTarget::Ssim(value) if value > 0.0 && value <= 1.0 => Ok(()),
+            QualityTarget::Ssim(_) => {
+                Err("The target SSIM must lie above 0 and at most 1".to_string())
+            }
+        }
+    }
+
+    /// Returns the metric of the target between an image and its decode.
+    ///
+    /// # Arguments
+    /// * `original`: Image that was compressed
+    /// * `decoded`: Decode of the compressed image
+    pub fn measure(&self, original: &RgbImage, decoded: &RgbImage) -> f64 {
+        match self {
+            QualityTarget::Psnr(_) => psnr(original, decoded),
+            QualityTarget::Ssim(_) => ssim(original, decoded),
+        }
+    }
+
+    /// Returns true if a measure of the metric of the target reaches it.
+    ///
+    /// # Arguments
+    /// * `measured`: Value of the metric, as returned by `measure`
+    pub fn is_met(&self, measured: f64) -> bool {
+        match *self {
+            QualityTarget::Psnr(target) | QualityTarget::Ssim(target) => measured >= target,
+        }
+    }
+}
+
+/// Compresses an image with the smallest settings found whose round trip reaches `target`,
+/// measured on the in-memory decode of every attempt, which reuses the transforms of `cache`.
+/// Starting from `options`, it tries the luma range of a two-pass encode, which keeps the size,
+/// and then the largest max error whose corrections reach the target, unless the 64-bit layout
+/// reaches it in fewer bytes. A max error of 0 is lossless for 8-bit images; for deeper ones,
+/// whose densities the decode rounds to 8 bits, it is the closest the encoder gets when nothing
+/// reaches the target.
+///
+/// # Arguments
+/// * `cache`: Image to compress, along with its transforms
+/// * `options`: Settings of the first attempt, without a max error, which `cache` fits
+/// * `target`: Quality the round trip must reach
+/// * `timings`: Timings the stages of every attempt are added to
+pub(crate) fn compress_to_target(
+    cache: &CoefficientCache,
+    options: &EncoderOptions,
+    target: QualityTarget,
+    timings: &Timings,
+) -> (Vec<u8>, EncodeReport) {
+    let reference = cache.original();
+    let attempt = |options: &EncoderOptions| {
+        let options = EncoderOptions {
+            target: None,
+            ..options.clone()
+        };
+        let (bytes, report) = compress_prepared(cache, &options, timings)
+            .expect("the attempts only vary settings that were checked");
+        let decoded = timings.time("target", || {
+            decompress_image(&bytes).expect("the encoder writes images it decodes")
+        });
+        let met = target.is_met(target.measure(reference, &decoded));
+        (bytes, report, met)
+    };
+    let (bytes, report, met) = attempt(options);
+    if met {
+        return (bytes, report);
+    }
+    let options = if options.two_pass {
+        options.clone()
+    } else {
+        let two_pass = EncoderOptions {
+            two_pass: true,
+            luma_range: None,
+            ..options.clone()
+        };
+        let (bytes, report, met) = attempt(&two_pass);
+        if met {
+            return (bytes, report);
+        }
+        two_pass
+    };
+    // The corrections get smaller as the bound grows, so the largest bound that reaches the
+    // target gives the smallest refinement. A bound of 0 always reaches it for 8-bit images.
+    let (mut low, mut high) = (0, MAX_ERROR);
+    let refined = |max_error| {
+        attempt(&EncoderOptions {
+            max_error: Some(max_error),
+            ..options.clone()
+        })
+    };
+    let mut best = None;
+    while low < high {
+        let middle = low + (high - low).div_ceil(2);
+        let (bytes, report, met) = refined(middle);
+        if met {
+            best = Some((bytes, report, met));
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    let mut best = best.unwrap_or_else(|| refined(0));
+    if options.layout.word_bits == 32 && !options.chroma_priority {
+        let wide = attempt(&EncoderOptions {
+            layout: WordLayout {
+                chroma_weight: options.layout.chroma_weight,
+                ..WIDE_LAYOUT
+            },
+            ..options
+        });
+        if wide.2 && wide.0.len() < best.0.len() {
+            best = wide;
+        }
+    }
+    (best.0, best.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Encoder;
+    use crate::testkit::{synthetic_image, Pattern};
+
+    #[test]
+    fn the_smallest_settings_reaching_the_target_are_kept() {
+        let image = synthetic_image(Pattern::Noise { seed: 3 }, 24, 16);
+        let plain = Encoder::new().compress(&image).unwrap();
+        let measured = psnr(&image, &decompress_image(&plain).unwrap());
+        let reached = Encoder::new()
+            .target(QualityTarget::Psnr(measured - 1.0))
+            .compress(&image)
+            .unwrap();
+        assert_eq!(reached, plain);
+        for target in [
+            QualityTarget::Psnr(measured + 10.0),
+            QualityTarget::Ssim(0.999),
+        ] {
+            let compressed = Encoder::new().target(target).compress(&image).unwrap();
+            let decoded = decompress_image(&compressed).unwrap();
+            assert!(target.is_met(target.measure(&image, &decoded)));
+            assert!(compressed.len() > plain.len());
+        }
+        let lossless = Encoder::new()
+            .target(QualityTarget::Psnr(200.0))
+            .compress(&image)
+            .unwrap();
+        assert_eq!(decompress_image(&lossless).unwrap().pixels, image.pixels);
+        assert!(Encoder::new()
+            .target(QualityTarget::Psnr(40.0))
+            .max_error(2)
+            .compress(&image)
+            .is_err());
+        assert!(Encoder::new()
+            .target(QualityTarget::Ssim(0.0))
+            .compress(&image)
+            .is_err());
+    }
+}
@@ -0,0 +1,406 @@
+use crate::codec::{compress_image, decompress_with_options, DecodeOptions, EncoderOptions};
+use crate::encoder::Encoder;
+use crate::fixed::Arithmetic;
+use crate::metrics::psnr;
+use crate::ppm::{Rgb, RgbImage};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Synthetic image drawn by `synthetic_image`
+///
+/// `Gradient` ramps red from left to right, green from top to bottom, and blue along the
+/// diagonal. `Checkerboard` alternates a red and a blue square of `cell` pixels. `Noise` draws
+/// every channel of every pixel from a SplitMix64 generator started at `seed`, so the same seed
+/// always gives the same image, on every platform.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::testkit::{synthetic_image, Pattern};
+///
+/// let image = synthetic_image(Pattern::Noise { seed: 7 }, 8, 6);
+/// assert_eq!(image, synthetic_image(Pattern::Noise { seed: 7 }, 8, 6));
+/// assert_ne!(image, synthetic_image(Pattern::Noise { seed: 8 }, 8, 6));
+/// ```
+pub enum Pattern {
+    Gradient,
+    Checkerboard { cell: u32 },
+    Noise { seed: u64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Golden test vector
+///
+/// A synthetic image, the arithmetic it is compressed and decompressed with, and the FNV-1a
+/// hashes of the compressed image and of the decoded pixels that this version of rpeg
+/// produces. Every other setting is the default. A change that alters either hash changes the
+/// bitstream or the decoder, and needs a new format version or new reference hashes.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::testkit::{check_vector, VECTORS};
+///
+/// for vector in &VECTORS {
+///     check_vector(vector).unwrap();
+/// }
+/// ```
+pub struct TestVector {
+    /// Name of the vector, as reported when it fails.
+    pub name: &'static str,
+    /// Image that is compressed.
+    pub pattern: Pattern,
+    /// Width of the image, in pixels.
+    pub width: u32,
+    /// Height of the image, in pixels.
+    pub height: u32,
+    /// Arithmetic of both the encoder and the decoder.
+    pub arithmetic: Arithmetic,
+    /// FNV-1a hash of the compressed image.
+    pub compressed_hash: u64,
+    /// FNV-1a hash of the decoded channels, as big-endian 16-bit values in row-major order.
+    pub decoded_hash: u64,
+}
+
+/// Reference vectors of this version of rpeg. Odd sizes cover the trimming of the last row and
+/// column, and the noise covers the clipping of the quantized coefficients.
+pub const VECTORS: [TestVector; 7] = [
+    TestVector {
+        name: "gradient-16x10",
+        pattern: Pattern::Gradient,
+        width: 16,
+        height: 10,
+        arithmetic: Arithmetic::Float,
+        compressed_hash: 0x3d9295bdaa4688db,
+        decoded_hash: 0x9688b3373fd18165,
+    },
+    TestVector {
+        name: "checkerboard-17x11",
+        pattern: Pattern::Checkerboard { cell: 3 },
+        width: 17,
+        height: 11,
+        arithmetic: Arithmetic::Float,
+        compressed_hash: 0x28ab0db1910adb15,
+        decoded_hash: 0x07dc2e83df78f1a5,
+    },
+    TestVector {
+        name: "noise-32x24",
+        pattern: Pattern::Noise { seed: 411 },
+        width: 32,
+        height: 24,
+        arithmetic: Arithmetic::Float,
+        compressed_hash: 0x2d76408a220b65be,
+        decoded_hash: 0xa04af2bd0bffa6fc,
+    },
+    TestVector {
+        name: "gradient-16x10-fixed",
+        pattern: Pattern::Gradient,
+        width: 16,
+        height: 10,
+        arithmetic: Arithmetic::Fixed,
+        compressed_hash: 0x3d9295bdaa4688db,
+        decoded_hash: 0x2bcceb635f555460,
+    },
+    TestVector {
+        name: "noise-32x24-fixed",
+        pattern: Pattern::Noise { seed: 411 },
+        width: 32,
+        height: 24,
+        arithmetic: Arithmetic::Fixed,
+        compressed_hash: 0x2d76408a220b65be,
+        decoded_hash: 0xc54326d21621618a,
+    },
+    TestVector {
+        name: "gradient-16x10-single",
+        pattern: Pattern::Gradient,
+        width: 16,
+        height: 10,
+        arithmetic: Arithmetic::Single,
+        compressed_hash: 0x3d9295bdaa4688db,
+        decoded_hash: 0x9688b3373fd18165,
+    },
+    TestVector {
+        name: "noise-32x24-single",
+        pattern: Pattern::Noise { seed: 411 },
+        width: 32,
+        height: 24,
+        arithmetic: Arithmetic::Single,
+        compressed_hash: 0x2d76408a220b65be,
+        decoded_hash: 0xa04af2bd0bffa6fc,
+    },
+];
+
+/// Returns the next value of a SplitMix64 generator and advances its state.
+///
+/// # Arguments
+/// * `state`: State of the generator
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut value = *state;
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+    value ^ (value >> 31)
+}
+
+/// Returns the FNV-1a hash of `bytes`. It only uses integer arithmetic, so a hash is the same on
+/// every platform.
+///
+/// # Arguments
+/// * `bytes`: Bytes to hash
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Returns the `width` by `height` image of `pattern`, with a denominator of 255.
+///
+/// # Arguments
+/// * `pattern`: What to draw
+/// * `width`: Width of the image, in pixels
+/// * `height`: Height of the image, in pixels
+pub fn synthetic_image(pattern: Pattern, width: u32, height: u32) -> RgbImage {
+    let ramp =
+        |position: u32, length: u32| (position * 255 / length.saturating_sub(1).max(1)) as u16;
+    let mut state = match pattern {
+        Pattern::Noise { seed } => seed,
+        _ => 0,
+    };
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for row in 0..height {
+        for col in 0..width {
+            pixels.push(match pattern {
+                Pattern::Gradient => Rgb {
+                    red: ramp(col, width),
+                    green: ramp(row, height),
+                    blue: ramp(col + row, width + height - 1),
+                },
+                Pattern::Checkerboard { cell } => {
+                    if (col / cell.max(1) + row / cell.max(1)) % 2 == 0 {
+                        Rgb {
+                            red: 230,
+                            green: 40,
+                            blue: 40,
+                        }
+                    } else {
+                        Rgb {
+                            red: 20,
+                            green: 60,
+                            blue: 200,
+                        }
+                    }
+                }
+                Pattern::Noise { .. } => {
+                    let value = split_mix(&mut state);
+                    Rgb {
+                        red: (value >> 56) as u16,
+                        green: (value >> 48 & 0xff) as u16,
+                        blue: (value >> 40 & 0xff) as u16,
+                    }
+                }
+            });
+        }
+    }
+    RgbImage {
+        pixels,
+        width,
+        height,
+        denominator: 255,
+    }
+}
+
+/// Compresses and decompresses the image of `vector`, and returns the compressed image with
+/// the hashes of it and of the decoded pixels. Forks that change the bitstream on purpose can
+/// print these hashes into their own table of vectors.
+///
+/// # Arguments
+/// * `vector`: Vector to generate; its hashes are ignored
+pub fn generate_vector(vector: &TestVector) -> Result<(Vec<u8>, u64, u64), String> {
+    let image = synthetic_image(vector.pattern, vector.width, vector.height);
+    let options = EncoderOptions {
+        arithmetic: vector.arithmetic,
+        ..Default::default()
+    };
+    let compressed = compress_image(&image, &options);
+    let decode = DecodeOptions {
+        arithmetic: vector.arithmetic,
+        ..Default::default()
+    };
+    let decoded = decompress_with_options(&compressed, &decode)?;
+    let channels: Vec<u8> = decoded
+        .pixels
+        .iter()
+        .flat_map(|pixel| [pixel.red, pixel.green, pixel.blue])
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    let compressed_hash = fnv1a(&compressed);
+    Ok((compressed, compressed_hash, fnv1a(&channels)))
+}
+
+/// Checks that compressing and decompressing the image of `vector` gives its reference hashes,
+/// and describes the first mismatch otherwise.
+///
+/// # Arguments
+/// * `vector`: Vector to check
+pub fn check_vector(vector: &TestVector) -> Result<(), String> {
+    let (_, compressed_hash, decoded_hash) = generate_vector(vector)?;
+    if compressed_hash != vector.compressed_hash {
+        return Err(format!(
+            "{}: compressed image hashes to {compressed_hash:#018x}, expected {:#018x}",
+            vector.name, vector.compressed_hash
+        ));
+    }
+    if decoded_hash != vector.decoded_hash {
+        return Err(format!(
+            "{}: decoded pixels hash to {decoded_hash:#018x}, expected {:#018x}",
+            vector.name, vector.decoded_hash
+        ));
+    }
+    Ok(())
+}
+
+/// Checks every vector of `VECTORS`, and returns the description of every mismatch.
+pub fn check_all() -> Result<(), Vec<String>> {
+    let failures: Vec<String> = VECTORS
+        .iter()
+        .filter_map(|vector| check_vector(vector).err())
+        .collect();
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+/// Returns the PSNR of `image` after a round trip through the codec with `options`, decoded
+/// with the same arithmetic and otherwise the default settings. Settings the encoder rejects
+/// are returned as errors.
+///
+/// # Arguments
+/// * `image`: Image to compress
+/// * `options`: Settings used to compress the image
+pub fn roundtrip_psnr(image: &RgbImage, options: &EncoderOptions) -> Result<f64, String> {
+    let compressed = Encoder::from(options.clone()).compress(image)?;
+    let decode = DecodeOptions {
+        arithmetic: options.arithmetic,
+        ..Default::default()
+    };
+    Ok(psnr(image, &decompress_with_options(&compressed, &decode)?))
+}
+
+/// Returns how many decibels of PSNR a round trip of `image` through the codec with `options`
+/// loses compared to one with the default settings. It is negative when `options` do better,
+/// and 0 when both round trips are lossless.
+///
+/// # Arguments
+/// * `image`: Image to compress
+/// * `options`: Settings used to compress the image
+pub fn psnr_loss(image: &RgbImage, options: &EncoderOptions) -> Result<f64, String> {
+    let reference = roundtrip_psnr(image, &EncoderOptions::default())?;
+    let psnr = roundtrip_psnr(image, options)?;
+    Ok(if reference == psnr {
+        0.0
+    } else {
+        reference - psnr
+    })
+}
+
+/// Panics unless `image` survives a round trip through the codec with `options` and loses at
+/// most `max_psnr_loss` decibels of PSNR compared to the default settings, so that code
+/// embedding the codec can hold its settings to a quality in its own tests.
+///
+/// # Arguments
+/// * `image`: Image to compress
+/// * `options`: Settings used to compress the image
+/// * `max_psnr_loss`: Largest loss allowed, in decibels
+pub fn assert_roundtrip_within(image: &RgbImage, options: &EncoderOptions, max_psnr_loss: f64) {
+    match psnr_loss(image, options) {
+        Ok(loss) => assert!(
+            loss <= max_psnr_loss,
+            "round trip of a {}x{} image loses {loss:.3} dB of PSNR, more than {max_psnr_loss} dB",
+            image.width,
+            image.height
+        ),
+        Err(message) => panic!(
+            "round trip of a {}x{} image failed: {message}",
+            image.width, image.height
+        ),
+    }
+}
+
+/// Returns a proptest strategy for images from 1x1 to `max_width` by `max_height` pixels, with a
+/// denominator of 255 half of the time and of any 16-bit value otherwise, and random channels
+/// below it.
+///
+/// # Arguments
+/// * `max_width`: Largest width generated, at least 1
+/// * `max_height`: Largest height generated, at least 1
+#[cfg(feature = "proptest")]
+pub fn arbitrary_image(
+    max_width: u32,
+    max_height: u32,
+) -> impl proptest::strategy::Strategy<Value = RgbImage> {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    (
+        1..=max_width.max(1),
+        1..=max_height.max(1),
+        prop_oneof![Just(255_u16), 1..=u16::MAX],
+    )
+        .prop_flat_map(|(width, height, denominator)| {
+            let channel = 0..=denominator;
+            let pixel = (channel.clone(), channel.clone(), channel)
+                .prop_map(|(red, green, blue)| Rgb { red, green, blue });
+            vec(pixel, (width * height) as usize).prop_map(move |pixels| RgbImage {
+                pixels,
+                width,
+                height,
+                denominator,
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vectors_match_their_reference_hashes() {
+        assert_eq!(check_all(), Ok(()));
+        let changed = TestVector {
+            decoded_hash: VECTORS[0].decoded_hash ^ 1,
+            ..VECTORS[0]
+        };
+        assert!(check_vector(&changed)
+            .unwrap_err()
+            .contains("decoded pixels"));
+    }
+
+    #[test]
+    fn roundtrips_are_held_to_their_loss() {
+        let image = synthetic_image(Pattern::Noise { seed: 411 }, 32, 24);
+        let fixed = EncoderOptions {
+            arithmetic: Arithmetic::Fixed,
+            ..Default::default()
+        };
+        assert_roundtrip_within(&image, &fixed, 0.1);
+        let coarse = EncoderOptions {
+            luma_range: Some(0.02),
+            ..Default::default()
+        };
+        assert!(psnr_loss(&image, &coarse).unwrap() > 0.1);
+        assert!(roundtrip_psnr(&image, &coarse).unwrap() < roundtrip_psnr(&image, &fixed).unwrap());
+    }
+
+    #[cfg(feature = "proptest")]
+    proptest::proptest! {
+        #[test]
+        fn fixed_point_keeps_the_quality_of_any_image(image in arbitrary_image(24, 24)) {
+            let fixed = EncoderOptions {
+                arithmetic: Arithmetic::Fixed,
+                ..Default::default()
+            };
+            assert_roundtrip_within(&image, &fixed, 0.5);
+        }
+    }
+}
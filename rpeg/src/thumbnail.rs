@@ -0,0 +1,139 @@
+use crate::codec::decompress_image;
+use crate::error::{CliError, ErrorKind};
+use crate::format::Header;
+use crate::io::{read_input, Output};
+use crate::ppm::{Rgb, RgbImage};
+
+/// Width in pixels of the thumbnails embedded by `--embed-thumbnail`. Narrower images keep
+/// their width.
+pub const THUMBNAIL_WIDTH: u32 = 64;
+
+/// Returns a copy of `image` shrunk to `width` pixels wide, keeping its aspect ratio, by
+/// averaging the pixels every thumbnail pixel covers. Images narrower than `width` keep their
+/// size. The thumbnail has a denominator of 255.
+///
+/// # Arguments
+/// * `image`: Image to shrink
+/// * `width`: Largest width of the thumbnail
+pub fn downscale(image: &RgbImage, width: u32) -> RgbImage {
+    let (source_width, source_height) = (image.width as usize, image.height as usize);
+    if source_width == 0 || source_height == 0 {
+        return RgbImage {
+            pixels: Vec::new(),
+            width: 0,
+            height: 0,
+            denominator: 255,
+        };
+    }
+    let width = (width as usize).clamp(1, source_width);
+    let height =
+        ((source_height * width + source_width / 2) / source_width).clamp(1, u16::MAX as usize);
+    shrink(image, width as u32, height as u32, 255)
+}
+
+/// Returns a copy of `image` shrunk to `width` by `height` pixels, by averaging the pixels
+/// every pixel of the copy covers, with its values rescaled to `denominator`.
+///
+/// # Arguments
+/// * `image`: Image to shrink, at least as large as the copy
+/// * `width`: Width of the copy, at least 1
+/// * `height`: Height of the copy, at least 1
+/// * `denominator`: Denominator of the copy
+pub fn shrink(image: &RgbImage, width: u32, height: u32, denominator: u16) -> RgbImage {
+    let (source_width, source_height) = (image.width as usize, image.height as usize);
+    let (width, height) = (width as usize, height as usize);
+    let scale = denominator as f64 / image.denominator.max(1) as f64;
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in 0..height {
+        let rows = row * source_height / height..((row + 1) * source_height / height).max(row + 1);
+        for col in 0..width {
+            let cols = col * source_width / width..((col + 1) * source_width / width).max(col + 1);
+            let mut sum = [0.0; 3];
+            for y in rows.clone() {
+                for x in cols.clone() {
+                    let pixel = &image.pixels[y * source_width + x];
+                    sum[0] += pixel.red as f64;
+                    sum[1] += pixel.green as f64;
+                    sum[2] += pixel.blue as f64;
+                }
+            }
+            let count = (rows.len() * cols.len()) as f64;
+            let [red, green, blue] =
+                sum.map(|total| (total * scale / count).round().min(denominator as f64) as u16);
+            pixels.push(Rgb { red, green, blue });
+        }
+    }
+    RgbImage {
+        pixels,
+        width: width as u32,
+        height: height as u32,
+        denominator,
+    }
+}
+
+/// Returns the thumbnail embedded in the header of a compressed image. Images compressed
+/// without one are decoded and shrunk to `THUMBNAIL_WIDTH` instead, which is much slower.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+pub fn read_thumbnail(bytes: &[u8]) -> Result<RgbImage, String> {
+    let (header, _) = Header::read(bytes)?;
+    match header.thumbnail {
+        Some(thumbnail) => Ok(thumbnail),
+        None => Ok(downscale(&decompress_image(bytes)?, THUMBNAIL_WIDTH)),
+    }
+}
+
+/// Writes the thumbnail of a compressed image as a PPM image.
+///
+/// # Arguments
+/// * `filename`: Location of the compressed image, or None to read it from standard in
+/// * `output`: Destination of the thumbnail
+pub fn thumb(filename: Option<&str>, output: &Output) -> Result<(), CliError> {
+    let bytes = read_input(filename)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let thumbnail = read_thumbnail(&bytes).map_err(|message| CliError::stream(&bytes, message))?;
+    output
+        .write_with(|writer| thumbnail.write_to(writer))
+        .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{compress_image, EncoderOptions};
+
+    #[test]
+    fn thumbnails_are_embedded_in_the_header() {
+        let image = RgbImage {
+            pixels: (0..256 * 100)
+                .map(|i| Rgb {
+                    red: (i % 256) as u16,
+                    green: 128,
+                    blue: (i / 256) as u16,
+                })
+                .collect(),
+            width: 256,
+            height: 100,
+            denominator: 255,
+        };
+        let small = downscale(&image, THUMBNAIL_WIDTH);
+        assert_eq!((small.width, small.height), (64, 25));
+        assert_eq!(small.pixels[0].green, 128);
+        assert_eq!(small.pixels[0].red, 2);
+
+        let options = EncoderOptions {
+            embed_thumbnail: true,
+            ..Default::default()
+        };
+        let plain = compress_image(&image, &EncoderOptions::default());
+        let embedded = compress_image(&image, &options);
+        assert_eq!(embedded.len(), plain.len() + 4 + 64 * 25 * 3);
+        assert_eq!(read_thumbnail(&embedded), Ok(small));
+        assert_eq!(
+            decompress_image(&embedded).unwrap(),
+            decompress_image(&plain).unwrap()
+        );
+        assert_eq!(read_thumbnail(&plain).unwrap().width, 64);
+    }
+}
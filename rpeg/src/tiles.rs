@@ -0,0 +1,93 @@
+use scan_fmt::scan_fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// ## Represent a rectangle of pixels
+///
+/// `x` and `y` locate the top-left corner of the rectangle within the image.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::tiles::Rect;
+///
+/// let rect = Rect { x: 0, y: 8, width: 16, height: 4 };
+/// assert_eq!(Rect::parse("0,8,16,4"), Ok(rect));
+/// ```
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// Parses a rectangle written as `x,y,w,h`.
+    ///
+    /// # Arguments
+    /// * `text`: Rectangle description, as passed to `--region`
+    pub fn parse(text: &str) -> Result<Rect, String> {
+        let (x, y, width, height) = scan_fmt!(text, "{d},{d},{d},{d}", u32, u32, u32, u32)
+            .map_err(|_| format!("Expected a rectangle as x,y,w,h, found \"{text}\""))?;
+
+        Ok(Rect {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    /// Returns true iff the two rectangles share at least one pixel.
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.x < other.x.saturating_add(other.width)
+            && other.x < self.x.saturating_add(self.width)
+            && self.y < other.y.saturating_add(other.height)
+            && other.y < self.y.saturating_add(self.height)
+    }
+}
+
+/// Splits an image into tiles of `tile_size` x `tile_size` pixels, listed in row-major order.
+/// The tiles on the right and bottom edges are cut short to fit the image. A `tile_size` of 0
+/// gives a single tile covering the whole image.
+///
+/// # Arguments
+/// * `width`: Width of the (trimmed) image in pixels
+/// * `height`: Height of the (trimmed) image in pixels
+/// * `tile_size`: Side of a tile in pixels, which must be even
+pub fn tile_rects(width: u32, height: u32, tile_size: u32) -> Vec<Rect> {
+    if tile_size == 0 {
+        return vec![Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }];
+    }
+    let mut rects = Vec::new();
+    for y in (0..height).step_by(tile_size as usize) {
+        for x in (0..width).step_by(tile_size as usize) {
+            rects.push(Rect {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            });
+        }
+    }
+    rects
+}
+
+/// Returns the row-major index, within the whole image, of every 2x2 block inside `tile`.
+///
+/// # Arguments
+/// * `tile`: Tile whose corners lie on even coordinates
+/// * `width`: Width of the (trimmed) image in pixels
+pub fn tile_block_indices(tile: &Rect, width: u32) -> Vec<usize> {
+    let blocks_per_row = (width / 2) as usize;
+    let (left, top) = ((tile.x / 2) as usize, (tile.y / 2) as usize);
+    (top..top + (tile.height / 2) as usize)
+        .flat_map(|row| {
+            (left..left + (tile.width / 2) as usize).map(move |col| row * blocks_per_row + col)
+        })
+        .collect()
+}
@@ -0,0 +1,127 @@
+use crate::pixel::Pixel;
+use crate::ppm::Rgb;
+use crate::structs::ComponentVideo;
+use array2::array2::Array2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Stylized rendering of the decoded luma
+///
+/// `Monochrome` keeps the luma of every pixel and drops its chroma, giving a gray image.
+/// `Duotone` maps the luma through a gradient from `dark`, for black, to `light`, for white,
+/// both 8-bit colors. The decoder applies the tint where it converts the luma and chroma of the
+/// code words to pixels, so stylized previews cost no more than a plain decode.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::ppm::Rgb;
+/// use rpeg::tint::Tint;
+///
+/// let tint = Tint::parse_duotone("1a2b3c,#ffe0a0").unwrap();
+/// let dark = Rgb { red: 0x1a, green: 0x2b, blue: 0x3c };
+/// let light = Rgb { red: 0xff, green: 0xe0, blue: 0xa0 };
+/// assert_eq!(tint, Tint::Duotone { dark, light });
+/// assert!(Tint::parse_duotone("1a2b3c").is_err());
+/// ```
+pub enum Tint {
+    Monochrome,
+    Duotone { dark: Rgb, light: Rgb },
+}
+
+impl Tint {
+    /// Parses a duotone given as two hex colors separated by a comma, the dark one first, such
+    /// as `1a2b3c,ffe0a0`. Either color may start with `#`.
+    ///
+    /// # Arguments
+    /// * `text`: The two colors
+    pub fn parse_duotone(text: &str) -> Result<Tint, String> {
+        let color = |text: &str| {
+            let digits = text.trim().trim_start_matches('#');
+            if digits.len() != 6 || !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+                return None;
+            }
+            let channel =
+                |start: usize| u16::from_str_radix(&digits[start..start + 2], 16).unwrap();
+            Some(Rgb {
+                red: channel(0),
+                green: channel(2),
+                blue: channel(4),
+            })
+        };
+        match text
+            .split_once(',')
+            .map(|(dark, light)| (color(dark), color(light)))
+        {
+            Some((Some(dark), Some(light))) => Ok(Tint::Duotone { dark, light }),
+            _ => Err(
+                "A duotone is two hex colors separated by a comma, such as 1a2b3c,ffe0a0"
+                    .to_string(),
+            ),
+        }
+    }
+
+    /// Returns the luma and chroma of the ends of the gradient as pixels of type `P` see them:
+    /// black and white for `Monochrome`, and the two colors for `Duotone`.
+    fn ends<P: Pixel>(&self) -> [ComponentVideo; 2] {
+        match self {
+            Tint::Monochrome => [0.0, 1.0].map(|y| ComponentVideo {
+                y,
+                pb: 0.0,
+                pr: 0.0,
+            }),
+            Tint::Duotone { dark, light } => [dark, light].map(|color| {
+                let mut pixel = P::default();
+                for (index, value) in [color.red, color.green, color.blue].into_iter().enumerate() {
+                    pixel.set_channel(index, value);
+                }
+                pixel.to_ypbpr(255.0)
+            }),
+        }
+    }
+}
+
+/// Replaces the luma and chroma of every pixel by those of the tint at its luma. The color
+/// transform is linear, so interpolating between the luma and chroma of the two ends gives the
+/// straight gradient between their colors.
+///
+/// # Arguments
+/// * `cv_image`: Decoded luma and chroma, about to be converted to pixels of type `P`
+/// * `tint`: Tint to apply
+pub fn tint_component_video<P: Pixel>(cv_image: &mut Array2<ComponentVideo>, tint: &Tint) {
+    let [dark, light] = tint.ends::<P>();
+    for video in cv_image.data.iter_mut() {
+        let luma = video.y.clamp(0.0, 1.0);
+        *video = ComponentVideo {
+            y: dark.y + luma * (light.y - dark.y),
+            pb: dark.pb + luma * (light.pb - dark.pb),
+            pr: dark.pr + luma * (light.pr - dark.pr),
+        };
+    }
+}
+
+/// Applies a tint to pixels that were decoded without going through luma and chroma, such as
+/// the palette-coded tiles, by converting them to luma and chroma and back as pixels of type
+/// `P`.
+///
+/// # Arguments
+/// * `image`: Decoded pixels, whose densities are those of `P`
+/// * `denominator`: Denominator of the densities of the pixels
+/// * `tint`: Tint to apply
+pub fn tint_pixels<P: Pixel>(image: &Array2<Rgb>, denominator: u16, tint: &Tint) -> Array2<Rgb> {
+    let mut cv_image = image.map(|rgb| {
+        let mut pixel = P::default();
+        for (index, value) in [rgb.red, rgb.green, rgb.blue].into_iter().enumerate() {
+            pixel.set_channel(index, value);
+        }
+        pixel.to_ypbpr(denominator as f64)
+    });
+    tint_component_video::<P>(&mut cv_image, tint);
+    cv_image.map(|video| {
+        let pixel = P::from_ypbpr(video);
+        Rgb {
+            red: pixel.channel(0),
+            green: pixel.channel(1),
+            blue: pixel.channel(2),
+        }
+    })
+}
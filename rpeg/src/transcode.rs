@@ -0,0 +1,187 @@
+use crate::codec::{
+    assemble, block_ranges, read_prelude, read_tile_words, write_words, DecodeOptions,
+    EncoderOptions, Prelude,
+};
+use crate::dct_coeff::{dequantize, masked_luma_range, quantize};
+use crate::error::{CliError, ErrorKind};
+use crate::format::{Header, DEFAULT_LUMA_RANGE_MILLIS};
+use crate::io::{read_input, Output};
+use crate::structs::{DCTCoefficient, QuantizedBlock};
+use crate::tiles::{tile_block_indices, tile_rects};
+
+/// Requantizes a compressed image with the luma range and word layout of `options`, without
+/// going back to pixels: every code word is dequantized with the range and layout it was
+/// written with, then quantized again. The header, regions of interest, tiling, word order, and
+/// palette-coded tiles of the image are kept as they are, and a missing luma range gives the
+/// default one, as with `compress_image`, and the refinement of a near-lossless image, whose
+/// corrections no longer apply, is dropped. Returns an error for the settings that need the
+/// pixels, `two_pass`, `optimize`, `chroma_priority`, `max_error`, and `target`.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+/// * `options`: Settings giving the luma range and layout of the new code words
+pub fn transcode_image(bytes: &[u8], options: &EncoderOptions) -> Result<Vec<u8>, String> {
+    if options.two_pass
+        || options.optimize
+        || options.chroma_priority
+        || options.max_error.is_some()
+        || options.target.is_some()
+    {
+        return Err(
+            "Two-pass encoding, rounding optimization, chroma priority, a max error, and a quality target need the pixels"
+                .to_string(),
+        );
+    }
+    let luma_range = options
+        .luma_range
+        .map_or(DEFAULT_LUMA_RANGE_MILLIS, |range| {
+            (range * 1000.0).round() as u16
+        });
+    if !(1..=500).contains(&luma_range) {
+        return Err("The luma range must lie between 0.001 and 0.5".to_string());
+    }
+    options.layout.verify()?;
+    let Prelude {
+        header,
+        levels,
+        ranges,
+        payloads,
+    } = read_prelude(bytes, &DecodeOptions::default())?;
+    let target = Header {
+        luma_range,
+        layout: options.layout,
+        refinement: None,
+        ..header.clone()
+    };
+    let target_ranges = block_ranges(&target, &levels);
+    let tiles = tile_rects(
+        header.coded_width(),
+        header.coded_height(),
+        header.tile_size,
+    );
+    let mut transcoded = Vec::with_capacity(payloads.len());
+    for (index, (tile, payload)) in tiles.iter().zip(payloads).enumerate() {
+        if !header.tile_mode(index).holds_words() {
+            transcoded.push(payload.to_vec());
+            continue;
+        }
+        let indices = tile_block_indices(tile, header.coded_width());
+        let words: Vec<u64> = read_tile_words(payload, indices.len(), &header, false)?
+            .into_iter()
+            .zip(indices)
+            .map(|(word, block)| {
+                let coefficient = DCTCoefficient::from(header.layout.unpack(word));
+                let (mut range, mut target_range) = (ranges[block], target_ranges[block]);
+                if header.perceptual {
+                    range = masked_luma_range(range, coefficient.a, &header.layout);
+                }
+                let transform = dequantize(&coefficient, range, &header.layout);
+                if header.perceptual {
+                    let a = (transform.a * target.layout.a_scale()).round();
+                    target_range = masked_luma_range(target_range, a, &target.layout);
+                }
+                let coefficient = quantize(&transform, target_range, &target.layout);
+                target.layout.pack(&QuantizedBlock::from(&coefficient))
+            })
+            .collect();
+        transcoded.push(write_words(&words, target.order, &target.layout));
+    }
+    Ok(assemble(&target, &levels, &transcoded))
+}
+
+/// Requantizes the compressed image `filename` with `transcode_image` and writes the result
+/// to `output`. Failures are returned with the category the CLI exits with.
+///
+/// # Arguments
+/// * `filename`: Compressed image to requantize, or None to read from standard in
+/// * `output`: Destination of the requantized image
+/// * `options`: Settings giving the luma range and layout of the new code words
+pub fn transcode(
+    filename: Option<&str>,
+    output: &Output,
+    options: &EncoderOptions,
+) -> Result<(), CliError> {
+    let bytes = read_input(filename)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let transcoded = transcode_image(&bytes, options).map_err(|message| {
+        if options.two_pass || options.optimize {
+            CliError::new(ErrorKind::BadArguments, message)
+        } else {
+            CliError::stream(&bytes, message)
+        }
+    })?;
+    output
+        .write(&transcoded)
+        .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{compress_image, decompress_image};
+    use crate::dct_coeff::luma_range_for_quality;
+    use crate::layout::{NARROW_LAYOUT, WIDE_LAYOUT};
+    use crate::ppm::{Rgb, RgbImage};
+    use crate::roi::Region;
+
+    #[test]
+    fn transcoding_requantizes_without_the_pixels() {
+        let image = RgbImage {
+            pixels: (0..32 * 16_u16)
+                .map(|i| Rgb {
+                    red: (i % 32) * 8,
+                    green: (i / 32) * 16,
+                    blue: if (i / 4) % 2 == 0 { 40 } else { 200 },
+                })
+                .collect(),
+            width: 32,
+            height: 16,
+            denominator: 255,
+        };
+        let squared_error = |bytes: &[u8]| {
+            let decoded = decompress_image(bytes).unwrap();
+            let channels = |pixel: &Rgb| [pixel.red, pixel.green, pixel.blue].map(f64::from);
+            decoded
+                .pixels
+                .iter()
+                .zip(&image.pixels)
+                .map(|(a, b)| {
+                    let (a, b) = (channels(a), channels(b));
+                    (0..3).map(|i| (a[i] - b[i]).powi(2)).sum::<f64>()
+                })
+                .sum::<f64>()
+        };
+        let wide = EncoderOptions {
+            layout: WIDE_LAYOUT,
+            regions: vec![Region {
+                x: 0,
+                y: 0,
+                width: 8,
+                height: 8,
+                quality: 90,
+            }],
+            tile_size: 16,
+            progressive: true,
+            ..EncoderOptions::default()
+        };
+        let archived = compress_image(&image, &wide);
+        let narrow = EncoderOptions {
+            luma_range: Some(luma_range_for_quality(40)),
+            layout: NARROW_LAYOUT,
+            ..wide.clone()
+        };
+        let transcoded = transcode_image(&archived, &narrow).unwrap();
+        let direct = compress_image(&image, &narrow);
+        assert_eq!(transcoded.len(), direct.len());
+        assert!(transcoded.len() < archived.len());
+        assert!(squared_error(&transcoded) <= squared_error(&direct) * 1.5);
+
+        // With the same range and layout, every word dequantizes and quantizes back to itself.
+        assert_eq!(transcode_image(&archived, &wide), Ok(archived));
+        let two_pass = EncoderOptions {
+            two_pass: true,
+            ..EncoderOptions::default()
+        };
+        assert!(transcode_image(&direct, &two_pass).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::conversions::{component_video_to_blocks, pixels_to_component_video};
+use crate::dct_coeff::transform_block;
+use crate::layout::WordLayout;
+use crate::ppm::Rgb;
+use crate::structs::TransformedBlock;
+use array2::array2::Array2;
+
+/// Number of bins of the histogram of b, c, and d, which covers magnitudes from 0 to 0.5.
+const BINS: usize = 8192;
+
+/// Largest magnitude b, c, or d can reach, since every luma lies between 0 and 1.
+const MAX_MAGNITUDE: f64 = 0.5;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Statistics of the b, c, and d coefficients gathered by the first pass
+///
+/// The histogram counts the magnitudes of the coefficients, from which the quantization error
+/// of any luma range can be estimated without encoding the image again.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::layout::NARROW_LAYOUT;
+/// use rpeg::two_pass::CoefficientHistogram;
+///
+/// let mut histogram = CoefficientHistogram::new();
+/// for coefficient in [0.01, -0.02, 0.015, 0.0] {
+///     histogram.add(coefficient);
+/// }
+/// let best = histogram.best_luma_range(&NARROW_LAYOUT) as f64 / 1000.0;
+/// assert!(
+///     histogram.quantization_error(best, &NARROW_LAYOUT)
+///         <= histogram.quantization_error(0.3, &NARROW_LAYOUT)
+/// );
+/// ```
+pub struct CoefficientHistogram {
+    counts: Vec<u64>,
+}
+
+impl Default for CoefficientHistogram {
+    fn default() -> Self {
+        CoefficientHistogram::new()
+    }
+}
+
+impl CoefficientHistogram {
+    /// Returns an empty histogram.
+    pub fn new() -> Self {
+        CoefficientHistogram {
+            counts: vec![0; BINS],
+        }
+    }
+
+    /// Counts one b, c, or d coefficient.
+    ///
+    /// # Arguments
+    /// * `coefficient`: Coefficient before quantization
+    pub fn add(&mut self, coefficient: f64) {
+        let bin = (coefficient.abs() / MAX_MAGNITUDE * BINS as f64) as usize;
+        self.counts[bin.min(BINS - 1)] += 1;
+    }
+
+    /// Returns the total squared error of the counted coefficients once clamped to
+    /// `luma_range` and quantized into the signed fields of `layout`, taking every coefficient
+    /// at the center of its bin.
+    ///
+    /// # Arguments
+    /// * `luma_range`: Range b, c, and d are clamped to
+    /// * `layout`: Layout of the code words
+    pub fn quantization_error(&self, luma_range: f64, layout: &WordLayout) -> f64 {
+        let scale = layout.bcd_levels() / luma_range;
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bin, count)| {
+                let value = (bin as f64 + 0.5) * MAX_MAGNITUDE / BINS as f64;
+                let decoded = (value.min(luma_range) * scale).round() / scale;
+                (value - decoded) * (value - decoded) * *count as f64
+            })
+            .sum()
+    }
+
+    /// Returns the luma range, in thousandths, whose quantization error is the smallest. Ties
+    /// go to the wider range.
+    ///
+    /// # Arguments
+    /// * `layout`: Layout of the code words
+    pub fn best_luma_range(&self, layout: &WordLayout) -> u16 {
+        (1..=500_u16)
+            .rev()
+            .map(|millis| {
+                let error = self.quantization_error(millis as f64 / 1000.0, layout);
+                (millis, error)
+            })
+            .fold(
+                (500, f64::INFINITY),
+                |(best, best_error), (millis, error)| {
+                    if error < best_error {
+                        (millis, error)
+                    } else {
+                        (best, best_error)
+                    }
+                },
+            )
+            .0
+    }
+}
+
+/// First pass of `--two-pass`: returns the histogram of the b, c, and d coefficients of the
+/// background blocks of an image, those whose level is 0.
+///
+/// # Arguments
+/// * `image`: Image with even dimensions
+/// * `image_denominator`: Denominator of the Rgb values of the image
+/// * `levels`: Level of every block in row-major order, as returned by `roi::block_levels`
+pub fn gather(image: &Array2<Rgb>, image_denominator: u16, levels: &[u8]) -> CoefficientHistogram {
+    let blocks = component_video_to_blocks(&pixels_to_component_video(image, image_denominator));
+    let transforms: Vec<TransformedBlock> = blocks.data.iter().map(transform_block).collect();
+    gather_transforms(&transforms, levels)
+}
+
+/// Returns the histogram of `gather` from the transforms of the blocks, such as those of a
+/// `CoefficientCache`.
+///
+/// # Arguments
+/// * `transforms`: Transform of every block in row-major order
+/// * `levels`: Level of every block in row-major order, as returned by `roi::block_levels`
+pub fn gather_transforms<'a>(
+    transforms: impl IntoIterator<Item = &'a TransformedBlock>,
+    levels: &[u8],
+) -> CoefficientHistogram {
+    let mut histogram = CoefficientHistogram::new();
+    for (transform, level) in transforms.into_iter().zip(levels.iter()) {
+        if *level == 0 {
+            histogram.add(transform.b);
+            histogram.add(transform.c);
+            histogram.add(transform.d);
+        }
+    }
+    histogram
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dct_coeff::DEFAULT_LUMA_RANGE;
+    use crate::layout::{NARROW_LAYOUT, WIDE_LAYOUT};
+
+    #[test]
+    fn the_best_range_covers_the_coefficients() {
+        let mut smooth = CoefficientHistogram::new();
+        let mut edges = CoefficientHistogram::new();
+        for i in 0..1000 {
+            let small = (i % 40) as f64 / 1000.0 - 0.02;
+            smooth.add(small);
+            edges.add(small);
+            if i % 10 == 0 {
+                edges.add(0.45);
+            }
+        }
+        let smooth_range = smooth.best_luma_range(&NARROW_LAYOUT);
+        assert!((15..=40).contains(&smooth_range), "{smooth_range}");
+        assert!(edges.best_luma_range(&NARROW_LAYOUT) > smooth_range);
+        assert!(
+            smooth.quantization_error(smooth_range as f64 / 1000.0, &NARROW_LAYOUT)
+                < smooth.quantization_error(DEFAULT_LUMA_RANGE, &NARROW_LAYOUT)
+        );
+        assert!(
+            smooth.quantization_error(DEFAULT_LUMA_RANGE, &WIDE_LAYOUT)
+                < smooth.quantization_error(DEFAULT_LUMA_RANGE, &NARROW_LAYOUT)
+        );
+        assert_eq!(
+            CoefficientHistogram::new().best_luma_range(&NARROW_LAYOUT),
+            500
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use crate::error::{CliError, ErrorKind};
+use crate::io::{read_input, Output};
+use crate::png_image::read_png_or_ppm;
+use crate::ppm::{Rgb, RgbImage};
+use array2::array2::Array2;
+
+/// Returns the pixels of the area `width` x `height` of `image`, with their channels scaled to
+/// 0..255 from the denominator of the image.
+fn scaled_pixels(image: &RgbImage, width: usize, height: usize) -> Array2<[f64; 3]> {
+    let scale = 255.0 / image.denominator.max(1) as f64;
+    let pixels = image
+        .pixels
+        .iter()
+        .map(|pixel| [pixel.red, pixel.green, pixel.blue].map(|value| value as f64 * scale))
+        .collect();
+    Array2::from_row_major(image.width as usize, image.height as usize, pixels)
+        .crop(0, 0, width, height)
+}
+
+/// Returns the error of every pixel of `decoded`: the mean absolute difference of its red,
+/// green, and blue channels from `original`, on 0..255. Only the area both images cover is
+/// compared, since the decoder drops the last row and column of images with odd dimensions
+/// unless they were padded with `PadPolicy::Replicate`.
+///
+/// # Arguments
+/// * `original`: Reference image
+/// * `decoded`: Image compared to the reference
+pub fn pixel_errors(original: &RgbImage, decoded: &RgbImage) -> Array2<f64> {
+    let width = original.width.min(decoded.width) as usize;
+    let height = original.height.min(decoded.height) as usize;
+    scaled_pixels(original, width, height)
+        .zip_with(&scaled_pixels(decoded, width, height), |a, b| {
+            (0..3).map(|i| (a[i] - b[i]).abs()).sum::<f64>() / 3.0
+        })
+        .expect("both images are cropped to the same area")
+}
+
+/// Returns the errors with every pixel replaced by the mean error of its `block` x `block`
+/// block, so that the blocks losing the most quality stand out.
+///
+/// # Arguments
+/// * `errors`: Error of every pixel, as returned by `pixel_errors`
+/// * `block`: Side of the blocks, in pixels
+pub fn block_errors(errors: &Array2<f64>, block: usize) -> Array2<f64> {
+    let block = block.max(1);
+    let (width, height) = (errors.get_width(), errors.get_height());
+    let means = Array2::from_row_major(
+        width.div_ceil(block),
+        height.div_ceil(block),
+        (0..height.div_ceil(block))
+            .flat_map(|row| (0..width.div_ceil(block)).map(move |col| (col, row)))
+            .map(|(col, row)| {
+                let window = errors.crop(col * block, row * block, block, block);
+                window.data.iter().sum::<f64>() / window.size() as f64
+            })
+            .collect(),
+    );
+    let data = (0..height)
+        .flat_map(|row| (0..width).map(move |col| (col, row)))
+        .map(|(col, row)| *means.get(col / block, row / block).unwrap())
+        .collect();
+    Array2::from_row_major(width, height, data)
+}
+
+/// Returns the color of an error on the heatmap ramp, from black for no error through red and
+/// yellow to white for `max`.
+fn heat(error: f64, max: f64) -> Rgb {
+    let level = if max > 0.0 { error / max } else { 0.0 } * 3.0;
+    let channel = |start: f64| ((level - start).clamp(0.0, 1.0) * 255.0).round() as u16;
+    Rgb {
+        red: channel(0.0),
+        green: channel(1.0),
+        blue: channel(2.0),
+    }
+}
+
+/// Returns a heatmap of the errors, scaled so that the largest error is white.
+///
+/// # Arguments
+/// * `errors`: Error of every pixel, as returned by `pixel_errors` or `block_errors`
+pub fn heatmap(errors: &Array2<f64>) -> RgbImage {
+    let max = errors.data.iter().cloned().fold(0.0, f64::max);
+    RgbImage {
+        pixels: errors.data.iter().map(|&error| heat(error, max)).collect(),
+        width: errors.get_width() as u32,
+        height: errors.get_height() as u32,
+        denominator: 255,
+    }
+}
+
+/// Compares a decoded image to the original, writes a heatmap of the error of every pixel, or
+/// of every block if `block` is given, and prints the mean and largest error to standard out.
+///
+/// # Arguments
+/// * `original`: Location of the original PPM or PNG image
+/// * `decoded`: Location of the decoded PPM or PNG image
+/// * `output`: Destination of the heatmap, a PPM image
+/// * `block`: Side of the blocks to average the errors over, or None for a per-pixel heatmap
+pub fn visualdiff(
+    original: &str,
+    decoded: &str,
+    output: &Output,
+    block: Option<usize>,
+) -> Result<(), CliError> {
+    let read = |filename: &str| {
+        read_input(Some(filename))
+            .and_then(|bytes| read_png_or_ppm(&bytes))
+            .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))
+    };
+    let errors = pixel_errors(&read(original)?, &read(decoded)?);
+    let max = errors.data.iter().cloned().fold(0.0, f64::max);
+    let mean = errors.data.iter().sum::<f64>() / errors.size().max(1) as f64;
+    println!("mean error {mean:.3}, max error {max:.3} (levels of 255)");
+    let errors = match block {
+        Some(block) => block_errors(&errors, block),
+        None => errors,
+    };
+    let heatmap = heatmap(&errors);
+    output
+        .write_with(|writer| heatmap.write_to(writer))
+        .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_mapped_per_pixel_and_per_block() {
+        let gray = |value| Rgb {
+            red: value,
+            green: value,
+            blue: value,
+        };
+        let original = RgbImage {
+            pixels: vec![gray(100); 16],
+            width: 4,
+            height: 4,
+            denominator: 255,
+        };
+        let mut decoded = RgbImage {
+            width: 3,
+            height: 3,
+            pixels: vec![gray(100); 9],
+            ..original.clone()
+        };
+        decoded.pixels[4] = gray(112);
+
+        let errors = pixel_errors(&original, &decoded);
+        assert_eq!((errors.get_width(), errors.get_height()), (3, 3));
+        assert_eq!(errors.get(1, 1), Some(&12.0));
+        assert_eq!(errors.data.iter().sum::<f64>(), 12.0);
+
+        let blocks = block_errors(&errors, 2);
+        assert_eq!(blocks.get(0, 0), Some(&3.0));
+        assert_eq!(blocks.get(2, 2), Some(&0.0));
+
+        let image = heatmap(&errors);
+        assert_eq!(image.pixels[4], gray(255));
+        assert_eq!(image.pixels[0], gray(0));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::codec::EncoderOptions;
+use crate::encoder::Encoder;
+use crate::io::notice;
+use crate::png_image::read_png_or_ppm;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Time to wait for more changes after one, so that an image written in several steps is only
+/// recompressed once.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// Returns true for the files the watch mode compresses: PPM and PNG images.
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("ppm") || extension.eq_ignore_ascii_case("png")
+        })
+}
+
+/// Returns where the compressed version of `source` goes: the same path relative to
+/// `source_dir`, below `out_dir`, with the `rpeg` extension.
+///
+/// # Arguments
+/// * `source`: Location of the image
+/// * `source_dir`: Directory being watched
+/// * `out_dir`: Directory receiving the compressed images
+pub fn output_path(source: &Path, source_dir: &Path, out_dir: &Path) -> PathBuf {
+    let relative = source
+        .strip_prefix(source_dir)
+        .unwrap_or(source.file_name().map_or(source, Path::new));
+    out_dir.join(relative).with_extension("rpeg")
+}
+
+/// Compresses one image of the watched directory into `out_dir`, creating the directories
+/// leading to it, and returns the location of the compressed image.
+///
+/// # Arguments
+/// * `source`: Location of the PPM or PNG image
+/// * `source_dir`: Directory being watched
+/// * `out_dir`: Directory receiving the compressed images
+/// * `options`: Settings used to compress the image
+pub fn recompress(
+    source: &Path,
+    source_dir: &Path,
+    out_dir: &Path,
+    options: &EncoderOptions,
+) -> Result<PathBuf, String> {
+    let bytes = fs::read(source)
+        .map_err(|error| format!("Failed to read {}: {error}", source.display()))?;
+    let image =
+        read_png_or_ppm(&bytes).map_err(|message| format!("{}: {message}", source.display()))?;
+    let compressed = Encoder::from(options.clone()).compress(&image)?;
+    let output = output_path(source, source_dir, out_dir);
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|error| format!("Failed to create {}: {error}", parent.display()))?;
+    }
+    fs::write(&output, compressed)
+        .map_err(|error| format!("Failed to write {}: {error}", output.display()))?;
+    Ok(output)
+}
+
+/// Returns every image below `dir`.
+fn images_below(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut images = Vec::new();
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            images.extend(images_below(&path));
+        } else if is_image(&path) {
+            images.push(path);
+        }
+    }
+    images.sort();
+    images
+}
+
+/// Returns true if the compressed version of `source` is missing or older than the image.
+fn is_stale(source: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+    match (modified(source), modified(output)) {
+        (Ok(source), Ok(output)) => source > output,
+        _ => true,
+    }
+}
+
+/// Compresses `source`, reporting the outcome on standard error.
+fn recompress_and_report(
+    source: &Path,
+    source_dir: &Path,
+    out_dir: &Path,
+    options: &EncoderOptions,
+) {
+    match recompress(source, source_dir, out_dir, options) {
+        Ok(output) => notice(format!("{} -> {}", source.display(), output.display())),
+        Err(message) => eprintln!("{message}"),
+    }
+}
+
+/// Watches `source_dir` and its subdirectories, and compresses every PPM or PNG image that is
+/// created or modified into `out_dir`, keeping the directory structure. Images whose compressed
+/// version is missing or out of date are compressed on start. Every compression is reported on
+/// standard error, and failures do not stop the watch. Only returns if the directory cannot be
+/// watched.
+///
+/// # Arguments
+/// * `source_dir`: Directory to watch
+/// * `out_dir`: Directory receiving the compressed images
+/// * `options`: Settings used to compress every image
+pub fn watch(source_dir: &Path, out_dir: &Path, options: &EncoderOptions) -> Result<(), String> {
+    let source_dir = source_dir
+        .canonicalize()
+        .map_err(|error| format!("Failed to watch {}: {error}", source_dir.display()))?;
+    fs::create_dir_all(out_dir)
+        .map_err(|error| format!("Failed to create {}: {error}", out_dir.display()))?;
+    let out_dir = out_dir
+        .canonicalize()
+        .map_err(|error| format!("Failed to create {}: {error}", out_dir.display()))?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)
+        .map_err(|error| format!("Failed to watch {}: {error}", source_dir.display()))?;
+    watcher
+        .watch(&source_dir, RecursiveMode::Recursive)
+        .map_err(|error| format!("Failed to watch {}: {error}", source_dir.display()))?;
+    for source in images_below(&source_dir) {
+        if !source.starts_with(&out_dir)
+            && is_stale(&source, &output_path(&source, &source_dir, &out_dir))
+        {
+            recompress_and_report(&source, &source_dir, &out_dir, options);
+        }
+    }
+    notice(format!("Watching {}", source_dir.display()));
+
+    while let Ok(event) = receiver.recv() {
+        let mut changed = BTreeSet::new();
+        let mut collect = |event: notify::Result<notify::Event>| match event {
+            Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                changed.extend(event.paths)
+            }
+            Ok(_) => {}
+            Err(error) => eprintln!("Watch error: {error}"),
+        };
+        collect(event);
+        while let Ok(event) = receiver.recv_timeout(SETTLE_TIME) {
+            collect(event);
+        }
+        for source in changed {
+            if is_image(&source) && source.is_file() && !source.starts_with(&out_dir) {
+                recompress_and_report(&source, &source_dir, &out_dir, options);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::compress_image;
+    use crate::ppm::{Rgb, RgbImage};
+
+    #[test]
+    fn images_are_recompressed_into_the_output_tree() {
+        let root = std::env::temp_dir().join(format!("rpeg-watch-{}", std::process::id()));
+        let (source_dir, out_dir) = (root.join("src"), root.join("build"));
+        fs::create_dir_all(source_dir.join("icons")).unwrap();
+        let image = RgbImage {
+            pixels: vec![
+                Rgb {
+                    red: 1,
+                    green: 2,
+                    blue: 3
+                };
+                4
+            ],
+            width: 2,
+            height: 2,
+            denominator: 255,
+        };
+        let source = source_dir.join("icons").join("dot.ppm");
+        image.write(source.to_str()).unwrap();
+        assert!(is_image(&source) && !is_image(&root.join("notes.txt")));
+
+        let output = recompress(&source, &source_dir, &out_dir, &EncoderOptions::default());
+        assert_eq!(output, Ok(out_dir.join("icons").join("dot.rpeg")));
+        assert_eq!(
+            fs::read(out_dir.join("icons").join("dot.rpeg")).unwrap(),
+            compress_image(&image, &EncoderOptions::default())
+        );
+        assert!(!is_stale(&source, &out_dir.join("icons").join("dot.rpeg")));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
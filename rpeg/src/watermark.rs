@@ -0,0 +1,255 @@
+use crate::codec::{assemble, read_prelude, read_tile_words, write_words, DecodeOptions, Prelude};
+use crate::dct_coeff::dequantize_chroma;
+use crate::layout::{ChromaCoding, WordLayout};
+use crate::tiles::{tile_block_indices, tile_rects};
+
+/// Bytes in front of every embedded payload: `WATERMARK_MAGIC` and the length of the payload,
+/// as a big-endian u32.
+const PREFIX_LEN: usize = 6;
+
+/// Marks the start of an embedded payload, so that images without one are told apart.
+pub const WATERMARK_MAGIC: [u8; 2] = *b"wm";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// ## What embedding a watermark cost
+///
+/// `capacity` is the largest payload the image can carry, in bytes, and `changed` the number
+/// of chroma indices whose lowest bit had to be flipped. `psnr` estimates the quality of the
+/// watermarked image against the image without the watermark, from the chroma the flipped
+/// indices stand for, before the decoder clamps and rounds the pixels; it is infinite when no
+/// index changed.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::codec::{compress_image, EncoderOptions};
+/// use rpeg::ppm::{Rgb, RgbImage};
+/// use rpeg::watermark::{embed_watermark, extract_watermark};
+///
+/// let image = RgbImage {
+///     pixels: vec![Rgb { red: 120, green: 90, blue: 60 }; 32 * 32],
+///     width: 32,
+///     height: 32,
+///     denominator: 255,
+/// };
+/// let compressed = compress_image(&image, &EncoderOptions::default());
+/// let (marked, report) = embed_watermark(&compressed, b"id:7").unwrap();
+/// assert_eq!(report.capacity, 58);
+/// assert!(report.psnr > 30.0);
+/// assert_eq!(extract_watermark(&marked).unwrap(), b"id:7");
+/// ```
+pub struct WatermarkReport {
+    pub capacity: usize,
+    pub changed: usize,
+    pub psnr: f64,
+}
+
+/// Returns the largest value the Pb or Pr field of `layout` can hold.
+fn max_chroma_index(layout: &WordLayout) -> u16 {
+    match layout.chroma {
+        ChromaCoding::Indexed(_) => ((1_u32 << layout.pb.width) - 1) as u16,
+        ChromaCoding::Direct => (2.0 * layout.chroma_levels()) as u16,
+    }
+}
+
+/// Returns `index` with its lowest bit set to `bit`. A direct chroma level whose odd
+/// neighbour lies past the last level moves down by two steps instead, which keeps the bit.
+fn with_low_bit(index: u16, bit: bool, max: u16) -> u16 {
+    let marked = (index & !1) | bit as u16;
+    if marked > max {
+        marked - 2
+    } else {
+        marked
+    }
+}
+
+/// Returns the code words of every tile of a compressed image, in row-major tile order, or
+/// None for the palette-coded and raw tiles, which hold none.
+fn tile_words(prelude: &Prelude) -> Result<Vec<Option<Vec<u64>>>, String> {
+    let header = &prelude.header;
+    let tiles = tile_rects(
+        header.coded_width(),
+        header.coded_height(),
+        header.tile_size,
+    );
+    tiles
+        .iter()
+        .zip(&prelude.payloads)
+        .enumerate()
+        .map(|(index, (tile, payload))| {
+            if !header.tile_mode(index).holds_words() {
+                return Ok(None);
+            }
+            let block_count = tile_block_indices(tile, header.coded_width()).len();
+            read_tile_words(payload, block_count, header, false).map(Some)
+        })
+        .collect()
+}
+
+/// Returns the number of payload bytes the chroma of `block_count` blocks can carry.
+fn capacity_of(block_count: usize) -> usize {
+    (block_count * 2 / 8).saturating_sub(PREFIX_LEN)
+}
+
+/// Returns the largest payload, in bytes, that `embed_watermark` can hide in a compressed
+/// image: two bits per block that holds a code word, less the prefix of the payload.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+pub fn watermark_capacity(bytes: &[u8]) -> Result<usize, String> {
+    let prelude = read_prelude(bytes, &DecodeOptions::default())?;
+    let blocks = tile_words(&prelude)?.iter().flatten().map(Vec::len).sum();
+    Ok(capacity_of(blocks))
+}
+
+/// Hides `payload` in the lowest bits of the Pb and Pr indices of the blocks of a compressed
+/// image, for invisible asset tagging: every block carries two bits, Pb first, in the order
+/// the tiles and their words are stored, after a prefix giving the magic bytes and the length
+/// of the payload. Flipping the lowest bit moves the chroma of a block to a neighbouring
+/// table entry or level, so the luma, the size of the file, and every other part of it are
+/// kept. Palette-coded and raw tiles carry nothing. Returns the watermarked image and what it
+/// cost, or an error when the payload exceeds the capacity of the image.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+/// * `payload`: Bytes to hide
+pub fn embed_watermark(bytes: &[u8], payload: &[u8]) -> Result<(Vec<u8>, WatermarkReport), String> {
+    let prelude = read_prelude(bytes, &DecodeOptions::default())?;
+    let mut tiles = tile_words(&prelude)?;
+    let capacity = capacity_of(tiles.iter().flatten().map(Vec::len).sum());
+    if payload.len() > capacity {
+        return Err(format!(
+            "A payload of {} bytes exceeds the watermark capacity of {capacity} bytes",
+            payload.len()
+        ));
+    }
+    let mut message = WATERMARK_MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    message.extend_from_slice(payload);
+    let mut bits = message
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |bit| byte >> bit & 1 == 1));
+
+    let header = &prelude.header;
+    let layout = header.layout;
+    let max = max_chroma_index(&layout);
+    let (mut changed, mut squared_error) = (0, 0.0);
+    'tiles: for words in tiles.iter_mut().flatten() {
+        for word in words.iter_mut() {
+            let mut block = layout.unpack(*word);
+            let (pb, pr) = (block.pb, block.pr);
+            let Some(pb_bit) = bits.next() else {
+                break 'tiles;
+            };
+            block.pb = with_low_bit(pb, pb_bit, max);
+            if let Some(pr_bit) = bits.next() {
+                block.pr = with_low_bit(pr, pr_bit, max);
+            }
+            let shift = |old: u16, new: u16| {
+                dequantize_chroma(new as usize, &layout) - dequantize_chroma(old as usize, &layout)
+            };
+            let (pb_shift, pr_shift) = (shift(pb, block.pb), shift(pr, block.pr));
+            changed += (pb != block.pb) as usize + (pr != block.pr) as usize;
+            let red = 1.402 * pr_shift;
+            let green = -0.344136 * pb_shift - 0.714136 * pr_shift;
+            let blue = 1.772 * pb_shift;
+            squared_error += 4.0 * (red * red + green * green + blue * blue) * 255.0 * 255.0;
+            *word = layout.pack(&block);
+        }
+    }
+    let pixels = (header.width as usize * header.height as usize).max(1);
+    let mse = squared_error / (pixels * 3) as f64;
+    let psnr = if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / mse).log10()
+    };
+
+    let payloads: Vec<Vec<u8>> = tiles
+        .into_iter()
+        .zip(&prelude.payloads)
+        .map(|(words, payload)| match words {
+            Some(words) => write_words(&words, header.order, &layout),
+            None => payload.to_vec(),
+        })
+        .collect();
+    let report = WatermarkReport {
+        capacity,
+        changed,
+        psnr,
+    };
+    Ok((assemble(header, &prelude.levels, &payloads), report))
+}
+
+/// Returns the payload `embed_watermark` hid in a compressed image, or an error when the
+/// image carries none.
+///
+/// # Arguments
+/// * `bytes`: Compressed image, header included
+pub fn extract_watermark(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let prelude = read_prelude(bytes, &DecodeOptions::default())?;
+    let layout = prelude.header.layout;
+    let tiles = tile_words(&prelude)?;
+    let capacity = capacity_of(tiles.iter().flatten().map(Vec::len).sum());
+    let mut bits = tiles.iter().flatten().flatten().flat_map(|word| {
+        let block = layout.unpack(*word);
+        [block.pb & 1 == 1, block.pr & 1 == 1]
+    });
+    let mut read_bytes = |count: usize| -> Vec<u8> {
+        (0..count)
+            .map(|_| (0..8).fold(0, |byte, _| byte << 1 | bits.next().unwrap_or(false) as u8))
+            .collect()
+    };
+    let prefix = read_bytes(PREFIX_LEN);
+    let len = u32::from_be_bytes([prefix[2], prefix[3], prefix[4], prefix[5]]) as usize;
+    if prefix[..2] != WATERMARK_MAGIC || len > capacity {
+        return Err("The image carries no watermark".to_string());
+    }
+    Ok(read_bytes(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{compress_image, decompress_image, EncoderOptions};
+    use crate::layout::WIDE_LAYOUT;
+    use crate::metrics::psnr;
+    use crate::testkit::{synthetic_image, Pattern};
+
+    #[test]
+    fn payloads_round_trip_through_the_chroma_bits() {
+        let image = synthetic_image(Pattern::Noise { seed: 3 }, 40, 24);
+        for options in [
+            EncoderOptions {
+                tile_size: 16,
+                progressive: true,
+                ..EncoderOptions::default()
+            },
+            EncoderOptions {
+                layout: WIDE_LAYOUT,
+                ..EncoderOptions::default()
+            },
+        ] {
+            let compressed = compress_image(&image, &options);
+            // 240 blocks carry 480 bits, 60 bytes, of which the prefix takes 6.
+            assert_eq!(watermark_capacity(&compressed), Ok(54));
+            assert!(extract_watermark(&compressed).is_err());
+            let (marked, report) = embed_watermark(&compressed, b"asset 42").unwrap();
+            assert_eq!(marked.len(), compressed.len());
+            assert_eq!(extract_watermark(&marked).unwrap(), b"asset 42");
+            assert!(report.changed > 0 && report.changed <= 2 * (PREFIX_LEN + 8) * 4);
+            let measured = psnr(
+                &decompress_image(&compressed).unwrap(),
+                &decompress_image(&marked).unwrap(),
+            );
+            assert!(
+                (measured - report.psnr).abs() < 3.0,
+                "{measured} {}",
+                report.psnr
+            );
+            assert!(embed_watermark(&compressed, &[0; 55]).is_err());
+            let (full, _) = embed_watermark(&compressed, &[0xA5; 54]).unwrap();
+            assert_eq!(extract_watermark(&full).unwrap(), [0xA5; 54]);
+        }
+    }
+}
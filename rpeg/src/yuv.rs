@@ -0,0 +1,417 @@
+use crate::animation::{compress_frame_words, SequenceOptions};
+use crate::codec::stats::Timings;
+use crate::codec::{
+    assemble, block_ranges, chroma_priority_layout, encode_blocks, write_words, EncoderOptions,
+};
+use crate::dct_coeff::{transform_block, DEFAULT_LUMA_RANGE};
+use crate::encoder::{Backend, PadPolicy};
+use crate::error::{CliError, ErrorKind};
+use crate::fixed::Arithmetic;
+use crate::format::{Header, WordOrder, DEFAULT_LUMA_RANGE_MILLIS, MAX_METADATA_LEN};
+use crate::io::{read_input, Output};
+use crate::layout::NARROW_LAYOUT;
+use crate::pipeline::ordered;
+use crate::preprocess::Preprocess;
+use crate::roi::block_levels;
+use crate::structs::{Block, ComponentVideo};
+use crate::tiles::{tile_block_indices, tile_rects};
+use array2::array2::Array2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// ## Frame of raw planar YUV 4:2:0 video
+///
+/// The layout `ffmpeg -f rawvideo -pix_fmt yuv420p` writes: the luma plane, one byte per
+/// pixel, followed by the Cb and Cr planes, one byte per 2x2 pixels (rounded up along an odd
+/// dimension). The samples are BT.601 in the limited range, luma from 16 to 235 and chroma
+/// from 16 to 240 around 128, which is the YPbPr transform rpeg uses, so that a frame is split
+/// into blocks without going through Rgb.
+///
+/// # Usage Example
+///
+/// ```
+/// use rpeg::yuv::Yuv420Image;
+///
+/// let frame = vec![128_u8; Yuv420Image::frame_len(4, 2)];
+/// let image = Yuv420Image::from_bytes(&frame, 4, 2).unwrap();
+/// assert_eq!((image.luma.len(), image.cb.len()), (8, 2));
+/// assert!(Yuv420Image::from_bytes(&frame[1..], 4, 2).is_err());
+/// ```
+pub struct Yuv420Image {
+    pub width: u32,
+    pub height: u32,
+    pub luma: Vec<u8>,
+    pub cb: Vec<u8>,
+    pub cr: Vec<u8>,
+}
+
+impl Yuv420Image {
+    /// Returns the number of bytes of a frame of the given dimensions.
+    ///
+    /// # Arguments
+    /// * `width`: Width of the frame in pixels
+    /// * `height`: Height of the frame in pixels
+    pub fn frame_len(width: u32, height: u32) -> usize {
+        let (width, height) = (width as usize, height as usize);
+        width * height + 2 * width.div_ceil(2) * height.div_ceil(2)
+    }
+
+    /// Returns the frame held by `bytes`, which must be exactly one frame long.
+    ///
+    /// # Arguments
+    /// * `bytes`: Planes of the frame
+    /// * `width`: Width of the frame in pixels
+    /// * `height`: Height of the frame in pixels
+    pub fn from_bytes(bytes: &[u8], width: u32, height: u32) -> Result<Yuv420Image, String> {
+        if width == 0 || height == 0 {
+            return Err("A YUV frame needs at least one pixel".to_string());
+        }
+        let frame_len = Yuv420Image::frame_len(width, height);
+        if bytes.len() != frame_len {
+            return Err(format!(
+                "A {width}x{height} yuv420p frame is {frame_len} bytes, found {}",
+                bytes.len()
+            ));
+        }
+        let luma_len = (width * height) as usize;
+        let chroma_len = (frame_len - luma_len) / 2;
+        Ok(Yuv420Image {
+            width,
+            height,
+            luma: bytes[..luma_len].to_vec(),
+            cb: bytes[luma_len..luma_len + chroma_len].to_vec(),
+            cr: bytes[luma_len + chroma_len..].to_vec(),
+        })
+    }
+
+    /// Splits a stream of frames, as piped by ffmpeg, into its frames. Returns an error if the
+    /// stream is empty or ends within a frame.
+    ///
+    /// # Arguments
+    /// * `bytes`: Frames, one after the other
+    /// * `width`: Width of every frame in pixels
+    /// * `height`: Height of every frame in pixels
+    pub fn frames(bytes: &[u8], width: u32, height: u32) -> Result<Vec<Yuv420Image>, String> {
+        let frame_len = Yuv420Image::frame_len(width, height).max(1);
+        if bytes.is_empty() || !bytes.len().is_multiple_of(frame_len) {
+            return Err(format!(
+                "Expected whole {width}x{height} yuv420p frames of {frame_len} bytes, found {} bytes",
+                bytes.len()
+            ));
+        }
+        bytes
+            .chunks(frame_len)
+            .map(|frame| Yuv420Image::from_bytes(frame, width, height))
+            .collect()
+    }
+
+    /// Returns the 2x2 blocks of the frame in row-major block order, along with the width and
+    /// height of the image the decoder gives back, fitting an odd dimension to the blocks as
+    /// `pad` says. Every pixel of a block takes the chroma of the block.
+    ///
+    /// # Arguments
+    /// * `pad`: How an odd width or height is fitted to the blocks
+    pub fn blocks(&self, pad: PadPolicy) -> (Array2<Block>, u32, u32) {
+        let replicate = |length: u32| pad == PadPolicy::Replicate || length == 1;
+        let aligned = |length: u32| {
+            if replicate(length) {
+                length.next_multiple_of(2)
+            } else {
+                length & !1
+            }
+        };
+        let (coded_width, coded_height) = (aligned(self.width), aligned(self.height));
+        let (width, height) = (self.width as usize, self.height as usize);
+        let chroma_width = width.div_ceil(2);
+        let luma = |col: usize, row: usize| {
+            let sample = self.luma[row.min(height - 1) * width + col.min(width - 1)];
+            ((f64::from(sample) - 16.0) / 219.0).clamp(0.0, 1.0)
+        };
+        let chroma = |plane: &[u8], index: usize| {
+            ((f64::from(plane[index]) - 128.0) / 224.0).clamp(-0.5, 0.5)
+        };
+        let mut blocks =
+            Vec::with_capacity((coded_width as usize / 2) * (coded_height as usize / 2));
+        for row in (0..coded_height as usize).step_by(2) {
+            for col in (0..coded_width as usize).step_by(2) {
+                let index = (row / 2).min(height.div_ceil(2) - 1) * chroma_width
+                    + (col / 2).min(chroma_width - 1);
+                let (pb, pr) = (chroma(&self.cb, index), chroma(&self.cr, index));
+                let pixel = |x: usize, y: usize| ComponentVideo {
+                    y: luma(col + x, row + y),
+                    pb,
+                    pr,
+                };
+                blocks.push(Block {
+                    y1: pixel(0, 0),
+                    y2: pixel(1, 0),
+                    y3: pixel(0, 1),
+                    y4: pixel(1, 1),
+                });
+            }
+        }
+        let decoded = |length: u32, coded: u32| if replicate(length) { length } else { coded };
+        (
+            Array2::from_row_major(coded_width as usize / 2, coded_height as usize / 2, blocks),
+            decoded(self.width, coded_width),
+            decoded(self.height, coded_height),
+        )
+    }
+}
+
+/// Returns the width and height given to `--size`, written as WxH.
+///
+/// # Arguments
+/// * `text`: Dimensions, such as `1920x1080`
+pub fn parse_size(text: &str) -> Result<(u32, u32), String> {
+    let parsed = text
+        .split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+    match parsed {
+        Some((width, height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("Expected a size as WxH, found \"{text}\"")),
+    }
+}
+
+/// Compresses a YUV 4:2:0 frame into an rpeg image, taking its luma and chroma as they are
+/// instead of converting Rgb pixels. The result decodes like `compress_image` of the same
+/// picture. Returns an error for the settings that need the Rgb pixels: two-pass encoding,
+/// palette coding, content detection, sRGB conversion, preprocessing, embedded thumbnails,
+/// fixed-point or single precision arithmetic, the GPU backend, and a max error.
+///
+/// # Arguments
+/// * `image`: Frame to compress
+/// * `options`: Settings used to compress the frame
+pub fn compress_yuv420(image: &Yuv420Image, options: &EncoderOptions) -> Result<Vec<u8>, String> {
+    if options.two_pass
+        || options.palette
+        || options.detect_content
+        || options.srgb
+        || options.preprocess != Preprocess::default()
+        || options.embed_thumbnail
+        || options.arithmetic != Arithmetic::Float
+        || options.backend == Backend::Gpu
+        || options.max_error.is_some()
+        || options.target.is_some()
+    {
+        return Err(
+            "YUV input cannot be combined with settings that need the Rgb pixels".to_string(),
+        );
+    }
+    let luma_range = options
+        .luma_range
+        .map_or(DEFAULT_LUMA_RANGE_MILLIS, |range| {
+            (range * 1000.0).round() as u16
+        });
+    if !(1..=500).contains(&luma_range) {
+        return Err("The luma range must lie between 0.001 and 0.5".to_string());
+    }
+    if options.regions.len() > 255 {
+        return Err("At most 255 regions of interest are supported".to_string());
+    }
+    if options.metadata.len() > MAX_METADATA_LEN
+        || options
+            .metadata
+            .iter()
+            .any(|(key, value)| key.len() > MAX_METADATA_LEN || value.len() > MAX_METADATA_LEN)
+    {
+        return Err(format!(
+            "At most {MAX_METADATA_LEN} metadata entries of at most {MAX_METADATA_LEN} bytes are supported"
+        ));
+    }
+    if !options.tile_size.is_multiple_of(2) {
+        return Err("The tile size must be even".to_string());
+    }
+    options.layout.verify()?;
+
+    let timings = Timings::new();
+    let (blocks, width, height) = image.blocks(options.pad);
+    let header = Header {
+        width,
+        height,
+        order: if options.progressive {
+            WordOrder::Progressive
+        } else {
+            WordOrder::Sequential
+        },
+        region_qualities: options
+            .regions
+            .iter()
+            .map(|region| region.quality)
+            .collect(),
+        tile_size: options.tile_size,
+        luma_range,
+        layout: if options.chroma_priority {
+            chroma_priority_layout(&blocks.data.iter().map(transform_block).collect::<Vec<_>>())
+        } else {
+            options.layout
+        },
+        metadata: options.metadata.clone(),
+        thumbnail: None,
+        perceptual: options.perceptual,
+        palette: false,
+        tile_modes: Vec::new(),
+        srgb: false,
+        hdr_exponent: None,
+        signed: false,
+        chunked: options.chunked,
+        refinement: None,
+    };
+    let (coded_width, coded_height) = (header.coded_width(), header.coded_height());
+    let levels = block_levels(
+        &options.regions,
+        coded_width as usize,
+        coded_height as usize,
+    );
+    let ranges = block_ranges(&header, &levels);
+    let tiles = tile_rects(coded_width, coded_height, header.tile_size);
+    let encode_tile = |tile: &_| {
+        let indices = tile_block_indices(tile, coded_width);
+        let tile_blocks = Array2::from_row_major(
+            tile.width as usize / 2,
+            tile.height as usize / 2,
+            indices.iter().map(|index| blocks.data[*index]).collect(),
+        );
+        let tile_ranges: Vec<f64> = indices.iter().map(|index| ranges[*index]).collect();
+        let (words, _) = encode_blocks(
+            &tile_blocks,
+            &tile_ranges,
+            options.optimize,
+            options.perceptual,
+            &header.layout,
+            &timings,
+        );
+        write_words(&words, header.order, &header.layout)
+    };
+    let payloads = ordered(&tiles, options.deterministic, encode_tile);
+    Ok(assemble(&header, &levels, &payloads))
+}
+
+/// Compresses YUV 4:2:0 frames into a multi-frame stream, as `compress_frames` does for Rgb
+/// frames. Odd dimensions lose their last column or row.
+///
+/// # Arguments
+/// * `frames`: Frames of the sequence, in order, of equal dimensions
+/// * `options`: Settings used to compress the sequence
+pub fn compress_yuv420_frames(
+    frames: &[Yuv420Image],
+    options: &SequenceOptions,
+) -> Result<Vec<u8>, String> {
+    let first = frames.first().ok_or("Expected at least one frame")?;
+    if first.width < 2 || first.height < 2 {
+        return Err("Frames need at least 2x2 pixels".to_string());
+    }
+    let ranges = vec![DEFAULT_LUMA_RANGE; (first.width as usize / 2) * (first.height as usize / 2)];
+    let words = frames.iter().enumerate().map(|(index, frame)| {
+        if (frame.width, frame.height) != (first.width, first.height) {
+            return Err(format!(
+                "Frame {index} is {}x{}, expected {}x{}",
+                frame.width, frame.height, first.width, first.height
+            ));
+        }
+        let (blocks, _, _) = frame.blocks(PadPolicy::Trim);
+        let (words, _) = encode_blocks(
+            &blocks,
+            &ranges,
+            false,
+            false,
+            &NARROW_LAYOUT,
+            &Timings::new(),
+        );
+        Ok(words)
+    });
+    compress_frame_words(
+        first.width as usize & !1,
+        first.height as usize & !1,
+        words,
+        options,
+    )
+}
+
+/// Reads raw YUV 4:2:0 frames of the given size, such as ffmpeg pipes to standard in, and
+/// writes them to `output`: a single frame as an rpeg image compressed with `options`, and
+/// several as a multi-frame stream compressed with `sequence_options`. Failures are returned
+/// with the category the CLI exits with.
+///
+/// # Arguments
+/// * `filename`: Location of the frames, or None to read from standard in
+/// * `size`: Width and height of every frame in pixels
+/// * `output`: Destination of the compressed image or stream
+/// * `options`: Settings used to compress a single frame
+/// * `sequence_options`: Settings used to compress several frames
+pub fn compress_yuv(
+    filename: Option<&str>,
+    size: (u32, u32),
+    output: &Output,
+    options: &EncoderOptions,
+    sequence_options: &SequenceOptions,
+) -> Result<(), CliError> {
+    let bytes = read_input(filename)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let frames = Yuv420Image::frames(&bytes, size.0, size.1)
+        .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?;
+    let compressed = match frames.as_slice() {
+        [frame] => compress_yuv420(frame, options)
+            .map_err(|message| CliError::new(ErrorKind::BadArguments, message))?,
+        _ => compress_yuv420_frames(&frames, sequence_options)
+            .map_err(|message| CliError::new(ErrorKind::UnreadableInput, message))?,
+    };
+    output
+        .write(&compressed)
+        .map_err(|message| CliError::new(ErrorKind::Io, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::decompress_frames;
+    use crate::codec::{compress_image, decompress_image};
+    use crate::ppm::{Rgb, RgbImage};
+
+    #[test]
+    fn yuv_frames_compress_like_their_rgb() {
+        // A 6x4 frame of gray steps whose chroma is neutral, so that its Rgb is exact.
+        let (width, height) = (6, 4);
+        let luma: Vec<u8> = (0..width * height)
+            .map(|i| 16 + (i % width * 40 + i / width * 5) as u8)
+            .collect();
+        let mut bytes = luma.clone();
+        bytes.extend(vec![128; 2 * 3 * 2]);
+        let frame = Yuv420Image::from_bytes(&bytes, width, height).unwrap();
+        let rgb = RgbImage {
+            pixels: luma
+                .iter()
+                .map(|sample| {
+                    let value = (u16::from(*sample) - 16) * 255 / 219;
+                    Rgb {
+                        red: value,
+                        green: value,
+                        blue: value,
+                    }
+                })
+                .collect(),
+            width,
+            height,
+            denominator: 255,
+        };
+        let options = EncoderOptions {
+            arithmetic: Arithmetic::Float,
+            ..Default::default()
+        };
+        let compressed = compress_yuv420(&frame, &options).unwrap();
+        let decoded = decompress_image(&compressed).unwrap();
+        let reference = decompress_image(&compress_image(&rgb, &options)).unwrap();
+        assert_eq!((decoded.width, decoded.height), (6, 4));
+        assert!(decoded
+            .pixels
+            .iter()
+            .zip(&reference.pixels)
+            .all(|(a, b)| a.red.abs_diff(b.red) <= 2 && a.blue.abs_diff(b.blue) <= 2));
+
+        let stream =
+            compress_yuv420_frames(&[frame.clone(), frame], &SequenceOptions::default()).unwrap();
+        assert_eq!(decompress_frames(&stream).unwrap()[1], decoded);
+        assert!(Yuv420Image::frames(&bytes[1..], width, height).is_err());
+        assert_eq!(parse_size("640x480"), Ok((640, 480)));
+        assert!(parse_size("640").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! Runs the rpeg binary, whose argument errors exit the process.
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Returns a new empty directory for the files of `test`.
+fn scratch_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rpeg-cli-{test}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs rpeg with `args` from `dir`.
+fn rpeg(dir: &PathBuf, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rpeg"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn unknown_flags_are_bad_arguments() {
+    let dir = scratch_dir("flags");
+    std::fs::write(dir.join("x.ppm"), "P3\n2 2\n255\n0 0 0 0 0 0 0 0 0 0 0 0\n").unwrap();
+    let output = rpeg(&dir, &["-c", "--metrics", "x.ppm", "-o", "out.rpeg"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("Unknown flag --metrics\nUsage: "),
+        "{stderr}"
+    );
+    assert!(!dir.join("out.rpeg").exists());
+
+    let output = rpeg(&dir, &["-c", "--rotate", "0", "x.ppm"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "--rotate expects 90, 180, or 270\n"
+    );
+    let output = rpeg(&dir, &["-c", "--rotate", "90", "x.ppm", "-o", "out.rpeg"]);
+    assert!(output.status.success());
+    assert!(dir.join("out.rpeg").is_file());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn conflicting_settings_are_bad_arguments() {
+    let dir = scratch_dir("settings");
+    std::fs::write(dir.join("x.ppm"), "P3\n2 2\n255\n0 0 0 0 0 0 0 0 0 0 0 0\n").unwrap();
+    for (args, message) in [
+        (
+            &["-c", "--srgb", "--palette", "x.ppm"][..],
+            "sRGB conversion cannot be combined with palette coding or content detection\n",
+        ),
+        (
+            &["-c", "--srgb", "--detect-content", "x.ppm"],
+            "sRGB conversion cannot be combined with palette coding or content detection\n",
+        ),
+        (
+            &["-c", "--srgb", "--fixed-point", "x.ppm"],
+            "sRGB conversion is only available with double precision floating point arithmetic\n",
+        ),
+        (
+            &["pack", "--srgb", "--palette", "x.ppm", "-o", "set.rpeg"],
+            "sRGB conversion cannot be combined with palette coding or content detection\n",
+        ),
+    ] {
+        let output = rpeg(&dir, args);
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), message);
+    }
+    assert!(!dir.join("set.rpeg").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
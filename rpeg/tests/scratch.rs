@@ -0,0 +1,140 @@
+//! Counts the allocations of the scratch buffers with a global allocator of its own, which
+//! would count those of every other test if it lived in the library.
+
+use rpeg::codec::scratch::ScratchBuffers;
+use rpeg::codec::{
+    compress_image_with_report, compress_into, decompress_into, decompress_with_options,
+    DecodeOptions, EncoderOptions,
+};
+use rpeg::decoder::Decoder;
+use rpeg::encoder::Encoder;
+use rpeg::fixed::Arithmetic;
+use rpeg::layout::WIDE_LAYOUT;
+use rpeg::testkit::{synthetic_image, Pattern};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Allocator counting the allocations of every thread, so that the test sees its own.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn warm_buffers_allocate_nothing() {
+    let images = [
+        synthetic_image(Pattern::Noise { seed: 1 }, 150, 90),
+        synthetic_image(Pattern::Gradient, 150, 90),
+        synthetic_image(Pattern::Noise { seed: 2 }, 149, 87),
+    ];
+    // The buffers only cover double precision floating point arithmetic.
+    let float = EncoderOptions {
+        arithmetic: Arithmetic::Float,
+        ..Default::default()
+    };
+    let float_decode = DecodeOptions {
+        arithmetic: Arithmetic::Float,
+        ..Default::default()
+    };
+    let settings = [
+        float.clone(),
+        EncoderOptions {
+            luma_range: Some(0.1),
+            perceptual: true,
+            layout: WIDE_LAYOUT,
+            ..float.clone()
+        },
+        EncoderOptions {
+            pad: rpeg::encoder::PadPolicy::Replicate,
+            ..float.clone()
+        },
+    ];
+    let decode_settings = [
+        float_decode.clone(),
+        DecodeOptions {
+            dither: true,
+            ..float_decode.clone()
+        },
+    ];
+    let mut scratch = ScratchBuffers::new();
+    let mut compressed = Vec::new();
+    let mut decoded = images[0].clone();
+    for options in &settings {
+        assert!(ScratchBuffers::covers(options));
+        for image in &images {
+            let report = compress_into(image, options, &mut scratch, &mut compressed);
+            assert_eq!(
+                (compressed.clone(), report),
+                compress_image_with_report(image, options)
+            );
+            for decode_options in &decode_settings {
+                decompress_into(&compressed, decode_options, &mut scratch, &mut decoded).unwrap();
+                assert_eq!(
+                    decoded,
+                    decompress_with_options(&compressed, decode_options).unwrap()
+                );
+            }
+        }
+    }
+    let encoders = [
+        Encoder::from(float.clone()),
+        Encoder::from(float.clone())
+            .luma_range(0.1)
+            .perceptual(true)
+            .layout(WIDE_LAYOUT),
+        Encoder::from(float.clone()).pad(rpeg::encoder::PadPolicy::Replicate),
+    ];
+    for encoder in &encoders {
+        let decoder = Decoder::new().arithmetic(Arithmetic::Float).dither(true);
+        encoder
+            .compress_into(&images[0], &mut scratch, &mut compressed)
+            .unwrap();
+        assert_eq!(compressed, encoder.compress(&images[0]).unwrap());
+        let before = ALLOCATIONS.with(Cell::get);
+        encoder
+            .compress_into(&images[0], &mut scratch, &mut compressed)
+            .unwrap();
+        decoder
+            .decompress_into(&compressed, &mut scratch, &mut decoded)
+            .unwrap();
+        assert_eq!(ALLOCATIONS.with(Cell::get), before);
+    }
+    let tiled = EncoderOptions {
+        tile_size: 64,
+        ..float.clone()
+    };
+    assert!(!ScratchBuffers::covers(&tiled));
+    compress_into(&images[0], &tiled, &mut scratch, &mut compressed);
+    assert_eq!(compressed, compress_image_with_report(&images[0], &tiled).0);
+    decompress_into(&compressed, &float_decode, &mut scratch, &mut decoded).unwrap();
+    assert_eq!(
+        decoded,
+        decompress_with_options(&compressed, &float_decode).unwrap()
+    );
+    compress_into(&images[0], &settings[0], &mut scratch, &mut compressed);
+    let truncated = &compressed[..compressed.len() - 1];
+    assert_eq!(
+        decompress_into(truncated, &float_decode, &mut scratch, &mut decoded),
+        Err(decompress_with_options(truncated, &float_decode).unwrap_err())
+    );
+}
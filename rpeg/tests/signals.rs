@@ -0,0 +1,15 @@
+//! Raises SIGINT at the process, which would cancel the compressions of every other test if it
+//! ran in the library.
+
+#![cfg(unix)]
+
+use rpeg::cancel::{interrupted, on_signals};
+
+#[test]
+fn the_first_signal_cancels_the_token() {
+    let token = on_signals();
+    assert!(!token.is_cancelled() && !interrupted());
+    unsafe { libc::raise(libc::SIGINT) };
+    assert!(token.is_cancelled() && interrupted());
+    assert!(on_signals().is_cancelled());
+}